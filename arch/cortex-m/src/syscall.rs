@@ -452,4 +452,22 @@ impl<A: CortexMVariant> kernel::syscall::UserspaceKernelBoundary for SysCall<A>
             Err(ErrorCode::SIZE)
         }
     }
+
+    unsafe fn process_pointers(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &CortexMStoredState,
+    ) -> Option<(usize, usize)> {
+        // The PC is in the frame the hardware stacked, if the stack pointer
+        // is valid, as in `print_context`.
+        let invalid_stack_pointer = state.psp < accessible_memory_start as usize
+            || state.psp.saturating_add(SVC_FRAME_SIZE) > app_brk as usize;
+        let pc = if invalid_stack_pointer {
+            state.yield_pc
+        } else {
+            ptr::read((state.psp as *const usize).offset(6))
+        };
+        Some((pc, state.psp))
+    }
 }
@@ -29,6 +29,9 @@ pub use cortexm::support;
 pub use cortexm::systick;
 pub use cortexm::unhandled_interrupt;
 pub use cortexm::CortexMVariant;
+pub use cortexv7m::kernel_fault_frame;
+pub use cortexv7m::set_kernel_fault_hook;
+pub use cortexv7m::skip_faulting_instruction;
 
 // Enum with no variants to ensure that this type is not instantiable. It is
 // only used to pass architecture-specific constants and functions via the
@@ -12,6 +12,64 @@ extern "C" {
     static _sstack: u8;
 }
 
+/// Stack pointer of the last hard fault in the kernel, which points at the
+/// registers the hardware stacked, or null.
+static mut KERNEL_FAULT_STACK: *const u32 = core::ptr::null();
+
+/// Returns the registers the hardware stacked on the last hard fault in the
+/// kernel, `r0`-`r3`, `r12`, `lr`, `pc` and `xPSR`, if there was one.
+///
+/// The hard fault handler panics on a kernel fault, so panic handlers can use
+/// this to dump the frame of the fault.
+pub fn kernel_fault_frame() -> Option<[u32; 8]> {
+    // SAFETY: the hard fault handler sets the pointer once the frame is
+    // stacked, and nothing writes to the frame after that.
+    unsafe {
+        let stack = core::ptr::addr_of!(KERNEL_FAULT_STACK).read();
+        (!stack.is_null()).then(|| core::array::from_fn(|i| stack.add(i).read_volatile()))
+    }
+}
+
+/// Handler a test can install to survive a hard fault in the kernel.
+///
+/// The handler gets the registers the hardware stacked, as for
+/// [`kernel_fault_frame`]. If it returns `true`, the fault status registers
+/// are cleared and the kernel resumes with the registers of the frame, which
+/// the handler must have changed so that the fault does not repeat, for
+/// example with [`skip_faulting_instruction`]. If it returns `false`, the
+/// fault is reported as usual.
+pub type KernelFaultHook = fn(frame: &mut [u32; 8]) -> bool;
+
+static mut KERNEL_FAULT_HOOK: Option<KernelFaultHook> = None;
+
+/// Installs `hook` to handle the hard faults in the kernel, or removes the
+/// hook with `None`. Faults from a kernel stack overflow are never handed to
+/// the hook.
+///
+/// # Safety
+///
+/// Only tests that fault on purpose may install a hook, and they must remove
+/// it before the code that faults returns.
+pub unsafe fn set_kernel_fault_hook(hook: Option<KernelFaultHook>) {
+    *core::ptr::addr_of_mut!(KERNEL_FAULT_HOOK) = hook;
+}
+
+/// Moves the stacked `pc` of `frame` past the instruction that faulted, so
+/// the kernel resumes after it.
+///
+/// Only precise faults stack the address of the faulting instruction, such
+/// as MemManage faults and precise bus faults on a load or store.
+///
+/// # Safety
+///
+/// The stacked `pc` must point at an instruction in readable memory.
+pub unsafe fn skip_faulting_instruction(frame: &mut [u32; 8]) {
+    let halfword = core::ptr::read_volatile(frame[6] as *const u16);
+    // Thumb-2 instructions that start with 0b11101, 0b11110 or 0b11111 are
+    // 32 bits long, all others 16 bits.
+    frame[6] += if halfword >> 11 >= 0b11101 { 4 } else { 2 };
+}
+
 /// ARMv7-M systick handler function.
 ///
 /// For documentation of this function, please see
@@ -278,15 +336,27 @@ pub unsafe fn switch_to_user_arm_v7m(
 
 #[cfg(any(doc, all(target_arch = "arm", target_os = "none")))]
 /// Continue the hardfault handler for all hard-faults that occurred
-/// during kernel execution. This function must never return.
+/// during kernel execution. This function only returns if the
+/// [`KernelFaultHook`] handled the fault, which returns from the exception.
 unsafe extern "C" fn hard_fault_handler_arm_v7m_kernel(
     faulting_stack: *mut u32,
     stack_overflow: u32,
-) -> ! {
+) {
     if stack_overflow != 0 {
         // Panic to show the correct error.
         panic!("kernel stack overflow");
     } else {
+        if let Some(hook) = core::ptr::addr_of!(KERNEL_FAULT_HOOK).read() {
+            if hook(&mut *(faulting_stack as *mut [u32; 8])) {
+                // The status bits are sticky and cleared by writing ones.
+                core::ptr::write_volatile(0xE000ED28 as *mut u32, u32::MAX);
+                core::ptr::write_volatile(0xE000ED2C as *mut u32, u32::MAX);
+                return;
+            }
+        }
+
+        KERNEL_FAULT_STACK = faulting_stack;
+
         // Show the normal kernel hardfault message.
         let stacked_r0: u32 = *faulting_stack.offset(0);
         let stacked_r1: u32 = *faulting_stack.offset(1);
@@ -462,9 +532,9 @@ pub unsafe extern "C" fn hard_fault_handler_arm_v7m() {
     ite   ne               // check if the result of that bitwise AND was not 0
     movne r1, #1           // BFSR & 0b00110000 != 0; r1 = 1
     moveq r1, #0           // BFSR & 0b00110000 == 0; r1 = 0
-    and r5, r2, r1         // bitwise and r1 and r2, store in r5
-    cmp  r5, #1            //  update condition codes to reflect if r1 == 1 && r2 == 1
-    itt  eq                // if r5==1 run the next 2 instructions, else skip to branch
+    and r3, r2, r1         // bitwise and r1 and r2, store in r3
+    cmp  r3, #1            //  update condition codes to reflect if r1 == 1 && r2 == 1
+    itt  eq                // if r3==1 run the next 2 instructions, else skip to branch
     // if true, The hardware couldn't use the stack, so we have no saved data and
     // we cannot use the kernel stack as is. We just want to report that
     // the kernel's stack overflowed, since that is essential for
@@ -480,7 +550,10 @@ pub unsafe extern "C" fn hard_fault_handler_arm_v7m() {
     // to non-naked handler.
     cmp r2, #0
     // Per ARM calling convention, faulting stack is passed in r0, whether
-    // there was a stack overflow in r1. This function must never return.
+    // there was a stack overflow in r1. The branch leaves the `EXC_RETURN`
+    // value in lr, so if that function returns, it returns from the
+    // exception. Unless the stack overflowed, only r0-r3 are overwritten
+    // above, which the hardware restores from the stacked frame.
     bne {kernel_hard_fault_handler} // branch to kernel hard fault handler
     // Otherwise, the hard fault occurred in userspace. In this case, read
     // the relevant SCB registers:
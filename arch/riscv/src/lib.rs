@@ -11,6 +11,7 @@ use core::fmt::Write;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 
 pub mod csr;
+pub mod mcycle;
 pub mod pmp;
 pub mod support;
 pub mod syscall;
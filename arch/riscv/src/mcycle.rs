@@ -0,0 +1,29 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! The `mcycle` counter of the hart, which counts the cycles it ran.
+
+use kernel::hil;
+
+use crate::csr::CSR;
+
+/// `mcycle` as a `CycleCounter`.
+///
+/// The counter runs from reset. Stopping it takes `mcountinhibit`, which not
+/// every hart implements, so `start` and `stop` do nothing.
+pub struct Mcycle;
+
+impl hil::hw_debug::CycleCounter for Mcycle {
+    fn start(&self) {}
+
+    fn stop(&self) {}
+
+    fn count(&self) -> u64 {
+        CSR.read_cycle_counter()
+    }
+
+    fn reset(&self) {
+        CSR.reset_cycle_counter();
+    }
+}
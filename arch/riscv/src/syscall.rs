@@ -749,4 +749,13 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             Err(ErrorCode::SIZE)
         }
     }
+
+    unsafe fn process_pointers(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Riscv32iStoredState,
+    ) -> Option<(usize, usize)> {
+        Some((state.pc as usize, state.regs[R_SP] as usize))
+    }
 }
@@ -6,7 +6,9 @@
 //!
 //! This provides components for attaching the kernel debug output (for panic!,
 //! print!, debug!, etc.) to the output. `DebugWriterComponent` uses a UART mux,
-//! and `DebugWriterNoMuxComponent` just uses a UART interface directly.
+//! `DebugWriterNoMuxComponent` just uses a UART interface directly, and
+//! `DebugWriterFanOutComponent` sends the output to several transmitters at
+//! once.
 //!
 //! Usage
 //! -----
@@ -22,11 +24,18 @@
 //!     create_capability!(kernel::capabilities::SetDebugWriterCapability),
 //! )
 //! .finalize(components::debug_writer_no_mux_component_static!());
+//!
+//! let debug_fan_out = components::debug_writer::DebugWriterFanOutComponent::new(
+//!     [(uart_device, true), (rtt, true)],
+//!     create_capability!(kernel::capabilities::SetDebugWriterCapability),
+//! )
+//! .finalize(components::debug_writer_fan_out_component_static!(2));
 //! ```
 
 // Author: Brad Campbell <bradjc@virginia.edu>
 // Last modified: 11/07/2019
 
+use capsules_core::uart_fan_out::{FanOutChannel, UartFanOut};
 use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
@@ -45,7 +54,7 @@ pub const DEFAULT_DEBUG_BUFFER_KBYTE: usize = 2;
 
 // Bytes [0, DEBUG_BUFFER_SPLIT) are used for output_buf while bytes
 // [DEBUG_BUFFER_SPLIT, DEFAULT_DEBUG_BUFFER_KBYTE * 1024) are used for internal_buf.
-const DEBUG_BUFFER_SPLIT: usize = 64;
+pub const DEBUG_BUFFER_SPLIT: usize = 64;
 
 /// The optional argument to this macro allows boards to specify the size of the in-RAM
 /// buffer used for storing debug messages.
@@ -89,6 +98,38 @@ macro_rules! debug_writer_no_mux_component_static {
     };};
 }
 
+/// The first argument is the number of channels. The optional second argument
+/// allows boards to specify the size of the in-RAM buffer used for storing
+/// debug messages.
+#[macro_export]
+macro_rules! debug_writer_fan_out_component_static {
+    ($N:expr, $BUF_SIZE_KB:expr) => {{
+        let channel_buffers =
+            kernel::static_buf!([[u8; $crate::debug_writer::DEBUG_BUFFER_SPLIT]; $N]);
+        let channels =
+            kernel::static_buf!([capsules_core::uart_fan_out::FanOutChannel<'static>; $N]);
+        let fan_out = kernel::static_buf!(capsules_core::uart_fan_out::UartFanOut<'static>);
+        let ring = kernel::static_buf!(kernel::collections::ring_buffer::RingBuffer<'static, u8>);
+        let buffer = kernel::static_buf!([u8; 1024 * $BUF_SIZE_KB]);
+        let debug = kernel::static_buf!(kernel::debug::DebugWriter);
+        let debug_wrapper = kernel::static_buf!(kernel::debug::DebugWriterWrapper);
+
+        (
+            channel_buffers,
+            channels,
+            fan_out,
+            ring,
+            buffer,
+            debug,
+            debug_wrapper,
+        )
+    };};
+    ($N:expr $(,)?) => {{
+        use $crate::debug_writer::DEFAULT_DEBUG_BUFFER_KBYTE;
+        $crate::debug_writer_fan_out_component_static!($N, DEFAULT_DEBUG_BUFFER_KBYTE)
+    };};
+}
+
 pub struct DebugWriterComponent<const BUF_SIZE_BYTES: usize, C: SetDebugWriterCapability> {
     uart_mux: &'static MuxUart<'static>,
     marker: core::marker::PhantomData<[u8; BUF_SIZE_BYTES]>,
@@ -207,3 +248,69 @@ impl<
         });
     }
 }
+
+pub struct DebugWriterFanOutComponent<
+    const N: usize,
+    const BUF_SIZE_BYTES: usize,
+    C: SetDebugWriterCapability,
+> {
+    channels: [(&'static dyn uart::Transmit<'static>, bool); N],
+    marker: core::marker::PhantomData<[u8; BUF_SIZE_BYTES]>,
+    capability: C,
+}
+
+impl<const N: usize, const BUF_SIZE_BYTES: usize, C: SetDebugWriterCapability>
+    DebugWriterFanOutComponent<N, BUF_SIZE_BYTES, C>
+{
+    /// Creates the component for `channels`, each a transmitter and whether
+    /// the channel starts enabled.
+    pub fn new(channels: [(&'static dyn uart::Transmit<'static>, bool); N], capability: C) -> Self {
+        Self {
+            channels,
+            marker: core::marker::PhantomData,
+            capability,
+        }
+    }
+}
+
+impl<const N: usize, const BUF_SIZE_BYTES: usize, C: SetDebugWriterCapability> Component
+    for DebugWriterFanOutComponent<N, BUF_SIZE_BYTES, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[[u8; DEBUG_BUFFER_SPLIT]; N]>,
+        &'static mut MaybeUninit<[FanOutChannel<'static>; N]>,
+        &'static mut MaybeUninit<UartFanOut<'static>>,
+        &'static mut MaybeUninit<RingBuffer<'static, u8>>,
+        &'static mut MaybeUninit<[u8; BUF_SIZE_BYTES]>,
+        &'static mut MaybeUninit<kernel::debug::DebugWriter>,
+        &'static mut MaybeUninit<kernel::debug::DebugWriterWrapper>,
+    );
+    type Output = &'static UartFanOut<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        // Each channel gets a buffer as long as the output buffer of the debug
+        // writer, so it sends whole transmissions.
+        let channel_buffers = s.0.write([[0; DEBUG_BUFFER_SPLIT]; N]);
+        let mut channel_buffers = channel_buffers.iter_mut();
+        let channels = s.1.write(self.channels.map(|(transmit, enabled)| {
+            FanOutChannel::new(transmit, channel_buffers.next().unwrap(), enabled)
+        }));
+        let fan_out = s.2.write(UartFanOut::new(channels));
+        fan_out.setup();
+
+        let buf = s.4.write([0; BUF_SIZE_BYTES]);
+        let (output_buf, internal_buf) = buf.split_at_mut(DEBUG_BUFFER_SPLIT);
+        let ring_buffer = s.3.write(RingBuffer::new(internal_buf));
+        let debugger = s.5.write(kernel::debug::DebugWriter::new(
+            fan_out,
+            output_buf,
+            ring_buffer,
+        ));
+        hil::uart::Transmit::set_transmit_client(fan_out, debugger);
+
+        let debug_wrapper = s.6.write(kernel::debug::DebugWriterWrapper::new(debugger));
+        kernel::debug::set_debug_writer_wrapper(debug_wrapper, self.capability);
+
+        fan_out
+    }
+}
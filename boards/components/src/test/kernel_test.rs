@@ -0,0 +1,50 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the runner that starts the tests of a kernel test board.
+//!
+//! Finalize it at the end of `main()`, after the debug writer and the tests
+//! are set up. The suite starts once the kernel loop runs.
+//!
+//! Usage
+//! -----
+//! ```rust,ignore
+//! components::test::kernel_test::TestRunnerComponent::new(test_launcher)
+//!     .finalize(components::test_runner_component_static!(TestLauncher));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules_core::test::kernel_test::{KernelTestRunner, KernelTestSuite};
+use kernel::component::Component;
+use kernel::deferred_call::DeferredCallClient;
+
+#[macro_export]
+macro_rules! test_runner_component_static {
+    ($S:ty $(,)?) => {{
+        kernel::static_buf!(capsules_core::test::kernel_test::KernelTestRunner<$S>)
+    };};
+}
+
+pub struct TestRunnerComponent<S: 'static + KernelTestSuite> {
+    suite: &'static S,
+}
+
+impl<S: 'static + KernelTestSuite> TestRunnerComponent<S> {
+    pub fn new(suite: &'static S) -> Self {
+        Self { suite }
+    }
+}
+
+impl<S: 'static + KernelTestSuite> Component for TestRunnerComponent<S> {
+    type StaticInput = &'static mut MaybeUninit<KernelTestRunner<S>>;
+    type Output = &'static KernelTestRunner<S>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let runner = s.write(KernelTestRunner::new(self.suite));
+        runner.register();
+        runner.start();
+        runner
+    }
+}
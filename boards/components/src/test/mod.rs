@@ -2,4 +2,5 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+pub mod kernel_test;
 pub mod multi_alarm_test;
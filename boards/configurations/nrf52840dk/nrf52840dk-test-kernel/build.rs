@@ -0,0 +1,20 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! This board uses a custom build script to link the kernel for the second
+//! flash bank when built with the `bank_b` feature. The script is lightly
+//! adapted from the `default_linker_script` in `tock_build_scripts`, and uses
+//! the functions provided by that crate.
+
+fn main() {
+    let linker_script = if std::env::var_os("CARGO_FEATURE_BANK_B").is_some() {
+        "layout_bank_b.ld"
+    } else {
+        "layout.ld"
+    };
+    tock_build_scripts::default::rustflags_check();
+    tock_build_scripts::default::include_tock_kernel_layout();
+    tock_build_scripts::default::add_board_dir_to_linker_search_path();
+    tock_build_scripts::default::set_and_track_linker_script(linker_script);
+}
@@ -16,6 +16,8 @@ use nrf52840::uart::{Uarte, UARTE0_BASE};
 use crate::CHIP;
 use crate::PROCESSES;
 use crate::PROCESS_PRINTER;
+use crate::SLEEP_MONITOR;
+use crate::TEST_LAUNCHER;
 
 enum Writer {
     WriterUart(/* initialized */ bool),
@@ -75,13 +77,19 @@ pub unsafe fn panic_fmt(pi: &PanicInfo) -> ! {
     let led_kernel_pin = &nrf52840::gpio::GPIOPin::new(Pin::P0_13);
     let led = &mut led::LedLow::new(led_kernel_pin);
     let writer = &mut *addr_of_mut!(WRITER);
-    debug::panic(
-        &mut [led],
+    debug::panic_print(
         writer,
         pi,
         &cortexm4::support::nop,
         PROCESSES.unwrap().as_slice(),
         &*addr_of!(CHIP),
         &*addr_of!(PROCESS_PRINTER),
-    )
+    );
+    let test = TEST_LAUNCHER.and_then(|launcher| launcher.current_test());
+    let restart = crate::test::panic_reset::announce(writer, test);
+    crate::test::crash_dump::write(writer, test, SLEEP_MONITOR, PROCESSES.unwrap().as_slice());
+    if let Some(index) = restart {
+        crate::test::panic_reset::restart(index);
+    }
+    debug::panic_blink_forever(&mut [led])
 }
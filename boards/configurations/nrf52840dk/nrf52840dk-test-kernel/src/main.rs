@@ -9,18 +9,21 @@
 #![deny(missing_docs)]
 
 use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use capsules_core::test::kernel_test::KernelTestSuite;
+use capsules_core::virtualizers::virtual_alarm::MuxAlarm;
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
 use core::cell::Cell;
 use kernel::component::Component;
+use kernel::hil::gpio::{Configure, Output};
 use kernel::hil::time::Counter;
 use kernel::platform::{KernelResources, SyscallDriverLookup};
 use kernel::process::ProcessArray;
-use kernel::scheduler::round_robin::RoundRobinSched;
 use kernel::utilities::cells::NumericCellExt;
 use kernel::{capabilities, create_capability, static_init};
-use nrf52840::chip::Nrf52DefaultPeripherals;
 use nrf52840::gpio::Pin;
 use nrf52840::interrupt_service::Nrf52840DefaultPeripherals;
 use nrf52_components::{UartChannel, UartPins};
+use test::reporter::{Duration, Failure, Pass};
 
 mod test;
 
@@ -34,14 +37,17 @@ const UART_RXD: Pin = Pin::P0_08;
 /// Debug Writer
 pub mod io;
 
-// Number of concurrent processes this platform supports.
-const NUM_PROCS: usize = 0;
+// Number of concurrent processes this platform supports. Tests load processes
+// from embedded app images into these slots.
+const NUM_PROCS: usize = 3;
 
 /// Static variables used by io.rs.
 static mut PROCESSES: Option<&'static ProcessArray<NUM_PROCS>> = None;
 static mut CHIP: Option<&'static nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>> = None;
 static mut PROCESS_PRINTER: Option<&'static capsules_system::process_printer::ProcessPrinterText> =
     None;
+static mut TEST_LAUNCHER: Option<&'static TestLauncher> = None;
+static mut SLEEP_MONITOR: Option<&'static test::sleep_test::SleepMonitor> = None;
 
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
@@ -54,16 +60,31 @@ static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 
 /// Supported drivers by the platform
 pub struct Platform {
-    scheduler: &'static RoundRobinSched<'static>,
+    read_only_state: &'static ReadOnlyStateDriver,
+    shared_lock: &'static test::priority_inversion_test::SharedLock,
+    report_driver: &'static test::report_driver::ReportDriver,
+    second_report_driver: &'static test::report_driver::ReportDriver,
+    scheduler: &'static test::scheduler::TestScheduler,
+    sleep_monitor: &'static test::sleep_test::SleepMonitor,
+    switch_recorder: &'static test::context_switch_test::SwitchRecorder,
     systick: cortexm4::systick::SysTick,
 }
 
+type ReadOnlyStateDriver =
+    capsules_extra::read_only_state::ReadOnlyStateDriver<'static, nrf52840::rtc::Rtc<'static>>;
+
 impl SyscallDriverLookup for Platform {
-    fn with_driver<F, R>(&self, _driver_num: usize, f: F) -> R
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
     where
         F: FnOnce(Option<&dyn kernel::syscall::SyscallDriver>) -> R,
     {
-        f(None)
+        match driver_num {
+            capsules_extra::read_only_state::DRIVER_NUM => f(Some(self.read_only_state)),
+            test::embedded_apps::LOCK_DRIVER_NUM => f(Some(self.shared_lock)),
+            test::embedded_apps::REPORT_DRIVER_NUM => f(Some(self.report_driver)),
+            test::embedded_apps::SECOND_REPORT_DRIVER_NUM => f(Some(self.second_report_driver)),
+            _ => f(None),
+        }
     }
 }
 
@@ -73,36 +94,213 @@ impl SyscallDriverLookup for Platform {
 
 struct TestLauncher {
     test_index: Cell<usize>,
-    peripherals: &'static Nrf52DefaultPeripherals<'static>,
+    /// Why the running test skipped itself, if it did.
+    skipped: Cell<Option<&'static str>>,
+    peripherals: &'static Nrf52840DefaultPeripherals<'static>,
+    mux_alarm: &'static MuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    apps: &'static test::embedded_apps::AppLoader,
+    alarm_driver: &'static test::grant_failure_test::TestAlarmDriver,
+    uart_mux: &'static MuxUart<'static>,
+    scheduler: &'static test::scheduler::TestScheduler,
+    shared_lock: &'static test::priority_inversion_test::SharedLock,
+    report_driver: &'static test::report_driver::ReportDriver,
+    second_report_driver: &'static test::report_driver::ReportDriver,
+    sleep_monitor: &'static test::sleep_test::SleepMonitor,
+    switch_recorder: &'static test::context_switch_test::SwitchRecorder,
+    watchdog: &'static test::watchdog_test::TestWatchdog,
+    flash_log: &'static test::flash_log::FlashLog,
+    invariant_monitor: &'static test::invariant_monitor::InvariantMonitor,
+    syscall_recorder: &'static test::syscall_trace::SyscallRecorder,
+    scratch: &'static test::scratch::ScratchArena,
+    seed: &'static test::seed::TestSeed,
+    timeout: &'static test::timeout::TestTimeout,
+    timer: test::test_timer::TestTimer<cortexm4::dwt::Dwt>,
+    peripheral_monitor: &'static test::peripheral_power::PeripheralMonitor,
 }
 impl TestLauncher {
-    fn new(peripherals: &'static Nrf52DefaultPeripherals<'static>) -> Self {
+    fn new(
+        peripherals: &'static Nrf52840DefaultPeripherals<'static>,
+        mux_alarm: &'static MuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+        apps: &'static test::embedded_apps::AppLoader,
+        alarm_driver: &'static test::grant_failure_test::TestAlarmDriver,
+        uart_mux: &'static MuxUart<'static>,
+        scheduler: &'static test::scheduler::TestScheduler,
+        shared_lock: &'static test::priority_inversion_test::SharedLock,
+        report_driver: &'static test::report_driver::ReportDriver,
+        second_report_driver: &'static test::report_driver::ReportDriver,
+        sleep_monitor: &'static test::sleep_test::SleepMonitor,
+        switch_recorder: &'static test::context_switch_test::SwitchRecorder,
+        watchdog: &'static test::watchdog_test::TestWatchdog,
+        flash_log: &'static test::flash_log::FlashLog,
+        invariant_monitor: &'static test::invariant_monitor::InvariantMonitor,
+        syscall_recorder: &'static test::syscall_trace::SyscallRecorder,
+        scratch: &'static test::scratch::ScratchArena,
+        seed: &'static test::seed::TestSeed,
+        timeout: &'static test::timeout::TestTimeout,
+        peripheral_monitor: &'static test::peripheral_power::PeripheralMonitor,
+    ) -> Self {
         Self {
             test_index: Cell::new(0),
+            skipped: Cell::new(None),
             peripherals,
+            mux_alarm,
+            apps,
+            alarm_driver,
+            uart_mux,
+            scheduler,
+            shared_lock,
+            report_driver,
+            second_report_driver,
+            sleep_monitor,
+            switch_recorder,
+            watchdog,
+            flash_log,
+            invariant_monitor,
+            syscall_recorder,
+            scratch,
+            seed,
+            timeout,
+            // The core runs at 64 MHz.
+            timer: test::test_timer::TestTimer::new(cortexm4::dwt::Dwt::new(), 64),
+            peripheral_monitor,
+        }
+    }
+
+    /// Continues with the test after test `index`, which reset the board on
+    /// purpose or panicked.
+    fn resume_after(&self, index: usize) {
+        self.test_index.set(index + 1);
+    }
+
+    /// Marks the running test skipped for `reason`, before it reports.
+    pub(crate) fn skip(&self, reason: &'static str) {
+        self.skipped.set(Some(reason));
+    }
+
+    /// The test started last, and its index, if any.
+    fn current_test(&self) -> Option<(usize, &'static test::registry::KernelTest)> {
+        let index = self.test_index.get().checked_sub(1)?;
+        test::registry::test(index).map(|test| (index, test))
+    }
+
+    /// Drives the power analyzer sync pin, if any, high while a test runs.
+    fn set_power_sync(&self, running: bool) {
+        if let Some(pin) = test::config::BOARD_TEST_CONFIG.power_sync {
+            let pin = &self.peripherals.gpio_port[pin];
+            pin.make_output();
+            if running {
+                pin.set();
+            } else {
+                pin.clear();
+            }
         }
     }
 
     fn next(&'static self) {
+        // Tests that use pseudo-random data need the seed.
+        if !self.seed.chosen(self) {
+            return;
+        }
         let index = self.test_index.get();
+        let Some(test) = test::registry::test(index) else {
+            self.peripheral_monitor.check_suite();
+            test::reporter::reporter().suite_complete();
+            return;
+        };
+        // The flash tests take the NVMC over from the flash log.
+        if test.nvmc && !self.flash_log.suspend(self) {
+            return;
+        }
         self.test_index.increment();
-        match index {
-            0 => unsafe { test::sha256_test::run_sha256(self) },
-            1 => unsafe { test::hmac_sha256_test::run_hmacsha256(self) },
-            2 => unsafe { test::siphash24_test::run_siphash24(self) },
-            3 => unsafe { test::aes_test::run_aes128_ctr(&self.peripherals.ecb, self) },
-            4 => unsafe { test::aes_test::run_aes128_cbc(&self.peripherals.ecb, self) },
-            5 => unsafe { test::aes_test::run_aes128_ecb(&self.peripherals.ecb, self) },
-            6 => unsafe { test::ecdsa_p256_test::run_ecdsa_p256(self) },
-            _ => kernel::debug!("All tests finished."),
+        self.set_power_sync(true);
+        test::reporter::reporter().test_start(index, test.name);
+        self.timeout.start(test.timeout_ms(), self);
+        self.timer.start();
+        (test.run)(self, test);
+    }
+
+    /// Reports the result of the running test and starts the next one.
+    fn finish(&'static self, mut result: Result<Pass, Failure>) {
+        let duration = Duration {
+            ms: self.timeout.elapsed_ms(),
+            core_us: self.timer.elapsed_us(),
+        };
+        self.set_power_sync(false);
+        let index = self.test_index.get() - 1;
+        if let (Err(_), Some(seed)) = (&result, self.seed.take_used()) {
+            kernel::debug!("Test {} failed with seed {:#018x}", index, seed);
+        }
+        let violations = self.invariant_monitor.take_violations();
+        if violations > 0 {
+            kernel::debug!(
+                "Test {} failed: {} kernel invariants violated",
+                index,
+                violations
+            );
+            result = result.and(Err(Failure::Invariants(violations)));
         }
+        // A test that wrote to its scratch buffers after it finished shows
+        // up here, or with the next test.
+        let overruns = self.scratch.reset();
+        if overruns > 0 {
+            kernel::debug!(
+                "Test {} failed: scratch memory written outside its buffers",
+                index
+            );
+            result = result.and(Err(Failure::ScratchOverrun));
+        }
+        // The result line the host runner keys its results on.
+        test::reporter::report_result(index, result, Some(duration));
+        self.flash_log.resume();
+        self.next();
     }
 }
 impl CapsuleTestClient for TestLauncher {
-    fn done(&'static self, _result: Result<(), CapsuleTestError>) {
+    fn done(&'static self, result: Result<(), CapsuleTestError>) {
+        // A test that timed out may still report, after the launcher moved on.
+        let skipped = self.skipped.take();
+        if self.timeout.stop() {
+            let pass = skipped.map_or(Pass::Ran, Pass::Skipped);
+            self.finish(result.map(|()| pass).map_err(Failure::Test));
+        }
+    }
+}
+impl test::timeout::TestTimeoutClient for TestLauncher {
+    fn timed_out(&'static self, timeout_ms: u32) {
+        kernel::debug!(
+            "Test {} failed: no result after {} ms",
+            self.test_index.get() - 1,
+            timeout_ms
+        );
+        self.finish(Err(Failure::Timeout(timeout_ms)));
+    }
+}
+impl test::seed::TestSeedClient for TestLauncher {
+    fn seed_chosen(&'static self) {
+        // The tests start, so a kernel received over the UART works.
+        test::update::confirm(&self.peripherals.nrf52.pwr_clk);
         self.next();
     }
 }
+impl test::flash_log::FlashLogClient for TestLauncher {
+    fn log_idle(&'static self) {
+        self.next();
+    }
+}
+impl KernelTestSuite for TestLauncher {
+    fn start(&'static self) {
+        let dump_requested = test::config::BOARD_TEST_CONFIG
+            .debug_output
+            .flash_dump_button
+            .is_some_and(|pin| test::flash_log::button_held(&self.peripherals.gpio_port[pin]));
+        self.peripheral_monitor.record_baseline();
+        if dump_requested {
+            self.flash_log.dump(self);
+        } else {
+            self.next();
+        }
+    }
+}
 
 /// This is in a separate, inline(never) function so that its stack frame is
 /// removed when this function returns. Otherwise, the stack space used for
@@ -128,10 +326,10 @@ impl KernelResources<nrf52840::chip::NRF52<'static, Nrf52840DefaultPeripherals<'
     type SyscallDriverLookup = Self;
     type SyscallFilter = ();
     type ProcessFault = ();
-    type Scheduler = RoundRobinSched<'static>;
+    type Scheduler = test::scheduler::TestScheduler;
     type SchedulerTimer = cortexm4::systick::SysTick;
-    type WatchDog = ();
-    type ContextSwitchCallback = ();
+    type WatchDog = test::sleep_test::SleepMonitor;
+    type ContextSwitchCallback = test::context_switch_test::SwitchRecorder;
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         self
@@ -149,10 +347,10 @@ impl KernelResources<nrf52840::chip::NRF52<'static, Nrf52840DefaultPeripherals<'
         &self.systick
     }
     fn watchdog(&self) -> &Self::WatchDog {
-        &()
+        self.sleep_monitor
     }
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
-        &()
+        self.switch_recorder
     }
 }
 
@@ -163,6 +361,9 @@ pub unsafe fn main() {
     // INITIAL SETUP
     //--------------------------------------------------------------------------
 
+    // Start the kernel received over the UART, if any.
+    test::update::start_bank_b();
+
     // Apply errata fixes and enable interrupts.
     nrf52840::init();
 
@@ -234,12 +435,46 @@ pub unsafe fn main() {
     let uart_mux = components::console::UartMuxComponent::new(uart_channel, 115200)
         .finalize(components::uart_mux_component_static!());
 
-    // Create the debugger object that handles calls to `debug!()`.
-    components::debug_writer::DebugWriterComponent::new(
-        uart_mux,
+    // Create the debugger object that handles calls to `debug!()`. It writes
+    // both to the UART and to RTT, so results still reach the harness while a
+    // test uses the UART, and keeps the last output in flash.
+    let debug_uart = static_init!(UartDevice<'static>, UartDevice::new(uart_mux, false));
+    debug_uart.setup();
+    let rtt_memory = components::segger_rtt::SeggerRttMemoryComponent::new()
+        .finalize(components::segger_rtt_memory_component_static!());
+    let rtt = components::segger_rtt::SeggerRttComponent::new(mux_alarm, rtt_memory)
+        .finalize(components::segger_rtt_component_static!(nrf52840::rtc::Rtc));
+    let flash_log = test::flash_log::new_flash_log(&base_peripherals.nvmc, uart_mux, mux_alarm);
+    let debug_output = &test::config::BOARD_TEST_CONFIG.debug_output;
+    components::debug_writer::DebugWriterFanOutComponent::new(
+        [
+            (debug_uart, debug_output.uart),
+            (rtt, debug_output.rtt),
+            (flash_log, debug_output.flash),
+        ],
         create_capability!(capabilities::SetDebugWriterCapability),
     )
-    .finalize(components::debug_writer_component_static!());
+    .finalize(components::debug_writer_fan_out_component_static!(3));
+
+    // Kernel invariants that do not hold fail the test that was running.
+    let invariant_monitor = static_init!(
+        test::invariant_monitor::InvariantMonitor,
+        test::invariant_monitor::InvariantMonitor::new()
+    );
+    kernel::invariant::set_invariant_client(
+        invariant_monitor,
+        create_capability!(capabilities::SetInvariantClientCapability),
+    );
+
+    // Tests record the system calls of the app they check.
+    let syscall_recorder = static_init!(
+        test::syscall_trace::SyscallRecorder,
+        test::syscall_trace::SyscallRecorder::new()
+    );
+    kernel::syscall_trace::set_syscall_trace_client(
+        syscall_recorder,
+        create_capability!(capabilities::SyscallTraceCapability),
+    );
 
     //--------------------------------------------------------------------------
     // NRF CLOCK SETUP
@@ -251,21 +486,183 @@ pub unsafe fn main() {
     // PLATFORM AND SCHEDULER
     //--------------------------------------------------------------------------
 
-    let scheduler = components::sched::round_robin::RoundRobinComponent::new(processes)
+    let round_robin = components::sched::round_robin::RoundRobinComponent::new(processes)
         .finalize(components::round_robin_component_static!(NUM_PROCS));
+    // The priority inversion test switches to priority scheduling.
+    let priority = components::sched::priority::PriorityComponent::new(board_kernel)
+        .finalize(components::priority_component_static!());
+    let scheduler = static_init!(
+        test::scheduler::TestScheduler,
+        test::scheduler::TestScheduler::new(round_robin, priority)
+    );
+
+    // Tests check userspace readable allow with embedded apps that use this
+    // driver.
+    let memory_allocation_capability = create_capability!(capabilities::MemoryAllocationCapability);
+    let read_only_state = static_init!(
+        ReadOnlyStateDriver,
+        ReadOnlyStateDriver::new(
+            rtc,
+            board_kernel.create_grant(
+                capsules_extra::read_only_state::DRIVER_NUM,
+                &memory_allocation_capability
+            )
+        )
+    );
+
+    // The shared lock the priority inversion test's apps contend for.
+    let shared_lock = static_init!(
+        test::priority_inversion_test::SharedLock,
+        test::priority_inversion_test::SharedLock::new(board_kernel.create_grant(
+            test::embedded_apps::LOCK_DRIVER_NUM,
+            &memory_allocation_capability
+        ))
+    );
+
+    // The drivers the yield and upcall order tests' apps report to.
+    let report_driver = static_init!(
+        test::report_driver::ReportDriver,
+        test::report_driver::ReportDriver::new(board_kernel.create_grant(
+            test::embedded_apps::REPORT_DRIVER_NUM,
+            &memory_allocation_capability
+        ))
+    );
+    let second_report_driver = static_init!(
+        test::report_driver::ReportDriver,
+        test::report_driver::ReportDriver::new(board_kernel.create_grant(
+            test::embedded_apps::SECOND_REPORT_DRIVER_NUM,
+            &memory_allocation_capability
+        ))
+    );
+
+    // The watchdog of the kernel: the sleep test observes when the kernel
+    // suspends it, and the watchdog test counts the calls the chip watchdog
+    // gets.
+    let watchdog = static_init!(
+        test::watchdog_test::TestWatchdog,
+        test::watchdog_test::TestWatchdog::new(
+            test::config::BOARD_TEST_CONFIG
+                .watchdog_timeout_ms
+                .map(nrf52840::wdt::Wdt::new)
+        )
+    );
+    let sleep_monitor = static_init!(
+        test::sleep_test::SleepMonitor,
+        test::sleep_test::SleepMonitor::new(rtc, watchdog)
+    );
+    SLEEP_MONITOR = Some(sleep_monitor);
+
+    // The context switch callback, which passes switches on to the read-only
+    // state driver and lets the context switch test count them.
+    let switch_recorder = static_init!(
+        test::context_switch_test::SwitchRecorder,
+        test::context_switch_test::SwitchRecorder::new(read_only_state, scheduler)
+    );
 
     let platform = Platform {
+        read_only_state,
+        shared_lock,
+        report_driver,
+        second_report_driver,
         scheduler,
+        sleep_monitor,
+        switch_recorder,
         systick: cortexm4::systick::SysTick::new_with_calibration(64000000),
     };
 
-    let test_launcher = static_init!(TestLauncher, TestLauncher::new(base_peripherals));
+    // Grants must exist before the tests load processes. The alarm driver is
+    // not reachable from userspace; tests call it directly.
+    let alarm_driver = components::alarm::AlarmDriverComponent::new(
+        board_kernel,
+        capsules_core::alarm::DRIVER_NUM,
+        mux_alarm,
+    )
+    .finalize(components::alarm_component_static!(nrf52840::rtc::Rtc));
+
+    let apps = static_init!(
+        test::embedded_apps::AppLoader,
+        test::embedded_apps::AppLoader::new(board_kernel, chip)
+    );
+
+    // Temporary buffers of the tests, freed after each test.
+    let scratch = static_init!(
+        test::scratch::ScratchArena,
+        test::scratch::ScratchArena::new(static_init!([u32; 1024], [0; 1024]))
+    );
+
+    // Receives a new kernel over the UART, instead of the seed.
+    let updater = test::update::new_updater(
+        &base_peripherals.nvmc,
+        &base_peripherals.pwr_clk,
+        flash_log,
+        uart_mux,
+        mux_alarm,
+    );
+
+    // Seed of the tests that use pseudo-random data, which the UART can set.
+    let seed = test::seed::new_test_seed(&base_peripherals.trng, uart_mux, mux_alarm, updater);
+    // Fails a test that does not report its result in time.
+    let timeout = test::timeout::new_test_timeout(mux_alarm);
+    // Finds the peripherals tests leave on.
+    let peripheral_monitor = static_init!(
+        test::peripheral_power::PeripheralMonitor,
+        test::peripheral_power::PeripheralMonitor::new()
+    );
+
+    let test_launcher = static_init!(
+        TestLauncher,
+        TestLauncher::new(
+            nrf52840_peripherals,
+            mux_alarm,
+            apps,
+            alarm_driver,
+            uart_mux,
+            scheduler,
+            shared_lock,
+            report_driver,
+            second_report_driver,
+            sleep_monitor,
+            switch_recorder,
+            watchdog,
+            flash_log,
+            invariant_monitor,
+            syscall_recorder,
+            scratch,
+            seed,
+            timeout,
+            peripheral_monitor
+        )
+    );
+    TEST_LAUNCHER = Some(test_launcher);
 
     //--------------------------------------------------------------------------
     // TESTS
     //--------------------------------------------------------------------------
 
-    test_launcher.next();
+    test::chip_revision_test::print_header();
+    test::update::report_bank(&base_peripherals.pwr_clk);
+    test::registry::check_tests();
+    let test_count = test::registry::test_count();
+    // A reset that continues the run does not start the results over.
+    let resumed = test::panic_reset::restarted(&base_peripherals.pwr_clk)
+        || test::watchdog_test::reset_expected(&base_peripherals.pwr_clk);
+    // Lets the host runner check that every test of the image ran.
+    test::reporter::reporter().suite_start(test_count, resumed);
+    // Lets the host runner check that two boards run the tests together.
+    if let Some(peer) = test::config::BOARD_TEST_CONFIG.radio_peer.as_ref() {
+        kernel::debug!("Radio peer role: {:?}", peer.role);
+    }
+    if let Some(index) = test::panic_reset::panicked_test(&base_peripherals.pwr_clk) {
+        test_launcher.resume_after(index);
+    }
+    // The watchdog test is the last, and its result line follows the reset.
+    if let Some(result) = test::watchdog_test::resume_after_reset(&base_peripherals.pwr_clk) {
+        let result = result.map(|()| Pass::Ran).map_err(Failure::Test);
+        test::reporter::report_result(test_count - 1, result, None);
+        test_launcher.resume_after(test_count - 1);
+    }
+    components::test::kernel_test::TestRunnerComponent::new(test_launcher)
+        .finalize(components::test_runner_component_static!(TestLauncher));
 
     //--------------------------------------------------------------------------
     // KERNEL LOOP
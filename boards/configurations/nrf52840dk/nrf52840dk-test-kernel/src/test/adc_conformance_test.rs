@@ -0,0 +1,55 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the `hil::adc::AdcHighSpeed` conformance cases on the SAADC. The
+//! input pin may be left floating.
+//!
+//! The expected output ends with
+//! AdcHighSpeedConformance: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::test::conformance::adc::TestAdcHighSpeed;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::{static_buf, static_init};
+use nrf52840::adc::{Adc, AdcChannel, AdcChannelSetup};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+
+/// The SAADC timer divides 16 MHz, so this rate is produced exactly.
+const FREQUENCY_HZ: u32 = 10_000;
+
+/// Samples per buffer, 10 ms at `FREQUENCY_HZ`.
+const SAMPLES: usize = 100;
+
+const BUFFERS_TO_COLLECT: usize = 10;
+
+type AdcConformanceTest =
+    TestAdcHighSpeed<'static, Adc<'static>, VirtualMuxAlarm<'static, Rtc<'static>>>;
+
+pub unsafe fn run_adc_highspeed_conformance(
+    adc: &'static Adc<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let buffer1 = static_init!([u16; SAMPLES], [0; SAMPLES]);
+    let buffer2 = static_init!([u16; SAMPLES], [0; SAMPLES]);
+    let test = static_init!(
+        AdcConformanceTest,
+        TestAdcHighSpeed::new(
+            adc,
+            AdcChannelSetup::new(AdcChannel::AnalogInput1),
+            FREQUENCY_HZ,
+            SAMPLES,
+            BUFFERS_TO_COLLECT,
+            alarm,
+            buffer1,
+            buffer2
+        )
+    );
+    test.set_client(client);
+    test.run();
+}
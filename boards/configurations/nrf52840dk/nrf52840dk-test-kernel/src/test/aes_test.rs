@@ -33,22 +33,36 @@ use kernel::hil::symmetric_encryption::{AES128, AES128_BLOCK_SIZE, AES128_KEY_SI
 use kernel::static_init;
 use nrf52840::aes::AesECB;
 
-pub unsafe fn run_aes128_ctr(aes: &'static AesECB, client: &'static dyn CapsuleTestClient) {
-    let t = static_init_test_ctr(aes, client);
+use crate::test::scratch::ScratchArena;
+
+pub unsafe fn run_aes128_ctr(
+    aes: &'static AesECB,
+    scratch: &'static ScratchArena,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let t = static_init_test_ctr(aes, scratch, client);
     aes.set_client(t);
 
     t.run();
 }
 
-pub unsafe fn run_aes128_cbc(aes: &'static AesECB, client: &'static dyn CapsuleTestClient) {
-    let t = static_init_test_cbc(aes, client);
+pub unsafe fn run_aes128_cbc(
+    aes: &'static AesECB,
+    scratch: &'static ScratchArena,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let t = static_init_test_cbc(aes, scratch, client);
     aes.set_client(t);
 
     t.run();
 }
 
-pub unsafe fn run_aes128_ecb(aes: &'static AesECB, client: &'static dyn CapsuleTestClient) {
-    let t = static_init_test_ecb(aes, client);
+pub unsafe fn run_aes128_ecb(
+    aes: &'static AesECB,
+    scratch: &'static ScratchArena,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let t = static_init_test_ecb(aes, scratch, client);
     aes.set_client(t);
 
     t.run();
@@ -56,12 +70,13 @@ pub unsafe fn run_aes128_ecb(aes: &'static AesECB, client: &'static dyn CapsuleT
 
 unsafe fn static_init_test_ctr(
     aes: &'static AesECB,
+    scratch: &'static ScratchArena,
     client: &'static dyn CapsuleTestClient,
 ) -> &'static TestAes128Ctr<'static, AesECB<'static>> {
-    let source = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
-    let data = static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE]);
-    let key = static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE]);
-    let iv = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+    let source = scratch.buffer(4 * AES128_BLOCK_SIZE);
+    let data = scratch.buffer(6 * AES128_BLOCK_SIZE);
+    let key = scratch.buffer(AES128_KEY_SIZE);
+    let iv = scratch.buffer(AES128_BLOCK_SIZE);
 
     let test = static_init!(
         TestAes128Ctr<'static, AesECB>,
@@ -73,12 +88,13 @@ unsafe fn static_init_test_ctr(
 
 unsafe fn static_init_test_cbc(
     aes: &'static AesECB,
+    scratch: &'static ScratchArena,
     client: &'static dyn CapsuleTestClient,
 ) -> &'static TestAes128Cbc<'static, AesECB<'static>> {
-    let source = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
-    let data = static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE]);
-    let key = static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE]);
-    let iv = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+    let source = scratch.buffer(4 * AES128_BLOCK_SIZE);
+    let data = scratch.buffer(6 * AES128_BLOCK_SIZE);
+    let key = scratch.buffer(AES128_KEY_SIZE);
+    let iv = scratch.buffer(AES128_BLOCK_SIZE);
 
     let test = static_init!(
         TestAes128Cbc<'static, AesECB>,
@@ -90,11 +106,12 @@ unsafe fn static_init_test_cbc(
 
 unsafe fn static_init_test_ecb(
     aes: &'static AesECB,
+    scratch: &'static ScratchArena,
     client: &'static dyn CapsuleTestClient,
 ) -> &'static TestAes128Ecb<'static, AesECB<'static>> {
-    let source = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
-    let data = static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE]);
-    let key = static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE]);
+    let source = scratch.buffer(4 * AES128_BLOCK_SIZE);
+    let data = scratch.buffer(6 * AES128_BLOCK_SIZE);
+    let key = scratch.buffer(AES128_KEY_SIZE);
 
     let test = static_init!(
         TestAes128Ecb<'static, AesECB>,
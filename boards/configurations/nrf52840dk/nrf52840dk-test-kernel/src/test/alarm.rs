@@ -0,0 +1,24 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Virtual alarms for the tests.
+
+use core::mem::MaybeUninit;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use nrf52840::rtc::Rtc;
+
+pub(crate) type TestAlarm = VirtualMuxAlarm<'static, Rtc<'static>>;
+
+/// Sets up a virtual alarm of `mux_alarm` in `buffer`, from
+/// `static_buf!(TestAlarm)`. Not inlined, so the tests share its code.
+#[inline(never)]
+pub(crate) fn new_alarm(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    buffer: &'static mut MaybeUninit<TestAlarm>,
+) -> &'static TestAlarm {
+    let alarm = buffer.write(VirtualMuxAlarm::new(mux_alarm));
+    alarm.setup();
+    alarm
+}
@@ -0,0 +1,85 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Benchmarks code the kernel and the tests run often, with the cycle counter
+//! of the core, and prints the fewest, median and most cycles of each, so a
+//! regression shows up when the output of two runs is compared. Only code
+//! that finishes before it returns can be timed this way, and the benchmarks
+//! are:
+//!
+//! 1. `memcpy_1k`: copying 1 KiB between two scratch buffers.
+//! 2. `memcmp_1k`: comparing the two equal buffers.
+//! 3. `rng_1k`: filling a buffer with 1 KiB of `TestRng` data.
+//! 4. `mpu_switch`: setting up the MPU for one of two loaded apps after the
+//!    other, as every switch to a process that did not run last does.
+//! 5. `mpu_resume`: setting it up for the same app again, which the Cortex-M
+//!    MPU driver skips.
+//!
+//! The cycles depend on the build, so the benchmarks have no bounds to fail
+//! and the test always passes on a core with a cycle counter.
+//!
+//! The expected output ends with
+//! Bench: all cases passed
+
+use core::hint::black_box;
+
+use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use cortexm4::dwt::Dwt;
+use kernel::debug;
+use kernel::kernel_bench;
+use kernel::process::Process;
+use kernel::test::bench::{bench_mpu, BenchRunner};
+use kernel::test::rng::TestRng;
+use kernel::utilities::cells::OptionalCell;
+
+use crate::test::embedded_apps::{AppImage, AppLoader, IDLE_APP, YIELD_APP};
+use crate::test::registry::skip;
+use crate::test::scratch::ScratchArena;
+
+/// Bytes each benchmark works on.
+const LEN: usize = 1024;
+
+/// Timed runs of each benchmark.
+const RUNS: usize = 64;
+
+/// Apps the MPU is set up for. They are removed before they run.
+const MPU_APPS: [&AppImage; 2] = [&IDLE_APP, &YIELD_APP];
+
+pub unsafe fn run_bench(
+    scratch: &'static ScratchArena,
+    rng: TestRng,
+    apps: &'static AppLoader,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let cycles = Dwt::new();
+    if !cycles.is_cycle_counter_present() {
+        skip(client, "Bench: no cycle counter");
+        return;
+    }
+    let runner = BenchRunner::new(&cycles);
+    let src = scratch.buffer(LEN);
+    let dst = scratch.buffer(LEN);
+    rng.fill(src);
+
+    kernel_bench!(runner, "memcpy_1k", RUNS, {
+        black_box(&mut *dst).copy_from_slice(black_box(&*src))
+    });
+    kernel_bench!(runner, "memcmp_1k", RUNS, {
+        black_box(black_box(&*dst) == black_box(&*src));
+    });
+    kernel_bench!(runner, "rng_1k", RUNS, { rng.fill(black_box(&mut *dst)) });
+
+    let processes: [OptionalCell<&'static dyn Process>; 2] = Default::default();
+    let loaded = apps.load_all(&MPU_APPS, &processes);
+    if let (Ok(()), Some(first), Some(second)) = (loaded, processes[0].get(), processes[1].get()) {
+        bench_mpu(&runner, [first, second]);
+    }
+    if let Err(reason) = loaded.and(apps.remove_all(&processes)) {
+        debug!("Bench: mpu_switch failed: {}", reason);
+        client.done(Err(CapsuleTestError::IncorrectResult));
+        return;
+    }
+    debug!("Bench: all cases passed");
+    client.done(Ok(()));
+}
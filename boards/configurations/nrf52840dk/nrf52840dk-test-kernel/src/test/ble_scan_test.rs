@@ -0,0 +1,353 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Tests scanning for BLE advertisements, with a second DK listed in
+//! `BOARD_TEST_CONFIG.radio_peer` that runs the same image with the other
+//! role.
+//!
+//! The initiator sends ADV_NONCONN_IND PDUs with `ADDRESS` and the advertising
+//! data of the peer configuration on channels 37, 38 and 39, every
+//! `ADV_INTERVAL_MS`, for `ADV_EVENTS` advertising events. The responder
+//! listens on one advertising channel at a time and ignores the PDUs of other
+//! devices. The cases are:
+//!
+//! 1. `Parse`: a PDU from `ADDRESS` is an ADV_NONCONN_IND with a random
+//!    address, its length field matches the received length and its
+//!    advertising data is the configured data.
+//! 2. `Rssi`: the radio reports a signal strength between `MIN_RSSI_DBM` and
+//!    0 dBm for the PDU.
+//! 3. `Hopping`: the responder moves to the next channel after each PDU and
+//!    after `SCAN_WINDOW_MS` without one, and receives PDUs on all three
+//!    channels within `SCAN_WINDOWS` windows. The data whitening depends on
+//!    the channel, so a PDU with a valid CRC was sent on the channel it was
+//!    received on.
+//!
+//! The expected output on both boards ends with
+//! BleScan: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::ble_advertising::{self, BleAdvertisementDriver, RadioChannel};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::ble_radio::Radio;
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::{PeerRole, BOARD_TEST_CONFIG};
+use crate::test::registry::{report, skip};
+
+/// Static random address of the initiator, least significant byte first as
+/// on air. The two most significant bits are set.
+const ADDRESS: [u8; 6] = [0x57, 0x7e, 0x00, 0x00, 0x00, 0xc0];
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3
+const ADV_NONCONN_IND: u8 = 0b0010;
+const PDU_TYPE_MASK: u8 = 0b1111;
+const TXADD: u8 = 1 << 6;
+const LENGTH_MASK: u8 = 0x3f;
+const ADV_A_LEN: usize = 6;
+const MAX_ADV_DATA_LEN: usize = 31;
+const HEADER_LEN: usize = 2;
+const PDU_LEN: usize = HEADER_LEN + ADV_A_LEN + MAX_ADV_DATA_LEN;
+
+const CHANNELS: [RadioChannel; 3] = [
+    RadioChannel::AdvertisingChannel37,
+    RadioChannel::AdvertisingChannel38,
+    RadioChannel::AdvertisingChannel39,
+];
+
+/// Time between the advertising events of the initiator.
+const ADV_INTERVAL_MS: u32 = 30;
+
+/// Advertising events the initiator sends, for about 30 seconds.
+const ADV_EVENTS: u32 = 1000;
+
+/// Time the responder listens on a channel without receiving a PDU from the
+/// initiator before it moves on.
+const SCAN_WINDOW_MS: u32 = 100;
+
+/// Scan windows the responder waits for PDUs on all channels, about a minute.
+const SCAN_WINDOWS: u32 = 600;
+
+/// Weakest signal strength expected from a board within radio range.
+const MIN_RSSI_DBM: i8 = -100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Advertise,
+    Parse,
+    Rssi,
+    Hopping,
+}
+
+/// Writes an ADV_NONCONN_IND PDU from `ADDRESS` with `adv_data` to `buf`.
+/// Returns its length.
+fn write_pdu(buf: &mut [u8], adv_data: &[u8]) -> usize {
+    let payload_len = ADV_A_LEN + adv_data.len();
+    buf[0] = ADV_NONCONN_IND | TXADD;
+    buf[1] = payload_len as u8;
+    buf[HEADER_LEN..HEADER_LEN + ADV_A_LEN].copy_from_slice(&ADDRESS);
+    buf[HEADER_LEN + ADV_A_LEN..HEADER_LEN + payload_len].copy_from_slice(adv_data);
+    HEADER_LEN + payload_len
+}
+
+/// An advertising PDU the responder received.
+struct Advertisement<'b> {
+    pdu_type: u8,
+    random_address: bool,
+    address: &'b [u8],
+    data: &'b [u8],
+}
+
+/// Splits the advertising PDU in the first `len` bytes of `buf`, or returns
+/// `None` if its length field does not match `len`.
+fn parse_pdu(buf: &[u8], len: usize) -> Option<Advertisement<'_>> {
+    let pdu = buf.get(..len)?;
+    let payload_len = usize::from(*pdu.get(1)? & LENGTH_MASK);
+    if len != HEADER_LEN + payload_len || payload_len < ADV_A_LEN {
+        return None;
+    }
+    let (address, data) = pdu[HEADER_LEN..].split_at(ADV_A_LEN);
+    Some(Advertisement {
+        pdu_type: pdu[0] & PDU_TYPE_MASK,
+        random_address: pdu[0] & TXADD != 0,
+        address,
+        data,
+    })
+}
+
+pub unsafe fn run_ble_scan(
+    radio: &'static Radio<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(peer) = BOARD_TEST_CONFIG.radio_peer.as_ref() else {
+        skip(client, "BleScan: no peer board configured");
+        return;
+    };
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+    let buffer = static_init!([u8; PDU_LEN], [0; PDU_LEN]);
+    let test = static_init!(
+        TestBleScan,
+        TestBleScan::new(radio, alarm, peer.role, peer.adv_data, buffer)
+    );
+    alarm.set_alarm_client(test);
+    radio.set_transmit_client(test);
+    radio.set_receive_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestBleScan {
+    radio: &'static Radio<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    role: PeerRole,
+    adv_data: &'static [u8],
+    buffer: TakeCell<'static, [u8]>,
+    step: Cell<Step>,
+    /// Index into `CHANNELS` of the channel the radio uses.
+    channel: Cell<usize>,
+    /// Advertising events sent, or scan windows started.
+    count: Cell<u32>,
+    /// Channels with a PDU from the initiator, one bit per index into
+    /// `CHANNELS`.
+    received: Cell<u8>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestBleScan {
+    pub fn new(
+        radio: &'static Radio<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        role: PeerRole,
+        adv_data: &'static [u8],
+        buffer: &'static mut [u8],
+    ) -> Self {
+        TestBleScan {
+            radio,
+            alarm,
+            role,
+            adv_data,
+            buffer: TakeCell::new(buffer),
+            step: Cell::new(match role {
+                PeerRole::Initiator => Step::Advertise,
+                PeerRole::Responder => Step::Parse,
+            }),
+            channel: Cell::new(0),
+            count: Cell::new(0),
+            received: Cell::new(0),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if self.adv_data.len() > MAX_ADV_DATA_LEN {
+            self.fail("advertising data longer than 31 bytes");
+            return;
+        }
+        match self.role {
+            PeerRole::Initiator => self.advertise(),
+            PeerRole::Responder => self.scan(0),
+        }
+    }
+
+    /// Sends the PDU on the current channel.
+    fn advertise(&self) {
+        let Some(buffer) = self.buffer.take() else {
+            self.fail("transmit buffer missing");
+            return;
+        };
+        let len = write_pdu(buffer, self.adv_data);
+        self.radio
+            .transmit_advertisement(buffer, len, CHANNELS[self.channel.get()]);
+    }
+
+    /// Listens on `CHANNELS[channel]` for one scan window.
+    fn scan(&self, channel: usize) {
+        if self.count.get() == SCAN_WINDOWS {
+            debug!(
+                "BleScan: PDUs received on channels {:#05b} of 0b111",
+                self.received.get()
+            );
+            self.step.set(Step::Hopping);
+            self.fail("no PDU on every channel");
+            return;
+        }
+        self.count.set(self.count.get() + 1);
+        self.channel.set(channel);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(SCAN_WINDOW_MS));
+        self.radio.receive_advertisement(CHANNELS[channel]);
+    }
+
+    fn next_channel(&self) -> usize {
+        (self.channel.get() + 1) % CHANNELS.len()
+    }
+
+    fn check_pdu(&self, advertisement: &Advertisement) {
+        self.step.set(Step::Parse);
+        if advertisement.pdu_type != ADV_NONCONN_IND {
+            debug!("BleScan: PDU type {:#06b}", advertisement.pdu_type);
+            self.fail("not an ADV_NONCONN_IND");
+            return;
+        }
+        if !advertisement.random_address {
+            self.fail("TxAdd not set");
+            return;
+        }
+        if advertisement.data != self.adv_data {
+            self.fail("advertising data differs");
+            return;
+        }
+
+        self.step.set(Step::Rssi);
+        match self.radio.last_rssi_dbm() {
+            Some(rssi) if (MIN_RSSI_DBM..=0).contains(&rssi) => (),
+            Some(rssi) => {
+                debug!("BleScan: RSSI {} dBm", rssi);
+                self.fail("RSSI out of range");
+                return;
+            }
+            None => {
+                self.fail("no RSSI reported");
+                return;
+            }
+        }
+
+        self.step.set(Step::Hopping);
+        let received = self.received.get() | 1 << self.channel.get();
+        self.received.set(received);
+        if received == 0b111 {
+            self.finish(Ok(()));
+        } else {
+            let _ = self.alarm.disarm();
+            self.scan(self.next_channel());
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("BleScan: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        report(&self.client, "BleScan", result);
+    }
+}
+
+impl ble_advertising::TxClient for TestBleScan {
+    fn transmit_event(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.buffer.replace(buf);
+        if self.finished.get() {
+            return;
+        }
+        if result.is_err() {
+            self.fail("transmission failed");
+            return;
+        }
+        let next = self.next_channel();
+        self.channel.set(next);
+        if next != 0 {
+            self.advertise();
+        } else {
+            self.count.set(self.count.get() + 1);
+            if self.count.get() == ADV_EVENTS {
+                self.finish(Ok(()));
+            } else {
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ADV_INTERVAL_MS));
+            }
+        }
+    }
+}
+
+impl ble_advertising::RxClient for TestBleScan {
+    fn receive_event(&self, buf: &'static mut [u8], len: u8, result: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        let advertisement = result
+            .ok()
+            .and_then(|()| parse_pdu(buf, usize::from(len)))
+            .filter(|advertisement| advertisement.address == ADDRESS);
+        match advertisement {
+            Some(advertisement) => self.check_pdu(&advertisement),
+            // A corrupted PDU or one from another device, keep listening.
+            None => self
+                .radio
+                .receive_advertisement(CHANNELS[self.channel.get()]),
+        }
+    }
+}
+
+impl AlarmClient for TestBleScan {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        match self.role {
+            PeerRole::Initiator => self.advertise(),
+            PeerRole::Responder => self.scan(self.next_channel()),
+        }
+    }
+}
+
+impl CapsuleTest for TestBleScan {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
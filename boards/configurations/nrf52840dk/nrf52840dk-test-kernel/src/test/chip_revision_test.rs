@@ -0,0 +1,58 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Identifies the chip the suite runs on. [`print_header()`] prints the FICR
+//! identification, and the startup errata workarounds `nrf52840::init()`
+//! applies, before the first test, so results from a different chip revision
+//! stand out in the logs. The test checks that the FICR reports an nRF52840
+//! whose variant, and so hardware revision, the chip crate recognizes.
+//!
+//! The expected output ends with
+//! ChipRevision: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use nrf52840::crt1::NRF52832_STARTUP_ERRATA;
+use nrf52840::ficr::{Part, FICR_INSTANCE};
+
+/// Prints the chip identification as the header of the test output.
+pub fn print_header() {
+    // SAFETY: the FICR instance is never written.
+    let ficr = unsafe { &*core::ptr::addr_of!(FICR_INSTANCE) };
+    debug!("{}", ficr);
+    debug!(
+        "Hardware revision: {}, nRF52832 startup errata workarounds: {:?}",
+        ficr.hardware_revision().unwrap_or('?'),
+        NRF52832_STARTUP_ERRATA
+    );
+}
+
+pub unsafe fn run_chip_revision(client: &'static dyn CapsuleTestClient) {
+    if let Err(reason) = check_chip_revision() {
+        debug!("ChipRevision: Identify failed: {}", reason);
+        client.done(Err(CapsuleTestError::IncorrectResult));
+        return;
+    }
+    debug!("ChipRevision: all cases passed");
+    client.done(Ok(()));
+}
+
+fn ensure(condition: bool, reason: &'static str) -> Result<(), &'static str> {
+    if condition {
+        Ok(())
+    } else {
+        Err(reason)
+    }
+}
+
+fn check_chip_revision() -> Result<(), &'static str> {
+    // SAFETY: as in `print_header()`.
+    let ficr = unsafe { &*core::ptr::addr_of!(FICR_INSTANCE) };
+
+    ensure(ficr.part() == Part::N52840, "not an nRF52840")?;
+    ensure(
+        ficr.hardware_revision().is_some(),
+        "variant not recognized by the chip crate",
+    )
+}
@@ -0,0 +1,204 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that setting up a component twice, as happens when a board calls
+//! `setup()` on a device its component already set up, is harmless. The
+//! cases are:
+//!
+//! 1. `AlarmSetup`: two virtual alarms that were each set up twice on the
+//!    board's alarm mux fire once each.
+//! 2. `UartSetup`: a virtual UART device that was set up twice on the
+//!    board's UART mux transmits a line once.
+//! 3. `DebugWriter`: a second debug writer replaces the first one. The last
+//!    line of the test is printed through it.
+//!
+//! The expected output ends with
+//! ComponentSetup: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use kernel::component::Component;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::hil::uart::{Transmit, TransmitClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{capabilities, create_capability, debug, static_init, ErrorCode};
+use nrf52840::rtc::Rtc;
+
+use crate::test::registry::report;
+
+/// Line transmitted through the test's UART device.
+const UART_LINE: &[u8] = b"ComponentSetup: line from a device set up twice\r\n";
+
+/// Delays of the two virtual alarms.
+const ALARM_DELAYS_MS: [u32; 2] = [10, 20];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    AlarmSetup,
+    UartSetup,
+    DebugWriter,
+}
+
+pub unsafe fn run_component_setup(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    uart_mux: &'static MuxUart<'static>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarms = static_init!(
+        [VirtualMuxAlarm<'static, Rtc<'static>>; 2],
+        [
+            VirtualMuxAlarm::new(mux_alarm),
+            VirtualMuxAlarm::new(mux_alarm)
+        ]
+    );
+    for alarm in alarms.iter() {
+        alarm.setup();
+        alarm.setup();
+    }
+
+    let uart_device = static_init!(UartDevice<'static>, UartDevice::new(uart_mux, false));
+    uart_device.setup();
+    uart_device.setup();
+
+    let buffer = static_init!([u8; UART_LINE.len()], [0; UART_LINE.len()]);
+    buffer.copy_from_slice(UART_LINE);
+
+    let test = static_init!(
+        TestComponentSetup,
+        TestComponentSetup::new(alarms, uart_device, uart_mux, buffer)
+    );
+    for alarm in alarms.iter() {
+        alarm.set_alarm_client(test);
+    }
+    uart_device.set_transmit_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestComponentSetup {
+    alarms: &'static [VirtualMuxAlarm<'static, Rtc<'static>>; 2],
+    uart_device: &'static UartDevice<'static>,
+    uart_mux: &'static MuxUart<'static>,
+    buffer: TakeCell<'static, [u8]>,
+    fired: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestComponentSetup {
+    pub fn new(
+        alarms: &'static [VirtualMuxAlarm<'static, Rtc<'static>>; 2],
+        uart_device: &'static UartDevice<'static>,
+        uart_mux: &'static MuxUart<'static>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        TestComponentSetup {
+            alarms,
+            uart_device,
+            uart_mux,
+            buffer: TakeCell::new(buffer),
+            fired: Cell::new(0),
+            step: Cell::new(Step::AlarmSetup),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        for (alarm, delay) in self.alarms.iter().zip(ALARM_DELAYS_MS) {
+            alarm.set_alarm(alarm.now(), alarm.ticks_from_ms(delay));
+        }
+    }
+
+    fn transmit(&self) {
+        self.step.set(Step::UartSetup);
+        let Some(buffer) = self.buffer.take() else {
+            self.fail("buffer missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        if let Err((e, buffer)) = self.uart_device.transmit_buffer(buffer, UART_LINE.len()) {
+            self.buffer.replace(buffer);
+            self.fail("transmit_buffer rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    fn replace_debug_writer(&self) {
+        self.step.set(Step::DebugWriter);
+        // SAFETY: this step runs once, so the component's static buffers are
+        // not aliased.
+        unsafe {
+            components::debug_writer::DebugWriterComponent::new(
+                self.uart_mux,
+                create_capability!(capabilities::SetDebugWriterCapability),
+            )
+            .finalize(components::debug_writer_component_static!(1));
+        }
+        self.finish(Ok(()));
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("ComponentSetup: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "ComponentSetup", result);
+    }
+}
+
+impl AlarmClient for TestComponentSetup {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let fired = self.fired.get() + 1;
+        self.fired.set(fired);
+        match fired.cmp(&self.alarms.len()) {
+            core::cmp::Ordering::Less => {}
+            core::cmp::Ordering::Equal => self.transmit(),
+            core::cmp::Ordering::Greater => {
+                self.fail("alarm fired twice", CapsuleTestError::IncorrectResult)
+            }
+        }
+    }
+}
+
+impl TransmitClient for TestComponentSetup {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+        rcode: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(tx_buffer);
+        if self.finished.get() {
+            return;
+        }
+        if let Err(e) = rcode {
+            self.fail("transmission failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        if tx_len != UART_LINE.len() {
+            self.fail(
+                "wrong length transmitted",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        self.replace_debug_writer();
+    }
+}
+
+impl CapsuleTest for TestComponentSetup {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,282 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Description of the external wiring available to tests on this board.
+//!
+//! Tests that need jumpers or attached devices read them from
+//! [`BOARD_TEST_CONFIG`] and skip themselves when the entry is `None`, so the
+//! same image runs on a bare DK and on a fully wired test rig.
+
+use capsules_core::test::conformance::i2c::I2cTarget;
+use kernel::hil::radio::RadioChannel;
+use nrf52840::gpio::Pin;
+
+/// Two pins connected by a jumper wire.
+pub(crate) struct PinPair {
+    /// Pin the test drives.
+    pub output: Pin,
+    /// Pin the test observes.
+    pub input: Pin,
+}
+
+/// SPI bus pins, with chip select jumpered to an observer input.
+pub(crate) struct SpiChipSelectPins {
+    pub sck: Pin,
+    pub mosi: Pin,
+    pub miso: Pin,
+    pub chip_select: Pin,
+    /// Input pin connected to `chip_select`.
+    pub observer: Pin,
+}
+
+/// I2C bus pins and the register-based device attached to them.
+pub(crate) struct I2cTargetConfig {
+    pub scl: Pin,
+    pub sda: Pin,
+    pub target: I2cTarget,
+}
+
+/// Pins of an I2C master and an I2C slave peripheral, with the two SCL pins
+/// and the two SDA pins connected by jumper wires.
+pub(crate) struct I2cLoopbackPins {
+    pub master_scl: Pin,
+    pub master_sda: Pin,
+    pub slave_scl: Pin,
+    pub slave_sda: Pin,
+    /// Pin jumpered to the SDA line, which the bus recovery test holds low
+    /// as a stuck slave does.
+    pub stuck_sda: Option<Pin>,
+}
+
+/// Pins of an SPI slave peripheral, each connected by a jumper wire to the
+/// pin of the same signal in `spi_chip_select`.
+pub(crate) struct SpiSlavePins {
+    pub sck: Pin,
+    pub mosi: Pin,
+    pub miso: Pin,
+    /// Connected to `chip_select`.
+    pub csn: Pin,
+}
+
+/// Sensor models the sensor plausibility test knows how to drive.
+// Only constructed by test rigs that list attached sensors.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub(crate) enum SensorModel {
+    Bme280,
+    Bmp280,
+    Sht4x,
+}
+
+/// An environment sensor on the sensor I2C bus.
+pub(crate) struct AttachedSensor {
+    pub model: SensorModel,
+    /// 7-bit I2C address of the sensor.
+    pub address: u8,
+}
+
+/// I2C bus with environment sensors attached. Each model may be listed once.
+pub(crate) struct SensorBus {
+    pub scl: Pin,
+    pub sda: Pin,
+    pub sensors: &'static [AttachedSensor],
+}
+
+/// SPI bus pins of an SX1276/77/78/79 LoRa module with a dummy load on its
+/// antenna port.
+pub(crate) struct LoRaModule {
+    pub sck: Pin,
+    pub mosi: Pin,
+    pub miso: Pin,
+    pub chip_select: Pin,
+    /// Pin connected to the module's NRESET line, if any.
+    pub reset: Option<Pin>,
+    /// Carrier frequency the module is built for, for example 868_100_000.
+    pub frequency_hz: u32,
+}
+
+/// INA219 current monitor with its shunt in the supply of the nRF52840, for
+/// example in place of the jumper on P22 of the DK after SB40 is cut, and the
+/// current the board may draw.
+pub(crate) struct CurrentMonitor {
+    pub scl: Pin,
+    pub sda: Pin,
+    /// 7-bit I2C address of the INA219, 0x40 with A0 and A1 grounded.
+    pub address: u8,
+    /// Resistance of the shunt, in milliohms, not zero. The INA219 resolves
+    /// 10 uV across it, so 10_000 resolves 1 uA up to 32 mA.
+    pub shunt_milliohms: u32,
+    /// Highest current, in microamps, the board may draw while the chip
+    /// sleeps.
+    pub sleep_max_ua: u32,
+    /// Highest current, in microamps, the board may draw while a process
+    /// keeps the core busy.
+    pub active_max_ua: u32,
+}
+
+/// Role of this board in the tests that need a second DK.
+// Only constructed by test rigs with two boards.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum PeerRole {
+    /// Sends frames and checks which of them the peer acknowledges.
+    Initiator,
+    /// Acknowledges frames and checks which of them its MAC layer accepts.
+    Responder,
+}
+
+/// A second DK within radio range that runs this image with the other role.
+pub(crate) struct RadioPeer {
+    pub role: PeerRole,
+    /// 802.15.4 channel both boards use, for example the quietest channel
+    /// the energy scan test reports.
+    pub channel: RadioChannel,
+    /// Advertising data the initiator broadcasts in the BLE scan test, at
+    /// most 31 bytes, for example `b"\x05\x09tock"` for the complete local
+    /// name "tock".
+    pub adv_data: &'static [u8],
+}
+
+/// What the panic handler does once it printed the panic.
+// `Reset` is only set by test rigs that run unattended.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum PanicPolicy {
+    /// Blink the kernel LED until the board is reset.
+    Halt,
+    /// Reset the board and continue with the test after the one that
+    /// panicked, which the launcher reports failed.
+    Reset,
+}
+
+/// How the launcher prints the test results, which selects its
+/// `TestReporter`.
+// `Tap` is only set by test rigs that read TAP.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum ResultFormat {
+    /// The lines the kernel test runner reads, `Test <index> passed`.
+    Launcher,
+    /// TAP 14, `ok <number> - <name>`.
+    Tap,
+    /// One JSON object per line, `{"event":"test_result",...}`.
+    JsonLines,
+}
+
+/// Channels debug output, and with it the test results, is written to.
+pub(crate) struct DebugOutput {
+    /// Write to the console UART.
+    pub uart: bool,
+    /// Write to the SEGGER RTT up buffer, which a debug probe reads without
+    /// using the UART, for example while a test drives the UART itself.
+    pub rtt: bool,
+    /// Keep the last output in flash. Flash writes stall the core and upset
+    /// timing tests, so enable this for long runs only.
+    pub flash: bool,
+    /// Button that, held while the board boots, dumps the flash log over the
+    /// UART before the tests run.
+    pub flash_dump_button: Option<Pin>,
+}
+
+/// External hardware the tests may rely on.
+pub(crate) struct BoardTestConfig {
+    /// Jumpered pins for the GPIO loopback tests.
+    pub gpio_loopback: Option<PinPair>,
+    /// Pins for the SPI chip select tests, also used by the wiretap test.
+    pub spi_chip_select: Option<SpiChipSelectPins>,
+    /// Device for the I2C transfer and NACK tests.
+    pub i2c_target: Option<I2cTargetConfig>,
+    /// Jumpered pins for the I2C master/slave loopback test.
+    pub i2c_loopback: Option<I2cLoopbackPins>,
+    /// Jumpered pins for the SPI master/slave loopback test.
+    pub spi_slave: Option<SpiSlavePins>,
+    /// Jumpered pins for the UART loopback test, the TXD of UARTE1 as the
+    /// output and its RXD as the input.
+    pub uart_loopback: Option<PinPair>,
+    /// Sensors checked by the sensor plausibility test, for example
+    /// `AttachedSensor { model: SensorModel::Bme280, address: 0x76 }`.
+    pub sensors: Option<SensorBus>,
+    /// LoRa module for the SX127x test.
+    pub lora: Option<LoRaModule>,
+    /// Second board for the two-board radio tests.
+    pub radio_peer: Option<RadioPeer>,
+    /// Current monitor and budgets for the current consumption test.
+    pub current_monitor: Option<CurrentMonitor>,
+    /// Whether the nRF USB port is connected to a host that enumerates the
+    /// devices the USB tests attach. The keyboard test presses F13 on it.
+    pub usb_host: bool,
+    /// Time each test gets to report its result, in milliseconds, before the
+    /// launcher reports it failed and starts the next test, or `None` to wait
+    /// for every test however long it takes. The registry gives tests that
+    /// take longer a timeout of their own.
+    pub test_timeout_ms: Option<u32>,
+    /// Timeout of the chip watchdog the kernel tickles, in milliseconds, or
+    /// `None` to leave the watchdog off. Once started, the watchdog runs until
+    /// the next reset, so every test runs under it.
+    pub watchdog_timeout_ms: Option<u32>,
+    /// Pin the test launcher holds high while each test runs. Wired to a
+    /// digital input of a power analyzer, such as a PPK2 or a Joulescope, it
+    /// lets the kernel test runner split a capture into the tests.
+    pub power_sync: Option<Pin>,
+    /// Channels that receive debug output from boot on.
+    pub debug_output: DebugOutput,
+    /// Format of the result lines: the ones the kernel test runner reads,
+    /// TAP 14 for off-the-shelf TAP consumers, or JSON lines for other CI
+    /// tools. The runner reads the first two.
+    pub result_format: ResultFormat,
+    /// What happens after a panic. `Reset` lets an unattended run get past
+    /// a test that panics, at the cost of the state the halted board keeps
+    /// for a debugger.
+    pub panic_policy: PanicPolicy,
+}
+
+pub(crate) const BOARD_TEST_CONFIG: BoardTestConfig = BoardTestConfig {
+    gpio_loopback: Some(PinPair {
+        output: Pin::P1_01,
+        input: Pin::P1_02,
+    }),
+    spi_chip_select: Some(SpiChipSelectPins {
+        sck: Pin::P1_03,
+        mosi: Pin::P1_04,
+        miso: Pin::P1_05,
+        chip_select: Pin::P1_06,
+        observer: Pin::P1_07,
+    }),
+    // The DK has no I2C device on board.
+    i2c_target: None,
+    i2c_loopback: Some(I2cLoopbackPins {
+        master_scl: Pin::P1_08,
+        master_sda: Pin::P1_09,
+        slave_scl: Pin::P1_10,
+        slave_sda: Pin::P1_11,
+        stuck_sda: Some(Pin::P1_12),
+    }),
+    spi_slave: Some(SpiSlavePins {
+        sck: Pin::P1_13,
+        mosi: Pin::P1_14,
+        miso: Pin::P1_15,
+        csn: Pin::P0_26,
+    }),
+    uart_loopback: Some(PinPair {
+        output: Pin::P0_27,
+        input: Pin::P0_28,
+    }),
+    sensors: None,
+    lora: None,
+    radio_peer: None,
+    current_monitor: None,
+    usb_host: false,
+    test_timeout_ms: Some(60_000),
+    watchdog_timeout_ms: Some(5_000),
+    power_sync: None,
+    debug_output: DebugOutput {
+        uart: true,
+        rtt: true,
+        flash: false,
+        // Button 1.
+        flash_dump_button: Some(Pin::P0_11),
+    },
+    result_format: ResultFormat::Launcher,
+    panic_policy: PanicPolicy::Halt,
+};
@@ -0,0 +1,255 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that the kernel calls the `ContextSwitchCallback` of the board
+//! before every switch to a process, with the process it switches to. The
+//! board's callback is a `SwitchRecorder`, which passes the call on to the
+//! read-only state driver and counts the calls for the processes the test
+//! watches. Two apps that spin and one that yield-waits forever run under
+//! round robin scheduling. The cases are:
+//!
+//! 1. `Attribute`: every call is for the process the scheduler decided to
+//!    run, which is about to run, and only the watched processes run.
+//! 2. `Count`: each process got a call for every time it stopped executing,
+//!    by a system call or an expired timeslice, so no switch went unnoticed.
+//!    The spinning apps were switched to repeatedly.
+//!
+//! The expected output ends with
+//! ContextSwitch: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::platform::ContextSwitchCallback;
+use kernel::process::{Process, ProcessId, State};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppImage, AppLoader, BUSY_APP, IDLE_APP, SPINNER_APP};
+use crate::test::registry::report;
+use crate::test::scheduler::TestScheduler;
+
+/// Number of processes the recorder can watch.
+const WATCHED: usize = 3;
+
+/// Time the apps run for.
+const RUN_MS: u32 = 200;
+
+/// Default timeslice of the round robin scheduler the board uses.
+const TIMESLICE_MS: u32 = 10;
+
+/// Apps the test runs, the spinning ones first.
+const APPS: [&AppImage; WATCHED] = [&BUSY_APP, &SPINNER_APP, &IDLE_APP];
+
+/// A context switch callback that counts the switches to the processes a test
+/// watches, and checks each switch against the scheduler's decision.
+pub struct SwitchRecorder {
+    inner: &'static dyn ContextSwitchCallback,
+    scheduler: &'static TestScheduler,
+    watched: [OptionalCell<ProcessId>; WATCHED],
+    /// Number of calls for each watched process.
+    counts: [Cell<usize>; WATCHED],
+    /// Number of calls for processes the test does not watch.
+    unwatched: Cell<usize>,
+    /// Number of calls for a process that is not running, or that the
+    /// scheduler did not decide to run.
+    misattributed: Cell<usize>,
+}
+
+impl SwitchRecorder {
+    pub fn new(
+        inner: &'static dyn ContextSwitchCallback,
+        scheduler: &'static TestScheduler,
+    ) -> Self {
+        SwitchRecorder {
+            inner,
+            scheduler,
+            watched: Default::default(),
+            counts: Default::default(),
+            unwatched: Cell::new(0),
+            misattributed: Cell::new(0),
+        }
+    }
+
+    /// Starts counting the calls for `processes` afresh.
+    fn watch(&self, processes: [ProcessId; WATCHED]) {
+        for ((watched, count), processid) in self.watched.iter().zip(&self.counts).zip(processes) {
+            watched.set(processid);
+            count.set(0);
+        }
+        self.unwatched.set(0);
+        self.misattributed.set(0);
+    }
+
+    /// Stops counting, and returns the calls for each watched process.
+    fn unwatch(&self) -> [usize; WATCHED] {
+        self.watched.iter().for_each(OptionalCell::clear);
+        self.counts.each_ref().map(Cell::get)
+    }
+}
+
+impl ContextSwitchCallback for SwitchRecorder {
+    fn context_switch_hook(&self, process: &dyn Process) {
+        self.inner.context_switch_hook(process);
+
+        let processid = process.processid();
+        if process.get_state() != State::Running || self.scheduler.chosen() != Some(processid) {
+            self.misattributed.set(self.misattributed.get() + 1);
+        }
+        match self
+            .watched
+            .iter()
+            .position(|watched| watched.get() == Some(processid))
+        {
+            Some(index) => self.counts[index].set(self.counts[index].get() + 1),
+            None => self.unwatched.set(self.unwatched.get() + 1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Attribute,
+    Count,
+}
+
+pub unsafe fn run_context_switch(
+    apps: &'static AppLoader,
+    recorder: &'static SwitchRecorder,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestContextSwitch,
+        TestContextSwitch::new(apps, recorder, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestContextSwitch {
+    apps: &'static AppLoader,
+    recorder: &'static SwitchRecorder,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    processes: [OptionalCell<&'static dyn Process>; WATCHED],
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestContextSwitch {
+    pub fn new(
+        apps: &'static AppLoader,
+        recorder: &'static SwitchRecorder,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestContextSwitch {
+            apps,
+            recorder,
+            alarm,
+            processes: Default::default(),
+            step: Cell::new(Step::Attribute),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.start() {
+            self.fail(reason);
+        }
+    }
+
+    /// Loads the apps and watches them. The apps do not run before this
+    /// returns, so the recorder sees their first switch.
+    fn start(&self) -> Result<(), &'static str> {
+        self.apps.load_all(&APPS, &self.processes)?;
+        let mut processids = [None; WATCHED];
+        for (process, processid) in self.processes.iter().zip(&mut processids) {
+            *processid = process.map(|process| process.processid());
+        }
+        self.recorder.watch(processids.map(Option::unwrap));
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RUN_MS));
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        let counts = self.recorder.unwatch();
+        let misattributed = self.recorder.misattributed.get();
+        let unwatched = self.recorder.unwatched.get();
+        debug!(
+            "ContextSwitch: switches {:?}, {} misattributed, {} to other processes",
+            counts, misattributed, unwatched
+        );
+        if misattributed != 0 {
+            return Err("callback called for a process the kernel did not run");
+        }
+        if unwatched != 0 {
+            return Err("callback called for an unexpected process");
+        }
+
+        self.step.set(Step::Count);
+        for (process, count) in self.processes.iter().zip(counts) {
+            let process = process.get().ok_or("app not loaded")?;
+            let stops = process.debug_syscall_count() + process.debug_timeslice_expiration_count();
+            if count < stops {
+                debug!(
+                    "ContextSwitch: {} switched to {} times, stopped {} times",
+                    process.get_process_name(),
+                    count,
+                    stops
+                );
+                return Err("switch to a process without a callback");
+            }
+        }
+        if counts[..2]
+            .iter()
+            .any(|&count| count < (RUN_MS / TIMESLICE_MS / 2) as usize)
+        {
+            return Err("spinning apps not switched to repeatedly");
+        }
+        self.apps.remove_all(&self.processes)?;
+        self.finish(Ok(()));
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("ContextSwitch: {:?} failed: {}", self.step.get(), reason);
+        let _ = self.apps.remove_all(&self.processes);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "ContextSwitch", result);
+    }
+}
+
+impl AlarmClient for TestContextSwitch {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestContextSwitch {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
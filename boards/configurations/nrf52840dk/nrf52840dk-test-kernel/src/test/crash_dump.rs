@@ -0,0 +1,176 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Writes a crash dump after the panic report, between a start and an end
+//! marker, so the host runner can pick it out of the output:
+//!
+//! ```text
+//! --- crash dump start ---
+//! test 12 aes128_ctr
+//! register sp 0x2000f1a0
+//! register cfsr 0x00008200
+//! frame pc 0x0001a2b4
+//! stack 0x2000f1a0 0x00000000 0x2000f1c8 0x0001a2b5 0x20003c10
+//! sleep 41 0x0000a2f0 0x0000a30c
+//! process 0 slot_one Running pc 0x00041a2c sp 0x20008f60 restarts 1 grants 2 grant_region 0x000003c0 mpu 0x20009000+0x00000100
+//! --- crash dump end ---
+//! ```
+//!
+//! Each line is a keyword and its values, separated by spaces. Register
+//! values, addresses and ticks are in hexadecimal, with `0x`:
+//!
+//! - `test <index> <name>`: the test the launcher started last.
+//! - `register <name> <value>`: the core registers at the panic, and the
+//!   fault status registers.
+//! - `frame <name> <value>`: the registers the hardware stacked on a hard
+//!   fault in the kernel, if the panic comes from one.
+//! - `stack <address> <word>...`: the kernel stack, from the stack pointer
+//!   at the panic to its top, eight words per line.
+//! - `sleep <number> <start> <end>`: the trace of the last sleeps the sleep
+//!   monitor keeps, oldest first, in RTC ticks.
+//! - `process <slot> <name> <state> ...`: the `ProcessSnapshot` of each
+//!   process, with `-` for values the kernel does not know, and the start and
+//!   size of each MPU region added to the process.
+
+use core::arch::asm;
+use core::fmt::Write;
+
+use kernel::process::{ProcessSlot, ProcessSnapshot};
+
+use crate::test::registry::KernelTest;
+use crate::test::sleep_test::SleepMonitor;
+
+pub const DUMP_START: &str = "--- crash dump start ---";
+pub const DUMP_END: &str = "--- crash dump end ---";
+
+/// Number of stack words on each `stack` line.
+const WORDS_PER_LINE: usize = 8;
+
+/// Fault status registers of the system control block.
+const FAULT_REGISTERS: [(&str, usize); 6] = [
+    ("icsr", 0xE000_ED04),
+    ("shcsr", 0xE000_ED24),
+    ("cfsr", 0xE000_ED28),
+    ("hfsr", 0xE000_ED2C),
+    ("mmfar", 0xE000_ED34),
+    ("bfar", 0xE000_ED38),
+];
+
+const FRAME_REGISTERS: [&str; 8] = ["r0", "r1", "r2", "r3", "r12", "lr", "pc", "xpsr"];
+
+extern "C" {
+    static _estack: u8;
+}
+
+/// Writes the dump. `test` is the test the launcher started last, if any.
+///
+/// **NOTE:** `writer` must be synchronous.
+pub unsafe fn write(
+    writer: &mut dyn Write,
+    test: Option<(usize, &KernelTest)>,
+    sleep_monitor: Option<&SleepMonitor>,
+    processes: &[ProcessSlot],
+) {
+    let sp: u32;
+    let control: u32;
+    let ipsr: u32;
+    let primask: u32;
+    let basepri: u32;
+    asm!(
+        "mov {sp}, sp",
+        "mrs {control}, CONTROL",
+        "mrs {ipsr}, IPSR",
+        "mrs {primask}, PRIMASK",
+        "mrs {basepri}, BASEPRI",
+        sp = out(reg) sp,
+        control = out(reg) control,
+        ipsr = out(reg) ipsr,
+        primask = out(reg) primask,
+        basepri = out(reg) basepri,
+        options(nomem, nostack),
+    );
+
+    let _ = write!(writer, "\r\n{}\r\n", DUMP_START);
+    if let Some((index, test)) = test {
+        let _ = write!(writer, "test {} {}\r\n", index, test.name);
+    }
+    for (name, value) in [
+        ("sp", sp),
+        ("control", control),
+        ("ipsr", ipsr),
+        ("primask", primask),
+        ("basepri", basepri),
+    ] {
+        let _ = write!(writer, "register {} {:#010x}\r\n", name, value);
+    }
+    for (name, address) in FAULT_REGISTERS {
+        let value = (address as *const u32).read_volatile();
+        let _ = write!(writer, "register {} {:#010x}\r\n", name, value);
+    }
+    if let Some(frame) = cortexm4::kernel_fault_frame() {
+        for (name, value) in FRAME_REGISTERS.iter().zip(frame) {
+            let _ = write!(writer, "frame {} {:#010x}\r\n", name, value);
+        }
+    }
+
+    let top = core::ptr::addr_of!(_estack) as usize;
+    let mut address = sp as usize & !3;
+    while address < top {
+        let _ = write!(writer, "stack {:#010x}", address);
+        for _ in 0..WORDS_PER_LINE {
+            if address >= top {
+                break;
+            }
+            let _ = write!(writer, " {:#010x}", (address as *const u32).read_volatile());
+            address += 4;
+        }
+        let _ = write!(writer, "\r\n");
+    }
+
+    if let Some(monitor) = sleep_monitor {
+        for (n, start, end) in monitor.trace() {
+            let _ = write!(writer, "sleep {} {:#010x} {:#010x}\r\n", n, start, end);
+        }
+    }
+    for (slot, process) in processes.iter().enumerate() {
+        if let Some(process) = process.get() {
+            let _ = write!(writer, "process {} {}", slot, process.get_process_name());
+            write_snapshot(writer, &process.debug_snapshot());
+        }
+    }
+    let _ = write!(writer, "{}\r\n", DUMP_END);
+}
+
+fn write_snapshot(writer: &mut dyn Write, snapshot: &ProcessSnapshot) {
+    let _ = write!(writer, " {:?}", snapshot.state);
+    for (name, value) in [("pc", snapshot.pc), ("sp", snapshot.sp)] {
+        match value {
+            Some(value) => {
+                let _ = write!(writer, " {} {:#010x}", name, value);
+            }
+            None => {
+                let _ = write!(writer, " {} -", name);
+            }
+        }
+    }
+    let _ = write!(writer, " restarts {}", snapshot.restart_count);
+    match snapshot.grants_allocated {
+        Some(grants) => {
+            let _ = write!(writer, " grants {}", grants);
+        }
+        None => {
+            let _ = write!(writer, " grants -");
+        }
+    }
+    let _ = write!(writer, " grant_region {:#010x}", snapshot.grant_region_size);
+    for region in snapshot.mpu_regions.iter().flatten() {
+        let _ = write!(
+            writer,
+            " mpu {:#010x}+{:#010x}",
+            region.start_address() as usize,
+            region.size()
+        );
+    }
+    let _ = write!(writer, "\r\n");
+}
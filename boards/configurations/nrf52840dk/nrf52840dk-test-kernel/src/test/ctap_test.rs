@@ -0,0 +1,52 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the CTAP HID test on a `CtapHid` capsule attached to a fake USB host,
+//! so the security key code path is covered without a browser or a USB
+//! cable.
+//!
+//! The expected output ends with
+//! CtapHid: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::test::ctap::{FakeUsbHost, TestCtapHid, REPORT_LEN};
+use capsules_extra::usb::ctap::CtapHid;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::usb::UsbController;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+
+const STRINGS: &[&str; 3] = &["Tock", "CTAP test", "0"];
+
+pub unsafe fn run_ctap(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let host = static_init!(FakeUsbHost<'static>, FakeUsbHost::new());
+    host.register();
+    let hid = static_init!(
+        CtapHid<'static, FakeUsbHost<'static>>,
+        // Nordic Semiconductor VID.
+        CtapHid::new(host, 0x1915, 0x503a, STRINGS)
+    );
+    host.set_client(hid);
+
+    let test = static_init!(
+        TestCtapHid<'static, VirtualMuxAlarm<'static, Rtc<'static>>>,
+        TestCtapHid::new(
+            hid,
+            host,
+            alarm,
+            static_init!([u8; REPORT_LEN], [0; REPORT_LEN]),
+            static_init!([u8; REPORT_LEN], [0; REPORT_LEN]),
+        )
+    );
+    test.set_client(client);
+    test.run();
+}
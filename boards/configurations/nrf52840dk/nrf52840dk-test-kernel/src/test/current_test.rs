@@ -0,0 +1,256 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Measures the current the board draws asleep and busy with the INA219
+//! listed in `BOARD_TEST_CONFIG.current_monitor`, on TWI1, and checks it
+//! against the budgets listed there. The nRF52840 cannot measure its own
+//! supply current, so without a monitor the test skips itself.
+//!
+//! The INA219 averages 128 samples of the voltage across the shunt, about
+//! 68 ms, so each reading covers a window rather than the instant of the I2C
+//! transfer. The cases are:
+//!
+//! 1. `Sleep`: with no process runnable, after the output of earlier tests
+//!    drained, the board draws at most `sleep_max_ua`.
+//! 2. `Active`: with a process that never yields, the board draws at most
+//!    `active_max_ua`, and more than asleep, which a monitor outside the
+//!    supply of the chip would not show.
+//!
+//! The expected output ends with
+//! Current: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::i2c::{Error, I2CHwMasterClient, I2CMaster};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::Process;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{capabilities, create_capability, static_buf, static_init};
+use nrf52840::i2c::{Speed, TWI};
+use nrf52840::pinmux::Pinmux;
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::{CurrentMonitor, BOARD_TEST_CONFIG};
+use crate::test::embedded_apps::{AppLoader, BUSY_APP};
+use crate::test::registry::{report, skip};
+
+/// INA219 configuration register: shunt range +/-320 mV, 128 averaged
+/// samples, shunt voltage measured continuously.
+const CONFIGURATION: [u8; 3] = [0x00, 0x1F, 0xFD];
+
+/// INA219 shunt voltage register, in steps of 10 uV.
+const SHUNT_VOLTAGE: u8 = 0x01;
+
+/// Time the test waits for the output of earlier tests to drain, as every
+/// UART interrupt wakes the chip.
+const SETTLE_MS: u32 = 100;
+
+/// Time the board stays in each state before the reading, two averaged
+/// conversions of the INA219.
+const WINDOW_MS: u32 = 150;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Configure,
+    Sleep,
+    Active,
+}
+
+pub unsafe fn run_current(
+    apps: &'static AppLoader,
+    twi: &'static TWI<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(monitor) = BOARD_TEST_CONFIG.current_monitor.as_ref() else {
+        skip(client, "Current: no current monitor configured");
+        return;
+    };
+
+    twi.configure(
+        Pinmux::new(monitor.scl as u32),
+        Pinmux::new(monitor.sda as u32),
+    );
+    twi.set_speed(Speed::K100);
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let buffer = static_init!([u8; 3], [0; 3]);
+    let test = static_init!(
+        TestCurrent,
+        TestCurrent::new(apps, monitor, twi, alarm, buffer)
+    );
+    twi.set_master_client(test);
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestCurrent {
+    apps: &'static AppLoader,
+    monitor: &'static CurrentMonitor,
+    twi: &'static TWI<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    buffer: TakeCell<'static, [u8]>,
+    process: OptionalCell<&'static dyn Process>,
+    /// Current the board drew asleep, in microamps.
+    sleep_ua: Cell<i32>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestCurrent {
+    pub fn new(
+        apps: &'static AppLoader,
+        monitor: &'static CurrentMonitor,
+        twi: &'static TWI<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        TestCurrent {
+            apps,
+            monitor,
+            twi,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            process: OptionalCell::empty(),
+            sleep_ua: Cell::new(0),
+            step: Cell::new(Step::Configure),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.twi.enable();
+        let result = self.buffer.take().map_or(Err("buffer in use"), |buffer| {
+            buffer.copy_from_slice(&CONFIGURATION);
+            self.twi
+                .write(self.monitor.address, buffer, CONFIGURATION.len())
+                .map_err(|(_, buffer)| {
+                    self.buffer.replace(buffer);
+                    "configuration write rejected"
+                })
+        });
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Starts reading the shunt voltage the INA219 averaged last.
+    fn read(&self) -> Result<(), &'static str> {
+        let buffer = self.buffer.take().ok_or("buffer in use")?;
+        buffer[0] = SHUNT_VOLTAGE;
+        self.twi
+            .write_read(self.monitor.address, buffer, 1, 2)
+            .map_err(|(_, buffer)| {
+                self.buffer.replace(buffer);
+                "shunt voltage read rejected"
+            })
+    }
+
+    /// Checks the current of the step that ended, in microamps, and starts
+    /// the next step.
+    fn check(&self, ua: i32) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Configure => {
+                self.step.set(Step::Sleep);
+                self.wait(SETTLE_MS + WINDOW_MS);
+            }
+            Step::Sleep => {
+                debug!("Current: asleep {} uA", ua);
+                if ua > self.monitor.sleep_max_ua as i32 {
+                    return Err("current asleep over budget");
+                }
+                self.sleep_ua.set(ua);
+
+                self.step.set(Step::Active);
+                let process = self
+                    .apps
+                    .load(&BUSY_APP)
+                    .map_err(|_| "loading app failed")?
+                    .ok_or("no process created")?;
+                self.process.set(process);
+                self.wait(WINDOW_MS);
+            }
+            Step::Active => {
+                debug!("Current: busy {} uA", ua);
+                if let Some(process) = self.process.take() {
+                    let process_management_cap =
+                        create_capability!(capabilities::ProcessManagementCapability);
+                    process.terminate(None);
+                    self.apps
+                        .kernel()
+                        .remove_process(process.processid(), &process_management_cap)
+                        .map_err(|_| "terminated process not removed")?;
+                }
+                if ua > self.monitor.active_max_ua as i32 {
+                    return Err("current while busy over budget");
+                }
+                if ua <= self.sleep_ua.get() {
+                    return Err("current while busy not above asleep");
+                }
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("Current: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "Current", result);
+    }
+}
+
+impl AlarmClient for TestCurrent {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.read() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl I2CHwMasterClient for TestCurrent {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        // Shunt voltage in steps of 10 uV, over a shunt in milliohms.
+        let ua = i32::from(i16::from_be_bytes([buffer[0], buffer[1]])) * 10_000
+            / self.monitor.shunt_milliohms as i32;
+        self.buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        let result = status
+            .map_err(|_| "INA219 did not answer")
+            .and_then(|()| self.check(ua));
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestCurrent {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,262 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the DateTime HIL as implemented by `DateTimeSoftware` on the RTC,
+//! since the nRF52840 has no calendar RTC. The cases are:
+//!
+//! 1. `Unset`: reading the date and time before it is set fails with `OFF`.
+//! 2. `Set`: setting a date and time succeeds, and a second request while it
+//!    is pending is refused.
+//! 3. `Read`: the date and time read back is the one set, with the day of the
+//!    week computed from the date.
+//! 4. `Advance`: after a few seconds timed by the RTC, the clock has advanced
+//!    by as many seconds, across midnight into a leap day.
+//! 5. `Invalid`: dates that do not exist and times out of range are refused.
+//! 6. `Persist`: the date and time survives a soft reset. The software clock
+//!    cannot, so this case is skipped on this board.
+//!
+//! The expected output ends with
+//! DateTime: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::long_alarm::LongAlarm;
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::date_time_software::DateTimeSoftware;
+use kernel::debug;
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues, DayOfWeek, Month};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::new_alarm;
+use crate::test::registry::report;
+
+type RtcAlarm = VirtualMuxAlarm<'static, Rtc<'static>>;
+type Clock = DateTimeSoftware<'static, LongAlarm<'static, RtcAlarm>>;
+
+/// Seconds the test waits for the clock to advance.
+const ADVANCE_S: u32 = 3;
+
+/// Two seconds before midnight at the end of February in a leap year. The day
+/// of the week is wrong on purpose: 28 February 2024 was a Wednesday.
+const START: DateTimeValues = DateTimeValues {
+    year: 2024,
+    month: Month::February,
+    day: 28,
+    day_of_week: DayOfWeek::Monday,
+    hour: 23,
+    minute: 59,
+    seconds: 58,
+};
+
+/// `START` as the clock reports it.
+const START_READ: DateTimeValues = DateTimeValues {
+    day_of_week: DayOfWeek::Wednesday,
+    ..START
+};
+
+/// `START` advanced by `ADVANCE_S` seconds.
+const ADVANCED: DateTimeValues = DateTimeValues {
+    year: 2024,
+    month: Month::February,
+    day: 29,
+    day_of_week: DayOfWeek::Thursday,
+    hour: 0,
+    minute: 0,
+    seconds: 1,
+};
+
+/// Dates and times the clock must refuse.
+const INVALID: [DateTimeValues; 5] = [
+    DateTimeValues {
+        year: 2023,
+        day: 29,
+        ..START
+    },
+    DateTimeValues {
+        month: Month::April,
+        day: 31,
+        ..START
+    },
+    DateTimeValues { hour: 24, ..START },
+    DateTimeValues {
+        seconds: 60,
+        ..START
+    },
+    DateTimeValues {
+        year: 1969,
+        ..START
+    },
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Unset,
+    Set,
+    Read,
+    Advance,
+    Invalid,
+    Persist,
+}
+
+pub unsafe fn run_date_time(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let virtual_alarm = new_alarm(mux_alarm, static_buf!(RtcAlarm));
+    let long_alarm = static_init!(LongAlarm<'static, RtcAlarm>, LongAlarm::new(virtual_alarm));
+    virtual_alarm.set_alarm_client(long_alarm);
+    long_alarm.setup();
+
+    let clock = static_init!(Clock, DateTimeSoftware::new(long_alarm));
+    kernel::deferred_call::DeferredCallClient::register(clock);
+
+    let test = static_init!(TestDateTime, TestDateTime::new(clock, long_alarm));
+    clock.set_client(test);
+    long_alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestDateTime {
+    clock: &'static Clock,
+    alarm: &'static LongAlarm<'static, RtcAlarm>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestDateTime {
+    pub fn new(clock: &'static Clock, alarm: &'static LongAlarm<'static, RtcAlarm>) -> Self {
+        TestDateTime {
+            clock,
+            alarm,
+            step: Cell::new(Step::Unset),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if self.clock.get_date_time().is_err() {
+            self.fail("read not started");
+        }
+    }
+
+    fn set(&self) -> Result<(), &'static str> {
+        self.step.set(Step::Set);
+        self.clock
+            .set_date_time(START)
+            .map_err(|_| "set not started")?;
+        if self.clock.set_date_time(START) != Err(ErrorCode::ALREADY) {
+            return Err("second set accepted while pending");
+        }
+        if self.clock.get_date_time() != Err(ErrorCode::BUSY) {
+            return Err("read accepted while a set is pending");
+        }
+        Ok(())
+    }
+
+    fn check_invalid(&self) -> Result<(), &'static str> {
+        self.step.set(Step::Invalid);
+        for date_time in INVALID {
+            if self.clock.set_date_time(date_time) != Err(ErrorCode::INVAL) {
+                return Err("invalid date or time accepted");
+            }
+        }
+        Ok(())
+    }
+
+    fn check_read(&self, date_time: Result<DateTimeValues, ErrorCode>) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Unset => {
+                if date_time != Err(ErrorCode::OFF) {
+                    return Err("unset clock did not fail with OFF");
+                }
+                self.set()
+            }
+            Step::Read => {
+                debug!("DateTime: read {:?}", date_time);
+                if date_time != Ok(START_READ) {
+                    return Err("read a different date and time");
+                }
+                self.step.set(Step::Advance);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_seconds(ADVANCE_S));
+                Ok(())
+            }
+            Step::Advance => {
+                debug!("DateTime: advanced to {:?}", date_time);
+                if date_time != Ok(ADVANCED) {
+                    return Err("clock did not advance with the RTC");
+                }
+                self.check_invalid()?;
+
+                self.step.set(Step::Persist);
+                debug!("DateTime: the software clock does not persist across a reset, skipping Persist");
+                self.finish(Ok(()));
+                Ok(())
+            }
+            _ => Err("unexpected read"),
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("DateTime: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "DateTime", result);
+    }
+}
+
+impl DateTimeClient for TestDateTime {
+    fn get_date_time_done(&self, date_time: Result<DateTimeValues, ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check_read(date_time) {
+            self.fail(reason);
+        }
+    }
+
+    fn set_date_time_done(&self, result: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        if self.step.get() != Step::Set || result.is_err() {
+            self.fail("set failed");
+            return;
+        }
+        self.step.set(Step::Read);
+        if self.clock.get_date_time().is_err() {
+            self.fail("read not started");
+        }
+    }
+}
+
+impl AlarmClient for TestDateTime {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if self.clock.get_date_time().is_err() {
+            self.fail("read not started");
+        }
+    }
+}
+
+impl CapsuleTest for TestDateTime {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,159 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that processes that are always runnable do not starve deferred
+//! calls, which the test launcher and many drivers rely on to call back
+//! after they return. Two apps spin and share the core, while the bottom
+//! half of a TIMER2 interrupt sets a deferred call `SAMPLES` times, each
+//! from 1 to 2 ms after the last one was serviced.
+//!
+//! The kernel loop counts the iterations every deferred call of the board,
+//! not only the one of the test, is pending at the start of. A deferred call
+//! set in a bottom half or a system call is serviced in the iteration after,
+//! before the kernel runs a process again, so the test fails if any was
+//! pending at the start of more than one.
+//!
+//! The expected output ends with
+//! DeferredCall: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::process::Process;
+use kernel::static_init;
+use kernel::utilities::cells::OptionalCell;
+use nrf52840::timer::{BitmodeValue, CompareClient, Timer};
+
+use crate::test::embedded_apps::{AppImage, AppLoader, BUSY_APP, SPINNER_APP};
+use crate::test::registry::report;
+
+/// TIMER2 counts at 16 MHz / 2^4 = 1 MHz.
+const TIMER_PRESCALER: u8 = 4;
+
+/// Times the deferred call is set.
+const SAMPLES: u32 = 50;
+
+/// Most kernel loop iterations a deferred call may be pending at the start
+/// of.
+const MAX_WAIT: u32 = 1;
+
+/// Apps that spin while the deferred call is set.
+const APPS: [&AppImage; 2] = [&BUSY_APP, &SPINNER_APP];
+
+pub unsafe fn run_deferred_call(
+    timer: &'static Timer,
+    apps: &'static AppLoader,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let test = static_init!(TestDeferredCall, TestDeferredCall::new(timer, apps));
+    test.register();
+    timer.set_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestDeferredCall {
+    timer: &'static Timer,
+    apps: &'static AppLoader,
+    deferred_call: DeferredCall,
+    processes: [OptionalCell<&'static dyn Process>; 2],
+    /// Times the deferred call was serviced.
+    serviced: Cell<u32>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestDeferredCall {
+    pub fn new(timer: &'static Timer, apps: &'static AppLoader) -> Self {
+        TestDeferredCall {
+            timer,
+            apps,
+            deferred_call: DeferredCall::new(),
+            processes: Default::default(),
+            serviced: Cell::new(0),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.apps.load_all(&APPS, &self.processes) {
+            self.fail(reason);
+            return;
+        }
+        // Only the waits from here on count.
+        DeferredCall::take_longest_wait();
+        self.timer.start(TIMER_PRESCALER, BitmodeValue::Size32Bits);
+        self.schedule();
+    }
+
+    /// Raises the next interrupt between 1 and 2 ms from now, at varying
+    /// points of the timeslices of the apps.
+    fn schedule(&self) {
+        let offset = 1000 + self.serviced.get() * 397 % 1000;
+        let now = self.timer.capture(1);
+        self.timer.set_compare(0, now.wrapping_add(offset), false);
+        self.timer.enable_interrupt(0);
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        let wait = DeferredCall::take_longest_wait();
+        debug!("DeferredCall: longest wait {} kernel loop iterations", wait);
+        if wait > MAX_WAIT {
+            return Err("deferred call starved by processes");
+        }
+        self.apps.remove_all(&self.processes)
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("DeferredCall: failed: {}", reason);
+        let _ = self.apps.remove_all(&self.processes);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.timer.stop();
+        report(&self.client, "DeferredCall", result);
+    }
+}
+
+impl CompareClient for TestDeferredCall {
+    fn compare(&self, bitmask: u8) {
+        if !self.finished.get() && bitmask & 1 != 0 {
+            self.deferred_call.set();
+        }
+    }
+}
+
+impl DeferredCallClient for TestDeferredCall {
+    fn handle_deferred_call(&self) {
+        if self.finished.get() {
+            return;
+        }
+        self.serviced.set(self.serviced.get() + 1);
+        if self.serviced.get() < SAMPLES {
+            self.schedule();
+            return;
+        }
+        match self.check() {
+            Ok(()) => self.finish(Ok(())),
+            Err(reason) => self.fail(reason),
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+impl CapsuleTest for TestDeferredCall {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,53 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the `hil::digest` conformance cases against the software SHA-256
+//! implementation.
+//!
+//! The message is the `SHA256_HELLO` vector, 12 repetitions of "hello " (72
+//! bytes), so the odd-sized chunks straddle the 64-byte block boundary.
+//!
+//! The expected output ends with
+//! DigestConformance: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::test::conformance::digest::TestDigestConformance;
+use capsules_extra::sha256::Sha256Software;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::static_init;
+use kernel::test::vectors::sha2::SHA256_HELLO;
+
+use crate::test::scratch::ScratchArena;
+
+type Sha256ConformanceTest = TestDigestConformance<Sha256Software<'static>, 32>;
+
+pub unsafe fn run_digest_conformance(
+    scratch: &'static ScratchArena,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let t = static_init_test_digest_conformance(scratch, client);
+    t.run();
+}
+
+unsafe fn static_init_test_digest_conformance(
+    scratch: &'static ScratchArena,
+    client: &'static dyn CapsuleTestClient,
+) -> &'static Sha256ConformanceTest {
+    let sha = static_init!(Sha256Software<'static>, Sha256Software::new());
+    sha.register();
+
+    let data = scratch.buffer(SHA256_HELLO.message.len());
+    SHA256_HELLO.message.copy_to(data);
+    let expected = scratch.array();
+    expected.copy_from_slice(SHA256_HELLO.digest);
+    let output = scratch.array();
+
+    let test = static_init!(
+        Sha256ConformanceTest,
+        TestDigestConformance::new(sha, data, expected, output)
+    );
+    test.set_client(client);
+
+    test
+}
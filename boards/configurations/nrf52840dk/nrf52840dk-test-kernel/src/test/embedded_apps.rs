@@ -0,0 +1,590 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Minimal TBF process images built into the test kernel, and a loader that
+//! creates processes from them while tests run.
+//!
+//! Most images run the same three instructions, a loop around the
+//! yield-wait system call, so a loaded process stops using the CPU after its
+//! first time slice. The images differ in package name and minimum RAM size,
+//! which lets process management tests tell processes apart without a
+//! separately built userspace.
+
+use core::cell::Cell;
+
+use kernel::capabilities;
+use kernel::create_capability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::process::{Process, ProcessFaultPolicy, ProcessLoadError};
+use kernel::utilities::cells::OptionalCell;
+use kernel::Kernel;
+use nrf52840::chip::NRF52;
+use nrf52840::interrupt_service::Nrf52840DefaultPeripherals;
+
+pub(crate) type ChipHw = NRF52<'static, Nrf52840DefaultPeripherals<'static>>;
+
+/// Size of each image. Images are aligned to their size so the MPU can cover
+/// one with a single region.
+const IMAGE_LEN: usize = 512;
+
+/// Base header plus the Main, Package Name and Kernel Version TLVs.
+const HEADER_LEN: usize = 16 + (4 + 12) + (4 + 8) + (4 + 4);
+
+/// Length of the package name of every image.
+const NAME_LEN: usize = 8;
+
+/// `movs r0, #1; svc #0; b <movs>`: yield-wait forever.
+const YIELD_LOOP: [u8; 6] = [0x01, 0x20, 0x00, 0xDF, 0xFC, 0xE7];
+
+/// `b .`: spin forever without a system call, using every time slice.
+const BUSY_LOOP: [u8; 2] = [0xFE, 0xE7];
+
+/// `udf #0`: fault on the first instruction.
+const FAULT: [u8; 2] = [0x00, 0xDE];
+
+/// Offset in process RAM of the word `COPY_STATE_LOOP` copies the context
+/// switch count to. The read-only state region is the 16 bytes before it.
+pub(crate) const COPIED_STATE_OFFSET: usize = 16;
+
+/// Allows the first 16 bytes of RAM, whose address the kernel passes in r1,
+/// to the read-only state driver with a userspace readable allow. Then
+/// copies the context switch count the driver keeps there to the next word,
+/// forever and without further system calls:
+///
+/// `movs r4, r1; movs r0, #9; movs r1, #0; movs r2, r4; movs r3, #16;
+/// svc #7; ldr r5, [r4, #0]; str r5, [r4, #16]; b <ldr>`
+const COPY_STATE_LOOP: [u8; 18] = [
+    0x0C, 0x00, 0x09, 0x20, 0x00, 0x21, 0x22, 0x00, 0x10, 0x23, 0x07, 0xDF, 0x25, 0x68, 0x25, 0x61,
+    0xFC, 0xE7,
+];
+
+/// Driver number of the test grant. No capsule on this board uses it.
+const TEST_GRANT_DRIVER_NUM: usize = 0xF0000;
+
+/// Driver number of the shared lock the priority inversion test provides.
+/// `LOCK_WAITER` and `LOCK_HOLDER` encode it.
+pub(crate) const LOCK_DRIVER_NUM: usize = 0xF0001;
+
+/// Driver numbers of the two report drivers. `YIELD_CHECK` and
+/// `UPCALL_ORDER` encode them.
+pub(crate) const REPORT_DRIVER_NUM: usize = 0xF0002;
+pub(crate) const SECOND_REPORT_DRIVER_NUM: usize = 0xF0003;
+
+/// Checks both yield variants. With r5 holding the RAM address r1 points to
+/// at start, the app keeps the flag yield-no-wait writes in the byte at r5
+/// and counts its upcalls in the word at r5 + 4. `report` passes both to
+/// command 1. The app clears both, subscribes, and then:
+///
+/// 1. yield-no-waits and reports, with no upcall pending;
+/// 2. asks for an upcall with command 2 and reports, before yielding;
+/// 3. yield-no-waits and reports, with the upcall pending;
+/// 4. reports, yield-waits, and reports again once it has an upcall;
+/// 5. yield-waits forever.
+///
+/// `mov r5, r1; movs r0, #0; str r0, [r5]; str r0, [r5, #4];
+/// movw r0, #2; movt r0, #0xF; movs r1, #0; adr r2, <upcall>; adds r2, #1;
+/// movs r3, #0; svc #1; movs r0, #0; mov r1, r5; svc #0; bl <report>;
+/// movw r0, #2; movt r0, #0xF; movs r1, #2; svc #2; bl <report>;
+/// movs r0, #0; mov r1, r5; svc #0; bl <report>; bl <report>;
+/// movs r0, #1; svc #0; bl <report>; movs r0, #1; svc #0; b <movs r0>`
+/// `report`: `movw r0, #2; movt r0, #0xF; movs r1, #1; ldrb r2, [r5];
+/// ldr r3, [r5, #4]; svc #2; bx lr; nop;`
+/// `upcall`: `ldr r0, [r5, #4]; adds r0, #1; str r0, [r5, #4]; bx lr`
+const YIELD_CHECK: [u8; 108] = [
+    0x0D, 0x46, 0x00, 0x20, 0x28, 0x60, 0x68, 0x60, 0x40, 0xF2, 0x02, 0x00, 0xC0, 0xF2, 0x0F, 0x00,
+    0x00, 0x21, 0x14, 0xA2, 0x01, 0x32, 0x00, 0x23, 0x01, 0xDF, 0x00, 0x20, 0x29, 0x46, 0x00, 0xDF,
+    0x00, 0xF0, 0x16, 0xF8, 0x40, 0xF2, 0x02, 0x00, 0xC0, 0xF2, 0x0F, 0x00, 0x02, 0x21, 0x02, 0xDF,
+    0x00, 0xF0, 0x0E, 0xF8, 0x00, 0x20, 0x29, 0x46, 0x00, 0xDF, 0x00, 0xF0, 0x09, 0xF8, 0x00, 0xF0,
+    0x07, 0xF8, 0x01, 0x20, 0x00, 0xDF, 0x00, 0xF0, 0x03, 0xF8, 0x01, 0x20, 0x00, 0xDF, 0xFC, 0xE7,
+    0x40, 0xF2, 0x02, 0x00, 0xC0, 0xF2, 0x0F, 0x00, 0x01, 0x21, 0x2A, 0x78, 0x6B, 0x68, 0x02, 0xDF,
+    0x70, 0x47, 0x00, 0xBF, 0x68, 0x68, 0x01, 0x30, 0x68, 0x60, 0x70, 0x47,
+];
+
+/// Offset in process RAM of the log `UPCALL_ORDER` keeps of its upcalls. The
+/// word before it counts the upcalls.
+pub(crate) const UPCALL_LOG_OFFSET: usize = 8;
+
+/// Logs the first argument of every upcall it gets. With r5 holding the RAM
+/// address r1 points to at start, the app counts its upcalls in the word at
+/// r5 + 4 and appends the argument to the log at r5 + 8. The app clears the
+/// count and subscribes the logging upcall to the report driver's upcall 0,
+/// the second report driver's upcall 0 and the report driver's upcall 1.
+/// Then it has the report driver schedule upcall 0 with argument 9,
+/// subscribes to it again, which cancels the pending upcall, reports with
+/// command 1 and yield-waits forever:
+///
+/// `mov r5, r1; movs r0, #0; str r0, [r5, #4];`
+/// three times `movw r0, #<2, 3, 2>; movt r0, #0xF; movs r1, #<0, 0, 1>;
+/// adr r2, <upcall>; adds r2, #1; movs r3, #0; svc #1;`
+/// `movw r0, #2; movt r0, #0xF; movs r1, #2; movs r2, #9; movs r3, #0;
+/// svc #2; movw r0, #2; movt r0, #0xF; movs r1, #0; adr r2, <upcall>;
+/// adds r2, #1; movs r3, #0; svc #1; movw r0, #2; movt r0, #0xF; movs r1, #1;
+/// movs r2, #0; movs r3, #0; svc #2; movs r0, #1; svc #0; b <movs r0>`
+/// `upcall`: `ldr r3, [r5, #4]; lsls r1, r3, #2; add r1, r5;
+/// str r0, [r1, #8]; adds r3, #1; str r3, [r5, #4]; bx lr`
+const UPCALL_ORDER: [u8; 130] = [
+    0x0D, 0x46, 0x00, 0x20, 0x68, 0x60, 0x40, 0xF2, 0x02, 0x00, 0xC0, 0xF2, 0x0F, 0x00, 0x00, 0x21,
+    0x18, 0xA2, 0x01, 0x32, 0x00, 0x23, 0x01, 0xDF, 0x40, 0xF2, 0x03, 0x00, 0xC0, 0xF2, 0x0F, 0x00,
+    0x00, 0x21, 0x14, 0xA2, 0x01, 0x32, 0x00, 0x23, 0x01, 0xDF, 0x40, 0xF2, 0x02, 0x00, 0xC0, 0xF2,
+    0x0F, 0x00, 0x01, 0x21, 0x0F, 0xA2, 0x01, 0x32, 0x00, 0x23, 0x01, 0xDF, 0x40, 0xF2, 0x02, 0x00,
+    0xC0, 0xF2, 0x0F, 0x00, 0x02, 0x21, 0x09, 0x22, 0x00, 0x23, 0x02, 0xDF, 0x40, 0xF2, 0x02, 0x00,
+    0xC0, 0xF2, 0x0F, 0x00, 0x00, 0x21, 0x07, 0xA2, 0x01, 0x32, 0x00, 0x23, 0x01, 0xDF, 0x40, 0xF2,
+    0x02, 0x00, 0xC0, 0xF2, 0x0F, 0x00, 0x01, 0x21, 0x00, 0x22, 0x00, 0x23, 0x02, 0xDF, 0x01, 0x20,
+    0x00, 0xDF, 0xFC, 0xE7, 0x6B, 0x68, 0x99, 0x00, 0x29, 0x44, 0x88, 0x60, 0x01, 0x33, 0x6B, 0x60,
+    0x70, 0x47,
+];
+
+/// Subscribes to lock releases, then tries to acquire the lock with command
+/// 1 until it succeeds, waiting for a release after each failure. Holds the
+/// lock forever after:
+///
+/// `movw r0, #1; movt r0, #0xF; movs r1, #0; adr r2, <bx>; adds r2, #1;
+/// movs r3, #0; svc #1; movw r0, #1; movt r0, #0xF; movs r1, #1; movs r2, #0;
+/// movs r3, #0; svc #2; cmp r0, #128; beq <held>; movs r0, #1; svc #0;
+/// b <movw>; movs r0, #1; svc #0; b <held>; nop; bx lr`
+const LOCK_WAITER: [u8; 54] = [
+    0x40, 0xF2, 0x01, 0x00, 0xC0, 0xF2, 0x0F, 0x00, 0x00, 0x21, 0x0A, 0xA2, 0x01, 0x32, 0x00, 0x23,
+    0x01, 0xDF, 0x40, 0xF2, 0x01, 0x00, 0xC0, 0xF2, 0x0F, 0x00, 0x01, 0x21, 0x00, 0x22, 0x00, 0x23,
+    0x02, 0xDF, 0x80, 0x28, 0x02, 0xD0, 0x01, 0x20, 0x00, 0xDF, 0xF2, 0xE7, 0x01, 0x20, 0x00, 0xDF,
+    0xFC, 0xE7, 0x00, 0xBF, 0x70, 0x47,
+];
+
+/// Acquires the lock with command 1, spins 2^20 iterations, about 50 ms,
+/// releases it with command 2 and then yield-waits forever:
+///
+/// `movw r0, #1; movt r0, #0xF; movs r1, #1; movs r2, #0; movs r3, #0;
+/// svc #2; movs r4, #16; lsls r4, r4, #16; subs r4, #1; bne <subs>;
+/// movw r0, #1; movt r0, #0xF; movs r1, #2; movs r2, #0; movs r3, #0;
+/// svc #2; movs r0, #1; svc #0; b <movs r0>`
+const LOCK_HOLDER: [u8; 46] = [
+    0x40, 0xF2, 0x01, 0x00, 0xC0, 0xF2, 0x0F, 0x00, 0x01, 0x21, 0x00, 0x22, 0x00, 0x23, 0x02, 0xDF,
+    0x10, 0x24, 0x24, 0x04, 0x01, 0x3C, 0xFD, 0xD1, 0x40, 0xF2, 0x01, 0x00, 0xC0, 0xF2, 0x0F, 0x00,
+    0x02, 0x21, 0x00, 0x22, 0x00, 0x23, 0x02, 0xDF, 0x01, 0x20, 0x00, 0xDF, 0xFC, 0xE7,
+];
+
+/// Offset in process RAM of the registers `SYSCALL_MATRIX` logs. The word
+/// before it counts the system calls the app made.
+pub(crate) const MATRIX_LOG_OFFSET: usize = 4;
+
+/// Length of a system call in the table of `SYSCALL_MATRIX`: the class and
+/// the four arguments, a word each.
+const MATRIX_CALL_LEN: usize = 20;
+
+/// Largest number of system calls an image that runs `SYSCALL_MATRIX` holds,
+/// leaving room for the word that ends the table.
+pub(crate) const MATRIX_MAX_CALLS: usize =
+    (IMAGE_LEN - HEADER_LEN - SYSCALL_MATRIX.len() - 4) / MATRIX_CALL_LEN;
+
+/// Makes the system calls in the table that follows the code, each given as
+/// the class and the values of r0 to r3, and logs the four registers every
+/// call returns. With r5 holding the RAM address r1 points to at start, r4
+/// pointing to the next call and r6 counting the calls made, the app stores
+/// the registers of call `n` at r5 + 4 + 16 * n and the count at r5. It jumps
+/// to the `svc` of the class through a branch table, as the class is part of
+/// the instruction. A class above 7 ends the table, after which the app
+/// yield-waits forever:
+///
+/// `mov r5, r1; adr r4, <table>; movs r6, #0; str r6, [r5];
+/// ldr r7, [r4]; cmp r7, #7; bhi <done>; ldr r0, [r4, #4]; ldr r1, [r4, #8];
+/// ldr r2, [r4, #12]; ldr r3, [r4, #16]; lsls r7, r7, #2; add pc, r7; nop;`
+/// for each class `n` from 0 to 7 `svc #<n>; b <store>;`
+/// `store`: `lsls r7, r6, #4; add r7, r5; str r0, [r7, #4];
+/// str r1, [r7, #8]; str r2, [r7, #12]; str r3, [r7, #16]; adds r6, #1;
+/// str r6, [r5]; adds r4, #20; b <ldr r7>`
+/// `done`: `movs r0, #1; svc #0; b <done>; nop`
+const SYSCALL_MATRIX: [u8; 88] = [
+    0x0D, 0x46, 0x15, 0xA4, 0x00, 0x26, 0x2E, 0x60, 0x27, 0x68, 0x07, 0x2F, 0x20, 0xD8, 0x60, 0x68,
+    0xA1, 0x68, 0xE2, 0x68, 0x23, 0x69, 0xBF, 0x00, 0xBF, 0x44, 0x00, 0xBF, 0x00, 0xDF, 0x0D, 0xE0,
+    0x01, 0xDF, 0x0B, 0xE0, 0x02, 0xDF, 0x09, 0xE0, 0x03, 0xDF, 0x07, 0xE0, 0x04, 0xDF, 0x05, 0xE0,
+    0x05, 0xDF, 0x03, 0xE0, 0x06, 0xDF, 0x01, 0xE0, 0x07, 0xDF, 0xFF, 0xE7, 0x37, 0x01, 0x2F, 0x44,
+    0x78, 0x60, 0xB9, 0x60, 0xFA, 0x60, 0x3B, 0x61, 0x01, 0x36, 0x2E, 0x60, 0x14, 0x34, 0xDB, 0xE7,
+    0x01, 0x20, 0x00, 0xDF, 0xFC, 0xE7, 0x00, 0xBF,
+];
+
+/// A TBF object stored in the kernel's flash.
+#[repr(C, align(512))]
+pub(crate) struct AppImage([u8; IMAGE_LEN]);
+
+impl AppImage {
+    fn address(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+}
+
+const fn put_u16(image: &mut [u8; IMAGE_LEN], offset: usize, value: u16) {
+    let bytes = value.to_le_bytes();
+    image[offset] = bytes[0];
+    image[offset + 1] = bytes[1];
+}
+
+const fn put_u32(image: &mut [u8; IMAGE_LEN], offset: usize, value: u32) {
+    let bytes = value.to_le_bytes();
+    let mut i = 0;
+    while i < 4 {
+        image[offset + i] = bytes[i];
+        i += 1;
+    }
+}
+
+/// Builds an enabled TBF v2 image named `name` that asks for
+/// `minimum_ram_size` bytes of RAM and runs `YIELD_LOOP`.
+const fn app_image(name: &[u8; NAME_LEN], minimum_ram_size: u32) -> AppImage {
+    app_image_with_code(name, minimum_ram_size, &YIELD_LOOP)
+}
+
+/// Like `app_image()`, but runs `code` instead.
+const fn app_image_with_code(
+    name: &[u8; NAME_LEN],
+    minimum_ram_size: u32,
+    code: &[u8],
+) -> AppImage {
+    let mut image = [0; IMAGE_LEN];
+
+    // Base header; the checksum at offset 12 is filled in last.
+    put_u16(&mut image, 0, 2);
+    put_u16(&mut image, 2, HEADER_LEN as u16);
+    put_u32(&mut image, 4, IMAGE_LEN as u32);
+    put_u32(&mut image, 8, 1);
+
+    // Main: the code starts right after the header.
+    put_u16(&mut image, 16, 1);
+    put_u16(&mut image, 18, 12);
+    put_u32(&mut image, 20, 0);
+    put_u32(&mut image, 24, 0);
+    put_u32(&mut image, 28, minimum_ram_size);
+
+    // Package Name.
+    put_u16(&mut image, 32, 3);
+    put_u16(&mut image, 34, NAME_LEN as u16);
+    let mut i = 0;
+    while i < NAME_LEN {
+        image[36 + i] = name[i];
+        i += 1;
+    }
+
+    // Kernel Version.
+    put_u16(&mut image, 44, 8);
+    put_u16(&mut image, 46, 4);
+    put_u16(&mut image, 48, kernel::KERNEL_MAJOR_VERSION);
+    put_u16(&mut image, 50, kernel::KERNEL_MINOR_VERSION);
+
+    let mut checksum = 0;
+    let mut offset = 0;
+    while offset < HEADER_LEN {
+        if offset != 12 {
+            checksum ^= u32::from_le_bytes([
+                image[offset],
+                image[offset + 1],
+                image[offset + 2],
+                image[offset + 3],
+            ]);
+        }
+        offset += 4;
+    }
+    put_u32(&mut image, 12, checksum);
+
+    let mut i = 0;
+    while i < code.len() {
+        image[HEADER_LEN + i] = code[i];
+        i += 1;
+    }
+
+    AppImage(image)
+}
+
+const fn put_word(code: &mut [u8; IMAGE_LEN - HEADER_LEN], offset: usize, value: u32) {
+    let bytes = value.to_le_bytes();
+    let mut i = 0;
+    while i < 4 {
+        code[offset + i] = bytes[i];
+        i += 1;
+    }
+}
+
+/// Builds an image named `name` that runs `SYSCALL_MATRIX` over `calls`,
+/// each the class and the values of r0 to r3 of a system call.
+pub(crate) const fn syscall_matrix_app(name: &[u8; NAME_LEN], calls: &[[u32; 5]]) -> AppImage {
+    assert!(calls.len() <= MATRIX_MAX_CALLS);
+
+    let mut code = [0; IMAGE_LEN - HEADER_LEN];
+    let mut i = 0;
+    while i < SYSCALL_MATRIX.len() {
+        code[i] = SYSCALL_MATRIX[i];
+        i += 1;
+    }
+
+    // The table follows the code, which is a multiple of four bytes long, so
+    // its words are aligned. A class of `u32::MAX` ends it.
+    let mut offset = SYSCALL_MATRIX.len();
+    let mut call = 0;
+    while call < calls.len() {
+        let mut word = 0;
+        while word < 5 {
+            put_word(&mut code, offset, calls[call][word]);
+            offset += 4;
+            word += 1;
+        }
+        call += 1;
+    }
+    put_word(&mut code, offset, u32::MAX);
+
+    app_image_with_code(name, 1024, &code)
+}
+
+/// Images stored back to back, which a process loader scans like the app
+/// flash region.
+#[repr(C)]
+pub(crate) struct AppImages<const N: usize>([AppImage; N]);
+
+impl<const N: usize> AppImages<N> {
+    pub fn image(&'static self, index: usize) -> &'static AppImage {
+        &self.0[index]
+    }
+
+    pub fn flash(&'static self) -> &'static [u8] {
+        // SAFETY: every image is `IMAGE_LEN` bytes long and aligned to its
+        // length, so the array has no padding between images.
+        unsafe { core::slice::from_raw_parts(self.0.as_ptr().cast::<u8>(), N * IMAGE_LEN) }
+    }
+}
+
+pub(crate) static SLOT_APP_ONE: AppImage = app_image(b"slot_one", 1024);
+pub(crate) static SLOT_APP_TWO: AppImage = app_image(b"slot_two", 2048);
+
+/// Two apps whose names share the prefix `shared`, followed by an unrelated
+/// one.
+pub(crate) static SHARED_PREFIX_APPS: AppImages<3> = AppImages([
+    app_image(b"shared_a", 1024),
+    app_image(b"shared_b", 1024),
+    app_image(b"unique_c", 1024),
+]);
+
+/// A second copy of `shared_b`, for loading it on its own.
+pub(crate) static SHARED_B_APP: AppImages<1> = AppImages([app_image(b"shared_b", 1024)]);
+
+/// Runs `COPY_STATE_LOOP`.
+pub(crate) static READ_ONLY_STATE_APP: AppImage =
+    app_image_with_code(b"ro_state", 1024, &COPY_STATE_LOOP);
+
+/// Runs `BUSY_LOOP`, for a duty cycle of 100%.
+pub(crate) static BUSY_APP: AppImage = app_image_with_code(b"cpu_busy", 1024, &BUSY_LOOP);
+
+/// Runs `YIELD_LOOP`, for a duty cycle of almost 0%.
+pub(crate) static IDLE_APP: AppImage = app_image(b"cpu_idle", 1024);
+
+/// Placeholder apps that take the process slots a test does not use.
+static FILLER_APPS: AppImages<{ crate::NUM_PROCS - 1 }> =
+    AppImages([app_image(b"filler_a", 1024), app_image(b"filler_b", 1024)]);
+
+/// Waits for the shared lock with `LOCK_WAITER`.
+pub(crate) static LOCK_WAITER_APP: AppImage = app_image_with_code(b"high_pri", 1024, &LOCK_WAITER);
+
+/// Spins with `BUSY_LOOP` and never touches the shared lock.
+pub(crate) static SPINNER_APP: AppImage = app_image_with_code(b"med_prio", 1024, &BUSY_LOOP);
+
+/// Holds the shared lock for a while with `LOCK_HOLDER`.
+pub(crate) static LOCK_HOLDER_APP: AppImage = app_image_with_code(b"low_prio", 1024, &LOCK_HOLDER);
+
+/// Runs `YIELD_CHECK`.
+pub(crate) static YIELD_APP: AppImage = app_image_with_code(b"yield_ck", 1024, &YIELD_CHECK);
+
+/// Runs `UPCALL_ORDER`.
+pub(crate) static UPCALL_ORDER_APP: AppImage =
+    app_image_with_code(b"upcall_o", 1024, &UPCALL_ORDER);
+
+/// Runs `FAULT`, which the board's fault policy stops.
+pub(crate) static FAULT_APP: AppImage = app_image_with_code(b"faulting", 1024, &FAULT);
+
+/// Per-process state tests keep in the test grant.
+#[derive(Default)]
+pub(crate) struct AppGrantData {
+    pub marker: u32,
+}
+
+pub(crate) type AppGrant = Grant<AppGrantData, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>;
+
+/// Loads embedded images into the app RAM region, one process at a time.
+///
+/// Process memory is not reclaimed when a process is removed, so each load
+/// takes fresh memory from the region.
+pub(crate) struct AppLoader {
+    kernel: &'static Kernel,
+    chip: &'static ChipHw,
+    fault_policy: capsules_system::process_policies::StopWithDebugFaultPolicy,
+    /// Grant the tests use to observe per-process kernel state.
+    pub grant: AppGrant,
+    memory_start: Cell<usize>,
+    memory_end: usize,
+}
+
+impl AppLoader {
+    /// Must be called before any process is loaded, as it creates the test
+    /// grant.
+    pub unsafe fn new(kernel: &'static Kernel, chip: &'static ChipHw) -> Self {
+        // These symbols are defined in the linker script.
+        extern "C" {
+            /// Beginning of the RAM region for app memory.
+            static mut _sappmem: u8;
+            /// End of the RAM region for app memory.
+            static _eappmem: u8;
+        }
+
+        let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        AppLoader {
+            kernel,
+            chip,
+            fault_policy: capsules_system::process_policies::StopWithDebugFaultPolicy {},
+            grant: kernel.create_grant(TEST_GRANT_DRIVER_NUM, &memory_allocation_cap),
+            memory_start: Cell::new(core::ptr::addr_of_mut!(_sappmem) as usize),
+            memory_end: core::ptr::addr_of!(_eappmem) as usize,
+        }
+    }
+
+    pub fn kernel(&self) -> &'static Kernel {
+        self.kernel
+    }
+
+    pub fn chip(&self) -> &'static ChipHw {
+        self.chip
+    }
+
+    pub fn fault_policy(&'static self) -> &'static dyn ProcessFaultPolicy {
+        &self.fault_policy
+    }
+
+    /// Hands `len` bytes of the app RAM region to another process loader.
+    pub fn take_memory(&self, len: usize) -> Option<&'static mut [u8]> {
+        let memory_start = self.memory_start.get();
+        if self.memory_end - memory_start < len {
+            return None;
+        }
+        self.memory_start.set(memory_start + len);
+        // SAFETY: the range has not been handed out before, and `load()`
+        // only uses memory after it.
+        Some(unsafe { core::slice::from_raw_parts_mut(memory_start as *mut u8, len) })
+    }
+
+    /// Returns the process in a slot that was created from `image`.
+    pub fn find(&self, image: &AppImage) -> Option<&'static dyn Process> {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        self.kernel
+            .process_iter_capability(&process_management_cap)
+            .find(|process| process.get_addresses().flash_start == image.address())
+    }
+
+    /// Returns the number of free process slots.
+    pub fn free_slots(&self) -> usize {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        crate::NUM_PROCS
+            - self
+                .kernel
+                .process_iter_capability(&process_management_cap)
+                .count()
+    }
+
+    /// Loads placeholder apps until only `free` process slots are free, for
+    /// tests that need the kernel to run out of slots or a process in a later
+    /// slot.
+    pub fn fill_slots(&'static self, free: usize) -> Result<(), &'static str> {
+        for image in FILLER_APPS.0.iter() {
+            if self.free_slots() <= free {
+                break;
+            }
+            if self.find(image).is_none() {
+                self.load(image)
+                    .map_err(|_| "loading placeholder failed")?
+                    .ok_or("no placeholder created")?;
+            }
+        }
+        if self.free_slots() != free {
+            return Err("process slots not filled");
+        }
+        Ok(())
+    }
+
+    /// Removes the placeholder apps `fill_slots()` loaded.
+    pub fn clear_slots(&self) -> Result<(), &'static str> {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        for image in FILLER_APPS.0.iter() {
+            if let Some(process) = self.find(image) {
+                process.terminate(None);
+                self.kernel
+                    .remove_process(process.processid(), &process_management_cap)
+                    .map_err(|_| "placeholder not removed")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a process from each of `images` and keeps it in the cell of
+    /// `processes` with the same index, for tests that run apps for a while.
+    pub fn load_all(
+        &'static self,
+        images: &[&'static AppImage],
+        processes: &[OptionalCell<&'static dyn Process>],
+    ) -> Result<(), &'static str> {
+        for (image, process) in images.iter().zip(processes) {
+            let loaded = self
+                .load(image)
+                .map_err(|_| "loading app failed")?
+                .ok_or("no process created")?;
+            process.set(loaded);
+        }
+        Ok(())
+    }
+
+    /// Terminates and removes the processes `load_all()` kept in
+    /// `processes`, and empties their cells.
+    pub fn remove_all(
+        &self,
+        processes: &[OptionalCell<&'static dyn Process>],
+    ) -> Result<(), &'static str> {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        for process in processes.iter().filter_map(OptionalCell::take) {
+            process.terminate(None);
+            self.kernel
+                .remove_process(process.processid(), &process_management_cap)
+                .map_err(|_| "terminated process not removed")?;
+        }
+        Ok(())
+    }
+
+    /// Creates a process from `image` in the first free process slot.
+    ///
+    /// Returns `None` if no process was created, for example because all
+    /// slots are taken.
+    pub fn load(
+        &'static self,
+        image: &'static AppImage,
+    ) -> Result<Option<&'static dyn Process>, ProcessLoadError> {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        let memory_start = self.memory_start.get();
+
+        // SAFETY: memory from `memory_start` on has not been handed to any
+        // process, and the kernel keeps no reference to the slice itself.
+        let memory = unsafe {
+            core::slice::from_raw_parts_mut(memory_start as *mut u8, self.memory_end - memory_start)
+        };
+        kernel::process::load_processes(
+            self.kernel,
+            self.chip,
+            &image.0,
+            memory,
+            &self.fault_policy,
+            &process_management_cap,
+        )?;
+
+        let loaded = self
+            .kernel
+            .process_iter_capability(&process_management_cap)
+            .find(|process| {
+                let addresses = process.get_addresses();
+                addresses.flash_start == image.address() && addresses.sram_start >= memory_start
+            });
+        if let Some(process) = loaded {
+            self.memory_start.set(process.get_addresses().sram_end);
+        }
+        Ok(loaded)
+    }
+}
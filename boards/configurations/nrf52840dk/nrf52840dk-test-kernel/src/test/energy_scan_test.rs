@@ -0,0 +1,206 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Scans all 802.15.4 channels with the energy detection of the nRF52840
+//! radio driver, and logs the noise floor of each channel, which also helps to
+//! pick a quiet channel for the test lab. The cases are:
+//!
+//! 1. `Scan`: every channel from 11 to 26 reports a level, for the channel it
+//!    was requested for, and a second request while one runs is refused
+//!    with `BUSY`. A request for zero iterations is refused with `INVAL`.
+//! 2. `Restore`: after the scan, the radio is still on and listens on the
+//!    channel it was configured for.
+//! 3. `Off`: once the radio is stopped, requests are refused with `OFF`.
+//!
+//! The levels depend on the surroundings, so the test does not check them.
+//! The expected output ends with
+//! EnergyScan: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::radio::{self, RadioChannel, RadioConfig, RadioData};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init, ErrorCode};
+use nrf52840::ieee802154_radio::{energy_level_dbm, EnergyDetectClient, Radio};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::registry::report;
+use crate::test::scratch::ScratchArena;
+
+/// Iterations of 128 us of each measurement, about 8 ms.
+const ITERATIONS: u32 = 64;
+
+/// Time a measurement gets to report its level.
+const TIMEOUT_MS: u32 = 50;
+
+const FIRST_CHANNEL: u8 = 11;
+const LAST_CHANNEL: u8 = 26;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Scan,
+    Restore,
+    Off,
+}
+
+pub unsafe fn run_energy_scan(
+    radio: &'static Radio<'static>,
+    scratch: &'static ScratchArena,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(TestEnergyScan, TestEnergyScan::new(radio, alarm));
+    alarm.set_alarm_client(test);
+    radio.set_energy_detect_client(test);
+    test.set_client(client);
+    test.run(scratch.buffer(radio::MAX_BUF_SIZE));
+}
+
+pub struct TestEnergyScan {
+    radio: &'static Radio<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    /// Channel being measured.
+    channel: Cell<u8>,
+    /// Channel with the lowest level so far, and its level.
+    quietest: Cell<(u8, u8)>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestEnergyScan {
+    pub fn new(
+        radio: &'static Radio<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestEnergyScan {
+            radio,
+            alarm,
+            channel: Cell::new(FIRST_CHANNEL),
+            quietest: Cell::new((FIRST_CHANNEL, u8::MAX)),
+            step: Cell::new(Step::Scan),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self, receive_buffer: &'static mut [u8]) {
+        self.radio.set_receive_buffer(receive_buffer);
+        if self.radio.start().is_err() {
+            self.fail("radio did not start");
+            return;
+        }
+        if self.radio.energy_detect(RadioChannel::Channel11, 0) != Err(ErrorCode::INVAL) {
+            self.fail("zero iterations accepted");
+            return;
+        }
+        if let Err(reason) = self.measure() {
+            self.fail(reason);
+        }
+    }
+
+    /// Requests the measurement of the current channel.
+    fn measure(&self) -> Result<(), &'static str> {
+        let channel = RadioChannel::try_from(self.channel.get()).or(Err("invalid channel"))?;
+        self.radio
+            .energy_detect(channel, ITERATIONS)
+            .or(Err("measurement refused"))?;
+        if self.radio.energy_detect(channel, ITERATIONS) != Err(ErrorCode::BUSY) {
+            return Err("second measurement not refused");
+        }
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TIMEOUT_MS));
+        Ok(())
+    }
+
+    fn check(&self, channel: RadioChannel, level: u8) -> Result<(), &'static str> {
+        let expected = self.channel.get();
+        if channel.get_channel_number() != expected {
+            return Err("level reported for another channel");
+        }
+        debug!(
+            "EnergyScan: channel {}: {} dBm (ED {})",
+            expected,
+            energy_level_dbm(level),
+            level
+        );
+        if level < self.quietest.get().1 {
+            self.quietest.set((expected, level));
+        }
+        if expected < LAST_CHANNEL {
+            self.channel.set(expected + 1);
+            return self.measure();
+        }
+        debug!("EnergyScan: quietest channel {}", self.quietest.get().0);
+
+        self.step.set(Step::Restore);
+        if !self.radio.is_on() {
+            return Err("radio turned off");
+        }
+        if self.radio.get_channel() != RadioChannel::Channel26.get_channel_number() {
+            return Err("configured channel changed");
+        }
+
+        self.step.set(Step::Off);
+        let _ = self.radio.stop();
+        if self
+            .radio
+            .energy_detect(RadioChannel::Channel11, ITERATIONS)
+            != Err(ErrorCode::OFF)
+        {
+            return Err("measurement accepted while off");
+        }
+        self.finish(Ok(()));
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("EnergyScan: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        // Stop listening, so the radio does not write to the receive buffer
+        // once the test has finished.
+        let _ = self.radio.stop();
+        report(&self.client, "EnergyScan", result);
+    }
+}
+
+impl EnergyDetectClient for TestEnergyScan {
+    fn energy_detected(&self, channel: RadioChannel, level: u8) {
+        if self.finished.get() {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        if let Err(reason) = self.check(channel, level) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl AlarmClient for TestEnergyScan {
+    fn alarm(&self) {
+        if !self.finished.get() {
+            self.fail("no level reported");
+        }
+    }
+}
+
+impl CapsuleTest for TestEnergyScan {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
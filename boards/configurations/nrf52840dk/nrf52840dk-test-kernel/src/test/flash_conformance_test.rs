@@ -0,0 +1,63 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the `hil::flash` and `hil::nonvolatile_storage` conformance cases
+//! against the nRF52840 NVMC.
+//!
+//! The tests overwrite the last four pages of flash, which this kernel never
+//! uses for processes. No page is write protected, so the protected-page case
+//! is skipped.
+//!
+//! The expected output ends with
+//! FlashConformance: all cases passed
+//! NonvolatileConformance: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::test::conformance::flash::{TestFlashConformance, TestNonvolatileConformance};
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use kernel::hil::flash::HasClient;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::static_init;
+use nrf52840::nvmc::{NrfPage, Nvmc};
+
+const PAGE_SIZE: usize = 4096;
+
+/// First of the two pages used by the page-level test.
+const FLASH_TEST_PAGE: usize = 252;
+
+/// Address of the two pages used by the byte-level test.
+const NONVOLATILE_TEST_REGION: usize = 254 * PAGE_SIZE;
+
+pub unsafe fn run_flash_conformance(nvmc: &'static Nvmc, client: &'static dyn CapsuleTestClient) {
+    let page = static_init!(NrfPage, NrfPage::default());
+    let test = static_init!(
+        TestFlashConformance<Nvmc>,
+        TestFlashConformance::new(nvmc, FLASH_TEST_PAGE, None, page)
+    );
+    nvmc.set_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub unsafe fn run_nonvolatile_conformance(
+    nvmc: &'static Nvmc,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let page = static_init!(NrfPage, NrfPage::default());
+    let nv_to_page = static_init!(
+        NonvolatileToPages<'static, Nvmc>,
+        NonvolatileToPages::new(nvmc, page)
+    );
+    nvmc.set_client(nv_to_page);
+
+    // An odd length so the write ends on an odd offset as well.
+    let buffer = static_init!([u8; 61], [0; 61]);
+    let test = static_init!(
+        TestNonvolatileConformance<'static>,
+        TestNonvolatileConformance::new(nv_to_page, NONVOLATILE_TEST_REGION, PAGE_SIZE, buffer)
+    );
+    nv_to_page.set_client(test);
+    test.set_client(client);
+    test.run();
+}
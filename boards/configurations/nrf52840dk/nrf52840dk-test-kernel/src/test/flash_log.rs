@@ -0,0 +1,358 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Keeps the last debug output in flash, so a board that hangs or loses power
+//! during a long run still leaves evidence of what it was doing.
+//!
+//! `FlashLog` is a transmitter for the debug writer's fan-out. It appends
+//! every transmission to a circular log in the `DEBUG_LOG` storage volume,
+//! which overwrites the oldest output once full. The log syncs the page it
+//! is filling to flash once the output has been quiet for `SYNC_MS`, so at
+//! most the output of the last moments before a hang is lost.
+//!
+//! Holding the dump button configured in `BOARD_TEST_CONFIG` while the board
+//! boots dumps the log over the UART, between a start and an end marker,
+//! before the tests run. Output of the booting kernel during the dump is not
+//! logged. Flashing a new kernel erases the log.
+//!
+//! The NVMC stalls the core while it erases and writes a page, for about
+//! 130 ms per sync, which upsets the timing of several tests. The board
+//! therefore logs to flash only when the flash channel is enabled in its
+//! configuration, as it is for long runs. The flash tests take the NVMC over,
+//! so the test launcher suspends the log while they run.
+
+use core::cell::Cell;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use capsules_extra::log::{Log, PAGE_HEADER_SIZE};
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::flash::HasClient;
+use kernel::hil::gpio::{Configure, FloatingState, Input};
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::hil::uart::{self, Transmit};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{static_buf, static_init, storage_volume, ErrorCode};
+use nrf52840::gpio::GPIOPin;
+use nrf52840::nvmc::{NrfPage, Nvmc};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+
+const PAGE_SIZE: usize = 4096;
+
+// Three flash pages.
+storage_volume!(DEBUG_LOG, 12);
+
+/// Time the output has to be quiet for before the log syncs to flash.
+const SYNC_MS: u32 = 1000;
+
+/// Longest entry the log dumps, the length of the debug writer's output
+/// buffer and with it of every transmission.
+const ENTRY_LEN: usize = components::debug_writer::DEBUG_BUFFER_SPLIT;
+
+const DUMP_START: &[u8] = b"\r\n--- flash log start ---\r\n";
+const DUMP_END: &[u8] = b"--- flash log end ---\r\n";
+
+/// Receives the callback once the log no longer stands in the way of the
+/// caller.
+pub trait FlashLogClient {
+    /// The dump finished, or the log stopped using the NVMC after a call to
+    /// `suspend`.
+    fn log_idle(&'static self);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Dump {
+    Off,
+    Entries,
+    End,
+}
+
+type DebugLog = Log<'static, Nvmc>;
+
+pub unsafe fn new_flash_log(
+    nvmc: &'static Nvmc,
+    uart_mux: &'static MuxUart<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+) -> &'static FlashLog {
+    let page = static_init!(NrfPage, NrfPage::default());
+    let log = static_init!(DebugLog, Log::new(&DEBUG_LOG, nvmc, page, true));
+    nvmc.set_client(log);
+    log.register();
+
+    let uart = static_init!(UartDevice<'static>, UartDevice::new(uart_mux, false));
+    uart.setup();
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let buffer = static_init!([u8; ENTRY_LEN], [0; ENTRY_LEN]);
+    let flash_log = static_init!(FlashLog, FlashLog::new(nvmc, log, uart, alarm, buffer));
+    log.set_read_client(flash_log);
+    log.set_append_client(flash_log);
+    uart.set_transmit_client(flash_log);
+    alarm.set_alarm_client(flash_log);
+    flash_log
+}
+
+/// Returns whether `pin`, a button that shorts to ground, is held down.
+pub fn button_held(pin: &GPIOPin) -> bool {
+    pin.make_input();
+    pin.set_floating_state(FloatingState::PullUp);
+    // Give the pull-up time to charge the line.
+    for _ in 0..1000 {
+        cortexm4::support::nop();
+    }
+    let held = !pin.read();
+    pin.deactivate_to_low_power();
+    held
+}
+
+pub struct FlashLog {
+    nvmc: &'static Nvmc,
+    log: &'static DebugLog,
+    uart: &'static UartDevice<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    /// Buffer the dump reads entries into.
+    buffer: TakeCell<'static, [u8]>,
+    /// Transmission waiting for a sync to finish.
+    waiting: TakeCell<'static, [u8]>,
+    waiting_len: Cell<usize>,
+    /// Whether an append or a sync is in progress.
+    busy: Cell<bool>,
+    /// Whether the log holds output that is not in flash yet.
+    unsynced: Cell<bool>,
+    suspended: Cell<bool>,
+    dump: Cell<Dump>,
+    client: OptionalCell<&'static dyn FlashLogClient>,
+    transmit_client: OptionalCell<&'static dyn uart::TransmitClient>,
+}
+
+impl FlashLog {
+    pub fn new(
+        nvmc: &'static Nvmc,
+        log: &'static DebugLog,
+        uart: &'static UartDevice<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        FlashLog {
+            nvmc,
+            log,
+            uart,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            waiting: TakeCell::empty(),
+            waiting_len: Cell::new(0),
+            busy: Cell::new(false),
+            unsynced: Cell::new(false),
+            suspended: Cell::new(false),
+            dump: Cell::new(Dump::Off),
+            client: OptionalCell::empty(),
+            transmit_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Writes the log over the UART, then calls `client`. The log takes no
+    /// output until the dump finishes.
+    pub fn dump(&self, client: &'static dyn FlashLogClient) {
+        self.client.set(client);
+        self.dump.set(Dump::Entries);
+        self.write_marker(DUMP_START);
+    }
+
+    /// Stops logging and returns whether the log is done with the NVMC. If
+    /// it is not, the log calls `client` once it is.
+    pub fn suspend(&self, client: &'static dyn FlashLogClient) -> bool {
+        self.suspended.set(true);
+        if self.busy.get() {
+            self.client.set(client);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Takes the NVMC back after `suspend` and continues logging.
+    pub fn resume(&self) {
+        if !self.suspended.replace(false) {
+            return;
+        }
+        self.nvmc.set_client(self.log);
+        if self.unsynced.get() {
+            self.schedule_sync();
+        }
+    }
+
+    fn schedule_sync(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(SYNC_MS));
+    }
+
+    /// Finishes an append or a sync, and tells a client waiting for the log
+    /// to suspend.
+    fn finished(&self) {
+        self.busy.set(false);
+        if self.suspended.get() {
+            self.client.take().map(|client| client.log_idle());
+        }
+    }
+
+    fn write_marker(&self, marker: &[u8]) {
+        let Some(buffer) = self.buffer.take() else {
+            return;
+        };
+        buffer[..marker.len()].copy_from_slice(marker);
+        if let Err((_, buffer)) = self.uart.transmit_buffer(buffer, marker.len()) {
+            self.buffer.replace(buffer);
+            self.end_dump();
+        }
+    }
+
+    fn read_next(&self) {
+        let Some(buffer) = self.buffer.take() else {
+            return;
+        };
+        if let Err((_, buffer)) = self.log.read(buffer, ENTRY_LEN) {
+            // The log has no entry left.
+            self.buffer.replace(buffer);
+            self.dump.set(Dump::End);
+            self.write_marker(DUMP_END);
+        }
+    }
+
+    fn end_dump(&self) {
+        self.dump.set(Dump::Off);
+        self.client.take().map(|client| client.log_idle());
+    }
+}
+
+impl Transmit<'static> for FlashLog {
+    fn set_transmit_client(&self, client: &'static dyn uart::TransmitClient) {
+        self.transmit_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.suspended.get() || self.dump.get() != Dump::Off {
+            return Err((ErrorCode::OFF, tx_buffer));
+        }
+        if self.busy.get() {
+            if self.waiting.is_some() {
+                return Err((ErrorCode::BUSY, tx_buffer));
+            }
+            // A sync is in progress, so append once it finishes.
+            self.waiting.replace(tx_buffer);
+            self.waiting_len.set(tx_len);
+            return Ok(());
+        }
+        self.log.append(tx_buffer, tx_len)?;
+        self.busy.set(true);
+        Ok(())
+    }
+
+    fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+}
+
+impl LogWriteClient for FlashLog {
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        _records_lost: bool,
+        error: Result<(), ErrorCode>,
+    ) {
+        self.finished();
+        if !self.suspended.get() {
+            self.unsynced.set(true);
+            self.schedule_sync();
+        }
+        self.transmit_client
+            .map(move |client| client.transmitted_buffer(buffer, length, error));
+    }
+
+    fn sync_done(&self, _error: Result<(), ErrorCode>) {
+        if let Some(buffer) = self.waiting.take() {
+            let result = if self.suspended.get() {
+                Err((ErrorCode::OFF, buffer))
+            } else {
+                self.log.append(buffer, self.waiting_len.get())
+            };
+            if let Err((error, buffer)) = result {
+                self.transmit_client
+                    .map(move |client| client.transmitted_buffer(buffer, 0, Err(error)));
+            } else {
+                // The append finishes the log's work.
+                return;
+            }
+        }
+        self.finished();
+    }
+
+    fn erase_done(&self, _error: Result<(), ErrorCode>) {}
+}
+
+impl LogReadClient for FlashLog {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, error: Result<(), ErrorCode>) {
+        if error.is_err() {
+            self.buffer.replace(buffer);
+            self.dump.set(Dump::End);
+            self.write_marker(DUMP_END);
+            return;
+        }
+        if let Err((_, buffer)) = self.uart.transmit_buffer(buffer, length) {
+            self.buffer.replace(buffer);
+            self.end_dump();
+        }
+    }
+
+    fn seek_done(&self, _error: Result<(), ErrorCode>) {}
+}
+
+impl uart::TransmitClient for FlashLog {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rval: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(buffer);
+        match self.dump.get() {
+            Dump::Entries => self.read_next(),
+            Dump::End => self.end_dump(),
+            Dump::Off => {}
+        }
+    }
+}
+
+impl AlarmClient for FlashLog {
+    fn alarm(&self) {
+        if self.suspended.get() || !self.unsynced.get() {
+            return;
+        }
+        if self.busy.get() {
+            // The append that finishes schedules the sync again.
+            return;
+        }
+        match self.log.sync() {
+            Ok(()) => {
+                self.unsynced.set(false);
+                // A sync of a page that holds no entry makes no callback.
+                if self.log.log_end() % PAGE_SIZE != PAGE_HEADER_SIZE {
+                    self.busy.set(true);
+                }
+            }
+            Err(_) => self.schedule_sync(),
+        }
+    }
+}
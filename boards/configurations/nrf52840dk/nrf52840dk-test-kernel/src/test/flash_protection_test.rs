@@ -0,0 +1,240 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks flash protection by the nRF52840 ACL through the NVMC driver. The
+//! test protects one page from writes and uses the page after it as an
+//! unprotected control. Both are below the pages the flash conformance tests
+//! use, and the protection lasts until the next reset. The cases are:
+//!
+//! 1. `Configure`: the region is configured once, then reconfiguring it, an
+//!    out of range region and an unaligned region are refused.
+//! 2. `WriteProtected`: writing and erasing the protected page through the
+//!    driver are rejected, and its contents are unchanged.
+//! 3. `WriteUnprotected`: writing the unprotected page still succeeds and
+//!    the page holds the written pattern.
+//!
+//! The expected output ends with
+//! FlashProtection: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::flash::{self, Flash, HasClient};
+use kernel::static_init;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use nrf52840::acl::{Acl, Permissions, NUM_REGIONS};
+use nrf52840::nvmc::{NrfPage, Nvmc};
+
+use crate::test::registry::report;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Page the test protects from writes.
+const PROTECTED_PAGE: usize = 250;
+
+/// Page next to the protected one that stays writable.
+const UNPROTECTED_PAGE: usize = 251;
+
+/// ACL region the test configures.
+const REGION: usize = 0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Configure,
+    WriteProtected,
+    WriteUnprotected,
+}
+
+pub unsafe fn run_flash_protection(
+    nvmc: &'static Nvmc,
+    acl: &'static Acl,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let page = static_init!(NrfPage, NrfPage::default());
+    let test = static_init!(
+        TestFlashProtection,
+        TestFlashProtection::new(nvmc, acl, page)
+    );
+    nvmc.set_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+/// Returns the contents of flash page `page`.
+fn page_contents(page: usize) -> &'static [u8] {
+    // SAFETY: the page is in internal flash, which is always mapped and
+    // readable unless an ACL region forbids it. The test only protects
+    // against writes.
+    unsafe { core::slice::from_raw_parts((page * PAGE_SIZE) as *const u8, PAGE_SIZE) }
+}
+
+/// Pattern written to the unprotected page.
+fn pattern(i: usize) -> u8 {
+    (i as u8) ^ 0xa5
+}
+
+pub struct TestFlashProtection {
+    nvmc: &'static Nvmc,
+    acl: &'static Acl,
+    buffer: TakeCell<'static, NrfPage>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestFlashProtection {
+    pub fn new(nvmc: &'static Nvmc, acl: &'static Acl, buffer: &'static mut NrfPage) -> Self {
+        TestFlashProtection {
+            nvmc,
+            acl,
+            buffer: TakeCell::new(buffer),
+            step: Cell::new(Step::Configure),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.configure().and_then(|()| self.write_protected()) {
+            self.fail(reason);
+            return;
+        }
+        self.write_unprotected();
+    }
+
+    fn configure(&self) -> Result<(), &'static str> {
+        self.step.set(Step::Configure);
+        let address = PROTECTED_PAGE * PAGE_SIZE;
+        self.acl
+            .protect_region(REGION, address, PAGE_SIZE, Permissions::ReadOnly)
+            .map_err(|_| "region not configured")?;
+        if self.acl.protect_region(
+            REGION,
+            address + PAGE_SIZE,
+            PAGE_SIZE,
+            Permissions::ReadOnly,
+        ) != Err(ErrorCode::ALREADY)
+        {
+            return Err("configured region changed");
+        }
+        if self
+            .acl
+            .protect_region(NUM_REGIONS, address, PAGE_SIZE, Permissions::ReadOnly)
+            != Err(ErrorCode::INVAL)
+        {
+            return Err("region out of range accepted");
+        }
+        if self
+            .acl
+            .protect_region(REGION + 1, address + 1, PAGE_SIZE, Permissions::ReadOnly)
+            != Err(ErrorCode::INVAL)
+        {
+            return Err("unaligned region accepted");
+        }
+        if !self.acl.is_write_protected(address, PAGE_SIZE) {
+            return Err("page not protected");
+        }
+        if self
+            .acl
+            .is_write_protected(UNPROTECTED_PAGE * PAGE_SIZE, PAGE_SIZE)
+        {
+            return Err("neighbouring page protected");
+        }
+        Ok(())
+    }
+
+    fn write_protected(&self) -> Result<(), &'static str> {
+        self.step.set(Step::WriteProtected);
+        let buffer = self.buffer.take().ok_or("buffer missing")?;
+        let contents = page_contents(PROTECTED_PAGE);
+        // Writing the complement guarantees every byte would change.
+        for (byte, old) in buffer.as_mut().iter_mut().zip(contents) {
+            *byte = !old;
+        }
+        match self.nvmc.write_page(PROTECTED_PAGE, buffer) {
+            Ok(()) => return Err("protected write accepted"),
+            Err((_, buffer)) => self.buffer.replace(buffer),
+        };
+        if self.nvmc.erase_page(PROTECTED_PAGE).is_ok() {
+            return Err("protected erase accepted");
+        }
+        let unchanged = self.buffer.map_or(false, |buffer| {
+            buffer
+                .as_mut()
+                .iter()
+                .zip(page_contents(PROTECTED_PAGE))
+                .all(|(written, now)| *written == !now)
+        });
+        if !unchanged {
+            return Err("protected page changed");
+        }
+        Ok(())
+    }
+
+    fn write_unprotected(&self) {
+        self.step.set(Step::WriteUnprotected);
+        let Some(buffer) = self.buffer.take() else {
+            self.fail("buffer missing");
+            return;
+        };
+        for (i, byte) in buffer.as_mut().iter_mut().enumerate() {
+            *byte = pattern(i);
+        }
+        if let Err((_, buffer)) = self.nvmc.write_page(UNPROTECTED_PAGE, buffer) {
+            self.buffer.replace(buffer);
+            self.fail("unprotected write rejected");
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("FlashProtection: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "FlashProtection", result);
+    }
+}
+
+impl flash::Client<Nvmc> for TestFlashProtection {
+    fn read_complete(&self, read_buffer: &'static mut NrfPage, _result: Result<(), flash::Error>) {
+        self.buffer.replace(read_buffer);
+        self.fail("unexpected read_complete");
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut NrfPage, result: Result<(), flash::Error>) {
+        self.buffer.replace(write_buffer);
+        if self.finished.get() {
+            return;
+        }
+        if result.is_err() {
+            self.fail("unprotected write failed");
+            return;
+        }
+        let written = page_contents(UNPROTECTED_PAGE)
+            .iter()
+            .enumerate()
+            .all(|(i, byte)| *byte == pattern(i));
+        if !written {
+            self.fail("unprotected page does not hold the pattern");
+            return;
+        }
+        self.finish(Ok(()));
+    }
+
+    fn erase_complete(&self, _result: Result<(), flash::Error>) {
+        self.fail("unexpected erase_complete");
+    }
+}
+
+impl CapsuleTest for TestFlashProtection {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
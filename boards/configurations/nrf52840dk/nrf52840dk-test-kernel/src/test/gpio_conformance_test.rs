@@ -0,0 +1,42 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the `hil::gpio` conformance cases on the pins listed in
+//! `BOARD_TEST_CONFIG.gpio_loopback`, which must be connected by a jumper.
+//!
+//! The expected output ends with
+//! GpioConformance: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::test::conformance::gpio::TestGpioConformance;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::Port;
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::skip;
+
+type GpioConformanceTest = TestGpioConformance<'static, VirtualMuxAlarm<'static, Rtc<'static>>>;
+
+pub unsafe fn run_gpio_conformance(
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.gpio_loopback.as_ref() else {
+        skip(client, "GpioConformance: no loopback pins configured");
+        return;
+    };
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        GpioConformanceTest,
+        TestGpioConformance::new(&gpio_port[pins.output], &gpio_port[pins.input], alarm)
+    );
+    test.set_client(client);
+    test.run();
+}
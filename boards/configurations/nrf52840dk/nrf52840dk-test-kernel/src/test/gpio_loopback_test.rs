@@ -0,0 +1,209 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Drives the output pin listed in `BOARD_TEST_CONFIG.gpio_loopback` with the
+//! nRF52840 GPIO driver and checks what the jumpered input pin sees, down to
+//! the GPIOTE interrupt of each edge. In each case the interrupt of the input
+//! is enabled for one edge, and the output drives `EDGES` edges from low, one
+//! per `EDGE_WINDOW_MS`, as the GPIOTE event of two edges before the bottom
+//! half reads as one. After each edge the input reads the new level, and has
+//! had an interrupt for each edge so far that matches, and none for the
+//! others. The cases are:
+//!
+//! 1. `RisingEdge`: `set()` and `clear()` drive the edges.
+//! 2. `FallingEdge`: the same, with interrupts for falling edges.
+//! 3. `EitherEdge`: `toggle()` drives the edges, and returns the new level.
+//!
+//! The expected output ends with
+//! GpioLoopback: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::MuxAlarm;
+use kernel::debug;
+use kernel::hil::gpio::{Client, Configure, Input, Interrupt, InterruptEdge, Output};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::{GPIOPin, Port};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::{report, skip};
+
+/// Edges the output drives in each case. Even, so each case ends low.
+const EDGES: usize = 4;
+
+/// Time an edge has to reach the bottom half of its interrupt.
+const EDGE_WINDOW_MS: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    RisingEdge,
+    FallingEdge,
+    EitherEdge,
+}
+
+pub unsafe fn run_gpio_loopback(
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.gpio_loopback.as_ref() else {
+        skip(client, "GpioLoopback: no loopback pins configured");
+        return;
+    };
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestGpioLoopback,
+        TestGpioLoopback::new(&gpio_port[pins.output], &gpio_port[pins.input], alarm)
+    );
+    alarm.set_alarm_client(test);
+    gpio_port[pins.input].set_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestGpioLoopback {
+    output: &'static GPIOPin<'static>,
+    input: &'static GPIOPin<'static>,
+    alarm: &'static TestAlarm,
+    /// Edges driven in this case, and the interrupts expected and taken for
+    /// them.
+    edges: Cell<usize>,
+    expected: Cell<usize>,
+    interrupts: Cell<usize>,
+    /// Level the last `toggle()` returned.
+    returned_level: Cell<bool>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestGpioLoopback {
+    pub fn new(
+        output: &'static GPIOPin<'static>,
+        input: &'static GPIOPin<'static>,
+        alarm: &'static TestAlarm,
+    ) -> Self {
+        TestGpioLoopback {
+            output,
+            input,
+            alarm,
+            edges: Cell::new(0),
+            expected: Cell::new(0),
+            interrupts: Cell::new(0),
+            returned_level: Cell::new(false),
+            step: Cell::new(Step::RisingEdge),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.output.make_output();
+        self.output.clear();
+        self.input.make_input();
+        self.start(Step::RisingEdge, InterruptEdge::RisingEdge);
+    }
+
+    /// Enables the interrupt of the input for `edge` and drives the first
+    /// edge of `step`. The output is low, as `EDGES` is even.
+    fn start(&self, step: Step, edge: InterruptEdge) {
+        self.step.set(step);
+        self.edges.set(0);
+        self.expected.set(0);
+        self.interrupts.set(0);
+        self.input.enable_interrupts(edge);
+        self.drive_edge();
+    }
+
+    fn drive_edge(&self) {
+        // The output starts each case low, so the edges alternate from
+        // rising.
+        let high = self.edges.get() % 2 == 0;
+        let matches = match self.step.get() {
+            Step::RisingEdge => high,
+            Step::FallingEdge => !high,
+            Step::EitherEdge => true,
+        };
+        if self.step.get() == Step::EitherEdge {
+            self.returned_level.set(self.output.toggle());
+        } else if high {
+            self.output.set();
+        } else {
+            self.output.clear();
+        }
+        self.edges.set(self.edges.get() + 1);
+        self.expected
+            .set(self.expected.get() + usize::from(matches));
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(EDGE_WINDOW_MS));
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        let high = self.edges.get() % 2 == 1;
+        // `toggle()` only drives the edges of `EitherEdge`.
+        let returned = self.step.get() != Step::EitherEdge || self.returned_level.get() == high;
+        if self.input.read() != high || !returned {
+            return Err("level not read back");
+        }
+        if self.interrupts.get() != self.expected.get() {
+            return Err("interrupts do not match the edges");
+        }
+        if self.edges.get() < EDGES {
+            self.drive_edge();
+            return Ok(());
+        }
+        self.input.disable_interrupts();
+        match self.step.get() {
+            Step::RisingEdge => self.start(Step::FallingEdge, InterruptEdge::FallingEdge),
+            Step::FallingEdge => self.start(Step::EitherEdge, InterruptEdge::EitherEdge),
+            Step::EitherEdge => self.finish(Ok(())),
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("GpioLoopback: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.input.disable_interrupts();
+        self.output.deactivate_to_low_power();
+        self.input.deactivate_to_low_power();
+        report(&self.client, "GpioLoopback", result);
+    }
+}
+
+impl AlarmClient for TestGpioLoopback {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl Client for TestGpioLoopback {
+    fn fired(&self) {
+        self.interrupts.set(self.interrupts.get() + 1);
+    }
+}
+
+impl CapsuleTest for TestGpioLoopback {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
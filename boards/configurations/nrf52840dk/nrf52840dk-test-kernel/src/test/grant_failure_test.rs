@@ -0,0 +1,174 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks what capsules see when a process's grant region is full. An
+//! embedded app is loaded and its grant region is filled with custom grant
+//! allocations, until not even a word can be allocated. The cases are:
+//!
+//! 1. `Saturate`: the filling leaves the process running.
+//! 2. `Allocate`: entering the test grant fails with `OutOfMemory`, and the
+//!    alarm driver's `allocate_grant()` and `command()` report `NOMEM`. A
+//!    second attempt fails the same way, so a failed allocation leaves no
+//!    half-allocated grant behind.
+//! 3. `Recover`: after the process restarts with an empty grant region, the
+//!    same capsules serve it normally.
+//!
+//! The expected output ends with
+//! GrantFailure: all cases passed
+
+use capsules_core::alarm::AlarmDriver;
+use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+use kernel::debug;
+use kernel::process::{Error, Process, ProcessId};
+use kernel::syscall::SyscallDriver;
+use kernel::{capabilities, create_capability, ErrorCode};
+use nrf52840::rtc::Rtc;
+
+use crate::test::embedded_apps::{AppLoader, SLOT_APP_ONE};
+
+/// Largest custom grant allocated while filling the grant region. Smaller
+/// allocations follow, halving down to a single word.
+const FILL_CHUNK_LEN: usize = 1024;
+
+/// Alarm driver command that returns the alarm frequency.
+const ALARM_FREQUENCY: usize = 1;
+
+pub(crate) type TestAlarmDriver = AlarmDriver<'static, VirtualMuxAlarm<'static, Rtc<'static>>>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Saturate,
+    Allocate,
+    Recover,
+}
+
+pub unsafe fn run_grant_failure(
+    apps: &'static AppLoader,
+    alarm_driver: &'static TestAlarmDriver,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let mut step = Step::Saturate;
+    let result = check_grant_failure(apps, alarm_driver, &mut step);
+    if let Err(reason) = result {
+        debug!("GrantFailure: {:?} failed: {}", step, reason);
+        client.done(Err(CapsuleTestError::IncorrectResult));
+        return;
+    }
+    debug!("GrantFailure: all cases passed");
+    client.done(Ok(()));
+}
+
+fn ensure(condition: bool, reason: &'static str) -> Result<(), &'static str> {
+    if condition {
+        Ok(())
+    } else {
+        Err(reason)
+    }
+}
+
+/// Allocates custom grants in `process` until no allocation of a single
+/// word succeeds. Returns the number of bytes allocated.
+fn fill_grant_region(process: &dyn Process) -> usize {
+    let mut filled = 0;
+    let mut chunk = FILL_CHUNK_LEN;
+    while chunk >= core::mem::size_of::<usize>() {
+        if process
+            .allocate_custom_grant(chunk, core::mem::size_of::<usize>())
+            .is_ok()
+        {
+            filled += chunk;
+        } else {
+            chunk /= 2;
+        }
+    }
+    filled
+}
+
+/// Checks that neither the test grant nor the alarm driver can allocate a
+/// grant for `processid`.
+fn check_allocation_fails(
+    apps: &AppLoader,
+    alarm_driver: &TestAlarmDriver,
+    processid: ProcessId,
+) -> Result<(), &'static str> {
+    ensure(
+        matches!(
+            apps.grant.enter(processid, |_, _| ()),
+            Err(Error::OutOfMemory)
+        ),
+        "test grant allocated in full grant region",
+    )?;
+    ensure(
+        matches!(
+            alarm_driver.allocate_grant(processid),
+            Err(Error::OutOfMemory)
+        ),
+        "alarm grant allocated in full grant region",
+    )?;
+    ensure(
+        alarm_driver
+            .command(ALARM_FREQUENCY, 0, 0, processid)
+            .get_failure()
+            == Some(ErrorCode::NOMEM),
+        "alarm command did not fail with NOMEM",
+    )
+}
+
+fn check_grant_failure(
+    apps: &'static AppLoader,
+    alarm_driver: &TestAlarmDriver,
+    step: &mut Step,
+) -> Result<(), &'static str> {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let process_start_cap = create_capability!(capabilities::ProcessStartCapability);
+
+    *step = Step::Saturate;
+    let process: &dyn Process = apps
+        .load(&SLOT_APP_ONE)
+        .map_err(|_| "loading failed")?
+        .ok_or("no process created")?;
+    let processid = process.processid();
+    ensure(fill_grant_region(process) > 0, "grant region already full")?;
+    ensure(process.is_running(), "process stopped by filling")?;
+
+    *step = Step::Allocate;
+    check_allocation_fails(apps, alarm_driver, processid)?;
+    check_allocation_fails(apps, alarm_driver, processid)?;
+    ensure(
+        process.grant_allocated_count() == Some(0),
+        "failed allocation counted",
+    )?;
+    ensure(process.is_running(), "process stopped by failed allocation")?;
+
+    *step = Step::Recover;
+    process.terminate(None);
+    process.start(&process_start_cap);
+    let processid = process.processid();
+    ensure(
+        apps.grant
+            .enter(processid, |data, _| data.marker == 0)
+            .unwrap_or(false),
+        "test grant not allocated after restart",
+    )?;
+    alarm_driver
+        .allocate_grant(processid)
+        .map_err(|_| "alarm grant not allocated after restart")?;
+    ensure(
+        alarm_driver
+            .command(ALARM_FREQUENCY, 0, 0, processid)
+            .is_success_u32(),
+        "alarm command failed after restart",
+    )?;
+    ensure(
+        process.grant_allocated_count() == Some(2),
+        "wrong number of grants allocated",
+    )?;
+
+    // Free the slot for later tests.
+    process.terminate(None);
+    apps.kernel()
+        .remove_process(processid, &process_management_cap)
+        .map_err(|_| "terminated process not removed")
+}
@@ -0,0 +1,112 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the `hil::i2c` conformance cases on TWI1 against the device listed in
+//! `BOARD_TEST_CONFIG.i2c_target`, and the master/slave loopback test with
+//! TWI0 as the master and TWI1 as the slave on the pins listed in
+//! `BOARD_TEST_CONFIG.i2c_loopback`. The loopback relies on the internal
+//! pull-ups, so keep the jumper wires short.
+//!
+//! The expected output ends with
+//! I2cConformance: all cases passed
+//! I2cLoopback: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::test::conformance::i2c::{TestI2cConformance, TestI2cLoopback};
+use kernel::hil::gpio::{Configure, FloatingState};
+use kernel::static_init;
+use nrf52840::gpio::Port;
+use nrf52840::i2c::{Speed, TWI};
+use nrf52840::pinmux::Pinmux;
+
+use crate::test::config::{I2cLoopbackPins, BOARD_TEST_CONFIG};
+use crate::test::registry::skip;
+
+/// Address the TWIS answers to in the loopback test.
+pub const LOOPBACK_ADDRESS: u8 = 0x42;
+
+/// Whether the loopback pins were handed to the TWIs, which `Pinmux::new()`
+/// allows once per pin.
+static mut LOOPBACK_CONFIGURED: bool = false;
+
+/// Connects `master` and `slave` to the jumpered loopback `pins`, with the
+/// master at 100 kHz.
+pub unsafe fn configure_loopback(
+    pins: &I2cLoopbackPins,
+    master: &TWI,
+    slave: &TWI,
+    gpio_port: &Port<'static, { nrf52840::gpio::NUM_PINS }>,
+) {
+    if LOOPBACK_CONFIGURED {
+        return;
+    }
+    LOOPBACK_CONFIGURED = true;
+    for pin in [
+        pins.master_scl,
+        pins.master_sda,
+        pins.slave_scl,
+        pins.slave_sda,
+    ] {
+        gpio_port[pin].set_i2c_pin_cfg();
+    }
+    // One set of pull-ups is enough for the jumpered bus.
+    gpio_port[pins.master_scl].set_floating_state(FloatingState::PullUp);
+    gpio_port[pins.master_sda].set_floating_state(FloatingState::PullUp);
+
+    master.configure(
+        Pinmux::new(pins.master_scl as u32),
+        Pinmux::new(pins.master_sda as u32),
+    );
+    master.set_speed(Speed::K100);
+    slave.configure(
+        Pinmux::new(pins.slave_scl as u32),
+        Pinmux::new(pins.slave_sda as u32),
+    );
+}
+
+pub unsafe fn run_i2c_conformance(
+    twi: &'static TWI<'static>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(config) = BOARD_TEST_CONFIG.i2c_target.as_ref() else {
+        skip(client, "I2cConformance: no target device configured");
+        return;
+    };
+
+    twi.configure(
+        Pinmux::new(config.scl as u32),
+        Pinmux::new(config.sda as u32),
+    );
+    twi.set_speed(Speed::K100);
+
+    let buffer = static_init!([u8; 32], [0; 32]);
+    let test = static_init!(
+        TestI2cConformance<'static, TWI<'static>>,
+        TestI2cConformance::new(twi, &config.target, buffer)
+    );
+    test.set_client(client);
+    test.run();
+}
+
+pub unsafe fn run_i2c_loopback(
+    master: &'static TWI<'static>,
+    slave: &'static TWI<'static>,
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.i2c_loopback.as_ref() else {
+        skip(client, "I2cLoopback: no loopback pins configured");
+        return;
+    };
+    configure_loopback(pins, master, slave, gpio_port);
+
+    let master_buffer = static_init!([u8; 16], [0; 16]);
+    let slave_buffer = static_init!([u8; 16], [0; 16]);
+    let test = static_init!(
+        TestI2cLoopback<'static, TWI<'static>, TWI<'static>>,
+        TestI2cLoopback::new(master, slave, LOOPBACK_ADDRESS, master_buffer, slave_buffer)
+    );
+    test.set_client(client);
+    test.run();
+}
@@ -0,0 +1,308 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that the TWIM master recovers the bus from a slave holding SDA low,
+//! on the loopback bus of the I2C loopback test, with TWI0 as the master and
+//! TWI1 as the slave. The pin `stuck_sda`, jumpered to SDA, holds it low in
+//! place of the slave. To act like a slave that was reset in the middle of a
+//! byte, the GPIOTE events of the falling edges of SCL count in TIMER2
+//! through PPI, and TIMER2 lets go of SDA after `RELEASE_PULSES` of them,
+//! without the CPU, which runs the recovery. The cases are:
+//!
+//! 1. `Stuck`: with SDA held low for good, the recovery gives up and reports
+//!    the bus still stuck.
+//! 2. `Recover`: with SDA let go after `RELEASE_PULSES`, the recovery clocks
+//!    SCL no more than that, before the STOP condition, and frees the bus.
+//! 3. `Transfer`: a write then reaches the slave.
+//!
+//! The expected output ends with
+//! I2cRecovery: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::gpio::{Configure, Interrupt, InterruptEdge, Output};
+use kernel::hil::i2c::{
+    Error, I2CHwMasterClient, I2CHwSlaveClient, I2CMaster, I2CSlave, SlaveTransmissionType,
+};
+use kernel::static_init;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use nrf52840::gpio::{GPIOPin, Port};
+use nrf52840::i2c::TWI;
+use nrf52840::ppi::{Channel, Ppi};
+use nrf52840::timer::{BitmodeValue, Timer};
+
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::i2c_conformance_test::{configure_loopback, LOOPBACK_ADDRESS};
+use crate::test::registry::{report, skip};
+
+/// Falling edges of SCL after which the stuck slave lets go of SDA.
+const RELEASE_PULSES: u32 = 3;
+
+/// PPI channels from the SCL edges to TIMER2, and from TIMER2 to the pin
+/// holding SDA.
+const COUNT_CHANNEL: usize = 0;
+const RELEASE_CHANNEL: usize = 1;
+
+/// Bytes of the write of the `Transfer` case.
+const LEN: usize = 4;
+const PATTERN: [u8; LEN] = [0xc3, 0x3c, 0x81, 0x7e];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Stuck,
+    Recover,
+    Transfer,
+}
+
+pub unsafe fn run_i2c_recovery(
+    master: &'static TWI<'static>,
+    slave: &'static TWI<'static>,
+    timer: &'static Timer,
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some((pins, stuck_sda)) = BOARD_TEST_CONFIG
+        .i2c_loopback
+        .as_ref()
+        .and_then(|pins| Some((pins, pins.stuck_sda?)))
+    else {
+        skip(client, "I2cRecovery: no pin jumpered to SDA configured");
+        return;
+    };
+    configure_loopback(pins, master, slave, gpio_port);
+
+    let master_buffer = static_init!([u8; LEN], [0; LEN]);
+    let slave_buffer = static_init!([u8; LEN], [0; LEN]);
+    let test = static_init!(
+        TestI2cRecovery,
+        TestI2cRecovery::new(
+            master,
+            slave,
+            timer,
+            &gpio_port[pins.slave_scl],
+            &gpio_port[stuck_sda],
+            master_buffer,
+            slave_buffer,
+        )
+    );
+    master.set_master_client(test);
+    slave.set_slave_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestI2cRecovery {
+    master: &'static TWI<'static>,
+    slave: &'static TWI<'static>,
+    timer: &'static Timer,
+    ppi: Ppi,
+    /// The SCL pin of the slave, which is free for the GPIOTE while the
+    /// slave is off.
+    scl: &'static GPIOPin<'static>,
+    stuck_sda: &'static GPIOPin<'static>,
+    master_buffer: TakeCell<'static, [u8]>,
+    slave_buffer: TakeCell<'static, [u8]>,
+    /// Whether each side saw the write of the `Transfer` case.
+    master_done: Cell<bool>,
+    slave_done: Cell<bool>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestI2cRecovery {
+    pub fn new(
+        master: &'static TWI<'static>,
+        slave: &'static TWI<'static>,
+        timer: &'static Timer,
+        scl: &'static GPIOPin<'static>,
+        stuck_sda: &'static GPIOPin<'static>,
+        master_buffer: &'static mut [u8],
+        slave_buffer: &'static mut [u8],
+    ) -> Self {
+        TestI2cRecovery {
+            master,
+            slave,
+            timer,
+            ppi: Ppi::new(),
+            scl,
+            stuck_sda,
+            master_buffer: TakeCell::new(master_buffer),
+            slave_buffer: TakeCell::new(slave_buffer),
+            master_done: Cell::new(false),
+            slave_done: Cell::new(false),
+            step: Cell::new(Step::Stuck),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        I2CSlave::disable(self.slave);
+        I2CMaster::enable(self.master);
+        if let Err(reason) = self.stuck().and_then(|()| self.recover()) {
+            self.release();
+            self.fail(reason);
+            return;
+        }
+        self.release();
+        if let Err(reason) = self.transfer() {
+            self.fail(reason);
+        }
+    }
+
+    fn stuck(&self) -> Result<(), &'static str> {
+        // Like the TWI, the pin drives only low.
+        self.stuck_sda.set_i2c_pin_cfg();
+        self.stuck_sda.clear();
+        self.stuck_sda.make_output();
+        match self.master.recover_bus() {
+            Err(ErrorCode::FAIL) => Ok(()),
+            Ok(()) => Err("recovery reported a stuck bus free"),
+            Err(_) => Err("recovery failed to run"),
+        }
+    }
+
+    fn recover(&self) -> Result<(), &'static str> {
+        self.step.set(Step::Recover);
+        let edges = self
+            .scl
+            .enable_event(InterruptEdge::FallingEdge)
+            .ok_or("no GPIOTE channel for SCL")?;
+        let release = self
+            .stuck_sda
+            .enable_toggle_task(false)
+            .ok_or("no GPIOTE channel for SDA")?;
+        self.ppi
+            .configure_channel(COUNT_CHANNEL, edges, self.timer.count_task_address())
+            .and_then(|()| {
+                self.ppi.configure_channel(
+                    RELEASE_CHANNEL,
+                    self.timer.compare_event_address(0),
+                    release,
+                )
+            })
+            .map_err(|_| "PPI channels not configured")?;
+        self.timer.set_compare(0, RELEASE_PULSES, false);
+        self.timer.start_counter(BitmodeValue::Size32Bits);
+        self.ppi.enable(Channel::CH0::SET + Channel::CH1::SET);
+
+        self.master
+            .recover_bus()
+            .map_err(|_| "recovery did not free the bus")?;
+        // The pulses, and the falling edge before the STOP condition.
+        let pulses = self.timer.capture(1);
+        debug!("I2cRecovery: bus free after {} falling edges", pulses);
+        if pulses != RELEASE_PULSES + 1 {
+            return Err("recovery clocked SCL the wrong number of times");
+        }
+        Ok(())
+    }
+
+    /// Lets go of SDA and of the GPIOTE channels, PPI channels and timer.
+    fn release(&self) {
+        self.ppi.disable(Channel::CH0::SET + Channel::CH1::SET);
+        self.timer.stop();
+        self.scl.disable_interrupts();
+        self.scl.set_i2c_pin_cfg();
+        self.stuck_sda.disable_toggle_task();
+        self.stuck_sda.set_i2c_pin_cfg();
+    }
+
+    fn transfer(&self) -> Result<(), &'static str> {
+        self.step.set(Step::Transfer);
+        I2CSlave::enable(self.slave);
+        self.slave
+            .set_address(LOOPBACK_ADDRESS)
+            .map_err(|_| "slave address not set")?;
+        let buffer = self.slave_buffer.take().ok_or("slave buffer missing")?;
+        buffer.fill(0);
+        self.slave
+            .write_receive(buffer, LEN)
+            .map_err(|(_, buffer)| {
+                self.slave_buffer.replace(buffer);
+                "slave rejected the write buffer"
+            })?;
+
+        let buffer = self.master_buffer.take().ok_or("master buffer missing")?;
+        buffer.copy_from_slice(&PATTERN);
+        self.master
+            .write(LOOPBACK_ADDRESS, buffer, LEN)
+            .map_err(|(_, buffer)| {
+                self.master_buffer.replace(buffer);
+                "write rejected"
+            })
+    }
+
+    fn check_transferred(&self) {
+        if self.master_done.get() && self.slave_done.get() {
+            self.finish(Ok(()));
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("I2cRecovery: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        I2CMaster::disable(self.master);
+        I2CSlave::disable(self.slave);
+        report(&self.client, "I2cRecovery", result);
+    }
+}
+
+impl I2CHwMasterClient for TestI2cRecovery {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        self.master_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        if status.is_err() {
+            self.fail("write after the recovery failed");
+            return;
+        }
+        self.master_done.set(true);
+        self.check_transferred();
+    }
+}
+
+impl I2CHwSlaveClient for TestI2cRecovery {
+    fn command_complete(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        transmission_type: SlaveTransmissionType,
+    ) {
+        let data = length == LEN && buffer[..LEN] == PATTERN;
+        self.slave_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        if let SlaveTransmissionType::Write = transmission_type {
+            if !data {
+                self.fail("slave received the wrong data");
+                return;
+            }
+            self.slave_done.set(true);
+            self.check_transferred();
+        }
+    }
+
+    fn read_expected(&self) {}
+
+    fn write_expected(&self) {}
+}
+
+impl CapsuleTest for TestI2cRecovery {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,330 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that the TWIM master tolerates a slave stretching the clock, and
+//! stops a transfer the slave stretches for too long, on the loopback bus of
+//! the I2C loopback test, with TWI0 as the master and TWI1 as the slave. The
+//! TWIS holds the clock low after a read command until it has a buffer to
+//! send, so the slave stretches a read for as long as the test waits to hand
+//! it one. TIMER1 times the transfers of the master. The cases are:
+//!
+//! 1. `Stretch`: reads the slave stretches for 1, 10 and 25 ms, the longest
+//!    an SMBus slave may stretch a message, complete with the data of the
+//!    slave.
+//! 2. `Timeout`: a read the slave stretches without end fails with
+//!    `Error::Timeout` once `TIMEOUT_US` passed, and not before.
+//! 3. `Recover`: after the slave let go of the bus, a write reaches the
+//!    slave, so the bus does not hang.
+//!
+//! The expected output ends with
+//! I2cStretch: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::i2c::{
+    Error, I2CHwMasterClient, I2CHwSlaveClient, I2CMaster, I2CSlave, SlaveTransmissionType,
+};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks, Ticks24, Time};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::Port;
+use nrf52840::i2c::{TIMEOUT_US, TWI};
+use nrf52840::rtc::Rtc;
+use nrf52840::timer::TimerAlarm;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::i2c_conformance_test::{configure_loopback, LOOPBACK_ADDRESS};
+use crate::test::registry::{report, skip};
+
+/// Times the slave stretches the reads of the `Stretch` case for.
+const STRETCH_MS: [u32; 3] = [1, 10, 25];
+
+/// Time past `TIMEOUT_US` the master may take to report the timeout.
+const TIMEOUT_SLACK_MS: u32 = 5;
+
+/// Bytes of each transfer.
+const LEN: usize = 4;
+
+/// The bytes the slave sends and the master writes.
+const PATTERN: [u8; LEN] = [0x5a, 0xa5, 0x0f, 0xf0];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Stretch,
+    Timeout,
+    Recover,
+}
+
+pub unsafe fn run_i2c_stretch(
+    master: &'static TWI<'static>,
+    slave: &'static TWI<'static>,
+    timer: &'static TimerAlarm<'static>,
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.i2c_loopback.as_ref() else {
+        skip(client, "I2cStretch: no loopback pins configured");
+        return;
+    };
+    configure_loopback(pins, master, slave, gpio_port);
+    // The timeout stays set up after the test, and TIMER1 has no other
+    // client.
+    master.set_timer_ref(timer);
+    timer.set_alarm_client(master);
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let master_buffer = static_init!([u8; LEN], [0; LEN]);
+    let slave_buffer = static_init!([u8; LEN], [0; LEN]);
+    let test = static_init!(
+        TestI2cStretch,
+        TestI2cStretch::new(master, slave, alarm, master_buffer, slave_buffer)
+    );
+    master.set_master_client(test);
+    slave.set_slave_client(test);
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestI2cStretch {
+    master: &'static TWI<'static>,
+    slave: &'static TWI<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    master_buffer: TakeCell<'static, [u8]>,
+    slave_buffer: TakeCell<'static, [u8]>,
+    /// Index in `STRETCH_MS` of the read being stretched.
+    case: Cell<usize>,
+    /// Start of the read that must time out.
+    started_at: Cell<Ticks24>,
+    /// Whether each side saw the write of the `Recover` case.
+    master_done: Cell<bool>,
+    slave_done: Cell<bool>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestI2cStretch {
+    pub fn new(
+        master: &'static TWI<'static>,
+        slave: &'static TWI<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        master_buffer: &'static mut [u8],
+        slave_buffer: &'static mut [u8],
+    ) -> Self {
+        TestI2cStretch {
+            master,
+            slave,
+            alarm,
+            master_buffer: TakeCell::new(master_buffer),
+            slave_buffer: TakeCell::new(slave_buffer),
+            case: Cell::new(0),
+            started_at: Cell::new(Ticks24::from(0)),
+            master_done: Cell::new(false),
+            slave_done: Cell::new(false),
+            step: Cell::new(Step::Stretch),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        I2CSlave::enable(self.slave);
+        let result = self
+            .slave
+            .set_address(LOOPBACK_ADDRESS)
+            .map_err(|_| "slave address not set")
+            .and_then(|()| {
+                I2CMaster::enable(self.master);
+                self.read(STRETCH_MS[0])
+            });
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+
+    /// Starts a read the slave stretches until the alarm fires after `ms`.
+    fn read(&self, ms: u32) -> Result<(), &'static str> {
+        let buffer = self.master_buffer.take().ok_or("master buffer missing")?;
+        buffer.fill(0);
+        self.master
+            .read(LOOPBACK_ADDRESS, buffer, LEN)
+            .map_err(|(_, buffer)| {
+                self.master_buffer.replace(buffer);
+                "read rejected"
+            })?;
+        let now = self.alarm.now();
+        self.started_at.set(now);
+        self.alarm.set_alarm(now, self.alarm.ticks_from_ms(ms));
+        Ok(())
+    }
+
+    /// Hands the slave the bytes of the stretched read.
+    fn release(&self) -> Result<(), &'static str> {
+        let buffer = self.slave_buffer.take().ok_or("slave buffer missing")?;
+        buffer.copy_from_slice(&PATTERN);
+        self.slave.read_send(buffer, LEN).map_err(|(_, buffer)| {
+            self.slave_buffer.replace(buffer);
+            "slave rejected the read buffer"
+        })
+    }
+
+    /// Resets the slave, which lets go of the clock, and writes to it.
+    fn recover(&self) -> Result<(), &'static str> {
+        self.step.set(Step::Recover);
+        I2CSlave::disable(self.slave);
+        I2CSlave::enable(self.slave);
+        self.slave
+            .set_address(LOOPBACK_ADDRESS)
+            .map_err(|_| "slave address not set")?;
+        let buffer = self.slave_buffer.take().ok_or("slave buffer missing")?;
+        buffer.fill(0);
+        self.slave
+            .write_receive(buffer, LEN)
+            .map_err(|(_, buffer)| {
+                self.slave_buffer.replace(buffer);
+                "slave rejected the write buffer"
+            })?;
+
+        let buffer = self.master_buffer.take().ok_or("master buffer missing")?;
+        buffer.copy_from_slice(&PATTERN);
+        self.master
+            .write(LOOPBACK_ADDRESS, buffer, LEN)
+            .map_err(|(_, buffer)| {
+                self.master_buffer.replace(buffer);
+                "write rejected"
+            })
+    }
+
+    /// Checks the end of a master transfer and starts the next one.
+    fn check_master(&self, status: Result<(), Error>, data: bool) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Stretch => {
+                status.map_err(|_| "stretched read failed")?;
+                if !data {
+                    return Err("stretched read returned the wrong data");
+                }
+                let case = self.case.get() + 1;
+                if case < STRETCH_MS.len() {
+                    self.case.set(case);
+                    self.read(STRETCH_MS[case])
+                } else {
+                    self.step.set(Step::Timeout);
+                    self.read(TIMEOUT_US / 1000 + TIMEOUT_SLACK_MS)
+                }
+            }
+            Step::Timeout => {
+                let elapsed = self
+                    .alarm
+                    .ticks_to_ms(self.alarm.now().wrapping_sub(self.started_at.get()));
+                debug!("I2cStretch: timed out after {} ms", elapsed);
+                if status != Err(Error::Timeout) {
+                    return Err("read did not time out");
+                }
+                if elapsed < TIMEOUT_US / 1000 {
+                    return Err("read timed out too early");
+                }
+                let _ = self.alarm.disarm();
+                self.recover()
+            }
+            Step::Recover => {
+                status.map_err(|_| "write after the timeout failed")?;
+                self.master_done.set(true);
+                self.check_recovered();
+                Ok(())
+            }
+        }
+    }
+
+    fn check_recovered(&self) {
+        if self.master_done.get() && self.slave_done.get() {
+            self.finish(Ok(()));
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("I2cStretch: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        I2CMaster::disable(self.master);
+        I2CSlave::disable(self.slave);
+        report(&self.client, "I2cStretch", result);
+    }
+}
+
+impl I2CHwMasterClient for TestI2cStretch {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        let data = buffer[..LEN] == PATTERN;
+        self.master_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check_master(status, data) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl I2CHwSlaveClient for TestI2cStretch {
+    fn command_complete(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        transmission_type: SlaveTransmissionType,
+    ) {
+        let data = length == LEN && buffer[..LEN] == PATTERN;
+        self.slave_buffer.replace(buffer);
+        if self.finished.get() || self.step.get() != Step::Recover {
+            return;
+        }
+        if let SlaveTransmissionType::Write = transmission_type {
+            if !data {
+                self.fail("slave received the wrong data");
+                return;
+            }
+            self.slave_done.set(true);
+            self.check_recovered();
+        }
+    }
+
+    // The slave is not prepared for reads on purpose, and writes only come
+    // once it is.
+    fn read_expected(&self) {}
+
+    fn write_expected(&self) {}
+}
+
+impl AlarmClient for TestI2cStretch {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let result = match self.step.get() {
+            Step::Stretch => self.release(),
+            Step::Timeout => Err("read did not time out"),
+            Step::Recover => Ok(()),
+        };
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestI2cStretch {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
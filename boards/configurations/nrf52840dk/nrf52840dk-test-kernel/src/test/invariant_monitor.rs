@@ -0,0 +1,82 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Receives the kernel invariants that do not hold, which the kernel checks as
+//! this board builds it with the `kernel_test` feature. The monitor prints
+//! each violation with the location of the check and counts them, so the test
+//! launcher can fail the test that was running when they happened. It also
+//! keeps the process state changes since tests last cleared them.
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::invariant::{InvariantClient, Violation};
+use kernel::process::{ProcessId, State};
+
+/// Number of process state changes the monitor keeps.
+const TRANSITIONS_LEN: usize = 16;
+
+pub struct InvariantMonitor {
+    violations: Cell<usize>,
+    /// The first state changes since the last `clear_transitions`.
+    transitions: [Cell<Option<(ProcessId, State, State)>>; TRANSITIONS_LEN],
+    transition_count: Cell<usize>,
+}
+
+impl InvariantMonitor {
+    pub fn new() -> Self {
+        InvariantMonitor {
+            violations: Cell::new(0),
+            transitions: [const { Cell::new(None) }; TRANSITIONS_LEN],
+            transition_count: Cell::new(0),
+        }
+    }
+
+    /// Returns the number of violations since the last call.
+    pub fn take_violations(&self) -> usize {
+        self.violations.replace(0)
+    }
+
+    /// Returns the number of violations since the last `take_violations`.
+    pub fn violations(&self) -> usize {
+        self.violations.get()
+    }
+
+    pub fn clear_transitions(&self) {
+        self.transitions
+            .iter()
+            .for_each(|transition| transition.set(None));
+        self.transition_count.set(0);
+    }
+
+    /// Returns the kept state changes of `processid`, oldest first, and
+    /// whether changes were dropped because the trace was full.
+    pub fn transitions(
+        &self,
+        processid: ProcessId,
+    ) -> (impl Iterator<Item = (State, State)> + '_, bool) {
+        let changes = self
+            .transitions
+            .iter()
+            .filter_map(|transition| transition.get())
+            .filter(move |(id, _, _)| *id == processid)
+            .map(|(_, from, to)| (from, to));
+        (changes, self.transition_count.get() > TRANSITIONS_LEN)
+    }
+}
+
+impl InvariantClient for InvariantMonitor {
+    fn violated(&self, violation: Violation) {
+        debug!("Kernel invariant violated: {}", violation);
+        self.violations.set(self.violations.get() + 1);
+    }
+
+    fn process_state_changed(&self, processid: ProcessId, from: State, to: State) {
+        let count = self.transition_count.get();
+        if let Some(transition) = self.transitions.get(count) {
+            transition.set(Some((processid, from, to)));
+        }
+        self.transition_count.set(count + 1);
+    }
+}
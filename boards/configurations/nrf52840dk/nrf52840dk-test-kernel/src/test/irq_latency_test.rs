@@ -0,0 +1,182 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Measures the time from a hardware interrupt to its bottom half in the
+//! kernel loop. The top half of every interrupt only marks it pending, and
+//! the kernel loop calls the driver of the peripheral once it gets to it, so
+//! this time is how late every driver learns of an event.
+//!
+//! A compare event of TIMER2 raises the interrupt, and the top half is
+//! entered a fixed few cycles later, so the compare value is the time of the
+//! interrupt. The TIMER2 driver calls the test from the bottom half, where
+//! it captures the counter again. Each case takes `SAMPLES` interrupts, at
+//! varying points of what the board does, and checks that none of them
+//! waited longer than `MAX_LATENCY_US` for its bottom half:
+//!
+//! 1. `Idle`: no process runs, so the interrupt mostly wakes the kernel from
+//!    sleep.
+//! 2. `Loaded`: two apps spin and share the core, so the interrupt stops a
+//!    process, which the kernel switches away from before it gets to the
+//!    bottom half.
+//!
+//! The expected output ends with
+//! IrqLatency: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::process::Process;
+use kernel::static_init;
+use kernel::utilities::cells::OptionalCell;
+use nrf52840::timer::{BitmodeValue, CompareClient, Timer};
+
+use crate::test::embedded_apps::{AppImage, AppLoader, BUSY_APP, SPINNER_APP};
+use crate::test::registry::report;
+
+/// TIMER2 counts at 16 MHz / 2^4 = 1 MHz.
+const TIMER_PRESCALER: u8 = 4;
+
+/// Interrupts each case takes.
+const SAMPLES: u32 = 100;
+
+/// Longest time an interrupt may wait for its bottom half. Switching away
+/// from a process and back into the kernel loop takes a few microseconds,
+/// so this leaves room for a bottom half of another interrupt that runs
+/// first.
+const MAX_LATENCY_US: u32 = 100;
+
+/// Apps that spin in the `Loaded` case.
+const APPS: [&AppImage; 2] = [&BUSY_APP, &SPINNER_APP];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Idle,
+    Loaded,
+}
+
+pub unsafe fn run_irq_latency(
+    timer: &'static Timer,
+    apps: &'static AppLoader,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let test = static_init!(TestIrqLatency, TestIrqLatency::new(timer, apps));
+    timer.set_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestIrqLatency {
+    timer: &'static Timer,
+    apps: &'static AppLoader,
+    processes: [OptionalCell<&'static dyn Process>; 2],
+    /// Interrupts taken in this case, and their longest and total latency.
+    samples: Cell<u32>,
+    max_us: Cell<u32>,
+    total_us: Cell<u32>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestIrqLatency {
+    pub fn new(timer: &'static Timer, apps: &'static AppLoader) -> Self {
+        TestIrqLatency {
+            timer,
+            apps,
+            processes: Default::default(),
+            samples: Cell::new(0),
+            max_us: Cell::new(0),
+            total_us: Cell::new(0),
+            step: Cell::new(Step::Idle),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.timer.start(TIMER_PRESCALER, BitmodeValue::Size32Bits);
+        self.schedule();
+    }
+
+    /// Raises the next interrupt between 1 and 2 ms from now. The offset
+    /// steps through the timeslices of the apps, so the interrupts stop them
+    /// at different points.
+    fn schedule(&self) {
+        let offset = 1000 + self.samples.get() * 397 % 1000;
+        let now = self.timer.capture(1);
+        self.timer.set_compare(0, now.wrapping_add(offset), false);
+        self.timer.enable_interrupt(0);
+    }
+
+    /// Records the latency of the interrupt that just reached its bottom
+    /// half, and moves on to the next sample or case.
+    fn sample(&self, latency_us: u32) -> Result<(), &'static str> {
+        self.samples.set(self.samples.get() + 1);
+        self.max_us.set(self.max_us.get().max(latency_us));
+        self.total_us.set(self.total_us.get() + latency_us);
+        if self.samples.get() < SAMPLES {
+            self.schedule();
+            return Ok(());
+        }
+
+        debug!(
+            "IrqLatency: {:?} max {} us, mean {} us",
+            self.step.get(),
+            self.max_us.get(),
+            self.total_us.get() / SAMPLES
+        );
+        if self.max_us.get() > MAX_LATENCY_US {
+            return Err("interrupt waited too long for its bottom half");
+        }
+        match self.step.get() {
+            Step::Idle => {
+                self.step.set(Step::Loaded);
+                self.apps.load_all(&APPS, &self.processes)?;
+                self.samples.set(0);
+                self.max_us.set(0);
+                self.total_us.set(0);
+                self.schedule();
+            }
+            Step::Loaded => {
+                self.apps.remove_all(&self.processes)?;
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("IrqLatency: {:?} failed: {}", self.step.get(), reason);
+        let _ = self.apps.remove_all(&self.processes);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.timer.stop();
+        report(&self.client, "IrqLatency", result);
+    }
+}
+
+impl CompareClient for TestIrqLatency {
+    fn compare(&self, bitmask: u8) {
+        let handled = self.timer.capture(1);
+        if self.finished.get() || bitmask & 1 == 0 {
+            return;
+        }
+        let latency_us = handled.wrapping_sub(self.timer.captured(0));
+        if let Err(reason) = self.sample(latency_us) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestIrqLatency {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,149 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that the kernel survives a fault a test takes on purpose, with the
+//! kernel fault hook of the ARMv7-M hard fault handler.
+//!
+//! The test takes the highest MPU region over, which wins over the regions of
+//! the processes, and denies privileged access to a word of RAM with it. It
+//! installs the hook and reads the word, which faults. The MemManage fault
+//! escalates to a hard fault, as the kernel leaves the MemManage exception
+//! disabled. The hook handles only a fault whose MMFAR holds the address of
+//! the word: it records CFSR and moves the stacked `pc` past the load with
+//! `skip_faulting_instruction()`. The test then removes the hook, gives the
+//! region back as it found it, and checks that:
+//!
+//! 1. the load faulted once at the word, and the kernel resumed after it,
+//! 2. CFSR reported a data access violation,
+//! 3. the handler cleared the MemManage status after the hook handled the
+//!    fault.
+//!
+//! The expected output ends with
+//! KernelFault: all cases passed
+
+use core::sync::atomic::{compiler_fence, AtomicU32, Ordering};
+
+use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+
+/// Configurable Fault Status Register, whose low byte is the MemManage
+/// status.
+const CFSR: *const u32 = 0xE000_ED28 as *const u32;
+/// MemManage Fault Address Register.
+const MMFAR: *const u32 = 0xE000_ED34 as *const u32;
+
+/// CFSR bits of a data access violation, of an address in MMFAR, and of the
+/// whole MemManage status.
+const DACCVIOL: u32 = 1 << 1;
+const MMARVALID: u32 = 1 << 7;
+const MMFSR: u32 = 0xff;
+
+const MPU_CTRL: *mut u32 = 0xE000_ED94 as *mut u32;
+const MPU_RNR: *mut u32 = 0xE000_ED98 as *mut u32;
+const MPU_RBAR: *mut u32 = 0xE000_ED9C as *mut u32;
+const MPU_RASR: *mut u32 = 0xE000_EDA0 as *mut u32;
+
+/// MPU_CTRL with the MPU on and the default memory map for privileged code.
+const MPU_ON: u32 = 0b101;
+
+/// The region the test takes over, the highest of the eight.
+const REGION: u32 = 7;
+
+/// A 32-byte region, the smallest the MPU has, with no access and execute
+/// never.
+const RASR_NO_ACCESS: u32 = 1 << 28 | 4 << 1 | 1;
+
+/// The word the test denies access to, alone in its region.
+#[repr(align(32))]
+struct Guarded([u32; 8]);
+
+static mut GUARDED: Guarded = Guarded([0; 8]);
+
+/// The faults the hook handled, and the CFSR of the last.
+static FAULTS: AtomicU32 = AtomicU32::new(0);
+static FAULT_CFSR: AtomicU32 = AtomicU32::new(0);
+
+fn guarded_address() -> u32 {
+    // SAFETY: only the address is taken, the word is never referenced.
+    unsafe { core::ptr::addr_of!(GUARDED.0) as u32 }
+}
+
+pub unsafe fn run_kernel_fault(client: &'static dyn CapsuleTestClient) {
+    if let Err(reason) = check_kernel_fault() {
+        debug!("KernelFault: Resume failed: {}", reason);
+        client.done(Err(CapsuleTestError::IncorrectResult));
+        return;
+    }
+    debug!("KernelFault: all cases passed");
+    client.done(Ok(()));
+}
+
+/// Handles the faults at the guarded word, and leaves any other fault to the
+/// handler.
+fn hook(frame: &mut [u32; 8]) -> bool {
+    // SAFETY: the status registers can be read at any time.
+    let (cfsr, mmfar) = unsafe { (CFSR.read_volatile(), MMFAR.read_volatile()) };
+    if cfsr & MMARVALID == 0 || mmfar != guarded_address() {
+        return false;
+    }
+    FAULTS.store(FAULTS.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    FAULT_CFSR.store(cfsr, Ordering::Relaxed);
+    // SAFETY: the fault is precise, so the stacked `pc` points at the load,
+    // in flash.
+    unsafe { cortexm4::skip_faulting_instruction(frame) };
+    true
+}
+
+fn ensure(condition: bool, reason: &'static str) -> Result<(), &'static str> {
+    if condition {
+        Ok(())
+    } else {
+        Err(reason)
+    }
+}
+
+/// Waits for the writes to the MPU to take effect.
+unsafe fn sync_mpu() {
+    core::arch::asm!("dsb", "isb", options(nostack, preserves_flags));
+}
+
+unsafe fn check_kernel_fault() -> Result<(), &'static str> {
+    let address = guarded_address();
+
+    let ctrl = MPU_CTRL.read_volatile();
+    let rnr = MPU_RNR.read_volatile();
+    MPU_RNR.write_volatile(REGION);
+    let rbar = MPU_RBAR.read_volatile();
+    let rasr = MPU_RASR.read_volatile();
+    MPU_RBAR.write_volatile(address);
+    MPU_RASR.write_volatile(RASR_NO_ACCESS);
+    MPU_CTRL.write_volatile(MPU_ON);
+    sync_mpu();
+
+    cortexm4::set_kernel_fault_hook(Some(hook));
+    compiler_fence(Ordering::SeqCst);
+    let _ = core::ptr::addr_of!(GUARDED.0[0]).read_volatile();
+    compiler_fence(Ordering::SeqCst);
+    cortexm4::set_kernel_fault_hook(None);
+
+    MPU_RASR.write_volatile(0);
+    MPU_RBAR.write_volatile(rbar);
+    MPU_RASR.write_volatile(rasr);
+    MPU_RNR.write_volatile(rnr);
+    MPU_CTRL.write_volatile(ctrl);
+    sync_mpu();
+
+    ensure(
+        FAULTS.load(Ordering::Relaxed) == 1,
+        "the load did not fault once at the word",
+    )?;
+    ensure(
+        FAULT_CFSR.load(Ordering::Relaxed) & DACCVIOL != 0,
+        "CFSR reported no data access violation",
+    )?;
+    ensure(
+        CFSR.read_volatile() & MMFSR == 0,
+        "the MemManage status stayed set",
+    )
+}
@@ -0,0 +1,395 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Tests the USB HID keyboard capsule on the USBD peripheral.
+//!
+//! The cases are:
+//!
+//! 1. `Descriptor`: the report descriptor the keyboard exposes describes a
+//!    Generic Desktop keyboard application collection with 8 byte input
+//!    reports, the size of the interrupt endpoint, and 1 byte LED output
+//!    reports. Its collections are balanced and its key array covers F13.
+//! 2. `Endpoint`: once the keyboard is enabled, the USBD state machine of its
+//!    interrupt endpoint is idle in both directions.
+//!
+//! With `BOARD_TEST_CONFIG.usb_host` set, the keyboard attaches to the host
+//! on the nRF USB port, and
+//!
+//! 3. `Press`: a report that presses F13 is sent once the host enumerated the
+//!    keyboard and polls its endpoint, and
+//! 4. `Release`: a report that releases all keys is sent as well, with the
+//!    endpoint back to idle after each report.
+//!
+//! Without a host, the keyboard stays detached, and
+//!
+//! 3. `Queued`: a report is taken into the endpoint, which waits for DMA or
+//!    for the host, is not reported as sent within `QUEUE_WAIT_MS`, and is
+//!    returned by `send_cancel()`. The endpoint stays busy until the next bus
+//!    reset, so no later test may use the USBD.
+//!
+//! The expected output ends with
+//! KeyboardHid: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::usb::keyboard_hid::{KeyboardHid, REPORT_DESCRIPTOR};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::hil::usb::{Client, TransferType, UsbController};
+use kernel::hil::usb_hid::{self, UsbHid};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::power::Power;
+use nrf52840::rtc::Rtc;
+use nrf52840::usbd::{BulkInState, BulkOutState, EndpointState, UsbState, Usbd};
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::report;
+
+const STRINGS: &[&str; 3] = &["Tock", "Keyboard test", "0"];
+
+/// Interrupt endpoint of the keyboard.
+const ENDPOINT: usize = 1;
+
+/// Size of a boot keyboard input report: modifiers, a reserved byte and six
+/// key codes.
+const INPUT_REPORT_BITS: u32 = 64;
+
+/// Size of the LED output report.
+const OUTPUT_REPORT_BITS: u32 = 8;
+
+/// Usage ID of F13, which no common host binds to an action.
+const KEY_F13: u8 = 0x68;
+
+/// Time the host has to enumerate the keyboard and take the first report.
+const ENUMERATION_TIMEOUT_MS: u32 = 10_000;
+
+/// Time the host has to take a later report.
+const REPORT_TIMEOUT_MS: u32 = 1000;
+
+/// Time a queued report must stay unsent without a host.
+const QUEUE_WAIT_MS: u32 = 100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Descriptor,
+    Endpoint,
+    Queued,
+    Press,
+    Release,
+}
+
+/// Sizes of the reports a HID report descriptor describes.
+struct ReportLayout {
+    input_bits: u32,
+    output_bits: u32,
+    /// Logical maximum of the array input, the highest key code.
+    key_max: Option<u32>,
+}
+
+/// Walks the short items of a HID report descriptor (HID 1.11 section 6.2.2)
+/// and adds up the reports of a descriptor without report IDs.
+fn parse_report_descriptor(descriptor: &[u8]) -> Result<ReportLayout, &'static str> {
+    // Usage Page (Generic Desktop), Usage (Keyboard), Collection (Application)
+    if !descriptor.starts_with(&[0x05, 0x01, 0x09, 0x06, 0xa1, 0x01]) {
+        return Err("not a keyboard application collection");
+    }
+    let mut layout = ReportLayout {
+        input_bits: 0,
+        output_bits: 0,
+        key_max: None,
+    };
+    let mut report_size = 0;
+    let mut report_count = 0;
+    let mut logical_max = 0;
+    let mut depth = 0u32;
+    let mut pos = 0;
+    while pos < descriptor.len() {
+        let prefix = descriptor[pos];
+        if prefix == 0xfe {
+            return Err("long item");
+        }
+        let size = match prefix & 0b11 {
+            3 => 4,
+            size => usize::from(size),
+        };
+        let data = descriptor
+            .get(pos + 1..pos + 1 + size)
+            .ok_or("item past the end")?;
+        let value = data
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | u32::from(*byte));
+        match prefix & 0b1111_1100 {
+            // Input
+            0x80 => {
+                layout.input_bits += report_size * report_count;
+                // An array, rather than variable, input.
+                if value & 0b10 == 0 {
+                    layout.key_max = Some(logical_max);
+                }
+            }
+            // Output
+            0x90 => layout.output_bits += report_size * report_count,
+            // Collection
+            0xa0 => depth += 1,
+            // End Collection
+            0xc0 => depth = depth.checked_sub(1).ok_or("unbalanced collection")?,
+            // Logical Maximum
+            0x24 => logical_max = value,
+            // Report Size
+            0x74 => report_size = value,
+            // Report ID
+            0x84 => return Err("report IDs in a boot keyboard"),
+            // Report Count
+            0x94 => report_count = value,
+            _ => (),
+        }
+        pos += 1 + size;
+    }
+    if depth != 0 {
+        return Err("unclosed collection");
+    }
+    Ok(layout)
+}
+
+pub unsafe fn run_keyboard_hid(
+    usbd: &'static Usbd<'static>,
+    power: &'static Power<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let keyboard = static_init!(
+        KeyboardHid<'static, Usbd<'static>>,
+        // Nordic Semiconductor VID.
+        KeyboardHid::new(usbd, 0x1915, 0x503a, STRINGS)
+    );
+    usbd.set_client(keyboard);
+
+    let test = static_init!(
+        TestKeyboardHid,
+        TestKeyboardHid::new(
+            usbd,
+            keyboard,
+            alarm,
+            static_init!([u8; 64], [0; 64]),
+            BOARD_TEST_CONFIG.usb_host,
+        )
+    );
+    keyboard.set_client(test);
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run(power);
+}
+
+pub struct TestKeyboardHid {
+    usbd: &'static Usbd<'static>,
+    keyboard: &'static KeyboardHid<'static, Usbd<'static>>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    report: TakeCell<'static, [u8; 64]>,
+    host: bool,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestKeyboardHid {
+    pub fn new(
+        usbd: &'static Usbd<'static>,
+        keyboard: &'static KeyboardHid<'static, Usbd<'static>>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        report: &'static mut [u8; 64],
+        host: bool,
+    ) -> Self {
+        TestKeyboardHid {
+            usbd,
+            keyboard,
+            alarm,
+            report: TakeCell::new(report),
+            host,
+            step: Cell::new(Step::Descriptor),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'static self, power: &Power) {
+        match parse_report_descriptor(REPORT_DESCRIPTOR) {
+            Ok(layout) => {
+                if layout.input_bits != INPUT_REPORT_BITS {
+                    debug!("KeyboardHid: {} bit input report", layout.input_bits);
+                    self.fail("input report is not 8 bytes");
+                    return;
+                }
+                if layout.output_bits != OUTPUT_REPORT_BITS {
+                    self.fail("LED report is not 1 byte");
+                    return;
+                }
+                if !layout
+                    .key_max
+                    .is_some_and(|key_max| key_max >= u32::from(KEY_F13))
+                {
+                    self.fail("key array does not cover F13");
+                    return;
+                }
+            }
+            Err(reason) => {
+                self.fail(reason);
+                return;
+            }
+        }
+
+        self.step.set(Step::Endpoint);
+        if self.host && !power.is_vbus_present() {
+            self.fail("no VBUS on the nRF USB port");
+            return;
+        }
+        self.keyboard.enable();
+        if !self.endpoint_idle() {
+            debug!(
+                "KeyboardHid: endpoint state {:?}",
+                self.usbd.endpoint_state(ENDPOINT)
+            );
+            self.fail("endpoint not idle after enable");
+            return;
+        }
+
+        if self.host {
+            self.keyboard.attach();
+            self.step.set(Step::Press);
+            self.send([0, 0, KEY_F13, 0, 0, 0, 0, 0], ENUMERATION_TIMEOUT_MS);
+        } else {
+            if matches!(
+                self.usbd.get_state(),
+                UsbState::Attached | UsbState::Configured
+            ) {
+                self.fail("USBD attached without a host");
+                return;
+            }
+            self.step.set(Step::Queued);
+            self.send([0, 0, KEY_F13, 0, 0, 0, 0, 0], QUEUE_WAIT_MS);
+            if !matches!(
+                self.usbd.endpoint_state(ENDPOINT),
+                EndpointState::Bulk(
+                    TransferType::Interrupt,
+                    Some(BulkInState::InDma | BulkInState::InData),
+                    _
+                )
+            ) {
+                self.fail("report not taken into the endpoint");
+            }
+        }
+    }
+
+    /// Whether the IN and OUT state machines of the keyboard endpoint are
+    /// idle.
+    fn endpoint_idle(&self) -> bool {
+        matches!(
+            self.usbd.endpoint_state(ENDPOINT),
+            EndpointState::Bulk(
+                TransferType::Interrupt,
+                Some(BulkInState::Init),
+                Some(BulkOutState::Init)
+            )
+        )
+    }
+
+    fn send(&self, keys: [u8; 8], timeout_ms: u32) {
+        let Some(report) = self.report.take() else {
+            self.fail("report buffer missing");
+            return;
+        };
+        report[..8].copy_from_slice(&keys);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(timeout_ms));
+        if let Err((e, report)) = self.keyboard.send_buffer(report) {
+            self.report.replace(report);
+            debug!("KeyboardHid: send_buffer failed: {:?}", e);
+            self.fail("report refused");
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("KeyboardHid: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        report(&self.client, "KeyboardHid", result);
+    }
+}
+
+impl usb_hid::Client<'static, [u8; 64]> for TestKeyboardHid {
+    fn packet_received(
+        &'static self,
+        _result: Result<(), ErrorCode>,
+        _buffer: &'static mut [u8; 64],
+        _endpoint: usize,
+    ) {
+    }
+
+    fn packet_transmitted(
+        &'static self,
+        result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; 64],
+        _endpoint: usize,
+    ) {
+        self.report.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        if result.is_err() {
+            self.fail("report not sent");
+            return;
+        }
+        match self.step.get() {
+            Step::Press if self.endpoint_idle() => {
+                self.step.set(Step::Release);
+                self.send([0; 8], REPORT_TIMEOUT_MS);
+            }
+            Step::Release if self.endpoint_idle() => {
+                self.usbd.detach();
+                self.finish(Ok(()));
+            }
+            Step::Press | Step::Release => self.fail("endpoint not idle after report"),
+            _ => self.fail("report sent without a host"),
+        }
+    }
+}
+
+impl AlarmClient for TestKeyboardHid {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        match self.step.get() {
+            Step::Queued => match self.keyboard.send_cancel() {
+                Ok(report) => {
+                    self.report.replace(report);
+                    self.finish(Ok(()));
+                }
+                Err(_) => self.fail("queued report not returned"),
+            },
+            Step::Press => self.fail("no host took the report"),
+            _ => self.fail("timed out"),
+        }
+    }
+}
+
+impl CapsuleTest for TestKeyboardHid {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
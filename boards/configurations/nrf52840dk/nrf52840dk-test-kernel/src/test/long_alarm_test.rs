@@ -0,0 +1,207 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks `LongAlarm`, which extends a virtual alarm on the 24-bit RTC to
+//! 64-bit ticks. Waiting for a long alarm would take hours, so the test checks
+//! that one is scheduled correctly, and the capsule's unit tests simulate it
+//! firing. The cases are:
+//!
+//! 1. `Counter`: the low 24 bits of the 64-bit counter are the RTC counter,
+//!    and the counter does not go backwards.
+//! 2. `Short`: an alarm shorter than a counter period fires after its
+//!    interval, and not much later.
+//! 3. `Hours`: an alarm three hours away is armed with its full interval,
+//!    while the virtual alarm under it is armed at most half a counter period
+//!    ahead. Disarming it leaves the virtual alarm armed to observe the
+//!    counter.
+//!
+//! The expected output ends with
+//! LongAlarm: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::long_alarm::LongAlarm;
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks, Ticks24, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::new_alarm;
+use crate::test::registry::report;
+
+type RtcAlarm = VirtualMuxAlarm<'static, Rtc<'static>>;
+
+/// Interval of the short alarm.
+const SHORT_MS: u32 = 100;
+
+/// Delay tolerated after the short alarm's interval.
+const SHORT_LATE_MS: u32 = 20;
+
+/// Interval of the long alarm.
+const LONG_S: u32 = 3 * 3600;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Counter,
+    Short,
+    Hours,
+}
+
+pub unsafe fn run_long_alarm(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let virtual_alarm = new_alarm(mux_alarm, static_buf!(RtcAlarm));
+    let long_alarm = static_init!(LongAlarm<'static, RtcAlarm>, LongAlarm::new(virtual_alarm));
+    virtual_alarm.set_alarm_client(long_alarm);
+    long_alarm.setup();
+
+    let test = static_init!(TestLongAlarm, TestLongAlarm::new(virtual_alarm, long_alarm));
+    long_alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestLongAlarm {
+    virtual_alarm: &'static RtcAlarm,
+    long_alarm: &'static LongAlarm<'static, RtcAlarm>,
+    /// 64-bit time the short alarm was set at.
+    start: Cell<u64>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestLongAlarm {
+    pub fn new(
+        virtual_alarm: &'static RtcAlarm,
+        long_alarm: &'static LongAlarm<'static, RtcAlarm>,
+    ) -> Self {
+        TestLongAlarm {
+            virtual_alarm,
+            long_alarm,
+            start: Cell::new(0),
+            step: Cell::new(Step::Counter),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.check_counter() {
+            self.fail(reason);
+            return;
+        }
+
+        self.step.set(Step::Short);
+        let now = self.long_alarm.now();
+        self.start.set(now.into_u64());
+        self.long_alarm
+            .set_alarm(now, self.long_alarm.ticks_from_ms(SHORT_MS));
+    }
+
+    fn check_counter(&self) -> Result<(), &'static str> {
+        let first = self.long_alarm.now();
+        let rtc = self.virtual_alarm.now();
+        let second = self.long_alarm.now();
+        if second < first {
+            return Err("64-bit counter went backwards");
+        }
+        // The RTC may tick between the reads.
+        let low = Ticks24::from(first.into_u32());
+        if rtc.wrapping_sub(low).into_u32() > second.wrapping_sub(first).into_u32() {
+            return Err("low bits are not the RTC counter");
+        }
+        Ok(())
+    }
+
+    fn check_short(&self) -> Result<(), &'static str> {
+        let elapsed = self.long_alarm.now().into_u64() - self.start.get();
+        let elapsed_ms = self.long_alarm.ticks_to_ms(elapsed.into());
+        debug!("LongAlarm: short alarm fired after {} ms", elapsed_ms);
+        if elapsed_ms < SHORT_MS {
+            return Err("alarm fired early");
+        }
+        if elapsed_ms > SHORT_MS + SHORT_LATE_MS {
+            return Err("alarm fired late");
+        }
+        Ok(())
+    }
+
+    fn check_hours(&self) -> Result<(), &'static str> {
+        let now = self.long_alarm.now();
+        let dt = self.long_alarm.ticks_from_seconds(LONG_S);
+        self.long_alarm.set_alarm(now, dt);
+        if !self.long_alarm.is_armed() {
+            return Err("long alarm not armed");
+        }
+        let remaining = self.long_alarm.get_alarm().wrapping_sub(now);
+        if remaining != dt || remaining.into_u64() <= Ticks24::MASK as u64 {
+            return Err("long alarm interval not kept");
+        }
+        debug!(
+            "LongAlarm: {} s alarm armed for {} ticks",
+            LONG_S,
+            remaining.into_u64()
+        );
+
+        let half_period = Ticks24::half_max_value().into_u32();
+        let underlying = self
+            .virtual_alarm
+            .get_alarm()
+            .wrapping_sub(self.virtual_alarm.now());
+        if !self.virtual_alarm.is_armed() || underlying.into_u32() > half_period {
+            return Err("virtual alarm not armed within half a counter period");
+        }
+
+        self.long_alarm
+            .disarm()
+            .map_err(|_| "long alarm not disarmed")?;
+        if self.long_alarm.is_armed() || !self.virtual_alarm.is_armed() {
+            return Err("counter not observed after disarming");
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("LongAlarm: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.long_alarm.disarm();
+        report(&self.client, "LongAlarm", result);
+    }
+}
+
+impl AlarmClient for TestLongAlarm {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let result = match self.step.get() {
+            Step::Short => self.check_short().and_then(|()| {
+                self.step.set(Step::Hours);
+                self.check_hours()
+            }),
+            _ => Err("unexpected alarm"),
+        };
+        match result {
+            Ok(()) => self.finish(Ok(())),
+            Err(reason) => self.fail(reason),
+        }
+    }
+}
+
+impl CapsuleTest for TestLongAlarm {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
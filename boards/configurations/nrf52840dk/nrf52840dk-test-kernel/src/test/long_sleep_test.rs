@@ -0,0 +1,223 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Sleeps for ten minutes on a `LongAlarm`, as a battery-powered board waits
+//! between measurements, and checks that it woke when it should have and that
+//! the `DateTimeSoftware` calendar on the RTC kept the time. Ten minutes is
+//! longer than the 24-bit RTC counter takes to wrap, 512 s, so the alarm is
+//! only right if `LongAlarm` follows the wrap. The test is slow, and only
+//! runs in an image built with the `slow` feature. The cases are:
+//!
+//! 1. `Set`: the calendar is set to five minutes to midnight on New Year's
+//!    Eve.
+//! 2. `Sleep`: with nothing else to do, the chip sleeps for most of the ten
+//!    minutes, and wakes within a few RTC ticks of the alarm.
+//! 3. `Calendar`: the calendar reads five past midnight on New Year's Day.
+//!
+//! The expected output ends with
+//! LongSleep: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::long_alarm::LongAlarm;
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::date_time_software::DateTimeSoftware;
+use kernel::debug;
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues, DayOfWeek, Month};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::new_alarm;
+use crate::test::registry::report;
+use crate::test::sleep_test::SleepMonitor;
+
+type RtcAlarm = VirtualMuxAlarm<'static, Rtc<'static>>;
+type Clock = DateTimeSoftware<'static, LongAlarm<'static, RtcAlarm>>;
+
+/// Time the board sleeps, in milliseconds. Half a second past the ten
+/// minutes, so the calendar has counted them in full when the board wakes.
+const SLEEP_MS: u32 = 10 * 60 * 1000 + 500;
+
+/// Lowest share of the sleep, in percent, the chip must be asleep for. The
+/// chip wakes to rearm the alarm under `LongAlarm`, and for nothing else.
+const ASLEEP_MIN_PERCENT: u64 = 99;
+
+/// Highest number of RTC ticks, about 30 us each, the chip may wake after the
+/// alarm.
+const WAKE_MAX_TICKS: u64 = 3;
+
+/// Five minutes to midnight on New Year's Eve, a Tuesday.
+const START: DateTimeValues = DateTimeValues {
+    year: 2024,
+    month: Month::December,
+    day: 31,
+    day_of_week: DayOfWeek::Tuesday,
+    hour: 23,
+    minute: 55,
+    seconds: 0,
+};
+
+/// `START` ten minutes later.
+const WOKEN: DateTimeValues = DateTimeValues {
+    year: 2025,
+    month: Month::January,
+    day: 1,
+    day_of_week: DayOfWeek::Wednesday,
+    hour: 0,
+    minute: 5,
+    seconds: 0,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Set,
+    Sleep,
+    Calendar,
+}
+
+pub unsafe fn run_long_sleep(
+    monitor: &'static SleepMonitor,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let virtual_alarm = new_alarm(mux_alarm, static_buf!(RtcAlarm));
+    let long_alarm = static_init!(LongAlarm<'static, RtcAlarm>, LongAlarm::new(virtual_alarm));
+    virtual_alarm.set_alarm_client(long_alarm);
+    long_alarm.setup();
+
+    let clock = static_init!(Clock, DateTimeSoftware::new(long_alarm));
+    kernel::deferred_call::DeferredCallClient::register(clock);
+
+    let test = static_init!(
+        TestLongSleep,
+        TestLongSleep::new(clock, long_alarm, monitor)
+    );
+    clock.set_client(test);
+    long_alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestLongSleep {
+    clock: &'static Clock,
+    alarm: &'static LongAlarm<'static, RtcAlarm>,
+    monitor: &'static SleepMonitor,
+    /// Time the chip slept before the sleep started, in RTC ticks.
+    asleep_before: Cell<u64>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestLongSleep {
+    pub fn new(
+        clock: &'static Clock,
+        alarm: &'static LongAlarm<'static, RtcAlarm>,
+        monitor: &'static SleepMonitor,
+    ) -> Self {
+        TestLongSleep {
+            clock,
+            alarm,
+            monitor,
+            asleep_before: Cell::new(0),
+            step: Cell::new(Step::Set),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if self.clock.set_date_time(START).is_err() {
+            self.fail("setting the date and time failed");
+        }
+    }
+
+    fn sleep(&self) {
+        debug!("LongSleep: sleeping for {} ms", SLEEP_MS);
+        self.step.set(Step::Sleep);
+        self.asleep_before.set(self.monitor.asleep_ticks());
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(SLEEP_MS));
+    }
+
+    fn check_wake(&self) -> Result<(), &'static str> {
+        let late = self
+            .alarm
+            .now()
+            .wrapping_sub(self.alarm.get_alarm())
+            .into_u64();
+        let sleep_ticks = self.alarm.ticks_from_ms(SLEEP_MS).into_u64();
+        let asleep = (self.monitor.asleep_ticks() - self.asleep_before.get()) * 100 / sleep_ticks;
+        debug!(
+            "LongSleep: woke {} ticks after the alarm, asleep {}% of the time",
+            late, asleep
+        );
+        if late > WAKE_MAX_TICKS {
+            return Err("chip did not wake on time");
+        }
+        if asleep < ASLEEP_MIN_PERCENT {
+            return Err("chip slept for too little of the time");
+        }
+        self.step.set(Step::Calendar);
+        self.clock
+            .get_date_time()
+            .map_err(|_| "reading the date and time failed")
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("LongSleep: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "LongSleep", result);
+    }
+}
+
+impl DateTimeClient for TestLongSleep {
+    fn get_date_time_done(&self, date_time: Result<DateTimeValues, ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        if date_time == Ok(WOKEN) {
+            self.finish(Ok(()));
+        } else {
+            self.fail("calendar did not keep the time");
+        }
+    }
+
+    fn set_date_time_done(&self, result: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        match result {
+            Ok(()) => self.sleep(),
+            Err(_) => self.fail("setting the date and time failed"),
+        }
+    }
+}
+
+impl AlarmClient for TestLongSleep {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check_wake() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestLongSleep {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,594 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Tests the 802.15.4 address filtering and ACKs upper layers rely on, with a
+//! second DK listed in `BOARD_TEST_CONFIG.radio_peer` that runs the same image
+//! with the other role.
+//!
+//! The initiator sends data frames that request an ACK, and the responder
+//! listens through `AwakeMac` with its PAN and addresses set. The cases are:
+//!
+//! 1. `Sync`: a frame to the responder, repeated until the responder, which
+//!    may still be running earlier tests, acknowledges it.
+//! 2. `OtherPan`: a frame to the short address of the responder in another
+//!    PAN is neither acknowledged nor accepted.
+//! 3. `OtherAddress`: a frame to another short address is neither
+//!    acknowledged nor accepted.
+//! 4. `Broadcast`: a broadcast frame is accepted, but not acknowledged, even
+//!    though it requests an ACK.
+//! 5. `LongAddress`: a frame to the long address of the responder is
+//!    acknowledged and accepted.
+//! 6. `BroadcastPan`: a frame to the broadcast PAN is acknowledged and
+//!    accepted.
+//! 7. `SequenceWrap`: frames with sequence numbers 0xff and 0x00 are
+//!    acknowledged with the same numbers, and accepted.
+//!
+//! Every ACK must carry the sequence number of the frame it acknowledges and
+//! arrive within `ACK_DEADLINE_US` of the end of the frame. The frame the
+//! initiator sends after an ACK must start at least macSifsPeriod after the
+//! end of the ACK, which TIMER2 measures from the radio events. The responder
+//! checks that its MAC layer accepts the frames of cases 1 and 4 to 7, in
+//! order, and ignores the retransmissions of a frame the initiator did not
+//! see the ACK of.
+//!
+//! The expected output on both boards ends with
+//! MacFilter: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ieee802154::mac::{AwakeMac, Mac};
+use kernel::debug;
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks, Ticks24, Time};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::ieee802154_radio::Radio;
+use nrf52840::rtc::Rtc;
+use nrf52840::timer::Timer;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::{PeerRole, BOARD_TEST_CONFIG};
+use crate::test::radio_timing_test::{
+    EventCapture, EVENTS_CRCOK, EVENTS_FRAMESTART, FRAMESTART_US, TURNAROUND_US,
+};
+use crate::test::registry::{report, skip};
+use crate::test::scratch::ScratchArena;
+
+const PAN: u16 = 0x7e57;
+const OTHER_PAN: u16 = 0x7e58;
+const INITIATOR_ADDRESS: u16 = 0x0001;
+const RESPONDER_ADDRESS: u16 = 0x0002;
+const OTHER_ADDRESS: u16 = 0x0003;
+const RESPONDER_LONG_ADDRESS: [u8; 8] = [0x7e, 0x57, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02];
+const BROADCAST: u16 = 0xffff;
+
+/// Time between the end of a frame and the end of its ACK: the
+/// macAckWaitDuration of the 2.4 GHz PHY, 54 symbols, and the 11 bytes of
+/// the ACK on air.
+const ACK_DEADLINE_US: u32 = 864 + 11 * 32;
+
+/// Time the initiator waits for an ACK before it sends the frame again, or
+/// moves on to the next case if the frame must not be acknowledged.
+const ACK_WINDOW_MS: u32 = 100;
+
+/// Times the initiator sends a frame that must be acknowledged.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Times the initiator sends the first frame, for up to 30 seconds.
+const SYNC_ATTEMPTS: u32 = 300;
+
+/// Time the responder waits for all frames.
+const RESPONDER_TIMEOUT_MS: u32 = 60_000;
+
+const PAYLOAD: &[u8] = b"tock";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Sync,
+    OtherPan,
+    OtherAddress,
+    Broadcast,
+    LongAddress,
+    BroadcastPan,
+    SequenceWrap,
+}
+
+#[derive(Clone, Copy)]
+enum Destination {
+    Short(u16),
+    Long([u8; 8]),
+}
+
+/// A frame the initiator sends.
+struct Case {
+    step: Step,
+    pan: u16,
+    destination: Destination,
+    sequence: u8,
+    /// Whether the responder acknowledges the frame.
+    acked: bool,
+    /// Whether the MAC layer of the responder passes the frame on.
+    accepted: bool,
+}
+
+const CASES: [Case; 8] = [
+    Case {
+        step: Step::Sync,
+        pan: PAN,
+        destination: Destination::Short(RESPONDER_ADDRESS),
+        sequence: 0x10,
+        acked: true,
+        accepted: true,
+    },
+    Case {
+        step: Step::OtherPan,
+        pan: OTHER_PAN,
+        destination: Destination::Short(RESPONDER_ADDRESS),
+        sequence: 0x11,
+        acked: false,
+        accepted: false,
+    },
+    Case {
+        step: Step::OtherAddress,
+        pan: PAN,
+        destination: Destination::Short(OTHER_ADDRESS),
+        sequence: 0x12,
+        acked: false,
+        accepted: false,
+    },
+    Case {
+        step: Step::Broadcast,
+        pan: PAN,
+        destination: Destination::Short(BROADCAST),
+        sequence: 0x13,
+        acked: false,
+        accepted: true,
+    },
+    Case {
+        step: Step::LongAddress,
+        pan: PAN,
+        destination: Destination::Long(RESPONDER_LONG_ADDRESS),
+        sequence: 0x14,
+        acked: true,
+        accepted: true,
+    },
+    Case {
+        step: Step::BroadcastPan,
+        pan: BROADCAST,
+        destination: Destination::Short(RESPONDER_ADDRESS),
+        sequence: 0x15,
+        acked: true,
+        accepted: true,
+    },
+    Case {
+        step: Step::SequenceWrap,
+        pan: PAN,
+        destination: Destination::Short(RESPONDER_ADDRESS),
+        sequence: 0xff,
+        acked: true,
+        accepted: true,
+    },
+    Case {
+        step: Step::SequenceWrap,
+        pan: PAN,
+        destination: Destination::Short(RESPONDER_ADDRESS),
+        sequence: 0x00,
+        acked: true,
+        accepted: true,
+    },
+];
+
+/// Writes the frame of `case` to `buf`, after the PSDU offset, as a 2006 data
+/// frame from the short address of the initiator. Returns its length.
+fn write_frame(buf: &mut [u8], case: &Case) -> usize {
+    // Data frame, ACK request, PAN ID compression, 2006 frame version and a
+    // short source address.
+    let mut fcf: u16 = 0b001 | 1 << 5 | 1 << 6 | 0b01 << 12 | 0b10 << 14;
+    let frame = &mut buf[radio::PSDU_OFFSET..];
+    frame[3..5].copy_from_slice(&case.pan.to_le_bytes());
+    let mut len = 5;
+    match case.destination {
+        Destination::Short(address) => {
+            fcf |= 0b10 << 10;
+            frame[len..len + 2].copy_from_slice(&address.to_le_bytes());
+            len += 2;
+        }
+        Destination::Long(address) => {
+            fcf |= 0b11 << 10;
+            for (byte, address_byte) in frame[len..len + 8].iter_mut().zip(address.iter().rev()) {
+                *byte = *address_byte;
+            }
+            len += 8;
+        }
+    }
+    frame[0..2].copy_from_slice(&fcf.to_le_bytes());
+    frame[2] = case.sequence;
+    frame[len..len + 2].copy_from_slice(&INITIATOR_ADDRESS.to_le_bytes());
+    len += 2;
+    frame[len..len + PAYLOAD.len()].copy_from_slice(PAYLOAD);
+    len + PAYLOAD.len()
+}
+
+pub unsafe fn run_mac_filter(
+    radio: &'static Radio<'static>,
+    scratch: &'static ScratchArena,
+    timer: &'static Timer,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(peer) = BOARD_TEST_CONFIG.radio_peer.as_ref() else {
+        skip(client, "MacFilter: no peer board configured");
+        return;
+    };
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+    radio.set_channel(peer.channel);
+    radio.set_receive_buffer(scratch.buffer(radio::MAX_BUF_SIZE));
+
+    match peer.role {
+        PeerRole::Initiator => {
+            let test = static_init!(
+                TestMacFilterInitiator,
+                TestMacFilterInitiator::new(
+                    radio,
+                    timer,
+                    alarm,
+                    scratch.buffer(radio::MAX_BUF_SIZE)
+                )
+            );
+            alarm.set_alarm_client(test);
+            radio.set_transmit_client(test);
+            radio.set_receive_client(test);
+            radio.set_pan(PAN);
+            radio.set_address(INITIATOR_ADDRESS);
+            test.set_client(client);
+            test.run();
+        }
+        PeerRole::Responder => {
+            let mac = static_init!(AwakeMac<'static, Radio<'static>>, AwakeMac::new(radio));
+            radio.set_transmit_client(mac);
+            radio.set_receive_client(mac);
+            let test = static_init!(
+                TestMacFilterResponder,
+                TestMacFilterResponder::new(radio, mac, alarm)
+            );
+            alarm.set_alarm_client(test);
+            mac.set_receive_client(test);
+            mac.set_pan(PAN);
+            mac.set_address(RESPONDER_ADDRESS);
+            mac.set_address_long(RESPONDER_LONG_ADDRESS);
+            test.set_client(client);
+            test.run();
+        }
+    }
+}
+
+pub struct TestMacFilterInitiator {
+    radio: &'static Radio<'static>,
+    capture: EventCapture,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    buffer: TakeCell<'static, [u8]>,
+    case: Cell<usize>,
+    attempts: Cell<u32>,
+    /// Whether the frame was sent and its ACK window is open.
+    waiting: Cell<bool>,
+    /// End of the frame the initiator waits for the ACK of.
+    sent_at: Cell<Ticks24>,
+    /// Whether the frame being sent follows an ACK.
+    after_ack: Cell<bool>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestMacFilterInitiator {
+    pub fn new(
+        radio: &'static Radio<'static>,
+        timer: &'static Timer,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        TestMacFilterInitiator {
+            radio,
+            capture: EventCapture::new(timer),
+            alarm,
+            buffer: TakeCell::new(buffer),
+            case: Cell::new(0),
+            attempts: Cell::new(0),
+            waiting: Cell::new(false),
+            sent_at: Cell::new(Ticks24::from(0)),
+            after_ack: Cell::new(false),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.capture.start(&[EVENTS_CRCOK, EVENTS_FRAMESTART]) {
+            self.fail(reason);
+            return;
+        }
+        if self.radio.start().is_err() {
+            self.fail("radio did not start");
+            return;
+        }
+        self.send();
+    }
+
+    fn current(&self) -> &'static Case {
+        &CASES[self.case.get()]
+    }
+
+    fn send(&self) {
+        let Some(buffer) = self.buffer.take() else {
+            self.fail("transmit buffer missing");
+            return;
+        };
+        let len = write_frame(buffer, self.current());
+        self.attempts.set(self.attempts.get() + 1);
+        if let Err((_, buffer)) = self.radio.transmit(buffer, len) {
+            self.buffer.replace(buffer);
+            self.fail("transmission refused");
+        }
+    }
+
+    /// Sends the frame of the current case again, if it has attempts left.
+    fn retry(&self, reason: &str) {
+        let attempts = match self.current().step {
+            Step::Sync => SYNC_ATTEMPTS,
+            _ => MAX_ATTEMPTS,
+        };
+        if self.attempts.get() < attempts {
+            self.send();
+        } else {
+            self.fail(reason);
+        }
+    }
+
+    fn next_case(&self) {
+        self.attempts.set(0);
+        if self.case.get() + 1 == CASES.len() {
+            self.finish(Ok(()));
+        } else {
+            self.case.set(self.case.get() + 1);
+            self.send();
+        }
+    }
+
+    fn check_ack(&self, sequence: u8) {
+        let case = self.current();
+        if sequence != case.sequence {
+            debug!(
+                "MacFilter: ACK for {:#04x} while waiting for {:#04x}",
+                sequence, case.sequence
+            );
+            return;
+        }
+        let elapsed = self
+            .alarm
+            .ticks_to_us(self.alarm.now().wrapping_sub(self.sent_at.get()));
+        self.waiting.set(false);
+        let _ = self.alarm.disarm();
+        if !case.acked {
+            self.fail("frame acknowledged");
+        } else if elapsed > ACK_DEADLINE_US {
+            debug!("MacFilter: ACK after {} us", elapsed);
+            self.fail("ACK too late");
+        } else {
+            self.after_ack.set(true);
+            self.next_case();
+        }
+    }
+
+    /// Checks that the frame just sent started at least macSifsPeriod after
+    /// the end of the ACK before it.
+    fn check_spacing(&self) -> Result<(), &'static str> {
+        let spacing = self
+            .capture
+            .time(1)
+            .wrapping_sub(FRAMESTART_US)
+            .wrapping_sub(self.capture.time(0));
+        if spacing < TURNAROUND_US {
+            debug!("MacFilter: frame {} us after the ACK", spacing);
+            return Err("frame sent too soon after the ACK");
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("MacFilter: {:?} failed: {}", self.current().step, reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        self.capture.stop();
+        let _ = self.radio.stop();
+        report(&self.client, "MacFilter", result);
+    }
+}
+
+impl radio::TxClient for TestMacFilterInitiator {
+    fn send_done(&self, buf: &'static mut [u8], _acked: bool, result: Result<(), ErrorCode>) {
+        self.buffer.replace(buf);
+        let after_ack = self.after_ack.replace(false);
+        if self.finished.get() {
+            return;
+        }
+        match result {
+            Ok(()) => {
+                if after_ack {
+                    if let Err(reason) = self.check_spacing() {
+                        self.fail(reason);
+                        return;
+                    }
+                }
+                let now = self.alarm.now();
+                self.sent_at.set(now);
+                self.waiting.set(true);
+                self.alarm
+                    .set_alarm(now, self.alarm.ticks_from_ms(ACK_WINDOW_MS));
+            }
+            // The channel stayed busy.
+            Err(_) => self.retry("channel busy"),
+        }
+    }
+}
+
+impl radio::RxClient for TestMacFilterInitiator {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        _lqi: u8,
+        crc_valid: bool,
+        _result: Result<(), ErrorCode>,
+    ) {
+        // An ACK is 3 bytes long, with frame type 0b010.
+        let frame = &buf[radio::PSDU_OFFSET..];
+        let ack = (crc_valid && frame_len == 3 && frame[0] & 0b111 == 0b010).then_some(frame[2]);
+        self.radio.set_receive_buffer(buf);
+        if let Some(sequence) = ack.filter(|_| self.waiting.get() && !self.finished.get()) {
+            self.check_ack(sequence);
+        }
+    }
+}
+
+impl AlarmClient for TestMacFilterInitiator {
+    fn alarm(&self) {
+        if self.finished.get() || !self.waiting.replace(false) {
+            return;
+        }
+        if self.current().acked {
+            self.retry("no ACK");
+        } else {
+            self.next_case();
+        }
+    }
+}
+
+impl CapsuleTest for TestMacFilterInitiator {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
+
+pub struct TestMacFilterResponder {
+    radio: &'static Radio<'static>,
+    mac: &'static AwakeMac<'static, Radio<'static>>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    /// Index of the next case whose frame the MAC layer must accept.
+    case: Cell<usize>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestMacFilterResponder {
+    pub fn new(
+        radio: &'static Radio<'static>,
+        mac: &'static AwakeMac<'static, Radio<'static>>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestMacFilterResponder {
+            radio,
+            mac,
+            alarm,
+            case: Cell::new(0),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if self.mac.start().is_err() {
+            self.fail("radio did not start");
+            return;
+        }
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(RESPONDER_TIMEOUT_MS),
+        );
+    }
+
+    /// Index of the next case whose frame the MAC layer must accept, after
+    /// `case`.
+    fn next_accepted(case: usize) -> usize {
+        (case + 1..CASES.len())
+            .find(|index| CASES[*index].accepted)
+            .unwrap_or(CASES.len())
+    }
+
+    fn check_frame(&self, sequence: u8) {
+        let case = self.case.get();
+        // The initiator sends a frame again if it did not see the ACK.
+        let previous = CASES[..case].iter().rev().find(|case| case.accepted);
+        if previous.is_some_and(|previous| previous.sequence == sequence) {
+            return;
+        }
+        if sequence != CASES[case].sequence {
+            debug!("MacFilter: accepted frame {:#04x}", sequence);
+            self.fail("unexpected frame accepted");
+            return;
+        }
+        let next = Self::next_accepted(case);
+        if next == CASES.len() {
+            self.finish(Ok(()));
+        } else {
+            self.case.set(next);
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        let step = CASES[self.case.get()].step;
+        debug!("MacFilter: {:?} failed: {}", step, reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        // The initiator is done once it saw the last ACK, which the radio
+        // sent before passing the frame on.
+        let _ = self.radio.stop();
+        report(&self.client, "MacFilter", result);
+    }
+}
+
+impl radio::RxClient for TestMacFilterResponder {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        _lqi: u8,
+        crc_valid: bool,
+        _result: Result<(), ErrorCode>,
+    ) {
+        let sequence = buf[radio::PSDU_OFFSET + radio::MHR_FC_SIZE];
+        self.mac.set_receive_buffer(buf);
+        if crc_valid && frame_len > radio::MHR_FC_SIZE && !self.finished.get() {
+            self.check_frame(sequence);
+        }
+    }
+}
+
+impl AlarmClient for TestMacFilterResponder {
+    fn alarm(&self) {
+        if !self.finished.get() {
+            self.fail("frames missing");
+        }
+    }
+}
+
+impl CapsuleTest for TestMacFilterResponder {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
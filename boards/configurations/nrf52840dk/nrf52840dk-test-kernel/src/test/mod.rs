@@ -2,8 +2,79 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2023.
 
+pub(crate) mod adc_conformance_test;
 pub(crate) mod aes_test;
+pub(crate) mod alarm;
+pub(crate) mod bench_test;
+pub(crate) mod ble_scan_test;
+pub(crate) mod chip_revision_test;
+pub(crate) mod component_setup_test;
+pub(crate) mod config;
+pub(crate) mod context_switch_test;
+pub(crate) mod crash_dump;
+pub(crate) mod ctap_test;
+pub(crate) mod current_test;
+pub(crate) mod date_time_test;
+pub(crate) mod deferred_call_test;
+pub(crate) mod digest_conformance_test;
 pub(crate) mod ecdsa_p256_test;
+pub(crate) mod embedded_apps;
+pub(crate) mod energy_scan_test;
+pub(crate) mod flash_conformance_test;
+pub(crate) mod flash_log;
+pub(crate) mod flash_protection_test;
+pub(crate) mod gpio_conformance_test;
+pub(crate) mod gpio_loopback_test;
+pub(crate) mod grant_failure_test;
 pub(crate) mod hmac_sha256_test;
+pub(crate) mod i2c_conformance_test;
+pub(crate) mod i2c_recovery_test;
+pub(crate) mod i2c_stretch_test;
+pub(crate) mod invariant_monitor;
+pub(crate) mod irq_latency_test;
+pub(crate) mod kernel_fault_test;
+pub(crate) mod keyboard_hid_test;
+pub(crate) mod long_alarm_test;
+pub(crate) mod long_sleep_test;
+pub(crate) mod mac_filter_test;
+pub(crate) mod panic_reset;
+pub(crate) mod peripheral_power;
+pub(crate) mod ppi_test;
+pub(crate) mod prescaler_matrix_test;
+pub(crate) mod priority_inversion_test;
+pub(crate) mod process_id_test;
+pub(crate) mod process_slot_test;
+pub(crate) mod process_state_test;
+pub(crate) mod process_stats_test;
+pub(crate) mod queue_fuzz_test;
+pub(crate) mod radio_timing_test;
+pub(crate) mod registry;
+pub(crate) mod report_driver;
+pub(crate) mod reporter;
+pub(crate) mod scheduler;
+pub(crate) mod scheduler_timer_conformance_test;
+pub(crate) mod scratch;
+pub(crate) mod screen_test;
+pub(crate) mod seed;
+pub(crate) mod sensor_plausibility_test;
 pub(crate) mod sha256_test;
 pub(crate) mod siphash24_test;
+pub(crate) mod sleep_test;
+pub(crate) mod spi_conformance_test;
+pub(crate) mod spi_slave_test;
+pub(crate) mod static_allocation_test;
+pub(crate) mod sx127x_replay_test;
+pub(crate) mod sx127x_test;
+pub(crate) mod syscall_matrix_test;
+pub(crate) mod syscall_trace;
+pub(crate) mod test_timer;
+pub(crate) mod timeout;
+pub(crate) mod touch_test;
+pub(crate) mod uart_loopback_test;
+pub(crate) mod udp_smoke_test;
+pub(crate) mod upcall_order_test;
+pub(crate) mod update;
+pub(crate) mod userspace_readable_test;
+pub(crate) mod watchdog_test;
+pub(crate) mod wiretap_test;
+pub(crate) mod yield_test;
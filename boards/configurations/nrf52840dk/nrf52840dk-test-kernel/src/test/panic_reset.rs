@@ -0,0 +1,80 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Lets a run continue past a test that panics, with
+//! `BoardTestConfig::panic_policy` set to `PanicPolicy::Reset`.
+//!
+//! After the panic report, the panic handler announces the reset, and after
+//! the crash dump it keeps the index of the test that ran in GPREGRET2, which
+//! survives a soft reset, and resets the chip. At boot, the launcher reports
+//! that test failed and continues with the next one:
+//!
+//! ```text
+//! Panic: restarting after test 12
+//! --- crash dump start ---
+//! ...
+//! Test suite: 51 tests
+//! Test 12 failed: the kernel panicked
+//! Test 12 failed
+//! Test seed: 0x0123456789abcdef
+//! Test 13: ...
+//! ```
+//!
+//! A panic outside a test still halts the board, as it would happen again
+//! after the reset. The board takes a new seed when it restarts, unless the
+//! host sends it the first one again, as the kernel test runner does.
+
+use core::fmt::Write;
+
+use kernel::debug;
+use nrf52840::power::Power;
+
+use crate::test::config::{PanicPolicy, BOARD_TEST_CONFIG};
+use crate::test::registry::KernelTest;
+use crate::test::reporter::{self, Failure};
+
+/// Bit of GPREGRET2 that marks a panic, above the index of the test. The
+/// watchdog test uses values without it.
+const PANIC_MARK: u8 = 0x80;
+
+/// Returns the index of the test that panicked if the board restarts to
+/// continue after it, and says so. `test` is the test the launcher started
+/// last, if any. After a test with an index too large for GPREGRET2, the
+/// board halts.
+///
+/// **NOTE:** `writer` must be synchronous.
+pub fn announce(writer: &mut dyn Write, test: Option<(usize, &KernelTest)>) -> Option<usize> {
+    if BOARD_TEST_CONFIG.panic_policy != PanicPolicy::Reset {
+        return None;
+    }
+    let (index, _) = test.filter(|(index, _)| *index < PANIC_MARK as usize)?;
+    let _ = write!(writer, "Panic: restarting after test {}\r\n", index);
+    Some(index)
+}
+
+/// Records that test `index` panicked and resets the chip.
+pub unsafe fn restart(index: usize) {
+    Power::new().set_gpregret2(PANIC_MARK | index as u8);
+    cortexm4::scb::reset();
+}
+
+/// Whether a test panicked before the reset, without clearing the mark.
+pub fn restarted(power: &Power) -> bool {
+    power.get_gpregret2() & PANIC_MARK != 0
+}
+
+/// Checks, once at boot, whether a test panicked before the reset, and clears
+/// the mark. Returns the index of the test, which the launcher continues
+/// after.
+pub fn panicked_test(power: &Power) -> Option<usize> {
+    let mark = power.get_gpregret2();
+    if mark & PANIC_MARK == 0 {
+        return None;
+    }
+    power.set_gpregret2(0);
+    let index = usize::from(mark & !PANIC_MARK);
+    debug!("Test {} failed: the kernel panicked", index);
+    reporter::report_result(index, Err(Failure::Panic), None);
+    Some(index)
+}
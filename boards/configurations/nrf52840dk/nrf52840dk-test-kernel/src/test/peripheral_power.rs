@@ -0,0 +1,109 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Finds peripherals that tests leave enabled.
+//!
+//! A peripheral left enabled keeps its clock and power domain on, so the
+//! tests that follow run against hardware in another state than they would
+//! alone, and the sleep current they measure includes it. Before the first
+//! test, `PeripheralMonitor` records which of the peripherals in
+//! `PERIPHERALS` are enabled. After the last test, it prints the ones that
+//! are on and were not before the suite, or `none`:
+//!
+//! ```text
+//! Peripherals on after the suite: saadc pwm0
+//! ```
+//!
+//! Leaks are only reported, the tests that cause them do not fail. After a
+//! reset that continues the run, the baseline is the state at that boot.
+
+use core::cell::Cell;
+use core::fmt;
+
+/// Peripherals the monitor checks, with the address of the register that is
+/// not zero while the peripheral is on: `STATE` for the radio, `ENABLE` for
+/// the others. The serial peripherals share their instance, and with it the
+/// `ENABLE` register, so each is named after all of them.
+const PERIPHERALS: [(&str, usize); 16] = [
+    ("radio", 0x4000_1550),
+    ("uarte0", 0x4000_2500),
+    ("spim0_twim0", 0x4000_3500),
+    ("spim1_twim1", 0x4000_4500),
+    ("saadc", 0x4000_7500),
+    ("pwm0", 0x4001_C500),
+    ("pdm", 0x4001_D500),
+    ("pwm1", 0x4002_1500),
+    ("pwm2", 0x4002_2500),
+    ("spim2", 0x4002_3500),
+    ("i2s", 0x4002_5500),
+    ("usbd", 0x4002_7500),
+    ("uarte1", 0x4002_8500),
+    ("qspi", 0x4002_9500),
+    ("pwm3", 0x4002_D500),
+    ("spim3", 0x4002_F500),
+];
+
+const _: () = assert!(PERIPHERALS.len() <= u32::BITS as usize);
+
+/// The peripherals that are on, one bit per entry of `PERIPHERALS`.
+fn enabled() -> u32 {
+    PERIPHERALS
+        .iter()
+        .enumerate()
+        .fold(0, |on, (bit, (_, address))| {
+            // SAFETY: the addresses are registers of the peripherals, which
+            // can be read at any time.
+            let value = unsafe { core::ptr::read_volatile(*address as *const u32) };
+            if value != 0 {
+                on | 1 << bit
+            } else {
+                on
+            }
+        })
+}
+
+/// Prints the names of the peripherals in a set, or `none`.
+struct Names(u32);
+
+impl fmt::Display for Names {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("none");
+        }
+        let mut separator = "";
+        for (bit, (name, _)) in PERIPHERALS.iter().enumerate() {
+            if self.0 & 1 << bit != 0 {
+                f.write_str(separator)?;
+                f.write_str(name)?;
+                separator = " ";
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct PeripheralMonitor {
+    /// The peripherals on before the first test.
+    baseline: Cell<u32>,
+}
+
+impl PeripheralMonitor {
+    pub const fn new() -> Self {
+        PeripheralMonitor {
+            baseline: Cell::new(0),
+        }
+    }
+
+    /// Records the peripherals on before the first test.
+    pub fn record_baseline(&self) {
+        self.baseline.set(enabled());
+    }
+
+    /// Prints the peripherals on after the last test that were not before
+    /// the first.
+    pub fn check_suite(&self) {
+        let on = enabled() & !self.baseline.get();
+        kernel::debug!("Peripherals on after the suite: {}", Names(on));
+    }
+}
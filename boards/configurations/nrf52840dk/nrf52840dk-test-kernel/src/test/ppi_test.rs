@@ -0,0 +1,252 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks PPI routing on the pins listed in `BOARD_TEST_CONFIG.gpio_loopback`,
+//! which must be connected by a jumper. A PPI channel connects a periodic
+//! TIMER2 compare event to the GPIOTE task that toggles the output pin, so
+//! the pin toggles without the CPU. The test counts the edges on the input
+//! pin over a fixed window. The cases are:
+//!
+//! 1. `Route`: with the channel enabled, the input pin sees the expected
+//!    number of edges.
+//! 2. `GroupDisable`: disabling the channel group containing the channel
+//!    disables the channel, and the pin stops toggling.
+//! 3. `GroupEnable`: enabling the group again enables the channel, and the
+//!    pin toggles as before.
+//!
+//! The expected output ends with
+//! Ppi: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::gpio::{Client, Configure, Interrupt, InterruptEdge};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::{GPIOPin, Port};
+use nrf52840::ppi::{Channel, Ppi};
+use nrf52840::rtc::Rtc;
+use nrf52840::timer::{BitmodeValue, Timer};
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::{report, skip};
+
+/// TIMER2 counts at 16 MHz / 2^4 = 1 MHz.
+const TIMER_PRESCALER: u8 = 4;
+
+/// Time between toggles of the output pin. One timer tick is a microsecond.
+const TOGGLE_PERIOD_US: u32 = 500;
+
+/// Window over which edges are counted.
+const WINDOW_MS: u32 = 20;
+
+/// Edges expected in a window while the channel is enabled. The window and
+/// the timer run from different clocks, so some slack is allowed.
+const EXPECTED_EDGES: usize = (WINDOW_MS * 1000 / TOGGLE_PERIOD_US) as usize;
+const EDGE_SLACK: usize = 4;
+
+/// Edges tolerated in a window while the channel is disabled: one edge may
+/// have been latched just before the channel was disabled.
+const MAX_DISABLED_EDGES: usize = 1;
+
+/// PPI channel and group the test uses.
+const PPI_CHANNEL: usize = 0;
+const PPI_GROUP: usize = 0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Route,
+    GroupDisable,
+    GroupEnable,
+}
+
+pub unsafe fn run_ppi(
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    timer: &'static Timer,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.gpio_loopback.as_ref() else {
+        skip(client, "Ppi: no loopback pins configured");
+        return;
+    };
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestPpi,
+        TestPpi::new(
+            &gpio_port[pins.output],
+            &gpio_port[pins.input],
+            timer,
+            alarm
+        )
+    );
+    alarm.set_alarm_client(test);
+    gpio_port[pins.input].set_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestPpi {
+    output: &'static GPIOPin<'static>,
+    input: &'static GPIOPin<'static>,
+    timer: &'static Timer,
+    ppi: Ppi,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    edges: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestPpi {
+    pub fn new(
+        output: &'static GPIOPin<'static>,
+        input: &'static GPIOPin<'static>,
+        timer: &'static Timer,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestPpi {
+            output,
+            input,
+            timer,
+            ppi: Ppi::new(),
+            alarm,
+            edges: Cell::new(0),
+            step: Cell::new(Step::Route),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let Some(toggle_task) = self.output.enable_toggle_task(false) else {
+            self.fail("no GPIOTE channel for the output pin");
+            return;
+        };
+        if self
+            .ppi
+            .configure_channel(
+                PPI_CHANNEL,
+                self.timer.compare_event_address(0),
+                toggle_task,
+            )
+            .is_err()
+        {
+            self.fail("PPI channel not configured");
+            return;
+        }
+
+        self.input.make_input();
+        self.input.enable_interrupts(InterruptEdge::EitherEdge);
+        self.timer.set_compare(0, TOGGLE_PERIOD_US, true);
+        self.timer.start(TIMER_PRESCALER, BitmodeValue::Size32Bits);
+        self.ppi.enable(Channel::CH0::SET);
+        self.count_edges();
+    }
+
+    /// Starts a new window for counting edges.
+    fn count_edges(&self) {
+        self.edges.set(0);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(WINDOW_MS));
+    }
+
+    fn toggled(&self) -> bool {
+        self.edges.get().abs_diff(EXPECTED_EDGES) <= EDGE_SLACK
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Route => {
+                if !self.toggled() {
+                    return Err("wrong number of edges with the channel enabled");
+                }
+
+                self.step.set(Step::GroupDisable);
+                self.ppi
+                    .set_group(PPI_GROUP, Channel::CH0::SET)
+                    .map_err(|_| "group not set")?;
+                self.ppi
+                    .disable_group(PPI_GROUP)
+                    .map_err(|_| "group not disabled")?;
+                if self.ppi.is_enabled(PPI_CHANNEL) {
+                    return Err("channel enabled after disabling its group");
+                }
+                self.count_edges();
+            }
+            Step::GroupDisable => {
+                if self.edges.get() > MAX_DISABLED_EDGES {
+                    return Err("pin toggled with the group disabled");
+                }
+
+                self.step.set(Step::GroupEnable);
+                self.ppi
+                    .enable_group(PPI_GROUP)
+                    .map_err(|_| "group not enabled")?;
+                if !self.ppi.is_enabled(PPI_CHANNEL) {
+                    return Err("channel disabled after enabling its group");
+                }
+                self.count_edges();
+            }
+            Step::GroupEnable => {
+                if !self.toggled() {
+                    return Err("wrong number of edges after enabling the group");
+                }
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the timer and returns the pins and the PPI channel.
+    fn release(&self) {
+        self.ppi.disable(Channel::CH0::SET);
+        let _ = self.ppi.set_group(PPI_GROUP, Channel::CH0::CLEAR);
+        self.timer.stop();
+        self.output.disable_toggle_task();
+        self.input.disable_interrupts();
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("Ppi: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.release();
+        report(&self.client, "Ppi", result);
+    }
+}
+
+impl Client for TestPpi {
+    fn fired(&self) {
+        self.edges.set(self.edges.get() + 1);
+    }
+}
+
+impl AlarmClient for TestPpi {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestPpi {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,320 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the tick conversions of the time HIL against the counters they
+//! describe. TIMER2 runs in a matrix of prescaler and bit width
+//! configurations, each measured over a window timed by the RTC. The expected
+//! tick counts are computed with `ConvertTicks`, so a wrong `Frequency` or an
+//! off-by-one in the conversions shows up as a mismatch. The cases are:
+//!
+//! 1. `Rtc`: the RTC prescaler gives the frequency its `Time` implementation
+//!    reports.
+//! 2. `Timer`: for each configuration, TIMER2 counts the ticks the RTC window
+//!    converts to, modulo its bit width, and converting the count back to
+//!    milliseconds gives the length of the window.
+//! 3. `TimerAlarm`: TIMER1, driven through the `Alarm` HIL, counts at the
+//!    frequency its `Time` implementation reports.
+//!
+//! The expected output ends with
+//! PrescalerMatrix: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Frequency, Ticks, Ticks32, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+use nrf52840::timer::{BitmodeValue, Timer, TimerAlarm};
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::registry::report;
+
+/// Length of the RTC window each configuration is measured over.
+const WINDOW_MS: u32 = 50;
+
+/// Error allowed between a measured and an expected count: two RTC ticks for
+/// the unknown phases of the RTC at the start and end of the window, and a
+/// little for reading both counters.
+const TOLERANCE_US: u32 = 2 * 1_000_000 / 32_768 + 10;
+
+/// CC register of TIMER2 the test captures the counter into.
+const CAPTURE: usize = 1;
+
+/// TIMER frequency for a prescaler, which divides 16 MHz by 2^`PRESCALER`.
+enum TimerFrequency<const PRESCALER: u8> {}
+
+impl<const PRESCALER: u8> Frequency for TimerFrequency<PRESCALER> {
+    fn frequency() -> u32 {
+        16_000_000 >> PRESCALER
+    }
+}
+
+/// A TIMER running with prescaler `PRESCALER`, for the `ConvertTicks`
+/// conversions at its frequency.
+struct TimerClock<const PRESCALER: u8>;
+
+impl<const PRESCALER: u8> Time for TimerClock<PRESCALER> {
+    type Frequency = TimerFrequency<PRESCALER>;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        0u32.into()
+    }
+}
+
+fn ticks_from_us<const PRESCALER: u8>(us: u32) -> Ticks32 {
+    TimerClock::<PRESCALER>.ticks_from_us(us)
+}
+
+fn ticks_to_ms<const PRESCALER: u8>(ticks: Ticks32) -> u32 {
+    TimerClock::<PRESCALER>.ticks_to_ms(ticks)
+}
+
+/// One entry of the configuration matrix.
+#[derive(Clone, Copy)]
+struct Config {
+    prescaler: u8,
+    bitmode: BitmodeValue,
+    bits: u32,
+    ticks_from_us: fn(u32) -> Ticks32,
+    ticks_to_ms: fn(Ticks32) -> u32,
+}
+
+impl Config {
+    const fn new<const PRESCALER: u8>(bitmode: BitmodeValue, bits: u32) -> Config {
+        Config {
+            prescaler: PRESCALER,
+            bitmode,
+            bits,
+            ticks_from_us: ticks_from_us::<PRESCALER>,
+            ticks_to_ms: ticks_to_ms::<PRESCALER>,
+        }
+    }
+
+    /// Returns whether the counter wraps within a window.
+    fn wraps(&self) -> bool {
+        let max_ms = (self.ticks_to_ms)(Ticks32::from(u32::MAX >> (32 - self.bits)));
+        max_ms <= WINDOW_MS
+    }
+}
+
+/// The matrix covers the fastest and slowest prescalers, the 1 MHz most
+/// drivers use, each bit width, and an 8-bit counter that wraps several times
+/// per window.
+const CONFIGS: [Config; 6] = [
+    Config::new::<0>(BitmodeValue::Size32Bits, 32),
+    Config::new::<4>(BitmodeValue::Size32Bits, 32),
+    Config::new::<9>(BitmodeValue::Size32Bits, 32),
+    Config::new::<4>(BitmodeValue::Size24Bits, 24),
+    Config::new::<9>(BitmodeValue::Size16Bits, 16),
+    Config::new::<9>(BitmodeValue::Size8Bits, 8),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Rtc,
+    Timer,
+    TimerAlarm,
+}
+
+pub unsafe fn run_prescaler_matrix(
+    rtc: &'static Rtc<'static>,
+    timer: &'static Timer,
+    timer_alarm: &'static TimerAlarm<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestPrescalerMatrix,
+        TestPrescalerMatrix::new(rtc, timer, timer_alarm, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestPrescalerMatrix {
+    rtc: &'static Rtc<'static>,
+    timer: &'static Timer,
+    timer_alarm: &'static TimerAlarm<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    /// Index of the configuration being measured.
+    config: Cell<usize>,
+    /// RTC and counter values at the start of the window.
+    start: Cell<(u32, u32)>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestPrescalerMatrix {
+    pub fn new(
+        rtc: &'static Rtc<'static>,
+        timer: &'static Timer,
+        timer_alarm: &'static TimerAlarm<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestPrescalerMatrix {
+            rtc,
+            timer,
+            timer_alarm,
+            alarm,
+            config: Cell::new(0),
+            start: Cell::new((0, 0)),
+            step: Cell::new(Step::Rtc),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let prescaler = self.rtc.prescaler();
+        if 32_768 / (prescaler + 1) != <Rtc as Time>::Frequency::frequency() {
+            self.fail("RTC prescaler does not match the reported frequency");
+            return;
+        }
+
+        self.step.set(Step::Timer);
+        // Another test may have left a compare that clears the counter.
+        self.timer.set_compare(0, 0, false);
+        self.start_timer();
+    }
+
+    fn start_timer(&self) {
+        let config = CONFIGS[self.config.get()];
+        self.timer.start(config.prescaler, config.bitmode);
+        self.start_window(0);
+    }
+
+    fn start_timer_alarm(&self) {
+        // Far enough in the future not to fire during the window.
+        let now = self.timer_alarm.now();
+        self.timer_alarm
+            .set_alarm(now, self.timer_alarm.ticks_from_seconds(10));
+        self.start_window(self.timer_alarm.now().into_u32());
+    }
+
+    /// Records the start of a window in which the counter advances from
+    /// `counter`.
+    fn start_window(&self, counter: u32) {
+        let now = self.alarm.now();
+        self.start.set((now.into_u32(), counter));
+        self.alarm
+            .set_alarm(now, self.alarm.ticks_from_ms(WINDOW_MS));
+    }
+
+    /// Returns the length of the window ending now in microseconds.
+    fn window_us(&self) -> u32 {
+        let elapsed = self.alarm.now().wrapping_sub(self.start.get().0.into());
+        self.alarm.ticks_to_us(elapsed)
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Rtc => return Err("unexpected alarm"),
+            Step::Timer => {
+                let config = CONFIGS[self.config.get()];
+                let counter = self.timer.capture(CAPTURE);
+                let window_us = self.window_us();
+                self.timer.stop();
+
+                let expected = (config.ticks_from_us)(window_us).into_u32();
+                let tolerance = (config.ticks_from_us)(TOLERANCE_US).into_u32() + 1;
+                let mask = u32::MAX >> (32 - config.bits);
+                let error = counter.wrapping_sub(expected) & mask;
+                let error = error.min((mask - error).wrapping_add(1));
+                debug!(
+                    "PrescalerMatrix: prescaler {}, {} bits: {} ticks, expected {} +/- {}",
+                    config.prescaler,
+                    config.bits,
+                    counter,
+                    expected & mask,
+                    tolerance
+                );
+                if error > tolerance {
+                    return Err("TIMER count does not match the RTC window");
+                }
+                if !config.wraps() {
+                    let counter_ms = (config.ticks_to_ms)(counter.into());
+                    if counter_ms.abs_diff(window_us / 1000) > 1 {
+                        return Err("TIMER count converts to the wrong number of ms");
+                    }
+                }
+
+                self.config.set(self.config.get() + 1);
+                if self.config.get() < CONFIGS.len() {
+                    self.start_timer();
+                } else {
+                    self.step.set(Step::TimerAlarm);
+                    self.start_timer_alarm();
+                }
+            }
+            Step::TimerAlarm => {
+                let elapsed = self
+                    .timer_alarm
+                    .now()
+                    .wrapping_sub(self.start.get().1.into());
+                let window_us = self.window_us();
+                let _ = self.timer_alarm.disarm();
+
+                let expected = self.timer_alarm.ticks_from_us(window_us).into_u32();
+                let tolerance = self.timer_alarm.ticks_from_us(TOLERANCE_US).into_u32() + 1;
+                debug!(
+                    "PrescalerMatrix: TIMER1 alarm: {} ticks, expected {} +/- {}",
+                    elapsed.into_u32(),
+                    expected,
+                    tolerance
+                );
+                if elapsed.into_u32().abs_diff(expected) > tolerance {
+                    return Err("TIMER1 does not count at its reported frequency");
+                }
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        match self.step.get() {
+            Step::Timer => debug!(
+                "PrescalerMatrix: {:?} failed for configuration {}: {}",
+                self.step.get(),
+                self.config.get(),
+                reason
+            ),
+            _ => debug!("PrescalerMatrix: {:?} failed: {}", self.step.get(), reason),
+        }
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.timer.stop();
+        report(&self.client, "PrescalerMatrix", result);
+    }
+}
+
+impl AlarmClient for TestPrescalerMatrix {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestPrescalerMatrix {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,368 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the behavior of the priority scheduler in a classic priority
+//! inversion. Three embedded apps run in priority order: `high_pri` waits for
+//! a lock held by `low_prio`, while `med_prio` spins without touching it. The
+//! scheduler implements no priority inheritance, as its documentation states,
+//! so the test expects the inversion to last as long as `med_prio` runs. The
+//! cases are:
+//!
+//! 1. `Hold`: `low_prio`, alone, acquires the lock and holds it while it runs
+//!    its critical section.
+//! 2. `Inversion`: `high_pri` and `med_prio` load into higher priority slots.
+//!    `high_pri` fails to acquire the lock and waits. For much longer than the
+//!    critical section, `low_prio` does not run, so the lock is not released
+//!    and `high_pri` keeps waiting.
+//! 3. `Resolve`: once `med_prio` is removed, `low_prio` finishes its critical
+//!    section and releases the lock, and `high_pri` acquires it.
+//!
+//! The expected output ends with
+//! PriorityInversion: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::{Process, ProcessId};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init, ErrorCode};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppLoader, LOCK_HOLDER_APP, LOCK_WAITER_APP, SPINNER_APP};
+use crate::test::registry::report;
+use crate::test::scheduler::TestScheduler;
+
+/// Time `low_prio` runs alone before the other apps load. Well within its
+/// critical section.
+const HOLD_MS: u32 = 5;
+
+/// Time the inversion is observed for, several times the critical section.
+const INVERSION_MS: u32 = 200;
+
+/// Time `low_prio` gets to finish its critical section once `med_prio` is
+/// removed.
+const RESOLVE_MS: u32 = 200;
+
+/// A lock a single process can hold at a time.
+///
+/// ### Command
+///
+/// - `0`: Driver existence check.
+/// - `1`: Acquire the lock. Fails with `BUSY` if another process holds it.
+/// - `2`: Release the lock, which the calling process must hold.
+///
+/// ### Subscribe
+///
+/// - `0`: Upcall when the lock is released.
+pub(crate) struct SharedLock {
+    grant: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    owner: OptionalCell<ProcessId>,
+    /// Number of acquire attempts that failed because the lock was held.
+    refused: Cell<usize>,
+}
+
+impl SharedLock {
+    pub fn new(grant: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>) -> Self {
+        SharedLock {
+            grant,
+            owner: OptionalCell::empty(),
+            refused: Cell::new(0),
+        }
+    }
+
+    fn owner(&self) -> Option<ProcessId> {
+        self.owner.get()
+    }
+
+    /// Forgets the owner and the refused attempts of an earlier test run.
+    fn reset(&self) {
+        self.owner.clear();
+        self.refused.set(0);
+    }
+}
+
+impl SyscallDriver for SharedLock {
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => match self.owner.get() {
+                Some(owner) if owner == processid => CommandReturn::failure(ErrorCode::ALREADY),
+                Some(_) => {
+                    self.refused.set(self.refused.get() + 1);
+                    CommandReturn::failure(ErrorCode::BUSY)
+                }
+                None => {
+                    self.owner.set(processid);
+                    CommandReturn::success()
+                }
+            },
+            2 => {
+                if self.owner.get() != Some(processid) {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.owner.clear();
+                self.grant.each(|_, _, kernel_data| {
+                    let _ = kernel_data.schedule_upcall(0, (0, 0, 0));
+                });
+                CommandReturn::success()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.grant.enter(processid, |_, _| {})
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Hold,
+    Inversion,
+    Resolve,
+}
+
+pub unsafe fn run_priority_inversion(
+    apps: &'static AppLoader,
+    scheduler: &'static TestScheduler,
+    lock: &'static SharedLock,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestPriorityInversion,
+        TestPriorityInversion::new(apps, scheduler, lock, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestPriorityInversion {
+    apps: &'static AppLoader,
+    scheduler: &'static TestScheduler,
+    lock: &'static SharedLock,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    high: OptionalCell<&'static dyn Process>,
+    medium: OptionalCell<&'static dyn Process>,
+    low: OptionalCell<&'static dyn Process>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestPriorityInversion {
+    pub fn new(
+        apps: &'static AppLoader,
+        scheduler: &'static TestScheduler,
+        lock: &'static SharedLock,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestPriorityInversion {
+            apps,
+            scheduler,
+            lock,
+            alarm,
+            high: OptionalCell::empty(),
+            medium: OptionalCell::empty(),
+            low: OptionalCell::empty(),
+            step: Cell::new(Step::Hold),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.lock.reset();
+        self.scheduler.set_priority(true);
+        if let Err(reason) = self.start() {
+            self.fail(reason);
+        }
+    }
+
+    /// Loads `low_prio` into the last slot, as the other slots will hold the
+    /// higher priority apps.
+    fn start(&self) -> Result<(), &'static str> {
+        self.apps.fill_slots(1)?;
+        let low = self
+            .apps
+            .load(&LOCK_HOLDER_APP)
+            .map_err(|_| "loading low_prio failed")?
+            .ok_or("no process created for low_prio")?;
+        self.low.set(low);
+        self.wait(HOLD_MS);
+        Ok(())
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Returns whether the processes are in the process array in priority
+    /// order.
+    fn in_priority_order(&self, processes: [&dyn Process; 3]) -> bool {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        let mut next = 0;
+        for process in self
+            .apps
+            .kernel()
+            .process_iter_capability(&process_management_cap)
+        {
+            if next < processes.len() && process.processid() == processes[next].processid() {
+                next += 1;
+            }
+        }
+        next == processes.len()
+    }
+
+    /// Terminates and removes `process`.
+    fn remove(&self, process: &dyn Process) -> Result<(), &'static str> {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        process.terminate(None);
+        self.apps
+            .kernel()
+            .remove_process(process.processid(), &process_management_cap)
+            .map_err(|_| "terminated process not removed")
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        let low = self.low.get().ok_or("low_prio not loaded")?;
+        match self.step.get() {
+            Step::Hold => {
+                if self.lock.owner() != Some(low.processid()) {
+                    return Err("low_prio does not hold the lock");
+                }
+                if low.debug_syscall_count() != 1 {
+                    return Err("low_prio left its critical section early");
+                }
+
+                self.step.set(Step::Inversion);
+                self.apps.clear_slots()?;
+                let high = self
+                    .apps
+                    .load(&LOCK_WAITER_APP)
+                    .map_err(|_| "loading high_pri failed")?
+                    .ok_or("no process created for high_pri")?;
+                self.high.set(high);
+                let medium = self
+                    .apps
+                    .load(&SPINNER_APP)
+                    .map_err(|_| "loading med_prio failed")?
+                    .ok_or("no process created for med_prio")?;
+                self.medium.set(medium);
+                if !self.in_priority_order([high, medium, low]) {
+                    return Err("apps not loaded in priority order");
+                }
+                self.wait(INVERSION_MS);
+            }
+            Step::Inversion => {
+                let high = self.high.get().ok_or("high_pri not loaded")?;
+                let medium = self.medium.get().ok_or("med_prio not loaded")?;
+                debug!(
+                    "PriorityInversion: after {} ms, lock held by {:?}, {} refused, system calls {}/{}/{}",
+                    INVERSION_MS,
+                    self.lock.owner(),
+                    self.lock.refused.get(),
+                    high.debug_syscall_count(),
+                    medium.debug_syscall_count(),
+                    low.debug_syscall_count()
+                );
+                if self.lock.refused.get() != 1 {
+                    return Err("high_pri did not wait for the lock exactly once");
+                }
+                if self.lock.owner() != Some(low.processid()) {
+                    return Err("lock released while med_prio runs");
+                }
+                if low.debug_syscall_count() != 1 {
+                    return Err("low_prio ran while med_prio is ready");
+                }
+                if medium.debug_syscall_count() != 0 {
+                    return Err("med_prio made a system call");
+                }
+
+                self.step.set(Step::Resolve);
+                self.remove(medium)?;
+                self.wait(RESOLVE_MS);
+            }
+            Step::Resolve => {
+                let high = self.high.get().ok_or("high_pri not loaded")?;
+                if low.debug_syscall_count() < 2 {
+                    return Err("low_prio did not release the lock");
+                }
+                if self.lock.owner() != Some(high.processid()) {
+                    return Err("high_pri did not acquire the released lock");
+                }
+                if self.lock.refused.get() != 1 {
+                    return Err("high_pri retried without a release");
+                }
+
+                self.remove(high)?;
+                self.remove(low)?;
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!(
+            "PriorityInversion: {:?} failed: {}",
+            self.step.get(),
+            reason
+        );
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        // Leave the slots free and the board scheduling round robin for later
+        // tests, even after a failure.
+        for process in [self.high.take(), self.medium.take(), self.low.take()]
+            .into_iter()
+            .flatten()
+        {
+            if process.get_state() != kernel::process::State::Terminated {
+                let _ = self.remove(process);
+            }
+        }
+        let _ = self.apps.clear_slots();
+        self.lock.reset();
+        self.scheduler.set_priority(false);
+        report(&self.client, "PriorityInversion", result);
+    }
+}
+
+impl AlarmClient for TestPriorityInversion {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestPriorityInversion {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
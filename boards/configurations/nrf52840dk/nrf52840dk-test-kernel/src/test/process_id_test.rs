@@ -0,0 +1,420 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that `ProcessId`s of past process generations are rejected by the
+//! kernel, and that ShortIds follow their documented uniqueness rules.
+//! Placeholder apps take all process slots but one. The cases are:
+//!
+//! 1. `Liveness`: an app is restarted several times. After each restart every
+//!    earlier `ProcessId` is rejected by the kernel and by the grant, and
+//!    reports no ShortId, flash range or storage permissions. Its
+//!    `LocallyUnique` ShortId never compares equal, not even to itself.
+//! 2. `Collision`: a sequential loader that assigns ShortIds from a hash of
+//!    the first six characters of the package name scans `shared_a`,
+//!    `shared_b` and `unique_c`. `shared_a` loads, `shared_b` is skipped
+//!    because its ShortId is taken by a running process, and `unique_c`
+//!    finds no free slot.
+//! 3. `Restart`: `shared_a` keeps its ShortId across restarts, while its
+//!    earlier `ProcessId`s no longer report it.
+//! 4. `Reload`: once `shared_a` is removed, `shared_b` loads with the same
+//!    ShortId, and the `ProcessId`s of `shared_a` do not refer to it.
+//!
+//! The expected output ends with
+//! ProcessId: all cases passed
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_system::process_checker::basic::AppIdAssignerNames;
+use capsules_system::storage_permissions::null::NullStoragePermissions;
+use kernel::component::Component;
+use kernel::debug;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::process::{
+    Process, ProcessBinary, ProcessCheckerMachine, ProcessId, ProcessLoadError,
+    ProcessLoadingAsync, ProcessLoadingAsyncClient, ProcessStandardDebugFull,
+    SequentialProcessLoaderMachine, ShortId,
+};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_init, ErrorCode};
+
+use crate::test::embedded_apps::{
+    AppImages, AppLoader, ChipHw, SHARED_B_APP, SHARED_PREFIX_APPS, SLOT_APP_ONE,
+};
+use crate::test::registry::report;
+
+/// Number of times the apps are restarted.
+const RESTARTS: usize = 3;
+
+/// Number of name characters the ShortId is computed from.
+const PREFIX_LEN: usize = 6;
+
+/// RAM handed to each sequential loader.
+const LOADER_MEMORY_LEN: usize = 16 * 1024;
+
+/// Process binaries each sequential loader can hold.
+const LOADER_BINARIES: usize = 4;
+
+type IdAssigner = AppIdAssignerNames<'static, fn(&'static str) -> u32>;
+type Loader = SequentialProcessLoaderMachine<'static, ChipHw, ProcessStandardDebugFull>;
+
+/// ShortId hash under which all names with the same prefix collide.
+fn name_prefix_hash(name: &'static str) -> u32 {
+    let prefix = &name.as_bytes()[..name.len().min(PREFIX_LEN)];
+    kernel::utilities::helpers::crc32_posix(prefix)
+}
+
+static NAME_PREFIX_HASHER: fn(&'static str) -> u32 = name_prefix_hash;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Liveness,
+    Collision,
+    Restart,
+    Reload,
+}
+
+pub unsafe fn run_process_id(apps: &'static AppLoader, client: &'static dyn CapsuleTestClient) {
+    let assigner = static_init!(IdAssigner, AppIdAssignerNames::new(&NAME_PREFIX_HASHER));
+    let credentials_policy = components::appid::checker_null::AppCheckerNullComponent::new()
+        .finalize(components::app_checker_null_component_static!());
+    let storage_policy =
+        components::storage_permissions::null::StoragePermissionsNullComponent::new().finalize(
+            components::storage_permissions_null_component_static!(
+                ChipHw,
+                ProcessStandardDebugFull
+            ),
+        );
+
+    let collision_loader = create_loader(
+        apps,
+        assigner,
+        storage_policy,
+        &SHARED_PREFIX_APPS,
+        components::appid::checker::ProcessCheckerMachineComponent::new(credentials_policy)
+            .finalize(components::process_checker_machine_component_static!()),
+        components::process_loader_sequential_component_static!(
+            ChipHw,
+            ProcessStandardDebugFull,
+            LOADER_BINARIES
+        ),
+    );
+    let reload_loader = create_loader(
+        apps,
+        assigner,
+        storage_policy,
+        &SHARED_B_APP,
+        components::appid::checker::ProcessCheckerMachineComponent::new(credentials_policy)
+            .finalize(components::process_checker_machine_component_static!()),
+        components::process_loader_sequential_component_static!(
+            ChipHw,
+            ProcessStandardDebugFull,
+            LOADER_BINARIES
+        ),
+    );
+    let (Some(collision_loader), Some(reload_loader)) = (collision_loader, reload_loader) else {
+        debug!("ProcessId: {:?} failed: no app memory left", Step::Liveness);
+        client.done(Err(CapsuleTestError::ErrorCode(ErrorCode::NOMEM)));
+        return;
+    };
+
+    let test = static_init!(
+        ProcessIdTest,
+        ProcessIdTest::new(apps, collision_loader, reload_loader)
+    );
+    collision_loader.set_client(test);
+    reload_loader.set_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+/// Creates a sequential process loader over `images` that assigns ShortIds
+/// with `policy`. Unlike `ProcessLoaderSequentialComponent` it does not
+/// start the loader, so the test decides when `images` are scanned.
+unsafe fn create_loader<const N: usize>(
+    apps: &'static AppLoader,
+    policy: &'static IdAssigner,
+    storage_policy: &'static NullStoragePermissions<ChipHw, ProcessStandardDebugFull>,
+    images: &'static AppImages<N>,
+    checker: &'static ProcessCheckerMachine,
+    s: (
+        &'static mut MaybeUninit<Loader>,
+        &'static mut MaybeUninit<[Option<ProcessBinary>; LOADER_BINARIES]>,
+    ),
+) -> Option<&'static Loader> {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let memory = apps.take_memory(LOADER_MEMORY_LEN)?;
+
+    const ARRAY_REPEAT_VALUE: Option<ProcessBinary> = None;
+    let process_binaries = s.1.write([ARRAY_REPEAT_VALUE; LOADER_BINARIES]);
+
+    let loader = s.0.write(SequentialProcessLoaderMachine::new(
+        checker,
+        process_binaries,
+        apps.kernel(),
+        apps.chip(),
+        images.flash(),
+        memory,
+        apps.fault_policy(),
+        storage_policy,
+        policy,
+        &process_management_cap,
+    ));
+    checker.set_client(loader);
+    loader.register();
+    Some(loader)
+}
+
+fn ensure(condition: bool, reason: &'static str) -> Result<(), &'static str> {
+    if condition {
+        Ok(())
+    } else {
+        Err(reason)
+    }
+}
+
+/// Whether the kernel rejects `processid` everywhere it is accepted as a
+/// handle.
+fn is_rejected(apps: &AppLoader, processid: ProcessId) -> bool {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let mapped =
+        apps.kernel()
+            .process_map_or_external(false, processid, |_| true, &process_management_cap);
+    !mapped
+        && apps.grant.enter(processid, |_, _| ()).is_err()
+        && matches!(processid.short_app_id(), ShortId::LocallyUnique)
+        && processid.get_editable_flash_range() == (0, 0)
+        && processid.get_storage_permissions().is_none()
+}
+
+/// Restarts `process` `RESTARTS` times, checking after each restart that
+/// all earlier `ProcessId`s are rejected. Returns those `ProcessId`s.
+fn restart_in_sequence(
+    apps: &AppLoader,
+    process: &dyn Process,
+) -> Result<[ProcessId; RESTARTS], &'static str> {
+    let process_start_cap = create_capability!(capabilities::ProcessStartCapability);
+    let mut old_ids = [process.processid(); RESTARTS];
+    for generation in 0..RESTARTS {
+        let processid = process.processid();
+        old_ids[generation] = processid;
+        process.terminate(None);
+        ensure(
+            apps.grant.enter(processid, |_, _| ()).is_err(),
+            "grant of terminated process entered",
+        )?;
+        process.start(&process_start_cap);
+        ensure(
+            old_ids[..=generation]
+                .iter()
+                .all(|id| is_rejected(apps, *id)),
+            "earlier identifier accepted",
+        )?;
+        ensure(
+            !is_rejected(apps, process.processid()),
+            "current identifier rejected",
+        )?;
+    }
+    Ok(old_ids)
+}
+
+/// Terminates `process` and frees its slot for the next case.
+fn remove(apps: &AppLoader, process: &dyn Process) -> Result<(), &'static str> {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let processid = process.processid();
+    process.terminate(None);
+    apps.kernel()
+        .remove_process(processid, &process_management_cap)
+        .map_err(|_| "terminated process not removed")?;
+    ensure(is_rejected(apps, processid), "removed identifier accepted")
+}
+
+pub struct ProcessIdTest {
+    apps: &'static AppLoader,
+    collision_loader: &'static Loader,
+    reload_loader: &'static Loader,
+    step: Cell<Step>,
+    loaded: Cell<usize>,
+    no_slot: Cell<usize>,
+    /// ShortId `shared_a` was loaded with.
+    shared_id: Cell<ShortId>,
+    /// `ProcessId`s `shared_a` had.
+    shared_ids: Cell<Option<[ProcessId; RESTARTS]>>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl ProcessIdTest {
+    pub fn new(
+        apps: &'static AppLoader,
+        collision_loader: &'static Loader,
+        reload_loader: &'static Loader,
+    ) -> Self {
+        ProcessIdTest {
+            apps,
+            collision_loader,
+            reload_loader,
+            step: Cell::new(Step::Liveness),
+            loaded: Cell::new(0),
+            no_slot: Cell::new(0),
+            shared_id: Cell::new(ShortId::LocallyUnique),
+            shared_ids: Cell::new(None),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.apps.fill_slots(1).and_then(|()| self.check_liveness()) {
+            self.fail(reason, CapsuleTestError::IncorrectResult);
+            return;
+        }
+        self.step.set(Step::Collision);
+        self.collision_loader.start();
+    }
+
+    fn check_liveness(&self) -> Result<(), &'static str> {
+        let process = self
+            .apps
+            .load(&SLOT_APP_ONE)
+            .map_err(|_| "loading failed")?
+            .ok_or("no process created")?;
+        let processid = process.processid();
+        ensure(
+            processid.short_app_id() != processid.short_app_id(),
+            "LocallyUnique ShortId equal to itself",
+        )?;
+        restart_in_sequence(self.apps, process)?;
+        ensure(
+            matches!(process.short_app_id(), ShortId::LocallyUnique),
+            "ShortId assigned by the synchronous loader",
+        )?;
+        remove(self.apps, process)
+    }
+
+    fn check_collision(&self) -> Result<(), &'static str> {
+        ensure(self.loaded.get() == 1, "wrong number of processes loaded")?;
+        ensure(
+            self.no_slot.get() == 1,
+            "colliding ShortId not skipped before the slot check",
+        )?;
+        let shared_a = self
+            .apps
+            .find(SHARED_PREFIX_APPS.image(0))
+            .ok_or("shared_a not loaded")?;
+        let expected = ShortId::from(core::num::NonZeroU32::new(name_prefix_hash("shared")));
+        ensure(
+            shared_a.short_app_id() == expected,
+            "ShortId not assigned by the policy",
+        )?;
+        ensure(
+            shared_a.processid().short_app_id() == expected,
+            "ShortId not reported for ProcessId",
+        )?;
+        self.shared_id.set(expected);
+        Ok(())
+    }
+
+    fn check_restart(&self) -> Result<(), &'static str> {
+        let shared_a = self
+            .apps
+            .find(SHARED_PREFIX_APPS.image(0))
+            .ok_or("shared_a not loaded")?;
+        let shared_id = self.shared_id.get();
+        let old_ids = restart_in_sequence(self.apps, shared_a)?;
+        ensure(
+            shared_a.short_app_id() == shared_id,
+            "ShortId changed across restarts",
+        )?;
+        ensure(
+            old_ids.iter().all(|id| id.short_app_id() != shared_id),
+            "earlier identifier reports the ShortId",
+        )?;
+        self.shared_ids.set(Some(old_ids));
+        let last_id = shared_a.processid();
+        remove(self.apps, shared_a)?;
+        ensure(
+            last_id.short_app_id() != shared_id,
+            "removed identifier reports the ShortId",
+        )
+    }
+
+    fn check_reload(&self) -> Result<(), &'static str> {
+        ensure(self.loaded.get() == 1, "shared_b not loaded")?;
+        ensure(self.no_slot.get() == 0, "no free slot")?;
+        let shared_b = self
+            .apps
+            .find(SHARED_B_APP.image(0))
+            .ok_or("shared_b not found")?;
+        ensure(
+            shared_b.short_app_id() == self.shared_id.get(),
+            "ShortId of removed process not reassigned",
+        )?;
+        let old_ids = self.shared_ids.get().ok_or("no earlier identifiers")?;
+        ensure(
+            old_ids
+                .iter()
+                .all(|id| *id != shared_b.processid() && is_rejected(self.apps, *id)),
+            "identifier of removed process refers to its successor",
+        )?;
+        remove(self.apps, shared_b)
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("ProcessId: {:?} failed: {}", self.step.get(), reason);
+        let _ = self.apps.clear_slots();
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "ProcessId", result);
+    }
+}
+
+impl ProcessLoadingAsyncClient for ProcessIdTest {
+    fn process_loaded(&self, result: Result<(), ProcessLoadError>) {
+        match result {
+            Ok(()) => self.loaded.set(self.loaded.get() + 1),
+            Err(ProcessLoadError::NoProcessSlot) => self.no_slot.set(self.no_slot.get() + 1),
+            Err(_) => self.fail("loading failed", CapsuleTestError::IncorrectResult),
+        }
+    }
+
+    fn process_loading_finished(&self) {
+        if self.finished.get() {
+            return;
+        }
+        match self.step.get() {
+            Step::Collision => {
+                let result = self.check_collision().and_then(|()| {
+                    self.step.set(Step::Restart);
+                    self.check_restart()
+                });
+                if let Err(reason) = result {
+                    self.fail(reason, CapsuleTestError::IncorrectResult);
+                    return;
+                }
+                self.step.set(Step::Reload);
+                self.loaded.set(0);
+                self.no_slot.set(0);
+                self.reload_loader.start();
+            }
+            Step::Reload => match self.check_reload().and_then(|()| self.apps.clear_slots()) {
+                Ok(()) => self.finish(Ok(())),
+                Err(reason) => self.fail(reason, CapsuleTestError::IncorrectResult),
+            },
+            Step::Liveness | Step::Restart => {}
+        }
+    }
+}
+
+impl CapsuleTest for ProcessIdTest {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,207 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that no state leaks from one process generation to the next, both
+//! when a process is restarted in place and when its slot is reused for a
+//! different process. Placeholder apps take all process slots but one. The
+//! cases are:
+//!
+//! 1. `Load`: with the slot taken, loading another app creates no process.
+//! 2. `Restart`: after `terminate()` and `start()` the process has a new
+//!    `ProcessId`, the old one is rejected, its grant and the MPU region
+//!    added before the restart are gone.
+//! 3. `Reuse`: after the process is removed, a different embedded app loads
+//!    into the freed slot with a fresh identifier, empty grants, its own
+//!    memory and no MPU regions of the old process.
+//! 4. `RemoveRunning`: a running process cannot be removed.
+//!
+//! The expected output ends with
+//! ProcessSlot: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::process::{Process, ProcessAddresses, ProcessId};
+use kernel::{capabilities, create_capability, ErrorCode};
+
+use crate::test::embedded_apps::{AppLoader, SLOT_APP_ONE, SLOT_APP_TWO};
+
+/// Marker the test stores in the first process's grant.
+const MARKER: u32 = 0x5107_0001;
+
+/// Size of the MPU region added to the first process.
+const MPU_REGION_SIZE: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Load,
+    Restart,
+    Reuse,
+    RemoveRunning,
+}
+
+pub unsafe fn run_process_slot(apps: &'static AppLoader, client: &'static dyn CapsuleTestClient) {
+    let mut step = Step::Load;
+    let result = apps
+        .fill_slots(1)
+        .and_then(|()| check_generations(apps, &mut step))
+        .and(apps.clear_slots());
+    if let Err(reason) = result {
+        debug!("ProcessSlot: {:?} failed: {}", step, reason);
+        client.done(Err(CapsuleTestError::IncorrectResult));
+        return;
+    }
+    debug!("ProcessSlot: all cases passed");
+    client.done(Ok(()));
+}
+
+fn ensure(condition: bool, reason: &'static str) -> Result<(), &'static str> {
+    if condition {
+        Ok(())
+    } else {
+        Err(reason)
+    }
+}
+
+/// Returns the marker in `processid`'s grant, allocating the grant if
+/// needed, or `None` if the kernel rejects `processid`.
+fn grant_marker(apps: &AppLoader, processid: ProcessId) -> Option<u32> {
+    apps.grant.enter(processid, |data, _| data.marker).ok()
+}
+
+/// Whether `processid` still refers to a process.
+fn is_valid(apps: &AppLoader, processid: ProcessId) -> bool {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+    apps.kernel()
+        .process_map_or_external(false, processid, |_| true, &process_management_cap)
+}
+
+/// Whether the memory of `a` and `b` overlaps.
+fn memory_overlaps(a: &ProcessAddresses, b: &ProcessAddresses) -> bool {
+    a.sram_start < b.sram_end && b.sram_start < a.sram_end
+}
+
+fn check_generations(apps: &'static AppLoader, step: &mut Step) -> Result<(), &'static str> {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let process_start_cap = create_capability!(capabilities::ProcessStartCapability);
+    let kernel = apps.kernel();
+
+    *step = Step::Load;
+    let first: &dyn Process = apps
+        .load(&SLOT_APP_ONE)
+        .map_err(|_| "loading failed")?
+        .ok_or("no process created")?;
+    let first_id = first.processid();
+    let first_addresses = first.get_addresses();
+    ensure(first.get_process_name() == "slot_one", "wrong process")?;
+
+    ensure(
+        apps.grant
+            .enter(first_id, |data, _| data.marker = MARKER)
+            .is_ok(),
+        "grant not entered",
+    )?;
+    ensure(
+        matches!(apps.load(&SLOT_APP_TWO), Ok(None)),
+        "process created without a free slot",
+    )?;
+    let region = first
+        .add_mpu_region(
+            first_addresses.sram_app_brk as *const u8,
+            first_addresses.sram_grant_start - first_addresses.sram_app_brk,
+            MPU_REGION_SIZE,
+        )
+        .ok_or("MPU region not added")?;
+    ensure(
+        first.debug_snapshot().mpu_regions.contains(&Some(region)),
+        "MPU region missing from snapshot",
+    )?;
+
+    *step = Step::Restart;
+    first.terminate(None);
+    ensure(
+        grant_marker(apps, first_id).is_none(),
+        "grant of terminated process entered",
+    )?;
+    first.start(&process_start_cap);
+    let restarted_id = first.processid();
+    ensure(restarted_id != first_id, "identifier reused")?;
+    ensure(!is_valid(apps, first_id), "old identifier accepted")?;
+    ensure(
+        grant_marker(apps, first_id).is_none(),
+        "old identifier enters grant",
+    )?;
+    ensure(
+        grant_marker(apps, restarted_id) == Some(0),
+        "grant kept across restart",
+    )?;
+    let snapshot = first.debug_snapshot();
+    ensure(
+        snapshot.mpu_regions.iter().all(Option::is_none),
+        "MPU region kept across restart",
+    )?;
+    ensure(
+        first.remove_mpu_region(region) == Err(ErrorCode::INVAL),
+        "MPU region kept across restart",
+    )?;
+    ensure(snapshot.restart_count == 1, "restart not counted")?;
+
+    *step = Step::Reuse;
+    ensure(
+        kernel.remove_process(restarted_id, &process_management_cap) == Err(ErrorCode::BUSY),
+        "running process removed",
+    )?;
+    first.terminate(None);
+    kernel
+        .remove_process(restarted_id, &process_management_cap)
+        .map_err(|_| "terminated process not removed")?;
+    ensure(!is_valid(apps, restarted_id), "removed identifier accepted")?;
+
+    let second: &dyn Process = apps
+        .load(&SLOT_APP_TWO)
+        .map_err(|_| "loading into freed slot failed")?
+        .ok_or("no process created in freed slot")?;
+    let second_id = second.processid();
+    let second_addresses = second.get_addresses();
+    ensure(second.get_process_name() == "slot_two", "wrong process")?;
+    ensure(
+        second_id != first_id && second_id != restarted_id,
+        "identifier reused",
+    )?;
+    ensure(!is_valid(apps, restarted_id), "old identifier accepted")?;
+    let snapshot = second.debug_snapshot();
+    ensure(snapshot.restart_count == 0, "restart count inherited")?;
+    ensure(snapshot.grants_allocated == Some(0), "grants inherited")?;
+    ensure(
+        grant_marker(apps, second_id) == Some(0),
+        "grant data inherited",
+    )?;
+    ensure(
+        grant_marker(apps, restarted_id).is_none(),
+        "old identifier enters grant",
+    )?;
+    ensure(
+        second_addresses.flash_start != first_addresses.flash_start,
+        "old flash region",
+    )?;
+    ensure(
+        !memory_overlaps(&first_addresses, &second_addresses),
+        "memory of removed process reused",
+    )?;
+    ensure(
+        second.remove_mpu_region(region) == Err(ErrorCode::INVAL),
+        "MPU region of removed process inherited",
+    )?;
+
+    *step = Step::RemoveRunning;
+    ensure(
+        kernel.remove_process(second_id, &process_management_cap) == Err(ErrorCode::BUSY),
+        "running process removed",
+    )?;
+
+    // Free the slot for later tests.
+    second.terminate(None);
+    kernel
+        .remove_process(second_id, &process_management_cap)
+        .map_err(|_| "terminated process not removed")
+}
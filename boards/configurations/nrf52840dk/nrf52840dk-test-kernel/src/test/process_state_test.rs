@@ -0,0 +1,294 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Drives embedded apps through the process state machine and checks each
+//! state change the kernel reports, as this board builds the kernel with the
+//! `kernel_test` feature. The kernel checks every change against the legal
+//! state machine itself, and the test expects it to report no violation. The
+//! cases are:
+//!
+//! 1. `Start`: a new process runs its first upcall and yields.
+//! 2. `Stop`: the stopped process stays stopped, and resuming it returns it
+//!    to `Yielded`.
+//! 3. `Restart`: restarting the process terminates it, and it starts over,
+//!    runs again and yields.
+//! 4. `Fault`: a process that faults is terminated and then marked faulted by
+//!    the board's fault policy, and does not run again. Its snapshot shows
+//!    the faulting instruction, the first one of its code.
+//!
+//! The expected output ends with
+//! ProcessState: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::{Process, ProcessId, State, StoppedState};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppLoader, FAULT_APP, IDLE_APP};
+use crate::test::invariant_monitor::InvariantMonitor;
+use crate::test::registry::report;
+
+/// Time a process gets to reach the state a step expects.
+const STEP_MS: u32 = 20;
+
+/// Changes of a new process that runs its first upcall and yields.
+const START: [(State, State); 2] = [
+    (State::Yielded, State::Running),
+    (State::Running, State::Yielded),
+];
+
+/// Changes of a stop of a yielded process and its resume.
+const STOP: [(State, State); 2] = [
+    (State::Yielded, State::Stopped(StoppedState::Yielded)),
+    (State::Stopped(StoppedState::Yielded), State::Yielded),
+];
+
+/// Changes of a restart, before the process runs.
+const RESTART: [(State, State); 2] = [
+    (State::Yielded, State::Terminated),
+    (State::Terminated, State::Yielded),
+];
+
+/// Changes of a process that faults on its first instruction, with the
+/// board's fault policy.
+const FAULT: [(State, State); 3] = [
+    (State::Yielded, State::Running),
+    (State::Running, State::Terminated),
+    (State::Terminated, State::Faulted),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Start,
+    Stop,
+    Restart,
+    Fault,
+}
+
+pub unsafe fn run_process_state(
+    apps: &'static AppLoader,
+    monitor: &'static InvariantMonitor,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestProcessState,
+        TestProcessState::new(apps, monitor, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestProcessState {
+    apps: &'static AppLoader,
+    monitor: &'static InvariantMonitor,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    idle: OptionalCell<&'static dyn Process>,
+    faulting: OptionalCell<&'static dyn Process>,
+    /// Violations the monitor counted before the test started.
+    violations: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestProcessState {
+    pub fn new(
+        apps: &'static AppLoader,
+        monitor: &'static InvariantMonitor,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestProcessState {
+            apps,
+            monitor,
+            alarm,
+            idle: OptionalCell::empty(),
+            faulting: OptionalCell::empty(),
+            violations: Cell::new(0),
+            step: Cell::new(Step::Start),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.violations.set(self.monitor.violations());
+        self.monitor.clear_transitions();
+        match self.apps.load(&IDLE_APP) {
+            Ok(Some(process)) => self.idle.set(process),
+            _ => {
+                self.fail("no process created");
+                return;
+            }
+        }
+        self.wait();
+    }
+
+    fn wait(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(STEP_MS));
+    }
+
+    /// Checks that the state changes of `processid` since the monitor was
+    /// last cleared are `expected`.
+    fn expect(
+        &self,
+        processid: ProcessId,
+        expected: &[(State, State)],
+        reason: &'static str,
+    ) -> Result<(), &'static str> {
+        let (transitions, dropped) = self.monitor.transitions(processid);
+        if dropped || !transitions.eq(expected.iter().copied()) {
+            self.print_transitions(processid);
+            return Err(reason);
+        }
+        Ok(())
+    }
+
+    fn print_transitions(&self, processid: ProcessId) {
+        let (transitions, dropped) = self.monitor.transitions(processid);
+        transitions.for_each(|(from, to)| {
+            debug!(
+                "ProcessState: {:?} went from {:?} to {:?}",
+                processid, from, to
+            )
+        });
+        if dropped {
+            debug!("ProcessState: later changes dropped");
+        }
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        let idle = self.idle.get().ok_or("process not loaded")?;
+        match self.step.get() {
+            Step::Start => {
+                self.expect(
+                    idle.processid(),
+                    &START,
+                    "new process did not run and yield",
+                )?;
+
+                self.step.set(Step::Stop);
+                self.monitor.clear_transitions();
+                idle.stop();
+                self.wait();
+            }
+            Step::Stop => {
+                if idle.get_state() != State::Stopped(StoppedState::Yielded) {
+                    return Err("stopped process left the stopped state");
+                }
+                idle.resume();
+                self.expect(
+                    idle.processid(),
+                    &STOP,
+                    "stop and resume did not restore the state",
+                )?;
+
+                // The restarted process has a new identifier, which it takes
+                // before it leaves `Terminated`.
+                self.step.set(Step::Restart);
+                self.monitor.clear_transitions();
+                let old = idle.processid();
+                idle.try_restart(None);
+                self.expect(old, &RESTART[..1], "restart did not terminate")?;
+                self.expect(idle.processid(), &RESTART[1..], "restart did not reset")?;
+                self.monitor.clear_transitions();
+                self.wait();
+            }
+            Step::Restart => {
+                self.expect(
+                    idle.processid(),
+                    &START,
+                    "restarted process did not run and yield",
+                )?;
+
+                self.step.set(Step::Fault);
+                self.monitor.clear_transitions();
+                let faulting = self
+                    .apps
+                    .load(&FAULT_APP)
+                    .map_err(|_| "loading the faulting app failed")?
+                    .ok_or("no process created for the faulting app")?;
+                self.faulting.set(faulting);
+                self.wait();
+            }
+            Step::Fault => {
+                let faulting = self.faulting.get().ok_or("faulting app not loaded")?;
+                self.expect(
+                    faulting.processid(),
+                    &FAULT,
+                    "fault did not stop the process",
+                )?;
+                let snapshot = faulting.debug_snapshot();
+                if snapshot.state != State::Faulted {
+                    return Err("faulted process left the faulted state");
+                }
+                if snapshot.pc != Some(faulting.get_addresses().flash_non_protected_start) {
+                    return Err("snapshot does not show the faulting instruction");
+                }
+                if self.monitor.violations() != self.violations.get() {
+                    return Err("kernel reported invariant violations");
+                }
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Terminates and removes `process`.
+    fn remove(&self, process: &dyn Process) {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        process.terminate(None);
+        let _ = self
+            .apps
+            .kernel()
+            .remove_process(process.processid(), &process_management_cap);
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("ProcessState: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        // Free the slots for later tests, even after a failure.
+        for process in [self.idle.take(), self.faulting.take()]
+            .into_iter()
+            .flatten()
+        {
+            self.remove(process);
+        }
+        report(&self.client, "ProcessState", result);
+    }
+}
+
+impl AlarmClient for TestProcessState {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestProcessState {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,220 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the per-process CPU accounting the process console lists as
+//! `Quanta` and `Time(ms)`. Two embedded apps with known duty cycles each run
+//! for the same window, one after the other so each has the CPU to itself.
+//! The cases are:
+//!
+//! 1. `Busy`: an app that spins without system calls executes for about the
+//!    whole window, and the number of timeslices it exceeded matches the time
+//!    it executed.
+//! 2. `Idle`: an app that loops around yield-wait executes for a tiny fraction
+//!    of the window and never exceeds a timeslice.
+//! 3. `Ratio`: the executed times of the two apps reflect their duty cycles.
+//!
+//! The expected output ends with
+//! ProcessStats: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::Process;
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppImage, AppLoader, BUSY_APP, IDLE_APP};
+use crate::test::registry::report;
+
+/// Time each app runs for.
+const RUN_MS: u32 = 200;
+
+/// Default timeslice of the round robin scheduler the board uses.
+const TIMESLICE_US: u64 = 10_000;
+
+/// Lowest share of the window, in percent, the busy app must execute for.
+/// The rest goes to interrupts and the test itself.
+const BUSY_MIN_PERCENT: u64 = 90;
+
+/// Highest share of the window, in percent, the idle app may execute for.
+const IDLE_MAX_PERCENT: u64 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Busy,
+    Idle,
+    Ratio,
+}
+
+pub unsafe fn run_process_stats(
+    apps: &'static AppLoader,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(TestProcessStats, TestProcessStats::new(apps, alarm));
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestProcessStats {
+    apps: &'static AppLoader,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    process: OptionalCell<&'static dyn Process>,
+    /// Time in microseconds the busy app executed for.
+    busy_us: Cell<u64>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestProcessStats {
+    pub fn new(
+        apps: &'static AppLoader,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestProcessStats {
+            apps,
+            alarm,
+            process: OptionalCell::empty(),
+            busy_us: Cell::new(0),
+            step: Cell::new(Step::Busy),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.start(&BUSY_APP) {
+            self.fail(reason);
+        }
+    }
+
+    /// Loads `image` and lets it run for `RUN_MS`.
+    fn start(&self, image: &'static AppImage) -> Result<(), &'static str> {
+        let process = self
+            .apps
+            .load(image)
+            .map_err(|_| "loading app failed")?
+            .ok_or("no process created")?;
+        if process.debug_executed_time_us() != 0 {
+            return Err("new process has executed time");
+        }
+        self.process.set(process);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RUN_MS));
+        Ok(())
+    }
+
+    /// Terminates and removes `process`, to free the slot for the next app.
+    fn remove(&self, process: &dyn Process) -> Result<(), &'static str> {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        process.terminate(None);
+        self.apps
+            .kernel()
+            .remove_process(process.processid(), &process_management_cap)
+            .map_err(|_| "terminated process not removed")
+    }
+
+    fn check(&self, process: &dyn Process) -> Result<(), &'static str> {
+        let executed_us = process.debug_executed_time_us();
+        let expirations = process.debug_timeslice_expiration_count() as u64;
+        let syscalls = process.debug_syscall_count();
+        let window_us = RUN_MS as u64 * 1000;
+        debug!(
+            "ProcessStats: {} executed for {} us in {} ms, {} timeslices expired",
+            process.get_process_name(),
+            executed_us,
+            RUN_MS,
+            expirations
+        );
+        self.remove(process)?;
+
+        match self.step.get() {
+            Step::Busy => {
+                if executed_us * 100 < window_us * BUSY_MIN_PERCENT {
+                    return Err("busy app executed for too little of the window");
+                }
+                if executed_us > window_us + TIMESLICE_US {
+                    return Err("busy app executed for longer than the window");
+                }
+                // The scheduler continues a preempted timeslice, so each
+                // expiration accounts for one whole timeslice.
+                if executed_us < expirations * TIMESLICE_US
+                    || executed_us > (expirations + 1) * TIMESLICE_US
+                {
+                    return Err("expired timeslices do not match the executed time");
+                }
+
+                self.busy_us.set(executed_us);
+                self.step.set(Step::Idle);
+                self.start(&IDLE_APP)
+            }
+            Step::Idle => {
+                if expirations != 0 {
+                    return Err("idle app exceeded a timeslice");
+                }
+                if syscalls == 0 {
+                    return Err("idle app made no system call");
+                }
+                if executed_us * 100 > window_us * IDLE_MAX_PERCENT {
+                    return Err("idle app executed for too much of the window");
+                }
+
+                self.step.set(Step::Ratio);
+                let busy_us = self.busy_us.get();
+                debug!(
+                    "ProcessStats: duty cycles {}% and {}%",
+                    busy_us * 100 / window_us,
+                    executed_us * 100 / window_us
+                );
+                if busy_us < executed_us.max(1) * (BUSY_MIN_PERCENT / IDLE_MAX_PERCENT) {
+                    return Err("executed times do not reflect the duty cycles");
+                }
+                self.finish(Ok(()));
+                Ok(())
+            }
+            Step::Ratio => Err("unexpected alarm"),
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("ProcessStats: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "ProcessStats", result);
+    }
+}
+
+impl AlarmClient for TestProcessStats {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let Some(process) = self.process.take() else {
+            return;
+        };
+        if let Err(reason) = self.check(process) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestProcessStats {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
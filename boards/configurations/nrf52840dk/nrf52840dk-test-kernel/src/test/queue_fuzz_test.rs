@@ -0,0 +1,162 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Applies pseudo-random operations to `kernel::collections::ring_buffer`
+//! queues of random capacities, and checks each queue against a plain model
+//! after every operation. The operations are those of the `Queue` trait:
+//! `Enqueue`, `Push`, `Dequeue`, `RemoveFirstMatching`, `Retain` and `Empty`.
+//! The data comes from the launcher's test seed, so a failure repeats with
+//! the seed the launcher prints.
+//!
+//! The expected output ends with
+//! QueueFuzz: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use kernel::collections::queue::Queue;
+use kernel::collections::ring_buffer::RingBuffer;
+use kernel::debug;
+use kernel::test::rng::TestRng;
+
+/// Number of queues the test creates.
+const ROUNDS: usize = 64;
+
+/// Number of operations on each queue.
+const OPERATIONS: usize = 128;
+
+/// Largest capacity of a queue. A ring buffer holds one element less than
+/// its length.
+const MAX_CAPACITY: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Enqueue,
+    Push,
+    Dequeue,
+    RemoveFirstMatching,
+    Retain,
+    Empty,
+}
+
+const STEPS: [Step; 6] = [
+    Step::Enqueue,
+    Step::Push,
+    Step::Dequeue,
+    Step::RemoveFirstMatching,
+    Step::Retain,
+    Step::Empty,
+];
+
+/// What the queue should hold, oldest element first.
+struct Model {
+    elements: [u8; MAX_CAPACITY],
+    len: usize,
+    capacity: usize,
+}
+
+impl Model {
+    fn remove(&mut self, index: usize) -> u8 {
+        let element = self.elements[index];
+        self.elements.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        element
+    }
+
+    fn append(&mut self, element: u8) {
+        self.elements[self.len] = element;
+        self.len += 1;
+    }
+}
+
+pub unsafe fn run_queue_fuzz(rng: TestRng, client: &'static dyn CapsuleTestClient) {
+    for round in 0..ROUNDS {
+        if let Err((step, operation, reason)) = check_queue(&rng) {
+            debug!(
+                "QueueFuzz: {:?} failed: {} (queue {}, operation {})",
+                step, reason, round, operation
+            );
+            client.done(Err(CapsuleTestError::IncorrectResult));
+            return;
+        }
+    }
+    debug!("QueueFuzz: all cases passed");
+    client.done(Ok(()));
+}
+
+fn check_queue(rng: &TestRng) -> Result<(), (Step, usize, &'static str)> {
+    let capacity = rng.below(MAX_CAPACITY as u32) as usize + 1;
+    let mut ring = [0; MAX_CAPACITY + 1];
+    let mut queue = RingBuffer::new(&mut ring[..capacity + 1]);
+    let mut model = Model {
+        elements: [0; MAX_CAPACITY],
+        len: 0,
+        capacity,
+    };
+
+    for operation in 0..OPERATIONS {
+        let step = STEPS[rng.below(STEPS.len() as u32) as usize];
+        let fail = |reason| (step, operation, reason);
+        // Small values, so the predicates below match some elements.
+        let value = rng.below(8) as u8;
+        match step {
+            Step::Enqueue => {
+                let full = model.len == model.capacity;
+                if !full {
+                    model.append(value);
+                }
+                if queue.enqueue(value) == full {
+                    return Err(fail("enqueue did not fail exactly when full"));
+                }
+            }
+            Step::Push => {
+                let expected = (model.len == model.capacity).then(|| model.remove(0));
+                model.append(value);
+                if queue.push(value) != expected {
+                    return Err(fail("push did not return the oldest element when full"));
+                }
+            }
+            Step::Dequeue => {
+                let expected = (model.len > 0).then(|| model.remove(0));
+                if queue.dequeue() != expected {
+                    return Err(fail("dequeue did not return the oldest element"));
+                }
+            }
+            Step::RemoveFirstMatching => {
+                let expected = model.elements[..model.len]
+                    .iter()
+                    .position(|element| *element == value)
+                    .map(|index| model.remove(index));
+                if queue.remove_first_matching(|element| *element == value) != expected {
+                    return Err(fail("wrong element removed"));
+                }
+            }
+            Step::Retain => {
+                let divisor = value.max(2);
+                let elements = model.elements;
+                let len = model.len;
+                model.len = 0;
+                for element in elements[..len].iter().filter(|e| *e % divisor != 0) {
+                    model.append(*element);
+                }
+                queue.retain(|element| element % divisor != 0);
+            }
+            Step::Empty => {
+                model.len = 0;
+                queue.empty();
+            }
+        }
+
+        if queue.len() != model.len
+            || queue.has_elements() != (model.len > 0)
+            || queue.is_full() != (model.len == model.capacity)
+        {
+            return Err(fail("length does not match the model"));
+        }
+        let (first, second) = queue.as_slices();
+        let contents = first.unwrap_or(&[]).iter().chain(second.unwrap_or(&[]));
+        if !contents.eq(model.elements[..model.len].iter()) {
+            return Err(fail("elements do not match the model"));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,300 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the timing of a transmission by the 802.15.4 radio driver against
+//! the 2.4 GHz PHY of IEEE 802.15.4. PPI channels capture the time of the
+//! radio events in TIMER2 as they happen, so the measurement adds no work to
+//! the interrupt handler of the driver. The board sends one broadcast frame
+//! without an ACK request, on a channel that must be quiet for the assessment
+//! to find it idle at once. The cases are:
+//!
+//! 1. `Cca`: the clear channel assessment, from the receiver being ready to
+//!    the channel found idle, lasts 8 symbols.
+//! 2. `TxTurnaround`: the preamble of the frame starts at most
+//!    aTurnaroundTime, 12 symbols, after the channel was found idle.
+//! 3. `RxTurnaround`: the receiver is ready at most aTurnaroundTime after the
+//!    end of the frame, in time for an ACK.
+//!
+//! A busy channel cannot be made on demand, so the backoff after a busy
+//! assessment is not checked. The spacing between an ACK and the next frame
+//! needs a peer, and the 802.15.4 address filtering and ACK test checks it.
+//!
+//! The expected output ends with
+//! RadioTiming: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::registers::FieldValue;
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::ieee802154_radio::Radio;
+use nrf52840::ppi::{Channel, Ppi};
+use nrf52840::rtc::Rtc;
+use nrf52840::timer::{BitmodeValue, Timer};
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::registry::report;
+use crate::test::scratch::ScratchArena;
+
+/// TIMER2 counts at 16 MHz / 2^4 = 1 MHz.
+const TIMER_PRESCALER: u8 = 4;
+
+/// Radio events the tests capture the time of.
+pub const EVENTS_CRCOK: usize = 0x4000_1130;
+pub const EVENTS_FRAMESTART: usize = 0x4000_1138;
+pub const EVENTS_CCAIDLE: usize = 0x4000_1144;
+pub const EVENTS_RXREADY: usize = 0x4000_1158;
+pub const EVENTS_PHYEND: usize = 0x4000_116C;
+
+/// Time from the start of the preamble of a frame to its FRAMESTART event,
+/// after the preamble, the SFD and the PHR: 6 bytes of 32 us.
+pub const FRAMESTART_US: u32 = 6 * 32;
+
+/// aTurnaroundTime, 12 symbols of 16 us, which is also the shortest
+/// interframe spacing, macSifsPeriod.
+pub const TURNAROUND_US: u32 = 12 * 16;
+
+/// The clear channel assessment, 8 symbols.
+const CCA_US: u32 = 8 * 16;
+
+/// Time the assessment may last longer, for the events to pass through PPI.
+const CCA_SLACK_US: u32 = 4;
+
+/// Time the test waits after the transmission for the receiver to be ready.
+const SETTLE_MS: u32 = 2;
+
+/// A data frame from short address 1 to the broadcast address, in PAN
+/// 0x7e57, without an ACK request.
+const FRAME: [u8; 9] = [0x41, 0x88, 0x00, 0x57, 0x7e, 0xff, 0xff, 0x01, 0x00];
+
+/// Captures the time of radio events in TIMER2, through the first PPI
+/// channels, without the CPU, so the radio driver runs as it does outside
+/// the tests.
+pub struct EventCapture {
+    timer: &'static Timer,
+    ppi: Ppi,
+    /// The channels in use.
+    channels: Cell<u32>,
+}
+
+impl EventCapture {
+    pub fn new(timer: &'static Timer) -> Self {
+        EventCapture {
+            timer,
+            ppi: Ppi::new(),
+            channels: Cell::new(0),
+        }
+    }
+
+    /// Starts the timer, counting microseconds, and captures the time of each
+    /// of `events` in the CC register of the same index.
+    pub fn start(&self, events: &[usize]) -> Result<(), &'static str> {
+        for (index, event) in events.iter().enumerate() {
+            self.ppi
+                .configure_channel(index, *event, self.timer.capture_task_address(index))
+                .map_err(|_| "PPI channel not configured")?;
+        }
+        self.channels.set((1 << events.len()) - 1);
+        self.timer.start(TIMER_PRESCALER, BitmodeValue::Size32Bits);
+        self.ppi.enable(self.channel_mask());
+        Ok(())
+    }
+
+    /// The time of the last event `index`, in microseconds.
+    pub fn time(&self, index: usize) -> u32 {
+        self.timer.captured(index)
+    }
+
+    pub fn stop(&self) {
+        self.ppi.disable(self.channel_mask());
+        self.timer.stop();
+    }
+
+    fn channel_mask(&self) -> FieldValue<u32, Channel::Register> {
+        FieldValue::<u32, Channel::Register>::new(u32::MAX, 0, self.channels.get())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Cca,
+    TxTurnaround,
+    RxTurnaround,
+}
+
+pub unsafe fn run_radio_timing(
+    radio: &'static Radio<'static>,
+    scratch: &'static ScratchArena,
+    timer: &'static Timer,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+    radio.set_receive_buffer(scratch.buffer(radio::MAX_BUF_SIZE));
+
+    let test = static_init!(
+        TestRadioTiming,
+        TestRadioTiming::new(radio, timer, alarm, scratch.buffer(radio::MAX_BUF_SIZE))
+    );
+    alarm.set_alarm_client(test);
+    radio.set_transmit_client(test);
+    radio.set_receive_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestRadioTiming {
+    radio: &'static Radio<'static>,
+    capture: EventCapture,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    buffer: TakeCell<'static, [u8]>,
+    /// Time the receiver was ready for the assessment, before the receiver
+    /// that follows the frame overwrites it.
+    cca_start: Cell<u32>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestRadioTiming {
+    pub fn new(
+        radio: &'static Radio<'static>,
+        timer: &'static Timer,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        TestRadioTiming {
+            radio,
+            capture: EventCapture::new(timer),
+            alarm,
+            buffer: TakeCell::new(buffer),
+            cca_start: Cell::new(0),
+            step: Cell::new(Step::Cca),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let result = self
+            .capture
+            .start(&[
+                EVENTS_RXREADY,
+                EVENTS_CCAIDLE,
+                EVENTS_FRAMESTART,
+                EVENTS_PHYEND,
+            ])
+            .and_then(|()| self.radio.start().map_err(|_| "radio did not start"))
+            .and_then(|()| {
+                let buffer = self.buffer.take().ok_or("transmit buffer missing")?;
+                buffer[radio::PSDU_OFFSET..radio::PSDU_OFFSET + FRAME.len()]
+                    .copy_from_slice(&FRAME);
+                self.radio
+                    .transmit(buffer, FRAME.len())
+                    .map_err(|(_, buffer)| {
+                        self.buffer.replace(buffer);
+                        "transmission refused"
+                    })
+            });
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        let cca = self.capture.time(1).wrapping_sub(self.cca_start.get());
+        let tx = self
+            .capture
+            .time(2)
+            .wrapping_sub(FRAMESTART_US)
+            .wrapping_sub(self.capture.time(1));
+        let rx = self.capture.time(0).wrapping_sub(self.capture.time(3));
+        debug!(
+            "RadioTiming: CCA {} us, idle to TX {} us, TX to RX {} us",
+            cca, tx, rx
+        );
+        if !(CCA_US..=CCA_US + CCA_SLACK_US).contains(&cca) {
+            return Err("assessment of the wrong length");
+        }
+        self.step.set(Step::TxTurnaround);
+        if tx > TURNAROUND_US {
+            return Err("frame started too late after the assessment");
+        }
+        self.step.set(Step::RxTurnaround);
+        if rx > TURNAROUND_US {
+            return Err("receiver ready too late after the frame");
+        }
+        self.finish(Ok(()));
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("RadioTiming: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        self.capture.stop();
+        let _ = self.radio.stop();
+        report(&self.client, "RadioTiming", result);
+    }
+}
+
+impl radio::TxClient for TestRadioTiming {
+    fn send_done(&self, buf: &'static mut [u8], _acked: bool, result: Result<(), ErrorCode>) {
+        self.buffer.replace(buf);
+        if self.finished.get() {
+            return;
+        }
+        if result.is_err() {
+            self.fail("transmission failed");
+            return;
+        }
+        // The driver turns the receiver on once this returns, which captures
+        // the time again.
+        self.cca_start.set(self.capture.time(0));
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(SETTLE_MS));
+    }
+}
+
+impl radio::RxClient for TestRadioTiming {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        _frame_len: usize,
+        _lqi: u8,
+        _crc_valid: bool,
+        _result: Result<(), ErrorCode>,
+    ) {
+        self.radio.set_receive_buffer(buf);
+    }
+}
+
+impl AlarmClient for TestRadioTiming {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestRadioTiming {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,792 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! The tests the launcher runs, in order.
+//!
+//! Each test is a `KernelTest` with the name the launcher prints before it
+//! runs, as
+//!
+//! ```text
+//! Test 3: aes128_ctr
+//! ```
+//!
+//! so the host runner can name tests that print no result line of their own.
+//! Any module of the board registers tests with `register_kernel_tests!`,
+//! which places them in the `.kernel_tests` linker section, and the launcher
+//! runs the tests it finds between the start and end of that section. The
+//! tests of this file are registered below, and the watchdog test is
+//! registered with `last:`, as it resets the board.
+//!
+//! Tests run sorted by name, whichever module registers them and wherever the
+//! linker places them, and draw their pseudo-random data from a generator
+//! keyed by their name rather than their index. Adding a test therefore leaves
+//! the order and results of the others as they were. Names must be unique, as
+//! the runner tells results apart by name. The build checks the tests of each
+//! `register_kernel_tests!`, and the board checks all of them at boot, before
+//! the first test.
+//!
+//! Each test belongs to a `Suite`, and the cargo feature of the suite selects
+//! whether the image runs it. Tests that take minutes are also tagged slow,
+//! and only run with the `slow` feature. A test the image does not run is
+//! registered as an empty entry, so its code is not linked into the image.
+//!
+//! A test of hardware behavior that is known to be broken can stay in the
+//! registry marked with `expected_failure()`, which gives the reason, such as
+//! the issue that tracks it. The launcher then reports its failure as
+//! expected, and a pass as unexpected, and neither fails the suite.
+
+use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::utilities::cells::OptionalCell;
+
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::TestLauncher;
+
+/// A group of tests that an image runs or leaves out as a whole.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Suite {
+    /// The digests, ciphers and signatures, feature `crypto`.
+    Crypto,
+    /// Processes, their grants and syscalls, and how they are scheduled,
+    /// isolated by the MPU, feature `mpu`.
+    Mpu,
+    /// The 802.15.4 and BLE radio, feature `radio`.
+    Radio,
+    /// The other peripherals and the devices on the test shield, feature
+    /// `peripherals`.
+    Peripherals,
+    /// Benchmarks, which print cycle counts rather than check results,
+    /// feature `bench`.
+    Bench,
+    /// Faults the kernel takes on purpose and survives, feature `fault`.
+    Fault,
+}
+
+impl Suite {
+    /// Whether the image is built with the feature of the suite.
+    const fn enabled(self) -> bool {
+        match self {
+            Suite::Crypto => cfg!(feature = "crypto"),
+            Suite::Mpu => cfg!(feature = "mpu"),
+            Suite::Radio => cfg!(feature = "radio"),
+            Suite::Peripherals => cfg!(feature = "peripherals"),
+            Suite::Bench => cfg!(feature = "bench"),
+            Suite::Fault => cfg!(feature = "fault"),
+        }
+    }
+}
+
+/// A test the launcher runs.
+#[derive(Clone, Copy)]
+pub(crate) struct KernelTest {
+    /// Suite the test belongs to.
+    pub suite: Suite,
+    /// Name of the test, in snake case.
+    pub name: &'static str,
+    /// Whether the test takes the NVMC over from the flash log.
+    pub nvmc: bool,
+    /// Whether the test takes minutes, and only runs in an image built with
+    /// the `slow` feature.
+    slow: bool,
+    /// Time the test gets to report its result, in milliseconds, instead of
+    /// the `test_timeout_ms` of the board test configuration.
+    timeout_ms: Option<u32>,
+    /// Why the test is expected to fail, if it is.
+    pub expected_failure: Option<&'static str>,
+    /// Starts the test, which reports to the launcher when it is done. Also
+    /// gets the test itself.
+    pub run: fn(&'static TestLauncher, &'static KernelTest),
+}
+
+impl KernelTest {
+    pub const fn new(
+        suite: Suite,
+        name: &'static str,
+        run: fn(&'static TestLauncher, &'static KernelTest),
+    ) -> Self {
+        KernelTest {
+            suite,
+            name,
+            nvmc: false,
+            slow: false,
+            timeout_ms: None,
+            expected_failure: None,
+            run,
+        }
+    }
+
+    pub const fn with_nvmc(self) -> Self {
+        KernelTest { nvmc: true, ..self }
+    }
+
+    /// Tags the test slow, so only an image built with the `slow` feature
+    /// runs it.
+    pub const fn slow(self) -> Self {
+        KernelTest { slow: true, ..self }
+    }
+
+    /// Gives the test `timeout_ms` to report its result, for tests that take
+    /// longer than the others.
+    pub const fn with_timeout(self, timeout_ms: u32) -> Self {
+        KernelTest {
+            timeout_ms: Some(timeout_ms),
+            ..self
+        }
+    }
+
+    /// Marks the test as expected to fail, for `reason`, such as the issue
+    /// that tracks the broken behavior it checks.
+    // Only used while a registered test checks known-broken behavior.
+    #[allow(dead_code)]
+    pub const fn expected_failure(self, reason: &'static str) -> Self {
+        KernelTest {
+            expected_failure: Some(reason),
+            ..self
+        }
+    }
+
+    /// The entry `register_kernel_tests!` places for the test: the test if
+    /// its suite is enabled, and it is not slow or the image runs slow tests,
+    /// and none otherwise.
+    pub const fn registered(self) -> Option<Self> {
+        if self.suite.enabled() && (!self.slow || cfg!(feature = "slow")) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// Time the test gets to report its result, or `None` if the board test
+    /// configuration turns timeouts off.
+    pub fn timeout_ms(&self) -> Option<u32> {
+        BOARD_TEST_CONFIG
+            .test_timeout_ms
+            .map(|default| self.timeout_ms.unwrap_or(default))
+    }
+}
+
+/// Registers kernel tests with the launcher, which runs them sorted by name.
+/// Tests registered with `last:` run after all the others. The build fails if
+/// two of the tests given have the same name.
+///
+/// ```ignore
+/// register_kernel_tests![KernelTest::new(Suite::Crypto, "sha256", |launcher, _| unsafe {
+///     super::sha256_test::run_sha256(launcher.scratch, launcher)
+/// })];
+/// ```
+macro_rules! register_kernel_tests {
+    (@section $section:literal, $($test:expr),*) => {
+        const _: () = $crate::test::registry::check_unique_names(&[$($test),*]);
+        $(
+            const _: () = {
+                #[used]
+                #[link_section = $section]
+                static TEST: Option<$crate::test::registry::KernelTest> =
+                    $crate::test::registry::KernelTest::registered($test);
+            };
+        )*
+    };
+    (last: $($test:expr),* $(,)?) => {
+        $crate::test::registry::register_kernel_tests!(@section ".kernel_tests.last", $($test),*);
+    };
+    ($($test:expr),* $(,)?) => {
+        $crate::test::registry::register_kernel_tests!(@section ".kernel_tests", $($test),*);
+    };
+}
+pub(crate) use register_kernel_tests;
+
+register_kernel_tests![
+    KernelTest::new(Suite::Crypto, "sha256", |launcher, _| unsafe {
+        super::sha256_test::run_sha256(launcher.scratch, launcher)
+    }),
+    KernelTest::new(Suite::Crypto, "hmac_sha256", |launcher, _| unsafe {
+        super::hmac_sha256_test::run_hmacsha256(launcher.scratch, launcher)
+    }),
+    KernelTest::new(Suite::Crypto, "siphash24", |launcher, _| unsafe {
+        super::siphash24_test::run_siphash24(launcher)
+    }),
+    KernelTest::new(Suite::Crypto, "aes128_ctr", |launcher, _| unsafe {
+        super::aes_test::run_aes128_ctr(&launcher.peripherals.nrf52.ecb, launcher.scratch, launcher)
+    }),
+    KernelTest::new(Suite::Crypto, "aes128_cbc", |launcher, _| unsafe {
+        super::aes_test::run_aes128_cbc(&launcher.peripherals.nrf52.ecb, launcher.scratch, launcher)
+    }),
+    KernelTest::new(Suite::Crypto, "aes128_ecb", |launcher, _| unsafe {
+        super::aes_test::run_aes128_ecb(&launcher.peripherals.nrf52.ecb, launcher.scratch, launcher)
+    }),
+    KernelTest::new(Suite::Crypto, "ecdsa_p256", |launcher, _| unsafe {
+        super::ecdsa_p256_test::run_ecdsa_p256(launcher.scratch, launcher)
+    }),
+    KernelTest::new(Suite::Crypto, "digest_conformance", |launcher, _| unsafe {
+        super::digest_conformance_test::run_digest_conformance(launcher.scratch, launcher)
+    }),
+    KernelTest::new(
+        Suite::Peripherals,
+        "flash_conformance",
+        |launcher, _| unsafe {
+            super::flash_conformance_test::run_flash_conformance(
+                &launcher.peripherals.nrf52.nvmc,
+                launcher,
+            )
+        },
+    )
+    .with_nvmc(),
+    KernelTest::new(
+        Suite::Peripherals,
+        "nonvolatile_conformance",
+        |launcher, _| unsafe {
+            super::flash_conformance_test::run_nonvolatile_conformance(
+                &launcher.peripherals.nrf52.nvmc,
+                launcher,
+            )
+        },
+    )
+    .with_nvmc(),
+    KernelTest::new(
+        Suite::Peripherals,
+        "gpio_conformance",
+        |launcher, _| unsafe {
+            super::gpio_conformance_test::run_gpio_conformance(
+                &launcher.peripherals.gpio_port,
+                launcher.mux_alarm,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(Suite::Peripherals, "gpio_loopback", |launcher, _| unsafe {
+        super::gpio_loopback_test::run_gpio_loopback(
+            &launcher.peripherals.gpio_port,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(
+        Suite::Peripherals,
+        "spi_conformance",
+        |launcher, _| unsafe {
+            super::spi_conformance_test::run_spi_conformance(
+                &launcher.peripherals.nrf52.spim2,
+                &launcher.peripherals.gpio_port,
+                launcher.mux_alarm,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(
+        Suite::Peripherals,
+        "i2c_conformance",
+        |launcher, _| unsafe {
+            super::i2c_conformance_test::run_i2c_conformance(
+                &launcher.peripherals.nrf52.twi1,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(Suite::Peripherals, "i2c_loopback", |launcher, _| unsafe {
+        super::i2c_conformance_test::run_i2c_loopback(
+            &launcher.peripherals.nrf52.twi0,
+            &launcher.peripherals.nrf52.twi1,
+            &launcher.peripherals.gpio_port,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Peripherals, "i2c_stretch", |launcher, _| unsafe {
+        super::i2c_stretch_test::run_i2c_stretch(
+            &launcher.peripherals.nrf52.twi0,
+            &launcher.peripherals.nrf52.twi1,
+            &launcher.peripherals.nrf52.timer1,
+            &launcher.peripherals.gpio_port,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Peripherals, "i2c_recovery", |launcher, _| unsafe {
+        super::i2c_recovery_test::run_i2c_recovery(
+            &launcher.peripherals.nrf52.twi0,
+            &launcher.peripherals.nrf52.twi1,
+            &launcher.peripherals.nrf52.timer2,
+            &launcher.peripherals.gpio_port,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Peripherals, "spi_slave", |launcher, _| unsafe {
+        super::spi_slave_test::run_spi_slave(
+            &launcher.peripherals.nrf52.spim2,
+            &launcher.peripherals.nrf52.spis1,
+            &launcher.peripherals.gpio_port,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Peripherals, "uart_loopback", |launcher, _| unsafe {
+        super::uart_loopback_test::run_uart_loopback(&launcher.peripherals.uarte1, launcher)
+    }),
+    KernelTest::new(
+        Suite::Peripherals,
+        "adc_highspeed_conformance",
+        |launcher, _| unsafe {
+            super::adc_conformance_test::run_adc_highspeed_conformance(
+                &launcher.peripherals.nrf52.adc,
+                launcher.mux_alarm,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(
+        Suite::Peripherals,
+        "sensor_plausibility",
+        |launcher, _| unsafe {
+            super::sensor_plausibility_test::run_sensor_plausibility(
+                &launcher.peripherals.nrf52.twi1,
+                launcher.mux_alarm,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(Suite::Peripherals, "screen", |launcher, _| unsafe {
+        super::screen_test::run_screen(launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Peripherals, "touch", |launcher, _| unsafe {
+        super::touch_test::run_touch(launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Peripherals, "sx127x", |launcher, _| unsafe {
+        super::sx127x_test::run_sx127x(
+            &launcher.peripherals.nrf52.spim2,
+            &launcher.peripherals.gpio_port,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "process_slot", |launcher, _| unsafe {
+        super::process_slot_test::run_process_slot(launcher.apps, launcher)
+    }),
+    KernelTest::new(Suite::Mpu, "process_id", |launcher, _| unsafe {
+        super::process_id_test::run_process_id(launcher.apps, launcher)
+    }),
+    KernelTest::new(Suite::Mpu, "grant_failure", |launcher, _| unsafe {
+        super::grant_failure_test::run_grant_failure(launcher.apps, launcher.alarm_driver, launcher)
+    }),
+    KernelTest::new(Suite::Mpu, "userspace_readable", |launcher, _| unsafe {
+        super::userspace_readable_test::run_userspace_readable(
+            launcher.apps,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "component_setup", |launcher, _| unsafe {
+        super::component_setup_test::run_component_setup(
+            launcher.mux_alarm,
+            launcher.uart_mux,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "static_allocation", |launcher, _| unsafe {
+        super::static_allocation_test::run_static_allocation(launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Peripherals, "chip_revision", |launcher, _| unsafe {
+        super::chip_revision_test::run_chip_revision(launcher)
+    }),
+    KernelTest::new(
+        Suite::Peripherals,
+        "flash_protection",
+        |launcher, _| unsafe {
+            super::flash_protection_test::run_flash_protection(
+                &launcher.peripherals.nrf52.nvmc,
+                &launcher.peripherals.acl,
+                launcher,
+            )
+        },
+    )
+    .with_nvmc(),
+    KernelTest::new(Suite::Peripherals, "ppi", |launcher, _| unsafe {
+        super::ppi_test::run_ppi(
+            &launcher.peripherals.gpio_port,
+            &launcher.peripherals.nrf52.timer2,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(
+        Suite::Peripherals,
+        "prescaler_matrix",
+        |launcher, _| unsafe {
+            super::prescaler_matrix_test::run_prescaler_matrix(
+                &launcher.peripherals.nrf52.rtc,
+                &launcher.peripherals.nrf52.timer2,
+                &launcher.peripherals.nrf52.timer1,
+                launcher.mux_alarm,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(Suite::Peripherals, "long_alarm", |launcher, _| unsafe {
+        super::long_alarm_test::run_long_alarm(launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Peripherals, "date_time", |launcher, _| unsafe {
+        super::date_time_test::run_date_time(launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Peripherals, "long_sleep", |launcher, _| unsafe {
+        super::long_sleep_test::run_long_sleep(launcher.sleep_monitor, launcher.mux_alarm, launcher)
+    })
+    .with_timeout(11 * 60 * 1000)
+    .slow(),
+    KernelTest::new(Suite::Mpu, "process_stats", |launcher, _| unsafe {
+        super::process_stats_test::run_process_stats(launcher.apps, launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Mpu, "priority_inversion", |launcher, _| unsafe {
+        super::priority_inversion_test::run_priority_inversion(
+            launcher.apps,
+            launcher.scheduler,
+            launcher.shared_lock,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "yield", |launcher, _| unsafe {
+        super::yield_test::run_yield(
+            launcher.apps,
+            launcher.report_driver,
+            launcher.syscall_recorder,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "upcall_order", |launcher, _| unsafe {
+        super::upcall_order_test::run_upcall_order(
+            launcher.apps,
+            launcher.report_driver,
+            launcher.second_report_driver,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "sleep", |launcher, _| unsafe {
+        super::sleep_test::run_sleep(
+            launcher.apps,
+            launcher.sleep_monitor,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "context_switch", |launcher, _| unsafe {
+        super::context_switch_test::run_context_switch(
+            launcher.apps,
+            launcher.switch_recorder,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "irq_latency", |launcher, _| unsafe {
+        super::irq_latency_test::run_irq_latency(
+            &launcher.peripherals.nrf52.timer2,
+            launcher.apps,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "deferred_call", |launcher, _| unsafe {
+        super::deferred_call_test::run_deferred_call(
+            &launcher.peripherals.nrf52.timer2,
+            launcher.apps,
+            launcher,
+        )
+    }),
+    KernelTest::new(
+        Suite::Peripherals,
+        "systick_conformance",
+        |launcher, _| unsafe {
+            super::scheduler_timer_conformance_test::run_systick_conformance(
+                &launcher.peripherals.nrf52.rtc,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(
+        Suite::Peripherals,
+        "virtual_scheduler_timer_conformance",
+        |launcher, _| unsafe {
+            super::scheduler_timer_conformance_test::run_virtual_scheduler_timer_conformance(
+                &launcher.peripherals.nrf52.rtc,
+                launcher.mux_alarm,
+                launcher,
+            )
+        },
+    ),
+    KernelTest::new(Suite::Mpu, "process_state", |launcher, _| unsafe {
+        super::process_state_test::run_process_state(
+            launcher.apps,
+            launcher.invariant_monitor,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "syscall_matrix", |launcher, _| unsafe {
+        super::syscall_matrix_test::run_syscall_matrix(
+            launcher.apps,
+            launcher.report_driver,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Mpu, "queue_fuzz", |launcher, test| unsafe {
+        super::queue_fuzz_test::run_queue_fuzz(launcher.seed.rng(test.name), launcher)
+    }),
+    KernelTest::new(Suite::Bench, "bench", |launcher, test| unsafe {
+        super::bench_test::run_bench(
+            launcher.scratch,
+            launcher.seed.rng(test.name),
+            launcher.apps,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Fault, "kernel_fault", |launcher, _| unsafe {
+        super::kernel_fault_test::run_kernel_fault(launcher)
+    }),
+    KernelTest::new(Suite::Radio, "energy_scan", |launcher, _| unsafe {
+        super::energy_scan_test::run_energy_scan(
+            &launcher.peripherals.ieee802154_radio,
+            launcher.scratch,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Radio, "radio_timing", |launcher, _| unsafe {
+        super::radio_timing_test::run_radio_timing(
+            &launcher.peripherals.ieee802154_radio,
+            launcher.scratch,
+            &launcher.peripherals.nrf52.timer2,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Radio, "mac_filter", |launcher, _| unsafe {
+        super::mac_filter_test::run_mac_filter(
+            &launcher.peripherals.ieee802154_radio,
+            launcher.scratch,
+            &launcher.peripherals.nrf52.timer2,
+            launcher.mux_alarm,
+            launcher,
+        )
+    })
+    // The responder waits up to a minute for the initiator.
+    .with_timeout(120_000),
+    KernelTest::new(Suite::Radio, "udp_smoke", |launcher, _| unsafe {
+        super::udp_smoke_test::run_udp_smoke(
+            &launcher.peripherals.ieee802154_radio,
+            &launcher.peripherals.nrf52.ecb,
+            launcher.mux_alarm,
+            launcher,
+        )
+    })
+    // As in `mac_filter`, the responder waits up to a minute.
+    .with_timeout(120_000),
+    KernelTest::new(Suite::Radio, "ble_scan", |launcher, _| unsafe {
+        super::ble_scan_test::run_ble_scan(
+            &launcher.peripherals.nrf52.ble_radio,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Peripherals, "ctap", |launcher, _| unsafe {
+        super::ctap_test::run_ctap(launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Peripherals, "keyboard_hid", |launcher, _| unsafe {
+        super::keyboard_hid_test::run_keyboard_hid(
+            &launcher.peripherals.usbd,
+            &launcher.peripherals.nrf52.pwr_clk,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Peripherals, "wiretap", |launcher, _| unsafe {
+        super::wiretap_test::run_wiretap(
+            &launcher.peripherals.nrf52.spim2,
+            &launcher.peripherals.gpio_port,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+    KernelTest::new(Suite::Peripherals, "sx127x_replay", |launcher, _| unsafe {
+        super::sx127x_replay_test::run_sx127x_replay(launcher.mux_alarm, launcher)
+    }),
+    KernelTest::new(Suite::Peripherals, "current", |launcher, _| unsafe {
+        super::current_test::run_current(
+            launcher.apps,
+            &launcher.peripherals.nrf52.twi1,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+];
+
+// Resets the board, so it must stay the last test.
+register_kernel_tests![
+    last: KernelTest::new(Suite::Peripherals, "watchdog", |launcher, _| unsafe {
+        super::watchdog_test::run_watchdog(
+            launcher.apps,
+            launcher.watchdog,
+            &launcher.peripherals.nrf52.pwr_clk,
+            super::config::BOARD_TEST_CONFIG.watchdog_timeout_ms,
+            launcher.mux_alarm,
+            launcher,
+        )
+    }),
+];
+
+extern "C" {
+    /// Beginning of the `.kernel_tests` section.
+    static _skernel_tests: u8;
+    /// Beginning of the tests registered with `last:`.
+    static _skernel_tests_last: u8;
+    /// End of the `.kernel_tests` section.
+    static _ekernel_tests: u8;
+}
+
+/// The entries of the section between `start` and `end`.
+///
+/// # Safety
+///
+/// `register_kernel_tests!` must have placed only entries of this type
+/// between the two, which the linker script keeps in flash.
+unsafe fn entries(start: *const u8, end: *const u8) -> &'static [Option<KernelTest>] {
+    core::slice::from_raw_parts(
+        start.cast::<Option<KernelTest>>(),
+        end.offset_from(start) as usize / core::mem::size_of::<Option<KernelTest>>(),
+    )
+}
+
+/// The entries of the tests the launcher runs first, and of those it runs
+/// last.
+fn sections() -> (&'static [Option<KernelTest>], &'static [Option<KernelTest>]) {
+    // SAFETY: the linker script places the `.kernel_tests` and
+    // `.kernel_tests.last` sections, which only `register_kernel_tests!`
+    // places entries in, between these symbols.
+    unsafe {
+        let start = core::ptr::addr_of!(_skernel_tests);
+        let last = core::ptr::addr_of!(_skernel_tests_last);
+        let end = core::ptr::addr_of!(_ekernel_tests);
+        (entries(start, last), entries(last, end))
+    }
+}
+
+/// The tests of `entries`, sorted by name. The flash has no room to sort them
+/// in, so each step looks for the test that follows the one before. Tests with
+/// the same name, which the board refuses to run, follow in the order of
+/// their entries.
+fn by_name(entries: &'static [Option<KernelTest>]) -> impl Iterator<Item = &'static KernelTest> {
+    let key = |test: &'static KernelTest| (test.name, core::ptr::from_ref(test));
+    let mut previous = None;
+    core::iter::from_fn(move || {
+        let next = entries
+            .iter()
+            .flatten()
+            .filter(|test| previous.is_none_or(|previous| key(test) > previous))
+            .min_by_key(|test| key(test))?;
+        previous = Some(key(next));
+        Some(next)
+    })
+}
+
+/// The tests the launcher runs, sorted by name, and those registered with
+/// `last:` after the others.
+pub(crate) fn tests() -> impl Iterator<Item = &'static KernelTest> {
+    let (tests, last) = sections();
+    by_name(tests).chain(by_name(last))
+}
+
+/// Test `index` of the ones the launcher runs.
+pub(crate) fn test(index: usize) -> Option<&'static KernelTest> {
+    tests().nth(index)
+}
+
+/// Number of tests the launcher runs. The watchdog test, if its suite is
+/// enabled, is the last.
+pub(crate) fn test_count() -> usize {
+    let (tests, last) = sections();
+    tests.iter().chain(last).flatten().count()
+}
+
+/// Whether `a` and `b` are the same name, usable in constants.
+// Only called from the constants of `register_kernel_tests!`, which the
+// dead code lint does not look into.
+#[allow(dead_code)]
+const fn same_name(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Fails the build if two of `tests` have the same name, for
+/// `register_kernel_tests!`.
+// As for `same_name()`.
+#[allow(dead_code)]
+pub(crate) const fn check_unique_names(tests: &[KernelTest]) {
+    let mut i = 0;
+    while i < tests.len() {
+        let mut j = i + 1;
+        while j < tests.len() {
+            if same_name(tests[i].name, tests[j].name) {
+                // Constants can only panic with a plain string, so the build
+                // error gives just the name that is used twice.
+                panic!("{}", tests[i].name);
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Panics if two tests have the same name, or if no suite is enabled, which
+/// the build cannot check for tests registered by different
+/// `register_kernel_tests!`.
+pub(crate) fn check_tests() {
+    assert!(test_count() != 0, "no test suite is enabled");
+    let mut previous: Option<&str> = None;
+    for test in tests() {
+        if previous == Some(test.name) {
+            panic!("two tests are named {}", test.name);
+        }
+        previous = Some(test.name);
+    }
+    let (tests, last) = sections();
+    for test in last.iter().flatten() {
+        if tests.iter().flatten().any(|other| other.name == test.name) {
+            panic!("two tests are named {}", test.name);
+        }
+    }
+}
+
+/// Skips a test that cannot run on this board, after printing `reason`,
+/// which starts with the prefix of the test, as in
+/// `Ppi: no loopback pins configured, skipping`. The launcher reports the
+/// test skipped, with the reason after the prefix.
+pub(crate) fn skip(client: &'static dyn CapsuleTestClient, reason: &'static str) {
+    debug!("{}, skipping", reason);
+    // SAFETY: the launcher is set once, before the tests run.
+    if let Some(launcher) = unsafe { crate::TEST_LAUNCHER } {
+        let (_, after_prefix) = reason.split_once(':').unwrap_or(("", reason));
+        launcher.skip(after_prefix.strip_prefix(' ').unwrap_or(after_prefix));
+    }
+    client.done(Ok(()));
+}
+
+/// Reports the `result` of a test to its `client`, after printing
+/// `<prefix>: all cases passed` if it passed.
+pub(crate) fn report(
+    client: &OptionalCell<&'static dyn CapsuleTestClient>,
+    prefix: &str,
+    result: Result<(), CapsuleTestError>,
+) {
+    if result.is_ok() {
+        debug!("{}: all cases passed", prefix);
+    }
+    client.map(|client| client.done(result));
+}
@@ -0,0 +1,103 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A driver embedded apps report to, and that gives them upcalls on their
+//! request or on the request of a test. The board provides two instances, so
+//! tests can check how upcalls from different capsules interleave.
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::process::ProcessId;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::ErrorCode;
+
+/// Number of reports the driver records.
+const MAX_REPORTS: usize = 8;
+
+pub(crate) type ReportGrant = Grant<(), UpcallCount<2>, AllowRoCount<0>, AllowRwCount<0>>;
+
+/// ### Command
+///
+/// - `0`: Driver existence check.
+/// - `1`: Record the two arguments as a report.
+/// - `2`: Schedule upcall 0 for the calling process, with the first argument
+///   as the first upcall argument.
+///
+/// ### Subscribe
+///
+/// - `0` and `1`: Upcalls on request of the process or of a test.
+pub(crate) struct ReportDriver {
+    grant: ReportGrant,
+    reports: [Cell<(usize, usize)>; MAX_REPORTS],
+    report_count: Cell<usize>,
+}
+
+impl ReportDriver {
+    pub fn new(grant: ReportGrant) -> Self {
+        ReportDriver {
+            grant,
+            reports: Default::default(),
+            report_count: Cell::new(0),
+        }
+    }
+
+    /// Returns the reports recorded so far.
+    pub fn reports(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.reports[..self.report_count.get()]
+            .iter()
+            .map(|report| report.get())
+    }
+
+    /// Forgets the reports of an earlier test.
+    pub fn reset(&self) {
+        self.report_count.set(0);
+    }
+
+    /// Schedules upcall `subscribe_num` for `processid`, with `value` as its
+    /// first argument.
+    pub fn notify(
+        &self,
+        processid: ProcessId,
+        subscribe_num: usize,
+        value: usize,
+    ) -> Result<(), ErrorCode> {
+        self.grant
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(subscribe_num, (value, 0, 0))
+                    .map_err(|_| ErrorCode::FAIL)
+            })
+            .map_err(ErrorCode::from)?
+    }
+}
+
+impl SyscallDriver for ReportDriver {
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                let count = self.report_count.get();
+                if count == MAX_REPORTS {
+                    return CommandReturn::failure(ErrorCode::NOMEM);
+                }
+                self.reports[count].set((arg1, arg2));
+                self.report_count.set(count + 1);
+                CommandReturn::success()
+            }
+            2 => self.notify(processid, 0, arg1).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.grant.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,381 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! How the launcher reports the suite and the tests it runs.
+//!
+//! The launcher tells a [`TestReporter`] when the suite starts, when each
+//! test starts and what its result is, and when the suite is complete. The
+//! reporter writes these out in its own format, so another output backend
+//! is a new implementation, without changes to the launcher. [`reporter`]
+//! returns the one for the `result_format` of the board test configuration.
+//!
+//! [`LauncherReporter`] prints the lines the kernel test runner keys its
+//! results on:
+//!
+//! ```text
+//! Test suite: 51 tests
+//! Test 0: aes128_ctr
+//! Test 0 passed (1840 us)
+//! Test 1: sha256
+//! Test 1 failed (412 us)
+//! All tests finished.
+//! ```
+//!
+//! The time in brackets is how long the core ran for the test, by the
+//! `TestTimer`. The result of a test that panicked has none. A test the
+//! registry expects to fail ends with `Test <index> xfail (<time>): <reason>`
+//! when it fails and `Test <index> xpass (<time>): <reason>` when it passes.
+//! A test that cannot run on this board ends with
+//! `Test <index> skipped (<time>): <reason>`.
+//!
+//! [`TapReporter`] prints TAP 14 instead, so any TAP consumer can read the
+//! results. Tests are numbered from one, and named as in the registry:
+//!
+//! ```text
+//! TAP version 14
+//! 1..51
+//! Test 0: aes128_ctr
+//! ok 1 - aes128_ctr
+//! Test 1: sha256
+//! not ok 2 - sha256
+//! All tests finished.
+//! ```
+//!
+//! The result of a test expected to fail carries a TODO directive with the
+//! reason, as `not ok 3 - nvmc_erase # TODO issue #123`, so TAP consumers
+//! count neither its failure nor its pass against the suite. A skipped test
+//! carries a SKIP directive instead, as `ok 4 - ppi # SKIP no loopback pins
+//! configured`.
+//!
+//! It keeps the lines that start a test and end the suite, which the runner
+//! reads and TAP consumers pass over, as they do the other lines the launcher
+//! and the tests print. After a reset that continues the run, the board
+//! prints neither the version nor the plan again, so the results that follow
+//! continue the same document and keep their numbers.
+//!
+//! [`JsonReporter`] prints one JSON object per line for each event, for CI
+//! tools that read JSON lines:
+//!
+//! ```text
+//! {"event":"suite_start","tests":51}
+//! {"event":"test_start","index":0,"name":"aes128_ctr"}
+//! {"event":"test_result","index":0,"name":"aes128_ctr","result":"pass","duration_ms":312,"core_us":1840}
+//! {"event":"test_start","index":1,"name":"sha256"}
+//! {"event":"test_result","index":1,"name":"sha256","result":"fail","duration_ms":60000,"core_us":412,"failure":"no result after 60000 ms"}
+//! {"event":"suite_complete"}
+//! ```
+//!
+//! After a reset that continues the run, `suite_start` has `"resumed":true`.
+//! A test that panicked has no `duration_ms` or `core_us`. A test expected to
+//! fail has the result `xfail` or `xpass` instead, and the reason in
+//! `expected_failure`. A skipped test has the result `skip`, and the reason
+//! in `skip_reason`. The tools skip the other lines the launcher and the
+//! tests print, which are not JSON objects. Strings are escaped, as the
+//! reasons the registry gives for expected failures are free text. The
+//! kernel test runner reads the first two formats, not this one.
+
+use core::fmt;
+
+use capsules_core::test::capsule_test::CapsuleTestError;
+use kernel::debug;
+
+use crate::test::config::{ResultFormat, BOARD_TEST_CONFIG};
+use crate::test::registry;
+
+/// How a test that did not fail ended.
+#[derive(Clone, Copy)]
+pub(crate) enum Pass {
+    /// The test ran its cases.
+    Ran,
+    /// The test cannot run on this board, for the reason given.
+    Skipped(&'static str),
+}
+
+/// Why a test failed.
+pub(crate) enum Failure {
+    /// The test reported the error.
+    Test(CapsuleTestError),
+    /// The test broke this many kernel invariants.
+    Invariants(usize),
+    /// The test wrote outside its scratch buffers.
+    ScratchOverrun,
+    /// The test did not report its result within this many milliseconds.
+    Timeout(u32),
+    /// The kernel panicked while the test ran.
+    Panic,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Test(CapsuleTestError::IncorrectResult) => f.write_str("incorrect result"),
+            Failure::Test(CapsuleTestError::ErrorCode(error)) => write!(f, "error {:?}", error),
+            Failure::Invariants(count) => write!(f, "{} kernel invariants violated", count),
+            Failure::ScratchOverrun => f.write_str("scratch memory written outside its buffers"),
+            Failure::Timeout(timeout_ms) => write!(f, "no result after {} ms", timeout_ms),
+            Failure::Panic => f.write_str("the kernel panicked"),
+        }
+    }
+}
+
+/// How long a test ran.
+#[derive(Clone, Copy)]
+pub(crate) struct Duration {
+    /// Milliseconds from its start to its result, by the RTC.
+    pub ms: u32,
+    /// Microseconds the core ran for it, by the cycle counter.
+    pub core_us: u32,
+}
+
+/// Writes out the progress and results of a run.
+pub(crate) trait TestReporter {
+    /// The launcher runs `count` tests, or, if `resumed`, those of them after
+    /// the test a reset interrupted.
+    fn suite_start(&self, count: usize, resumed: bool);
+
+    /// Test `index` starts.
+    fn test_start(&self, index: usize, name: &str);
+
+    /// Test `index` finished with `result`, after `duration` if the launcher
+    /// timed it. `expected_failure` is why the registry expects the
+    /// test to fail, if it does.
+    fn test_result(
+        &self,
+        index: usize,
+        name: &str,
+        expected_failure: Option<&str>,
+        result: Result<Pass, Failure>,
+        duration: Option<Duration>,
+    );
+
+    /// The launcher ran the last test.
+    fn suite_complete(&self);
+}
+
+/// The reporter for the `result_format` of the board test configuration.
+pub(crate) fn reporter() -> &'static dyn TestReporter {
+    match BOARD_TEST_CONFIG.result_format {
+        ResultFormat::Launcher => &LauncherReporter,
+        ResultFormat::Tap => &TapReporter,
+        ResultFormat::JsonLines => &JsonReporter,
+    }
+}
+
+/// Reports the result of test `index`, by the name the registry gives it.
+pub(crate) fn report_result(
+    index: usize,
+    result: Result<Pass, Failure>,
+    duration: Option<Duration>,
+) {
+    let test = registry::test(index);
+    let name = test.map_or("", |test| test.name);
+    let expected_failure = test.and_then(|test| test.expected_failure);
+    reporter().test_result(index, name, expected_failure, result, duration);
+}
+
+/// Prints the lines the kernel test runner reads.
+pub(crate) struct LauncherReporter;
+
+impl TestReporter for LauncherReporter {
+    fn suite_start(&self, count: usize, _resumed: bool) {
+        debug!("Test suite: {} tests", count);
+    }
+
+    fn test_start(&self, index: usize, name: &str) {
+        debug!("Test {}: {}", index, name);
+    }
+
+    fn test_result(
+        &self,
+        index: usize,
+        _name: &str,
+        expected_failure: Option<&str>,
+        result: Result<Pass, Failure>,
+        duration: Option<Duration>,
+    ) {
+        // The launcher printed why the test failed already.
+        let (outcome, reason) = match (result, expected_failure) {
+            (Ok(Pass::Skipped(reason)), _) => ("skipped", Some(reason)),
+            (Ok(Pass::Ran), None) => ("passed", None),
+            (Err(_), None) => ("failed", None),
+            (Ok(Pass::Ran), Some(reason)) => ("xpass", Some(reason)),
+            (Err(_), Some(reason)) => ("xfail", Some(reason)),
+        };
+        let core_us = CoreTime(duration.map(|duration| duration.core_us));
+        debug!(
+            "Test {} {}{}{}",
+            index,
+            outcome,
+            core_us,
+            Suffix(": ", reason)
+        );
+    }
+
+    fn suite_complete(&self) {
+        debug!("All tests finished.");
+    }
+}
+
+/// Text printed after `separator`, if there is any.
+struct Suffix<'a>(&'static str, Option<&'a str>);
+
+impl fmt::Display for Suffix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.1 {
+            Some(text) => write!(f, "{}{}", self.0, text),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The time the core ran for a test, printed in brackets after its result,
+/// if the launcher timed it.
+struct CoreTime(Option<u32>);
+
+impl fmt::Display for CoreTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(core_us) => write!(f, " ({} us)", core_us),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Prints the results as TAP 14.
+pub(crate) struct TapReporter;
+
+impl TestReporter for TapReporter {
+    fn suite_start(&self, count: usize, resumed: bool) {
+        if !resumed {
+            debug!("TAP version 14");
+            debug!("1..{}", count);
+        }
+    }
+
+    fn test_start(&self, index: usize, name: &str) {
+        LauncherReporter.test_start(index, name);
+    }
+
+    fn test_result(
+        &self,
+        index: usize,
+        name: &str,
+        expected_failure: Option<&str>,
+        result: Result<Pass, Failure>,
+        _duration: Option<Duration>,
+    ) {
+        let status = if result.is_ok() { "ok" } else { "not ok" };
+        let directive = match (result, expected_failure) {
+            (Ok(Pass::Skipped(reason)), _) => Suffix(" # SKIP ", Some(reason)),
+            (_, reason) => Suffix(" # TODO ", reason),
+        };
+        debug!("{} {} - {}{}", status, index + 1, name, directive);
+    }
+
+    fn suite_complete(&self) {
+        LauncherReporter.suite_complete();
+    }
+}
+
+/// Prints one JSON object per event.
+pub(crate) struct JsonReporter;
+
+/// A member of a JSON object, printed with the comma before it if it has a
+/// value, and left out if not.
+struct Member<T>(&'static str, Option<T>);
+
+impl<T: fmt::Display> fmt::Display for Member<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.1 {
+            Some(value) => write!(f, ",\"{}\":{}", self.0, value),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A JSON string, with the quotes, backslashes and control characters of
+/// the value escaped.
+struct Quoted<T>(T);
+
+impl<T: fmt::Display> fmt::Display for Quoted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\"")?;
+        fmt::write(&mut Escaped(f), format_args!("{}", self.0))?;
+        f.write_str("\"")
+    }
+}
+
+/// Writes text into a JSON string.
+struct Escaped<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl fmt::Write for Escaped<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if c < ' ' => write!(self.0, "\\u{:04x}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TestReporter for JsonReporter {
+    fn suite_start(&self, count: usize, resumed: bool) {
+        debug!(
+            "{{\"event\":\"suite_start\",\"tests\":{}{}}}",
+            count,
+            Member("resumed", resumed.then_some(true))
+        );
+    }
+
+    fn test_start(&self, index: usize, name: &str) {
+        debug!(
+            "{{\"event\":\"test_start\",\"index\":{},\"name\":{}}}",
+            index,
+            Quoted(name)
+        );
+    }
+
+    fn test_result(
+        &self,
+        index: usize,
+        name: &str,
+        expected_failure: Option<&str>,
+        result: Result<Pass, Failure>,
+        duration: Option<Duration>,
+    ) {
+        let outcome = match (&result, expected_failure.is_some()) {
+            (Ok(Pass::Skipped(_)), _) => "skip",
+            (Ok(Pass::Ran), false) => "pass",
+            (Err(_), false) => "fail",
+            (Ok(Pass::Ran), true) => "xpass",
+            (Err(_), true) => "xfail",
+        };
+        let skip_reason = match result {
+            Ok(Pass::Skipped(reason)) => Some(reason),
+            _ => None,
+        };
+        debug!(
+            "{{\"event\":\"test_result\",\"index\":{},\"name\":{},\"result\":\"{}\"{}{}{}{}{}}}",
+            index,
+            Quoted(name),
+            outcome,
+            Member("duration_ms", duration.map(|duration| duration.ms)),
+            Member("core_us", duration.map(|duration| duration.core_us)),
+            Member("failure", result.err().map(Quoted)),
+            Member("expected_failure", expected_failure.map(Quoted)),
+            Member("skip_reason", skip_reason.map(Quoted)),
+        );
+    }
+
+    fn suite_complete(&self) {
+        debug!("{{\"event\":\"suite_complete\"}}");
+    }
+}
@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A scheduler tests can switch between round robin and fixed priority
+//! scheduling while the kernel runs. The board starts with round robin.
+
+use core::cell::Cell;
+
+use kernel::platform::chip::Chip;
+use kernel::process::{ProcessId, StoppedExecutingReason};
+use kernel::scheduler::priority::PrioritySched;
+use kernel::scheduler::round_robin::RoundRobinSched;
+use kernel::scheduler::{Scheduler, SchedulingDecision};
+use kernel::utilities::cells::OptionalCell;
+
+pub struct TestScheduler {
+    round_robin: &'static RoundRobinSched<'static>,
+    priority: &'static PrioritySched,
+    /// Whether tests asked for priority scheduling.
+    use_priority: Cell<bool>,
+    /// Whether the priority scheduler chose the process that is running, so
+    /// switching while a process runs reports its result to the scheduler
+    /// that chose it.
+    priority_running: Cell<bool>,
+    /// The process of the last decision to run a process.
+    chosen: OptionalCell<ProcessId>,
+}
+
+impl TestScheduler {
+    pub fn new(
+        round_robin: &'static RoundRobinSched<'static>,
+        priority: &'static PrioritySched,
+    ) -> Self {
+        TestScheduler {
+            round_robin,
+            priority,
+            use_priority: Cell::new(false),
+            priority_running: Cell::new(false),
+            chosen: OptionalCell::empty(),
+        }
+    }
+
+    /// Selects priority scheduling if `priority` is true and round robin
+    /// scheduling otherwise, from the next scheduling decision on.
+    pub fn set_priority(&self, priority: bool) {
+        self.use_priority.set(priority);
+    }
+
+    /// Returns the process the scheduler last decided to run.
+    pub fn chosen(&self) -> Option<ProcessId> {
+        self.chosen.get()
+    }
+}
+
+impl<C: Chip> Scheduler<C> for TestScheduler {
+    fn next(&self) -> SchedulingDecision {
+        self.priority_running.set(self.use_priority.get());
+        let decision = if self.use_priority.get() {
+            Scheduler::<C>::next(self.priority)
+        } else {
+            Scheduler::<C>::next(self.round_robin)
+        };
+        if let SchedulingDecision::RunProcess((processid, _)) = decision {
+            self.chosen.set(processid);
+        }
+        decision
+    }
+
+    fn result(&self, result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        if self.priority_running.get() {
+            Scheduler::<C>::result(self.priority, result, execution_time_us)
+        } else {
+            Scheduler::<C>::result(self.round_robin, result, execution_time_us)
+        }
+    }
+
+    unsafe fn execute_kernel_work(&self, chip: &C) {
+        if self.use_priority.get() {
+            self.priority.execute_kernel_work(chip)
+        } else {
+            self.round_robin.execute_kernel_work(chip)
+        }
+    }
+
+    unsafe fn do_kernel_work_now(&self, chip: &C) -> bool {
+        if self.use_priority.get() {
+            self.priority.do_kernel_work_now(chip)
+        } else {
+            self.round_robin.do_kernel_work_now(chip)
+        }
+    }
+
+    unsafe fn continue_process(&self, id: ProcessId, chip: &C) -> bool {
+        if self.priority_running.get() {
+            self.priority.continue_process(id, chip)
+        } else {
+            self.round_robin.continue_process(id, chip)
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the `SchedulerTimer` conformance cases on the SysTick the board uses
+//! and on a `VirtualSchedulerTimer` on the RTC, the implementation for chips
+//! without a dedicated timer. Both are timed against the RTC.
+//!
+//! The expected output ends with
+//! SchedulerTimerConformance: all cases passed
+//! for each of them.
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::test::conformance::scheduler_timer::TestSchedulerTimerConformance;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use cortexm4::systick::SysTick;
+use kernel::debug;
+use kernel::platform::scheduler_timer::VirtualSchedulerTimer;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::new_alarm;
+
+type RtcAlarm = VirtualMuxAlarm<'static, Rtc<'static>>;
+
+pub unsafe fn run_systick_conformance(
+    rtc: &'static Rtc<'static>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    debug!("SchedulerTimerConformance: running on SysTick");
+    // The SysTick of the platform refers to the same hardware, which the
+    // kernel leaves alone while no process runs.
+    let systick = static_init!(SysTick, SysTick::new_with_calibration(64000000));
+    let test = static_init!(
+        TestSchedulerTimerConformance<'static, SysTick, Rtc<'static>>,
+        TestSchedulerTimerConformance::new(systick, rtc)
+    );
+    test.set_client(client);
+    test.run();
+}
+
+pub unsafe fn run_virtual_scheduler_timer_conformance(
+    rtc: &'static Rtc<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    debug!("SchedulerTimerConformance: running on VirtualSchedulerTimer");
+    let alarm = new_alarm(mux_alarm, static_buf!(RtcAlarm));
+    let timer = static_init!(
+        VirtualSchedulerTimer<RtcAlarm>,
+        VirtualSchedulerTimer::new(alarm)
+    );
+    let test = static_init!(
+        TestSchedulerTimerConformance<'static, VirtualSchedulerTimer<RtcAlarm>, Rtc<'static>>,
+        TestSchedulerTimerConformance::new(timer, rtc)
+    );
+    test.set_client(client);
+    test.run();
+}
@@ -0,0 +1,111 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Scratch memory tests take their temporary buffers from, instead of each
+//! test holding static buffers of its own that later tests could reach.
+//!
+//! `ScratchArena` hands out zeroed buffers from one region, one after the
+//! other, and the test launcher frees all of them at once when a test
+//! finishes. Every buffer is followed by a canary, and memory that is not
+//! handed out holds the canary pattern as well. Before freeing the buffers,
+//! the arena checks that the pattern is intact, so a test that writes past
+//! a buffer, or that keeps using a buffer it got from the arena after it
+//! finished, shows up as a failure. A write through a stale buffer that
+//! lands in a buffer of a later test is not detected.
+//!
+//! ```rust,ignore
+//! let data = scratch.buffer(64);
+//! let output: &'static mut [u8; 32] = scratch.array();
+//! ```
+
+use core::cell::Cell;
+
+/// Byte the canaries and the free memory hold.
+const CANARY: u8 = 0xA5;
+
+/// Length of the canary after each buffer. Buffers start at offsets that are
+/// multiples of it, so they stay word aligned for DMA.
+const CANARY_LEN: usize = 4;
+
+/// Number of buffers the arena hands out between resets.
+const MAX_BUFFERS: usize = 16;
+
+pub struct ScratchArena {
+    start: *mut u8,
+    len: usize,
+    /// Number of bytes handed out, including canaries.
+    used: Cell<usize>,
+    /// Offset of the canary after each buffer handed out.
+    canaries: [Cell<usize>; MAX_BUFFERS],
+    buffer_count: Cell<usize>,
+}
+
+impl ScratchArena {
+    /// Hands out `memory`, which is made of words so buffers are word aligned.
+    pub fn new(memory: &'static mut [u32]) -> Self {
+        memory.fill(u32::from_ne_bytes([CANARY; 4]));
+        ScratchArena {
+            start: memory.as_mut_ptr().cast(),
+            len: memory.len() * 4,
+            used: Cell::new(0),
+            canaries: [const { Cell::new(0) }; MAX_BUFFERS],
+            buffer_count: Cell::new(0),
+        }
+    }
+
+    /// Returns a zeroed buffer of `len` bytes, valid until the running test
+    /// finishes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the arena has no room left for the buffer.
+    pub fn buffer(&self, len: usize) -> &'static mut [u8] {
+        let offset = self.used.get();
+        let count = self.buffer_count.get();
+        let canary = offset + len;
+        let end = (canary + CANARY_LEN).next_multiple_of(CANARY_LEN);
+        if count == MAX_BUFFERS || end > self.len {
+            panic!("Scratch arena exhausted by a buffer of {} bytes", len);
+        }
+        self.canaries[count].set(canary);
+        self.buffer_count.set(count + 1);
+        self.used.set(end);
+
+        // SAFETY: the range is within the arena and not part of any buffer
+        // handed out since the last reset. Buffers handed out before it are
+        // no longer valid.
+        let buffer = unsafe { core::slice::from_raw_parts_mut(self.start.add(offset), len) };
+        buffer.fill(0);
+        buffer
+    }
+
+    /// Like `buffer()`, for capsules that take arrays.
+    pub fn array<const N: usize>(&self) -> &'static mut [u8; N] {
+        self.buffer(N).try_into().unwrap()
+    }
+
+    /// Checks the canaries and the free memory, then frees every buffer.
+    /// Returns the number of canaries, counting the free memory as one, that
+    /// were overwritten.
+    pub fn reset(&self) -> usize {
+        // SAFETY: tests must not use their buffers once they finish, which is
+        // what the canaries check.
+        let memory = unsafe { core::slice::from_raw_parts_mut(self.start, self.len) };
+        let intact = |range: &[u8]| range.iter().all(|byte| *byte == CANARY);
+
+        let count = self.buffer_count.replace(0);
+        let overwritten = self.canaries[..count]
+            .iter()
+            .filter(|canary| {
+                let start = canary.get();
+                !intact(&memory[start..start + CANARY_LEN])
+            })
+            .count()
+            + usize::from(!intact(&memory[self.used.get()..]));
+
+        memory.fill(CANARY);
+        self.used.set(0);
+        overwritten
+    }
+}
@@ -0,0 +1,74 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the screen test on the ST7735 driver over a capture bus with no
+//! display attached, so no wiring is needed.
+//!
+//! The expected output ends with
+//! ScreenTest: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::bus::{self, BusAddr8};
+use capsules_extra::st77xx::{SendCommand, ST77XX};
+use capsules_extra::test::screen::{CaptureBus, TestScreen};
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::time::Alarm;
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::GPIOPin;
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+
+/// ST77xx memory write command, after which the pixel data follows.
+const WRITE_RAM: u64 = 0x2C;
+
+/// Write chunk size, a whole number of RGB565 pixels.
+const CHUNK_SIZE: usize = 512;
+
+type Capture = CaptureBus<'static, BusAddr8>;
+type Screen = ST77XX<'static, VirtualMuxAlarm<'static, Rtc<'static>>, Capture, GPIOPin<'static>>;
+
+pub unsafe fn run_screen(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    // The ST77xx driver swaps the bytes of each RGB565 pixel before sending.
+    let capture = static_init!(Capture, CaptureBus::new(None, WRITE_RAM, true));
+    capture.register();
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let buffer = static_init!(
+        [u8; capsules_extra::st77xx::BUFFER_SIZE],
+        [0; capsules_extra::st77xx::BUFFER_SIZE]
+    );
+    let sequence_buffer = static_init!(
+        [SendCommand; capsules_extra::st77xx::SEQUENCE_BUFFER_SIZE],
+        [SendCommand::Nop; capsules_extra::st77xx::SEQUENCE_BUFFER_SIZE]
+    );
+    let screen = static_init!(
+        Screen,
+        ST77XX::new(
+            capture,
+            alarm,
+            None,
+            None,
+            buffer,
+            sequence_buffer,
+            &capsules_extra::st77xx::ST7735
+        )
+    );
+    bus::Bus::set_client(capture, screen);
+    alarm.set_alarm_client(screen);
+
+    let chunk = static_init!([u8; CHUNK_SIZE], [0; CHUNK_SIZE]);
+    let test = static_init!(
+        TestScreen<'static, Screen>,
+        TestScreen::new(screen, capture, chunk)
+    );
+    test.set_client(client);
+    test.run();
+    let _ = screen.init();
+}
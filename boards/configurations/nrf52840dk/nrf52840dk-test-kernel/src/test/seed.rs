@@ -0,0 +1,338 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Chooses the seed of the tests that draw pseudo-random data from a
+//! `kernel::test::rng::TestRng`.
+//!
+//! Before the first test runs, `TestSeed` takes a seed from the TRNG and
+//! prints it:
+//!
+//! ```text
+//! Test seed: 0x0123456789abcdef
+//! ```
+//!
+//! For `COMMAND_WINDOW_MS` after that, the seed can be replaced over the UART
+//! by sending a line
+//!
+//! ```text
+//! seed 0123456789abcdef
+//! ```
+//!
+//! The same window takes the `update` command of `update::Updater`, which
+//! replaces the kernel instead of running the tests.
+//!
+//! Every test gets a generator of its own, seeded from the seed and the name
+//! of the test, so it sees the same data whether or not the tests before it
+//! ran, and when tests are added or reordered. The test launcher prints the
+//! seed again when a test that took a generator fails, which is all it takes
+//! to run that test with the same data again.
+
+use core::cell::Cell;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use kernel::debug;
+use kernel::hil::entropy::{Client32, Continue, Entropy32};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::hil::uart::{self, Receive};
+use kernel::test::rng::TestRng;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::update::Updater;
+
+/// Time after the TRNG seed is printed during which the UART can replace it.
+const COMMAND_WINDOW_MS: u32 = 1000;
+
+const COMMAND: &[u8] = b"seed ";
+const UPDATE_COMMAND: &[u8] = b"update ";
+
+/// Longest command line kept; longer lines are ignored.
+const LINE_LEN: usize = 32;
+
+/// FNV-1a hash of `name`, which sets the seeds of the tests apart.
+fn name_hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Receives the callback once the seed is chosen.
+pub trait TestSeedClient {
+    fn seed_chosen(&'static self);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Unchosen,
+    /// Waiting for the TRNG, then for a command.
+    Choosing,
+    Chosen,
+}
+
+pub unsafe fn new_test_seed(
+    trng: &'static dyn Entropy32<'static>,
+    uart_mux: &'static MuxUart<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    updater: &'static Updater,
+) -> &'static TestSeed {
+    let uart = static_init!(UartDevice<'static>, UartDevice::new(uart_mux, true));
+    uart.setup();
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let rx_buffer = static_init!([u8; 1], [0; 1]);
+    let seed = static_init!(
+        TestSeed,
+        TestSeed::new(trng, uart, alarm, rx_buffer, updater)
+    );
+    trng.set_client(seed);
+    uart.set_receive_client(seed);
+    alarm.set_alarm_client(seed);
+    seed
+}
+
+pub struct TestSeed {
+    trng: &'static dyn Entropy32<'static>,
+    uart: &'static UartDevice<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    updater: &'static Updater,
+    line: Cell<[u8; LINE_LEN]>,
+    /// Length of the line received so far, more than `LINE_LEN` if it is
+    /// too long.
+    line_len: Cell<usize>,
+    seed: Cell<u64>,
+    /// Number of TRNG words in `seed`.
+    words: Cell<usize>,
+    state: Cell<State>,
+    /// Whether the window for a command is open.
+    listening: Cell<bool>,
+    /// Whether a generator was handed out since the last call to `take_used`.
+    used: Cell<bool>,
+    client: OptionalCell<&'static dyn TestSeedClient>,
+}
+
+impl TestSeed {
+    pub fn new(
+        trng: &'static dyn Entropy32<'static>,
+        uart: &'static UartDevice<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        rx_buffer: &'static mut [u8],
+        updater: &'static Updater,
+    ) -> Self {
+        TestSeed {
+            trng,
+            uart,
+            alarm,
+            rx_buffer: TakeCell::new(rx_buffer),
+            updater,
+            line: Cell::new([0; LINE_LEN]),
+            line_len: Cell::new(0),
+            seed: Cell::new(0),
+            words: Cell::new(0),
+            state: Cell::new(State::Unchosen),
+            listening: Cell::new(false),
+            used: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Returns whether the seed is chosen. If it is not, starts choosing it
+    /// and calls `client` once it is.
+    pub fn chosen(&self, client: &'static dyn TestSeedClient) -> bool {
+        match self.state.get() {
+            State::Chosen => return true,
+            State::Choosing => {}
+            State::Unchosen => {
+                self.state.set(State::Choosing);
+                if let Err(error) = self.trng.get() {
+                    debug!("Test seed: TRNG failed ({:?}), using 0", error);
+                    self.listen();
+                }
+            }
+        }
+        self.client.set(client);
+        false
+    }
+
+    /// Returns the generator of the test named `name`.
+    pub fn rng(&self, name: &str) -> TestRng {
+        self.used.set(true);
+        TestRng::new(self.seed.get().wrapping_add(name_hash(name)))
+    }
+
+    /// Returns the seed if a test took a generator since the last call.
+    pub fn take_used(&self) -> Option<u64> {
+        self.used.replace(false).then(|| self.seed.get())
+    }
+
+    /// Prints the seed and opens the window for a command to replace it.
+    fn listen(&self) {
+        debug!("Test seed: {:#018x}", self.seed.get());
+        self.listening.set(true);
+        self.rx_buffer.take().map(|buffer| {
+            if let Err((error, buffer)) = self.uart.receive_buffer(buffer, 1) {
+                debug!("Test seed: cannot receive commands ({:?})", error);
+                self.rx_buffer.replace(buffer);
+            }
+        });
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(COMMAND_WINDOW_MS),
+        );
+    }
+
+    /// Closes the window for commands.
+    fn stop_listening(&self) -> bool {
+        if !self.listening.replace(false) {
+            return false;
+        }
+        let _ = self.alarm.disarm();
+        let _ = self.uart.receive_abort();
+        true
+    }
+
+    /// Closes the window for commands and lets the tests run.
+    fn finish(&self) {
+        if !self.stop_listening() {
+            return;
+        }
+        self.state.set(State::Chosen);
+        self.client.take().map(|client| client.seed_chosen());
+    }
+
+    /// Hands an `update <length> <crc32>` command to the updater, returning
+    /// whether it took it.
+    fn update(&self, args: &[u8]) -> bool {
+        let mut args = core::str::from_utf8(args).unwrap_or("").split_whitespace();
+        let len = args.next().and_then(|len| len.parse().ok());
+        let crc = args
+            .next()
+            .map(|crc| crc.strip_prefix("0x").unwrap_or(crc))
+            .and_then(|crc| u32::from_str_radix(crc, 16).ok());
+        let (Some(len), Some(crc)) = (len, crc) else {
+            debug!("Update: expected \"update <length> <crc32>\"");
+            return false;
+        };
+        match self.updater.start(len, crc) {
+            Ok(()) => true,
+            Err(reason) => {
+                debug!("Update: failed: {}", reason);
+                false
+            }
+        }
+    }
+
+    /// Handles a complete line, returning whether it set the seed.
+    fn command(&self, line: &[u8]) -> bool {
+        if let Some(args) = line.strip_prefix(UPDATE_COMMAND) {
+            // The tests do not run while the image is received, and the
+            // board restarts after.
+            if self.update(args) {
+                self.stop_listening();
+            }
+            return false;
+        }
+        let seed = line
+            .strip_prefix(COMMAND)
+            .and_then(|hex| core::str::from_utf8(hex).ok())
+            .map(|hex| hex.trim())
+            .map(|hex| hex.strip_prefix("0x").unwrap_or(hex))
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok());
+        match seed {
+            Some(seed) => {
+                self.seed.set(seed);
+                debug!("Test seed: {:#018x}, from the UART", seed);
+                true
+            }
+            None => {
+                debug!("Test seed: expected \"seed <hex>\"");
+                false
+            }
+        }
+    }
+
+    fn receive(&self, byte: u8) -> bool {
+        let len = self.line_len.get();
+        if byte != b'\r' && byte != b'\n' {
+            let mut line = self.line.get();
+            if let Some(slot) = line.get_mut(len) {
+                *slot = byte;
+                self.line.set(line);
+            }
+            self.line_len.set(len.saturating_add(1));
+            return false;
+        }
+        self.line_len.set(0);
+        match self.line.get().get(..len) {
+            Some([]) => false,
+            Some(line) => self.command(line),
+            None => {
+                debug!("Test seed: command too long");
+                false
+            }
+        }
+    }
+}
+
+impl Client32 for TestSeed {
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> Continue {
+        for word in entropy {
+            self.seed.set(self.seed.get() << 32 | u64::from(word));
+            self.words.set(self.words.get() + 1);
+            if self.words.get() == 2 {
+                self.listen();
+                return Continue::Done;
+            }
+        }
+        if error.is_err() {
+            debug!("Test seed: TRNG failed ({:?})", error);
+            self.listen();
+            return Continue::Done;
+        }
+        Continue::More
+    }
+}
+
+impl uart::ReceiveClient for TestSeed {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        let byte = buffer[0];
+        self.rx_buffer.replace(buffer);
+        if !self.listening.get() {
+            return;
+        }
+        if rx_len == 1 && self.receive(byte) {
+            self.finish();
+            return;
+        }
+        // The update command closes the window as well.
+        if !self.listening.get() {
+            return;
+        }
+        self.rx_buffer.take().map(|buffer| {
+            if let Err((_, buffer)) = self.uart.receive_buffer(buffer, 1) {
+                self.rx_buffer.replace(buffer);
+            }
+        });
+    }
+}
+
+impl AlarmClient for TestSeed {
+    fn alarm(&self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,85 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the sensor plausibility test on TWI1 for the sensors listed in
+//! `BOARD_TEST_CONFIG.sensors`. Sensor models that are not listed are not
+//! instantiated, so their checks are skipped.
+//!
+//! The expected output ends with
+//! SensorPlausibility: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::test::sensors::{Quantity, TestSensorPlausibility};
+use kernel::component::Component;
+use kernel::debug;
+use kernel::{static_buf, static_init};
+use nrf52840::i2c::{Speed, TWI};
+use nrf52840::pinmux::Pinmux;
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::{SensorModel, BOARD_TEST_CONFIG};
+use crate::test::registry::skip;
+
+type SensorPlausibilityTest =
+    TestSensorPlausibility<'static, VirtualMuxAlarm<'static, Rtc<'static>>>;
+
+pub unsafe fn run_sensor_plausibility(
+    twi: &'static TWI<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(bus) = BOARD_TEST_CONFIG.sensors.as_ref() else {
+        skip(client, "SensorPlausibility: no sensor bus configured");
+        return;
+    };
+
+    twi.configure(Pinmux::new(bus.scl as u32), Pinmux::new(bus.sda as u32));
+    twi.set_speed(Speed::K100);
+    let mux_i2c = components::i2c::I2CMuxComponent::new(twi, None)
+        .finalize(components::i2c_mux_component_static!(TWI<'static>));
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+    let test = static_init!(SensorPlausibilityTest, TestSensorPlausibility::new(alarm));
+
+    for sensor in bus.sensors {
+        let added = match sensor.model {
+            SensorModel::Bme280 => {
+                let bme280 = components::bme280::Bme280Component::new(mux_i2c, sensor.address)
+                    .finalize(components::bme280_component_static!(TWI<'static>));
+                bme280.startup();
+                test.add("BME280", Quantity::Temperature(bme280))
+                    .and_then(|()| test.add("BME280", Quantity::Humidity(bme280)))
+            }
+            SensorModel::Bmp280 => {
+                let bmp280 =
+                    components::bmp280::Bmp280Component::new(mux_i2c, sensor.address, mux_alarm)
+                        .finalize(components::bmp280_component_static!(
+                            Rtc<'static>,
+                            TWI<'static>
+                        ));
+                let _ = bmp280.begin_reset();
+                test.add("BMP280", Quantity::Temperature(bmp280))
+            }
+            SensorModel::Sht4x => {
+                let sht4x =
+                    components::sht4x::SHT4xComponent::new(mux_i2c, sensor.address, mux_alarm)
+                        .finalize(components::sht4x_component_static!(
+                            Rtc<'static>,
+                            TWI<'static>
+                        ));
+                test.add("SHT4x", Quantity::Temperature(sht4x))
+                    .and_then(|()| test.add("SHT4x", Quantity::Humidity(sht4x)))
+            }
+        };
+        if added.is_err() {
+            debug!("SensorPlausibility: too many sensors listed, ignoring the rest");
+            break;
+        }
+    }
+
+    test.set_client(client);
+    test.run();
+}
@@ -0,0 +1,359 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that the kernel work loop puts the chip to sleep when it has nothing
+//! to do, and that the chip wakes promptly on an interrupt. A kernel that busy
+//! loops instead works just as well, but drains the battery.
+//!
+//! The board's watchdog is a `SleepMonitor`, which the kernel suspends right
+//! before it sleeps and resumes right after it wakes, and which passes the
+//! calls on to the watchdog of the watchdog test. The monitor keeps a
+//! trace of the last sleeps, timed with the RTC. The cycle counter of the
+//! core stops while it sleeps, so the ratio of counted cycles to the cycles a
+//! window would take at the core frequency is the share of the window the
+//! core was awake. The cases are:
+//!
+//! 1. `Idle`: with no process runnable and no interrupt but the test's alarm,
+//!    the chip sleeps for most of a window and the core counts few cycles.
+//! 2. `Wake`: the chip wakes from sleep within a few RTC ticks of the alarm
+//!    that wakes it.
+//! 3. `Busy`: with a process that never yields, the chip does not sleep and
+//!    the core counts the cycles of about the whole window, so the ratio does
+//!    tell the two apart.
+//!
+//! The expected output ends with
+//! Sleep: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use cortexm4::dwt::Dwt;
+use kernel::debug;
+use kernel::hil::hw_debug::CycleCounter;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks, Ticks24, Time};
+use kernel::platform::watchdog::WatchDog;
+use kernel::process::Process;
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppLoader, BUSY_APP};
+use crate::test::registry::report;
+
+/// Number of sleeps the monitor keeps in its trace.
+const TRACE_LEN: usize = 8;
+
+/// Frequency the core runs at, in cycles per millisecond.
+const CYCLES_PER_MS: u64 = 64_000;
+
+/// Time the test waits for the output of earlier tests to drain, as every
+/// UART interrupt wakes the chip.
+const SETTLE_MS: u32 = 100;
+
+/// Time each window lasts.
+const WINDOW_MS: u32 = 100;
+
+/// Time until the alarm that wakes the chip in `Wake`.
+const WAKE_MS: u32 = 10;
+
+/// Highest share of an idle window, in percent, the core may be awake for.
+const IDLE_AWAKE_MAX_PERCENT: u64 = 10;
+
+/// Lowest share of a busy window, in percent, the core must be awake for.
+const BUSY_AWAKE_MIN_PERCENT: u64 = 90;
+
+/// Highest number of RTC ticks, about 30 us each, the chip may take to wake.
+const WAKE_MAX_TICKS: u32 = 3;
+
+/// A watchdog that records when the kernel puts the chip to sleep, and passes
+/// all calls on to the watchdog that guards the chip.
+pub struct SleepMonitor {
+    rtc: &'static Rtc<'static>,
+    watchdog: &'static dyn WatchDog,
+    /// Number of times the chip slept.
+    sleeps: Cell<usize>,
+    /// Total time the chip slept, in RTC ticks.
+    asleep_ticks: Cell<u64>,
+    /// Time the current sleep started, while the chip sleeps.
+    asleep_since: OptionalCell<Ticks24>,
+    /// Start and end tick of the last `TRACE_LEN` sleeps, with sleep `n` at
+    /// index `n % TRACE_LEN`.
+    trace: [Cell<(u32, u32)>; TRACE_LEN],
+}
+
+impl SleepMonitor {
+    pub fn new(rtc: &'static Rtc<'static>, watchdog: &'static dyn WatchDog) -> Self {
+        SleepMonitor {
+            rtc,
+            watchdog,
+            sleeps: Cell::new(0),
+            asleep_ticks: Cell::new(0),
+            asleep_since: OptionalCell::empty(),
+            trace: Default::default(),
+        }
+    }
+
+    pub fn sleeps(&self) -> usize {
+        self.sleeps.get()
+    }
+
+    /// Total time the chip slept, in RTC ticks.
+    pub fn asleep_ticks(&self) -> u64 {
+        self.asleep_ticks.get()
+    }
+
+    /// Returns the start and end of the last sleep, if the chip slept.
+    fn last_sleep(&self) -> Option<(u32, u32)> {
+        let sleeps = self.sleeps.get();
+        (sleeps > 0).then(|| self.trace[(sleeps - 1) % TRACE_LEN].get())
+    }
+
+    /// Returns the number, start and end of each sleep in the trace, oldest
+    /// first.
+    pub fn trace(&self) -> impl Iterator<Item = (usize, u32, u32)> + '_ {
+        let sleeps = self.sleeps.get();
+        (sleeps.saturating_sub(TRACE_LEN)..sleeps).map(|n| {
+            let (start, end) = self.trace[n % TRACE_LEN].get();
+            (n, start, end)
+        })
+    }
+
+    /// Prints the trace, oldest sleep first.
+    fn print_trace(&self) {
+        for (n, start, end) in self.trace() {
+            debug!("Sleep: sleep {} from tick {} to {}", n, start, end);
+        }
+    }
+}
+
+impl WatchDog for SleepMonitor {
+    fn setup(&self) {
+        self.watchdog.setup();
+    }
+
+    fn tickle(&self) {
+        self.watchdog.tickle();
+    }
+
+    fn suspend(&self) {
+        self.watchdog.suspend();
+        self.asleep_since.set(self.rtc.now());
+    }
+
+    fn resume(&self) {
+        let now = self.rtc.now();
+        if let Some(start) = self.asleep_since.take() {
+            let sleeps = self.sleeps.get();
+            self.trace[sleeps % TRACE_LEN].set((start.into_u32(), now.into_u32()));
+            self.sleeps.set(sleeps + 1);
+            self.asleep_ticks
+                .set(self.asleep_ticks.get() + now.wrapping_sub(start).into_u32() as u64);
+        }
+        self.watchdog.resume();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Settle,
+    Idle,
+    Wake,
+    Busy,
+}
+
+pub unsafe fn run_sleep(
+    apps: &'static AppLoader,
+    monitor: &'static SleepMonitor,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(TestSleep, TestSleep::new(apps, monitor, alarm));
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestSleep {
+    apps: &'static AppLoader,
+    monitor: &'static SleepMonitor,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    cycles: Dwt,
+    process: OptionalCell<&'static dyn Process>,
+    /// Number of sleeps, time asleep and cycle count when the current
+    /// window started.
+    window_start: Cell<(usize, u64, u32)>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestSleep {
+    pub fn new(
+        apps: &'static AppLoader,
+        monitor: &'static SleepMonitor,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestSleep {
+            apps,
+            monitor,
+            alarm,
+            cycles: Dwt::new(),
+            process: OptionalCell::empty(),
+            window_start: Cell::new((0, 0, 0)),
+            step: Cell::new(Step::Settle),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if !self.cycles.is_cycle_counter_present() {
+            debug!("Sleep: no cycle counter, skipping");
+            self.finish(Ok(()));
+            return;
+        }
+        self.wait(SETTLE_MS);
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Starts a window: remembers how much the chip slept so far, and the
+    /// count of the cycle counter. The launcher times the test with the same
+    /// counter, so it is not reset.
+    fn start_window(&self) {
+        self.cycles.start();
+        self.window_start.set((
+            self.monitor.sleeps(),
+            self.monitor.asleep_ticks(),
+            self.cycles.count() as u32,
+        ));
+        self.wait(WINDOW_MS);
+    }
+
+    /// Ends a window, returning the number of times the chip slept, the share
+    /// of the window it slept for and the share the core was awake for, in
+    /// percent.
+    fn end_window(&self) -> (usize, u64, u64) {
+        let (sleeps, asleep_ticks, cycles) = self.window_start.get();
+        let window_ticks = self.alarm.ticks_from_ms(WINDOW_MS).into_u32() as u64;
+        let sleeps = self.monitor.sleeps() - sleeps;
+        let asleep = (self.monitor.asleep_ticks() - asleep_ticks) * 100 / window_ticks;
+        let cycles = (self.cycles.count() as u32).wrapping_sub(cycles);
+        let awake = cycles as u64 * 100 / (WINDOW_MS as u64 * CYCLES_PER_MS);
+        debug!(
+            "Sleep: {:?} window slept {} times for {}% of the time, core awake {}%",
+            self.step.get(),
+            sleeps,
+            asleep,
+            awake
+        );
+        (sleeps, asleep, awake)
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Settle => {
+                self.step.set(Step::Idle);
+                self.start_window();
+            }
+            Step::Idle => {
+                let (sleeps, asleep, awake) = self.end_window();
+                if sleeps == 0 {
+                    return Err("kernel did not sleep with nothing to do");
+                }
+                if awake > IDLE_AWAKE_MAX_PERCENT {
+                    return Err("core awake for too much of an idle window");
+                }
+                if asleep < 100 - IDLE_AWAKE_MAX_PERCENT {
+                    return Err("chip slept for too little of an idle window");
+                }
+
+                self.step.set(Step::Wake);
+                self.window_start
+                    .set((self.monitor.sleeps(), self.monitor.asleep_ticks(), 0));
+                self.wait(WAKE_MS);
+            }
+            Step::Wake => {
+                let (sleeps, _, _) = self.window_start.get();
+                if self.monitor.sleeps() == sleeps {
+                    return Err("chip did not sleep until the alarm");
+                }
+                let (_, woke) = self.monitor.last_sleep().ok_or("no sleep traced")?;
+                let latency = Ticks24::from(woke)
+                    .wrapping_sub(self.alarm.get_alarm())
+                    .into_u32();
+                debug!("Sleep: woke {} ticks after the alarm", latency);
+                if latency > WAKE_MAX_TICKS {
+                    return Err("chip did not wake promptly on the alarm");
+                }
+
+                self.step.set(Step::Busy);
+                let process = self
+                    .apps
+                    .load(&BUSY_APP)
+                    .map_err(|_| "loading app failed")?
+                    .ok_or("no process created")?;
+                self.process.set(process);
+                self.start_window();
+            }
+            Step::Busy => {
+                let (sleeps, _, awake) = self.end_window();
+                if let Some(process) = self.process.take() {
+                    let process_management_cap =
+                        create_capability!(capabilities::ProcessManagementCapability);
+                    process.terminate(None);
+                    self.apps
+                        .kernel()
+                        .remove_process(process.processid(), &process_management_cap)
+                        .map_err(|_| "terminated process not removed")?;
+                }
+                if sleeps != 0 {
+                    return Err("kernel slept with a runnable process");
+                }
+                if awake < BUSY_AWAKE_MIN_PERCENT {
+                    return Err("core counted too few cycles of a busy window");
+                }
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("Sleep: {:?} failed: {}", self.step.get(), reason);
+        self.monitor.print_trace();
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "Sleep", result);
+    }
+}
+
+impl AlarmClient for TestSleep {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestSleep {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,77 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the `hil::spi` chip select conformance cases on SPIM2, using the
+//! pins listed in `BOARD_TEST_CONFIG.spi_chip_select`. The chip select pin
+//! must be jumpered to the observer pin.
+//!
+//! The expected output ends with
+//! SpiConformance: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::test::conformance::spi::TestSpiConformance;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::hil::spi::cs::{ActiveLow, IntoChipSelect};
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::Port;
+use nrf52840::pinmux::Pinmux;
+use nrf52840::rtc::Rtc;
+use nrf52840::spi::SPIM;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::{SpiChipSelectPins, BOARD_TEST_CONFIG};
+use crate::test::registry::skip;
+
+/// Whether the SPI pins were handed to the SPIM, which `Pinmux::new()`
+/// allows once per pin.
+static mut PINS_CONFIGURED: bool = false;
+
+/// Connects `spim` to the SPI `pins`.
+pub unsafe fn configure_spim(pins: &SpiChipSelectPins, spim: &SPIM) {
+    if PINS_CONFIGURED {
+        return;
+    }
+    PINS_CONFIGURED = true;
+    spim.configure(
+        Pinmux::new(pins.mosi as u32),
+        Pinmux::new(pins.miso as u32),
+        Pinmux::new(pins.sck as u32),
+    );
+}
+
+type SpiConformanceTest =
+    TestSpiConformance<'static, SPIM<'static>, VirtualMuxAlarm<'static, Rtc<'static>>>;
+
+pub unsafe fn run_spi_conformance(
+    spim: &'static SPIM<'static>,
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.spi_chip_select.as_ref() else {
+        skip(client, "SpiConformance: no chip select loopback configured");
+        return;
+    };
+    configure_spim(pins, spim);
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let write_buffer = static_init!([u8; 8], [0xA5; 8]);
+    let read_buffer = static_init!([u8; 8], [0; 8]);
+    let chip_select = IntoChipSelect::<_, ActiveLow>::into_cs(&gpio_port[pins.chip_select]);
+
+    let test = static_init!(
+        SpiConformanceTest,
+        TestSpiConformance::new(
+            spim,
+            chip_select,
+            &gpio_port[pins.observer],
+            alarm,
+            write_buffer,
+            read_buffer
+        )
+    );
+    test.set_client(client);
+    test.run();
+}
@@ -0,0 +1,316 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the `hil::spi::SpiSlave` implementation of the SPIS, with SPIM2 as
+//! the master on the pins of `BOARD_TEST_CONFIG.spi_chip_select`, each
+//! jumpered to the pin of the same signal in `BOARD_TEST_CONFIG.spi_slave`.
+//! SPIS1 shares its registers with TWI1, so when the test reports, it turns
+//! the SPIS off and leaves the instance to TWI1, whose tests set their own
+//! pins. The SPIS keeps up with SPIM2 at any rate, up to 8 MHz, so the test
+//! leaves the rate of the master as it finds it. The cases are:
+//!
+//! 1. `Unprepared`: before the slave has buffers, it ignores a transaction,
+//!    and the master reads the write byte of the slave.
+//! 2. `Exchange`: once the slave has buffers, the next transaction swaps the
+//!    bytes of the two sides, and the slave reports all of them.
+//! 3. `Overrun`: the master clocks more bytes than the buffers of the slave
+//!    hold. The master reads the write byte past them, and the slave reports
+//!    `ErrorCode::SIZE` with the bytes that fit.
+//!
+//! The expected output ends with
+//! SpiSlave: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::spi::cs::{ActiveLow, IntoChipSelect};
+use kernel::hil::spi::{SpiMaster, SpiMasterClient, SpiSlave, SpiSlaveClient};
+use kernel::static_init;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+use nrf52840::gpio::Port;
+use nrf52840::pinmux::Pinmux;
+use nrf52840::spi::SPIM;
+use nrf52840::spis::SPIS;
+
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::{report, skip};
+use crate::test::spi_conformance_test::configure_spim;
+
+/// Bytes the master clocks in each transaction.
+const LEN: usize = 4;
+
+/// Bytes the slave has buffers for in the `Overrun` case.
+const SHORT_LEN: usize = 2;
+
+const MASTER_PATTERN: [u8; LEN] = [0x3c, 0xc3, 0x5a, 0xa5];
+const SLAVE_PATTERN: [u8; LEN] = [0x81, 0x42, 0x24, 0x18];
+
+/// Byte the slave sends when it has no byte of a buffer to send.
+const WRITE_BYTE: u8 = 0xee;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Unprepared,
+    Exchange,
+    Overrun,
+}
+
+pub unsafe fn run_spi_slave(
+    spim: &'static SPIM<'static>,
+    spis: &'static SPIS<'static>,
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let (Some(master_pins), Some(slave_pins)) = (
+        BOARD_TEST_CONFIG.spi_chip_select.as_ref(),
+        BOARD_TEST_CONFIG.spi_slave.as_ref(),
+    ) else {
+        skip(client, "SpiSlave: no SPI slave pins configured");
+        return;
+    };
+    configure_spim(master_pins, spim);
+    spis.configure(
+        Pinmux::new(slave_pins.mosi as u32),
+        Pinmux::new(slave_pins.miso as u32),
+        Pinmux::new(slave_pins.sck as u32),
+        Pinmux::new(slave_pins.csn as u32),
+    );
+
+    let [master_write, master_read, slave_write, slave_read] =
+        static_init!([[u8; LEN]; 4], [[0; LEN]; 4]);
+    let test = static_init!(
+        TestSpiSlave,
+        TestSpiSlave::new(
+            spim,
+            spis,
+            master_write,
+            master_read,
+            slave_write,
+            slave_read
+        )
+    );
+    spim.set_client(test);
+    spis.set_client(Some(test));
+    test.set_client(client);
+    test.run(IntoChipSelect::<_, ActiveLow>::into_cs(
+        &gpio_port[master_pins.chip_select],
+    ));
+}
+
+pub struct TestSpiSlave {
+    spim: &'static SPIM<'static>,
+    spis: &'static SPIS<'static>,
+    master_write: TakeCell<'static, [u8]>,
+    master_read: TakeCell<'static, [u8]>,
+    slave_write: TakeCell<'static, [u8]>,
+    slave_read: TakeCell<'static, [u8]>,
+    /// Whether each side saw the end of the transaction of the case.
+    master_done: Cell<bool>,
+    slave_done: Cell<bool>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestSpiSlave {
+    pub fn new(
+        spim: &'static SPIM<'static>,
+        spis: &'static SPIS<'static>,
+        master_write: &'static mut [u8],
+        master_read: &'static mut [u8],
+        slave_write: &'static mut [u8],
+        slave_read: &'static mut [u8],
+    ) -> Self {
+        TestSpiSlave {
+            spim,
+            spis,
+            master_write: TakeCell::new(master_write),
+            master_read: TakeCell::new(master_read),
+            slave_write: TakeCell::new(slave_write),
+            slave_read: TakeCell::new(slave_read),
+            master_done: Cell::new(false),
+            slave_done: Cell::new(false),
+            step: Cell::new(Step::Unprepared),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self, chip_select: <SPIM<'static> as SpiMaster<'static>>::ChipSelect) {
+        let result = self
+            .spim
+            .specify_chip_select(chip_select)
+            .and_then(|()| self.spis.init())
+            .map_err(|_| "SPI not configured")
+            .and_then(|()| {
+                self.spis.set_write_byte(WRITE_BYTE);
+                // The slave has no buffers, so no end of the transaction.
+                self.slave_done.set(true);
+                self.transfer()
+            });
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+
+    /// Hands the slave its buffers, for `len` bytes.
+    fn prepare(&self, len: usize) -> Result<(), &'static str> {
+        let (Some(write), Some(read)) = (self.slave_write.take(), self.slave_read.take()) else {
+            return Err("buffers missing");
+        };
+        write.copy_from_slice(&SLAVE_PATTERN);
+        read.fill(0);
+        self.spis
+            .read_write_bytes(Some(write), Some(read), len)
+            .map_err(|_| "slave rejected the buffers")
+    }
+
+    /// Clocks `LEN` bytes from the master.
+    fn transfer(&self) -> Result<(), &'static str> {
+        let (Some(write), Some(read)) = (self.master_write.take(), self.master_read.take()) else {
+            return Err("buffers missing");
+        };
+        write.copy_from_slice(&MASTER_PATTERN);
+        read.fill(0);
+        self.spim
+            .read_write_bytes(SubSliceMut::new(write), Some(SubSliceMut::new(read)))
+            .map_err(|_| "read_write_bytes rejected")
+    }
+
+    /// Bytes the slave has buffers for in the case.
+    fn slave_len(&self) -> usize {
+        match self.step.get() {
+            Step::Unprepared => 0,
+            Step::Exchange => LEN,
+            Step::Overrun => SHORT_LEN,
+        }
+    }
+
+    /// Checks what the master read in the case.
+    fn check_master(&self, read: &[u8]) -> Result<(), &'static str> {
+        let sent = self.slave_len();
+        if read[..sent] != SLAVE_PATTERN[..sent]
+            || read[sent..].iter().any(|&byte| byte != WRITE_BYTE)
+        {
+            return Err("master read the wrong data");
+        }
+        Ok(())
+    }
+
+    /// Starts the next case once both sides saw the end of the transaction.
+    fn check_done(&self) -> Result<(), &'static str> {
+        if !(self.master_done.get() && self.slave_done.get()) {
+            return Ok(());
+        }
+        self.master_done.set(false);
+        self.slave_done.set(false);
+        match self.step.get() {
+            Step::Unprepared => self.step.set(Step::Exchange),
+            Step::Exchange => self.step.set(Step::Overrun),
+            Step::Overrun => {
+                self.finish(Ok(()));
+                return Ok(());
+            }
+        }
+        self.prepare(self.slave_len())
+            .and_then(|()| self.transfer())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("SpiSlave: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        // Turns the SPIS off and clears its shortcut, which gives SPI1_TWI1
+        // back to TWI1, whichever test uses it next.
+        self.spis.set_client(None);
+        report(&self.client, "SpiSlave", result);
+    }
+}
+
+impl SpiMasterClient for TestSpiSlave {
+    fn read_write_done(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+        status: Result<usize, ErrorCode>,
+    ) {
+        self.master_write.replace(write_buffer.take());
+        let Some(read) = read_buffer.map(|buf| buf.take()) else {
+            self.fail("buffers missing");
+            return;
+        };
+        let result = self.check_master(read);
+        self.master_read.replace(read);
+        if self.finished.get() {
+            return;
+        }
+        let result = status
+            .map_err(|_| "transfer failed")
+            .and(result)
+            .and_then(|()| {
+                self.master_done.set(true);
+                self.check_done()
+            });
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+}
+
+impl SpiSlaveClient for TestSpiSlave {
+    // The SPIS reports no chip select.
+    fn chip_selected(&self) {}
+
+    fn read_write_done(
+        &self,
+        write_buffer: Option<&'static mut [u8]>,
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        write_buffer.map(|buf| self.slave_write.replace(buf));
+        let Some(read) = read_buffer else {
+            self.fail("buffers missing");
+            return;
+        };
+        // The SPIS ends no transaction of the `Unprepared` case, as it has
+        // no buffers then, so the step is that of a case with buffers.
+        let expected_len = self.slave_len();
+        let data = read[..expected_len] == MASTER_PATTERN[..expected_len]
+            && read[expected_len..].iter().all(|&byte| byte == 0);
+        self.slave_read.replace(read);
+        if self.finished.get() {
+            return;
+        }
+        let expected_status = match expected_len {
+            LEN => Ok(()),
+            _ => Err(ErrorCode::SIZE),
+        };
+        let result = if status != expected_status {
+            Err("slave reported the wrong status")
+        } else if len != expected_len || !data {
+            Err("slave received the wrong data")
+        } else {
+            self.slave_done.set(true);
+            self.check_done()
+        };
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestSpiSlave {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
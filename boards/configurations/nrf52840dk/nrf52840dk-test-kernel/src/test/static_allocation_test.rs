@@ -0,0 +1,183 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Reports the memory the board and the tests before this one allocated with
+//! `static_init!()`, and checks it against the BSS section the linker laid
+//! out. The report needs the kernel's static allocation log, so this test is
+//! skipped unless the board is built with the `static_allocation_report`
+//! feature. The cases are:
+//!
+//! 1. `Report`: every recorded buffer is printed as address, size and type,
+//!    and none was dropped because the log was full.
+//! 2. `Bss`: every buffer lies in the BSS section, and together they fit in
+//!    it.
+//!
+//! The expected output ends with
+//! StaticAllocation: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::static_init::{static_allocations, StaticAllocation, StaticAllocations};
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::registry::{report, skip};
+
+/// Debug buffer space needed before a report line is printed. Lines with
+/// longer type names may be cut short.
+const LINE_LEN: usize = 160;
+
+/// Time the debug writer gets to drain its buffer when it is too full for the
+/// next report line.
+const DRAIN_MS: u32 = 20;
+
+extern "C" {
+    /// Start of the BSS section, from the kernel linker script.
+    static _szero: u8;
+    /// End of the BSS section, from the kernel linker script.
+    static _ezero: u8;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Report,
+    Bss,
+}
+
+pub unsafe fn run_static_allocation(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(allocations) = static_allocations() else {
+        skip(
+            client,
+            "StaticAllocation: static_allocation_report feature not enabled",
+        );
+        return;
+    };
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestStaticAllocation,
+        TestStaticAllocation::new(alarm, allocations)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestStaticAllocation {
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    /// Allocations recorded when the test started. The test's own buffers
+    /// are not part of the report.
+    allocations: StaticAllocations,
+    /// Index of the next allocation to print.
+    next: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestStaticAllocation {
+    pub fn new(
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        allocations: StaticAllocations,
+    ) -> Self {
+        TestStaticAllocation {
+            alarm,
+            allocations,
+            next: Cell::new(0),
+            step: Cell::new(Step::Report),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if self.allocations.dropped() != 0 {
+            self.fail("static allocation log full");
+            return;
+        }
+        self.report();
+    }
+
+    /// Prints as many report lines as the debug buffer has room for, and
+    /// waits for it to drain if lines are left.
+    fn report(&self) {
+        for allocation in self.allocations.iter().skip(self.next.get()) {
+            if kernel::debug::debug_available_len() < LINE_LEN {
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(DRAIN_MS));
+                return;
+            }
+            debug!(
+                "StaticAllocation: {:#010x} {:>6} {}",
+                allocation.address, allocation.size, allocation.type_name
+            );
+            self.next.set(self.next.get() + 1);
+        }
+
+        self.step.set(Step::Bss);
+        match self.check_bss() {
+            Ok(()) => self.finish(Ok(())),
+            Err(reason) => self.fail(reason),
+        }
+    }
+
+    fn check_bss(&self) -> Result<(), &'static str> {
+        let bss_start = core::ptr::addr_of!(_szero) as usize;
+        let bss_end = core::ptr::addr_of!(_ezero) as usize;
+        let in_bss = |allocation: StaticAllocation| {
+            allocation.address >= bss_start && allocation.address + allocation.size <= bss_end
+        };
+        if !self.allocations.iter().all(in_bss) {
+            return Err("buffer outside the BSS section");
+        }
+        let total = self.allocations.total_size();
+        debug!(
+            "StaticAllocation: {} buffers, {} of {} BSS bytes",
+            self.allocations.iter().count(),
+            total,
+            bss_end - bss_start
+        );
+        if total > bss_end - bss_start {
+            return Err("buffers larger than the BSS section");
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("StaticAllocation: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "StaticAllocation", result);
+    }
+}
+
+impl AlarmClient for TestStaticAllocation {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        self.report();
+    }
+}
+
+impl CapsuleTest for TestStaticAllocation {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
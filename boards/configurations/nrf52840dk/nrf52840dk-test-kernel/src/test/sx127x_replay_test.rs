@@ -0,0 +1,178 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the SX127x LoRa transceiver test against a replayed trace of its
+//! register accesses, so it needs no module. The cases are:
+//!
+//! 1. `Driver`: the SX127x test passes on the replay.
+//! 2. `Trace`: the test made every transfer of `TRACE`, in order, and no
+//!    other.
+//!
+//! The trace is the test at 868 MHz without a reset pin, with the answers of
+//! an SX1276 whose first poll of the interrupt flags finds the packet still
+//! being sent.
+//!
+//! The expected output ends with
+//! Sx127xReplay: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::test::replay::{SpiReplay, Transfer};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice;
+use capsules_extra::test::sx127x::{TestSx127x, BUFFER_LEN};
+use kernel::component::Component;
+use kernel::debug;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+
+const FREQUENCY_HZ: u32 = 868_000_000;
+
+/// Register accesses of the SX127x test. The first byte of each transfer is
+/// the register address, with the top bit set for writes; the radio answers
+/// it with zero.
+#[rustfmt::skip]
+static TRACE: [Transfer; 19] = [
+    // RegVersion
+    Transfer { write: &[0x42, 0x00], read: &[0x00, 0x12] },
+    // RegOpMode: sleep, LoRa sleep, read back
+    Transfer { write: &[0x81, 0x00], read: &[0x00, 0x09] },
+    Transfer { write: &[0x81, 0x80], read: &[0x00, 0x00] },
+    Transfer { write: &[0x01, 0x00], read: &[0x00, 0x80] },
+    // RegFrfMsb..RegFrfLsb: write, read back
+    Transfer { write: &[0x86, 0xD9, 0x00, 0x00], read: &[0x00, 0x6C, 0x80, 0x00] },
+    Transfer { write: &[0x06, 0x00, 0x00, 0x00], read: &[0x00, 0xD9, 0x00, 0x00] },
+    // RegPaConfig
+    Transfer { write: &[0x89, 0x00], read: &[0x00, 0x4F] },
+    // RegOpMode: standby
+    Transfer { write: &[0x81, 0x81], read: &[0x00, 0x80] },
+    // RegIrqFlags
+    Transfer { write: &[0x92, 0xFF], read: &[0x00, 0x00] },
+    // RegFifoAddrPtr, RegFifoTxBaseAddr
+    Transfer { write: &[0x8D, 0x80, 0x80], read: &[0x00, 0x00, 0x80] },
+    // RegFifo
+    Transfer {
+        write: &[0x80,
+                 0x5A, 0x47, 0x60, 0x0D, 0x2E, 0xCB, 0xF4, 0x91,
+                 0xB2, 0x5F, 0x78, 0x65, 0x06, 0x23, 0xCC, 0xE9],
+        read: &[],
+    },
+    // RegPayloadLength
+    Transfer { write: &[0xA2, 0x10], read: &[0x00, 0x01] },
+    // RegOpMode: transmit
+    Transfer { write: &[0x81, 0x83], read: &[0x00, 0x81] },
+    // RegIrqFlags: polled until TxDone
+    Transfer { write: &[0x12, 0x00], read: &[0x00, 0x00] },
+    Transfer { write: &[0x12, 0x00], read: &[0x00, 0x08] },
+    // RegOpMode: back in standby
+    Transfer { write: &[0x01, 0x00], read: &[0x00, 0x81] },
+    // RegIrqFlags: clear TxDone, read back
+    Transfer { write: &[0x92, 0x08], read: &[0x00, 0x08] },
+    Transfer { write: &[0x12, 0x00], read: &[0x00, 0x00] },
+    // RegOpMode: LoRa sleep
+    Transfer { write: &[0x81, 0x80], read: &[0x00, 0x81] },
+];
+
+type Replay = SpiReplay<'static>;
+type Sx127xTest = TestSx127x<
+    'static,
+    VirtualSpiMasterDevice<'static, Replay>,
+    VirtualMuxAlarm<'static, Rtc<'static>>,
+>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Driver,
+    Trace,
+}
+
+pub unsafe fn run_sx127x_replay(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let replay = static_init!(Replay, SpiReplay::new(&TRACE));
+    replay.register();
+    let mux_spi = components::spi::SpiMuxComponent::new(replay)
+        .finalize(components::spi_mux_component_static!(Replay));
+    let spi_device = static_init!(
+        VirtualSpiMasterDevice<'static, Replay>,
+        VirtualSpiMasterDevice::new(mux_spi, ())
+    );
+    spi_device.setup();
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let write_buffer = static_init!([u8; BUFFER_LEN], [0; BUFFER_LEN]);
+    let read_buffer = static_init!([u8; BUFFER_LEN], [0; BUFFER_LEN]);
+    let driver = static_init!(
+        Sx127xTest,
+        TestSx127x::new(
+            spi_device,
+            None,
+            alarm,
+            FREQUENCY_HZ,
+            write_buffer,
+            read_buffer
+        )
+    );
+
+    let test = static_init!(TestSx127xReplay, TestSx127xReplay::new(replay));
+    test.set_client(client);
+    driver.set_client(test);
+    driver.run();
+}
+
+pub struct TestSx127xReplay {
+    replay: &'static Replay,
+    step: Cell<Step>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestSx127xReplay {
+    pub fn new(replay: &'static Replay) -> Self {
+        TestSx127xReplay {
+            replay,
+            step: Cell::new(Step::Driver),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("Sx127xReplay: {:?} failed: {}", self.step.get(), reason);
+        self.client
+            .map(|client| client.done(Err(CapsuleTestError::IncorrectResult)));
+    }
+}
+
+impl CapsuleTestClient for TestSx127xReplay {
+    fn done(&'static self, result: Result<(), CapsuleTestError>) {
+        if result.is_err() {
+            if let Some(divergence) = self.replay.divergence() {
+                debug!("Sx127xReplay: left the trace at {:?}", divergence);
+            }
+            self.fail("SX127x test failed");
+            return;
+        }
+
+        self.step.set(Step::Trace);
+        if !self.replay.is_complete() {
+            self.fail("trace not played to the end");
+            return;
+        }
+
+        debug!("Sx127xReplay: all cases passed");
+        self.client.map(|client| client.done(Ok(())));
+    }
+}
+
+impl CapsuleTest for TestSx127xReplay {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
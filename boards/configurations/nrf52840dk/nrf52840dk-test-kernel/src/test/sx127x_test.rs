@@ -0,0 +1,79 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the SX127x LoRa transceiver test on SPIM2, using the module listed in
+//! `BOARD_TEST_CONFIG.lora`. The module's antenna port must be terminated
+//! with a dummy load.
+//!
+//! The expected output ends with
+//! Sx127x: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice;
+use capsules_extra::test::sx127x::{TestSx127x, BUFFER_LEN};
+use kernel::component::Component;
+use kernel::hil::spi::cs::ActiveLow;
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::Port;
+use nrf52840::pinmux::Pinmux;
+use nrf52840::rtc::Rtc;
+use nrf52840::spi::SPIM;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::skip;
+
+type Sx127xTest = TestSx127x<
+    'static,
+    VirtualSpiMasterDevice<'static, SPIM<'static>>,
+    VirtualMuxAlarm<'static, Rtc<'static>>,
+>;
+
+pub unsafe fn run_sx127x(
+    spim: &'static SPIM<'static>,
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(module) = BOARD_TEST_CONFIG.lora.as_ref() else {
+        skip(client, "Sx127x: no LoRa module configured");
+        return;
+    };
+
+    spim.configure(
+        Pinmux::new(module.mosi as u32),
+        Pinmux::new(module.miso as u32),
+        Pinmux::new(module.sck as u32),
+    );
+    let mux_spi = components::spi::SpiMuxComponent::new(spim)
+        .finalize(components::spi_mux_component_static!(SPIM<'static>));
+    let spi_device = components::spi::SpiComponent::<_, _, ActiveLow>::new(
+        mux_spi,
+        &gpio_port[module.chip_select],
+    )
+    .finalize(components::spi_component_static!(SPIM<'static>));
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let write_buffer = static_init!([u8; BUFFER_LEN], [0; BUFFER_LEN]);
+    let read_buffer = static_init!([u8; BUFFER_LEN], [0; BUFFER_LEN]);
+    let reset = module
+        .reset
+        .map(|pin| &gpio_port[pin] as &dyn kernel::hil::gpio::Pin);
+
+    let test = static_init!(
+        Sx127xTest,
+        TestSx127x::new(
+            spi_device,
+            reset,
+            alarm,
+            module.frequency_hz,
+            write_buffer,
+            read_buffer
+        )
+    );
+    test.set_client(client);
+    test.run();
+}
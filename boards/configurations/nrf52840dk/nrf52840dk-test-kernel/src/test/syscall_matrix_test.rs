@@ -0,0 +1,369 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Calls every system call class with boundary values from an embedded app
+//! and checks the kernel answers each call as TRD104 requires. The matrix in
+//! `CALLS` is built into the app's image at compile time, and the app logs
+//! the registers every call returns. The values are 0, `u32::MAX`, unaligned
+//! pointers, ranges that wrap around the address space, and driver and
+//! subdriver numbers that only match existing ones in their low bits, so a
+//! kernel that truncates an argument reaches a driver it must not reach. The
+//! cases are:
+//!
+//! 1. `Returns`: the app makes every call and keeps running, and each call
+//!    returns the variant and error code TRD104 requires, with the pointer
+//!    and value a failed subscribe or allow returns unchanged.
+//! 2. `Arguments`: the report driver got the `u32::MAX` arguments of the one
+//!    command that reaches it whole, and no call on an aliased number.
+//!
+//! A call with an unknown class faults the process, so the matrix leaves
+//! the class out.
+//!
+//! The expected output ends with
+//! SyscallMatrix: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::{Process, State};
+use kernel::syscall::SyscallClass;
+use kernel::utilities::arch_helpers::TRD104SyscallReturnVariant;
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init, ErrorCode};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{
+    syscall_matrix_app, AppImage, AppLoader, MATRIX_LOG_OFFSET, REPORT_DRIVER_NUM,
+};
+use crate::test::registry::report;
+use crate::test::report_driver::ReportDriver;
+
+/// Time the app gets to make every call.
+const RUN_MS: u32 = 20;
+
+const MAX: u32 = u32::MAX;
+
+const REPORT: u32 = REPORT_DRIVER_NUM as u32;
+
+/// A driver number that differs from the report driver's in the top bit
+/// only.
+const ALIASED_REPORT: u32 = REPORT | 0x8000_0000;
+
+/// What TRD104 requires a call to return.
+#[derive(Clone, Copy, Debug)]
+enum Expect {
+    /// Yield returns no value, so the registers keep the arguments.
+    Unchanged,
+    /// `Success`, whatever values follow.
+    Success,
+    /// `Failure` with the error code.
+    Failure(ErrorCode),
+    /// `Failure with 2 u32` with the error code, followed by the values
+    /// passed in r2 and r3.
+    FailureArgs(ErrorCode),
+}
+
+struct Call {
+    class: SyscallClass,
+    /// Values of r0 to r3.
+    args: [u32; 4],
+    expect: Expect,
+}
+
+const fn call(class: SyscallClass, args: [u32; 4], expect: Expect) -> Call {
+    Call {
+        class,
+        args,
+        expect,
+    }
+}
+
+const CALLS: [Call; 18] = [
+    // An unknown yield type, and yield-no-wait with a flag address outside
+    // the process.
+    call(SyscallClass::Yield, [MAX, 0, 0, 0], Expect::Unchanged),
+    call(SyscallClass::Yield, [0, 1, 0, 0], Expect::Unchanged),
+    call(
+        SyscallClass::Subscribe,
+        [MAX, 0, 0, 0],
+        Expect::FailureArgs(ErrorCode::NODEVICE),
+    ),
+    call(
+        SyscallClass::Subscribe,
+        [ALIASED_REPORT, 0, 0, 0],
+        Expect::FailureArgs(ErrorCode::NODEVICE),
+    ),
+    call(
+        SyscallClass::Subscribe,
+        [REPORT, MAX, 0, MAX],
+        Expect::FailureArgs(ErrorCode::NOSUPPORT),
+    ),
+    // An unaligned upcall outside the process.
+    call(
+        SyscallClass::Subscribe,
+        [REPORT, 0, 1, 0],
+        Expect::FailureArgs(ErrorCode::INVAL),
+    ),
+    call(
+        SyscallClass::Command,
+        [MAX, 0, 0, 0],
+        Expect::Failure(ErrorCode::NODEVICE),
+    ),
+    call(
+        SyscallClass::Command,
+        [ALIASED_REPORT, 1, 0, 0],
+        Expect::Failure(ErrorCode::NODEVICE),
+    ),
+    // Command 1 of the report driver with the top bit set.
+    call(
+        SyscallClass::Command,
+        [REPORT, 0x8000_0001, 0, 0],
+        Expect::Failure(ErrorCode::NOSUPPORT),
+    ),
+    call(
+        SyscallClass::Command,
+        [REPORT, 1, MAX, MAX],
+        Expect::Success,
+    ),
+    // A buffer that wraps around the address space.
+    call(
+        SyscallClass::ReadWriteAllow,
+        [REPORT, 0, 1, MAX],
+        Expect::FailureArgs(ErrorCode::INVAL),
+    ),
+    // Any address is valid for an empty buffer, but the driver has no such
+    // allow.
+    call(
+        SyscallClass::ReadWriteAllow,
+        [REPORT, MAX, MAX, 0],
+        Expect::FailureArgs(ErrorCode::NOSUPPORT),
+    ),
+    call(
+        SyscallClass::ReadOnlyAllow,
+        [MAX, 0, 0, 0],
+        Expect::FailureArgs(ErrorCode::NODEVICE),
+    ),
+    call(
+        SyscallClass::ReadOnlyAllow,
+        [REPORT, 0, MAX, 1],
+        Expect::FailureArgs(ErrorCode::INVAL),
+    ),
+    call(
+        SyscallClass::UserspaceReadableAllow,
+        [REPORT, 0, 2, MAX],
+        Expect::FailureArgs(ErrorCode::INVAL),
+    ),
+    call(
+        SyscallClass::Memop,
+        [MAX, 0, 0, 0],
+        Expect::Failure(ErrorCode::NOSUPPORT),
+    ),
+    // Shrinking the break by 2 GiB.
+    call(
+        SyscallClass::Memop,
+        [1, 0x8000_0000, 0, 0],
+        Expect::Failure(ErrorCode::NOMEM),
+    ),
+    call(
+        SyscallClass::Exit,
+        [MAX, MAX, 0, 0],
+        Expect::Failure(ErrorCode::NOSUPPORT),
+    ),
+];
+
+/// `CALLS` as the table the app runs.
+const fn table() -> [[u32; 5]; CALLS.len()] {
+    let mut table = [[0; 5]; CALLS.len()];
+    let mut i = 0;
+    while i < CALLS.len() {
+        let [r0, r1, r2, r3] = CALLS[i].args;
+        table[i] = [CALLS[i].class as u32, r0, r1, r2, r3];
+        i += 1;
+    }
+    table
+}
+
+static SYSCALL_MATRIX_APP: AppImage = syscall_matrix_app(b"sys_mtrx", &table());
+
+impl Call {
+    /// Returns whether `returned` holds the registers TRD104 requires.
+    fn returned_correctly(&self, returned: [u32; 4]) -> bool {
+        let [r0, r1, r2, r3] = returned;
+        match self.expect {
+            Expect::Unchanged => returned == self.args,
+            Expect::Success => r0 == TRD104SyscallReturnVariant::Success as u32,
+            Expect::Failure(error) => {
+                r0 == TRD104SyscallReturnVariant::Failure as u32 && r1 == usize::from(error) as u32
+            }
+            Expect::FailureArgs(error) => {
+                r0 == TRD104SyscallReturnVariant::FailureU32U32 as u32
+                    && r1 == usize::from(error) as u32
+                    && [r2, r3] == [self.args[2], self.args[3]]
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Returns,
+    Arguments,
+}
+
+pub unsafe fn run_syscall_matrix(
+    apps: &'static AppLoader,
+    report_driver: &'static ReportDriver,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestSyscallMatrix,
+        TestSyscallMatrix::new(apps, report_driver, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestSyscallMatrix {
+    apps: &'static AppLoader,
+    report_driver: &'static ReportDriver,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    process: OptionalCell<&'static dyn Process>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestSyscallMatrix {
+    pub fn new(
+        apps: &'static AppLoader,
+        report_driver: &'static ReportDriver,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestSyscallMatrix {
+            apps,
+            report_driver,
+            alarm,
+            process: OptionalCell::empty(),
+            step: Cell::new(Step::Returns),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.report_driver.reset();
+        let process = match self.apps.load(&SYSCALL_MATRIX_APP) {
+            Ok(Some(process)) => process,
+            _ => {
+                self.fail("no process created");
+                return;
+            }
+        };
+        self.process.set(process);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RUN_MS));
+    }
+
+    /// Returns the number of calls the app made.
+    fn calls_made(&self, process: &dyn Process) -> usize {
+        let ram = process.get_addresses().sram_start;
+        // SAFETY: the count is in the process's RAM, which the kernel may
+        // access at any time. The app only runs while the kernel does not.
+        unsafe { core::ptr::read_volatile((ram + MATRIX_LOG_OFFSET - 4) as *const u32) as usize }
+    }
+
+    /// Returns the registers call `index` returned.
+    fn returned(&self, process: &dyn Process, index: usize) -> [u32; 4] {
+        let ram = process.get_addresses().sram_start + MATRIX_LOG_OFFSET + 16 * index;
+        // SAFETY: as in `calls_made()`. The log holds `CALLS.len()` entries,
+        // well within the app's RAM.
+        core::array::from_fn(|register| unsafe {
+            core::ptr::read_volatile((ram + 4 * register) as *const u32)
+        })
+    }
+
+    fn check(&self, process: &dyn Process) -> Result<(), &'static str> {
+        if process.get_state() != State::Yielded {
+            return Err("app stopped before making every call");
+        }
+        if self.calls_made(process) != CALLS.len() {
+            return Err("app did not make every call");
+        }
+        let mut wrong = 0;
+        for (index, call) in CALLS.iter().enumerate() {
+            let returned = self.returned(process, index);
+            if !call.returned_correctly(returned) {
+                debug!(
+                    "SyscallMatrix: {:?} {:#x?} returned {:#x?}, expected {:?}",
+                    call.class, call.args, returned, call.expect
+                );
+                wrong += 1;
+            }
+        }
+        if wrong > 0 {
+            return Err("calls returned values TRD104 does not allow");
+        }
+
+        self.step.set(Step::Arguments);
+        if !self
+            .report_driver
+            .reports()
+            .eq([(MAX as usize, MAX as usize)])
+        {
+            return Err("driver got truncated arguments or aliased calls");
+        }
+        self.finish(Ok(()));
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("SyscallMatrix: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        // Free the slot for later tests, even after a failure.
+        if let Some(process) = self.process.take() {
+            let process_management_cap =
+                create_capability!(capabilities::ProcessManagementCapability);
+            process.terminate(None);
+            let _ = self
+                .apps
+                .kernel()
+                .remove_process(process.processid(), &process_management_cap);
+        }
+        report(&self.client, "SyscallMatrix", result);
+    }
+}
+
+impl AlarmClient for TestSyscallMatrix {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let Some(process) = self.process.get() else {
+            return;
+        };
+        if let Err(reason) = self.check(process) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestSyscallMatrix {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
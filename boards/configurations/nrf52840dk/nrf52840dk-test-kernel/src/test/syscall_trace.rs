@@ -0,0 +1,92 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Records the system calls of one embedded app, which the kernel reports as
+//! this board builds it with the `kernel_test` feature. A test starts the
+//! recorder with the app's `ProcessId` once it loads the app, and checks the
+//! calls, their return values and the function calls the kernel pushes to
+//! the app against the exchange it expects.
+
+use core::cell::Cell;
+
+use kernel::process::{FunctionCall, ProcessId};
+use kernel::syscall::{Syscall, SyscallReturn};
+use kernel::syscall_trace::{self, SyscallTraceClient};
+use kernel::{capabilities, create_capability};
+
+/// Number of events the recorder keeps.
+const TRACE_LEN: usize = 32;
+
+/// An exchange between the kernel and the traced app.
+#[derive(Clone, Copy, Debug)]
+pub enum TraceEvent {
+    /// The app made a system call.
+    Call(Syscall),
+    /// The kernel returned a value for the last system call.
+    Return(SyscallReturn),
+    /// The kernel pushed a function call, such as an upcall, to the app.
+    Function(FunctionCall),
+}
+
+pub struct SyscallRecorder {
+    /// The first events since the last `start`.
+    events: [Cell<Option<TraceEvent>>; TRACE_LEN],
+    event_count: Cell<usize>,
+}
+
+impl SyscallRecorder {
+    pub fn new() -> Self {
+        SyscallRecorder {
+            events: [const { Cell::new(None) }; TRACE_LEN],
+            event_count: Cell::new(0),
+        }
+    }
+
+    /// Clears the events and records those of `processid` from now on.
+    pub fn start(&self, processid: ProcessId) {
+        self.events.iter().for_each(|event| event.set(None));
+        self.event_count.set(0);
+        syscall_trace::trace_process(
+            Some(processid),
+            create_capability!(capabilities::SyscallTraceCapability),
+        );
+    }
+
+    /// Stops recording, and keeps the events for `events`.
+    pub fn stop(&self) {
+        syscall_trace::trace_process(
+            None,
+            create_capability!(capabilities::SyscallTraceCapability),
+        );
+    }
+
+    /// Returns the kept events, oldest first, and whether events were dropped
+    /// because the trace was full.
+    pub fn events(&self) -> (impl Iterator<Item = TraceEvent> + '_, bool) {
+        let events = self.events.iter().filter_map(|event| event.get());
+        (events, self.event_count.get() > TRACE_LEN)
+    }
+
+    fn record(&self, event: TraceEvent) {
+        let count = self.event_count.get();
+        if let Some(slot) = self.events.get(count) {
+            slot.set(Some(event));
+        }
+        self.event_count.set(count + 1);
+    }
+}
+
+impl SyscallTraceClient for SyscallRecorder {
+    fn syscall(&self, _processid: ProcessId, syscall: Syscall) {
+        self.record(TraceEvent::Call(syscall));
+    }
+
+    fn syscall_returned(&self, _processid: ProcessId, return_value: SyscallReturn) {
+        self.record(TraceEvent::Return(return_value));
+    }
+
+    fn function_called(&self, _processid: ProcessId, function_call: FunctionCall) {
+        self.record(TraceEvent::Function(function_call));
+    }
+}
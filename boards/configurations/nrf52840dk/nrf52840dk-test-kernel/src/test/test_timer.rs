@@ -0,0 +1,57 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Times how long the core runs for each test, with its cycle counter.
+//!
+//! A `TestTimer` counts the cycles of a `CycleCounter`, the DWT CYCCNT of a
+//! Cortex-M core or `mcycle` of a RISC-V hart, from the start of a test to
+//! its result, and reports them in microseconds. The cycle counter of the
+//! nRF52840 stops while the core sleeps, so this is the time the kernel, the
+//! drivers and the processes worked for the test, which a slower driver adds
+//! to, rather than the time the test waited for the hardware. `TestTimeout`
+//! times the test by the RTC, in milliseconds, asleep or not.
+
+use core::cell::Cell;
+
+use kernel::hil::hw_debug::CycleCounter;
+
+pub struct TestTimer<C: CycleCounter> {
+    counter: C,
+    cycles_per_us: u32,
+    /// The count when the running or last test started.
+    started: Cell<u64>,
+}
+
+impl<C: CycleCounter> TestTimer<C> {
+    /// A timer for a core that runs `cycles_per_us` cycles per microsecond.
+    pub const fn new(counter: C, cycles_per_us: u32) -> Self {
+        TestTimer {
+            counter,
+            cycles_per_us,
+            started: Cell::new(0),
+        }
+    }
+
+    /// Starts timing a test. This also starts the counter, which it never
+    /// resets, so tests that read the counter themselves can share it.
+    pub fn start(&self) {
+        self.counter.start();
+        self.started.set(self.counter.count());
+    }
+
+    /// Microseconds the core ran since the running or last test started. A
+    /// count below the one at the start wrapped around 32 bits, as CYCCNT
+    /// does after 67 s at 64 MHz, so tests that keep the core busy for
+    /// longer report less.
+    pub fn elapsed_us(&self) -> u32 {
+        let started = self.started.get();
+        let now = self.counter.count();
+        let cycles = if now >= started {
+            now - started
+        } else {
+            now + (1 << 32) - started
+        };
+        (cycles / u64::from(self.cycles_per_us)) as u32
+    }
+}
@@ -0,0 +1,107 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Fails a test that does not report its result in time.
+//!
+//! Tests report their result through `CapsuleTestClient::done`, usually from
+//! the callback of an interrupt-driven HIL, so the launcher only moves on
+//! once the test calls it. `TestTimeout` runs while a test does, for the
+//! `test_timeout_ms` of the board test configuration, or the longer timeout
+//! the registry gives the test. If the test has not reported by then, the
+//! launcher prints
+//!
+//! ```text
+//! Test 12 failed: no result after 60000 ms
+//! ```
+//!
+//! reports the test failed and starts the next one. The test itself is not
+//! stopped, so its operations in flight may still complete. A result it
+//! reports while no test runs is ignored.
+//!
+//! It also times each test, for the reporters that report how long a test
+//! ran.
+
+use core::cell::Cell;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks, Ticks24, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+
+/// Receives the callback when a test runs out of time.
+pub trait TestTimeoutClient {
+    fn timed_out(&'static self, timeout_ms: u32);
+}
+
+pub unsafe fn new_test_timeout(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+) -> &'static TestTimeout {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+    let timeout = static_init!(TestTimeout, TestTimeout::new(alarm));
+    alarm.set_alarm_client(timeout);
+    timeout
+}
+
+pub struct TestTimeout {
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    /// Whether a test runs and has not reported yet.
+    running: Cell<bool>,
+    timeout_ms: Cell<u32>,
+    /// When the running or last test started.
+    started: Cell<Ticks24>,
+    client: OptionalCell<&'static dyn TestTimeoutClient>,
+}
+
+impl TestTimeout {
+    fn new(alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>) -> Self {
+        TestTimeout {
+            alarm,
+            running: Cell::new(false),
+            timeout_ms: Cell::new(0),
+            started: Cell::new(0.into()),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts timing a test that gets `timeout_ms`, or all the time it
+    /// needs if `None`. `client` is told if the test runs out of time.
+    pub fn start(&self, timeout_ms: Option<u32>, client: &'static dyn TestTimeoutClient) {
+        self.client.set(client);
+        self.running.set(true);
+        let now = self.alarm.now();
+        self.started.set(now);
+        if let Some(timeout_ms) = timeout_ms {
+            self.timeout_ms.set(timeout_ms);
+            self.alarm
+                .set_alarm(now, self.alarm.ticks_from_ms(timeout_ms));
+        }
+    }
+
+    /// Milliseconds since the running or last test started. The RTC wraps
+    /// after 512 s, so longer tests report less.
+    pub fn elapsed_ms(&self) -> u32 {
+        let elapsed = self.alarm.now().wrapping_sub(self.started.get());
+        self.alarm.ticks_to_ms(elapsed)
+    }
+
+    /// Stops timing the running test, as it reported its result. Returns
+    /// `false` if no test runs, so the result comes from a test that timed
+    /// out.
+    pub fn stop(&self) -> bool {
+        let _ = self.alarm.disarm();
+        self.running.replace(false)
+    }
+}
+
+impl AlarmClient for TestTimeout {
+    fn alarm(&self) {
+        if self.running.replace(false) {
+            self.client
+                .map(|client| client.timed_out(self.timeout_ms.get()));
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the touch test unattended, with scripted events from a fake touch
+//! source, as the DK has no touch panel.
+//!
+//! The expected output ends with
+//! TouchTest: all cases passed
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::test::touch::{FakeTouch, TestTouch};
+use kernel::deferred_call::DeferredCallClient;
+use kernel::{static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+
+pub unsafe fn run_touch(
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let fake = static_init!(FakeTouch<'static>, FakeTouch::new());
+    fake.register();
+
+    let test = static_init!(
+        TestTouch<'static, VirtualMuxAlarm<'static, Rtc<'static>>>,
+        TestTouch::new_unattended(fake, alarm)
+    );
+    test.set_client(client);
+    test.run();
+}
@@ -0,0 +1,272 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that the UARTE receives a stream longer than one EasyDMA transfer
+//! without losing bytes, with the TXD of UARTE1 jumpered to its RXD on the
+//! pins of `BOARD_TEST_CONFIG.uart_loopback`. The UARTE moves at most 255
+//! bytes in one transfer, and the driver starts the next one from the ENDRX
+//! interrupt, while the RX FIFO holds the bytes that arrive in between.
+//!
+//! Each stream starts with a burst of `debug!()` output longer than the
+//! buffer of the debug writer, so the console on UARTE0 drains the buffer
+//! while UARTE1 streams. Each line of the burst either fits in the buffer, or
+//! the count of bytes the debug writer dropped grows by exactly the part that
+//! does not fit. The cases are:
+//!
+//! 1. `Stream`: a receive of `STREAM_LEN` bytes, more than two transfers,
+//!    gets every byte of a transmit of as many, in order.
+//! 2. `Abort`: a receive of `STREAM_LEN` bytes, aborted once a transmit of
+//!    `PARTIAL_LEN` bytes ended, reports `ErrorCode::CANCEL` with all of
+//!    them, the last ones of the second transfer and the one still on the
+//!    wire included.
+//!
+//! The expected output ends with
+//! UartLoopback: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::debug::{debug_available_len, debug_dropped_len, debug_print};
+use kernel::hil::uart::{self, Configure, Receive, ReceiveClient, Transmit, TransmitClient};
+use kernel::static_init;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use nrf52840::pinmux::Pinmux;
+use nrf52840::uart::Uarte;
+
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::{report, skip};
+
+/// Bytes of the `Stream` case, and of the receive buffer.
+const STREAM_LEN: usize = 600;
+
+/// Bytes of the `Abort` case, which end in the second transfer.
+const PARTIAL_LEN: usize = 300;
+
+/// The rate of the console, which gives the driver the same time to start
+/// the next transfer.
+const BAUD_RATE: u32 = 115200;
+
+/// Lines of the burst of debug output, which together are longer than the
+/// debug buffer.
+const BURST_LINES: usize = 48;
+
+/// A line of the burst.
+const BURST_LINE: &str = "UartLoopback: burst 0123456789abcdefghijklmnopqrstuvwxyz\r\n";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Stream,
+    Abort,
+}
+
+pub unsafe fn run_uart_loopback(
+    uarte: &'static Uarte<'static>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.uart_loopback.as_ref() else {
+        skip(client, "UartLoopback: no loopback pins configured");
+        return;
+    };
+    uarte.initialize(
+        Pinmux::new(pins.output as u32),
+        Pinmux::new(pins.input as u32),
+        None,
+        None,
+    );
+
+    // Byte `i` of the stream is `i as u8`, so a lost byte shifts the ones
+    // after it.
+    let tx_buffer = static_init!([u8; STREAM_LEN], [0; STREAM_LEN]);
+    for (i, byte) in tx_buffer.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let rx_buffer = static_init!([u8; STREAM_LEN], [0; STREAM_LEN]);
+    let test = static_init!(
+        TestUartLoopback,
+        TestUartLoopback::new(uarte, tx_buffer, rx_buffer)
+    );
+    uarte.set_transmit_client(test);
+    uarte.set_receive_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestUartLoopback {
+    uarte: &'static Uarte<'static>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    /// Whether each side saw the end of the `Stream` case.
+    transmitted: Cell<bool>,
+    received: Cell<bool>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestUartLoopback {
+    pub fn new(
+        uarte: &'static Uarte<'static>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> Self {
+        TestUartLoopback {
+            uarte,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            transmitted: Cell::new(false),
+            received: Cell::new(false),
+            step: Cell::new(Step::Stream),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let result = self
+            .uarte
+            .configure(uart::Parameters {
+                baud_rate: BAUD_RATE,
+                width: uart::Width::Eight,
+                parity: uart::Parity::None,
+                stop_bits: uart::StopBits::One,
+                hw_flow_control: false,
+            })
+            .map_err(|_| "UARTE not configured")
+            .and_then(|()| self.stream(STREAM_LEN));
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+
+    /// Receives into the whole buffer, transmits the first `len` bytes of the
+    /// stream and prints a burst of debug output.
+    fn stream(&self, len: usize) -> Result<(), &'static str> {
+        let (Some(tx), Some(rx)) = (self.tx_buffer.take(), self.rx_buffer.take()) else {
+            return Err("buffers missing");
+        };
+        rx.fill(0);
+        self.uarte
+            .receive_buffer(rx, STREAM_LEN)
+            .map_err(|_| "receive rejected")?;
+        self.uarte
+            .transmit_buffer(tx, len)
+            .map_err(|_| "transmit rejected")?;
+        self.burst()
+    }
+
+    /// Prints the burst, checking that the debug writer counts the bytes of
+    /// each line that do not fit in its buffer as dropped.
+    fn burst(&self) -> Result<(), &'static str> {
+        let start = debug_dropped_len();
+        let mut expected = 0;
+        for _ in 0..BURST_LINES {
+            expected += BURST_LINE.len().saturating_sub(debug_available_len());
+            debug_print(format_args!("{}", BURST_LINE));
+        }
+        if debug_dropped_len() - start != expected {
+            return Err("dropped debug bytes not counted");
+        }
+        Ok(())
+    }
+
+    /// Starts the `Abort` case once both sides saw the end of the stream.
+    fn check_streamed(&self) -> Result<(), &'static str> {
+        if !(self.transmitted.get() && self.received.get()) {
+            return Ok(());
+        }
+        self.step.set(Step::Abort);
+        self.stream(PARTIAL_LEN)
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("UartLoopback: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.uarte.receive_abort();
+        self.uarte.disable_uart();
+        report(&self.client, "UartLoopback", result);
+    }
+}
+
+impl TransmitClient for TestUartLoopback {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(tx_buffer);
+        if self.finished.get() {
+            return;
+        }
+        let result = rval.map_err(|_| "transmit failed").and_then(|()| {
+            match self.step.get() {
+                Step::Stream => {
+                    self.transmitted.set(true);
+                    self.check_streamed()
+                }
+                // The abort is pending until the receiver stopped, after the
+                // last byte arrived.
+                Step::Abort => match self.uarte.receive_abort() {
+                    Err(ErrorCode::BUSY) => Ok(()),
+                    _ => Err("receive ended before the abort"),
+                },
+            }
+        });
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+}
+
+impl ReceiveClient for TestUartLoopback {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        let (expected_len, expected_rval) = match self.step.get() {
+            Step::Stream => (STREAM_LEN, Ok(())),
+            Step::Abort => (PARTIAL_LEN, Err(ErrorCode::CANCEL)),
+        };
+        let data = rx_buffer[..expected_len]
+            .iter()
+            .enumerate()
+            .all(|(i, &byte)| byte == i as u8);
+        self.rx_buffer.replace(rx_buffer);
+        if self.finished.get() {
+            return;
+        }
+        debug!("UartLoopback: received {} bytes", rx_len);
+        let result = if rval != expected_rval {
+            Err("receive reported the wrong status")
+        } else if rx_len != expected_len || !data {
+            Err("bytes lost")
+        } else if self.step.get() == Step::Abort {
+            self.finish(Ok(()));
+            Ok(())
+        } else {
+            self.received.set(true);
+            self.check_streamed()
+        };
+        if let Err(reason) = result {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestUartLoopback {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,482 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Exchanges one UDP message with a second DK listed in
+//! `BOARD_TEST_CONFIG.radio_peer`, over the 802.15.4, 6LoWPAN, IPv6 and UDP
+//! stack of the kernel with link layer security, as an integration test of
+//! the radio, AES-CCM* on the ECB peripheral and the timers together.
+//!
+//! OpenThread runs in userspace, which this board does not load, so the test
+//! does not form a Thread network. It covers the layers of the kernel that
+//! Thread traffic goes through instead. The cases are:
+//!
+//! 1. `Exchange`: the initiator sends a request to the UDP port of the
+//!    responder every 500 ms, until the responder, which may still be running
+//!    earlier tests, answers. The responder answers the first request, to the
+//!    address and port it came from. Request and answer must arrive with
+//!    their payload, from the link-local address and the port of the peer.
+//! 2. `Security`: each board looked the link key up at least twice, to
+//!    secure the frame it sent and to check the frame it received.
+//!
+//! The expected output on both boards ends with
+//! UdpSmoke: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ieee802154::device::MacDevice;
+use capsules_extra::ieee802154::framer::{Framer, KeyProcedure};
+use capsules_extra::ieee802154::mac::{AwakeMac, Mac};
+use capsules_extra::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules_extra::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use capsules_extra::net::ipv6::ip_utils::IPAddr;
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules_extra::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules_extra::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::sixlowpan::sixlowpan_compression::Context;
+use capsules_extra::net::sixlowpan::sixlowpan_state::{
+    RxState, Sixlowpan, SixlowpanState, TxState,
+};
+use capsules_extra::net::udp::udp_port_table::{
+    SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS,
+};
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver, UDPRecvClient};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendClient, UDPSendStruct, UDPSender};
+use capsules_extra::net::udp::UDPHeader;
+use kernel::capabilities::{CreatePortTableCapability, NetworkCapabilityCreationCapability};
+use kernel::component::Component;
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::symmetric_encryption::AES128CCM;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{create_capability, debug, static_buf, static_init, ErrorCode};
+use nrf52840::aes::AesECB;
+use nrf52840::ieee802154_radio::Radio;
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::new_alarm;
+use crate::test::config::{PeerRole, BOARD_TEST_CONFIG};
+use crate::test::registry::{report, skip};
+
+type VirtualAlarm = VirtualMuxAlarm<'static, Rtc<'static>>;
+type TestFramer =
+    Framer<'static, AwakeMac<'static, Radio<'static>>, VirtualAES128CCM<'static, AesECB<'static>>>;
+type TestIp6Sender = IP6SendStruct<'static, VirtualAlarm>;
+
+const PAN: u16 = 0x7e57;
+const INITIATOR_ADDRESS: u16 = 0x0001;
+const RESPONDER_ADDRESS: u16 = 0x0002;
+const INITIATOR_LONG_ADDRESS: [u8; 8] = [0x7e, 0x57, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+const RESPONDER_LONG_ADDRESS: [u8; 8] = [0x7e, 0x57, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02];
+
+/// UDP port both boards bind.
+const PORT: u16 = 15411;
+
+/// Link key, with key index 1 as Thread uses for its first key sequence.
+const KEY: [u8; 16] = *b"tock udp smoke!!";
+const KEY_ID: KeyId = KeyId::Index(1);
+const SECURITY_LEVEL: SecurityLevel = SecurityLevel::EncMic32;
+
+const REQUEST: &[u8] = b"request";
+const ANSWER: &[u8] = b"answer";
+
+/// Time between two requests of the initiator.
+const REQUEST_INTERVAL_MS: u32 = 500;
+
+/// Requests the initiator sends, for up to 30 seconds.
+const MAX_REQUESTS: u32 = 60;
+
+/// Time the responder waits for a request.
+const RESPONDER_TIMEOUT_MS: u32 = 60_000;
+
+/// Size of the UDP payload buffers.
+const DGRAM_LEN: usize = 200;
+
+/// Size of the buffer 6LoWPAN reassembles received packets in.
+const REASSEMBLY_LEN: usize = 1280;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Exchange,
+    Security,
+}
+
+pub unsafe fn run_udp_smoke(
+    radio: &'static Radio<'static>,
+    ecb: &'static AesECB<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(peer) = BOARD_TEST_CONFIG.radio_peer.as_ref() else {
+        skip(client, "UdpSmoke: no peer board configured");
+        return;
+    };
+    let (address, long_address, peer_long_address) = match peer.role {
+        PeerRole::Initiator => (
+            INITIATOR_ADDRESS,
+            INITIATOR_LONG_ADDRESS,
+            RESPONDER_LONG_ADDRESS,
+        ),
+        PeerRole::Responder => (
+            RESPONDER_ADDRESS,
+            RESPONDER_LONG_ADDRESS,
+            INITIATOR_LONG_ADDRESS,
+        ),
+    };
+    let local_ip = IPAddr::generate_from_mac(MacAddress::Long(long_address));
+    let peer_ip = IPAddr::generate_from_mac(MacAddress::Long(peer_long_address));
+
+    // 802.15.4: AwakeMac, the framer that secures frames with AES-CCM*, and
+    // a MAC user for 6LoWPAN.
+    let aes_mux = components::ieee802154::MuxAes128ccmComponent::new(ecb)
+        .finalize(components::mux_aes128ccm_component_static!(AesECB));
+    let aes_ccm = static_init!(
+        VirtualAES128CCM<'static, AesECB<'static>>,
+        VirtualAES128CCM::new(
+            aes_mux,
+            static_init!(
+                [u8; components::ieee802154::CRYPT_SIZE],
+                [0; components::ieee802154::CRYPT_SIZE]
+            )
+        )
+    );
+    aes_ccm.setup();
+
+    let awake_mac = static_init!(AwakeMac<'static, Radio<'static>>, AwakeMac::new(radio));
+    radio.set_transmit_client(awake_mac);
+    radio.set_receive_client(awake_mac);
+    radio.set_receive_buffer(static_init!(
+        [u8; radio::MAX_BUF_SIZE],
+        [0; radio::MAX_BUF_SIZE]
+    ));
+    radio.set_channel(peer.channel);
+
+    let framer = static_init!(
+        TestFramer,
+        Framer::new(
+            awake_mac,
+            aes_ccm,
+            SubSliceMut::new(static_init!(
+                [u8; radio::MAX_BUF_SIZE],
+                [0; radio::MAX_BUF_SIZE]
+            ))
+        )
+    );
+    AES128CCM::set_client(aes_ccm, framer);
+    awake_mac.set_transmit_client(framer);
+    awake_mac.set_receive_client(framer);
+    awake_mac.set_config_client(framer);
+
+    let mux_mac = static_init!(MuxMac<'static, TestFramer>, MuxMac::new(framer));
+    framer.set_transmit_client(mux_mac);
+    framer.set_receive_client(mux_mac);
+    let mac_user = static_init!(MacUser<'static, TestFramer>, MacUser::new(mux_mac));
+    mux_mac.add_user(mac_user);
+    mac_user.set_pan(PAN);
+    mac_user.set_address(address);
+    mac_user.set_address_long(long_address);
+
+    // 6LoWPAN and IPv6, without header compression contexts.
+    let ip_alarm = new_alarm(mux_alarm, static_buf!(VirtualAlarm));
+    let sixlowpan = static_init!(
+        Sixlowpan<'static, VirtualAlarm, Context>,
+        Sixlowpan::new(
+            Context {
+                prefix: [0; 16],
+                prefix_len: 0,
+                id: 0,
+                compress: false,
+            },
+            ip_alarm
+        )
+    );
+    let rx_state = static_init!(
+        RxState<'static>,
+        RxState::new(static_init!([u8; REASSEMBLY_LEN], [0; REASSEMBLY_LEN]))
+    );
+    sixlowpan.add_rx_state(rx_state);
+    mac_user.set_receive_client(sixlowpan);
+
+    let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+    let ip_vis = static_init!(
+        IpVisibilityCapability,
+        IpVisibilityCapability::new(&create_cap)
+    );
+    let udp_vis = static_init!(
+        UdpVisibilityCapability,
+        UdpVisibilityCapability::new(&create_cap)
+    );
+    let net_cap = static_init!(
+        NetworkCapability,
+        NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+    );
+
+    let ip6_packet = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload {
+            header: TransportHeader::UDP(UDPHeader::new()),
+            payload: static_init!([u8; DGRAM_LEN], [0; DGRAM_LEN]),
+        })
+    );
+    let ip_send = static_init!(
+        TestIp6Sender,
+        IP6SendStruct::new(
+            ip6_packet,
+            ip_alarm,
+            static_init!([u8; radio::MAX_BUF_SIZE], [0; radio::MAX_BUF_SIZE]),
+            TxState::new(sixlowpan),
+            mac_user,
+            MacAddress::Long(peer_long_address),
+            MacAddress::Long(long_address),
+            ip_vis
+        )
+    );
+    ip_alarm.set_alarm_client(ip_send);
+    ip_send.set_addr(local_ip);
+    ip_send.set_security(Some((SECURITY_LEVEL, KEY_ID)));
+    mac_user.set_transmit_client(ip_send);
+
+    let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+    sixlowpan.set_rx_client(ip_receive);
+
+    // UDP, with one socket bound to `PORT`.
+    let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
+    ip_receive.set_client(udp_recv_mux);
+    let udp_send_mux = static_init!(
+        MuxUdpSender<'static, TestIp6Sender>,
+        MuxUdpSender::new(ip_send)
+    );
+    ip_send.set_client(udp_send_mux);
+    let port_table = static_init!(
+        UdpPortManager,
+        UdpPortManager::new(
+            &create_capability!(CreatePortTableCapability),
+            static_init!(
+                [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS],
+                [None; MAX_NUM_BOUND_PORTS]
+            ),
+            udp_vis
+        )
+    );
+    let udp_send = static_init!(
+        UDPSendStruct<'static, TestIp6Sender>,
+        UDPSendStruct::new(udp_send_mux, udp_vis)
+    );
+    let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+    udp_recv_mux.add_client(udp_recv);
+
+    let alarm = new_alarm(mux_alarm, static_buf!(VirtualAlarm));
+    let test = static_init!(
+        TestUdpSmoke,
+        TestUdpSmoke::new(
+            peer.role,
+            radio,
+            udp_send,
+            alarm,
+            SubSliceMut::new(static_init!([u8; DGRAM_LEN], [0; DGRAM_LEN])),
+            peer_ip,
+            net_cap
+        )
+    );
+    alarm.set_alarm_client(test);
+    framer.set_key_procedure(test);
+    udp_send.set_client(test);
+    udp_recv.set_client(test);
+    test.set_client(client);
+
+    let bound = port_table
+        .create_socket()
+        .ok()
+        .and_then(|socket| port_table.bind(socket, PORT, net_cap).ok());
+    let Some((send_binding, receive_binding)) = bound else {
+        test.fail("port not bound");
+        return;
+    };
+    udp_send.set_binding(send_binding);
+    udp_recv.set_binding(receive_binding);
+
+    if awake_mac.start().is_err() {
+        test.fail("radio did not start");
+        return;
+    }
+    test.run();
+}
+
+pub struct TestUdpSmoke {
+    role: PeerRole,
+    radio: &'static Radio<'static>,
+    udp_send: &'static UDPSendStruct<'static, TestIp6Sender>,
+    alarm: &'static VirtualAlarm,
+    dgram: MapCell<SubSliceMut<'static, u8>>,
+    peer_ip: IPAddr,
+    net_cap: &'static NetworkCapability,
+    requests: Cell<u32>,
+    key_lookups: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestUdpSmoke {
+    pub fn new(
+        role: PeerRole,
+        radio: &'static Radio<'static>,
+        udp_send: &'static UDPSendStruct<'static, TestIp6Sender>,
+        alarm: &'static VirtualAlarm,
+        dgram: SubSliceMut<'static, u8>,
+        peer_ip: IPAddr,
+        net_cap: &'static NetworkCapability,
+    ) -> Self {
+        TestUdpSmoke {
+            role,
+            radio,
+            udp_send,
+            alarm,
+            dgram: MapCell::new(dgram),
+            peer_ip,
+            net_cap,
+            requests: Cell::new(0),
+            key_lookups: Cell::new(0),
+            step: Cell::new(Step::Exchange),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        match self.role {
+            PeerRole::Initiator => self.request(),
+            PeerRole::Responder => self.alarm.set_alarm(
+                self.alarm.now(),
+                self.alarm.ticks_from_ms(RESPONDER_TIMEOUT_MS),
+            ),
+        }
+    }
+
+    /// Sends a request, unless the previous one is still being sent, and
+    /// schedules the next one.
+    fn request(&self) {
+        if self.requests.get() == MAX_REQUESTS {
+            self.fail("no answer");
+            return;
+        }
+        self.requests.set(self.requests.get() + 1);
+        // The initiator tries again with the next request if this one fails.
+        let _ = self.send(self.peer_ip, REQUEST);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(REQUEST_INTERVAL_MS),
+        );
+    }
+
+    fn send(&self, destination: IPAddr, payload: &[u8]) -> Result<(), ErrorCode> {
+        let mut dgram = self.dgram.take().ok_or(ErrorCode::BUSY)?;
+        dgram[..payload.len()].copy_from_slice(payload);
+        dgram.slice(0..payload.len());
+        self.udp_send
+            .send_to(destination, PORT, dgram, self.net_cap)
+            .map_err(|mut dgram| {
+                dgram.reset();
+                self.dgram.replace(dgram);
+                ErrorCode::FAIL
+            })
+    }
+
+    fn check_security(&self) {
+        self.step.set(Step::Security);
+        if self.key_lookups.get() < 2 {
+            self.fail("frames not secured");
+        } else {
+            self.finish(Ok(()));
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("UdpSmoke: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        let _ = self.radio.stop();
+        report(&self.client, "UdpSmoke", result);
+    }
+}
+
+impl UDPRecvClient for TestUdpSmoke {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        if self.finished.get() {
+            return;
+        }
+        let expected = match self.role {
+            PeerRole::Initiator => ANSWER,
+            PeerRole::Responder => REQUEST,
+        };
+        if src_addr != self.peer_ip || src_port != PORT {
+            self.fail("message from another sender");
+        } else if payload != expected {
+            self.fail("payload differs");
+        } else if self.role == PeerRole::Initiator {
+            self.check_security();
+        } else if self.send(src_addr, ANSWER).is_err() {
+            self.fail("answer not sent");
+        }
+    }
+}
+
+impl UDPSendClient for TestUdpSmoke {
+    fn send_done(&self, result: Result<(), ErrorCode>, mut dgram: SubSliceMut<'static, u8>) {
+        dgram.reset();
+        self.dgram.replace(dgram);
+        if self.finished.get() || self.role == PeerRole::Initiator {
+            return;
+        }
+        if result.is_err() {
+            self.fail("answer not sent");
+        } else {
+            self.check_security();
+        }
+    }
+}
+
+impl KeyProcedure for TestUdpSmoke {
+    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        self.key_lookups.set(self.key_lookups.get() + 1);
+        (level == SECURITY_LEVEL && key_id == KEY_ID).then_some(KEY)
+    }
+}
+
+impl AlarmClient for TestUdpSmoke {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        match self.role {
+            PeerRole::Initiator => self.request(),
+            PeerRole::Responder => self.fail("no request"),
+        }
+    }
+}
+
+impl CapsuleTest for TestUdpSmoke {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,242 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the order in which upcalls queued by different capsules reach an
+//! app. An embedded app subscribes to two upcalls of one report driver and one
+//! upcall of a second report driver, and logs the argument of every upcall it
+//! gets. The kernel keeps a single FIFO of pending tasks per process and does
+//! not coalesce upcalls: an upcall scheduled while an earlier one for the same
+//! subscription is pending is delivered as well. Subscribing again cancels the
+//! pending upcalls of that subscription, as TRD104 requires. The cases are:
+//!
+//! 1. `Cancel`: an upcall the app asked for before subscribing again is never
+//!    delivered.
+//! 2. `Order`: upcalls queued alternately by both drivers, twice for the same
+//!    subscription, are all delivered in the order they were queued.
+//!
+//! The expected output ends with
+//! UpcallOrder: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::{Process, State};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppLoader, UPCALL_LOG_OFFSET, UPCALL_ORDER_APP};
+use crate::test::registry::report;
+use crate::test::report_driver::ReportDriver;
+
+/// Time the app gets to reach each step.
+const STEP_MS: u32 = 20;
+
+/// Argument of the upcall the app asks for and then cancels.
+const CANCELLED: u32 = 9;
+
+/// Upcalls the test queues, as the driver, the subscribe number and the
+/// argument. The arguments are the order they must be delivered in.
+const QUEUED: [(Driver, usize, u32); 5] = [
+    (Driver::First, 0, 1),
+    (Driver::Second, 0, 2),
+    (Driver::First, 1, 3),
+    (Driver::Second, 0, 4),
+    (Driver::First, 0, 5),
+];
+
+#[derive(Clone, Copy)]
+enum Driver {
+    First,
+    Second,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Cancel,
+    Order,
+}
+
+pub unsafe fn run_upcall_order(
+    apps: &'static AppLoader,
+    first: &'static ReportDriver,
+    second: &'static ReportDriver,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestUpcallOrder,
+        TestUpcallOrder::new(apps, first, second, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestUpcallOrder {
+    apps: &'static AppLoader,
+    first: &'static ReportDriver,
+    second: &'static ReportDriver,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    process: OptionalCell<&'static dyn Process>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestUpcallOrder {
+    pub fn new(
+        apps: &'static AppLoader,
+        first: &'static ReportDriver,
+        second: &'static ReportDriver,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestUpcallOrder {
+            apps,
+            first,
+            second,
+            alarm,
+            process: OptionalCell::empty(),
+            step: Cell::new(Step::Cancel),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.first.reset();
+        self.second.reset();
+        let process = match self.apps.load(&UPCALL_ORDER_APP) {
+            Ok(Some(process)) => process,
+            _ => {
+                self.fail("no process created");
+                return;
+            }
+        };
+        self.process.set(process);
+        self.wait();
+    }
+
+    fn wait(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(STEP_MS));
+    }
+
+    /// Returns the number of upcalls the app logged.
+    fn logged(&self, process: &dyn Process) -> usize {
+        let ram = process.get_addresses().sram_start;
+        // SAFETY: the count is in the process's RAM, which the kernel may
+        // access at any time. The app only runs while the kernel does not.
+        unsafe { core::ptr::read_volatile((ram + UPCALL_LOG_OFFSET - 4) as *const u32) as usize }
+    }
+
+    /// Returns the argument of the `index`th upcall the app logged.
+    fn log_entry(&self, process: &dyn Process, index: usize) -> u32 {
+        let ram = process.get_addresses().sram_start;
+        // SAFETY: as in `logged()`. The log holds at most `QUEUED.len()`
+        // entries, well within the app's RAM.
+        unsafe { core::ptr::read_volatile((ram + UPCALL_LOG_OFFSET + 4 * index) as *const u32) }
+    }
+
+    fn check(&self, process: &dyn Process) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Cancel => {
+                if !self.first.reports().eq([(0, 0)]) {
+                    return Err("app did not report ready");
+                }
+                if process.get_state() != State::Yielded {
+                    return Err("app not blocked in yield-wait");
+                }
+                if self.logged(process) != 0 {
+                    return Err("cancelled upcall delivered");
+                }
+
+                self.step.set(Step::Order);
+                for (driver, subscribe_num, value) in QUEUED {
+                    let driver = match driver {
+                        Driver::First => self.first,
+                        Driver::Second => self.second,
+                    };
+                    driver
+                        .notify(process.processid(), subscribe_num, value as usize)
+                        .map_err(|_| "upcall not scheduled")?;
+                }
+                self.wait();
+            }
+            Step::Order => {
+                let logged = self.logged(process);
+                debug!("UpcallOrder: app logged {} upcalls", logged);
+                for index in 0..logged.min(QUEUED.len()) {
+                    debug!(
+                        "UpcallOrder: upcall {} with {}",
+                        index,
+                        self.log_entry(process, index)
+                    );
+                }
+                if (0..logged.min(QUEUED.len()))
+                    .any(|index| self.log_entry(process, index) == CANCELLED)
+                {
+                    return Err("cancelled upcall delivered");
+                }
+                if logged != QUEUED.len() {
+                    return Err("upcalls coalesced or lost");
+                }
+                if !(0..logged)
+                    .map(|index| self.log_entry(process, index))
+                    .eq(QUEUED.map(|(_, _, value)| value))
+                {
+                    return Err("upcalls delivered out of order");
+                }
+
+                let process_management_cap =
+                    create_capability!(capabilities::ProcessManagementCapability);
+                process.terminate(None);
+                self.apps
+                    .kernel()
+                    .remove_process(process.processid(), &process_management_cap)
+                    .map_err(|_| "terminated process not removed")?;
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("UpcallOrder: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "UpcallOrder", result);
+    }
+}
+
+impl AlarmClient for TestUpcallOrder {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let Some(process) = self.process.get() else {
+            return;
+        };
+        if let Err(reason) = self.check(process) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestUpcallOrder {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
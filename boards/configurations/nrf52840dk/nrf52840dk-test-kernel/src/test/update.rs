@@ -0,0 +1,430 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Replaces the test kernel over the UART, so a board in a test rig can be
+//! re-imaged without a debug probe.
+//!
+//! The flash holds two kernels. Bank A, at the start of flash, is the kernel
+//! flashed with the probe and is never overwritten. Bank B, at `BANK_B`, holds
+//! the kernel received last, built with the `bank_b` feature to run from
+//! there. A record in the page after bank B tells bank A that bank B holds a
+//! complete image.
+//!
+//! At boot, before it sets anything up, bank A starts bank B if the record is
+//! valid. It marks the attempt in GPREGRET, which survives every reset but a
+//! power-on reset, and bank B clears the mark once the tests start. If the
+//! board resets while the mark is still set, by the watchdog or the reset pin,
+//! bank B did not come up: bank A runs its own tests instead, until the board
+//! loses power or receives a new image, and prints
+//!
+//! ```text
+//! Update: bank B did not start, running bank A
+//! ```
+//!
+//! An image is sent during the window for UART commands after `Test seed:`,
+//! with a line
+//!
+//! ```text
+//! update <length> <crc32>
+//! ```
+//!
+//! of the decimal length and the hexadecimal CRC-32 of the raw image. Bank B
+//! cannot overwrite itself, so it restarts in bank A, which prints its seed
+//! again, and the command has to be repeated. Bank A erases the record, then
+//! asks for each page with `Update: send page <n>` and writes it to bank B.
+//! Once the CRC-32 of bank B matches, it writes the record and restarts:
+//!
+//! ```text
+//! Update: image written, starting bank B
+//! ```
+//!
+//! Otherwise it prints `Update: failed: <reason>` and restarts in bank A.
+//! No tests run while an image is received.
+
+use core::cell::Cell;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use kernel::debug;
+use kernel::hil::flash::{self, Flash, HasClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::hil::uart::{self, Receive};
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::nvmc::{NrfPage, Nvmc};
+use nrf52840::power::Power;
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::flash_log::{FlashLog, FlashLogClient};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Start and length of bank B, where `layout_bank_b.ld` links the kernel.
+const BANK_B: usize = 0x0004_0000;
+const BANK_LEN: usize = 0x0004_0000;
+
+/// Page of the record that bank B holds a complete image.
+const RECORD_PAGE: usize = (BANK_B + BANK_LEN) / PAGE_SIZE;
+
+const RECORD_MAGIC: u32 = 0x4B42_4B54;
+
+/// Values of GPREGRET. Bank B started and has not reached the tests yet.
+const TRYING: u8 = 0xB1;
+/// Bank B did not reach the tests, so bank A runs.
+const FALLBACK: u8 = 0xB2;
+/// Bank A is to run once, to receive an image.
+const STAY: u8 = 0xB3;
+
+/// Time the last message gets to reach the UART before a restart.
+const RESTART_MS: u32 = 100;
+
+/// The record in `RECORD_PAGE`.
+struct Record {
+    magic: u32,
+    len: u32,
+    crc: u32,
+}
+
+impl Record {
+    fn read() -> Record {
+        let words = (RECORD_PAGE * PAGE_SIZE) as *const u32;
+        // SAFETY: the record page is in flash, which is always readable.
+        unsafe {
+            Record {
+                magic: words.read_volatile(),
+                len: words.add(1).read_volatile(),
+                crc: words.add(2).read_volatile(),
+            }
+        }
+    }
+
+    /// Whether bank B holds the image the record describes, which looks like
+    /// it can start.
+    fn valid(&self) -> bool {
+        if self.magic != RECORD_MAGIC || self.len == 0 || self.len as usize > BANK_LEN {
+            return false;
+        }
+        let vectors = BANK_B as *const u32;
+        // SAFETY: bank B is in flash, which is always readable.
+        let (stack, reset) = unsafe { (vectors.read_volatile(), vectors.add(1).read_volatile()) };
+        (0x2000_0000..=0x2004_0000).contains(&stack)
+            && (BANK_B..BANK_B + BANK_LEN).contains(&(reset as usize))
+            && crc32(bank_b(self.len as usize)) == self.crc
+    }
+}
+
+/// The first `len` bytes of bank B.
+fn bank_b(len: usize) -> &'static [u8] {
+    // SAFETY: bank B is in flash, which is always readable, and only this
+    // module writes to it, while nothing reads it.
+    unsafe { core::slice::from_raw_parts(BANK_B as *const u8, len) }
+}
+
+/// CRC-32 (IEEE) of `data`, as the runner computes it.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Starts the kernel in bank B if it holds one that is not known to fail,
+/// and this is bank A. Called first thing at boot, before the kernel touches
+/// any peripheral.
+pub unsafe fn start_bank_b() {
+    if cfg!(feature = "bank_b") {
+        return;
+    }
+    let power = Power::new();
+    match power.get_gpregret() {
+        TRYING => {
+            power.set_gpregret(FALLBACK);
+            return;
+        }
+        FALLBACK | STAY => return,
+        _ => (),
+    }
+    if !Record::read().valid() {
+        return;
+    }
+    power.set_gpregret(TRYING);
+
+    // The reset handler of bank B sets up its RAM and its vector table, as
+    // after a reset.
+    let vectors = BANK_B as *const u32;
+    let stack = vectors.read_volatile();
+    let reset = vectors.add(1).read_volatile();
+    core::arch::asm!(
+        "msr msp, {stack}",
+        "bx {reset}",
+        stack = in(reg) stack,
+        reset = in(reg) reset,
+        options(noreturn),
+    );
+}
+
+/// Prints which bank runs, if that is not the usual one. Bank B clears the
+/// mark that it started.
+pub fn report_bank(power: &Power) {
+    if cfg!(feature = "bank_b") {
+        debug!("Update: running bank B");
+        return;
+    }
+    match power.get_gpregret() {
+        FALLBACK => debug!("Update: bank B did not start, running bank A"),
+        STAY => {
+            debug!("Update: running bank A to receive an image");
+            power.set_gpregret(0);
+        }
+        _ => (),
+    }
+}
+
+/// Clears the mark that bank B started, once it reached the tests.
+pub fn confirm(power: &Power) {
+    if cfg!(feature = "bank_b") && power.get_gpregret() == TRYING {
+        power.set_gpregret(0);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Waiting for the flash log to let go of the NVMC.
+    Waiting,
+    Erasing,
+    Receiving,
+    Recording,
+    Restarting,
+}
+
+pub unsafe fn new_updater(
+    nvmc: &'static Nvmc,
+    power: &'static Power<'static>,
+    flash_log: &'static FlashLog,
+    uart_mux: &'static MuxUart<'static>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+) -> &'static Updater {
+    let uart = static_init!(UartDevice<'static>, UartDevice::new(uart_mux, true));
+    uart.setup();
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let rx_buffer = static_init!([u8; PAGE_SIZE], [0; PAGE_SIZE]);
+    let page = static_init!(NrfPage, NrfPage::default());
+    let updater = static_init!(
+        Updater,
+        Updater::new(nvmc, power, flash_log, uart, alarm, rx_buffer, page)
+    );
+    uart.set_receive_client(updater);
+    alarm.set_alarm_client(updater);
+    updater
+}
+
+pub struct Updater {
+    nvmc: &'static Nvmc,
+    power: &'static Power<'static>,
+    flash_log: &'static FlashLog,
+    uart: &'static UartDevice<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    page: TakeCell<'static, NrfPage>,
+    len: Cell<usize>,
+    crc: Cell<u32>,
+    /// Bytes written to bank B so far.
+    written: Cell<usize>,
+    state: Cell<State>,
+}
+
+impl Updater {
+    pub fn new(
+        nvmc: &'static Nvmc,
+        power: &'static Power<'static>,
+        flash_log: &'static FlashLog,
+        uart: &'static UartDevice<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+        rx_buffer: &'static mut [u8],
+        page: &'static mut NrfPage,
+    ) -> Self {
+        Updater {
+            nvmc,
+            power,
+            flash_log,
+            uart,
+            alarm,
+            rx_buffer: TakeCell::new(rx_buffer),
+            page: TakeCell::new(page),
+            len: Cell::new(0),
+            crc: Cell::new(0),
+            written: Cell::new(0),
+            state: Cell::new(State::Idle),
+        }
+    }
+
+    /// Starts receiving an image of `len` bytes with CRC-32 `crc`, or
+    /// returns why the image cannot be received. Once started, the board
+    /// restarts when it is done.
+    pub fn start(&'static self, len: usize, crc: u32) -> Result<(), &'static str> {
+        if self.state.get() != State::Idle {
+            return Err("already receiving an image");
+        }
+        if len == 0 || len > BANK_LEN {
+            return Err("the image does not fit bank B");
+        }
+        if cfg!(feature = "bank_b") {
+            debug!("Update: restarting in bank A to receive the image");
+            self.power.set_gpregret(STAY);
+            self.restart();
+            return Ok(());
+        }
+        self.len.set(len);
+        self.crc.set(crc);
+        self.state.set(State::Waiting);
+        if self.flash_log.suspend(self) {
+            self.erase_record();
+        }
+        Ok(())
+    }
+
+    /// Erases the record first, so bank B is not started while it holds part
+    /// of an image.
+    fn erase_record(&'static self) {
+        self.nvmc.set_client(self);
+        self.state.set(State::Erasing);
+        if self.nvmc.erase_page(RECORD_PAGE).is_err() {
+            self.fail("cannot erase the record");
+        }
+    }
+
+    fn receive_page(&self) {
+        let written = self.written.get();
+        let len = (self.len.get() - written).min(PAGE_SIZE);
+        self.state.set(State::Receiving);
+        debug!("Update: send page {}", written / PAGE_SIZE);
+        self.rx_buffer.take().map(|buffer| {
+            if let Err((_, buffer)) = self.uart.receive_buffer(buffer, len) {
+                self.rx_buffer.replace(buffer);
+                self.fail("cannot receive");
+            }
+        });
+    }
+
+    fn write_record(&self) {
+        if crc32(bank_b(self.len.get())) != self.crc.get() {
+            self.fail("CRC-32 mismatch");
+            return;
+        }
+        self.page.take().map(|page| {
+            page.0.fill(0xFF);
+            for (i, word) in [RECORD_MAGIC, self.len.get() as u32, self.crc.get()]
+                .iter()
+                .enumerate()
+            {
+                page.0[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+            }
+            self.state.set(State::Recording);
+            if let Err((_, page)) = self.nvmc.write_page(RECORD_PAGE, page) {
+                self.page.replace(page);
+                self.fail("cannot write the record");
+            }
+        });
+    }
+
+    /// Gives up on the image and restarts in bank A, which can receive
+    /// another.
+    fn fail(&self, reason: &str) {
+        debug!("Update: failed: {}", reason);
+        self.power.set_gpregret(STAY);
+        self.restart();
+    }
+
+    fn restart(&self) {
+        self.state.set(State::Restarting);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RESTART_MS));
+    }
+}
+
+impl FlashLogClient for Updater {
+    fn log_idle(&'static self) {
+        self.erase_record();
+    }
+}
+
+impl uart::ReceiveClient for Updater {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        rcode: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        if self.state.get() != State::Receiving {
+            self.rx_buffer.replace(buffer);
+            return;
+        }
+        if rcode.is_err() {
+            self.rx_buffer.replace(buffer);
+            self.fail("cannot receive");
+            return;
+        }
+        let written = self.written.get();
+        self.page.take().map(|page| {
+            page.0.fill(0xFF);
+            page.0[..rx_len].copy_from_slice(&buffer[..rx_len]);
+            self.written.set(written + rx_len);
+            if let Err((_, page)) = self.nvmc.write_page((BANK_B + written) / PAGE_SIZE, page) {
+                self.page.replace(page);
+                self.fail("cannot write bank B");
+            }
+        });
+        self.rx_buffer.replace(buffer);
+    }
+}
+
+impl flash::Client<Nvmc> for Updater {
+    fn read_complete(&self, page: &'static mut NrfPage, _result: Result<(), flash::Error>) {
+        self.page.replace(page);
+    }
+
+    fn write_complete(&self, page: &'static mut NrfPage, result: Result<(), flash::Error>) {
+        self.page.replace(page);
+        if result.is_err() {
+            self.fail("cannot write bank B");
+            return;
+        }
+        match self.state.get() {
+            State::Receiving if self.written.get() < self.len.get() => self.receive_page(),
+            State::Receiving => self.write_record(),
+            State::Recording => {
+                debug!("Update: image written, starting bank B");
+                self.power.set_gpregret(0);
+                self.restart();
+            }
+            _ => (),
+        }
+    }
+
+    fn erase_complete(&self, result: Result<(), flash::Error>) {
+        if result.is_err() {
+            self.fail("cannot erase the record");
+            return;
+        }
+        self.written.set(0);
+        self.receive_page();
+    }
+}
+
+impl AlarmClient for Updater {
+    fn alarm(&self) {
+        if self.state.get() == State::Restarting {
+            unsafe { cortexm4::scb::reset() };
+        }
+    }
+}
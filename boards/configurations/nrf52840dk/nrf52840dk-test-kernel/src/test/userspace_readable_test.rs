@@ -0,0 +1,236 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks userspace readable allow with the read-only state driver. An
+//! embedded app allows a region to the driver and then, without any other
+//! system call, copies the context switch count the driver writes there to a
+//! word next to it. The cases are:
+//!
+//! 1. `Observe`: the app sees the count grow, and has made a single system
+//!    call.
+//! 2. `Terminated`: the driver leaves the region of a terminated process
+//!    alone.
+//! 3. `Restart`: the restarted app allows the region again and sees a count
+//!    that starts over, rather than the count of the previous process.
+//!
+//! The expected output ends with
+//! UserspaceReadable: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::Process;
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppLoader, COPIED_STATE_OFFSET, READ_ONLY_STATE_APP};
+use crate::test::registry::report;
+
+/// Time the app runs for before each sample.
+const RUN_MS: u32 = 100;
+
+/// Time the restarted app runs for. Much shorter than the runs before the
+/// restart, so its count stays well below the previous one.
+const RESTART_RUN_MS: u32 = 20;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Observe,
+    Terminated,
+    Restart,
+}
+
+pub unsafe fn run_userspace_readable(
+    apps: &'static AppLoader,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestUserspaceReadable,
+        TestUserspaceReadable::new(apps, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestUserspaceReadable {
+    apps: &'static AppLoader,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    process: OptionalCell<&'static dyn Process>,
+    /// Count the app copied at the previous sample.
+    copied: Cell<u32>,
+    /// Count the driver wrote at the previous sample.
+    count: Cell<u32>,
+    /// Whether the first sample of `Observe` was taken.
+    sampled: Cell<bool>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestUserspaceReadable {
+    pub fn new(
+        apps: &'static AppLoader,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestUserspaceReadable {
+            apps,
+            alarm,
+            process: OptionalCell::empty(),
+            copied: Cell::new(0),
+            count: Cell::new(0),
+            sampled: Cell::new(false),
+            step: Cell::new(Step::Observe),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let process = match self.apps.load(&READ_ONLY_STATE_APP) {
+            Ok(Some(process)) => process,
+            _ => {
+                self.fail("no process created");
+                return;
+            }
+        };
+        self.process.set(process);
+        // Process memory is not cleared when it is handed out, so start from
+        // known values before the app first runs.
+        self.write_words(process, 0);
+        self.wait(RUN_MS);
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Returns the count the driver wrote and the copy the app made.
+    fn read_words(&self, process: &dyn Process) -> (u32, u32) {
+        let ram = process.get_addresses().sram_start;
+        // SAFETY: both words are in the process's RAM, which the kernel may
+        // access at any time. The app only runs while the kernel does not.
+        unsafe {
+            (
+                core::ptr::read_volatile(ram as *const u32),
+                core::ptr::read_volatile((ram + COPIED_STATE_OFFSET) as *const u32),
+            )
+        }
+    }
+
+    fn write_words(&self, process: &dyn Process, value: u32) {
+        let ram = process.get_addresses().sram_start;
+        // SAFETY: as in `read_words()`.
+        unsafe {
+            core::ptr::write_volatile(ram as *mut u32, value);
+            core::ptr::write_volatile((ram + COPIED_STATE_OFFSET) as *mut u32, value);
+        }
+    }
+
+    fn check(&self, process: &dyn Process) -> Result<(), &'static str> {
+        let (count, copied) = self.read_words(process);
+        match self.step.get() {
+            Step::Observe => {
+                if process.debug_syscall_count() != 1 {
+                    return Err("app made more than the allow system call");
+                }
+                if copied == 0 || copied > count {
+                    return Err("app did not see the count");
+                }
+                if self.sampled.get() && copied <= self.copied.get() {
+                    return Err("app did not see the count grow");
+                }
+                if !self.sampled.get() {
+                    self.sampled.set(true);
+                    self.copied.set(copied);
+                    self.wait(RUN_MS);
+                    return Ok(());
+                }
+
+                self.step.set(Step::Terminated);
+                process.terminate(None);
+                self.count.set(self.read_words(process).0);
+                self.wait(RESTART_RUN_MS);
+            }
+            Step::Terminated => {
+                if count != self.count.get() {
+                    return Err("region of terminated process written");
+                }
+
+                self.step.set(Step::Restart);
+                let process_start_cap = create_capability!(capabilities::ProcessStartCapability);
+                process.start(&process_start_cap);
+                self.write_words(process, 0);
+                self.wait(RESTART_RUN_MS);
+            }
+            Step::Restart => {
+                if process.debug_syscall_count() != 1 {
+                    return Err("app made more than the allow system call");
+                }
+                if copied == 0 || copied > count {
+                    return Err("restarted app did not see the count");
+                }
+                if count >= self.count.get() {
+                    return Err("count kept across restart");
+                }
+
+                // Free the slot for later tests.
+                let process_management_cap =
+                    create_capability!(capabilities::ProcessManagementCapability);
+                process.terminate(None);
+                self.apps
+                    .kernel()
+                    .remove_process(process.processid(), &process_management_cap)
+                    .map_err(|_| "terminated process not removed")?;
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!(
+            "UserspaceReadable: {:?} failed: {}",
+            self.step.get(),
+            reason
+        );
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        report(&self.client, "UserspaceReadable", result);
+    }
+}
+
+impl AlarmClient for TestUserspaceReadable {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let Some(process) = self.process.get() else {
+            return;
+        };
+        if let Err(reason) = self.check(process) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestUserspaceReadable {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
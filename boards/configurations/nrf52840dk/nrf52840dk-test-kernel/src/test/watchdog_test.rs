@@ -0,0 +1,288 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the kernel's use of the chip watchdog through
+//! `KernelResources::WatchDog`. The board's watchdog passes the kernel's calls
+//! on to a `TestWatchdog`, which counts them and passes them on to the nRF52
+//! watchdog, started at boot with the timeout in `BOARD_TEST_CONFIG`. The
+//! cases are:
+//!
+//! 1. `Alive`: with a spinning and a yield-waiting app, the board runs for
+//!    longer than the timeout. The kernel set up the watchdog once, tickled it
+//!    and resumed it after every sleep, and the watchdog did not reset the
+//!    chip.
+//! 2. `Starve`: once the tickles no longer reach the watchdog, it resets the
+//!    chip within its timeout. The test marks in a retained register that it
+//!    expects the reset.
+//! 3. `Resume`: after the reset, the board finds the mark and the watchdog as
+//!    the reset reason, and continues with the tests after this one.
+//!
+//! The expected output, after the reset, ends with
+//! Watchdog: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::platform::watchdog::WatchDog;
+use kernel::process::Process;
+use kernel::utilities::cells::OptionalCell;
+use kernel::{static_buf, static_init};
+use nrf52840::power::Power;
+use nrf52840::rtc::Rtc;
+use nrf52840::wdt::Wdt;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppLoader, BUSY_APP, IDLE_APP};
+use crate::test::registry::skip;
+
+/// Value of GPREGRET2 while the test expects the watchdog to reset the chip.
+const RESET_MARK: u8 = 0x57;
+
+/// Time the test waits for the reset beyond the timeout.
+const RESET_MARGIN_MS: u32 = 1_000;
+
+/// A watchdog that counts the kernel's calls, and stops passing tickles on to
+/// the chip watchdog once starved.
+pub struct TestWatchdog {
+    wdt: Option<Wdt>,
+    starved: Cell<bool>,
+    setups: Cell<usize>,
+    tickles: Cell<usize>,
+    suspends: Cell<usize>,
+    resumes: Cell<usize>,
+}
+
+impl TestWatchdog {
+    /// Creates the watchdog, with `wdt` the chip watchdog or `None` to only
+    /// count the calls.
+    pub fn new(wdt: Option<Wdt>) -> Self {
+        TestWatchdog {
+            wdt,
+            starved: Cell::new(false),
+            setups: Cell::new(0),
+            tickles: Cell::new(0),
+            suspends: Cell::new(0),
+            resumes: Cell::new(0),
+        }
+    }
+
+    /// Passes `f` the chip watchdog, unless it is starved.
+    fn forward(&self, f: impl FnOnce(&Wdt)) {
+        if !self.starved.get() {
+            self.wdt.as_ref().map(f);
+        }
+    }
+}
+
+impl WatchDog for TestWatchdog {
+    fn setup(&self) {
+        self.setups.set(self.setups.get() + 1);
+        self.forward(Wdt::setup);
+    }
+
+    fn tickle(&self) {
+        self.tickles.set(self.tickles.get() + 1);
+        self.forward(Wdt::tickle);
+    }
+
+    fn suspend(&self) {
+        self.suspends.set(self.suspends.get() + 1);
+        self.forward(Wdt::suspend);
+    }
+
+    fn resume(&self) {
+        self.resumes.set(self.resumes.get() + 1);
+        self.forward(Wdt::resume);
+    }
+}
+
+/// Whether the watchdog test expected the last reset, without clearing the
+/// mark.
+pub fn reset_expected(power: &Power) -> bool {
+    power.get_gpregret2() == RESET_MARK
+}
+
+/// Checks, once at boot, whether the watchdog test reset the chip, and clears
+/// the mark and the reset reasons for the next reset. Returns the result of
+/// the watchdog test if the board should continue after it, which the
+/// launcher reports, as the test itself cannot after the reset.
+pub fn resume_after_reset(power: &Power) -> Option<Result<(), CapsuleTestError>> {
+    let expected = reset_expected(power);
+    let by_watchdog = power.is_watchdog_reset();
+    power.set_gpregret2(0);
+    power.clear_reset_reasons();
+    if !expected {
+        return None;
+    }
+    if by_watchdog {
+        debug!("Watchdog: reset by the watchdog, resuming");
+        debug!("Watchdog: all cases passed");
+        Some(Ok(()))
+    } else {
+        debug!("Watchdog: Resume failed: reset not caused by the watchdog");
+        Some(Err(CapsuleTestError::IncorrectResult))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Alive,
+    Starve,
+}
+
+pub unsafe fn run_watchdog(
+    apps: &'static AppLoader,
+    watchdog: &'static TestWatchdog,
+    power: &'static Power<'static>,
+    timeout_ms: Option<u32>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(timeout_ms) = timeout_ms else {
+        skip(client, "Watchdog: no watchdog configured");
+        return;
+    };
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(
+        TestWatchdogReset,
+        TestWatchdogReset::new(apps, watchdog, power, timeout_ms, alarm)
+    );
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestWatchdogReset {
+    apps: &'static AppLoader,
+    watchdog: &'static TestWatchdog,
+    power: &'static Power<'static>,
+    timeout_ms: u32,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    processes: [OptionalCell<&'static dyn Process>; 2],
+    /// Number of tickles when the current step started.
+    tickles: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestWatchdogReset {
+    pub fn new(
+        apps: &'static AppLoader,
+        watchdog: &'static TestWatchdog,
+        power: &'static Power<'static>,
+        timeout_ms: u32,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestWatchdogReset {
+            apps,
+            watchdog,
+            power,
+            timeout_ms,
+            alarm,
+            processes: Default::default(),
+            tickles: Cell::new(0),
+            step: Cell::new(Step::Alive),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        if let Err(reason) = self.start() {
+            self.fail(reason);
+        }
+    }
+
+    fn start(&self) -> Result<(), &'static str> {
+        if !self.watchdog.wdt.as_ref().is_some_and(Wdt::is_running) {
+            return Err("watchdog not started at boot");
+        }
+        self.apps
+            .load_all(&[&BUSY_APP, &IDLE_APP], &self.processes)?;
+        self.tickles.set(self.watchdog.tickles.get());
+        self.wait(self.timeout_ms * 3 / 2);
+        Ok(())
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::Alive => {
+                self.apps.remove_all(&self.processes)?;
+                let watchdog = self.watchdog;
+                debug!(
+                    "Watchdog: {} setups, {} tickles, {} suspends, {} resumes",
+                    watchdog.setups.get(),
+                    watchdog.tickles.get(),
+                    watchdog.suspends.get(),
+                    watchdog.resumes.get()
+                );
+                if watchdog.setups.get() != 1 {
+                    return Err("watchdog not set up exactly once");
+                }
+                if watchdog.tickles.get() == self.tickles.get() {
+                    return Err("watchdog not tickled");
+                }
+                if watchdog.suspends.get() != watchdog.resumes.get() {
+                    return Err("watchdog not resumed after every suspend");
+                }
+
+                self.step.set(Step::Starve);
+                debug!(
+                    "Watchdog: starving the watchdog, expecting a reset within {} ms",
+                    self.timeout_ms
+                );
+                self.power.set_gpregret2(RESET_MARK);
+                self.watchdog.starved.set(true);
+                self.wait(self.timeout_ms + RESET_MARGIN_MS);
+            }
+            Step::Starve => {
+                self.watchdog.starved.set(false);
+                self.power.set_gpregret2(0);
+                return Err("starved watchdog did not reset the chip");
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("Watchdog: {:?} failed: {}", self.step.get(), reason);
+        let _ = self.apps.remove_all(&self.processes);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl AlarmClient for TestWatchdogReset {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(reason) = self.check() {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestWatchdogReset {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
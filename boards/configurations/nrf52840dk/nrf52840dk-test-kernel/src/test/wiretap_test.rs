@@ -0,0 +1,255 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the bytes the ST7735 driver sends while initializing, recorded by a
+//! wiretap on SPIM2, using the pins listed in
+//! `BOARD_TEST_CONFIG.spi_chip_select`. No display needs to be attached, as
+//! the transfers complete without one. The cases are:
+//!
+//! 1. `Init`: the driver reports the screen ready within `INIT_TIMEOUT_MS`.
+//! 2. `Sequence`: the capture holds the ST7735 init commands and their
+//!    parameters, in order and nothing else.
+//! 3. `Transactions`: each command and each parameter list went out in its
+//!    own transfer.
+//!
+//! The expected output ends with
+//! Wiretap: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::test::wiretap::{Capture, SpiWiretap};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice;
+use capsules_extra::bus::SpiMasterBus;
+use capsules_extra::st77xx::ST77XX;
+use kernel::component::Component;
+use kernel::debug;
+use kernel::hil::screen::{self, ScreenClient};
+use kernel::hil::spi::cs::{ActiveLow, IntoChipSelect};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+use kernel::{static_buf, static_init};
+use nrf52840::gpio::{GPIOPin, Port};
+use nrf52840::rtc::Rtc;
+use nrf52840::spi::SPIM;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::config::BOARD_TEST_CONFIG;
+use crate::test::registry::{report, skip};
+use crate::test::spi_conformance_test::configure_spim;
+
+/// The ST7735 init sequence, as the driver sends it without a D/C pin: each
+/// command byte followed by its default parameters.
+#[rustfmt::skip]
+const EXPECTED: [u8; 86] = [
+    0x01,                                     // SWRESET
+    0x11,                                     // SLPOUT
+    0xB1, 0x01, 0x2C, 0x2D,                   // FRMCTR1
+    0xB2, 0x01, 0x2C, 0x2D,                   // FRMCTR2
+    0xB3, 0x01, 0x2C, 0x2D, 0x01, 0x2C, 0x2D, // FRMCTR3
+    0xB4, 0x07,                               // INVCTR
+    0xC0, 0xA2, 0x02, 0x84,                   // PWCTR1
+    0xC1, 0xC5,                               // PWCTR2
+    0xC2, 0x0A, 0x00,                         // PWCTR3
+    0xC3, 0x8A, 0x2A,                         // PWCTR4
+    0xC4, 0x8A, 0xEE,                         // PWCTR5
+    0xC5, 0x0E,                               // VMCTR1
+    0x20,                                     // INVOFF
+    0x36, 0x00,                               // MADCTL
+    0x3A, 0x05,                               // COLMOD
+    0x2A, 0x00, 0x00, 0x00, 0x00,             // CASET
+    0x2B, 0x00, 0x00, 0x00, 0x00,             // RASET
+    0xE0, 0x02, 0x1c, 0x07, 0x12, 0x37, 0x32, 0x29, 0x2d, // GMCTRP1
+          0x29, 0x25, 0x2B, 0x39, 0x00, 0x01, 0x03, 0x10,
+    0xE1, 0x03, 0x1d, 0x07, 0x06, 0x2E, 0x2C, 0x29, 0x2D, // GMCTRN1
+          0x2E, 0x2E, 0x37, 0x3F, 0x00, 0x00, 0x02, 0x10,
+    0x13,                                     // NORON
+];
+
+/// Transfers in the init sequence: one per command, plus one per command
+/// with parameters.
+const EXPECTED_TRANSACTIONS: usize = 20 + 16;
+
+/// Bigger than the sequence, so anything extra shows up as a mismatch
+/// rather than an overwrite.
+const CAPTURE_LEN: usize = 128;
+
+/// The reset and command delays add up to under a second.
+const INIT_TIMEOUT_MS: u32 = 2000;
+
+const SPI_RATE: u32 = 4_000_000;
+
+type Wiretap = SpiWiretap<'static, SPIM<'static>>;
+type Screen = ST77XX<
+    'static,
+    VirtualMuxAlarm<'static, Rtc<'static>>,
+    SpiMasterBus<'static, VirtualSpiMasterDevice<'static, Wiretap>>,
+    GPIOPin<'static>,
+>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Init,
+    Sequence,
+    Transactions,
+}
+
+pub unsafe fn run_wiretap(
+    spim: &'static SPIM<'static>,
+    gpio_port: &'static Port<'static, { nrf52840::gpio::NUM_PINS }>,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let Some(pins) = BOARD_TEST_CONFIG.spi_chip_select.as_ref() else {
+        skip(client, "Wiretap: no SPI pins configured");
+        return;
+    };
+    configure_spim(pins, spim);
+
+    let capture_buffer = static_init!([u8; CAPTURE_LEN], [0; CAPTURE_LEN]);
+    let capture = static_init!(Capture<'static>, Capture::new(capture_buffer));
+    let wiretap = static_init!(Wiretap, SpiWiretap::new(spim, capture));
+    spim.set_client(wiretap);
+
+    let mux_spi = components::spi::SpiMuxComponent::new(wiretap)
+        .finalize(components::spi_mux_component_static!(Wiretap));
+    let bus = components::bus::SpiMasterBusComponent::new(
+        mux_spi,
+        IntoChipSelect::<_, ActiveLow>::into_cs(&gpio_port[pins.chip_select]),
+        SPI_RATE,
+        ClockPhase::SampleLeading,
+        ClockPolarity::IdleLow,
+    )
+    .finalize(components::spi_bus_component_static!(Wiretap));
+    let screen = components::st77xx::ST77XXComponent::new(
+        mux_alarm,
+        bus,
+        None,
+        None,
+        &capsules_extra::st77xx::ST7735,
+    )
+    .finalize(components::st77xx_component_static!(
+        SpiMasterBus<'static, VirtualSpiMasterDevice<'static, Wiretap>>,
+        Rtc<'static>,
+        GPIOPin<'static>,
+    ));
+
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(TestWiretap, TestWiretap::new(screen, capture, alarm));
+    screen::Screen::set_client(screen, test);
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestWiretap {
+    screen: &'static Screen,
+    capture: &'static Capture<'static>,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestWiretap {
+    pub fn new(
+        screen: &'static Screen,
+        capture: &'static Capture<'static>,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestWiretap {
+            screen,
+            capture,
+            alarm,
+            step: Cell::new(Step::Init),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.capture.clear();
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(INIT_TIMEOUT_MS));
+        if self.screen.init().is_err() {
+            self.fail("init rejected");
+        }
+    }
+
+    fn check(&self) {
+        self.step.set(Step::Sequence);
+        if let Some(index) = self.capture.mismatch(&EXPECTED) {
+            debug!(
+                "Wiretap: byte {} is {:?}, expected {:?} ({} bytes captured)",
+                index,
+                self.capture.get(index),
+                EXPECTED.get(index),
+                self.capture.len()
+            );
+            self.fail("init sequence differs");
+            return;
+        }
+
+        self.step.set(Step::Transactions);
+        if self.capture.transactions() != EXPECTED_TRANSACTIONS {
+            debug!(
+                "Wiretap: {} transfers, expected {}",
+                self.capture.transactions(),
+                EXPECTED_TRANSACTIONS
+            );
+            self.fail("wrong number of transfers");
+            return;
+        }
+
+        self.finish(Ok(()));
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("Wiretap: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        report(&self.client, "Wiretap", result);
+    }
+}
+
+impl ScreenClient for TestWiretap {
+    fn command_complete(&self, result: Result<(), ErrorCode>) {
+        if result.is_err() {
+            self.fail("transfer failed");
+        }
+    }
+
+    fn write_complete(&self, _buffer: SubSliceMut<'static, u8>, _result: Result<(), ErrorCode>) {}
+
+    fn screen_is_ready(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        self.check();
+    }
+}
+
+impl AlarmClient for TestWiretap {
+    fn alarm(&self) {
+        self.fail("screen not ready");
+    }
+}
+
+impl CapsuleTest for TestWiretap {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,327 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks the semantics of yield-no-wait and yield-wait with an embedded app
+//! that reports the flag yield-no-wait writes and the number of upcalls it
+//! has run at each step. The cases are:
+//!
+//! 1. `NoWait`: yield-no-wait with no upcall pending returns immediately and
+//!    writes 0 to the flag. With an upcall pending, the upcall does not run
+//!    before the app yields, and yield-no-wait runs it and writes 1.
+//! 2. `Wait`: yield-wait with no upcall pending blocks, and the app makes no
+//!    progress.
+//! 3. `Wake`: an upcall scheduled by the kernel wakes the app, which runs the
+//!    upcall exactly once before yield-wait returns.
+//!
+//! At `Wait` and at `Wake`, the system calls the app made, their return
+//! values and the function calls the kernel pushed to it must also be
+//! exactly those in `TRACE`.
+//!
+//! The expected output ends with
+//! Yield: all cases passed
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
+use kernel::process::{FunctionCall, FunctionCallSource, Process, State};
+use kernel::syscall::{Syscall, SyscallReturn};
+use kernel::upcall::UpcallId;
+use kernel::utilities::cells::OptionalCell;
+use kernel::{capabilities, create_capability, static_buf, static_init};
+use nrf52840::rtc::Rtc;
+
+use crate::test::alarm::{new_alarm, TestAlarm};
+use crate::test::embedded_apps::{AppLoader, REPORT_DRIVER_NUM, YIELD_APP};
+use crate::test::registry::report;
+use crate::test::report_driver::ReportDriver;
+use crate::test::syscall_trace::{SyscallRecorder, TraceEvent};
+
+/// Time the app gets to reach each step.
+const STEP_MS: u32 = 20;
+
+/// Flag and upcall count the app reports, in order, up to yield-wait.
+const BEFORE_WAIT: [(usize, usize); 4] = [(0, 0), (0, 0), (1, 1), (1, 1)];
+
+/// Report the app makes once yield-wait returns.
+const AFTER_WAIT: (usize, usize) = (1, 2);
+
+/// Whether `event` is a report of `flag` and `upcalls` by the app.
+fn is_report(event: TraceEvent, flag: usize, upcalls: usize) -> bool {
+    matches!(
+        event,
+        TraceEvent::Call(Syscall::Command {
+            driver_number: REPORT_DRIVER_NUM,
+            subdriver_number: 1,
+            arg0,
+            arg1,
+        }) if arg0 == flag && arg1 == upcalls
+    )
+}
+
+fn is_yield(event: TraceEvent, which: usize) -> bool {
+    matches!(event, TraceEvent::Call(Syscall::Yield { which: w, .. }) if w == which)
+}
+
+fn is_success(event: TraceEvent) -> bool {
+    matches!(event, TraceEvent::Return(SyscallReturn::Success))
+}
+
+fn is_upcall(event: TraceEvent) -> bool {
+    matches!(
+        event,
+        TraceEvent::Function(FunctionCall {
+            source: FunctionCallSource::Driver(UpcallId {
+                driver_num: REPORT_DRIVER_NUM,
+                subscribe_num: 0,
+            }),
+            ..
+        })
+    )
+}
+
+/// The exchange between the kernel and the app: the kernel starts the app,
+/// which subscribes and then makes the calls of `BEFORE_WAIT` up to
+/// yield-wait. The first `TRACE_BEFORE_WAIT` events are those before the
+/// upcall that wakes it, after which it reports `AFTER_WAIT` and yield-waits
+/// again.
+const TRACE: [fn(TraceEvent) -> bool; 21] = [
+    |event| {
+        matches!(
+            event,
+            TraceEvent::Function(FunctionCall {
+                source: FunctionCallSource::Kernel,
+                ..
+            })
+        )
+    },
+    |event| {
+        matches!(
+            event,
+            TraceEvent::Call(Syscall::Subscribe {
+                driver_number: REPORT_DRIVER_NUM,
+                subdriver_number: 0,
+                ..
+            })
+        )
+    },
+    |event| {
+        matches!(
+            event,
+            TraceEvent::Return(SyscallReturn::SubscribeSuccess(..))
+        )
+    },
+    |event| is_yield(event, 0),
+    |event| is_report(event, 0, 0),
+    is_success,
+    |event| {
+        matches!(
+            event,
+            TraceEvent::Call(Syscall::Command {
+                driver_number: REPORT_DRIVER_NUM,
+                subdriver_number: 2,
+                ..
+            })
+        )
+    },
+    is_success,
+    |event| is_report(event, 0, 0),
+    is_success,
+    |event| is_yield(event, 0),
+    is_upcall,
+    |event| is_report(event, 1, 1),
+    is_success,
+    |event| is_report(event, 1, 1),
+    is_success,
+    |event| is_yield(event, 1),
+    is_upcall,
+    |event| is_report(event, 1, 2),
+    is_success,
+    |event| is_yield(event, 1),
+];
+
+/// Number of events of `TRACE` before the kernel wakes the app.
+const TRACE_BEFORE_WAIT: usize = 17;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    NoWait,
+    Wait,
+    Wake,
+}
+
+pub unsafe fn run_yield(
+    apps: &'static AppLoader,
+    driver: &'static ReportDriver,
+    recorder: &'static SyscallRecorder,
+    mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>,
+    client: &'static dyn CapsuleTestClient,
+) {
+    let alarm = new_alarm(mux_alarm, static_buf!(TestAlarm));
+
+    let test = static_init!(TestYield, TestYield::new(apps, driver, recorder, alarm));
+    alarm.set_alarm_client(test);
+    test.set_client(client);
+    test.run();
+}
+
+pub struct TestYield {
+    apps: &'static AppLoader,
+    driver: &'static ReportDriver,
+    recorder: &'static SyscallRecorder,
+    alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    process: OptionalCell<&'static dyn Process>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl TestYield {
+    pub fn new(
+        apps: &'static AppLoader,
+        driver: &'static ReportDriver,
+        recorder: &'static SyscallRecorder,
+        alarm: &'static VirtualMuxAlarm<'static, Rtc<'static>>,
+    ) -> Self {
+        TestYield {
+            apps,
+            driver,
+            recorder,
+            alarm,
+            process: OptionalCell::empty(),
+            step: Cell::new(Step::NoWait),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.driver.reset();
+        let process = match self.apps.load(&YIELD_APP) {
+            Ok(Some(process)) => process,
+            _ => {
+                self.fail("no process created");
+                return;
+            }
+        };
+        self.process.set(process);
+        self.recorder.start(process.processid());
+        self.wait();
+    }
+
+    /// Whether the recorded exchange is the first `len` events of `TRACE`.
+    fn trace_is(&self, len: usize) -> bool {
+        let (mut events, dropped) = self.recorder.events();
+        !dropped
+            && TRACE[..len]
+                .iter()
+                .all(|expected| events.next().is_some_and(expected))
+            && events.next().is_none()
+    }
+
+    fn wait(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(STEP_MS));
+    }
+
+    fn check(&self, process: &dyn Process) -> Result<(), &'static str> {
+        match self.step.get() {
+            Step::NoWait => {
+                let reasons = [
+                    "yield-no-wait reported an upcall with none pending",
+                    "upcall ran outside of yield",
+                    "yield-no-wait did not run the pending upcall",
+                ];
+                let mut reports = self.driver.reports();
+                for (expected, reason) in BEFORE_WAIT.iter().zip(reasons) {
+                    if reports.next() != Some(*expected) {
+                        return Err(reason);
+                    }
+                }
+
+                self.step.set(Step::Wait);
+                self.wait();
+            }
+            Step::Wait => {
+                if !self.driver.reports().eq(BEFORE_WAIT) {
+                    return Err("app did not stop at yield-wait");
+                }
+                if process.get_state() != State::Yielded {
+                    return Err("app not blocked in yield-wait");
+                }
+                if !self.trace_is(TRACE_BEFORE_WAIT) {
+                    return Err("unexpected system calls before yield-wait");
+                }
+
+                self.step.set(Step::Wake);
+                self.driver
+                    .notify(process.processid(), 0, 0)
+                    .map_err(|_| "upcall not scheduled")?;
+                self.wait();
+            }
+            Step::Wake => {
+                let reports = self.driver.reports();
+                if !reports.eq(BEFORE_WAIT.into_iter().chain([AFTER_WAIT])) {
+                    return Err("yield-wait did not return after one upcall");
+                }
+                if !self.trace_is(TRACE.len()) {
+                    return Err("unexpected system calls after the upcall");
+                }
+
+                let process_management_cap =
+                    create_capability!(capabilities::ProcessManagementCapability);
+                process.terminate(None);
+                self.apps
+                    .kernel()
+                    .remove_process(process.processid(), &process_management_cap)
+                    .map_err(|_| "terminated process not removed")?;
+                self.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("Yield: {:?} failed: {}", self.step.get(), reason);
+        self.driver.reports().for_each(|(flag, upcalls)| {
+            debug!("Yield: reported flag {}, {} upcalls", flag, upcalls)
+        });
+        let (events, dropped) = self.recorder.events();
+        events.for_each(|event| debug!("Yield: traced {:?}", event));
+        if dropped {
+            debug!("Yield: later events dropped");
+        }
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.recorder.stop();
+        report(&self.client, "Yield", result);
+    }
+}
+
+impl AlarmClient for TestYield {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let Some(process) = self.process.get() else {
+            return;
+        };
+        if let Err(reason) = self.check(process) {
+            self.fail(reason);
+        }
+    }
+}
+
+impl CapsuleTest for TestYield {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
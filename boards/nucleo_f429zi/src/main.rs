@@ -30,6 +30,10 @@ use stm32f429zi::interrupt_service::Stm32f429ziDefaultPeripherals;
 /// Support routines for debugging I/O.
 pub mod io;
 
+// Unit Tests for drivers.
+#[allow(dead_code)]
+mod can_loopback_test;
+
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 4;
 
@@ -680,6 +684,7 @@ unsafe fn start() -> (
     // //
     // // See comment in `boards/imix/src/main.rs`
     // virtual_uart_rx_test::run_virtual_uart_receive(mux_uart);
+    // can_loopback_test::run_can_loopback(&peripherals.can1, mux_alarm);
 
     debug!("Initialization complete. Entering main loop");
 
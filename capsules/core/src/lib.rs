@@ -21,9 +21,11 @@ pub mod i2c_master;
 pub mod i2c_master_slave_combo;
 pub mod i2c_master_slave_driver;
 pub mod led;
+pub mod long_alarm;
 pub mod low_level_debug;
 pub mod process_console;
 pub mod rng;
 pub mod spi_controller;
 pub mod spi_peripheral;
+pub mod uart_fan_out;
 pub mod virtualizers;
@@ -0,0 +1,327 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Extend an Alarm with narrow ticks to 64-bit ticks.
+//!
+//! The `Alarm` HIL cannot schedule an alarm further away than one period of
+//! the underlying counter, which is 512 seconds for a 24-bit counter at
+//! 32.768 kHz. `LongAlarm` counts the wraps of the underlying counter in
+//! software and implements `Alarm` with `Ticks64`, so an alarm can be hours or
+//! days away. To observe every wrap, it keeps the underlying alarm armed at
+//! most half a counter period ahead, even while no alarm is set. It should
+//! therefore own its underlying alarm, usually a `VirtualMuxAlarm`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let virtual_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, Rtc<'static>>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! virtual_alarm.setup();
+//! let long_alarm = static_init!(
+//!     LongAlarm<'static, VirtualMuxAlarm<'static, Rtc<'static>>>,
+//!     LongAlarm::new(virtual_alarm)
+//! );
+//! virtual_alarm.set_alarm_client(long_alarm);
+//! long_alarm.setup();
+//! long_alarm.set_alarm(long_alarm.now(), long_alarm.ticks_from_seconds(3 * 3600));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::time::{self, Alarm, Ticks, Ticks64, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// An alarm with 64-bit ticks on top of an alarm with narrower ticks.
+pub struct LongAlarm<'a, A: Alarm<'a>> {
+    /// Underlying alarm, which this alarm uses exclusively.
+    alarm: &'a A,
+    /// Number of times the underlying counter wrapped before `last`.
+    wraps: Cell<u64>,
+    /// Last value read from the underlying counter.
+    last: Cell<A::Ticks>,
+    /// Reference time point of the current alarm.
+    reference: Cell<Ticks64>,
+    /// Duration of the current alarm w.r.t. the reference time point.
+    dt: Cell<Ticks64>,
+    /// Whether the current alarm should fire when the time has elapsed.
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<'a, A: Alarm<'a>> LongAlarm<'a, A> {
+    /// After calling new, always call setup()
+    pub fn new(alarm: &'a A) -> LongAlarm<'a, A> {
+        LongAlarm {
+            alarm,
+            wraps: Cell::new(0),
+            last: Cell::new(alarm.now()),
+            reference: Cell::new(0u32.into()),
+            dt: Cell::new(0u32.into()),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Call this method after new() and setting this alarm as the client of
+    /// the underlying alarm, to start counting the wraps of the counter.
+    pub fn setup(&self) {
+        self.rearm();
+    }
+
+    /// Arms the underlying alarm for the current alarm, or for the next
+    /// observation of the counter if the current alarm is further away than
+    /// half a counter period or there is none.
+    fn rearm(&self) {
+        let now = self.now();
+        let half_max = A::Ticks::half_max_value();
+        let dt = if self.armed.get() {
+            let expiration = self.reference.get().wrapping_add(self.dt.get());
+            if now.within_range(self.reference.get(), expiration) {
+                let remaining = expiration.wrapping_sub(now).into_u64();
+                if remaining < half_max.into_u32() as u64 {
+                    A::Ticks::from(remaining as u32)
+                } else {
+                    half_max
+                }
+            } else {
+                A::Ticks::from(0)
+            }
+        } else {
+            half_max
+        };
+        self.alarm.set_alarm(self.last.get(), dt);
+    }
+}
+
+impl<'a, A: Alarm<'a>> Time for LongAlarm<'a, A> {
+    type Frequency = A::Frequency;
+    type Ticks = Ticks64;
+
+    fn now(&self) -> Ticks64 {
+        let now = self.alarm.now();
+        if now < self.last.get() {
+            self.wraps.set(self.wraps.get() + 1);
+        }
+        self.last.set(now);
+        let high = self.wraps.get().checked_shl(A::Ticks::width()).unwrap_or(0);
+        Ticks64::from(high | now.into_u32() as u64)
+    }
+}
+
+impl<'a, A: Alarm<'a>> Alarm<'a> for LongAlarm<'a, A> {
+    fn set_alarm_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks64, dt: Ticks64) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+        self.rearm();
+    }
+
+    fn get_alarm(&self) -> Ticks64 {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        self.rearm();
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks64 {
+        Ticks64::from(self.alarm.minimum_dt().into_u32())
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for LongAlarm<'a, A> {
+    fn alarm(&self) {
+        let now = self.now();
+        let expiration = self.reference.get().wrapping_add(self.dt.get());
+        if self.armed.get() && !now.within_range(self.reference.get(), expiration) {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+        self.rearm();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::*;
+
+    /// 24-bit alarm at 32.768 kHz, like the nRF5x RTC.
+    struct FakeAlarm<'a> {
+        now: Cell<u32>,
+        reference: Cell<Ticks24>,
+        dt: Cell<Ticks24>,
+        armed: Cell<bool>,
+        client: OptionalCell<&'a dyn AlarmClient>,
+    }
+
+    impl FakeAlarm<'_> {
+        fn new(now: u32) -> Self {
+            Self {
+                now: Cell::new(now),
+                reference: Cell::new(0u32.into()),
+                dt: Cell::new(0u32.into()),
+                armed: Cell::new(false),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        /// Fast forwards time to the underlying alarm, plus some latency, and
+        /// calls the client. Returns the number of ticks that passed.
+        fn trigger_next_alarm(&self) -> u32 {
+            let expiration = self.reference.get().wrapping_add(self.dt.get());
+            let elapsed = expiration
+                .wrapping_sub(Ticks24::from(self.now.get()))
+                .into_u32()
+                + 3;
+            self.now.set(self.now.get().wrapping_add(elapsed));
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+            elapsed
+        }
+    }
+
+    impl Time for FakeAlarm<'_> {
+        type Ticks = Ticks24;
+        type Frequency = Freq32KHz;
+
+        fn now(&self) -> Ticks24 {
+            self.now.get().into()
+        }
+    }
+
+    impl<'a> Alarm<'a> for FakeAlarm<'a> {
+        fn set_alarm_client(&self, client: &'a dyn AlarmClient) {
+            self.client.set(client);
+        }
+
+        fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+            self.reference.set(reference);
+            self.dt.set(dt);
+            self.armed.set(true);
+        }
+
+        fn get_alarm(&self) -> Self::Ticks {
+            self.reference.get().wrapping_add(self.dt.get())
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            self.armed.set(false);
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            self.armed.get()
+        }
+
+        fn minimum_dt(&self) -> Self::Ticks {
+            0u32.into()
+        }
+    }
+
+    struct ClientCounter(Cell<usize>);
+    impl AlarmClient for ClientCounter {
+        fn alarm(&self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_now_counts_wraps() {
+        let alarm = FakeAlarm::new(Ticks24::MASK - 100);
+        let long = LongAlarm::new(&alarm);
+        alarm.set_alarm_client(&long);
+        long.setup();
+
+        let start = long.now().into_u64();
+        let mut elapsed = 0u64;
+        for _ in 0..10 {
+            elapsed += alarm.trigger_next_alarm() as u64;
+            assert!(alarm.is_armed());
+            assert!(alarm.dt.get() <= Ticks24::half_max_value());
+        }
+        assert_eq!(long.now().into_u64() - start, elapsed);
+        assert!(elapsed > 4 << 24);
+    }
+
+    #[test]
+    fn test_hours_long_alarm() {
+        let alarm = FakeAlarm::new(12_345);
+        let client = ClientCounter(Cell::new(0));
+        let long = LongAlarm::new(&alarm);
+        alarm.set_alarm_client(&long);
+        long.set_alarm_client(&client);
+        long.setup();
+
+        let reference = long.now();
+        let dt = long.ticks_from_seconds(3 * 3600);
+        assert_eq!(dt.into_u64(), 3 * 3600 * 32_768);
+        long.set_alarm(reference, dt);
+        assert_eq!(
+            long.get_alarm().into_u64(),
+            reference.into_u64() + dt.into_u64()
+        );
+
+        // 3 hours are 21 wraps of the 24-bit counter, each observed by at
+        // least two underlying alarms.
+        let mut triggers = 0;
+        while client.0.get() == 0 {
+            alarm.trigger_next_alarm();
+            triggers += 1;
+            assert!(triggers < 200);
+        }
+        assert!(triggers >= 42);
+        assert!(!long.is_armed());
+
+        let late = long.now().wrapping_sub(reference).into_u64() - dt.into_u64();
+        assert!(late < 10);
+
+        // Without an alarm the counter is still observed every half period.
+        assert!(alarm.is_armed());
+        assert_eq!(alarm.dt.get(), Ticks24::half_max_value());
+    }
+
+    #[test]
+    fn test_short_and_expired_alarms() {
+        let alarm = FakeAlarm::new(Ticks24::MASK - 10);
+        let client = ClientCounter(Cell::new(0));
+        let long = LongAlarm::new(&alarm);
+        alarm.set_alarm_client(&long);
+        long.set_alarm_client(&client);
+        long.setup();
+
+        // An alarm across the wrap uses the underlying alarm directly.
+        long.set_alarm(long.now(), 100u32.into());
+        assert_eq!(alarm.dt.get().into_u32(), 100);
+        alarm.trigger_next_alarm();
+        assert_eq!(client.0.get(), 1);
+
+        // An alarm already in the past fires on the next underlying alarm.
+        let now = long.now();
+        long.set_alarm(now.wrapping_sub(1000u32.into()), 10u32.into());
+        assert_eq!(alarm.dt.get().into_u32(), 0);
+        alarm.trigger_next_alarm();
+        assert_eq!(client.0.get(), 2);
+
+        // A disarmed alarm does not fire.
+        long.set_alarm(long.now(), 100u32.into());
+        assert_eq!(long.disarm(), Ok(()));
+        alarm.trigger_next_alarm();
+        assert_eq!(client.0.get(), 2);
+    }
+}
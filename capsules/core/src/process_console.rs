@@ -6,6 +6,15 @@
 //! a terminal to inspect and control userspace processes.
 //!
 //! For a more in-depth documentation check /doc/Process_Console.md
+//!
+//! The console reads one byte at a time and edits the command as it is
+//! typed: backspace and delete remove a byte, the left, right, home and end
+//! keys move the cursor, and up and down walk the command history. A command
+//! holds at most `COMMAND_BUF_LEN - 1` bytes, and the bytes typed past that
+//! are dropped without an echo. Bytes above 0x7F, and NUL, which ends a
+//! command, are dropped as well, as are escape sequences the console does
+//! not know, up to their final letter or `~`. Echoes that do not fit in the
+//! queue while the UART is busy are dropped.
 use core::cell::Cell;
 use core::cmp;
 use core::fmt;
@@ -94,7 +103,7 @@ enum WriterState {
 }
 
 /// Key that can be part from an escape sequence.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 enum EscKey {
     Up,
     Down,
@@ -176,9 +185,15 @@ impl EscState {
     }
 
     /// Checks if the escape state machine is in the middle
-    /// of an escape sequence
+    /// of an escape sequence, or at the end of an unrecognized one
     fn in_progress(&self) -> bool {
-        matches!(self, EscState::Bracket) || matches!(self, EscState::Bracket3)
+        matches!(
+            self,
+            EscState::Bracket
+                | EscState::Bracket3
+                | EscState::Unrecognized
+                | EscState::UnrecognizedDone
+        )
     }
 
     /// Checks if the escape state machine is at the start
@@ -307,6 +322,9 @@ impl Command {
     }
 
     fn delete_byte(&mut self, pos: usize) {
+        if self.len == 0 {
+            return;
+        }
         for i in pos..self.len {
             self.buf[i] = self.buf[i + 1];
         }
@@ -732,9 +750,10 @@ impl<
                             let _ = write(
                                 &mut console_writer,
                                 format_args!(
-                                    "{:<20}{:6}{:10}{:10}  {:2}/{:2}   {:?}\r\n",
+                                    "{:<20}{:6}{:10}{:10}{:10}  {:2}/{:2}   {:?}\r\n",
                                     pname,
                                     process.debug_timeslice_expiration_count(),
+                                    process.debug_executed_time_us() / 1000,
                                     process.debug_syscall_count(),
                                     process.get_restart_count(),
                                     grants_used,
@@ -895,7 +914,8 @@ impl<
                         } else if clean_str.starts_with("list") {
                             let _ = self
                                 .write_bytes(b" PID    ShortID    Name                Quanta  ");
-                            let _ = self.write_bytes(b"Syscalls  Restarts  Grants  State\r\n");
+                            let _ = self
+                                .write_bytes(b"Time(ms)  Syscalls  Restarts  Grants  State\r\n");
 
                             // Count the number of current processes.
                             let mut count = 0;
@@ -1047,8 +1067,12 @@ impl<
     fn write_byte(&self, byte: u8) -> Result<(), ErrorCode> {
         if self.tx_in_progress.get() {
             self.queue_buffer.map(|buf| {
-                buf[self.queue_size.get()] = byte;
-                self.queue_size.set(self.queue_size.get() + 1);
+                // Drop the byte if the queue is full, as `write_bytes` does.
+                let size = self.queue_size.get();
+                if let Some(queued) = buf.get_mut(size) {
+                    *queued = byte;
+                    self.queue_size.set(size + 1);
+                }
             });
             Err(ErrorCode::BUSY)
         } else {
@@ -1436,11 +1460,13 @@ impl<
                             }
                         } else if index < (command.len() - 1)
                             && read_buf[0] < ASCII_LIMIT
+                            && read_buf[0] != EOL
                             && !esc_state.has_started()
                             && !esc_state.in_progress()
                         {
                             // For some reason, sometimes reads return > 127 but no error,
                             // which causes utf-8 decoding failure, so check byte is < 128. -pal
+                            // A NUL would end the command where it was typed.
 
                             // Echo the typed byte
                             let _ = self.write_byte(read_buf[0]);
@@ -1489,3 +1515,115 @@ impl<
         let _ = self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `bytes` to the escape state machine and returns the last key it
+    /// completed and the bytes the console takes as typed.
+    fn feed(bytes: &[u8]) -> (Option<EscKey>, [Option<u8>; 8]) {
+        let mut state = EscState::Bypass;
+        let mut key = None;
+        let mut typed = [None; 8];
+        for (i, &byte) in bytes.iter().enumerate() {
+            state = state.next_state(byte);
+            if let EscState::Complete(completed) = state {
+                key = Some(completed);
+            } else if !state.has_started() && !state.in_progress() {
+                typed[i] = Some(byte);
+            }
+        }
+        (key, typed)
+    }
+
+    fn command(bytes: &[u8]) -> Command {
+        let mut command = Command::default();
+        for (i, &byte) in bytes.iter().enumerate() {
+            command.insert_byte(byte, i);
+        }
+        command
+    }
+
+    #[test]
+    fn escape_sequences_complete_keys() {
+        let keys = [
+            (&b"\x1b[A"[..], EscKey::Up),
+            (b"\x1b[B", EscKey::Down),
+            (b"\x1b[D", EscKey::Left),
+            (b"\x1b[C", EscKey::Right),
+            (b"\x1b[H", EscKey::Home),
+            (b"\x1b[F", EscKey::End),
+            (b"\x1b[3~", EscKey::Delete),
+            (b"\x7f", EscKey::Backspace),
+        ];
+        for (bytes, expected) in keys {
+            assert_eq!(feed(bytes), (Some(expected), [None; 8]));
+        }
+    }
+
+    #[test]
+    fn unknown_escape_sequence_is_dropped() {
+        // The sequence ends at its final letter, and the next byte is typed.
+        let (key, typed) = feed(b"\x1b[12;5Pa");
+        assert_eq!(key, None);
+        assert_eq!(typed[..7], [None; 7]);
+        assert_eq!(typed[7], Some(b'a'));
+    }
+
+    #[test]
+    fn binary_bytes_do_not_start_sequences() {
+        let (key, typed) = feed(&[0x00, 0x80, 0xff, b'[', b'A']);
+        assert_eq!(key, None);
+        assert_eq!(
+            typed[..5],
+            [Some(0x00), Some(0x80), Some(0xff), Some(b'['), Some(b'A')]
+        );
+    }
+
+    #[test]
+    fn command_edits_in_place() {
+        let mut edited = command(b"lst");
+        edited.insert_byte(b'i', 1);
+        assert_eq!(edited.len, 4);
+        assert_eq!(edited.buf[..5], *b"list\0");
+
+        edited.delete_byte(0);
+        assert_eq!(edited.len, 3);
+        assert_eq!(edited.buf[..4], *b"ist\0");
+    }
+
+    #[test]
+    fn longest_command_fits() {
+        let longest = [b'x'; COMMAND_BUF_LEN - 1];
+        let mut full = command(&longest);
+        assert_eq!(full.len, COMMAND_BUF_LEN - 1);
+        assert_eq!(full.buf[COMMAND_BUF_LEN - 1], EOL);
+
+        for _ in 0..COMMAND_BUF_LEN {
+            full.delete_byte(0);
+        }
+        assert_eq!(full.len, 0);
+        assert_eq!(full.buf, [EOL; COMMAND_BUF_LEN]);
+    }
+
+    #[test]
+    fn history_keeps_commands_in_order() {
+        let mut cmds = [Command::default(); 3];
+        let mut history = CommandHistory::new(&mut cmds);
+        let mut buffer = [EOL; COMMAND_BUF_LEN];
+        for name in [&b"list"[..], b"help", b"help"] {
+            buffer[..name.len()].copy_from_slice(name);
+            history.make_space(&buffer);
+        }
+        // The repeated command is kept once.
+        assert_eq!(history.next_cmd_idx(), Some(1));
+        assert_eq!(history.cmds[1].buf[..5], *b"help\0");
+        assert_eq!(history.next_cmd_idx(), Some(2));
+        assert_eq!(history.cmds[2].buf[..5], *b"list\0");
+        assert_eq!(history.next_cmd_idx(), None);
+        assert_eq!(history.prev_cmd_idx(), Some(1));
+        assert_eq!(history.prev_cmd_idx(), Some(0));
+        assert_eq!(history.prev_cmd_idx(), None);
+    }
+}
@@ -0,0 +1,251 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that split-phase HILs return the buffers they were lent.
+//!
+//! A split-phase call hands a buffer to the HIL implementation, which hands
+//! it back in the completion callback. The buffer that comes back must be the
+//! one that was lent, with the same length: a driver that returns another
+//! buffer, or a shortened or lengthened slice of it, leaves the caller with
+//! a buffer it cannot use as it expects.
+//!
+//! A [`BufferTracker`] tags each buffer the test lends by its address and
+//! length, and checks each buffer the HIL returns against the tags. A
+//! returned buffer that does not match is a [`BufferViolation`]:
+//!
+//! - `Foreign`: no buffer was lent at that address.
+//! - `Length`: a buffer was lent at that address, but with another length.
+//!
+//! [`TrackedClient`] stands in for the client of a HIL and checks the
+//! buffers of its callbacks before forwarding them:
+//!
+//! ```rust,ignore
+//! let tracker = static_init!(BufferTracker<2>, BufferTracker::new());
+//! let tracked = static_init!(
+//!     TrackedClient<'static, dyn uart::TransmitClient, 2>,
+//!     TrackedClient::new(tracker, test)
+//! );
+//! uart.set_transmit_client(tracked);
+//!
+//! tracker.lend(buffer);
+//! if let Err((_, buffer)) = uart.transmit_buffer(buffer, len) {
+//!     // The HIL rejected the call and handed the buffer straight back.
+//!     let _ = tracker.reclaim(buffer);
+//! }
+//! ```
+//!
+//! For a `SubSliceMut`, the buffer lent is the accessible portion of it.
+
+use core::cell::Cell;
+
+use kernel::hil::spi::SpiMasterClient;
+use kernel::hil::uart::TransmitClient;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// A returned buffer that does not match a lent one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BufferViolation {
+    Foreign,
+    Length,
+}
+
+/// Tags for up to `N` buffers lent at a time.
+pub struct BufferTracker<const N: usize> {
+    /// Address and length of each lent buffer.
+    lent: [Cell<Option<(usize, usize)>>; N],
+    violations: Cell<usize>,
+    first: OptionalCell<BufferViolation>,
+}
+
+impl<const N: usize> BufferTracker<N> {
+    pub const fn new() -> Self {
+        BufferTracker {
+            lent: [const { Cell::new(None) }; N],
+            violations: Cell::new(0),
+            first: OptionalCell::empty(),
+        }
+    }
+
+    /// Tags `buffer` as lent. Returns `false` if `N` buffers are lent
+    /// already, in which case `buffer` is not tracked.
+    pub fn lend(&self, buffer: &[u8]) -> bool {
+        match self.lent.iter().find(|tag| tag.get().is_none()) {
+            Some(tag) => {
+                tag.set(Some((buffer.as_ptr() as usize, buffer.len())));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the tag of a returned `buffer`. Returns the violation, if
+    /// `buffer` does not match a lent buffer, which the tracker records as
+    /// well.
+    pub fn reclaim(&self, buffer: &[u8]) -> Result<(), BufferViolation> {
+        let address = buffer.as_ptr() as usize;
+        let result = match self
+            .lent
+            .iter()
+            .find(|tag| tag.get().is_some_and(|(lent, _)| lent == address))
+        {
+            Some(tag) => match tag.take() {
+                Some((_, len)) if len == buffer.len() => Ok(()),
+                _ => Err(BufferViolation::Length),
+            },
+            None => Err(BufferViolation::Foreign),
+        };
+        if let Err(violation) = result {
+            self.violations.set(self.violations.get() + 1);
+            if self.first.is_none() {
+                self.first.set(violation);
+            }
+        }
+        result
+    }
+
+    /// Number of buffers lent and not returned.
+    pub fn outstanding(&self) -> usize {
+        self.lent.iter().filter(|tag| tag.get().is_some()).count()
+    }
+
+    /// Number of violations recorded.
+    pub fn violations(&self) -> usize {
+        self.violations.get()
+    }
+
+    /// The first violation recorded, if any.
+    pub fn first_violation(&self) -> Option<BufferViolation> {
+        self.first.get()
+    }
+
+    /// Forgets the lent buffers and the violations recorded so far.
+    pub fn reset(&self) {
+        self.lent.iter().for_each(|tag| tag.set(None));
+        self.violations.set(0);
+        self.first.clear();
+    }
+}
+
+impl<const N: usize> Default for BufferTracker<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Client wrapper that checks the buffers of a HIL's callbacks against a
+/// [`BufferTracker`].
+pub struct TrackedClient<'a, C: ?Sized, const N: usize> {
+    tracker: &'a BufferTracker<N>,
+    client: &'a C,
+}
+
+impl<'a, C: ?Sized, const N: usize> TrackedClient<'a, C, N> {
+    pub fn new(tracker: &'a BufferTracker<N>, client: &'a C) -> Self {
+        TrackedClient { tracker, client }
+    }
+}
+
+impl<C: ?Sized + TransmitClient, const N: usize> TransmitClient for TrackedClient<'_, C, N> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        let _ = self.tracker.reclaim(tx_buffer);
+        self.client.transmitted_buffer(tx_buffer, tx_len, rval);
+    }
+
+    fn transmitted_word(&self, rval: Result<(), ErrorCode>) {
+        self.client.transmitted_word(rval);
+    }
+}
+
+impl<C: ?Sized + SpiMasterClient, const N: usize> SpiMasterClient for TrackedClient<'_, C, N> {
+    fn read_write_done(
+        &self,
+        mut write_buffer: SubSliceMut<'static, u8>,
+        mut read_buffer: Option<SubSliceMut<'static, u8>>,
+        status: Result<usize, ErrorCode>,
+    ) {
+        let _ = self.tracker.reclaim(write_buffer.as_slice());
+        if let Some(read_buffer) = read_buffer.as_mut() {
+            let _ = self.tracker.reclaim(read_buffer.as_slice());
+        }
+        self.client
+            .read_write_done(write_buffer, read_buffer, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    fn leak(len: usize) -> &'static mut [u8] {
+        Box::leak(std::vec![0; len].into_boxed_slice())
+    }
+
+    /// Keeps the last buffer it got back.
+    struct Probe {
+        buffer: Cell<Option<&'static mut [u8]>>,
+    }
+
+    impl TransmitClient for Probe {
+        fn transmitted_buffer(
+            &self,
+            tx_buffer: &'static mut [u8],
+            _tx_len: usize,
+            _rval: Result<(), ErrorCode>,
+        ) {
+            self.buffer.set(Some(tx_buffer));
+        }
+    }
+
+    #[test]
+    fn matches_lent_buffers() {
+        let tracker: BufferTracker<2> = BufferTracker::new();
+        let first = leak(8);
+        let second = leak(8);
+        assert!(tracker.lend(first));
+        assert!(tracker.lend(second));
+        assert!(!tracker.lend(leak(4)));
+        assert_eq!(tracker.outstanding(), 2);
+
+        assert_eq!(tracker.reclaim(second), Ok(()));
+        assert_eq!(tracker.reclaim(first), Ok(()));
+        assert_eq!(tracker.outstanding(), 0);
+        assert_eq!(tracker.reclaim(first), Err(BufferViolation::Foreign));
+        assert_eq!(tracker.violations(), 1);
+
+        tracker.reset();
+        assert_eq!(tracker.violations(), 0);
+        assert_eq!(tracker.first_violation(), None);
+    }
+
+    #[test]
+    fn catches_swapped_and_shortened_buffers() {
+        let tracker: BufferTracker<2> = BufferTracker::new();
+        let probe = Probe {
+            buffer: Cell::new(None),
+        };
+        let tracked = TrackedClient::new(&tracker, &probe);
+
+        let buffer = leak(8);
+        tracker.lend(buffer);
+        tracked.transmitted_buffer(&mut buffer[..4], 4, Ok(()));
+        assert_eq!(tracker.first_violation(), Some(BufferViolation::Length));
+        // The buffer reaches the client all the same.
+        assert_eq!(probe.buffer.take().map(|buffer| buffer.len()), Some(4));
+
+        tracker.lend(leak(8));
+        tracked.transmitted_buffer(leak(8), 8, Ok(()));
+        assert_eq!(tracker.violations(), 2);
+        assert_eq!(tracker.outstanding(), 1);
+    }
+}
@@ -0,0 +1,251 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks that a HIL issues exactly one callback per request.
+//!
+//! A [`CheckedClient`] stands in for the client of a HIL implementation and
+//! forwards every callback to the real client. The test tells it of each
+//! request it makes, and each callback then settles one request. A callback
+//! that settles none is a [`Violation`], which the [`CallbackChecker`] the
+//! client belongs to records:
+//!
+//! - `Duplicate`: the callback follows the callback of the last request.
+//! - `WrongClient`: the client has no request, but another client of the
+//!   same checker waits for a callback, which likely went astray.
+//! - `Unrequested`: no client of the checker waits for a callback.
+//!
+//! ```rust,ignore
+//! let checker = static_init!(CallbackChecker, CallbackChecker::new());
+//! let checked = static_init!(
+//!     CheckedClient<'static, dyn SpiMasterClient>,
+//!     CheckedClient::new(checker, test)
+//! );
+//! spi.set_client(checked);
+//!
+//! checked.request();
+//! if let Err((_, write, read)) = spi.read_write_bytes(write, read) {
+//!     // No callback follows a request the HIL rejects.
+//!     checked.cancel();
+//! }
+//! ```
+//!
+//! The client traits implemented here are those of the alarm, UART transmit
+//! and SPI controller HILs. For any other HIL, a client can call
+//! [`CheckedClient::check`] from its own callback.
+
+use core::cell::Cell;
+
+use kernel::hil::spi::SpiMasterClient;
+use kernel::hil::time::AlarmClient;
+use kernel::hil::uart::TransmitClient;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// A callback that settles no request.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Violation {
+    Duplicate,
+    WrongClient,
+    Unrequested,
+}
+
+/// Records the violations of a group of checked clients.
+pub struct CallbackChecker {
+    /// Requests of all clients that wait for their callback.
+    pending: Cell<usize>,
+    violations: Cell<usize>,
+    first: OptionalCell<Violation>,
+}
+
+impl CallbackChecker {
+    pub const fn new() -> Self {
+        CallbackChecker {
+            pending: Cell::new(0),
+            violations: Cell::new(0),
+            first: OptionalCell::empty(),
+        }
+    }
+
+    /// Number of violations recorded.
+    pub fn violations(&self) -> usize {
+        self.violations.get()
+    }
+
+    /// The first violation recorded, if any.
+    pub fn first_violation(&self) -> Option<Violation> {
+        self.first.get()
+    }
+
+    /// Whether no client waits for a callback.
+    pub fn is_settled(&self) -> bool {
+        self.pending.get() == 0
+    }
+
+    /// Forgets the requests and violations recorded so far.
+    pub fn reset(&self) {
+        self.pending.set(0);
+        self.violations.set(0);
+        self.first.clear();
+    }
+
+    fn record(&self, violation: Violation) {
+        self.violations.set(self.violations.get() + 1);
+        if self.first.is_none() {
+            self.first.set(violation);
+        }
+    }
+}
+
+impl Default for CallbackChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Client wrapper that checks the callbacks of one HIL user against its
+/// requests.
+pub struct CheckedClient<'a, C: ?Sized> {
+    checker: &'a CallbackChecker,
+    client: &'a C,
+    /// Requests that wait for their callback.
+    pending: Cell<usize>,
+    /// Whether the last request got its callback, and no request was made
+    /// since.
+    settled: Cell<bool>,
+}
+
+impl<'a, C: ?Sized> CheckedClient<'a, C> {
+    pub fn new(checker: &'a CallbackChecker, client: &'a C) -> Self {
+        CheckedClient {
+            checker,
+            client,
+            pending: Cell::new(0),
+            settled: Cell::new(false),
+        }
+    }
+
+    /// Notes a request that a callback must follow. Call before making the
+    /// request, as the callback may come before the call returns.
+    pub fn request(&self) {
+        self.pending.set(self.pending.get() + 1);
+        self.checker.pending.set(self.checker.pending.get() + 1);
+        self.settled.set(false);
+    }
+
+    /// Withdraws the last request, which the HIL rejected.
+    pub fn cancel(&self) {
+        if self.pending.get() > 0 {
+            self.pending.set(self.pending.get() - 1);
+            self.checker.pending.set(self.checker.pending.get() - 1);
+        }
+    }
+
+    /// Settles a request with a callback. Returns the violation, if the
+    /// callback settles none, which the checker records as well.
+    pub fn check(&self) -> Result<(), Violation> {
+        if self.pending.get() > 0 {
+            self.pending.set(self.pending.get() - 1);
+            self.checker.pending.set(self.checker.pending.get() - 1);
+            self.settled.set(self.pending.get() == 0);
+            return Ok(());
+        }
+        let violation = if self.settled.get() {
+            Violation::Duplicate
+        } else if !self.checker.is_settled() {
+            Violation::WrongClient
+        } else {
+            Violation::Unrequested
+        };
+        self.checker.record(violation);
+        Err(violation)
+    }
+
+    /// Number of requests that wait for their callback.
+    pub fn pending(&self) -> usize {
+        self.pending.get()
+    }
+}
+
+// Callbacks that settle no request still reach the client, so the test sees
+// what the capsule under it would.
+
+impl<C: ?Sized + AlarmClient> AlarmClient for CheckedClient<'_, C> {
+    fn alarm(&self) {
+        let _ = self.check();
+        self.client.alarm();
+    }
+}
+
+impl<C: ?Sized + TransmitClient> TransmitClient for CheckedClient<'_, C> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        let _ = self.check();
+        self.client.transmitted_buffer(tx_buffer, tx_len, rval);
+    }
+
+    fn transmitted_word(&self, rval: Result<(), ErrorCode>) {
+        let _ = self.check();
+        self.client.transmitted_word(rval);
+    }
+}
+
+impl<C: ?Sized + SpiMasterClient> SpiMasterClient for CheckedClient<'_, C> {
+    fn read_write_done(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+        status: Result<usize, ErrorCode>,
+    ) {
+        let _ = self.check();
+        self.client
+            .read_write_done(write_buffer, read_buffer, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Client;
+
+    #[test]
+    fn one_callback_per_request() {
+        let checker = CallbackChecker::new();
+        let client = CheckedClient::new(&checker, &Client);
+        client.request();
+        client.request();
+        assert_eq!(client.check(), Ok(()));
+        assert!(!checker.is_settled());
+        assert_eq!(client.check(), Ok(()));
+        assert!(checker.is_settled());
+        assert_eq!(client.check(), Err(Violation::Duplicate));
+
+        client.request();
+        client.cancel();
+        assert!(checker.is_settled());
+        assert_eq!(client.check(), Err(Violation::Unrequested));
+        assert_eq!(checker.violations(), 2);
+        assert_eq!(checker.first_violation(), Some(Violation::Duplicate));
+    }
+
+    #[test]
+    fn callback_on_wrong_client() {
+        let checker = CallbackChecker::new();
+        let first = CheckedClient::new(&checker, &Client);
+        let second = CheckedClient::new(&checker, &Client);
+        first.request();
+        assert_eq!(second.check(), Err(Violation::WrongClient));
+        assert_eq!(first.pending(), 1);
+        assert_eq!(first.check(), Ok(()));
+
+        checker.reset();
+        assert_eq!(checker.violations(), 0);
+        assert_eq!(checker.first_violation(), None);
+    }
+}
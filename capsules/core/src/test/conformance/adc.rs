@@ -0,0 +1,261 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Conformance tests for `hil::adc::AdcHighSpeed` implementations.
+//!
+//! The test samples continuously into two buffers, handing each buffer back
+//! with `provide_buffer()` from the `samples_ready()` callback that returned
+//! it. No analog input is needed, the sample values are not checked. The
+//! test verifies that:
+//!
+//! 1. Buffers come back in the order they were handed to the driver, and
+//!    every buffer is full.
+//! 2. No samples are dropped at buffer swaps: the time between the first and
+//!    the last callback matches the number of samples collected in between at
+//!    the requested rate.
+//! 3. After `stop_sampling()` no further callbacks arrive within two buffer
+//!    periods, and `retrieve_buffers()` returns the buffer the driver still
+//!    held.
+//!
+//! The requested frequency must be one the driver can produce exactly,
+//! otherwise the timing check fails.
+
+use core::cell::Cell;
+
+use crate::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::adc::{AdcHighSpeed, HighSpeedClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// Allowed deviation of the measured sampling time, in percent.
+const RATE_TOLERANCE_PERCENT: u32 = 10;
+
+/// Allowed deviation of the measured sampling time for callback latency, in
+/// microseconds.
+const LATENCY_TOLERANCE_US: u32 = 1000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Sampling,
+    Stopped,
+}
+
+pub struct TestAdcHighSpeed<'a, A: AdcHighSpeed<'a>, T: Alarm<'a>> {
+    adc: &'a A,
+    channel: A::Channel,
+    frequency: u32,
+    samples: usize,
+    buffers_to_collect: usize,
+    alarm: &'a T,
+    buffer1: TakeCell<'static, [u16]>,
+    buffer2: TakeCell<'static, [u16]>,
+    /// Buffer the driver must return next, then the one it fills after it.
+    expected: Cell<*const u16>,
+    queued: Cell<*const u16>,
+    collected: Cell<usize>,
+    first_callback: Cell<T::Ticks>,
+    late_callbacks: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, A: AdcHighSpeed<'a>, T: Alarm<'a>> TestAdcHighSpeed<'a, A, T> {
+    /// Samples `channel` at `frequency` Hz, `samples` samples per buffer,
+    /// until `buffers_to_collect` buffers have been returned. Both buffers
+    /// must hold at least `samples` samples.
+    pub fn new(
+        adc: &'a A,
+        channel: A::Channel,
+        frequency: u32,
+        samples: usize,
+        buffers_to_collect: usize,
+        alarm: &'a T,
+        buffer1: &'static mut [u16],
+        buffer2: &'static mut [u16],
+    ) -> Self {
+        TestAdcHighSpeed {
+            adc,
+            channel,
+            frequency,
+            samples,
+            buffers_to_collect,
+            alarm,
+            expected: Cell::new(buffer1.as_ptr()),
+            queued: Cell::new(buffer2.as_ptr()),
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+            collected: Cell::new(0),
+            first_callback: Cell::new(T::Ticks::from(0)),
+            late_callbacks: Cell::new(0),
+            step: Cell::new(Step::Sampling),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.adc.set_highspeed_client(self);
+        self.alarm.set_alarm_client(self);
+
+        let (Some(buffer1), Some(buffer2)) = (self.buffer1.take(), self.buffer2.take()) else {
+            self.fail("buffers missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        if buffer1.len() < self.samples || buffer2.len() < self.samples {
+            self.buffer1.replace(buffer1);
+            self.buffer2.replace(buffer2);
+            self.fail("buffers too small", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        if let Err((e, buffer1, buffer2)) = self.adc.sample_highspeed(
+            &self.channel,
+            self.frequency,
+            buffer1,
+            self.samples,
+            buffer2,
+            self.samples,
+        ) {
+            self.buffer1.replace(buffer1);
+            self.buffer2.replace(buffer2);
+            self.fail("sample_highspeed rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    /// Expected time for `buffers` full buffers at the requested rate.
+    fn buffers_to_us(&self, buffers: usize) -> u32 {
+        ((buffers * self.samples) as u64 * 1_000_000 / self.frequency as u64) as u32
+    }
+
+    /// Checks the time from the first to the last callback, then stops.
+    fn stop(&self, buffer: &'static mut [u16]) {
+        self.buffer1.replace(buffer);
+
+        let elapsed = self.alarm.now().wrapping_sub(self.first_callback.get());
+        let elapsed_us = self.alarm.ticks_to_us(elapsed);
+        let expected_us = self.buffers_to_us(self.buffers_to_collect - 1);
+        let tolerance_us = expected_us * RATE_TOLERANCE_PERCENT / 100 + LATENCY_TOLERANCE_US;
+        if elapsed_us.abs_diff(expected_us) > tolerance_us {
+            debug!(
+                "AdcHighSpeedConformance: {} buffers took {}us, expected {}us",
+                self.buffers_to_collect - 1,
+                elapsed_us,
+                expected_us
+            );
+            self.fail("sampling rate not met", CapsuleTestError::IncorrectResult);
+            return;
+        }
+
+        self.step.set(Step::Stopped);
+        if let Err(e) = self.adc.stop_sampling() {
+            self.fail("stop_sampling failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        let window_ms = self.buffers_to_us(2) / 1000 + 1;
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(window_ms));
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!(
+            "AdcHighSpeedConformance: {:?} failed: {}",
+            self.step.get(),
+            reason
+        );
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        if self.step.get() == Step::Sampling {
+            let _ = self.adc.stop_sampling();
+        }
+        if result.is_ok() {
+            debug!("AdcHighSpeedConformance: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, A: AdcHighSpeed<'a>, T: Alarm<'a>> HighSpeedClient for TestAdcHighSpeed<'a, A, T> {
+    fn samples_ready(&self, buf: &'static mut [u16], length: usize) {
+        if self.finished.get() || self.step.get() == Step::Stopped {
+            self.late_callbacks.set(self.late_callbacks.get() + 1);
+            self.buffer2.replace(buf);
+            return;
+        }
+
+        let collected = self.collected.get() + 1;
+        self.collected.set(collected);
+        if collected == 1 {
+            self.first_callback.set(self.alarm.now());
+        }
+
+        if buf.as_ptr() != self.expected.get() {
+            self.buffer1.replace(buf);
+            self.fail(
+                "buffers returned out of order",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        if length != self.samples {
+            debug!(
+                "AdcHighSpeedConformance: buffer {} holds {} of {} samples",
+                collected, length, self.samples
+            );
+            self.buffer1.replace(buf);
+            self.fail("partial buffer", CapsuleTestError::IncorrectResult);
+            return;
+        }
+
+        if collected == self.buffers_to_collect {
+            self.stop(buf);
+            return;
+        }
+
+        self.expected.set(self.queued.get());
+        self.queued.set(buf.as_ptr());
+        if let Err((e, buf)) = self.adc.provide_buffer(buf, self.samples) {
+            self.buffer1.replace(buf);
+            self.fail("provide_buffer rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+}
+
+impl<'a, A: AdcHighSpeed<'a>, T: Alarm<'a>> AlarmClient for TestAdcHighSpeed<'a, A, T> {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if self.late_callbacks.get() != 0 {
+            self.fail(
+                "callback after stop_sampling",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        match self.adc.retrieve_buffers() {
+            // The last buffer returned was kept, so the driver must still
+            // hold the other one.
+            Ok((first, second)) => match first.or(second) {
+                Some(buf) => {
+                    self.buffer2.replace(buf);
+                    self.finish(Ok(()));
+                }
+                None => self.fail("driver kept a buffer", CapsuleTestError::IncorrectResult),
+            },
+            Err(e) => self.fail("retrieve_buffers failed", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+}
+
+impl<'a, A: AdcHighSpeed<'a>, T: Alarm<'a>> CapsuleTest for TestAdcHighSpeed<'a, A, T> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,347 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Conformance tests for `hil::digest` implementations.
+//!
+//! The test hashes a caller-supplied message whose digest is known and runs
+//! the following cases in order:
+//!
+//! 1. `OddChunks`: add the message with `add_mut_data` in odd-sized chunks
+//!    (1, 3, 7, 13, ... bytes) that never line up with a block boundary, then
+//!    `run()` and compare the result against the expected digest.
+//! 2. `ClearMidOperation`: start an `add_mut_data` and immediately call
+//!    `clear_data()`. The pending operation must complete with
+//!    `ErrorCode::CANCEL`.
+//! 3. `ClearRecompute`: hash the whole message again, which must produce the
+//!    expected digest (i.e. no state from the cancelled operation leaks).
+//! 4. `Verify`: add the message and `verify()` against the expected digest,
+//!    which must return `Ok(true)`.
+//! 5. `VerifyMismatch`: add the message and `verify()` against a digest with
+//!    one bit flipped, which must return `Ok(false)`.
+//! 6. `BusyRejected`: while an `add_mut_data` is outstanding, `run()` and
+//!    `verify()` must be rejected with `ErrorCode::BUSY` and have their
+//!    buffers returned. Once the operation completes, `run()` must produce
+//!    the expected digest, showing the rejected requests did not disturb the
+//!    engine state.
+//!
+//! The kernel does not provide a digest virtualizer, and a `Digest` has a
+//! single client, so the test cannot interleave two clients. `BusyRejected`
+//! checks the engine side of that instead, issuing the rejected requests
+//! itself.
+//!
+//! Any failure is reported through `CapsuleTestClient::done()` and printed
+//! with the name of the failing case.
+
+use core::cell::Cell;
+
+use crate::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::digest;
+use kernel::hil::digest::Digest;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::ErrorCode;
+
+/// Chunk sizes used for the `OddChunks` case, applied cyclically.
+const ODD_CHUNKS: [usize; 7] = [1, 3, 7, 13, 31, 63, 65];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Case {
+    OddChunks,
+    ClearMidOperation,
+    ClearRecompute,
+    Verify,
+    VerifyMismatch,
+    BusyRejected,
+}
+
+pub struct TestDigestConformance<D: 'static + Digest<'static, L>, const L: usize> {
+    digest: &'static D,
+    data: TakeCell<'static, [u8]>,
+    expected: TakeCell<'static, [u8; L]>,
+    output: TakeCell<'static, [u8; L]>,
+    case: Cell<Case>,
+    position: Cell<usize>,
+    chunk: Cell<usize>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<D: 'static + Digest<'static, L>, const L: usize> TestDigestConformance<D, L> {
+    /// `data` is the message to hash and `expected` its digest. `output` is
+    /// scratch space the test passes to `run()`.
+    pub fn new(
+        digest: &'static D,
+        data: &'static mut [u8],
+        expected: &'static mut [u8; L],
+        output: &'static mut [u8; L],
+    ) -> Self {
+        TestDigestConformance {
+            digest,
+            data: TakeCell::new(data),
+            expected: TakeCell::new(expected),
+            output: TakeCell::new(output),
+            case: Cell::new(Case::OddChunks),
+            position: Cell::new(0),
+            chunk: Cell::new(0),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'static self) {
+        self.digest.set_client(self);
+        self.start_case(Case::OddChunks);
+    }
+
+    fn start_case(&self, case: Case) {
+        debug!("DigestConformance: running {:?}", case);
+        self.case.set(case);
+        self.position.set(0);
+        self.chunk.set(0);
+        match case {
+            Case::OddChunks => self.add_next_chunk(),
+            Case::ClearMidOperation => {
+                if self.add_all() {
+                    self.digest.clear_data();
+                }
+            }
+            Case::ClearRecompute | Case::Verify | Case::VerifyMismatch => {
+                self.add_all();
+            }
+            Case::BusyRejected => {
+                if self.add_all() {
+                    self.check_busy();
+                }
+            }
+        }
+    }
+
+    fn next_case(&self) {
+        if self.finished.get() {
+            return;
+        }
+        match self.case.get() {
+            Case::OddChunks => self.start_case(Case::ClearMidOperation),
+            Case::ClearMidOperation => self.start_case(Case::ClearRecompute),
+            Case::ClearRecompute => self.start_case(Case::Verify),
+            Case::Verify => self.start_case(Case::VerifyMismatch),
+            Case::VerifyMismatch => self.start_case(Case::BusyRejected),
+            Case::BusyRejected => {
+                debug!("DigestConformance: all cases passed");
+                self.finished.set(true);
+                self.client.map(|client| client.done(Ok(())));
+            }
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        if self.finished.replace(true) {
+            return;
+        }
+        debug!(
+            "DigestConformance: {:?} failed: {}",
+            self.case.get(),
+            reason
+        );
+        self.client.map(|client| client.done(Err(error)));
+    }
+
+    /// Add the whole message in a single call. Returns whether the call was
+    /// accepted.
+    fn add_all(&self) -> bool {
+        self.data.take().is_some_and(|data| {
+            match self.digest.add_mut_data(SubSliceMut::new(data)) {
+                Ok(()) => true,
+                Err((e, data)) => {
+                    self.data.replace(data.take());
+                    self.fail("add_mut_data rejected", CapsuleTestError::ErrorCode(e));
+                    false
+                }
+            }
+        })
+    }
+
+    /// Add the next odd-sized chunk of the message, or compute the digest
+    /// if the whole message has been added.
+    fn add_next_chunk(&self) {
+        self.data.take().map(|data| {
+            let position = self.position.get();
+            if position >= data.len() {
+                self.data.replace(data);
+                self.compute();
+                return;
+            }
+            let size = ODD_CHUNKS[self.chunk.get() % ODD_CHUNKS.len()];
+            let end = core::cmp::min(data.len(), position + size);
+            self.chunk.set(self.chunk.get() + 1);
+            self.position.set(end);
+            let mut buffer = SubSliceMut::new(data);
+            buffer.slice(position..end);
+            if let Err((e, buffer)) = self.digest.add_mut_data(buffer) {
+                self.data.replace(buffer.take());
+                self.fail("add_mut_data rejected", CapsuleTestError::ErrorCode(e));
+            }
+        });
+    }
+
+    fn compute(&self) {
+        self.output.take().map(|output| {
+            if let Err((e, output)) = self.digest.run(output) {
+                self.output.replace(output);
+                self.fail("run rejected", CapsuleTestError::ErrorCode(e));
+            }
+        });
+    }
+
+    fn verify(&self) {
+        self.expected.take().map(|expected| {
+            if self.case.get() == Case::VerifyMismatch {
+                expected[0] ^= 0x01;
+            }
+            if let Err((e, expected)) = self.digest.verify(expected) {
+                if self.case.get() == Case::VerifyMismatch {
+                    expected[0] ^= 0x01;
+                }
+                self.expected.replace(expected);
+                self.fail("verify rejected", CapsuleTestError::ErrorCode(e));
+            }
+        });
+    }
+
+    /// Issue `run()` and `verify()` while an `add_mut_data` is outstanding.
+    /// Both must be rejected with `BUSY`.
+    fn check_busy(&self) {
+        let run_busy = self
+            .output
+            .take()
+            .is_some_and(|output| match self.digest.run(output) {
+                Ok(()) => false,
+                Err((e, output)) => {
+                    self.output.replace(output);
+                    e == ErrorCode::BUSY
+                }
+            });
+        let verify_busy =
+            self.expected
+                .take()
+                .is_some_and(|expected| match self.digest.verify(expected) {
+                    Ok(()) => false,
+                    Err((e, expected)) => {
+                        self.expected.replace(expected);
+                        e == ErrorCode::BUSY
+                    }
+                });
+        if !run_busy || !verify_busy {
+            // The engine accepted a request it cannot serve. Callbacks that
+            // follow are ignored once the test has finished.
+            self.fail(
+                "request accepted while busy",
+                CapsuleTestError::ErrorCode(ErrorCode::FAIL),
+            );
+        }
+    }
+
+    fn data_done(&self, result: Result<(), ErrorCode>, mut data: SubSliceMut<'static, u8>) {
+        let case = self.case.get();
+        if case == Case::ClearMidOperation {
+            data.reset();
+            self.data.replace(data.take());
+            match result {
+                Err(ErrorCode::CANCEL) => self.next_case(),
+                Ok(()) => self.fail(
+                    "cleared operation completed successfully",
+                    CapsuleTestError::IncorrectResult,
+                ),
+                Err(e) => self.fail(
+                    "cleared operation did not report CANCEL",
+                    CapsuleTestError::ErrorCode(e),
+                ),
+            }
+            return;
+        }
+
+        if let Err(e) = result {
+            data.reset();
+            self.data.replace(data.take());
+            self.fail("add_mut_data failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        if data.len() != 0 {
+            // Not everything was consumed; hand the remainder back.
+            if let Err((e, mut data)) = self.digest.add_mut_data(data) {
+                data.reset();
+                self.data.replace(data.take());
+                self.fail("add_mut_data rejected", CapsuleTestError::ErrorCode(e));
+            }
+            return;
+        }
+        data.reset();
+        self.data.replace(data.take());
+
+        match case {
+            Case::OddChunks => self.add_next_chunk(),
+            Case::ClearRecompute | Case::BusyRejected => self.compute(),
+            Case::Verify | Case::VerifyMismatch => self.verify(),
+            Case::ClearMidOperation => {}
+        }
+    }
+}
+
+impl<D: 'static + Digest<'static, L>, const L: usize> digest::ClientData<L>
+    for TestDigestConformance<D, L>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {
+        self.fail(
+            "unexpected add_data_done",
+            CapsuleTestError::IncorrectResult,
+        );
+    }
+
+    fn add_mut_data_done(&self, result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        self.data_done(result, data);
+    }
+}
+
+impl<D: 'static + Digest<'static, L>, const L: usize> digest::ClientHash<L>
+    for TestDigestConformance<D, L>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; L]) {
+        let matches = self
+            .expected
+            .map_or(false, |expected| expected[..] == digest[..]);
+        self.output.replace(digest);
+        match result {
+            Ok(()) if matches => self.next_case(),
+            Ok(()) => self.fail("digest mismatch", CapsuleTestError::IncorrectResult),
+            Err(e) => self.fail("run failed", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+}
+
+impl<D: 'static + Digest<'static, L>, const L: usize> digest::ClientVerify<L>
+    for TestDigestConformance<D, L>
+{
+    fn verification_done(&self, result: Result<bool, ErrorCode>, compare: &'static mut [u8; L]) {
+        let expect_match = self.case.get() != Case::VerifyMismatch;
+        if !expect_match {
+            compare[0] ^= 0x01;
+        }
+        self.expected.replace(compare);
+        match result {
+            Ok(matched) if matched == expect_match => self.next_case(),
+            Ok(_) => self.fail(
+                "wrong verification result",
+                CapsuleTestError::IncorrectResult,
+            ),
+            Err(e) => self.fail("verify failed", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+}
+
+impl<D: 'static + Digest<'static, L>, const L: usize> CapsuleTest for TestDigestConformance<D, L> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
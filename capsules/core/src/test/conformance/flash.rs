@@ -0,0 +1,412 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Conformance tests for `hil::flash` and `hil::nonvolatile_storage`
+//! implementations.
+//!
+//! `hil::flash` is page granular, so a single call can never span a page
+//! boundary. What implementations must guarantee instead is that operating on
+//! one page never disturbs its neighbour. [`TestFlashConformance`] uses two
+//! adjacent scratch pages `N` and `N + 1` and checks:
+//!
+//! 1. Writing `N` and then `N + 1` with different patterns and reading both
+//!    back returns each page's own pattern, byte for byte up to the boundary.
+//! 2. Erasing `N + 1` sets it to `0xFF` and leaves `N` intact.
+//! 3. If the board declares a protected page, writing it must either be
+//!    rejected synchronously or complete with
+//!    `Error::FlashMemoryProtectionError`. Reporting success, or a generic
+//!    `FlashError`, is a failure. Without a protected page this case is
+//!    skipped.
+//!
+//! Byte-addressed access is provided by `hil::nonvolatile_storage`, which is
+//! where unaligned offsets and writes across page boundaries are expressible.
+//! The documented semantics are that such a write is split into page
+//! operations by the implementation and the split is invisible to the caller.
+//! [`TestNonvolatileConformance`] checks this by:
+//!
+//! 1. Writing the whole supplied buffer at an odd address so that it straddles
+//!    a page boundary, and expecting `write_done` to report the full length.
+//! 2. Reading back windows at unaligned offsets and odd lengths, including one
+//!    that straddles the boundary, and comparing each against the pattern.
+//!
+//! Both tests are destructive for the pages they are given.
+
+use core::cell::Cell;
+
+use crate::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::flash;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Per-page test pattern, different for every page so that data landing on
+/// the wrong page is detected.
+fn page_pattern(page: usize, offset: usize) -> u8 {
+    (page.wrapping_mul(31) + offset.wrapping_mul(7) + 1) as u8
+}
+
+/// Address-based pattern for byte-addressed storage.
+fn address_pattern(address: usize) -> u8 {
+    (address % 251) as u8
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashStep {
+    WriteFirst,
+    WriteSecond,
+    ReadFirst,
+    ReadSecond,
+    EraseSecond,
+    ReadFirstAfterErase,
+    ReadSecondAfterErase,
+    WriteProtected,
+}
+
+pub struct TestFlashConformance<F: flash::Flash + 'static> {
+    flash: &'static F,
+    page: usize,
+    protected_page: Option<usize>,
+    buffer: TakeCell<'static, F::Page>,
+    step: Cell<FlashStep>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<F: flash::Flash + 'static> TestFlashConformance<F> {
+    /// `page` is the first of two adjacent pages the test may overwrite.
+    /// `protected_page`, if provided, is a page the hardware is configured to
+    /// refuse writes to.
+    pub fn new(
+        flash: &'static F,
+        page: usize,
+        protected_page: Option<usize>,
+        buffer: &'static mut F::Page,
+    ) -> Self {
+        TestFlashConformance {
+            flash,
+            page,
+            protected_page,
+            buffer: TakeCell::new(buffer),
+            step: Cell::new(FlashStep::WriteFirst),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.start(FlashStep::WriteFirst);
+    }
+
+    fn start(&self, step: FlashStep) {
+        if self.finished.get() {
+            return;
+        }
+        self.step.set(step);
+        match step {
+            FlashStep::WriteFirst => self.write(self.page),
+            FlashStep::WriteSecond => self.write(self.page + 1),
+            FlashStep::ReadFirst | FlashStep::ReadFirstAfterErase => self.read(self.page),
+            FlashStep::ReadSecond | FlashStep::ReadSecondAfterErase => self.read(self.page + 1),
+            FlashStep::EraseSecond => {
+                if let Err(e) = self.flash.erase_page(self.page + 1) {
+                    self.fail("erase rejected", CapsuleTestError::ErrorCode(e));
+                }
+            }
+            FlashStep::WriteProtected => match self.protected_page {
+                None => {
+                    debug!("FlashConformance: no protected page configured, skipping");
+                    self.pass();
+                }
+                Some(page) => {
+                    self.buffer.take().map(|buffer| {
+                        buffer.as_mut().fill(0);
+                        if let Err((e, buffer)) = self.flash.write_page(page, buffer) {
+                            // Refusing the write up front is conforming.
+                            self.buffer.replace(buffer);
+                            debug!("FlashConformance: protected write rejected with {:?}", e);
+                            self.pass();
+                        }
+                    });
+                }
+            },
+        }
+    }
+
+    fn write(&self, page: usize) {
+        self.buffer.take().map(|buffer| {
+            for (i, byte) in buffer.as_mut().iter_mut().enumerate() {
+                *byte = page_pattern(page, i);
+            }
+            if let Err((e, buffer)) = self.flash.write_page(page, buffer) {
+                self.buffer.replace(buffer);
+                self.fail("write rejected", CapsuleTestError::ErrorCode(e));
+            }
+        });
+    }
+
+    fn read(&self, page: usize) {
+        self.buffer.take().map(|buffer| {
+            buffer.as_mut().fill(0);
+            if let Err((e, buffer)) = self.flash.read_page(page, buffer) {
+                self.buffer.replace(buffer);
+                self.fail("read rejected", CapsuleTestError::ErrorCode(e));
+            }
+        });
+    }
+
+    /// Check the page buffer against `expected`, printing the first
+    /// mismatching offset.
+    fn check(&self, buffer: &mut F::Page, expected: impl Fn(usize) -> u8) -> bool {
+        match buffer
+            .as_mut()
+            .iter()
+            .enumerate()
+            .find(|(i, byte)| **byte != expected(*i))
+        {
+            Some((i, byte)) => {
+                debug!(
+                    "FlashConformance: offset {} is {:#x}, expected {:#x}",
+                    i,
+                    byte,
+                    expected(i)
+                );
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn pass(&self) {
+        if !self.finished.replace(true) {
+            debug!("FlashConformance: all cases passed");
+            self.client.map(|client| client.done(Ok(())));
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        if !self.finished.replace(true) {
+            debug!("FlashConformance: {:?} failed: {}", self.step.get(), reason);
+            self.client.map(|client| client.done(Err(error)));
+        }
+    }
+}
+
+impl<F: flash::Flash + 'static> flash::Client<F> for TestFlashConformance<F> {
+    fn read_complete(&self, buffer: &'static mut F::Page, result: Result<(), flash::Error>) {
+        let step = self.step.get();
+        let page = match step {
+            FlashStep::ReadFirst | FlashStep::ReadFirstAfterErase => self.page,
+            _ => self.page + 1,
+        };
+        let correct = match step {
+            FlashStep::ReadSecondAfterErase => self.check(buffer, |_| 0xFF),
+            _ => self.check(buffer, |i| page_pattern(page, i)),
+        };
+        self.buffer.replace(buffer);
+
+        if result.is_err() {
+            self.fail("read failed", CapsuleTestError::ErrorCode(ErrorCode::FAIL));
+        } else if !correct {
+            self.fail("page contents wrong", CapsuleTestError::IncorrectResult);
+        } else {
+            match step {
+                FlashStep::ReadFirst => self.start(FlashStep::ReadSecond),
+                FlashStep::ReadSecond => self.start(FlashStep::EraseSecond),
+                FlashStep::ReadFirstAfterErase => self.start(FlashStep::ReadSecondAfterErase),
+                FlashStep::ReadSecondAfterErase => self.start(FlashStep::WriteProtected),
+                _ => self.fail(
+                    "unexpected read_complete",
+                    CapsuleTestError::IncorrectResult,
+                ),
+            }
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut F::Page, result: Result<(), flash::Error>) {
+        self.buffer.replace(buffer);
+        match (self.step.get(), result) {
+            (FlashStep::WriteFirst, Ok(())) => self.start(FlashStep::WriteSecond),
+            (FlashStep::WriteSecond, Ok(())) => self.start(FlashStep::ReadFirst),
+            (FlashStep::WriteProtected, Err(flash::Error::FlashMemoryProtectionError)) => {
+                self.pass()
+            }
+            (FlashStep::WriteProtected, Ok(())) => self.fail(
+                "write to protected page succeeded",
+                CapsuleTestError::IncorrectResult,
+            ),
+            (FlashStep::WriteProtected, Err(_)) => self.fail(
+                "protected write did not report a protection error",
+                CapsuleTestError::IncorrectResult,
+            ),
+            (_, Err(_)) => self.fail("write failed", CapsuleTestError::ErrorCode(ErrorCode::FAIL)),
+            (_, Ok(())) => self.fail(
+                "unexpected write_complete",
+                CapsuleTestError::IncorrectResult,
+            ),
+        }
+    }
+
+    fn erase_complete(&self, result: Result<(), flash::Error>) {
+        match (self.step.get(), result) {
+            (FlashStep::EraseSecond, Ok(())) => self.start(FlashStep::ReadFirstAfterErase),
+            (FlashStep::EraseSecond, Err(_)) => {
+                self.fail("erase failed", CapsuleTestError::ErrorCode(ErrorCode::FAIL))
+            }
+            _ => self.fail(
+                "unexpected erase_complete",
+                CapsuleTestError::IncorrectResult,
+            ),
+        }
+    }
+}
+
+impl<F: flash::Flash + 'static> CapsuleTest for TestFlashConformance<F> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
+
+/// Number of unaligned read windows checked after the cross-page write.
+const NV_READS: usize = 4;
+
+pub struct TestNonvolatileConformance<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    region: usize,
+    page_size: usize,
+    buffer: TakeCell<'static, [u8]>,
+    /// Number of bytes written, fixed when the write is issued.
+    length: Cell<usize>,
+    /// `None` while writing, otherwise the index of the current read window.
+    read: OptionalCell<usize>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a> TestNonvolatileConformance<'a> {
+    /// `region` is the address of two adjacent pages of `page_size` bytes
+    /// the test may overwrite. `buffer` must be shorter than a page.
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        region: usize,
+        page_size: usize,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        TestNonvolatileConformance {
+            storage,
+            region,
+            page_size,
+            buffer: TakeCell::new(buffer),
+            length: Cell::new(0),
+            read: OptionalCell::empty(),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        self.buffer.take().map(|buffer| {
+            let length = buffer.len();
+            let start = self.start_address(length);
+            self.length.set(length);
+            for (i, byte) in buffer.iter_mut().enumerate() {
+                *byte = address_pattern(start + i);
+            }
+            if let Err(e) = self.storage.write(buffer, start, length) {
+                self.fail("write rejected", CapsuleTestError::ErrorCode(e));
+            }
+        });
+    }
+
+    /// An odd address such that a `length` byte write straddles the boundary
+    /// between the two pages of the region.
+    fn start_address(&self, length: usize) -> usize {
+        (self.region + self.page_size - length / 2) | 1
+    }
+
+    /// Read window `index` as an `(offset, length)` pair relative to the
+    /// start of the written data.
+    fn window(&self, index: usize) -> (usize, usize) {
+        let length = self.length.get();
+        let boundary = self.region + self.page_size - self.start_address(length);
+        match index {
+            0 => (0, length),
+            1 => (1, 7),
+            2 => (boundary - 3, 5),
+            _ => (length - 5, 5),
+        }
+    }
+
+    fn start_read(&self, index: usize) {
+        self.read.set(index);
+        self.buffer.take().map(|buffer| {
+            let (offset, length) = self.window(index);
+            buffer.fill(0);
+            let address = self.start_address(self.length.get()) + offset;
+            if let Err(e) = self.storage.read(buffer, address, length) {
+                self.fail("read rejected", CapsuleTestError::ErrorCode(e));
+            }
+        });
+    }
+
+    fn pass(&self) {
+        if !self.finished.replace(true) {
+            debug!("NonvolatileConformance: all cases passed");
+            self.client.map(|client| client.done(Ok(())));
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        if !self.finished.replace(true) {
+            match self.read.get() {
+                None => debug!("NonvolatileConformance: write failed: {}", reason),
+                Some(index) => debug!(
+                    "NonvolatileConformance: read window {:?} failed: {}",
+                    self.window(index),
+                    reason
+                ),
+            }
+            self.client.map(|client| client.done(Err(error)));
+        }
+    }
+}
+
+impl NonvolatileStorageClient for TestNonvolatileConformance<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let index = self.read.get().unwrap_or(0);
+        let (offset, expected_length) = self.window(index);
+        let address = self.start_address(self.length.get()) + offset;
+        let correct = buffer
+            .iter()
+            .take(length)
+            .enumerate()
+            .all(|(i, byte)| *byte == address_pattern(address + i));
+        self.buffer.replace(buffer);
+
+        if length != expected_length {
+            self.fail("short read", CapsuleTestError::IncorrectResult);
+        } else if !correct {
+            self.fail("data mismatch", CapsuleTestError::IncorrectResult);
+        } else if index + 1 < NV_READS {
+            self.start_read(index + 1);
+        } else {
+            self.pass();
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        if length != self.length.get() {
+            self.fail("short write", CapsuleTestError::IncorrectResult);
+        } else {
+            self.start_read(0);
+        }
+    }
+}
+
+impl CapsuleTest for TestNonvolatileConformance<'_> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
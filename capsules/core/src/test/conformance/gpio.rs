@@ -0,0 +1,237 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Conformance tests for `hil::gpio` implementations.
+//!
+//! The test needs two pins connected by a jumper wire: `output` drives the
+//! line and `input` observes it. It runs the following cases in order:
+//!
+//! 1. `Drive`: with `output` configured as an output and `input` as an input,
+//!    `set()`, `clear()` and `toggle()` on `output` are observed on `input`,
+//!    and `toggle()` returns the new level.
+//! 2. `PullUp` / `PullDown`: with both pins configured as inputs the line is
+//!    only held by the pull resistor of `input`, which must determine the level
+//!    read back after a short settling delay.
+//! 3. `Floating`: with no pull configured the level of the line is undefined,
+//!    so only `floating_state()` reporting `PullNone` is checked.
+//! 4. `InterruptEnabled`: enabling rising-edge interrupts twice and raising the
+//!    line must produce exactly one `fired()` callback.
+//! 5. `InterruptDisabled`: disabling interrupts twice and pulsing the line must
+//!    produce no further callbacks.
+//!
+//! Both pins are put into their low-power state when the test finishes.
+
+use core::cell::Cell;
+
+use crate::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::gpio;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+
+/// Time allowed for a pull resistor to settle the line, in milliseconds.
+const SETTLE_MS: u32 = 1;
+
+/// Time to wait for interrupt callbacks to arrive, in milliseconds.
+const INTERRUPT_WINDOW_MS: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Case {
+    Drive,
+    PullUp,
+    PullDown,
+    Floating,
+    InterruptEnabled,
+    InterruptDisabled,
+}
+
+pub struct TestGpioConformance<'a, A: Alarm<'a>> {
+    output: &'a dyn gpio::Pin,
+    input: &'a dyn gpio::InterruptPin<'a>,
+    alarm: &'a A,
+    case: Cell<Case>,
+    interrupts: Cell<usize>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, A: Alarm<'a>> TestGpioConformance<'a, A> {
+    pub fn new(
+        output: &'a dyn gpio::Pin,
+        input: &'a dyn gpio::InterruptPin<'a>,
+        alarm: &'a A,
+    ) -> Self {
+        TestGpioConformance {
+            output,
+            input,
+            alarm,
+            case: Cell::new(Case::Drive),
+            interrupts: Cell::new(0),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.alarm.set_alarm_client(self);
+        self.input.set_client(self);
+
+        self.case.set(Case::Drive);
+        self.output.make_output();
+        self.input.make_input();
+        self.input.set_floating_state(gpio::FloatingState::PullNone);
+        if !self.output.is_output() || !self.input.is_input() {
+            self.fail("pin configuration not reported");
+            return;
+        }
+        self.output.set();
+        if !self.input.read() {
+            self.fail("set() not observed");
+            return;
+        }
+        self.output.clear();
+        if self.input.read() {
+            self.fail("clear() not observed");
+            return;
+        }
+        if !self.output.toggle() || !self.input.read() {
+            self.fail("toggle() to high not observed");
+            return;
+        }
+        if self.output.toggle() || self.input.read() {
+            self.fail("toggle() to low not observed");
+            return;
+        }
+
+        // Release the line so only the pull resistors determine its level.
+        self.output.make_input();
+        self.output
+            .set_floating_state(gpio::FloatingState::PullNone);
+        self.start(Case::PullUp);
+    }
+
+    fn start(&self, case: Case) {
+        debug!("GpioConformance: running {:?}", case);
+        self.case.set(case);
+        match case {
+            Case::Drive => {}
+            Case::PullUp => {
+                self.input.set_floating_state(gpio::FloatingState::PullUp);
+                self.wait(SETTLE_MS);
+            }
+            Case::PullDown => {
+                self.input.set_floating_state(gpio::FloatingState::PullDown);
+                self.wait(SETTLE_MS);
+            }
+            Case::Floating => {
+                self.input.set_floating_state(gpio::FloatingState::PullNone);
+                if !matches!(self.input.floating_state(), gpio::FloatingState::PullNone) {
+                    self.fail("floating_state() does not report PullNone");
+                    return;
+                }
+                // Reading a floating input must work, whatever it returns.
+                let _ = self.input.read();
+                self.start(Case::InterruptEnabled);
+            }
+            Case::InterruptEnabled => {
+                self.output.make_output();
+                self.output.clear();
+                self.interrupts.set(0);
+                self.input
+                    .enable_interrupts(gpio::InterruptEdge::RisingEdge);
+                self.input
+                    .enable_interrupts(gpio::InterruptEdge::RisingEdge);
+                self.output.set();
+                self.wait(INTERRUPT_WINDOW_MS);
+            }
+            Case::InterruptDisabled => {
+                self.input.disable_interrupts();
+                self.input.disable_interrupts();
+                self.interrupts.set(0);
+                self.output.clear();
+                self.output.set();
+                self.wait(INTERRUPT_WINDOW_MS);
+            }
+        }
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn check(&self) {
+        match self.case.get() {
+            Case::PullUp => {
+                if !matches!(self.input.floating_state(), gpio::FloatingState::PullUp) {
+                    self.fail("floating_state() does not report PullUp");
+                } else if !self.input.read() {
+                    self.fail("line not pulled high");
+                } else {
+                    self.start(Case::PullDown);
+                }
+            }
+            Case::PullDown => {
+                if !matches!(self.input.floating_state(), gpio::FloatingState::PullDown) {
+                    self.fail("floating_state() does not report PullDown");
+                } else if self.input.read() {
+                    self.fail("line not pulled low");
+                } else {
+                    self.start(Case::Floating);
+                }
+            }
+            Case::InterruptEnabled => match self.interrupts.get() {
+                1 => self.start(Case::InterruptDisabled),
+                0 => self.fail("no interrupt for rising edge"),
+                _ => self.fail("more than one interrupt for one edge"),
+            },
+            Case::InterruptDisabled => {
+                if self.interrupts.get() != 0 {
+                    self.fail("interrupt fired while disabled");
+                } else {
+                    self.finish(Ok(()));
+                }
+            }
+            Case::Drive | Case::Floating => {}
+        }
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("GpioConformance: {:?} failed: {}", self.case.get(), reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.input.disable_interrupts();
+        self.output.deactivate_to_low_power();
+        self.input.deactivate_to_low_power();
+        if result.is_ok() {
+            debug!("GpioConformance: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for TestGpioConformance<'a, A> {
+    fn alarm(&self) {
+        if !self.finished.get() {
+            self.check();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> gpio::Client for TestGpioConformance<'a, A> {
+    fn fired(&self) {
+        self.interrupts.set(self.interrupts.get() + 1);
+    }
+}
+
+impl<'a, A: Alarm<'a>> CapsuleTest for TestGpioConformance<'a, A> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,455 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Conformance tests for the transfer and error semantics of
+//! `hil::i2c::I2CMaster` implementations.
+//!
+//! The test talks to a register-based target device described by an
+//! [`I2cTarget`], which may be an external device or the chip's own I2C slave
+//! peripheral wired back to the master. The cases are:
+//!
+//! 1. `WriteRead`: `write_read()` writes the register address and, after a
+//!    repeated start, reads back the register contents, which must match
+//!    `I2cTarget::expected`.
+//! 2. `AddressNakWrite` / `AddressNakRead`: `write()` and `read()` to an
+//!    address no device answers must complete with `Error::AddressNak`.
+//! 3. `DataNak`: writing one byte more than the target accepts must complete
+//!    with `Error::DataNak`. Skipped if `I2cTarget::data_nak_after` is `None`.
+//! 4. `Recover`: a final `write_read()` must succeed again, showing the
+//!    controller released the bus after the errors.
+//!
+//! [`TestI2cLoopback`] instead connects an `I2CMaster` to an `I2CSlave` on the
+//! same bus, typically two peripherals of one chip on jumpered pins, and checks
+//! that a master write arrives at the slave and a master read returns the data
+//! the slave provided, with both sides reporting the full length.
+
+use core::cell::Cell;
+
+use crate::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::i2c::{
+    Error, I2CHwMasterClient, I2CHwSlaveClient, I2CMaster, I2CSlave, SlaveTransmissionType,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// Description of the device the I2C conformance test runs against.
+pub struct I2cTarget {
+    /// 7-bit address of the target.
+    pub address: u8,
+    /// 7-bit address with no device on the bus.
+    pub absent_address: u8,
+    /// Register read back by the `write_read()` cases.
+    pub register: u8,
+    /// Contents of `register` and the following registers.
+    pub expected: &'static [u8],
+    /// Number of bytes, including the register address, the target
+    /// acknowledges in a single write before it NACKs.
+    pub data_nak_after: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Case {
+    WriteRead,
+    AddressNakWrite,
+    AddressNakRead,
+    DataNak,
+    Recover,
+}
+
+pub struct TestI2cConformance<'a, I: I2CMaster<'a>> {
+    i2c: &'a I,
+    target: &'a I2cTarget,
+    buffer: TakeCell<'static, [u8]>,
+    case: Cell<Case>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, I: I2CMaster<'a>> TestI2cConformance<'a, I> {
+    /// `buffer` must hold `target.expected`, and `data_nak_after + 1` bytes if
+    /// that is set.
+    pub fn new(i2c: &'a I, target: &'a I2cTarget, buffer: &'static mut [u8]) -> Self {
+        TestI2cConformance {
+            i2c,
+            target,
+            buffer: TakeCell::new(buffer),
+            case: Cell::new(Case::WriteRead),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.i2c.set_master_client(self);
+        self.i2c.enable();
+        self.start(Case::WriteRead);
+    }
+
+    fn start(&self, case: Case) {
+        debug!("I2cConformance: running {:?}", case);
+        self.case.set(case);
+        let Some(buffer) = self.buffer.take() else {
+            self.fail("buffer missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+
+        let result = match case {
+            Case::WriteRead | Case::Recover => {
+                let read_len = self.target.expected.len();
+                if buffer.len() < read_len.max(1) {
+                    self.buffer.replace(buffer);
+                    self.fail("buffer too small", CapsuleTestError::IncorrectResult);
+                    return;
+                }
+                buffer.iter_mut().for_each(|b| *b = 0);
+                buffer[0] = self.target.register;
+                self.i2c
+                    .write_read(self.target.address, buffer, 1, read_len)
+            }
+            Case::AddressNakWrite => {
+                buffer[0] = self.target.register;
+                self.i2c.write(self.target.absent_address, buffer, 1)
+            }
+            Case::AddressNakRead => self.i2c.read(self.target.absent_address, buffer, 1),
+            Case::DataNak => {
+                // Checked by `next()` before starting this case.
+                let len = self.target.data_nak_after.unwrap_or(0) + 1;
+                if buffer.len() < len {
+                    self.buffer.replace(buffer);
+                    self.fail("buffer too small", CapsuleTestError::IncorrectResult);
+                    return;
+                }
+                buffer[0] = self.target.register;
+                self.i2c.write(self.target.address, buffer, len)
+            }
+        };
+
+        if let Err((e, buffer)) = result {
+            self.buffer.replace(buffer);
+            self.fail("transfer rejected", CapsuleTestError::ErrorCode(e.into()));
+        }
+    }
+
+    fn next(&self) {
+        match self.case.get() {
+            Case::WriteRead => self.start(Case::AddressNakWrite),
+            Case::AddressNakWrite => self.start(Case::AddressNakRead),
+            Case::AddressNakRead if self.target.data_nak_after.is_some() => {
+                self.start(Case::DataNak)
+            }
+            Case::AddressNakRead => {
+                debug!("I2cConformance: target has no data NAK limit, skipping DataNak");
+                self.start(Case::Recover)
+            }
+            Case::DataNak => self.start(Case::Recover),
+            Case::Recover => self.finish(Ok(())),
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("I2cConformance: {:?} failed: {}", self.case.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.i2c.disable();
+        if result.is_ok() {
+            debug!("I2cConformance: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, I: I2CMaster<'a>> I2CHwMasterClient for TestI2cConformance<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        let matches_expected = buffer
+            .iter()
+            .zip(self.target.expected.iter())
+            .all(|(read, expected)| read == expected);
+        self.buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+
+        let expected_error = match self.case.get() {
+            Case::WriteRead | Case::Recover => None,
+            Case::AddressNakWrite | Case::AddressNakRead => Some(Error::AddressNak),
+            Case::DataNak => Some(Error::DataNak),
+        };
+        match (expected_error, status) {
+            (None, Ok(())) if !matches_expected => self.fail(
+                "register contents differ",
+                CapsuleTestError::IncorrectResult,
+            ),
+            (None, Ok(())) => self.next(),
+            (None, Err(e)) => {
+                debug!("I2cConformance: unexpected error: {}", e);
+                self.fail("transfer failed", CapsuleTestError::ErrorCode(e.into()))
+            }
+            (Some(expected), Err(e)) if e == expected => self.next(),
+            (Some(expected), Err(e)) => {
+                debug!("I2cConformance: expected {}, got {}", expected, e);
+                self.fail("wrong error", CapsuleTestError::IncorrectResult)
+            }
+            (Some(_), Ok(())) => self.fail(
+                "transfer succeeded without acknowledgement",
+                CapsuleTestError::IncorrectResult,
+            ),
+        }
+    }
+}
+
+impl<'a, I: I2CMaster<'a>> CapsuleTest for TestI2cConformance<'a, I> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
+
+/// First byte of the data the master writes in the loopback test.
+const MASTER_WRITE_SEED: u8 = 0x10;
+/// First byte of the data the slave returns in the loopback test.
+const SLAVE_SEND_SEED: u8 = 0xA0;
+
+fn loopback_pattern(seed: u8, index: usize) -> u8 {
+    seed.wrapping_add(index as u8)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum LoopbackCase {
+    MasterWrite,
+    MasterRead,
+}
+
+pub struct TestI2cLoopback<'a, M: I2CMaster<'a>, S: I2CSlave<'a>> {
+    master: &'a M,
+    slave: &'a S,
+    address: u8,
+    master_buffer: TakeCell<'static, [u8]>,
+    slave_buffer: TakeCell<'static, [u8]>,
+    case: Cell<LoopbackCase>,
+    len: Cell<usize>,
+    master_done: Cell<bool>,
+    slave_done: Cell<bool>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, M: I2CMaster<'a>, S: I2CSlave<'a>> TestI2cLoopback<'a, M, S> {
+    /// Each transfer moves as many bytes as the smaller of the two buffers
+    /// holds. The slave answers to `address`.
+    pub fn new(
+        master: &'a M,
+        slave: &'a S,
+        address: u8,
+        master_buffer: &'static mut [u8],
+        slave_buffer: &'static mut [u8],
+    ) -> Self {
+        TestI2cLoopback {
+            master,
+            slave,
+            address,
+            master_buffer: TakeCell::new(master_buffer),
+            slave_buffer: TakeCell::new(slave_buffer),
+            case: Cell::new(LoopbackCase::MasterWrite),
+            len: Cell::new(0),
+            master_done: Cell::new(false),
+            slave_done: Cell::new(false),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.master.set_master_client(self);
+        self.slave.set_slave_client(self);
+        if let Err(e) = self.slave.set_address(self.address) {
+            self.fail("set_address failed", CapsuleTestError::ErrorCode(e.into()));
+            return;
+        }
+        self.slave.enable();
+        self.master.enable();
+        self.start(LoopbackCase::MasterWrite);
+    }
+
+    fn start(&self, case: LoopbackCase) {
+        debug!("I2cLoopback: running {:?}", case);
+        self.case.set(case);
+        self.master_done.set(false);
+        self.slave_done.set(false);
+        let (Some(master_buffer), Some(slave_buffer)) =
+            (self.master_buffer.take(), self.slave_buffer.take())
+        else {
+            self.fail("buffers missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        let len = master_buffer.len().min(slave_buffer.len());
+        self.len.set(len);
+
+        // The slave must be ready before the master addresses it.
+        let (send_seed, receive) = match case {
+            LoopbackCase::MasterWrite => (MASTER_WRITE_SEED, &mut *slave_buffer),
+            LoopbackCase::MasterRead => (SLAVE_SEND_SEED, &mut *master_buffer),
+        };
+        receive.iter_mut().for_each(|b| *b = 0);
+        let slave_result = match case {
+            LoopbackCase::MasterWrite => {
+                for (i, b) in master_buffer.iter_mut().enumerate() {
+                    *b = loopback_pattern(send_seed, i);
+                }
+                self.slave.write_receive(slave_buffer, len)
+            }
+            LoopbackCase::MasterRead => {
+                for (i, b) in slave_buffer.iter_mut().enumerate() {
+                    *b = loopback_pattern(send_seed, i);
+                }
+                self.slave.read_send(slave_buffer, len)
+            }
+        };
+        if let Err((e, slave_buffer)) = slave_result {
+            self.slave_buffer.replace(slave_buffer);
+            self.master_buffer.replace(master_buffer);
+            self.fail(
+                "slave rejected buffer",
+                CapsuleTestError::ErrorCode(e.into()),
+            );
+            return;
+        }
+
+        let master_result = match case {
+            LoopbackCase::MasterWrite => self.master.write(self.address, master_buffer, len),
+            LoopbackCase::MasterRead => self.master.read(self.address, master_buffer, len),
+        };
+        if let Err((e, master_buffer)) = master_result {
+            self.master_buffer.replace(master_buffer);
+            self.fail(
+                "master transfer rejected",
+                CapsuleTestError::ErrorCode(e.into()),
+            );
+        }
+    }
+
+    /// Checks the case once both sides have reported completion.
+    fn check(&self) {
+        if !self.master_done.get() || !self.slave_done.get() {
+            return;
+        }
+        let (Some(master_buffer), Some(slave_buffer)) =
+            (self.master_buffer.take(), self.slave_buffer.take())
+        else {
+            self.fail("buffers missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        let (received, seed) = match self.case.get() {
+            LoopbackCase::MasterWrite => (&*slave_buffer, MASTER_WRITE_SEED),
+            LoopbackCase::MasterRead => (&*master_buffer, SLAVE_SEND_SEED),
+        };
+        let correct = received
+            .iter()
+            .take(self.len.get())
+            .enumerate()
+            .all(|(i, b)| *b == loopback_pattern(seed, i));
+        self.master_buffer.replace(master_buffer);
+        self.slave_buffer.replace(slave_buffer);
+
+        match self.case.get() {
+            _ if !correct => self.fail("data differs", CapsuleTestError::IncorrectResult),
+            LoopbackCase::MasterWrite => self.start(LoopbackCase::MasterRead),
+            LoopbackCase::MasterRead => self.finish(Ok(())),
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("I2cLoopback: {:?} failed: {}", self.case.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.master.disable();
+        self.slave.disable();
+        if result.is_ok() {
+            debug!("I2cLoopback: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, M: I2CMaster<'a>, S: I2CSlave<'a>> I2CHwMasterClient for TestI2cLoopback<'a, M, S> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        self.master_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        match status {
+            Ok(()) => {
+                self.master_done.set(true);
+                self.check();
+            }
+            Err(e) => {
+                debug!("I2cLoopback: master error: {}", e);
+                self.fail(
+                    "master transfer failed",
+                    CapsuleTestError::ErrorCode(e.into()),
+                );
+            }
+        }
+    }
+}
+
+impl<'a, M: I2CMaster<'a>, S: I2CSlave<'a>> I2CHwSlaveClient for TestI2cLoopback<'a, M, S> {
+    fn command_complete(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        transmission_type: SlaveTransmissionType,
+    ) {
+        self.slave_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        let expected_type = matches!(
+            (self.case.get(), transmission_type),
+            (LoopbackCase::MasterWrite, SlaveTransmissionType::Write)
+                | (LoopbackCase::MasterRead, SlaveTransmissionType::Read)
+        );
+        if !expected_type {
+            self.fail("wrong transmission type", CapsuleTestError::IncorrectResult);
+        } else if length != self.len.get() {
+            debug!(
+                "I2cLoopback: slave reported {} of {} bytes",
+                length,
+                self.len.get()
+            );
+            self.fail("wrong slave length", CapsuleTestError::IncorrectResult);
+        } else {
+            self.slave_done.set(true);
+            self.check();
+        }
+    }
+
+    fn read_expected(&self) {
+        self.fail(
+            "slave had no read buffer",
+            CapsuleTestError::IncorrectResult,
+        );
+    }
+
+    fn write_expected(&self) {
+        self.fail(
+            "slave had no write buffer",
+            CapsuleTestError::IncorrectResult,
+        );
+    }
+}
+
+impl<'a, M: I2CMaster<'a>, S: I2CSlave<'a>> CapsuleTest for TestI2cLoopback<'a, M, S> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
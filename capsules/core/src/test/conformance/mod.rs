@@ -0,0 +1,19 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! HIL conformance tests.
+//!
+//! Each test in this module is generic over a HIL trait rather than a
+//! particular driver, so that every implementation of that HIL (hardware or
+//! software) can be checked against the same observable semantics. Boards
+//! instantiate a test with their implementation and run it like any other
+//! [`CapsuleTest`](crate::test::capsule_test::CapsuleTest).
+
+pub mod adc;
+pub mod digest;
+pub mod flash;
+pub mod gpio;
+pub mod i2c;
+pub mod scheduler_timer;
+pub mod spi;
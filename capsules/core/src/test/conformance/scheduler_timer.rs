@@ -0,0 +1,193 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Conformance tests for `SchedulerTimer` implementations.
+//!
+//! The test times the scheduler timer against a clock. A scheduler timer only
+//! has to count while a process runs, and a timer clocked by the core may
+//! stop while the chip sleeps, so the test busy waits on the clock instead of
+//! setting an alarm. No process may run while the test does, as the kernel
+//! starts and resets the scheduler timer around every timeslice. It runs the
+//! following cases in order:
+//!
+//! 1. `Start`: right after `start()`, `get_remaining_us()` reports about the
+//!    whole timeslice, and never more.
+//! 2. `Monotonic`: the remaining time never increases, and decreases by the
+//!    time elapsed on the clock.
+//! 3. `Arm`: `arm()` and `disarm()`, in any order and repeated, neither stop
+//!    nor restart the timeslice.
+//! 4. `Expire`: a timeslice that expired several timeslices ago reports
+//!    `None`, so expiration is latched until it is observed.
+//! 5. `Restart`: `start()` after an expiration starts a new timeslice of the
+//!    new length.
+//! 6. `Reset`: after `reset()`, `start()` starts a new timeslice as after
+//!    boot.
+//!
+//! The timer is reset when the test finishes.
+
+use core::cell::Cell;
+use core::num::NonZeroU32;
+
+use crate::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::time::{ConvertTicks, Ticks, Time};
+use kernel::platform::scheduler_timer::SchedulerTimer;
+use kernel::utilities::cells::OptionalCell;
+
+/// Length of the timeslices the test starts, in microseconds. Within the
+/// 400 ms every implementation supports.
+const TIMESLICE_US: u32 = 10_000;
+
+/// Length of the timeslice `Restart` starts, in microseconds.
+const RESTART_US: u32 = 5_000;
+
+/// Time between two samples of the remaining time, in microseconds.
+const SAMPLE_US: u32 = 1_000;
+
+/// Number of samples `Monotonic` takes.
+const SAMPLES: usize = 4;
+
+/// Largest difference allowed between the remaining time and the time
+/// measured on the clock, in microseconds. Covers the resolution of a slow
+/// clock.
+const TOLERANCE_US: u32 = 200;
+
+/// Number of timeslices `Expire` waits for.
+const EXPIRED_TIMESLICES: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Case {
+    Start,
+    Monotonic,
+    Arm,
+    Expire,
+    Restart,
+    Reset,
+}
+
+pub struct TestSchedulerTimerConformance<'a, T: SchedulerTimer, C: Time> {
+    timer: &'a T,
+    clock: &'a C,
+    case: Cell<Case>,
+    /// Remaining time and clock time of the last sample.
+    last_sample: Cell<(u32, C::Ticks)>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, T: SchedulerTimer, C: Time> TestSchedulerTimerConformance<'a, T, C> {
+    pub fn new(timer: &'a T, clock: &'a C) -> Self {
+        TestSchedulerTimerConformance {
+            timer,
+            clock,
+            case: Cell::new(Case::Start),
+            last_sample: Cell::new((0, C::Ticks::from(0))),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let result = self.run_cases();
+        self.timer.disarm();
+        self.timer.reset();
+        let result = result.map_err(|reason| {
+            debug!(
+                "SchedulerTimerConformance: {:?} failed: {}",
+                self.case.get(),
+                reason
+            );
+            CapsuleTestError::IncorrectResult
+        });
+        if result.is_ok() {
+            debug!("SchedulerTimerConformance: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+
+    fn run_cases(&self) -> Result<(), &'static str> {
+        self.case.set(Case::Start);
+        self.start_timeslice(TIMESLICE_US)?;
+        self.wait_and_sample()?;
+
+        self.case.set(Case::Monotonic);
+        for _ in 0..SAMPLES {
+            self.wait_and_sample()?;
+        }
+
+        self.case.set(Case::Arm);
+        self.timer.arm();
+        self.timer.arm();
+        self.timer.disarm();
+        self.timer.arm();
+        self.timer.disarm();
+        self.timer.disarm();
+        self.wait_and_sample()?;
+
+        self.case.set(Case::Expire);
+        self.busy_wait(TIMESLICE_US * EXPIRED_TIMESLICES);
+        if self.timer.get_remaining_us().is_some() {
+            return Err("expiration not latched");
+        }
+
+        self.case.set(Case::Restart);
+        self.start_timeslice(RESTART_US)?;
+        self.wait_and_sample()?;
+
+        self.case.set(Case::Reset);
+        self.timer.reset();
+        self.start_timeslice(TIMESLICE_US)?;
+        self.wait_and_sample()
+    }
+
+    /// Starts a timeslice of `us` and checks the time it reports right away.
+    fn start_timeslice(&self, us: u32) -> Result<(), &'static str> {
+        self.timer.start(NonZeroU32::new(us).unwrap());
+        let remaining = self.sample().ok_or("new timeslice reported as expired")?;
+        if remaining > us {
+            return Err("new timeslice longer than requested");
+        }
+        if remaining + TOLERANCE_US < us {
+            return Err("new timeslice much shorter than requested");
+        }
+        Ok(())
+    }
+
+    /// Reads the remaining time and remembers it with the time on the clock.
+    fn sample(&self) -> Option<u32> {
+        let remaining = self.timer.get_remaining_us()?.get();
+        self.last_sample.set((remaining, self.clock.now()));
+        Some(remaining)
+    }
+
+    /// Waits `SAMPLE_US`, takes a sample and checks it against the last one.
+    fn wait_and_sample(&self) -> Result<(), &'static str> {
+        let (last, last_time) = self.last_sample.get();
+        self.busy_wait(SAMPLE_US);
+        let remaining = self.sample().ok_or("timeslice expired early")?;
+        if remaining > last {
+            return Err("remaining time increased");
+        }
+        let (_, time) = self.last_sample.get();
+        let elapsed = self.clock.ticks_to_us(time.wrapping_sub(last_time));
+        if (last - remaining).abs_diff(elapsed) > TOLERANCE_US {
+            debug!(
+                "SchedulerTimerConformance: {} us elapsed, remaining time went from {} to {} us",
+                elapsed, last, remaining
+            );
+            return Err("remaining time does not follow the clock");
+        }
+        Ok(())
+    }
+
+    fn busy_wait(&self, us: u32) {
+        let start = self.clock.now();
+        let duration = self.clock.ticks_from_us(us);
+        while self.clock.now().wrapping_sub(start) < duration {}
+    }
+}
+
+impl<T: SchedulerTimer, C: Time> CapsuleTest for TestSchedulerTimerConformance<'_, T, C> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
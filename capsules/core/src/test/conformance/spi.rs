@@ -0,0 +1,251 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Conformance tests for the chip-select behavior of `hil::spi::SpiMaster`
+//! implementations.
+//!
+//! The test needs the (active low) chip select line connected by a jumper
+//! wire to an `observer` input pin. The observer reads the line level from
+//! each `read_write_done` callback, and counts falling edges, i.e. chip select
+//! activations, through its interrupt. The cases are:
+//!
+//! 1. `Idle`: after `specify_chip_select()` the line is inactive (high).
+//! 2. `Auto`: without `hold_low()` two back-to-back transfers each activate
+//!    and release chip select, so the line is high in both callbacks and two
+//!    activations are observed.
+//! 3. `Hold`: after `hold_low()` two transfers keep the line low across both
+//!    callbacks. `release_low()` followed by a third transfer releases it, so
+//!    the line is high in the final callback and only one activation is
+//!    observed for all three transfers.
+//!
+//! The line is sampled in the callbacks, after the transfer has finished, so
+//! the test does not rely on the driver's timing during a transfer.
+
+use core::cell::Cell;
+
+use crate::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::gpio;
+use kernel::hil::spi::{SpiMaster, SpiMasterClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Time to wait for edge interrupts to arrive, in milliseconds.
+const EDGE_WINDOW_MS: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Idle,
+    AutoFirst,
+    AutoSecond,
+    AutoEdges,
+    HoldFirst,
+    HoldSecond,
+    HoldRelease,
+    HoldEdges,
+}
+
+pub struct TestSpiConformance<'a, S: SpiMaster<'a>, A: Alarm<'a>> {
+    spi: &'a S,
+    chip_select: S::ChipSelect,
+    observer: &'a dyn gpio::InterruptPin<'a>,
+    alarm: &'a A,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    step: Cell<Step>,
+    activations: Cell<usize>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, S: SpiMaster<'a>, A: Alarm<'a>> TestSpiConformance<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        chip_select: S::ChipSelect,
+        observer: &'a dyn gpio::InterruptPin<'a>,
+        alarm: &'a A,
+        write_buffer: &'static mut [u8],
+        read_buffer: &'static mut [u8],
+    ) -> Self {
+        TestSpiConformance {
+            spi,
+            chip_select,
+            observer,
+            alarm,
+            write_buffer: TakeCell::new(write_buffer),
+            read_buffer: TakeCell::new(read_buffer),
+            step: Cell::new(Step::Idle),
+            activations: Cell::new(0),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.spi.set_client(self);
+        self.alarm.set_alarm_client(self);
+        self.observer.set_client(self);
+        self.observer.make_input();
+        self.observer
+            .set_floating_state(gpio::FloatingState::PullNone);
+
+        self.step.set(Step::Idle);
+        if let Err(e) = self
+            .spi
+            .init()
+            .and_then(|()| self.spi.specify_chip_select(self.chip_select))
+        {
+            self.fail("configuring chip select", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        if !self.observer.read() {
+            self.fail(
+                "chip select active while idle",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        self.observer
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+
+        self.activations.set(0);
+        self.step.set(Step::AutoFirst);
+        self.transfer();
+    }
+
+    fn transfer(&self) {
+        let (Some(write), Some(read)) = (self.write_buffer.take(), self.read_buffer.take()) else {
+            self.fail("buffers missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        if let Err((e, write, read)) = self
+            .spi
+            .read_write_bytes(SubSliceMut::new(write), Some(SubSliceMut::new(read)))
+        {
+            self.write_buffer.replace(write.take());
+            read.map(|read| self.read_buffer.replace(read.take()));
+            self.fail("read_write_bytes rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    fn wait_for_edges(&self, step: Step) {
+        self.step.set(step);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(EDGE_WINDOW_MS));
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("SpiConformance: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.observer.disable_interrupts();
+        if result.is_ok() {
+            debug!("SpiConformance: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, S: SpiMaster<'a>, A: Alarm<'a>> SpiMasterClient for TestSpiConformance<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+        status: Result<usize, ErrorCode>,
+    ) {
+        let length = write_buffer.len();
+        self.write_buffer.replace(write_buffer.take());
+        read_buffer.map(|read| self.read_buffer.replace(read.take()));
+        if self.finished.get() {
+            return;
+        }
+
+        match status {
+            Ok(transferred) if transferred == length => {}
+            Ok(_) => {
+                self.fail("short transfer", CapsuleTestError::IncorrectResult);
+                return;
+            }
+            Err(e) => {
+                self.fail("transfer failed", CapsuleTestError::ErrorCode(e));
+                return;
+            }
+        }
+
+        let released = self.observer.read();
+        match self.step.get() {
+            Step::AutoFirst | Step::AutoSecond | Step::HoldRelease if !released => self.fail(
+                "chip select still active after transfer",
+                CapsuleTestError::IncorrectResult,
+            ),
+            Step::HoldFirst | Step::HoldSecond if released => self.fail(
+                "chip select released while held low",
+                CapsuleTestError::IncorrectResult,
+            ),
+            Step::AutoFirst => {
+                self.step.set(Step::AutoSecond);
+                self.transfer();
+            }
+            Step::AutoSecond => self.wait_for_edges(Step::AutoEdges),
+            Step::HoldFirst => {
+                self.step.set(Step::HoldSecond);
+                self.transfer();
+            }
+            Step::HoldSecond => {
+                self.spi.release_low();
+                self.step.set(Step::HoldRelease);
+                self.transfer();
+            }
+            Step::HoldRelease => self.wait_for_edges(Step::HoldEdges),
+            Step::Idle | Step::AutoEdges | Step::HoldEdges => self.fail(
+                "unexpected read_write_done",
+                CapsuleTestError::IncorrectResult,
+            ),
+        }
+    }
+}
+
+impl<'a, S: SpiMaster<'a>, A: Alarm<'a>> AlarmClient for TestSpiConformance<'a, S, A> {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        let activations = self.activations.get();
+        match self.step.get() {
+            Step::AutoEdges if activations == 2 => {
+                self.activations.set(0);
+                self.spi.hold_low();
+                self.step.set(Step::HoldFirst);
+                self.transfer();
+            }
+            Step::HoldEdges if activations == 1 => self.finish(Ok(())),
+            _ => {
+                debug!("SpiConformance: observed {} activations", activations);
+                self.fail(
+                    "wrong number of chip select activations",
+                    CapsuleTestError::IncorrectResult,
+                );
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMaster<'a>, A: Alarm<'a>> gpio::Client for TestSpiConformance<'a, S, A> {
+    fn fired(&self) {
+        self.activations.set(self.activations.get() + 1);
+    }
+}
+
+impl<'a, S: SpiMaster<'a>, A: Alarm<'a>> CapsuleTest for TestSpiConformance<'a, S, A> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
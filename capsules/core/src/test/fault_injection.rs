@@ -0,0 +1,1200 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Fault injection for checking how capsules handle a misbehaving HIL.
+//!
+//! A faulty layer sits between a capsule (or a virtualizer) and the
+//! implementation of a HIL, and forwards every call and callback unchanged
+//! until a [`FaultSchedule`] tells it to misbehave. The schedule is a script
+//! of [`Fault`]s, each for one call the layer counts, numbered from zero when
+//! the schedule is armed:
+//!
+//! ```rust,ignore
+//! static FAULTS: [(usize, Fault); 2] = [(0, Fault::Error(ErrorCode::FAIL)), (1, Fault::Delay(3))];
+//!
+//! let schedule = static_init!(FaultSchedule, FaultSchedule::new());
+//! let faulty = static_init!(
+//!     FaultySpi<'static, SPIM<'static>>,
+//!     FaultySpi::new(spim, schedule)
+//! );
+//! spim.set_client(faulty);
+//! faulty.register();
+//! // Hand `faulty` to the driver (or its mux) in place of `spim`, then
+//! schedule.arm(&FAULTS);
+//! ```
+//!
+//! Schedules can only be armed in a kernel built with the `kernel_test`
+//! feature; in any other kernel the layers only forward.
+//!
+//! The calls each layer counts, and what it does with each fault:
+//!
+//! - [`FaultyAlarm`] counts the times the alarm fires. It can delay or
+//!   duplicate a firing. Firing has no result, so errors have no effect.
+//! - [`FaultyUart`] counts calls to `transmit_buffer()` and
+//!   `transmit_word()`. Receiving passes straight through.
+//! - [`FaultySpi`] counts calls to `read_write_bytes()`. The single byte
+//!   calls are synchronous and pass straight through.
+//! - [`FaultyEntropy`] counts calls to `get()`. A delay withholds the request
+//!   from the entropy source, which starves the consumer until the request
+//!   is passed on.
+//!
+//! Completions that hand back a buffer cannot be duplicated, as the buffer
+//! can only be returned once, so duplicates only apply to the alarm and to
+//! `transmitted_word()`. Entropy is not duplicated either, as a consumer must
+//! never see the same entropy twice. Delayed and duplicated completions are
+//! delivered from a deferred call, so the board must call `register()`.
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::entropy::{self, Entropy32};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster, SpiMasterClient};
+use kernel::hil::time::{self, Alarm, Time};
+use kernel::hil::uart;
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// What a faulty layer does with one call.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fault {
+    /// The call fails with this error, as if the hardware rejected it.
+    Error(ErrorCode),
+    /// The completion is held back for this many deferred calls.
+    Delay(u8),
+    /// The completion is delivered twice.
+    Duplicate,
+}
+
+/// The faults to inject, by the number of the call they apply to.
+pub struct FaultSchedule {
+    faults: Cell<&'static [(usize, Fault)]>,
+    calls: Cell<usize>,
+    injected: Cell<usize>,
+}
+
+impl FaultSchedule {
+    pub const fn new() -> Self {
+        FaultSchedule {
+            faults: Cell::new(&[]),
+            calls: Cell::new(0),
+            injected: Cell::new(0),
+        }
+    }
+
+    /// Starts injecting `faults`, counting calls from zero. Has no effect
+    /// unless the kernel is built with the `kernel_test` feature.
+    pub fn arm(&self, faults: &'static [(usize, Fault)]) {
+        if kernel::test::KERNEL_TEST {
+            self.load(faults);
+        }
+    }
+
+    /// Stops injecting faults.
+    pub fn disarm(&self) {
+        self.load(&[]);
+    }
+
+    fn load(&self, faults: &'static [(usize, Fault)]) {
+        self.faults.set(faults);
+        self.calls.set(0);
+        self.injected.set(0);
+    }
+
+    /// Number of calls counted since the schedule was armed.
+    pub fn calls(&self) -> usize {
+        self.calls.get()
+    }
+
+    /// Number of faults injected since the schedule was armed.
+    pub fn injected(&self) -> usize {
+        self.injected.get()
+    }
+
+    /// Whether every fault of the schedule was injected.
+    pub fn is_done(&self) -> bool {
+        self.injected.get() == self.faults.get().len()
+    }
+
+    /// Counts a call and returns the fault to inject into it, if any.
+    fn next(&self) -> Option<Fault> {
+        let call = self.calls.get();
+        self.calls.set(call + 1);
+        let fault = self
+            .faults
+            .get()
+            .iter()
+            .find(|(index, _)| *index == call)
+            .map(|(_, fault)| *fault);
+        if fault.is_some() {
+            self.injected.set(self.injected.get() + 1);
+        }
+        fault
+    }
+}
+
+impl Default for FaultSchedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Holds a completion back for a number of deferred calls.
+struct Hold {
+    deferred_call: DeferredCall,
+    rounds: Cell<u8>,
+}
+
+impl Hold {
+    fn new() -> Self {
+        Hold {
+            deferred_call: DeferredCall::new(),
+            rounds: Cell::new(0),
+        }
+    }
+
+    fn start(&self, rounds: u8) {
+        self.rounds.set(rounds);
+        self.deferred_call.set();
+    }
+
+    /// Called from the deferred call. Whether the completion is due, or else
+    /// waits another round.
+    fn is_due(&self) -> bool {
+        match self.rounds.get() {
+            0 => true,
+            rounds => {
+                self.rounds.set(rounds - 1);
+                self.deferred_call.set();
+                false
+            }
+        }
+    }
+}
+
+/// Faulty layer for an alarm. The board sets it as the client of the
+/// underlying alarm.
+pub struct FaultyAlarm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    schedule: &'a FaultSchedule,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+    /// Firings not yet delivered to the client.
+    owed: Cell<usize>,
+    hold: Hold,
+}
+
+impl<'a, A: Alarm<'a>> FaultyAlarm<'a, A> {
+    pub fn new(alarm: &'a A, schedule: &'a FaultSchedule) -> Self {
+        FaultyAlarm {
+            alarm,
+            schedule,
+            client: OptionalCell::empty(),
+            owed: Cell::new(0),
+            hold: Hold::new(),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> Time for FaultyAlarm<'a, A> {
+    type Frequency = A::Frequency;
+    type Ticks = A::Ticks;
+
+    fn now(&self) -> Self::Ticks {
+        self.alarm.now()
+    }
+}
+
+impl<'a, A: Alarm<'a>> Alarm<'a> for FaultyAlarm<'a, A> {
+    fn set_alarm_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.alarm.set_alarm(reference, dt)
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        self.alarm.get_alarm()
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.alarm.disarm()
+    }
+
+    fn is_armed(&self) -> bool {
+        self.alarm.is_armed()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        self.alarm.minimum_dt()
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for FaultyAlarm<'a, A> {
+    fn alarm(&self) {
+        match self.schedule.next() {
+            Some(Fault::Delay(rounds)) => {
+                self.owed.set(self.owed.get() + 1);
+                self.hold.start(rounds);
+            }
+            Some(Fault::Duplicate) => {
+                self.client.map(|client| client.alarm());
+                self.owed.set(self.owed.get() + 1);
+                self.hold.start(0);
+            }
+            Some(Fault::Error(_)) | None => {
+                self.client.map(|client| client.alarm());
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> DeferredCallClient for FaultyAlarm<'a, A> {
+    fn handle_deferred_call(&self) {
+        if self.owed.get() > 0 && self.hold.is_due() {
+            self.owed.set(self.owed.get() - 1);
+            if self.owed.get() > 0 {
+                self.hold.start(0);
+            }
+            self.client.map(|client| client.alarm());
+        }
+    }
+
+    fn register(&'static self) {
+        self.hold.deferred_call.register(self);
+    }
+}
+
+/// A transmission the UART completed, held back by a [`FaultyUart`].
+enum Transmitted {
+    Buffer(&'static mut [u8], usize, Result<(), ErrorCode>),
+    Word(Result<(), ErrorCode>),
+}
+
+/// Faulty layer for a UART's transmit side. The board sets it as the
+/// transmit client of the underlying UART.
+///
+/// Configuring and receiving pass straight through, so the layer can stand
+/// in for the UART under a [`MuxUart`](crate::virtualizers::virtual_uart::MuxUart).
+pub struct FaultyUart<'a, U: ?Sized + uart::Uart<'a>> {
+    uart: &'a U,
+    schedule: &'a FaultSchedule,
+    client: OptionalCell<&'a dyn uart::TransmitClient>,
+    /// The fault to apply to the completion of the transmission in progress.
+    fault: OptionalCell<Fault>,
+    held: MapCell<Transmitted>,
+    hold: Hold,
+}
+
+impl<'a, U: ?Sized + uart::Uart<'a>> FaultyUart<'a, U> {
+    pub fn new(uart: &'a U, schedule: &'a FaultSchedule) -> Self {
+        FaultyUart {
+            uart,
+            schedule,
+            client: OptionalCell::empty(),
+            fault: OptionalCell::empty(),
+            held: MapCell::empty(),
+            hold: Hold::new(),
+        }
+    }
+
+    fn complete(&self, transmitted: Transmitted) {
+        match self.fault.take() {
+            Some(Fault::Delay(rounds)) => {
+                self.held.replace(transmitted);
+                self.hold.start(rounds);
+            }
+            Some(Fault::Duplicate) => {
+                if let Transmitted::Word(rval) = transmitted {
+                    self.held.replace(transmitted);
+                    self.hold.start(0);
+                    self.client.map(|client| client.transmitted_word(rval));
+                } else {
+                    self.deliver(transmitted);
+                }
+            }
+            Some(Fault::Error(_)) | None => self.deliver(transmitted),
+        }
+    }
+
+    fn deliver(&self, transmitted: Transmitted) {
+        self.client.map(move |client| match transmitted {
+            Transmitted::Buffer(tx_buffer, tx_len, rval) => {
+                client.transmitted_buffer(tx_buffer, tx_len, rval)
+            }
+            Transmitted::Word(rval) => client.transmitted_word(rval),
+        });
+    }
+}
+
+impl<'a, U: ?Sized + uart::Uart<'a>> uart::Transmit<'a> for FaultyUart<'a, U> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        match self.schedule.next() {
+            Some(Fault::Error(ecode)) => Err((ecode, tx_buffer)),
+            fault => {
+                self.fault.insert(fault);
+                self.uart
+                    .transmit_buffer(tx_buffer, tx_len)
+                    .inspect_err(|_| self.fault.clear())
+            }
+        }
+    }
+
+    fn transmit_word(&self, word: u32) -> Result<(), ErrorCode> {
+        match self.schedule.next() {
+            Some(Fault::Error(ecode)) => Err(ecode),
+            fault => {
+                self.fault.insert(fault);
+                self.uart
+                    .transmit_word(word)
+                    .inspect_err(|_| self.fault.clear())
+            }
+        }
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        self.uart.transmit_abort()
+    }
+}
+
+impl<'a, U: ?Sized + uart::Uart<'a>> uart::TransmitClient for FaultyUart<'a, U> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.complete(Transmitted::Buffer(tx_buffer, tx_len, rval));
+    }
+
+    fn transmitted_word(&self, rval: Result<(), ErrorCode>) {
+        self.complete(Transmitted::Word(rval));
+    }
+}
+
+impl<'a, U: ?Sized + uart::Uart<'a>> uart::Configure for FaultyUart<'a, U> {
+    fn configure(&self, params: uart::Parameters) -> Result<(), ErrorCode> {
+        self.uart.configure(params)
+    }
+}
+
+impl<'a, U: ?Sized + uart::Uart<'a>> uart::Receive<'a> for FaultyUart<'a, U> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.uart.set_receive_client(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.uart.receive_buffer(rx_buffer, rx_len)
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        self.uart.receive_word()
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        self.uart.receive_abort()
+    }
+}
+
+impl<'a, U: ?Sized + uart::Uart<'a>> DeferredCallClient for FaultyUart<'a, U> {
+    fn handle_deferred_call(&self) {
+        if self.held.is_some() && self.hold.is_due() {
+            if let Some(transmitted) = self.held.take() {
+                self.deliver(transmitted);
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.hold.deferred_call.register(self);
+    }
+}
+
+/// Faulty layer for an SPI controller. The board sets it as the client of
+/// the underlying controller.
+pub struct FaultySpi<'a, S: SpiMaster<'a>> {
+    spi: &'a S,
+    schedule: &'a FaultSchedule,
+    client: OptionalCell<&'a dyn SpiMasterClient>,
+    /// The fault to apply to the completion of the transfer in progress.
+    fault: OptionalCell<Fault>,
+    held: MapCell<(
+        SubSliceMut<'static, u8>,
+        Option<SubSliceMut<'static, u8>>,
+        Result<usize, ErrorCode>,
+    )>,
+    hold: Hold,
+}
+
+impl<'a, S: SpiMaster<'a>> FaultySpi<'a, S> {
+    pub fn new(spi: &'a S, schedule: &'a FaultSchedule) -> Self {
+        FaultySpi {
+            spi,
+            schedule,
+            client: OptionalCell::empty(),
+            fault: OptionalCell::empty(),
+            held: MapCell::empty(),
+            hold: Hold::new(),
+        }
+    }
+}
+
+impl<'a, S: SpiMaster<'a>> SpiMaster<'a> for FaultySpi<'a, S> {
+    type ChipSelect = S::ChipSelect;
+
+    fn init(&self) -> Result<(), ErrorCode> {
+        self.spi.init()
+    }
+
+    fn set_client(&self, client: &'a dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.held.is_some() || self.spi.is_busy()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            SubSliceMut<'static, u8>,
+            Option<SubSliceMut<'static, u8>>,
+        ),
+    > {
+        match self.schedule.next() {
+            Some(Fault::Error(ecode)) => Err((ecode, write_buffer, read_buffer)),
+            fault => {
+                self.fault.insert(fault);
+                self.spi
+                    .read_write_bytes(write_buffer, read_buffer)
+                    .inspect_err(|_| self.fault.clear())
+            }
+        }
+    }
+
+    fn write_byte(&self, val: u8) -> Result<(), ErrorCode> {
+        self.spi.write_byte(val)
+    }
+
+    fn read_byte(&self) -> Result<u8, ErrorCode> {
+        self.spi.read_byte()
+    }
+
+    fn read_write_byte(&self, val: u8) -> Result<u8, ErrorCode> {
+        self.spi.read_write_byte(val)
+    }
+
+    fn specify_chip_select(&self, cs: Self::ChipSelect) -> Result<(), ErrorCode> {
+        self.spi.specify_chip_select(cs)
+    }
+
+    fn set_rate(&self, rate: u32) -> Result<u32, ErrorCode> {
+        self.spi.set_rate(rate)
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.spi.get_rate()
+    }
+
+    fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
+        self.spi.set_polarity(polarity)
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.spi.get_polarity()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
+        self.spi.set_phase(phase)
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.spi.get_phase()
+    }
+
+    fn hold_low(&self) {
+        self.spi.hold_low()
+    }
+
+    fn release_low(&self) {
+        self.spi.release_low()
+    }
+}
+
+impl<'a, S: SpiMaster<'a>> SpiMasterClient for FaultySpi<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+        status: Result<usize, ErrorCode>,
+    ) {
+        if let Some(Fault::Delay(rounds)) = self.fault.take() {
+            self.held.replace((write_buffer, read_buffer, status));
+            self.hold.start(rounds);
+        } else {
+            self.client
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, status));
+        }
+    }
+}
+
+impl<'a, S: SpiMaster<'a>> DeferredCallClient for FaultySpi<'a, S> {
+    fn handle_deferred_call(&self) {
+        if self.held.is_some() && self.hold.is_due() {
+            if let Some((write_buffer, read_buffer, status)) = self.held.take() {
+                self.client
+                    .map(move |client| client.read_write_done(write_buffer, read_buffer, status));
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.hold.deferred_call.register(self);
+    }
+}
+
+/// Faulty layer for a 32-bit entropy source. The board sets it as the client
+/// of the underlying source.
+pub struct FaultyEntropy<'a, E: Entropy32<'a>> {
+    entropy: &'a E,
+    schedule: &'a FaultSchedule,
+    client: OptionalCell<&'a dyn entropy::Client32>,
+    /// Whether a request is withheld from the source.
+    withheld: Cell<bool>,
+    hold: Hold,
+}
+
+impl<'a, E: Entropy32<'a>> FaultyEntropy<'a, E> {
+    pub fn new(entropy: &'a E, schedule: &'a FaultSchedule) -> Self {
+        FaultyEntropy {
+            entropy,
+            schedule,
+            client: OptionalCell::empty(),
+            withheld: Cell::new(false),
+            hold: Hold::new(),
+        }
+    }
+}
+
+impl<'a, E: Entropy32<'a>> Entropy32<'a> for FaultyEntropy<'a, E> {
+    fn get(&self) -> Result<(), ErrorCode> {
+        match self.schedule.next() {
+            Some(Fault::Error(ecode)) => Err(ecode),
+            Some(Fault::Delay(rounds)) => {
+                self.withheld.set(true);
+                self.hold.start(rounds);
+                Ok(())
+            }
+            Some(Fault::Duplicate) | None => self.entropy.get(),
+        }
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        if self.withheld.replace(false) {
+            Ok(())
+        } else {
+            self.entropy.cancel()
+        }
+    }
+
+    fn set_client(&'a self, client: &'a dyn entropy::Client32) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, E: Entropy32<'a>> entropy::Client32 for FaultyEntropy<'a, E> {
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> entropy::Continue {
+        self.client.map_or(entropy::Continue::Done, |client| {
+            client.entropy_available(entropy, error)
+        })
+    }
+}
+
+impl<'a, E: Entropy32<'a>> DeferredCallClient for FaultyEntropy<'a, E> {
+    fn handle_deferred_call(&self) {
+        if self.withheld.get() && self.hold.is_due() {
+            self.withheld.set(false);
+            if let Err(ecode) = self.entropy.get() {
+                // The consumer was told a callback would follow.
+                self.client
+                    .map(|client| client.entropy_available(&mut core::iter::empty(), Err(ecode)));
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.hold.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::rng::{Entropy32To8, Entropy32ToRandom};
+    use crate::test::callback_checker::{CallbackChecker, CheckedClient, Violation};
+    use crate::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+    use crate::virtualizers::virtual_uart::{MuxUart, UartDevice};
+    use kernel::hil::entropy::Entropy8;
+    use kernel::hil::rng::{self, Rng};
+    use kernel::hil::time::{AlarmClient, Freq1KHz, Ticks, Ticks32};
+    use kernel::hil::uart::{Transmit, TransmitClient};
+    use kernel::utilities::cells::TakeCell;
+    use std::boxed::Box;
+    use std::sync::{Mutex, PoisonError};
+
+    /// Deferred calls are global, so the tests that run them take turns.
+    static DEFERRED_CALLS: Mutex<()> = Mutex::new(());
+
+    fn leak<T>(value: T) -> &'static T {
+        Box::leak(Box::new(value))
+    }
+
+    fn run_deferred_calls() {
+        while DeferredCall::has_tasks() {
+            DeferredCall::service_next_pending();
+        }
+    }
+
+    static FAULTS: [(usize, Fault); 2] = [(1, Fault::Error(ErrorCode::BUSY)), (3, Fault::Delay(2))];
+
+    #[test]
+    fn follows_schedule() {
+        let schedule = FaultSchedule::new();
+        schedule.load(&FAULTS);
+        assert_eq!(schedule.next(), None);
+        assert_eq!(schedule.next(), Some(Fault::Error(ErrorCode::BUSY)));
+        assert_eq!(schedule.next(), None);
+        assert!(!schedule.is_done());
+        assert_eq!(schedule.next(), Some(Fault::Delay(2)));
+        assert_eq!(schedule.next(), None);
+        assert_eq!(schedule.calls(), 5);
+        assert_eq!(schedule.injected(), 2);
+        assert!(schedule.is_done());
+
+        schedule.disarm();
+        assert_eq!(schedule.calls(), 0);
+        assert_eq!(schedule.next(), None);
+    }
+
+    #[test]
+    fn arms_only_in_test_kernels() {
+        let schedule = FaultSchedule::new();
+        schedule.arm(&FAULTS);
+        assert_eq!(schedule.next(), None);
+        assert_eq!(schedule.next().is_some(), kernel::test::KERNEL_TEST);
+    }
+
+    /// Alarm whose time only moves when it fires.
+    struct TestAlarm {
+        now: Cell<Ticks32>,
+        expiration: Cell<Option<Ticks32>>,
+        client: OptionalCell<&'static dyn AlarmClient>,
+    }
+
+    impl TestAlarm {
+        fn fire(&self) {
+            let expiration = self.expiration.take().expect("alarm not armed");
+            self.now.set(expiration);
+            self.client.map(|client| client.alarm());
+        }
+    }
+
+    impl Time for TestAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            self.now.get()
+        }
+    }
+
+    impl Alarm<'static> for TestAlarm {
+        fn set_alarm_client(&self, client: &'static dyn AlarmClient) {
+            self.client.set(client);
+        }
+
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            self.expiration.set(Some(reference.wrapping_add(dt)));
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            self.expiration.get().unwrap_or(0.into())
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            self.expiration.set(None);
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            self.expiration.get().is_some()
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    /// Records when a virtual alarm fired.
+    struct AlarmProbe {
+        alarm: &'static TestAlarm,
+        fired: Cell<std::vec::Vec<u32>>,
+    }
+
+    impl AlarmClient for AlarmProbe {
+        fn alarm(&self) {
+            let mut fired = self.fired.take();
+            fired.push(self.alarm.now().into_u32());
+            self.fired.set(fired);
+        }
+    }
+
+    #[test]
+    fn alarm_mux_survives_duplicate_and_delay() {
+        static ALARM_FAULTS: [(usize, Fault); 2] = [(0, Fault::Duplicate), (1, Fault::Delay(2))];
+        let _turn = DEFERRED_CALLS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let alarm = leak(TestAlarm {
+            now: Cell::new(0.into()),
+            expiration: Cell::new(None),
+            client: OptionalCell::empty(),
+        });
+        let schedule = leak(FaultSchedule::new());
+        let faulty = leak(FaultyAlarm::new(alarm, schedule));
+        alarm.set_alarm_client(faulty);
+        faulty.register();
+        let mux = leak(MuxAlarm::new(faulty));
+        faulty.set_alarm_client(mux);
+
+        let probes = [10, 20].map(|dt| {
+            let virtual_alarm = leak(VirtualMuxAlarm::new(mux));
+            virtual_alarm.setup();
+            let probe = leak(AlarmProbe {
+                alarm,
+                fired: Cell::new(std::vec::Vec::new()),
+            });
+            virtual_alarm.set_alarm_client(probe);
+            virtual_alarm.set_alarm(0.into(), dt.into());
+            probe
+        });
+
+        schedule.load(&ALARM_FAULTS);
+        // The first firing is delivered twice, the second late.
+        alarm.fire();
+        run_deferred_calls();
+        alarm.fire();
+        run_deferred_calls();
+
+        assert!(schedule.is_done());
+        assert!(!alarm.is_armed());
+        assert_eq!(probes[0].fired.take(), [10]);
+        assert_eq!(probes[1].fired.take(), [20]);
+    }
+
+    #[test]
+    fn checker_catches_duplicate_alarm() {
+        static ALARM_FAULTS: [(usize, Fault); 1] = [(0, Fault::Duplicate)];
+        let _turn = DEFERRED_CALLS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let alarm = leak(TestAlarm {
+            now: Cell::new(0.into()),
+            expiration: Cell::new(None),
+            client: OptionalCell::empty(),
+        });
+        let schedule = leak(FaultSchedule::new());
+        let faulty = leak(FaultyAlarm::new(alarm, schedule));
+        alarm.set_alarm_client(faulty);
+        faulty.register();
+        let probe = leak(AlarmProbe {
+            alarm,
+            fired: Cell::new(std::vec::Vec::new()),
+        });
+        let checker = leak(CallbackChecker::new());
+        let checked = leak(CheckedClient::new(checker, probe));
+        faulty.set_alarm_client(checked);
+
+        schedule.load(&ALARM_FAULTS);
+        checked.request();
+        faulty.set_alarm(0.into(), 10.into());
+        alarm.fire();
+        run_deferred_calls();
+
+        // Both callbacks reach the client, but only the first is expected.
+        assert_eq!(probe.fired.take(), [10, 10]);
+        assert_eq!(checker.violations(), 1);
+        assert_eq!(checker.first_violation(), Some(Violation::Duplicate));
+        assert!(checker.is_settled());
+    }
+
+    /// UART that completes a transmission when the test says so.
+    struct TestUart {
+        tx_buffer: TakeCell<'static, [u8]>,
+        tx_len: Cell<usize>,
+        client: OptionalCell<&'static dyn uart::TransmitClient>,
+    }
+
+    impl TestUart {
+        fn complete(&self) {
+            let tx_buffer = self.tx_buffer.take().expect("nothing transmitted");
+            self.client
+                .map(move |client| client.transmitted_buffer(tx_buffer, self.tx_len.get(), Ok(())));
+        }
+    }
+
+    impl uart::Configure for TestUart {
+        fn configure(&self, _params: uart::Parameters) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+    }
+
+    impl uart::Transmit<'static> for TestUart {
+        fn set_transmit_client(&self, client: &'static dyn uart::TransmitClient) {
+            self.client.set(client);
+        }
+
+        fn transmit_buffer(
+            &self,
+            tx_buffer: &'static mut [u8],
+            tx_len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            if self.tx_buffer.is_some() {
+                return Err((ErrorCode::BUSY, tx_buffer));
+            }
+            self.tx_buffer.replace(tx_buffer);
+            self.tx_len.set(tx_len);
+            Ok(())
+        }
+
+        fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn transmit_abort(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
+    impl uart::Receive<'static> for TestUart {
+        fn set_receive_client(&self, _client: &'static dyn uart::ReceiveClient) {}
+
+        fn receive_buffer(
+            &self,
+            rx_buffer: &'static mut [u8],
+            _rx_len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            Err((ErrorCode::NOSUPPORT, rx_buffer))
+        }
+
+        fn receive_word(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn receive_abort(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
+    /// Records the result of a transmission through a device of the mux.
+    struct UartProbe(Cell<Option<Result<usize, ErrorCode>>>);
+
+    impl TransmitClient for UartProbe {
+        fn transmitted_buffer(
+            &self,
+            _tx_buffer: &'static mut [u8],
+            tx_len: usize,
+            rval: Result<(), ErrorCode>,
+        ) {
+            self.0.set(Some(rval.map(|()| tx_len)));
+        }
+    }
+
+    #[test]
+    fn console_mux_survives_rejected_and_delayed_transmit() {
+        static CONSOLE_FAULTS: [(usize, Fault); 2] =
+            [(0, Fault::Error(ErrorCode::FAIL)), (1, Fault::Delay(1))];
+        let _turn = DEFERRED_CALLS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let uart = leak(TestUart {
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            client: OptionalCell::empty(),
+        });
+        let schedule = leak(FaultSchedule::new());
+        let faulty = leak(FaultyUart::new(uart, schedule));
+        uart.set_transmit_client(faulty);
+        faulty.register();
+        let mux = leak(MuxUart::new(faulty, Box::leak(Box::new([0; 8])), 115200));
+        faulty.set_transmit_client(mux);
+        mux.initialize();
+        mux.register();
+
+        let probes = [0, 1].map(|_| {
+            let device = leak(UartDevice::new(mux, false));
+            device.setup();
+            let probe = leak(UartProbe(Cell::new(None)));
+            device.set_transmit_client(probe);
+            assert_eq!(
+                device.transmit_buffer(Box::leak(Box::new([0; 4])), 4),
+                Ok(())
+            );
+            probe
+        });
+
+        // The mux serves the device set up last first. Its transmission is
+        // rejected, and the mux moves on to the other device without waiting
+        // for another transmission to finish.
+        schedule.load(&CONSOLE_FAULTS);
+        run_deferred_calls();
+        assert_eq!(probes[1].0.get(), Some(Err(ErrorCode::FAIL)));
+        assert_eq!(probes[0].0.get(), None);
+
+        // The other transmission completes late.
+        uart.complete();
+        assert_eq!(probes[0].0.get(), None);
+        run_deferred_calls();
+        assert_eq!(probes[0].0.get(), Some(Ok(4)));
+        assert!(schedule.is_done());
+    }
+
+    /// Entropy source that delivers the words the test gives it, while a
+    /// request is outstanding.
+    struct TestEntropy {
+        requested: Cell<bool>,
+        client: OptionalCell<&'static dyn entropy::Client32>,
+    }
+
+    impl TestEntropy {
+        fn deliver(&self, words: &[u32]) {
+            assert!(self.requested.get(), "entropy not requested");
+            let more = self
+                .client
+                .map(|client| client.entropy_available(&mut words.iter().copied(), Ok(())));
+            self.requested.set(more == Some(entropy::Continue::More));
+        }
+    }
+
+    impl Entropy32<'static> for TestEntropy {
+        fn get(&self) -> Result<(), ErrorCode> {
+            self.requested.set(true);
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<(), ErrorCode> {
+            self.requested.set(false);
+            Ok(())
+        }
+
+        fn set_client(&'static self, client: &'static dyn entropy::Client32) {
+            self.client.set(client);
+        }
+    }
+
+    fn faulty_entropy() -> (
+        &'static TestEntropy,
+        &'static FaultSchedule,
+        &'static FaultyEntropy<'static, TestEntropy>,
+    ) {
+        let source = leak(TestEntropy {
+            requested: Cell::new(false),
+            client: OptionalCell::empty(),
+        });
+        let schedule = leak(FaultSchedule::new());
+        let faulty = leak(FaultyEntropy::new(source, schedule));
+        source.set_client(faulty);
+        faulty.register();
+        (source, schedule, faulty)
+    }
+
+    /// Keeps the random words it gets, until it has `wanted` of them.
+    struct RandomProbe {
+        wanted: usize,
+        words: Cell<std::vec::Vec<u32>>,
+        errors: Cell<usize>,
+    }
+
+    impl rng::Client for RandomProbe {
+        fn randomness_available(
+            &self,
+            randomness: &mut dyn Iterator<Item = u32>,
+            error: Result<(), ErrorCode>,
+        ) -> rng::Continue {
+            if error.is_err() {
+                self.errors.set(self.errors.get() + 1);
+                return rng::Continue::Done;
+            }
+            let mut words = self.words.take();
+            while words.len() < self.wanted {
+                match randomness.next() {
+                    Some(word) => words.push(word),
+                    None => break,
+                }
+            }
+            let done = words.len() == self.wanted;
+            self.words.set(words);
+            if done {
+                rng::Continue::Done
+            } else {
+                rng::Continue::More
+            }
+        }
+    }
+
+    #[test]
+    fn random_waits_for_withheld_entropy() {
+        static ENTROPY_FAULTS: [(usize, Fault); 2] =
+            [(0, Fault::Error(ErrorCode::FAIL)), (1, Fault::Delay(3))];
+        let _turn = DEFERRED_CALLS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let (source, schedule, faulty) = faulty_entropy();
+        let random = leak(Entropy32ToRandom::new(faulty));
+        let probe = leak(RandomProbe {
+            wanted: 3,
+            words: Cell::new(std::vec::Vec::new()),
+            errors: Cell::new(0),
+        });
+        random.set_client(probe);
+        faulty.set_client(random);
+
+        schedule.load(&ENTROPY_FAULTS);
+        // A rejected request fails at once, and costs the consumer nothing.
+        assert_eq!(random.get(), Err(ErrorCode::FAIL));
+        assert!(!source.requested.get());
+
+        // A withheld request reaches the source late.
+        assert_eq!(random.get(), Ok(()));
+        assert!(!source.requested.get());
+        run_deferred_calls();
+        assert!(source.requested.get());
+
+        // The source runs dry halfway, then resumes.
+        source.deliver(&[1, 2]);
+        assert!(source.requested.get());
+        source.deliver(&[]);
+        assert!(source.requested.get());
+        source.deliver(&[3, 4]);
+        assert!(!source.requested.get());
+
+        assert_eq!(probe.words.take(), [1, 2, 3]);
+        assert_eq!(probe.errors.get(), 0);
+        assert!(schedule.is_done());
+    }
+
+    #[test]
+    fn cancel_drops_withheld_request() {
+        static ENTROPY_FAULTS: [(usize, Fault); 1] = [(0, Fault::Delay(1))];
+        let _turn = DEFERRED_CALLS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let (source, schedule, faulty) = faulty_entropy();
+        let random = leak(Entropy32ToRandom::new(faulty));
+        let probe = leak(RandomProbe {
+            wanted: 1,
+            words: Cell::new(std::vec::Vec::new()),
+            errors: Cell::new(0),
+        });
+        random.set_client(probe);
+        faulty.set_client(random);
+
+        schedule.load(&ENTROPY_FAULTS);
+        assert_eq!(random.get(), Ok(()));
+        assert_eq!(random.cancel(), Ok(()));
+        run_deferred_calls();
+        assert!(!source.requested.get());
+        assert_eq!(probe.words.take(), []);
+    }
+
+    /// Keeps the random bytes it gets, until it has `wanted` of them.
+    struct BytesProbe {
+        wanted: usize,
+        bytes: Cell<std::vec::Vec<u8>>,
+    }
+
+    impl entropy::Client8 for BytesProbe {
+        fn entropy_available(
+            &self,
+            entropy: &mut dyn Iterator<Item = u8>,
+            error: Result<(), ErrorCode>,
+        ) -> entropy::Continue {
+            assert_eq!(error, Ok(()));
+            let mut bytes = self.bytes.take();
+            while bytes.len() < self.wanted {
+                match entropy.next() {
+                    Some(byte) => bytes.push(byte),
+                    None => break,
+                }
+            }
+            let done = bytes.len() == self.wanted;
+            self.bytes.set(bytes);
+            if done {
+                entropy::Continue::Done
+            } else {
+                entropy::Continue::More
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_wait_for_withheld_entropy() {
+        static ENTROPY_FAULTS: [(usize, Fault); 1] = [(0, Fault::Delay(2))];
+        let _turn = DEFERRED_CALLS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let (source, schedule, faulty) = faulty_entropy();
+        let bytes = leak(Entropy32To8::new(faulty));
+        let probe = leak(BytesProbe {
+            wanted: 6,
+            bytes: Cell::new(std::vec::Vec::new()),
+        });
+        bytes.set_client(probe);
+        faulty.set_client(bytes);
+
+        schedule.load(&ENTROPY_FAULTS);
+        assert_eq!(bytes.get(), Ok(()));
+        run_deferred_calls();
+
+        // Every byte of each word once, none made up while the source is
+        // dry.
+        source.deliver(&[0x04030201]);
+        source.deliver(&[]);
+        let received = probe.bytes.take();
+        assert_eq!(received, [1, 2, 3, 4]);
+        probe.bytes.set(received);
+        source.deliver(&[0x08070605]);
+        assert!(!source.requested.get());
+        assert_eq!(probe.bytes.take(), [1, 2, 3, 4, 5, 6]);
+    }
+}
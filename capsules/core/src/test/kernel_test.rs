@@ -0,0 +1,63 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Starts the tests of a kernel test board once the kernel runs.
+//!
+//! A board that runs kernel tests sets up the debug writer, its peripherals
+//! and the tests in `main()`, but the tests may only start once the kernel
+//! loop runs, which services their callbacks and prints their `debug!()`
+//! output. `KernelTestRunner` starts the suite of the board from a deferred
+//! call, which the kernel loop services before it waits for interrupts.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! impl KernelTestSuite for TestLauncher {
+//!     fn start(&'static self) {
+//!         self.next();
+//!     }
+//! }
+//!
+//! components::test::kernel_test::TestRunnerComponent::new(test_launcher)
+//!     .finalize(components::test_runner_component_static!(TestLauncher));
+//! ```
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+
+/// The tests of a board, which run one after the other.
+pub trait KernelTestSuite {
+    /// Starts the first test. The suite starts the next ones as the tests
+    /// report their results.
+    fn start(&'static self);
+}
+
+pub struct KernelTestRunner<S: 'static + KernelTestSuite> {
+    suite: &'static S,
+    deferred_call: DeferredCall,
+}
+
+impl<S: 'static + KernelTestSuite> KernelTestRunner<S> {
+    pub fn new(suite: &'static S) -> Self {
+        KernelTestRunner {
+            suite,
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Starts the suite once the kernel loop runs.
+    pub fn start(&self) {
+        self.deferred_call.set();
+    }
+}
+
+impl<S: 'static + KernelTestSuite> DeferredCallClient for KernelTestRunner<S> {
+    fn handle_deferred_call(&self) {
+        self.suite.start();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -4,10 +4,17 @@
 
 pub mod alarm;
 pub mod alarm_edge_cases;
+pub mod buffer_tracker;
+pub mod callback_checker;
 pub mod capsule_test;
+pub mod conformance;
 pub mod double_grant_entry;
+pub mod fault_injection;
+pub mod kernel_test;
 pub mod random_alarm;
 pub mod random_timer;
+pub mod replay;
 pub mod rng;
 pub mod virtual_rng;
 pub mod virtual_uart;
+pub mod wiretap;
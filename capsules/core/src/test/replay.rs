@@ -0,0 +1,406 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Replay fakes that stand in for a device on a bus.
+//!
+//! A replay fake implements a bus HIL with no hardware behind it. It plays
+//! back a trace of [`Transfer`]s: the bytes the driver writes in each
+//! transfer are checked against the trace, and the driver reads the bytes
+//! the device answered with. This lets complex drivers be regression-tested
+//! on-target without the device attached.
+//!
+//! Traces are recorded by running the driver against the real device behind
+//! a wiretap with logging on (see [`super::wiretap`]), which prints each
+//! transfer as a [`Transfer`].
+//!
+//! Once the driver writes something other than the trace, or keeps going
+//! past its end, every transfer fails and [`SpiReplay::divergence`] tells
+//! where the driver left the trace. Completions are delivered from a
+//! deferred call, so the board must call `register()`.
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::i2c::{self, I2CHwMasterClient, I2CMaster};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster, SpiMasterClient};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// One transfer between the driver and the device.
+pub struct Transfer {
+    /// Bytes the driver writes.
+    pub write: &'static [u8],
+    /// Bytes the device answers with. The driver reads zeros past its end.
+    pub read: &'static [u8],
+}
+
+/// Where a driver left its trace.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Divergence {
+    /// The transfer at this index of the trace was written differently.
+    Write(usize),
+    /// The driver made a transfer after the last one of the trace.
+    PastEnd,
+}
+
+/// Position in a trace.
+struct Player {
+    trace: &'static [Transfer],
+    position: Cell<usize>,
+    divergence: OptionalCell<Divergence>,
+}
+
+impl Player {
+    fn new(trace: &'static [Transfer]) -> Self {
+        Player {
+            trace,
+            position: Cell::new(0),
+            divergence: OptionalCell::empty(),
+        }
+    }
+
+    /// Checks `written` against the next transfer and returns the device's
+    /// answer to it.
+    fn play(&self, written: &[u8]) -> Result<&'static [u8], ErrorCode> {
+        if self.divergence.is_some() {
+            return Err(ErrorCode::FAIL);
+        }
+        let index = self.position.get();
+        let Some(transfer) = self.trace.get(index) else {
+            self.divergence.set(Divergence::PastEnd);
+            return Err(ErrorCode::FAIL);
+        };
+        if transfer.write != written {
+            self.divergence.set(Divergence::Write(index));
+            return Err(ErrorCode::FAIL);
+        }
+        self.position.set(index + 1);
+        Ok(transfer.read)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.divergence.is_none() && self.position.get() == self.trace.len()
+    }
+}
+
+/// Copies `answer` to the start of `buffer`, zero-filling the rest.
+fn fill(buffer: &mut [u8], answer: &[u8]) {
+    let len = answer.len().min(buffer.len());
+    buffer[..len].copy_from_slice(&answer[..len]);
+    buffer[len..].fill(0);
+}
+
+/// Replays a trace as an [`SpiMaster`]. It has a single chip select, so
+/// [`SpiMaster::ChipSelect`] is `()`.
+pub struct SpiReplay<'a> {
+    player: Player,
+    client: OptionalCell<&'a dyn SpiMasterClient>,
+    /// The buffers of the transfer in progress, and its result.
+    pending: MapCell<(
+        SubSliceMut<'static, u8>,
+        Option<SubSliceMut<'static, u8>>,
+        Result<usize, ErrorCode>,
+    )>,
+    rate: Cell<u32>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    deferred_call: DeferredCall,
+}
+
+impl SpiReplay<'_> {
+    pub fn new(trace: &'static [Transfer]) -> Self {
+        SpiReplay {
+            player: Player::new(trace),
+            client: OptionalCell::empty(),
+            pending: MapCell::empty(),
+            rate: Cell::new(0),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Where the driver left the trace, if it did.
+    pub fn divergence(&self) -> Option<Divergence> {
+        self.player.divergence.get()
+    }
+
+    /// Whether the driver made every transfer of the trace and no other.
+    pub fn is_complete(&self) -> bool {
+        self.player.is_complete()
+    }
+}
+
+impl<'a> SpiMaster<'a> for SpiReplay<'a> {
+    type ChipSelect = ();
+
+    fn init(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        mut read_buffer: Option<SubSliceMut<'static, u8>>,
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            SubSliceMut<'static, u8>,
+            Option<SubSliceMut<'static, u8>>,
+        ),
+    > {
+        if self.pending.is_some() {
+            return Err((ErrorCode::BUSY, write_buffer, read_buffer));
+        }
+        let len = read_buffer.as_ref().map_or(write_buffer.len(), |read| {
+            read.len().min(write_buffer.len())
+        });
+        if len == 0 {
+            return Err((ErrorCode::INVAL, write_buffer, read_buffer));
+        }
+        let status = self.player.play(&write_buffer[..len]).map(|answer| {
+            if let Some(read) = read_buffer.as_mut() {
+                fill(&mut read.as_slice()[..len], answer);
+            }
+            len
+        });
+        self.pending.replace((write_buffer, read_buffer, status));
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn write_byte(&self, val: u8) -> Result<(), ErrorCode> {
+        self.read_write_byte(val).map(|_| ())
+    }
+
+    fn read_byte(&self) -> Result<u8, ErrorCode> {
+        self.read_write_byte(0)
+    }
+
+    fn read_write_byte(&self, val: u8) -> Result<u8, ErrorCode> {
+        if self.pending.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.player
+            .play(&[val])
+            .map(|answer| answer.first().copied().unwrap_or(0))
+    }
+
+    fn specify_chip_select(&self, _cs: Self::ChipSelect) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn set_rate(&self, rate: u32) -> Result<u32, ErrorCode> {
+        self.rate.set(rate);
+        Ok(rate)
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+
+    fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
+        self.polarity.set(polarity);
+        Ok(())
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
+        self.phase.set(phase);
+        Ok(())
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn hold_low(&self) {}
+
+    fn release_low(&self) {}
+}
+
+impl DeferredCallClient for SpiReplay<'_> {
+    fn handle_deferred_call(&self) {
+        if let Some((write_buffer, read_buffer, status)) = self.pending.take() {
+            self.client
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, status));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+/// Replays a trace as an [`I2CMaster`]. The address is not part of the
+/// trace. A transfer that leaves the trace fails with a data NAK, as if the
+/// device had rejected it.
+pub struct I2CReplay<'a> {
+    player: Player,
+    client: OptionalCell<&'a dyn I2CHwMasterClient>,
+    /// The buffer of the transfer in progress, and its result.
+    buffer: TakeCell<'static, [u8]>,
+    status: Cell<Result<(), i2c::Error>>,
+    deferred_call: DeferredCall,
+}
+
+impl I2CReplay<'_> {
+    pub fn new(trace: &'static [Transfer]) -> Self {
+        I2CReplay {
+            player: Player::new(trace),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            status: Cell::new(Ok(())),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Where the driver left the trace, if it did.
+    pub fn divergence(&self) -> Option<Divergence> {
+        self.player.divergence.get()
+    }
+
+    /// Whether the driver made every transfer of the trace and no other.
+    pub fn is_complete(&self) -> bool {
+        self.player.is_complete()
+    }
+
+    /// Plays the transfer writing the first `write_len` bytes of `data` and
+    /// reading `read_len` bytes into it.
+    fn transfer(
+        &self,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        if self.buffer.is_some() {
+            return Err((i2c::Error::Busy, data));
+        }
+        if write_len > data.len() || read_len > data.len() {
+            return Err((i2c::Error::Overrun, data));
+        }
+        let status = match self.player.play(&data[..write_len]) {
+            Ok(answer) => {
+                fill(&mut data[..read_len], answer);
+                Ok(())
+            }
+            Err(_) => Err(i2c::Error::DataNak),
+        };
+        self.status.set(status);
+        self.buffer.replace(data);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a> I2CMaster<'a> for I2CReplay<'a> {
+    fn set_master_client(&self, master_client: &'a dyn I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn write_read(
+        &self,
+        _addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.transfer(data, write_len, read_len)
+    }
+
+    fn write(
+        &self,
+        _addr: u8,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.transfer(data, len, 0)
+    }
+
+    fn read(
+        &self,
+        _addr: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.transfer(buffer, 0, len)
+    }
+}
+
+impl DeferredCallClient for I2CReplay<'_> {
+    fn handle_deferred_call(&self) {
+        if let Some(buffer) = self.buffer.take() {
+            let status = self.status.get();
+            self.client
+                .map(move |client| client.command_complete(buffer, status));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TRACE: [Transfer; 2] = [
+        Transfer {
+            write: &[0x42, 0x00],
+            read: &[0x00, 0x12],
+        },
+        Transfer {
+            write: &[0x81, 0x80],
+            read: &[],
+        },
+    ];
+
+    #[test]
+    fn follows_trace() {
+        let player = Player::new(&TRACE);
+        assert_eq!(player.play(&[0x42, 0x00]), Ok(&[0x00, 0x12][..]));
+        assert!(!player.is_complete());
+        assert_eq!(player.play(&[0x81, 0x80]), Ok(&[][..]));
+        assert!(player.is_complete());
+        assert_eq!(player.play(&[0x01]), Err(ErrorCode::FAIL));
+        assert_eq!(player.divergence.get(), Some(Divergence::PastEnd));
+    }
+
+    #[test]
+    fn stops_at_divergence() {
+        let player = Player::new(&TRACE);
+        assert!(player.play(&[0x42, 0x00]).is_ok());
+        assert_eq!(player.play(&[0x81, 0x00]), Err(ErrorCode::FAIL));
+        assert_eq!(player.divergence.get(), Some(Divergence::Write(1)));
+        assert_eq!(player.play(&[0x81, 0x80]), Err(ErrorCode::FAIL));
+        assert!(!player.is_complete());
+    }
+
+    #[test]
+    fn pads_answer() {
+        let mut buffer = [0xff; 4];
+        fill(&mut buffer, &[1, 2]);
+        assert_eq!(buffer, [1, 2, 0, 0]);
+        fill(&mut buffer[..1], &[3, 4]);
+        assert_eq!(buffer, [3, 2, 0, 0]);
+    }
+}
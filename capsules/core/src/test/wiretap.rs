@@ -0,0 +1,556 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Bus wiretaps for checking the traffic a driver generates.
+//!
+//! A wiretap sits between a capsule and a real bus, forwarding every call and
+//! callback unchanged, and records the bytes the capsule sends into a
+//! [`Capture`] ring buffer. Tests can then assert on the exact command
+//! sequence the driver emitted, e.g. the init sequence of a screen or
+//! sensor, while the bus and any attached device behave as usual.
+//!
+//! ```rust,ignore
+//! let capture = static_init!(Capture<'static>, Capture::new(capture_buffer));
+//! let wiretap = static_init!(
+//!     SpiWiretap<'static, SPIM<'static>>,
+//!     SpiWiretap::new(spim, capture)
+//! );
+//! spim.set_client(wiretap);
+//! // Hand `wiretap` to the driver (or its mux) in place of `spim`.
+//! ```
+//!
+//! Only written bytes are recorded: bytes read from the device, I2C
+//! addresses and chip select changes are not. Calls the bus rejects are not
+//! recorded either, but transfers that start and then fail are.
+//!
+//! With logging on, the SPI and I2C wiretaps also print each completed
+//! transfer, with the bytes read, as a
+//! [`Transfer`](super::replay::Transfer) of a replay trace.
+
+use core::cell::Cell;
+use core::fmt;
+
+use kernel::debug;
+use kernel::hil::i2c::{self, I2CHwMasterClient, I2CMaster};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster, SpiMasterClient};
+use kernel::hil::uart;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Longest I2C write the I2C wiretap logs in full.
+pub const I2C_LOG_WRITE_LEN: usize = 32;
+
+/// Formats bytes as a Rust slice expression.
+struct Bytes<'b>(&'b [u8]);
+
+impl fmt::Display for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("&[")?;
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{:#04x}", byte)?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// Prints a transfer so it can be pasted into a replay trace.
+fn log_transfer(write: &[u8], read: &[u8]) {
+    debug!(
+        "Transfer {{ write: {}, read: {} }},",
+        Bytes(write),
+        Bytes(read)
+    );
+}
+
+/// Ring buffer of the bytes sent through one or more wiretaps.
+///
+/// When full, the oldest bytes are overwritten; [`Capture::overwritten`]
+/// counts them so a test can tell a truncated capture from a short one.
+pub struct Capture<'a> {
+    buffer: TakeCell<'a, [u8]>,
+    start: Cell<usize>,
+    len: Cell<usize>,
+    overwritten: Cell<usize>,
+    transactions: Cell<usize>,
+}
+
+impl<'a> Capture<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Capture {
+            buffer: TakeCell::new(buffer),
+            start: Cell::new(0),
+            len: Cell::new(0),
+            overwritten: Cell::new(0),
+            transactions: Cell::new(0),
+        }
+    }
+
+    /// Number of bytes held, oldest first.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    /// Number of bytes lost because the ring was full.
+    pub fn overwritten(&self) -> usize {
+        self.overwritten.get()
+    }
+
+    /// Number of transfers recorded, including ones that sent no bytes
+    /// (e.g. I2C reads).
+    pub fn transactions(&self) -> usize {
+        self.transactions.get()
+    }
+
+    /// Returns the `index`th byte held, counting from the oldest.
+    pub fn get(&self, index: usize) -> Option<u8> {
+        if index >= self.len.get() {
+            return None;
+        }
+        self.buffer
+            .map(|buffer| buffer[(self.start.get() + index) % buffer.len()])
+    }
+
+    /// Returns the index of the first byte that differs from `expected`, or
+    /// `None` if the capture holds exactly `expected`. If one is a prefix of
+    /// the other, the index is the length of the shorter.
+    pub fn mismatch(&self, expected: &[u8]) -> Option<usize> {
+        let common = self.len.get().min(expected.len());
+        (0..common)
+            .find(|&i| self.get(i) != Some(expected[i]))
+            .or((self.len.get() != expected.len()).then_some(common))
+    }
+
+    /// Whether the capture holds exactly `expected`.
+    pub fn matches(&self, expected: &[u8]) -> bool {
+        self.mismatch(expected).is_none()
+    }
+
+    /// Discards everything recorded so far.
+    pub fn clear(&self) {
+        self.start.set(0);
+        self.len.set(0);
+        self.overwritten.set(0);
+        self.transactions.set(0);
+    }
+
+    fn record(&self, bytes: &[u8]) {
+        self.transactions.set(self.transactions.get() + 1);
+        self.buffer.map(|buffer| {
+            let size = buffer.len();
+            if size == 0 {
+                self.overwritten.set(self.overwritten.get() + bytes.len());
+                return;
+            }
+            for &byte in bytes {
+                buffer[(self.start.get() + self.len.get()) % size] = byte;
+                if self.len.get() == size {
+                    self.start.set((self.start.get() + 1) % size);
+                    self.overwritten.set(self.overwritten.get() + 1);
+                } else {
+                    self.len.set(self.len.get() + 1);
+                }
+            }
+        });
+    }
+
+    /// Drops the last transfer of `count` bytes, which the bus rejected. Any
+    /// older bytes it overwrote stay lost.
+    fn unrecord(&self, count: usize) {
+        self.transactions
+            .set(self.transactions.get().saturating_sub(1));
+        self.len.set(self.len.get().saturating_sub(count));
+    }
+}
+
+/// Wiretap for an [`SpiMaster`]. The board sets it as the client of the
+/// underlying bus.
+pub struct SpiWiretap<'a, S: SpiMaster<'a>> {
+    spi: &'a S,
+    capture: &'a Capture<'a>,
+    log: Cell<bool>,
+    client: OptionalCell<&'a dyn SpiMasterClient>,
+}
+
+impl<'a, S: SpiMaster<'a>> SpiWiretap<'a, S> {
+    pub fn new(spi: &'a S, capture: &'a Capture<'a>) -> Self {
+        SpiWiretap {
+            spi,
+            capture,
+            log: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Prints every transfer that completes while `log` is set.
+    pub fn set_logging(&self, log: bool) {
+        self.log.set(log);
+    }
+
+    fn write_byte_with<T>(
+        &self,
+        val: u8,
+        op: impl FnOnce() -> Result<T, ErrorCode>,
+    ) -> Result<T, ErrorCode> {
+        self.capture.record(&[val]);
+        op().inspect_err(|_| self.capture.unrecord(1))
+    }
+}
+
+impl<'a, S: SpiMaster<'a>> SpiMaster<'a> for SpiWiretap<'a, S> {
+    type ChipSelect = S::ChipSelect;
+
+    fn init(&self) -> Result<(), ErrorCode> {
+        self.spi.init()
+    }
+
+    fn set_client(&self, client: &'a dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.spi.is_busy()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            SubSliceMut<'static, u8>,
+            Option<SubSliceMut<'static, u8>>,
+        ),
+    > {
+        let len = read_buffer.as_ref().map_or(write_buffer.len(), |read| {
+            read.len().min(write_buffer.len())
+        });
+        self.capture.record(&write_buffer[..len]);
+        self.spi
+            .read_write_bytes(write_buffer, read_buffer)
+            .inspect_err(|_| self.capture.unrecord(len))
+    }
+
+    fn write_byte(&self, val: u8) -> Result<(), ErrorCode> {
+        self.write_byte_with(val, || self.spi.write_byte(val))
+    }
+
+    fn read_byte(&self) -> Result<u8, ErrorCode> {
+        self.write_byte_with(0, || self.spi.read_byte())
+    }
+
+    fn read_write_byte(&self, val: u8) -> Result<u8, ErrorCode> {
+        self.write_byte_with(val, || self.spi.read_write_byte(val))
+    }
+
+    fn specify_chip_select(&self, cs: Self::ChipSelect) -> Result<(), ErrorCode> {
+        self.spi.specify_chip_select(cs)
+    }
+
+    fn set_rate(&self, rate: u32) -> Result<u32, ErrorCode> {
+        self.spi.set_rate(rate)
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.spi.get_rate()
+    }
+
+    fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
+        self.spi.set_polarity(polarity)
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.spi.get_polarity()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
+        self.spi.set_phase(phase)
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.spi.get_phase()
+    }
+
+    fn hold_low(&self) {
+        self.spi.hold_low()
+    }
+
+    fn release_low(&self) {
+        self.spi.release_low()
+    }
+}
+
+impl<'a, S: SpiMaster<'a>> SpiMasterClient for SpiWiretap<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+        status: Result<usize, ErrorCode>,
+    ) {
+        if let (true, Ok(len)) = (self.log.get(), status) {
+            let read = read_buffer.as_ref().map_or(&[][..], |read| &read[..len]);
+            log_transfer(&write_buffer[..len], read);
+        }
+        self.client
+            .map(move |client| client.read_write_done(write_buffer, read_buffer, status));
+    }
+}
+
+/// Wiretap for an [`I2CMaster`]. The board sets it as the master client of
+/// the underlying bus.
+pub struct I2CWiretap<'a, I: I2CMaster<'a>> {
+    i2c: &'a I,
+    capture: &'a Capture<'a>,
+    log: Cell<bool>,
+    /// The bytes written by the transfer in progress, as reads overwrite
+    /// them, and the number of bytes it reads.
+    written: Cell<([u8; I2C_LOG_WRITE_LEN], usize)>,
+    read_len: Cell<usize>,
+    client: OptionalCell<&'a dyn I2CHwMasterClient>,
+}
+
+impl<'a, I: I2CMaster<'a>> I2CWiretap<'a, I> {
+    pub fn new(i2c: &'a I, capture: &'a Capture<'a>) -> Self {
+        I2CWiretap {
+            i2c,
+            capture,
+            log: Cell::new(false),
+            written: Cell::new(([0; I2C_LOG_WRITE_LEN], 0)),
+            read_len: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Prints every transfer that completes while `log` is set. Writes are
+    /// cut to `I2C_LOG_WRITE_LEN` bytes.
+    pub fn set_logging(&self, log: bool) {
+        self.log.set(log);
+    }
+
+    fn start(&self, written: &[u8], read_len: usize) {
+        self.capture.record(written);
+        if self.log.get() {
+            let mut stash = [0; I2C_LOG_WRITE_LEN];
+            let len = written.len().min(I2C_LOG_WRITE_LEN);
+            stash[..len].copy_from_slice(&written[..len]);
+            self.written.set((stash, len));
+            self.read_len.set(read_len);
+        }
+    }
+}
+
+impl<'a, I: I2CMaster<'a>> I2CMaster<'a> for I2CWiretap<'a, I> {
+    fn set_master_client(&self, master_client: &'a dyn I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {
+        self.i2c.enable()
+    }
+
+    fn disable(&self) {
+        self.i2c.disable()
+    }
+
+    fn write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        let len = write_len.min(data.len());
+        self.start(&data[..len], read_len);
+        self.i2c
+            .write_read(addr, data, write_len, read_len)
+            .inspect_err(|_| self.capture.unrecord(len))
+    }
+
+    fn write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        let recorded = len.min(data.len());
+        self.start(&data[..recorded], 0);
+        self.i2c
+            .write(addr, data, len)
+            .inspect_err(|_| self.capture.unrecord(recorded))
+    }
+
+    fn read(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.start(&[], len);
+        self.i2c
+            .read(addr, buffer, len)
+            .inspect_err(|_| self.capture.unrecord(0))
+    }
+}
+
+impl<'a, I: I2CMaster<'a>> I2CHwMasterClient for I2CWiretap<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        if self.log.get() && status.is_ok() {
+            let (written, len) = self.written.get();
+            let read_len = self.read_len.get().min(buffer.len());
+            log_transfer(&written[..len], &buffer[..read_len]);
+        }
+        self.client
+            .map(move |client| client.command_complete(buffer, status));
+    }
+}
+
+/// Wiretap for a UART's transmit side. The board sets it as the transmit
+/// client of the underlying UART.
+///
+/// If the UART also implements [`uart::Configure`] and [`uart::Receive`]
+/// those pass straight through, so the wiretap can stand in for a full
+/// [`uart::Uart`].
+pub struct UartWiretap<'a, U: uart::Transmit<'a>> {
+    uart: &'a U,
+    capture: &'a Capture<'a>,
+    client: OptionalCell<&'a dyn uart::TransmitClient>,
+}
+
+impl<'a, U: uart::Transmit<'a>> UartWiretap<'a, U> {
+    pub fn new(uart: &'a U, capture: &'a Capture<'a>) -> Self {
+        UartWiretap {
+            uart,
+            capture,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, U: uart::Transmit<'a>> uart::Transmit<'a> for UartWiretap<'a, U> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let len = tx_len.min(tx_buffer.len());
+        self.capture.record(&tx_buffer[..len]);
+        self.uart
+            .transmit_buffer(tx_buffer, tx_len)
+            .inspect_err(|_| self.capture.unrecord(len))
+    }
+
+    /// Records the low byte of `word`.
+    fn transmit_word(&self, word: u32) -> Result<(), ErrorCode> {
+        self.capture.record(&[word as u8]);
+        self.uart
+            .transmit_word(word)
+            .inspect_err(|_| self.capture.unrecord(1))
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        self.uart.transmit_abort()
+    }
+}
+
+impl<'a, U: uart::Transmit<'a>> uart::TransmitClient for UartWiretap<'a, U> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.client
+            .map(move |client| client.transmitted_buffer(tx_buffer, tx_len, rval));
+    }
+
+    fn transmitted_word(&self, rval: Result<(), ErrorCode>) {
+        self.client.map(|client| client.transmitted_word(rval));
+    }
+}
+
+impl<'a, U: uart::Transmit<'a> + uart::Configure> uart::Configure for UartWiretap<'a, U> {
+    fn configure(&self, params: uart::Parameters) -> Result<(), ErrorCode> {
+        self.uart.configure(params)
+    }
+}
+
+impl<'a, U: uart::Transmit<'a> + uart::Receive<'a>> uart::Receive<'a> for UartWiretap<'a, U> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.uart.set_receive_client(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.uart.receive_buffer(rx_buffer, rx_len)
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        self.uart.receive_word()
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        self.uart.receive_abort()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_in_order() {
+        let mut buffer = [0; 8];
+        let capture = Capture::new(&mut buffer);
+        capture.record(&[1, 2, 3]);
+        capture.record(&[]);
+        capture.record(&[4]);
+        assert!(capture.matches(&[1, 2, 3, 4]));
+        assert_eq!(capture.transactions(), 3);
+        assert_eq!(capture.mismatch(&[1, 2, 9, 4]), Some(2));
+        assert_eq!(capture.mismatch(&[1, 2, 3]), Some(3));
+        assert_eq!(capture.mismatch(&[1, 2, 3, 4, 5]), Some(4));
+    }
+
+    #[test]
+    fn overwrites_oldest_when_full() {
+        let mut buffer = [0; 4];
+        let capture = Capture::new(&mut buffer);
+        capture.record(&[1, 2, 3]);
+        capture.record(&[4, 5, 6]);
+        assert!(capture.matches(&[3, 4, 5, 6]));
+        assert_eq!(capture.overwritten(), 2);
+        capture.clear();
+        assert!(capture.is_empty());
+        capture.record(&[7]);
+        assert_eq!(capture.get(0), Some(7));
+        assert_eq!(capture.get(1), None);
+    }
+
+    #[test]
+    fn unrecords_rejected_transfer() {
+        let mut buffer = [0; 8];
+        let capture = Capture::new(&mut buffer);
+        capture.record(&[1, 2]);
+        capture.record(&[3, 4, 5]);
+        capture.unrecord(3);
+        assert!(capture.matches(&[1, 2]));
+        assert_eq!(capture.transactions(), 1);
+    }
+}
@@ -0,0 +1,201 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Send every transmission to several UART transmitters at once.
+//!
+//! `UartFanOut` implements `uart::Transmit` on top of a set of channels, each
+//! wrapping a transmitter such as a `UartDevice` or `SeggerRtt`. A
+//! transmission is copied into the buffer of every enabled channel and sent
+//! on all of them, and completes once the last channel completes. A channel
+//! that is disabled, or that refuses the transmission, misses it without
+//! affecting the others. This lets a board send its debug output to both a
+//! UART and RTT, and disable the UART channel while a test uses the UART.
+//!
+//! Each channel copies at most the length of its buffer, so channel buffers
+//! should be as long as the buffers the client transmits.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let channels = static_init!(
+//!     [FanOutChannel<'static>; 2],
+//!     [
+//!         FanOutChannel::new(uart_device, uart_buffer, true),
+//!         FanOutChannel::new(rtt, rtt_buffer, true),
+//!     ]
+//! );
+//! let fan_out = static_init!(UartFanOut<'static>, UartFanOut::new(channels));
+//! fan_out.setup();
+//! let debugger = static_init!(
+//!     kernel::debug::DebugWriter,
+//!     kernel::debug::DebugWriter::new(fan_out, output_buffer, ring_buffer)
+//! );
+//! fan_out.set_transmit_client(debugger);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::uart;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// One of the transmitters of a `UartFanOut`, with its own buffer.
+pub struct FanOutChannel<'a> {
+    uart: &'a dyn uart::Transmit<'a>,
+    buffer: TakeCell<'static, [u8]>,
+    enabled: Cell<bool>,
+    fan_out: OptionalCell<&'a UartFanOut<'a>>,
+}
+
+impl<'a> FanOutChannel<'a> {
+    pub fn new(
+        uart: &'a dyn uart::Transmit<'a>,
+        buffer: &'static mut [u8],
+        enabled: bool,
+    ) -> FanOutChannel<'a> {
+        FanOutChannel {
+            uart,
+            buffer: TakeCell::new(buffer),
+            enabled: Cell::new(enabled),
+            fan_out: OptionalCell::empty(),
+        }
+    }
+
+    /// Enables or disables the channel, from the next transmission on.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.set(enabled);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Starts sending `data` on the channel, returning whether it will make a
+    /// callback.
+    fn transmit(&self, data: &[u8]) -> bool {
+        if !self.enabled.get() {
+            return false;
+        }
+        let Some(buffer) = self.buffer.take() else {
+            return false;
+        };
+        let len = data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+        match self.uart.transmit_buffer(buffer, len) {
+            Ok(()) => true,
+            Err((_, buffer)) => {
+                self.buffer.replace(buffer);
+                false
+            }
+        }
+    }
+}
+
+impl uart::TransmitClient for FanOutChannel<'_> {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rval: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(buffer);
+        self.fan_out.map(|fan_out| fan_out.channel_done());
+    }
+
+    fn transmitted_word(&self, _rval: Result<(), ErrorCode>) {}
+}
+
+pub struct UartFanOut<'a> {
+    channels: &'a [FanOutChannel<'a>],
+    /// The client's buffer, held while the channels transmit its contents.
+    buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    /// Number of channels that have not completed the transmission yet.
+    pending: Cell<usize>,
+    client: OptionalCell<&'a dyn uart::TransmitClient>,
+}
+
+impl<'a> UartFanOut<'a> {
+    pub fn new(channels: &'a [FanOutChannel<'a>]) -> UartFanOut<'a> {
+        UartFanOut {
+            channels,
+            buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            pending: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Returns channel `index`, for example to enable or disable it.
+    pub fn channel(&self, index: usize) -> Option<&FanOutChannel<'a>> {
+        self.channels.get(index)
+    }
+
+    /// Makes the fan-out the client of its channels, and each channel the
+    /// client of its transmitter.
+    pub fn setup(&'a self) {
+        for channel in self.channels {
+            channel.fan_out.set(self);
+            channel.uart.set_transmit_client(channel);
+        }
+    }
+
+    fn channel_done(&self) {
+        let pending = self.pending.get().saturating_sub(1);
+        self.pending.set(pending);
+        if pending == 0 {
+            if let Some(buffer) = self.buffer.take() {
+                let len = self.len.get();
+                self.client
+                    .map(move |client| client.transmitted_buffer(buffer, len, Ok(())));
+            }
+        }
+    }
+}
+
+impl<'a> uart::Transmit<'a> for UartFanOut<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.buffer.is_some() {
+            return Err((ErrorCode::BUSY, tx_buffer));
+        }
+        if tx_len == 0 || tx_len > tx_buffer.len() {
+            return Err((ErrorCode::SIZE, tx_buffer));
+        }
+        let pending = self
+            .channels
+            .iter()
+            .filter(|channel| channel.transmit(&tx_buffer[..tx_len]))
+            .count();
+        if pending == 0 {
+            return Err((ErrorCode::OFF, tx_buffer));
+        }
+        self.pending.set(pending);
+        self.len.set(tx_len);
+        self.buffer.replace(tx_buffer);
+        Ok(())
+    }
+
+    fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        if self.buffer.is_some() {
+            // The channels complete their transmissions, and the last one
+            // returns the buffer.
+            Err(ErrorCode::FAIL)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -72,8 +72,16 @@ impl<'a, A: Alarm<'a>> VirtualMuxAlarm<'a, A> {
     }
 
     /// Call this method immediately after new() to link this to the mux, otherwise alarms won't
-    /// fire
+    /// fire. Calling it again, for example from both a component and the board, has no effect.
     pub fn setup(&'a self) {
+        if self
+            .mux
+            .virtual_alarms
+            .iter()
+            .any(|alarm| core::ptr::eq(alarm, self))
+        {
+            return;
+        }
         self.mux.virtual_alarms.push_head(self);
     }
 }
@@ -381,7 +389,7 @@ mod tests {
 
         fn now(&self) -> Ticks32 {
             // Every time we get now, it needs to increment to represent a free running timer
-            let new_now = Ticks32::from(self.now.get().into_u32() + 1);
+            let new_now = self.now.get().wrapping_add(1u32.into());
             self.now.set(new_now);
             new_now
         }
@@ -536,6 +544,29 @@ mod tests {
         assert!(!still_armed);
     }
 
+    #[test]
+    fn test_repeated_setup() {
+        let alarm = FakeAlarm::new();
+        let client = ClientCounter::new();
+
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+
+        let v_alarms = &[VirtualMuxAlarm::new(&mux), VirtualMuxAlarm::new(&mux)];
+        v_alarms[0].setup();
+        v_alarms[1].setup();
+        v_alarms[0].setup();
+        v_alarms[1].setup();
+
+        for v in v_alarms {
+            v.set_alarm_client(&client);
+            v.set_alarm(alarm.now(), 10.into());
+        }
+        run_until_disarmed(&alarm);
+
+        assert_eq!(client.count(), 2);
+    }
+
     #[test]
     fn test_quick_alarms_not_skipped() {
         let alarm = FakeAlarm::new();
@@ -581,4 +612,48 @@ mod tests {
         alarm.run_for_ticks(Ticks32::from(750));
         assert_eq!(client.count(), v_alarms.len());
     }
+
+    #[test]
+    fn test_alarms_across_wrap() {
+        let alarm = FakeAlarm::new();
+        alarm.now.set((u32::MAX - 50).into());
+
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+
+        let counters = [
+            ClientCounter::new(),
+            ClientCounter::new(),
+            ClientCounter::new(),
+        ];
+        let v_alarms = &[
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+        ];
+
+        // One alarm expires before the counter wraps, one after, and one a
+        // full counter period later.
+        let now = alarm.now();
+        for ((v, counter), dt) in v_alarms.iter().zip(&counters).zip([20, 500, u32::MAX]) {
+            v.setup();
+            v.set_alarm_client(counter);
+            v.set_alarm(now, dt.into());
+        }
+
+        alarm.run_for_ticks(Ticks32::from(100));
+        assert_eq!(counters.each_ref().map(ClientCounter::count), [1, 0, 0]);
+
+        alarm.run_for_ticks(Ticks32::from(500));
+        assert_eq!(counters.each_ref().map(ClientCounter::count), [1, 1, 0]);
+
+        // Past the wrap the long alarm must not mistake the small counter
+        // values for an expired alarm.
+        alarm.run_for_ticks(Ticks32::from(u32::MAX / 2));
+        assert_eq!(counters.each_ref().map(ClientCounter::count), [1, 1, 0]);
+
+        alarm.run_for_ticks(Ticks32::from(u32::MAX / 2));
+        assert_eq!(counters.each_ref().map(ClientCounter::count), [1, 1, 1]);
+        assert!(!alarm.is_armed());
+    }
 }
@@ -248,6 +248,9 @@ impl<'a> MuxUart<'a> {
                                     node.transmitting.set(false);
                                     client.transmitted_buffer(buf, 0, Err(ecode));
                                 });
+                                // Nothing is in flight, so move on to the
+                                // next device.
+                                self.do_next_op_async();
                             }
                         },
                         Operation::TransmitWord { word } => {
@@ -257,6 +260,7 @@ impl<'a> MuxUart<'a> {
                                     node.transmitting.set(false);
                                     client.transmitted_word(rcode);
                                 });
+                                self.do_next_op_async();
                             }
                         }
                     });
@@ -369,8 +373,17 @@ impl<'a> UartDevice<'a> {
         }
     }
 
-    /// Must be called right after `static_init!()`.
+    /// Must be called right after `static_init!()`. Calling it again has no
+    /// effect.
     pub fn setup(&'a self) {
+        if self
+            .mux
+            .devices
+            .iter()
+            .any(|device| core::ptr::eq(device, self))
+        {
+            return;
+        }
         self.mux.devices.push_head(self);
     }
 }
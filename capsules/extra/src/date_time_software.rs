@@ -0,0 +1,309 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software implementation of the DateTime HIL on top of a 64-bit counter.
+//!
+//! Chips without a calendar RTC can keep the date and time by counting from
+//! the moment it was set. The counter must not wrap, so it is usually a
+//! `LongAlarm` extending a virtual alarm. The day of the week is computed from
+//! the date, so the value passed to `set_date_time` is ignored. Reading the
+//! date and time before it was set fails with `OFF`. Nothing is retained
+//! across a reset.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let date_time = static_init!(
+//!     DateTimeSoftware<'static, LongAlarm<'static, VirtualMuxAlarm<'static, Rtc>>>,
+//!     DateTimeSoftware::new(long_alarm)
+//! );
+//! kernel::deferred_call::DeferredCallClient::register(date_time);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues, DayOfWeek, Month};
+use kernel::hil::time::{Frequency, Ticks, Ticks64, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+
+/// Earliest year the clock accepts, the start of its epoch.
+const EPOCH_YEAR: u16 = 1970;
+
+/// Latest year the clock accepts.
+const MAX_YEAR: u16 = 9999;
+
+#[derive(Clone, Copy, PartialEq)]
+enum DeferredCallTask {
+    Get,
+    Set,
+}
+
+pub struct DateTimeSoftware<'a, T: Time<Ticks = Ticks64>> {
+    time: &'a T,
+    /// Seconds since the start of 1970 at `reference`, once the clock is set.
+    seconds: OptionalCell<u64>,
+    /// Counter value when the clock was set.
+    reference: Cell<Ticks64>,
+    /// Value returned by the pending get.
+    date_time: OptionalCell<Result<DateTimeValues, ErrorCode>>,
+    deferred_call: DeferredCall,
+    deferred_call_task: OptionalCell<DeferredCallTask>,
+    client: OptionalCell<&'a dyn DateTimeClient>,
+}
+
+impl<'a, T: Time<Ticks = Ticks64>> DateTimeSoftware<'a, T> {
+    pub fn new(time: &'a T) -> Self {
+        DateTimeSoftware {
+            time,
+            seconds: OptionalCell::empty(),
+            reference: Cell::new(Ticks64::from(0u32)),
+            date_time: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            deferred_call_task: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Returns the current date and time, if the clock is set.
+    fn now(&self) -> Option<DateTimeValues> {
+        let seconds = self.seconds.get()?;
+        let elapsed = self
+            .time
+            .now()
+            .wrapping_sub(self.reference.get())
+            .into_u64();
+        let seconds = seconds + elapsed / T::Frequency::frequency() as u64;
+        Some(date_time_from_seconds(seconds))
+    }
+
+    /// Returns `ALREADY` if `task` is pending and `BUSY` if another task is.
+    fn check_idle(&self, task: DeferredCallTask) -> Result<(), ErrorCode> {
+        match self.deferred_call_task.get() {
+            None => Ok(()),
+            Some(pending) if pending == task => Err(ErrorCode::ALREADY),
+            Some(_) => Err(ErrorCode::BUSY),
+        }
+    }
+}
+
+impl<'a, T: Time<Ticks = Ticks64>> DateTime<'a> for DateTimeSoftware<'a, T> {
+    fn get_date_time(&self) -> Result<(), ErrorCode> {
+        self.check_idle(DeferredCallTask::Get)?;
+        self.date_time.set(self.now().ok_or(ErrorCode::OFF));
+        self.deferred_call_task.set(DeferredCallTask::Get);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        self.check_idle(DeferredCallTask::Set)?;
+        let seconds = seconds_from_date_time(&date_time).ok_or(ErrorCode::INVAL)?;
+        self.reference.set(self.time.now());
+        self.seconds.set(seconds);
+        self.deferred_call_task.set(DeferredCallTask::Set);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn DateTimeClient) {
+        self.client.set(client);
+    }
+}
+
+impl<T: Time<Ticks = Ticks64>> DeferredCallClient for DateTimeSoftware<'_, T> {
+    fn handle_deferred_call(&self) {
+        match self.deferred_call_task.take() {
+            Some(DeferredCallTask::Get) => {
+                let date_time = self.date_time.take().unwrap_or(Err(ErrorCode::FAIL));
+                self.client
+                    .map(|client| client.get_date_time_done(date_time));
+            }
+            Some(DeferredCallTask::Set) => {
+                self.client.map(|client| client.set_date_time_done(Ok(())));
+            }
+            None => {}
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+const MONTHS: [Month; 12] = [
+    Month::January,
+    Month::February,
+    Month::March,
+    Month::April,
+    Month::May,
+    Month::June,
+    Month::July,
+    Month::August,
+    Month::September,
+    Month::October,
+    Month::November,
+    Month::December,
+];
+
+const DAYS_OF_WEEK: [DayOfWeek; 7] = [
+    DayOfWeek::Sunday,
+    DayOfWeek::Monday,
+    DayOfWeek::Tuesday,
+    DayOfWeek::Wednesday,
+    DayOfWeek::Thursday,
+    DayOfWeek::Friday,
+    DayOfWeek::Saturday,
+];
+
+fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Returns the number of days in month `month` (1-12) of `year`.
+fn days_in_month(year: u16, month: u32) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days from the start of 1970 to the given date, with
+/// `month` from 1 to 12. Years start in March so leap days come last.
+fn days_from_civil(year: u16, month: u32, day: u8) -> u64 {
+    let year = year as u64 - u64::from(month <= 2);
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month as u64 + 9) % 12) + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the year, month (1-12) and day of the date `days` after the start
+/// of 1970. This is the inverse of [`days_from_civil`].
+fn civil_from_days(days: u64) -> (u16, u32, u8) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * march_month + 2) / 5 + 1) as u8;
+    let month = if march_month < 10 {
+        march_month + 3
+    } else {
+        march_month - 9
+    } as u32;
+    let year = (era * 400 + year_of_era) as u16 + u16::from(month <= 2);
+    (year, month, day)
+}
+
+/// Returns the seconds from the start of 1970 to `date_time`, or `None` if it
+/// is not a valid date and time.
+fn seconds_from_date_time(date_time: &DateTimeValues) -> Option<u64> {
+    let month = MONTHS.iter().position(|m| *m == date_time.month)? as u32 + 1;
+    if !(EPOCH_YEAR..=MAX_YEAR).contains(&date_time.year)
+        || date_time.day == 0
+        || date_time.day > days_in_month(date_time.year, month)
+        || date_time.hour > 23
+        || date_time.minute > 59
+        || date_time.seconds > 59
+    {
+        return None;
+    }
+    let days = days_from_civil(date_time.year, month, date_time.day);
+    Some(
+        days * SECONDS_PER_DAY
+            + date_time.hour as u64 * 3600
+            + date_time.minute as u64 * 60
+            + date_time.seconds as u64,
+    )
+}
+
+fn date_time_from_seconds(seconds: u64) -> DateTimeValues {
+    let days = seconds / SECONDS_PER_DAY;
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+    DateTimeValues {
+        year,
+        month: MONTHS[month as usize - 1],
+        day,
+        // 1 January 1970 was a Thursday.
+        day_of_week: DAYS_OF_WEEK[((days + 4) % 7) as usize],
+        hour: (seconds_of_day / 3600) as u8,
+        minute: (seconds_of_day / 60 % 60) as u8,
+        seconds: (seconds_of_day % 60) as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(year: u16, month: Month, day: u8) -> DateTimeValues {
+        DateTimeValues {
+            year,
+            month,
+            day,
+            day_of_week: DayOfWeek::Sunday,
+            hour: 0,
+            minute: 0,
+            seconds: 0,
+        }
+    }
+
+    /// Checks that the date is `days` after the start of 1970, on
+    /// `day_of_week`, both ways.
+    fn check_round_trip(year: u16, month: Month, day: u8, day_of_week: DayOfWeek, days: u64) {
+        let month_number = MONTHS.iter().position(|m| *m == month).unwrap() as u32 + 1;
+        assert_eq!(days_from_civil(year, month_number, day), days);
+        assert_eq!(civil_from_days(days), (year, month_number, day));
+
+        let seconds = days * SECONDS_PER_DAY + 23 * 3600 + 59 * 60 + 59;
+        let date_time = DateTimeValues {
+            day_of_week,
+            hour: 23,
+            minute: 59,
+            seconds: 59,
+            ..date_time(year, month, day)
+        };
+        assert_eq!(seconds_from_date_time(&date_time), Some(seconds));
+        assert_eq!(date_time_from_seconds(seconds), date_time);
+    }
+
+    #[test]
+    fn round_trips() {
+        check_round_trip(1970, Month::January, 1, DayOfWeek::Thursday, 0);
+        check_round_trip(2000, Month::February, 29, DayOfWeek::Tuesday, 11_016);
+        check_round_trip(2100, Month::February, 28, DayOfWeek::Sunday, 47_540);
+        check_round_trip(2100, Month::March, 1, DayOfWeek::Monday, 47_541);
+        check_round_trip(9999, Month::December, 31, DayOfWeek::Friday, 2_932_896);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for invalid in [
+            date_time(2023, Month::February, 29),
+            date_time(2100, Month::February, 29),
+            date_time(2024, Month::April, 31),
+            date_time(2024, Month::January, 0),
+            date_time(1969, Month::December, 31),
+            date_time(10_000, Month::January, 1),
+            DateTimeValues {
+                hour: 24,
+                ..date_time(2024, Month::January, 1)
+            },
+        ] {
+            assert_eq!(seconds_from_date_time(&invalid), None, "{:?}", invalid);
+        }
+        assert!(seconds_from_date_time(&date_time(2024, Month::February, 29)).is_some());
+    }
+}
@@ -199,7 +199,12 @@ impl<'a, R: radio::Radio<'a>> radio::RxClient for AwakeMac<'a, R> {
         // Filter packets by destination because radio is in promiscuous mode
         let mut addr_match = false;
         if let Some((_, (header, _))) = Header::decode(&buf[radio::PSDU_OFFSET..], false).done() {
-            if let Some(dst_addr) = header.dst_addr {
+            // A frame without a destination PAN is for the PAN of this device,
+            // and 0xFFFF is the broadcast PAN.
+            let pan_match = header
+                .dst_pan
+                .is_none_or(|pan| pan == self.radio.get_pan() || pan == 0xFFFF);
+            if let Some(dst_addr) = header.dst_addr.filter(|_| pan_match) {
                 addr_match = match dst_addr {
                     MacAddress::Short(addr) => {
                         // Check if address matches radio or is set to multicast short addr 0xFFFF
@@ -22,7 +22,7 @@
 // interface.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
-use crate::net::ieee802154::MacAddress;
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
@@ -112,6 +112,7 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     radio: &'a dyn MacDevice<'a>,
     dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
+    security: Cell<Option<(SecurityLevel, KeyId)>>,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
 }
@@ -163,9 +164,12 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         }
 
         // TODO: add error handling here
-        let _ = self
-            .sixlowpan
-            .init(self.src_mac_addr, dst_mac_addr, self.radio.get_pan(), None);
+        let _ = self.sixlowpan.init(
+            self.src_mac_addr,
+            dst_mac_addr,
+            self.radio.get_pan(),
+            self.security.get(),
+        );
 
         self.init_packet(dst, transport_header, payload);
 
@@ -194,11 +198,19 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             radio,
             dst_mac_addr,
             src_mac_addr,
+            security: Cell::new(None),
             client: OptionalCell::empty(),
             ip_vis,
         }
     }
 
+    /// Sets the link layer security of the frames packets are sent in. The
+    /// MAC device looks the key up, and must have a key procedure to do so.
+    /// Packets are sent without link layer security by default.
+    pub fn set_security(&self, security: Option<(SecurityLevel, KeyId)>) {
+        self.security.set(security);
+    }
+
     fn init_packet(
         &self,
         dst_addr: IPAddr,
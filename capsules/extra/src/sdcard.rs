@@ -208,6 +208,8 @@ enum AlarmState {
     WaitForDataBlocks { count: u32 },
 
     WaitForWriteBusy,
+
+    TransferRejected,
 }
 
 /// Error codes returned if an SD card transaction fails
@@ -386,7 +388,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         rb.slice(0..len);
 
         // start SPI transaction
-        let _ = self.spi.read_write_bytes(wb, Some(rb));
+        self.start_transfer(wb, rb);
     }
 
     /// wrapper for easy reading of bytes over SPI
@@ -412,7 +414,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         let mut rb: SubSliceMut<'static, u8> = read_buffer.into();
         rb.slice(0..recv_len);
 
-        let _ = self.spi.read_write_bytes(wb, Some(rb));
+        self.start_transfer(wb, rb);
     }
 
     /// wrapper for easy writing of bytes over SPI
@@ -430,7 +432,22 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         let mut rb: SubSliceMut<'static, u8> = read_buffer.into();
         rb.slice(0..recv_len);
 
-        let _ = self.spi.read_write_bytes(wb, Some(rb));
+        self.start_transfer(wb, rb);
+    }
+
+    /// start an SPI transaction. If the SPI device rejects it, it will never
+    /// complete, so take the buffers back and report the failure from the
+    /// alarm
+    fn start_transfer(&self, wb: SubSliceMut<'static, u8>, rb: SubSliceMut<'static, u8>) {
+        if let Err((_, wb, rb)) = self.spi.read_write_bytes(wb, Some(rb)) {
+            self.txbuffer.replace(wb.take());
+            if let Some(rb) = rb {
+                self.rxbuffer.replace(rb.take());
+            }
+            self.alarm_state.set(AlarmState::TransferRejected);
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.minimum_dt());
+        }
     }
 
     /// parse response bytes from SPI read buffer
@@ -1147,6 +1164,8 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             }
 
             AlarmState::RepeatHCSInit => {
+                self.alarm_state.set(AlarmState::Idle);
+
                 // check card initialization again
                 self.txbuffer.take().map(|write_buffer| {
                     self.rxbuffer.take().map(move |read_buffer| {
@@ -1165,11 +1184,11 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         );
                     });
                 });
-
-                self.alarm_state.set(AlarmState::Idle);
             }
 
             AlarmState::RepeatAppSpecificInit => {
+                self.alarm_state.set(AlarmState::Idle);
+
                 // check card initialization again
                 self.txbuffer.take().map(|write_buffer| {
                     self.rxbuffer.take().map(move |read_buffer| {
@@ -1188,11 +1207,11 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         );
                     });
                 });
-
-                self.alarm_state.set(AlarmState::Idle);
             }
 
             AlarmState::RepeatGenericInit => {
+                self.alarm_state.set(AlarmState::Idle);
+
                 // check card initialization again
                 self.txbuffer.take().map(|write_buffer| {
                     self.rxbuffer.take().map(move |read_buffer| {
@@ -1201,11 +1220,11 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         self.send_command(SDCmd::CMD1_Init, 0x0, write_buffer, read_buffer, 10);
                     });
                 });
-
-                self.alarm_state.set(AlarmState::Idle);
             }
 
             AlarmState::WaitForDataBlock => {
+                self.alarm_state.set(AlarmState::Idle);
+
                 // check card initialization again
                 self.txbuffer.take().map(|write_buffer| {
                     self.rxbuffer.take().map(move |read_buffer| {
@@ -1214,11 +1233,11 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         self.read_bytes(write_buffer, read_buffer, 1);
                     });
                 });
-
-                self.alarm_state.set(AlarmState::Idle);
             }
 
             AlarmState::WaitForDataBlocks { count } => {
+                self.alarm_state.set(AlarmState::Idle);
+
                 // check card initialization again
                 self.txbuffer.take().map(|write_buffer| {
                     self.rxbuffer.take().map(move |read_buffer| {
@@ -1227,11 +1246,11 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         self.read_bytes(write_buffer, read_buffer, 1);
                     });
                 });
-
-                self.alarm_state.set(AlarmState::Idle);
             }
 
             AlarmState::WaitForWriteBusy => {
+                self.alarm_state.set(AlarmState::Idle);
+
                 // check card initialization again
                 self.txbuffer.take().map(|write_buffer| {
                     self.rxbuffer.take().map(move |read_buffer| {
@@ -1240,8 +1259,30 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         self.read_bytes(write_buffer, read_buffer, 1);
                     });
                 });
+            }
 
+            AlarmState::TransferRejected => {
+                // the SPI device did not start the last transaction, so it
+                // will never complete
+                let error = match self.state.get() {
+                    SpiState::StartReadBlocks { .. }
+                    | SpiState::WaitReadBlock
+                    | SpiState::ReadBlockComplete
+                    | SpiState::WaitReadBlocks { .. }
+                    | SpiState::ReceivedBlock { .. }
+                    | SpiState::ReadBlocksComplete => SdCardError::ReadFailure,
+                    SpiState::StartWriteBlocks { .. }
+                    | SpiState::WriteBlockResponse
+                    | SpiState::WriteBlockBusy
+                    | SpiState::WaitWriteBlockBusy => SdCardError::WriteFailure,
+                    _ => SdCardError::InitializationFailure,
+                };
+                self.state.set(SpiState::Idle);
                 self.alarm_state.set(AlarmState::Idle);
+                self.alarm_count.set(0);
+                self.client.map(move |client| {
+                    client.error(error as u32);
+                });
             }
 
             AlarmState::Idle => {
@@ -1682,3 +1723,175 @@ impl<'a, A: hil::time::Alarm<'a>> SyscallDriver for SDCardDriver<'a, A> {
         self.grants.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+    use hil::time::{Alarm, AlarmClient, Freq1KHz, Ticks32, Time};
+    use std::boxed::Box;
+
+    /// Alarm that fires when the test says so.
+    struct TestAlarm {
+        armed: Cell<bool>,
+        client: OptionalCell<&'static dyn AlarmClient>,
+    }
+
+    impl TestAlarm {
+        fn fire(&self) {
+            assert!(self.armed.replace(false), "alarm not armed");
+            self.client.map(|client| client.alarm());
+        }
+    }
+
+    impl Time for TestAlarm {
+        type Ticks = Ticks32;
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> Ticks32 {
+            0u32.into()
+        }
+    }
+
+    impl Alarm<'static> for TestAlarm {
+        fn set_alarm_client(&self, client: &'static dyn AlarmClient) {
+            self.client.set(client);
+        }
+
+        fn set_alarm(&self, _reference: Ticks32, _dt: Ticks32) {
+            self.armed.set(true);
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            0u32.into()
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            self.armed.set(false);
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            self.armed.get()
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            Ticks32::from(1)
+        }
+    }
+
+    /// SPI device that rejects every transfer, as a faulty controller would.
+    struct RejectingSpi {
+        rejected: Cell<usize>,
+    }
+
+    impl SpiMasterDevice<'static> for RejectingSpi {
+        fn set_client(&self, _client: &'static dyn SpiMasterClient) {}
+
+        fn configure(
+            &self,
+            _cpol: ClockPolarity,
+            _cpal: ClockPhase,
+            _rate: u32,
+        ) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn read_write_bytes(
+            &self,
+            write_buffer: SubSliceMut<'static, u8>,
+            read_buffer: Option<SubSliceMut<'static, u8>>,
+        ) -> Result<
+            (),
+            (
+                ErrorCode,
+                SubSliceMut<'static, u8>,
+                Option<SubSliceMut<'static, u8>>,
+            ),
+        > {
+            self.rejected.set(self.rejected.get() + 1);
+            Err((ErrorCode::FAIL, write_buffer, read_buffer))
+        }
+
+        fn set_rate(&self, _rate: u32) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_rate(&self) -> u32 {
+            0
+        }
+
+        fn set_polarity(&self, _polarity: ClockPolarity) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_polarity(&self) -> ClockPolarity {
+            ClockPolarity::IdleLow
+        }
+
+        fn set_phase(&self, _phase: ClockPhase) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_phase(&self) -> ClockPhase {
+            ClockPhase::SampleLeading
+        }
+    }
+
+    struct TestClient {
+        errors: Cell<usize>,
+        last_error: Cell<u32>,
+    }
+
+    impl SDCardClient for TestClient {
+        fn card_detection_changed(&self, _installed: bool) {}
+        fn init_done(&self, _block_size: u32, _total_size: u64) {}
+        fn read_done(&self, _data: &'static mut [u8], _len: usize) {}
+        fn write_done(&self, _buffer: &'static mut [u8]) {}
+        fn error(&self, error: u32) {
+            self.errors.set(self.errors.get() + 1);
+            self.last_error.set(error);
+        }
+    }
+
+    #[test]
+    fn reports_rejected_transfer() {
+        let spi: &'static RejectingSpi = Box::leak(Box::new(RejectingSpi {
+            rejected: Cell::new(0),
+        }));
+        let alarm: &'static TestAlarm = Box::leak(Box::new(TestAlarm {
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }));
+        let client: &'static TestClient = Box::leak(Box::new(TestClient {
+            errors: Cell::new(0),
+            last_error: Cell::new(0),
+        }));
+        let sdcard: &'static SDCard<'static, TestAlarm> = Box::leak(Box::new(SDCard::new(
+            spi,
+            alarm,
+            None,
+            Box::leak(Box::new([0; TXRX_BUFFER_LENGTH])),
+            Box::leak(Box::new([0; TXRX_BUFFER_LENGTH])),
+        )));
+        alarm.set_alarm_client(sdcard);
+        sdcard.set_client(client);
+
+        for attempt in 1..=2 {
+            // The buffers came back, so the card can be initialized again.
+            assert_eq!(sdcard.initialize(), Ok(()));
+            assert_eq!(spi.rejected.get(), attempt);
+            // The error is reported from the alarm, not during the call.
+            assert_eq!(client.errors.get(), attempt - 1);
+            alarm.fire();
+            assert_eq!(client.errors.get(), attempt);
+            assert_eq!(
+                client.last_error.get(),
+                SdCardError::InitializationFailure as u32
+            );
+            assert!(!sdcard.is_initialized());
+        }
+    }
+}
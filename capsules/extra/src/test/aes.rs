@@ -9,8 +9,9 @@ use core::cell::Cell;
 use kernel::debug;
 use kernel::hil;
 use kernel::hil::symmetric_encryption::{
-    AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+    AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE,
 };
+use kernel::test::vectors::aes::{SP800_38A_CBC, SP800_38A_CTR, SP800_38A_ECB};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 
@@ -91,7 +92,7 @@ impl<'a, A: AES128<'a> + AES128ECB> TestAes128Ecb<'a, A> {
 
         // Copy key into key buffer and configure it in the hardware
         self.key.map(|key| {
-            for (i, b) in KEY.iter().enumerate() {
+            for (i, b) in SP800_38A_ECB.key.iter().enumerate() {
                 key[i] = *b;
             }
 
@@ -100,9 +101,9 @@ impl<'a, A: AES128<'a> + AES128ECB> TestAes128Ecb<'a, A> {
 
         // Copy mode-appropriate source into source buffer
         let source_mode = if self.encrypting.get() {
-            &PTXT
+            SP800_38A_ECB.plaintext
         } else {
-            &CTXT_ECB
+            SP800_38A_ECB.ciphertext
         };
         self.source.map(|source| {
             for (i, b) in source_mode.iter().enumerate() {
@@ -192,7 +193,7 @@ impl<'a, A: AES128<'a> + AES128Ctr> TestAes128Ctr<'a, A> {
 
         // Copy key into key buffer and configure it in the hardware
         self.key.map(|key| {
-            for (i, b) in KEY.iter().enumerate() {
+            for (i, b) in SP800_38A_CTR.key.iter().enumerate() {
                 key[i] = *b;
             }
 
@@ -201,7 +202,7 @@ impl<'a, A: AES128<'a> + AES128Ctr> TestAes128Ctr<'a, A> {
 
         // Copy mode-appropriate IV into IV buffer and configure it in the hardware
         self.iv.map(|iv| {
-            let iv_mode = &IV_CTR;
+            let iv_mode = SP800_38A_CTR.iv;
             for (i, b) in iv_mode.iter().enumerate() {
                 iv[i] = *b;
             }
@@ -211,9 +212,9 @@ impl<'a, A: AES128<'a> + AES128Ctr> TestAes128Ctr<'a, A> {
 
         // Copy mode-appropriate source into source buffer
         let source_mode = if self.encrypting.get() {
-            &PTXT
+            SP800_38A_CTR.plaintext
         } else {
-            &CTXT_CTR
+            SP800_38A_CTR.ciphertext
         };
         self.source.map(|source| {
             for (i, b) in source_mode.iter().enumerate() {
@@ -276,13 +277,13 @@ impl<'a, A: AES128<'a> + AES128Ctr> hil::symmetric_encryption::Client<'a> for Te
         self.data.replace(dest);
 
         let expected = if self.encrypting.get() {
-            &CTXT_CTR
+            SP800_38A_CTR.ciphertext
         } else {
-            &PTXT
+            SP800_38A_CTR.plaintext
         };
 
         if self.data.map_or(false, |data| {
-            &data[DATA_OFFSET..DATA_OFFSET + DATA_LEN] == expected.as_ref()
+            &data[DATA_OFFSET..DATA_OFFSET + DATA_LEN] == expected
         }) {
             debug!(
                 "aes_test CTR passed: (CTR {} {} {})",
@@ -364,7 +365,7 @@ impl<'a, A: AES128<'a> + AES128CBC> TestAes128Cbc<'a, A> {
 
         // Copy key into key buffer and configure it in the hardware
         self.key.map(|key| {
-            for (i, b) in KEY.iter().enumerate() {
+            for (i, b) in SP800_38A_CBC.key.iter().enumerate() {
                 key[i] = *b;
             }
 
@@ -373,7 +374,7 @@ impl<'a, A: AES128<'a> + AES128CBC> TestAes128Cbc<'a, A> {
 
         // Copy mode-appropriate IV into IV buffer and configure it in the hardware
         self.iv.map(|iv| {
-            let iv_mode = &IV_CBC;
+            let iv_mode = SP800_38A_CBC.iv;
 
             for (i, b) in iv_mode.iter().enumerate() {
                 iv[i] = *b;
@@ -384,9 +385,9 @@ impl<'a, A: AES128<'a> + AES128CBC> TestAes128Cbc<'a, A> {
 
         // Copy mode-appropriate source into source buffer
         let source_mode = if self.encrypting.get() {
-            &PTXT
+            SP800_38A_CBC.plaintext
         } else {
-            &CTXT_CBC
+            SP800_38A_CBC.ciphertext
         };
         self.source.map(|source| {
             for (i, b) in source_mode.iter().enumerate() {
@@ -449,13 +450,13 @@ impl<'a, A: AES128<'a> + AES128CBC> hil::symmetric_encryption::Client<'a> for Te
         self.data.replace(dest);
 
         let expected = if self.encrypting.get() {
-            &CTXT_CBC
+            SP800_38A_CBC.ciphertext
         } else {
-            &PTXT
+            SP800_38A_CBC.plaintext
         };
 
         if self.data.map_or(false, |data| {
-            &data[DATA_OFFSET..DATA_OFFSET + DATA_LEN] == expected.as_ref()
+            &data[DATA_OFFSET..DATA_OFFSET + DATA_LEN] == expected
         }) {
             debug!(
                 "aes_test passed (CBC {} {})",
@@ -514,13 +515,13 @@ impl<'a, A: AES128<'a> + AES128ECB> hil::symmetric_encryption::Client<'a> for Te
         self.data.replace(dest);
 
         let expected = if self.encrypting.get() {
-            &CTXT_ECB
+            SP800_38A_ECB.ciphertext
         } else {
-            &PTXT
+            SP800_38A_ECB.plaintext
         };
 
         if self.data.map_or(false, |data| {
-            &data[DATA_OFFSET..DATA_OFFSET + DATA_LEN] == expected.as_ref()
+            &data[DATA_OFFSET..DATA_OFFSET + DATA_LEN] == expected
         }) {
             debug!(
                 "aes_test passed (ECB {} {})",
@@ -561,69 +562,3 @@ impl<'a, A: AES128<'a> + AES128ECB> hil::symmetric_encryption::Client<'a> for Te
         }
     }
 }
-
-#[rustfmt::skip]
-const KEY: [u8; AES128_KEY_SIZE] = [
-    0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6,
-    0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c
-];
-
-#[rustfmt::skip]
-const IV_CTR: [u8; AES128_BLOCK_SIZE] = [
-    0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7,
-    0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff
-];
-
-#[rustfmt::skip]
-const IV_CBC: [u8; AES128_BLOCK_SIZE] = [
-    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
-    0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f
-];
-
-#[rustfmt::skip]
-const PTXT: [u8; 4 * AES128_BLOCK_SIZE] = [
-    0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96,
-    0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
-    0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c,
-    0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf, 0x8e, 0x51,
-    0x30, 0xc8, 0x1c, 0x46, 0xa3, 0x5c, 0xe4, 0x11,
-    0xe5, 0xfb, 0xc1, 0x19, 0x1a, 0x0a, 0x52, 0xef,
-    0xf6, 0x9f, 0x24, 0x45, 0xdf, 0x4f, 0x9b, 0x17,
-    0xad, 0x2b, 0x41, 0x7b, 0xe6, 0x6c, 0x37, 0x10
-];
-
-#[rustfmt::skip]
-const CTXT_CTR: [u8; 4 * AES128_BLOCK_SIZE] = [
-    0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26,
-    0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6, 0xce,
-    0x98, 0x06, 0xf6, 0x6b, 0x79, 0x70, 0xfd, 0xff,
-    0x86, 0x17, 0x18, 0x7b, 0xb9, 0xff, 0xfd, 0xff,
-    0x5a, 0xe4, 0xdf, 0x3e, 0xdb, 0xd5, 0xd3, 0x5e,
-    0x5b, 0x4f, 0x09, 0x02, 0x0d, 0xb0, 0x3e, 0xab,
-    0x1e, 0x03, 0x1d, 0xda, 0x2f, 0xbe, 0x03, 0xd1,
-    0x79, 0x21, 0x70, 0xa0, 0xf3, 0x00, 0x9c, 0xee
-];
-
-#[rustfmt::skip]
-const CTXT_CBC: [u8; 4 * AES128_BLOCK_SIZE] = [
-    0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46,
-    0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19, 0x7d,
-    0x50, 0x86, 0xcb, 0x9b, 0x50, 0x72, 0x19, 0xee,
-    0x95, 0xdb, 0x11, 0x3a, 0x91, 0x76, 0x78, 0xb2,
-    0x73, 0xbe, 0xd6, 0xb8, 0xe3, 0xc1, 0x74, 0x3b,
-    0x71, 0x16, 0xe6, 0x9e, 0x22, 0x22, 0x95, 0x16,
-    0x3f, 0xf1, 0xca, 0xa1, 0x68, 0x1f, 0xac, 0x09,
-    0x12, 0x0e, 0xca, 0x30, 0x75, 0x86, 0xe1, 0xa7
-];
-
-#[rustfmt::skip]
-const CTXT_ECB: [u8; 4 * AES128_BLOCK_SIZE] = [
-    0x3a, 0xd7, 0x7b, 0xb4, 0x0d, 0x7a, 0x36, 0x60,
-    0xa8, 0x9e, 0xca, 0xf3, 0x24, 0x66, 0xef, 0x97,
-    0xf5, 0xd3, 0xd5, 0x85, 0x03, 0xb9, 0x69, 0x9d,
-    0xe7, 0x85, 0x89, 0x5a, 0x96, 0xfd, 0xba, 0xaf,
-    0x43, 0xb1, 0xcd, 0x7f, 0x59, 0x8e, 0xce, 0x23,
-    0x88, 0x1b, 0x00, 0xe3, 0xed, 0x03, 0x06, 0x88,
-    0x7b, 0x0c, 0x78, 0x5e, 0x27, 0xe8, 0xad, 0x3f,
-    0x82, 0x23, 0x20, 0x71, 0x04, 0x72, 0x5d, 0xd4
-];
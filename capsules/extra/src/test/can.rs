@@ -0,0 +1,471 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Loopback test for `hil::can` controllers.
+//!
+//! The controller runs in `OperationMode::Loopback`, so every frame it sends
+//! is received by itself and no bus or transceiver is needed. The cases are:
+//!
+//! 1. `Enable`: the controller reports `State::Running` once enabled.
+//! 2. `Standard`: a frame with an 11-bit identifier is received with the
+//!    identifier, length and data it was sent with.
+//! 3. `Extended`: the same for a 29-bit identifier.
+//! 4. `FilterReject`: with every filter disabled, a sent frame is not
+//!    received.
+//! 5. `FilterAccept`: after enabling a filter on the last receive FIFO, a
+//!    sent frame is received again.
+//! 6. `Disable`: receiving stops and the controller reports
+//!    `State::Disabled`.
+//! 7. `NoAck`: the controller is enabled again in `OperationMode::Normal`
+//!    and sends a frame that no other node acknowledges. The transfer must
+//!    fail, and both the last `State::Error` passed to `state_changed` and
+//!    `get_state` must be `Error::Ack`. This needs a transceiver on a bus
+//!    with no other node. Without a transceiver the controller cannot join
+//!    the bus, so enabling must fail with the controller reporting
+//!    `State::Disabled`, and no frame is sent.
+//!
+//! The controller must not report an error state before `NoAck`, as a
+//! loopback transfer cannot fail.
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::can::{self, Id, State, STANDARD_CAN_PACKET_SIZE};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+const BITRATE: u32 = 125_000;
+
+/// Time a frame may take to be sent and received.
+const FRAME_TIMEOUT_MS: u32 = 100;
+
+const STANDARD_ID: u16 = 0x555;
+const EXTENDED_ID: u32 = 0x1555_5555;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Enable,
+    Standard,
+    Extended,
+    FilterReject,
+    FilterAccept,
+    Stop,
+    Disable,
+    NoAck,
+}
+
+pub struct TestCanLoopback<A: Alarm<'static> + 'static, C: can::Can + can::Filter + 'static> {
+    can: &'static C,
+    alarm: &'static A,
+    tx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    rx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    /// Identifier and length of the frame in flight.
+    id: Cell<Id>,
+    len: Cell<usize>,
+    sent: Cell<bool>,
+    received: Cell<bool>,
+    /// Last error passed to `state_changed`.
+    reported_error: Cell<Option<can::Error>>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<A: Alarm<'static> + 'static, C: can::Can + can::Filter + 'static> TestCanLoopback<A, C> {
+    pub fn new(
+        can: &'static C,
+        alarm: &'static A,
+        tx_buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        rx_buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Self {
+        TestCanLoopback {
+            can,
+            alarm,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            id: Cell::new(Id::Standard(0)),
+            len: Cell::new(0),
+            sent: Cell::new(false),
+            received: Cell::new(false),
+            reported_error: Cell::new(None),
+            step: Cell::new(Step::Enable),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'static self) {
+        can::Controller::set_client(self.can, Some(self));
+        can::Transmit::set_client(self.can, Some(self));
+        can::Receive::set_client(self.can, Some(self));
+        self.alarm.set_alarm_client(self);
+
+        self.step.set(Step::Enable);
+        if let Err(e) = self
+            .can
+            .set_bitrate(BITRATE)
+            .and_then(|()| self.can.set_operation_mode(can::OperationMode::Loopback))
+            .and_then(|()| self.can.set_automatic_retransmission(false))
+        {
+            self.fail("configure failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        if let Err(e) = can::Controller::enable(self.can) {
+            self.fail("enable rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    /// Sends a frame whose data depends on the identifier and length.
+    fn send(&self, step: Step, id: Id, len: usize) {
+        self.step.set(step);
+        self.id.set(id);
+        self.len.set(len);
+        self.sent.set(false);
+        self.received.set(false);
+
+        let Some(buffer) = self.tx_buffer.take() else {
+            self.fail("transmit buffer missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = pattern(id, i);
+        }
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(FRAME_TIMEOUT_MS));
+        if let Err((e, buffer)) = self.can.send(id, buffer, len) {
+            self.tx_buffer.replace(buffer);
+            self.fail("send rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    /// Moves on once the frame was sent and, unless it must be filtered,
+    /// received.
+    fn frame_done(&self) {
+        if !self.sent.get() || (!self.received.get() && self.step.get() != Step::FilterReject) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+
+        match self.step.get() {
+            Step::Standard => self.send(Step::Extended, Id::Extended(EXTENDED_ID), 5),
+            Step::Extended => self.reject_all(),
+            Step::FilterReject => {
+                // The frame was sent; give it the time it would take to be
+                // received.
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(FRAME_TIMEOUT_MS));
+            }
+            Step::FilterAccept => {
+                self.step.set(Step::Stop);
+                if let Err(e) = self.can.stop_receive() {
+                    self.fail("stop_receive rejected", CapsuleTestError::ErrorCode(e));
+                }
+            }
+            _ => self.fail("unexpected frame", CapsuleTestError::IncorrectResult),
+        }
+    }
+
+    fn reject_all(&self) {
+        self.step.set(Step::FilterReject);
+        for number in 0..self.can.filter_count() {
+            if let Err(e) = self.can.disable_filter(number as u32) {
+                self.fail("disable_filter failed", CapsuleTestError::ErrorCode(e));
+                return;
+            }
+        }
+        self.send(Step::FilterReject, Id::Standard(STANDARD_ID), 8);
+    }
+
+    fn accept_last_fifo(&self) {
+        self.step.set(Step::FilterAccept);
+        let filter = can::FilterParameters {
+            number: 0,
+            scale_bits: can::ScaleBits::Bits32,
+            identifier_mode: can::IdentifierMode::Mask,
+            fifo_number: self.can.receive_fifo_count() - 1,
+        };
+        if let Err(e) = self.can.enable_filter(filter) {
+            self.fail("enable_filter failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        self.send(Step::FilterAccept, Id::Extended(EXTENDED_ID), 8);
+    }
+
+    /// Enables the controller on the bus, where no node acknowledges the
+    /// next frame.
+    fn join_bus(&self) {
+        self.step.set(Step::NoAck);
+        if let Err(e) = self.can.set_operation_mode(can::OperationMode::Normal) {
+            self.fail("configure failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        if let Err(e) = can::Controller::enable(self.can) {
+            self.fail("enable rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    /// Checks the error reported once the unacknowledged frame settled.
+    fn check_no_ack(&self) {
+        let reported = self.reported_error.get();
+        if reported != Some(can::Error::Ack) {
+            debug!("CanLoopback: reported {:?}, expected Ack", reported);
+            self.fail("wrong error reported", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        if !self.check_state(State::Error(can::Error::Ack)) {
+            return;
+        }
+        if let Err(e) = can::Controller::disable(self.can) {
+            self.fail("disable rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    fn check_state(&self, expected: State) -> bool {
+        match self.can.get_state() {
+            Ok(state) if state == expected => true,
+            Ok(state) => {
+                debug!("CanLoopback: state {:?}, expected {:?}", state, expected);
+                self.fail("wrong state", CapsuleTestError::IncorrectResult);
+                false
+            }
+            Err(e) => {
+                self.fail("get_state failed", CapsuleTestError::ErrorCode(e));
+                false
+            }
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("CanLoopback: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        if result.is_ok() {
+            debug!("CanLoopback: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+fn pattern(id: Id, index: usize) -> u8 {
+    let id = match id {
+        Id::Standard(id) => id as u32,
+        Id::Extended(id) => id,
+    };
+    (id as u8).wrapping_add(index as u8 * 17)
+}
+
+fn same_id(a: Id, b: Id) -> bool {
+    match (a, b) {
+        (Id::Standard(a), Id::Standard(b)) => a == b,
+        (Id::Extended(a), Id::Extended(b)) => a == b,
+        _ => false,
+    }
+}
+
+impl<A: Alarm<'static> + 'static, C: can::Can + can::Filter + 'static> can::ControllerClient
+    for TestCanLoopback<A, C>
+{
+    fn state_changed(&self, state: State) {
+        if let State::Error(error) = state {
+            if self.step.get() == Step::NoAck {
+                self.reported_error.set(Some(error));
+                return;
+            }
+            debug!("CanLoopback: controller reported {:?}", error);
+            self.fail("error state reported", CapsuleTestError::IncorrectResult);
+        }
+    }
+
+    fn enabled(&self, status: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        if self.step.get() == Step::NoAck {
+            match status {
+                Ok(()) => {
+                    if self.check_state(State::Running) {
+                        self.send(Step::NoAck, Id::Standard(STANDARD_ID), 8);
+                    }
+                }
+                Err(_) => {
+                    if self.check_state(State::Disabled) {
+                        debug!("CanLoopback: NoAck: no bus to join, frame not sent");
+                        self.finish(Ok(()));
+                    }
+                }
+            }
+            return;
+        }
+        if let Err(e) = status {
+            self.fail("enable failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        if !self.check_state(State::Running) {
+            return;
+        }
+        let Some(buffer) = self.rx_buffer.take() else {
+            self.fail("receive buffer missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        if let Err((e, buffer)) = self.can.start_receive_process(buffer) {
+            self.rx_buffer.replace(buffer);
+            self.fail(
+                "start_receive_process rejected",
+                CapsuleTestError::ErrorCode(e),
+            );
+            return;
+        }
+        self.send(Step::Standard, Id::Standard(STANDARD_ID), 8);
+    }
+
+    fn disabled(&self, status: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        match status {
+            Ok(()) if self.step.get() == Step::Disable => {
+                if self.check_state(State::Disabled) {
+                    self.join_bus();
+                }
+            }
+            Ok(()) if self.step.get() == Step::NoAck => {
+                if self.check_state(State::Disabled) {
+                    self.finish(Ok(()));
+                }
+            }
+            Ok(()) => self.fail("unexpected disable", CapsuleTestError::IncorrectResult),
+            Err(e) => self.fail("disable failed", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, C: can::Can + can::Filter + 'static>
+    can::TransmitClient<STANDARD_CAN_PACKET_SIZE> for TestCanLoopback<A, C>
+{
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) {
+        self.tx_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        if self.step.get() == Step::NoAck {
+            if status.is_ok() {
+                self.fail("frame acknowledged", CapsuleTestError::IncorrectResult);
+                return;
+            }
+            // Give the error interrupt the time to report why it failed.
+            self.sent.set(true);
+            let _ = self.alarm.disarm();
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(FRAME_TIMEOUT_MS));
+            return;
+        }
+        if let Err(error) = status {
+            debug!("CanLoopback: transmit error {:?}", error);
+            self.fail("transmit failed", CapsuleTestError::ErrorCode(error.into()));
+            return;
+        }
+        self.sent.set(true);
+        self.frame_done();
+    }
+}
+
+impl<A: Alarm<'static> + 'static, C: can::Can + can::Filter + 'static>
+    can::ReceiveClient<STANDARD_CAN_PACKET_SIZE> for TestCanLoopback<A, C>
+{
+    fn message_received(
+        &self,
+        id: Id,
+        buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+        status: Result<(), can::Error>,
+    ) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(error) = status {
+            debug!("CanLoopback: receive error {:?}", error);
+            self.fail("receive failed", CapsuleTestError::ErrorCode(error.into()));
+            return;
+        }
+        if self.step.get() == Step::FilterReject {
+            self.fail("filtered frame received", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        let expected = self.id.get();
+        if !same_id(id, expected) {
+            debug!("CanLoopback: received {:?}, sent {:?}", id, expected);
+            self.fail("wrong identifier", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        if len != self.len.get() {
+            self.fail("wrong length", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        if buffer[..len]
+            .iter()
+            .enumerate()
+            .any(|(i, byte)| *byte != pattern(expected, i))
+        {
+            self.fail("data corrupted", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        self.received.set(true);
+        self.frame_done();
+    }
+
+    fn stopped(&self, buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE]) {
+        self.rx_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        if self.step.get() != Step::Stop {
+            self.fail("receiving stopped", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        if !self.check_state(State::Running) {
+            return;
+        }
+        self.step.set(Step::Disable);
+        if let Err(e) = can::Controller::disable(self.can) {
+            self.fail("disable rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, C: can::Can + can::Filter + 'static> AlarmClient
+    for TestCanLoopback<A, C>
+{
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if self.step.get() == Step::FilterReject && self.sent.get() {
+            self.accept_last_fifo();
+        } else if self.step.get() == Step::NoAck && self.sent.get() {
+            self.check_no_ack();
+        } else {
+            self.fail("timed out", CapsuleTestError::IncorrectResult);
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, C: can::Can + can::Filter + 'static> CapsuleTest
+    for TestCanLoopback<A, C>
+{
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
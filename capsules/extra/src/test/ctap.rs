@@ -0,0 +1,996 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Test for the CTAP HID transport of `usb::ctap::CtapHid`.
+//!
+//! A [`FakeUsbHost`] stands in for the USB controller and the browser behind
+//! it. It replays a recorded CTAPHID exchange report by report on the OUT
+//! endpoint and collects the reports the capsule sends on the IN endpoint.
+//! The test is the `usb_hid` client of the capsule as well, in place of the
+//! authenticator application, which parses the requests and answers with the
+//! recorded responses. The cases are:
+//!
+//! 1. `Init`: a CTAPHID_INIT request on the broadcast channel is answered
+//!    with its nonce and the channel the host uses from then on.
+//! 2. `GetInfo`: an authenticatorGetInfo request, in one report, is answered
+//!    with the recorded authenticator information.
+//! 3. `MakeCredential`: an authenticatorMakeCredential request, split over
+//!    two reports, has a canonical CBOR map of parameters with a 32 byte
+//!    clientDataHash, the relying party ID `example.com`, a user ID and ES256
+//!    among the algorithms. It is answered with a recorded attestation object
+//!    split over three reports.
+//!
+//! Both sides reassemble the messages of the other and check the channel,
+//! the command, the byte count and the sequence numbers of every report. The
+//! host compares each response with the recording and checks that its CBOR
+//! is well-formed. Control transfers are not exercised: the host starts
+//! exchanging reports right after the capsule attaches.
+
+use core::cell::Cell;
+
+use crate::usb::ctap::CtapHid;
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::hil::usb::TransferType;
+use kernel::hil::usb_hid::UsbHid;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell, VolatileCell};
+use kernel::ErrorCode;
+
+/// Size of a CTAPHID report.
+pub const REPORT_LEN: usize = 64;
+
+/// Interrupt endpoint `CtapHid` uses in both directions.
+const HID_ENDPOINT: usize = 1;
+
+/// Longest message either side reassembles.
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// Time the whole exchange may take.
+const TIMEOUT_MS: u32 = 1000;
+
+// CTAPHID framing, FIDO CTAP 2.0 section 8.1.
+const INIT_HEADER_LEN: usize = 7;
+const CONT_HEADER_LEN: usize = 5;
+const TYPE_INIT: u8 = 0x80;
+const MAX_SEQ: usize = 0x7f;
+const CTAPHID_INIT: u8 = 0x06;
+const CTAPHID_CBOR: u8 = 0x10;
+const BROADCAST_CID: u32 = 0xffff_ffff;
+
+/// Channel the authenticator allocates for the host.
+const CID: u32 = 0x7e57_0001;
+
+/// CTAPHID protocol version 2, device version 1.0.0, and the CBOR and NMSG
+/// capabilities.
+const DEVICE_INFO: [u8; 5] = [0x02, 0x01, 0x00, 0x00, 0x0c];
+
+// CTAP2 commands and status, FIDO CTAP 2.0 section 6.
+const AUTHENTICATOR_MAKE_CREDENTIAL: u8 = 0x01;
+const AUTHENTICATOR_GET_INFO: u8 = 0x04;
+const CTAP2_OK: u8 = 0x00;
+
+const RP_ID: &[u8] = b"example.com";
+const ES256: i64 = -7;
+const CLIENT_DATA_HASH_LEN: usize = 32;
+
+const NONCE: [u8; 8] = [0xc0, 0x94, 0x1f, 0x13, 0x4e, 0x96, 0x2f, 0xba];
+
+const INIT_RESPONSE: [u8; 17] = [
+    0xc0, 0x94, 0x1f, 0x13, 0x4e, 0x96, 0x2f, 0xba, // nonce
+    0x7e, 0x57, 0x00, 0x01, // CID
+    0x02, 0x01, 0x00, 0x00, 0x0c, // versions, capabilities
+];
+
+#[rustfmt::skip]
+const GET_INFO_RESPONSE: &[u8] = &[
+    CTAP2_OK,
+    0xa4, // map(4)
+    0x01, 0x82, // versions: array(2)
+    0x66, b'U', b'2', b'F', b'_', b'V', b'2', // "U2F_V2"
+    0x68, b'F', b'I', b'D', b'O', b'_', b'2', b'_', b'0', // "FIDO_2_0"
+    0x03, 0x50, // aaguid: bytes(16)
+    0x71, 0x9a, 0x1b, 0xa0, 0xa6, 0x4b, 0xa1, 0x8a, 0x29, 0x97, 0xea, 0xf8, 0x5c, 0x94, 0x93, 0x54,
+    0x04, 0xa2, // options: map(2)
+    0x62, b'r', b'k', 0xf5, // "rk": true
+    0x62, b'u', b'p', 0xf5, // "up": true
+    0x05, 0x19, 0x04, 0xb0, // maxMsgSize: 1200
+];
+
+#[rustfmt::skip]
+const MAKE_CREDENTIAL_REQUEST: &[u8] = &[
+    AUTHENTICATOR_MAKE_CREDENTIAL,
+    0xa4, // map(4)
+    0x01, 0x58, 0x20, // clientDataHash: bytes(32)
+    0x23, 0x35, 0x02, 0xc6, 0xe2, 0x33, 0xc3, 0x87, 0x89, 0x8e, 0x3e, 0xcd, 0x06, 0xeb, 0x6c, 0x84,
+    0xad, 0xe6, 0xd7, 0x87, 0x3d, 0x9d, 0x87, 0xa3, 0xea, 0x87, 0x19, 0x20, 0xb5, 0xb8, 0x31, 0x6f,
+    0x02, 0xa1, // rp: map(1)
+    0x62, b'i', b'd', // "id"
+    0x6b, b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c', b'o', b'm', // "example.com"
+    0x03, 0xa2, // user: map(2)
+    0x62, b'i', b'd', // "id"
+    0x48, 0x4d, 0xa2, 0xd2, 0xd2, 0x78, 0xe3, 0x97, 0xe2, // bytes(8)
+    0x64, b'n', b'a', b'm', b'e', // "name"
+    0x64, b't', b'o', b'c', b'k', // "tock"
+    0x04, 0x81, // pubKeyCredParams: array(1)
+    0xa2, // map(2)
+    0x63, b'a', b'l', b'g', 0x26, // "alg": -7
+    0x64, b't', b'y', b'p', b'e', // "type"
+    0x6a, b'p', b'u', b'b', b'l', b'i', b'c', b'-', b'k', b'e', b'y', // "public-key"
+];
+
+#[rustfmt::skip]
+const MAKE_CREDENTIAL_RESPONSE: &[u8] = &[
+    CTAP2_OK,
+    0xa3, // map(3)
+    0x01, 0x64, b'n', b'o', b'n', b'e', // fmt: "none"
+    0x02, 0x58, 0x94, // authData: bytes(148)
+    // SHA-256 of "example.com"
+    0xa3, 0x79, 0xa6, 0xf6, 0xee, 0xaf, 0xb9, 0xa5, 0x5e, 0x37, 0x8c, 0x11, 0x80, 0x34, 0xe2, 0x75,
+    0x1e, 0x68, 0x2f, 0xab, 0x9f, 0x2d, 0x30, 0xab, 0x13, 0xd2, 0x12, 0x55, 0x86, 0xce, 0x19, 0x47,
+    0x41, // flags: UP, AT
+    0x00, 0x00, 0x00, 0x00, // signCount
+    0x71, 0x9a, 0x1b, 0xa0, 0xa6, 0x4b, 0xa1, 0x8a, 0x29, 0x97, 0xea, 0xf8, 0x5c, 0x94, 0x93, 0x54,
+    0x00, 0x10, // credentialIdLength
+    0x20, 0xe2, 0xab, 0xa5, 0x8a, 0x18, 0x84, 0xe3, 0x4b, 0xd2, 0x4b, 0xba, 0xfa, 0xf5, 0x90, 0x30,
+    0xa5, // COSE_Key: map(5)
+    0x01, 0x02, // kty: EC2
+    0x03, 0x26, // alg: ES256
+    0x20, 0x01, // crv: P-256
+    0x21, 0x58, 0x20, // x: bytes(32)
+    0x35, 0x89, 0x9b, 0xae, 0x28, 0xe6, 0xed, 0x27, 0x71, 0x79, 0x7e, 0xcc, 0x58, 0x6f, 0x5e, 0x34,
+    0x95, 0x86, 0x53, 0xed, 0xe8, 0xf9, 0x18, 0xa8, 0x0f, 0x8e, 0x63, 0x97, 0x8e, 0xb4, 0x50, 0x4b,
+    0x22, 0x58, 0x20, // y: bytes(32)
+    0x0a, 0xc3, 0xe5, 0x9f, 0x82, 0x38, 0x39, 0x3c, 0x88, 0x56, 0x30, 0x8a, 0x25, 0x5a, 0xf0, 0x99,
+    0x7f, 0x53, 0x47, 0x70, 0x03, 0x09, 0xdf, 0xa4, 0xdc, 0x4b, 0xde, 0x84, 0x9f, 0x5e, 0xc2, 0xc1,
+    0x03, 0xa0, // attStmt: map(0)
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Init,
+    GetInfo,
+    MakeCredential,
+}
+
+/// A request of the host and the response it expects.
+struct Exchange {
+    step: Step,
+    cid: u32,
+    cmd: u8,
+    request: &'static [u8],
+    response: &'static [u8],
+}
+
+const EXCHANGES: [Exchange; 3] = [
+    Exchange {
+        step: Step::Init,
+        cid: BROADCAST_CID,
+        cmd: CTAPHID_INIT,
+        request: &NONCE,
+        response: &INIT_RESPONSE,
+    },
+    Exchange {
+        step: Step::GetInfo,
+        cid: CID,
+        cmd: CTAPHID_CBOR,
+        request: &[AUTHENTICATOR_GET_INFO],
+        response: GET_INFO_RESPONSE,
+    },
+    Exchange {
+        step: Step::MakeCredential,
+        cid: CID,
+        cmd: CTAPHID_CBOR,
+        request: MAKE_CREDENTIAL_REQUEST,
+        response: MAKE_CREDENTIAL_RESPONSE,
+    },
+];
+
+/// Reads the CBOR items CTAP2 uses (RFC 8949), all of definite length.
+struct Cbor<'b> {
+    data: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Cbor<'b> {
+    /// Deepest nesting `skip()` follows.
+    const MAX_DEPTH: usize = 8;
+
+    fn new(data: &'b [u8]) -> Self {
+        Cbor { data, pos: 0 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'b [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    /// Reads the major type and argument of the next item.
+    fn head(&mut self) -> Option<(u8, u64)> {
+        let initial = self.take(1)?[0];
+        let info = initial & 0x1f;
+        let argument = match info {
+            0..=23 => u64::from(info),
+            24..=27 => self
+                .take(1 << (info - 24))?
+                .iter()
+                .fold(0, |argument, byte| argument << 8 | u64::from(*byte)),
+            // Reserved, or an indefinite length.
+            _ => return None,
+        };
+        Some((initial >> 5, argument))
+    }
+
+    fn expect(&mut self, major: u8) -> Option<u64> {
+        self.head()
+            .and_then(|(found, argument)| (found == major).then_some(argument))
+    }
+
+    fn uint(&mut self) -> Option<u64> {
+        self.expect(0)
+    }
+
+    fn int(&mut self) -> Option<i64> {
+        match self.head()? {
+            (0, argument) => i64::try_from(argument).ok(),
+            (1, argument) => i64::try_from(argument).ok().map(|argument| -1 - argument),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self) -> Option<&'b [u8]> {
+        let len = self.expect(2)?;
+        self.take(usize::try_from(len).ok()?)
+    }
+
+    fn text(&mut self) -> Option<&'b [u8]> {
+        let len = self.expect(3)?;
+        self.take(usize::try_from(len).ok()?)
+    }
+
+    fn array(&mut self) -> Option<u64> {
+        self.expect(4)
+    }
+
+    fn map(&mut self) -> Option<u64> {
+        self.expect(5)
+    }
+
+    /// Skips the next item with everything nested in it.
+    fn skip(&mut self) -> Option<()> {
+        self.skip_nested(0)
+    }
+
+    fn skip_nested(&mut self, depth: usize) -> Option<()> {
+        if depth == Self::MAX_DEPTH {
+            return None;
+        }
+        let (major, argument) = self.head()?;
+        match major {
+            // Integers and simple values carry no content.
+            0 | 1 | 7 => Some(()),
+            2 | 3 => self.take(usize::try_from(argument).ok()?).map(|_| ()),
+            4 => (0..argument).try_for_each(|_| self.skip_nested(depth + 1)),
+            5 => (0..argument.checked_mul(2)?).try_for_each(|_| self.skip_nested(depth + 1)),
+            // A tag and the item it applies to.
+            _ => self.skip_nested(depth + 1),
+        }
+    }
+
+    /// Calls `entry` with the key of every entry of a map with text keys.
+    /// `entry` reads the value.
+    fn for_each_entry(
+        &mut self,
+        mut entry: impl FnMut(&'b [u8], &mut Self) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        let entries = self.map().ok_or("not a map")?;
+        for _ in 0..entries {
+            let key = self.text().ok_or("map key not text")?;
+            entry(key, self)?;
+        }
+        Ok(())
+    }
+
+    fn skip_value(&mut self) -> Result<(), &'static str> {
+        self.skip().ok_or("malformed CBOR")
+    }
+}
+
+/// Checks the parameters of an authenticatorMakeCredential request.
+fn check_make_credential(parameters: &[u8]) -> Result<(), &'static str> {
+    let mut cbor = Cbor::new(parameters);
+    let entries = cbor.map().ok_or("parameters not a map")?;
+    let mut last_key = None;
+    let mut present = 0u8;
+    for _ in 0..entries {
+        let key = cbor.uint().ok_or("parameter key not an integer")?;
+        if last_key.is_some_and(|last| key <= last) {
+            return Err("parameters not in canonical order");
+        }
+        last_key = Some(key);
+        match key {
+            1 => {
+                let hash = cbor.bytes().ok_or("clientDataHash not bytes")?;
+                if hash.len() != CLIENT_DATA_HASH_LEN {
+                    return Err("clientDataHash not 32 bytes");
+                }
+            }
+            2 => {
+                let mut id = None;
+                cbor.for_each_entry(|key, cbor| match key {
+                    b"id" => {
+                        id = Some(cbor.text().ok_or("rp ID not text")?);
+                        Ok(())
+                    }
+                    _ => cbor.skip_value(),
+                })?;
+                if id != Some(RP_ID) {
+                    return Err("wrong rp ID");
+                }
+            }
+            3 => {
+                let mut id = None;
+                cbor.for_each_entry(|key, cbor| match key {
+                    b"id" => {
+                        id = Some(cbor.bytes().ok_or("user ID not bytes")?);
+                        Ok(())
+                    }
+                    _ => cbor.skip_value(),
+                })?;
+                if id.is_none_or(|id| id.is_empty() || id.len() > 64) {
+                    return Err("user ID missing or longer than 64 bytes");
+                }
+            }
+            4 => {
+                let algorithms = cbor.array().ok_or("pubKeyCredParams not an array")?;
+                let mut es256 = false;
+                for _ in 0..algorithms {
+                    let mut alg = None;
+                    let mut public_key = false;
+                    cbor.for_each_entry(|key, cbor| match key {
+                        b"alg" => {
+                            alg = Some(cbor.int().ok_or("alg not an integer")?);
+                            Ok(())
+                        }
+                        b"type" => {
+                            public_key = cbor.text().ok_or("type not text")? == b"public-key";
+                            Ok(())
+                        }
+                        _ => cbor.skip_value(),
+                    })?;
+                    es256 |= public_key && alg == Some(ES256);
+                }
+                if !es256 {
+                    return Err("ES256 not offered");
+                }
+            }
+            _ => cbor.skip_value()?,
+        }
+        if key <= 4 {
+            present |= 1 << key;
+        }
+    }
+    if present != 0b11110 {
+        return Err("required parameter missing");
+    }
+    if !cbor.is_done() {
+        return Err("trailing bytes after parameters");
+    }
+    Ok(())
+}
+
+/// Collects the reports of one CTAPHID message.
+struct Reassembly {
+    data: MapCell<[u8; MAX_MESSAGE_LEN]>,
+    /// Whether an initialization packet started a message.
+    active: Cell<bool>,
+    cid: Cell<u32>,
+    cmd: Cell<u8>,
+    len: Cell<usize>,
+    received: Cell<usize>,
+    next_seq: Cell<usize>,
+}
+
+impl Reassembly {
+    fn new() -> Self {
+        Reassembly {
+            data: MapCell::new([0; MAX_MESSAGE_LEN]),
+            active: Cell::new(false),
+            cid: Cell::new(0),
+            cmd: Cell::new(0),
+            len: Cell::new(0),
+            received: Cell::new(0),
+            next_seq: Cell::new(0),
+        }
+    }
+
+    /// Adds one report to the message. Returns whether the message is
+    /// complete.
+    fn add(&self, report: &[u8]) -> Result<bool, &'static str> {
+        if report.len() != REPORT_LEN {
+            return Err("short report");
+        }
+        let cid = u32::from_be_bytes([report[0], report[1], report[2], report[3]]);
+        let payload = if report[4] & TYPE_INIT != 0 {
+            if self.active.get() {
+                return Err("initialization packet inside a message");
+            }
+            let len = usize::from(u16::from_be_bytes([report[5], report[6]]));
+            if len > MAX_MESSAGE_LEN {
+                return Err("message too long");
+            }
+            self.active.set(true);
+            self.cid.set(cid);
+            self.cmd.set(report[4] & !TYPE_INIT);
+            self.len.set(len);
+            self.received.set(0);
+            self.next_seq.set(0);
+            &report[INIT_HEADER_LEN..]
+        } else {
+            if !self.active.get() {
+                return Err("continuation packet without a message");
+            }
+            if cid != self.cid.get() {
+                return Err("continuation packet on another channel");
+            }
+            if usize::from(report[4]) != self.next_seq.get() {
+                return Err("continuation packet out of sequence");
+            }
+            self.next_seq.set(self.next_seq.get() + 1);
+            &report[CONT_HEADER_LEN..]
+        };
+
+        let received = self.received.get();
+        let count = payload.len().min(self.len.get() - received);
+        self.data.map(|data| {
+            data[received..received + count].copy_from_slice(&payload[..count]);
+        });
+        self.received.set(received + count);
+        Ok(self.received.get() == self.len.get())
+    }
+
+    /// Calls `f` with the channel, command and data of the complete message,
+    /// and gets ready for the next one.
+    fn take<R>(&self, f: impl FnOnce(u32, u8, &[u8]) -> R) -> Option<R> {
+        self.active.set(false);
+        let len = self.len.get();
+        self.data
+            .map(|data| f(self.cid.get(), self.cmd.get(), &data[..len]))
+    }
+}
+
+/// Splits one CTAPHID message into reports.
+struct Fragmenter {
+    data: MapCell<[u8; MAX_MESSAGE_LEN]>,
+    cid: Cell<u32>,
+    cmd: Cell<u8>,
+    len: Cell<usize>,
+    /// Message bytes written to reports so far.
+    sent: Cell<usize>,
+    /// Index of the next report, 0 for the initialization packet.
+    packet: Cell<usize>,
+}
+
+impl Fragmenter {
+    fn new() -> Self {
+        Fragmenter {
+            data: MapCell::new([0; MAX_MESSAGE_LEN]),
+            cid: Cell::new(0),
+            cmd: Cell::new(0),
+            len: Cell::new(0),
+            sent: Cell::new(0),
+            packet: Cell::new(0),
+        }
+    }
+
+    /// Starts a message with the concatenation of `parts` as its data.
+    fn start(&self, cid: u32, cmd: u8, parts: &[&[u8]]) -> Result<(), &'static str> {
+        let len = parts.iter().map(|part| part.len()).sum();
+        if len > MAX_MESSAGE_LEN {
+            return Err("message too long");
+        }
+        self.data.map(|data| {
+            let mut offset = 0;
+            for part in parts {
+                data[offset..offset + part.len()].copy_from_slice(part);
+                offset += part.len();
+            }
+        });
+        self.cid.set(cid);
+        self.cmd.set(cmd);
+        self.len.set(len);
+        self.sent.set(0);
+        self.packet.set(0);
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.packet.get() > 0 && self.sent.get() == self.len.get()
+    }
+
+    /// Writes the next report of the message to `report`.
+    fn next_report(&self, report: &mut [u8; REPORT_LEN]) -> Result<(), &'static str> {
+        let packet = self.packet.get();
+        if packet > MAX_SEQ + 1 {
+            return Err("message needs too many packets");
+        }
+        report.fill(0);
+        report[0..4].copy_from_slice(&self.cid.get().to_be_bytes());
+        let header_len = if packet == 0 {
+            report[4] = self.cmd.get() | TYPE_INIT;
+            report[5..7].copy_from_slice(&(self.len.get() as u16).to_be_bytes());
+            INIT_HEADER_LEN
+        } else {
+            report[4] = (packet - 1) as u8;
+            CONT_HEADER_LEN
+        };
+        let sent = self.sent.get();
+        let count = (REPORT_LEN - header_len).min(self.len.get() - sent);
+        self.data.map(|data| {
+            report[header_len..header_len + count].copy_from_slice(&data[sent..sent + count]);
+        });
+        self.sent.set(sent + count);
+        self.packet.set(packet + 1);
+        Ok(())
+    }
+}
+
+/// Receives the reports of a [`FakeUsbHost`].
+pub trait FakeUsbHostClient {
+    /// Called when the device consumed the report passed to `send_report()`.
+    fn report_sent(&self, result: Result<(), ErrorCode>);
+
+    /// Called with every report the device sends.
+    fn report_received(&self, report: &[u8]);
+}
+
+/// A USB controller with a host attached that exchanges reports on one
+/// interrupt endpoint.
+///
+/// The host sends a report when the test passes it to `send_report()`, and
+/// takes every report the device offers on the IN endpoint. Transfers run in
+/// deferred calls, one per direction and call, as a controller would handle
+/// them in its interrupts. Other endpoints and control transfers are ignored.
+pub struct FakeUsbHost<'a> {
+    client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
+    host_client: OptionalCell<&'a dyn FakeUsbHostClient>,
+    in_buffer: OptionalCell<&'a [VolatileCell<u8>]>,
+    out_buffer: OptionalCell<&'a [VolatileCell<u8>]>,
+    attached: Cell<bool>,
+    /// Report the host sends until the device consumes it.
+    pending_out: OptionalCell<[u8; REPORT_LEN]>,
+    /// Whether the device has data for the IN endpoint.
+    in_resumed: Cell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> FakeUsbHost<'a> {
+    pub fn new() -> Self {
+        FakeUsbHost {
+            client: OptionalCell::empty(),
+            host_client: OptionalCell::empty(),
+            in_buffer: OptionalCell::empty(),
+            out_buffer: OptionalCell::empty(),
+            attached: Cell::new(false),
+            pending_out: OptionalCell::empty(),
+            in_resumed: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn set_host_client(&self, client: &'a dyn FakeUsbHostClient) {
+        self.host_client.set(client);
+    }
+
+    /// Sends `report` to the OUT endpoint. Returns `OFF` while the device is
+    /// not attached and `BUSY` while the previous report is not consumed.
+    pub fn send_report(&self, report: &[u8; REPORT_LEN]) -> Result<(), ErrorCode> {
+        if !self.attached.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.pending_out.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.pending_out.set(*report);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn transfer_in(&self, client: &'a dyn hil::usb::Client<'a>) {
+        let hil::usb::InResult::Packet(len) =
+            client.packet_in(TransferType::Interrupt, HID_ENDPOINT)
+        else {
+            // The device resumes the endpoint once it has data.
+            return;
+        };
+        let mut report = [0; REPORT_LEN];
+        let len = len.min(REPORT_LEN);
+        self.in_buffer.map(|buffer| {
+            for (byte, cell) in report.iter_mut().zip(buffer.iter()).take(len) {
+                *byte = cell.get();
+            }
+        });
+        client.packet_transmitted(HID_ENDPOINT);
+        self.host_client
+            .map(|host_client| host_client.report_received(&report[..len]));
+    }
+
+    fn transfer_out(&self, client: &'a dyn hil::usb::Client<'a>, report: [u8; REPORT_LEN]) {
+        self.out_buffer.map(|buffer| {
+            for (cell, byte) in buffer.iter().zip(report.iter()) {
+                cell.set(*byte);
+            }
+        });
+        let result =
+            match client.packet_out(TransferType::Interrupt, HID_ENDPOINT, REPORT_LEN as u32) {
+                hil::usb::OutResult::Ok => Ok(()),
+                // Keep the report until the device resumes the endpoint.
+                hil::usb::OutResult::Delay => return,
+                hil::usb::OutResult::Error => Err(ErrorCode::FAIL),
+            };
+        self.pending_out.clear();
+        self.host_client
+            .map(|host_client| host_client.report_sent(result));
+    }
+}
+
+impl<'a> hil::usb::UsbController<'a> for FakeUsbHost<'a> {
+    fn set_client(&self, client: &'a dyn hil::usb::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn endpoint_set_ctrl_buffer(&self, _buf: &'a [VolatileCell<u8>]) {}
+
+    fn endpoint_set_in_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        if endpoint == HID_ENDPOINT {
+            self.in_buffer.set(buf);
+        }
+    }
+
+    fn endpoint_set_out_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        if endpoint == HID_ENDPOINT {
+            self.out_buffer.set(buf);
+        }
+    }
+
+    fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+
+    fn attach(&self) {
+        self.attached.set(true);
+    }
+
+    fn detach(&self) {
+        self.attached.set(false);
+        self.pending_out.clear();
+        self.in_resumed.set(false);
+    }
+
+    fn set_address(&self, _addr: u16) {}
+
+    fn enable_address(&self) {}
+
+    fn endpoint_in_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+
+    fn endpoint_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+
+    fn endpoint_in_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+
+    fn endpoint_resume_in(&self, endpoint: usize) {
+        if endpoint == HID_ENDPOINT && self.attached.get() {
+            self.in_resumed.set(true);
+            self.deferred_call.set();
+        }
+    }
+
+    fn endpoint_resume_out(&self, endpoint: usize) {
+        if endpoint == HID_ENDPOINT && self.pending_out.is_some() {
+            self.deferred_call.set();
+        }
+    }
+}
+
+impl DeferredCallClient for FakeUsbHost<'_> {
+    fn handle_deferred_call(&self) {
+        self.client.map(|client| {
+            if self.in_resumed.replace(false) {
+                self.transfer_in(client);
+            }
+            if let Some(report) = self.pending_out.get() {
+                self.transfer_out(client, report);
+            }
+        });
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+pub struct TestCtapHid<'a, A: Alarm<'a>> {
+    hid: &'a CtapHid<'a, FakeUsbHost<'a>>,
+    host: &'a FakeUsbHost<'a>,
+    alarm: &'a A,
+    /// Index into `EXCHANGES` of the current exchange.
+    exchange: Cell<usize>,
+    host_request: Fragmenter,
+    host_response: Reassembly,
+    device_request: Reassembly,
+    device_response: Fragmenter,
+    send_buffer: TakeCell<'static, [u8; REPORT_LEN]>,
+    recv_buffer: TakeCell<'static, [u8; REPORT_LEN]>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, A: Alarm<'a>> TestCtapHid<'a, A> {
+    pub fn new(
+        hid: &'a CtapHid<'a, FakeUsbHost<'a>>,
+        host: &'a FakeUsbHost<'a>,
+        alarm: &'a A,
+        send_buffer: &'static mut [u8; REPORT_LEN],
+        recv_buffer: &'static mut [u8; REPORT_LEN],
+    ) -> Self {
+        TestCtapHid {
+            hid,
+            host,
+            alarm,
+            exchange: Cell::new(0),
+            host_request: Fragmenter::new(),
+            host_response: Reassembly::new(),
+            device_request: Reassembly::new(),
+            device_response: Fragmenter::new(),
+            send_buffer: TakeCell::new(send_buffer),
+            recv_buffer: TakeCell::new(recv_buffer),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.host.set_host_client(self);
+        self.hid.set_client(self);
+        self.alarm.set_alarm_client(self);
+        hil::usb::Client::enable(self.hid);
+        hil::usb::Client::attach(self.hid);
+
+        let Some(buffer) = self.recv_buffer.take() else {
+            self.fail("receive buffer missing");
+            return;
+        };
+        if let Err((e, buffer)) = self.hid.receive_buffer(buffer) {
+            self.recv_buffer.replace(buffer);
+            debug!("CtapHid: receive_buffer failed: {:?}", e);
+            self.fail("receive refused");
+            return;
+        }
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TIMEOUT_MS));
+        self.start_exchange();
+    }
+
+    fn current(&self) -> &'static Exchange {
+        &EXCHANGES[self.exchange.get()]
+    }
+
+    fn start_exchange(&self) {
+        let exchange = self.current();
+        let started = self
+            .host_request
+            .start(exchange.cid, exchange.cmd, &[exchange.request]);
+        match started {
+            Ok(()) => self.host_send(),
+            Err(reason) => self.fail(reason),
+        }
+    }
+
+    /// Sends the next report of the request of the host.
+    fn host_send(&self) {
+        let mut report = [0; REPORT_LEN];
+        if let Err(reason) = self.host_request.next_report(&mut report) {
+            self.fail(reason);
+            return;
+        }
+        if let Err(e) = self.host.send_report(&report) {
+            debug!("CtapHid: send_report failed: {:?}", e);
+            self.fail("host could not send");
+        }
+    }
+
+    /// Sends the next report of the response of the authenticator.
+    fn device_send(&self) {
+        let Some(buffer) = self.send_buffer.take() else {
+            self.fail("send buffer missing");
+            return;
+        };
+        if let Err(reason) = self.device_response.next_report(buffer) {
+            self.send_buffer.replace(buffer);
+            self.fail(reason);
+            return;
+        }
+        if let Err((e, buffer)) = self.hid.send_buffer(buffer) {
+            self.send_buffer.replace(buffer);
+            debug!("CtapHid: send_buffer failed: {:?}", e);
+            self.fail("send refused");
+        }
+    }
+
+    /// Answers a complete request as the authenticator would.
+    fn respond(&self, cid: u32, cmd: u8, data: &[u8]) -> Result<(), &'static str> {
+        match cmd {
+            CTAPHID_INIT => {
+                if cid != BROADCAST_CID || data.len() != NONCE.len() {
+                    return Err("malformed CTAPHID_INIT");
+                }
+                self.device_response
+                    .start(cid, cmd, &[data, &CID.to_be_bytes(), &DEVICE_INFO])
+            }
+            CTAPHID_CBOR => {
+                if cid != CID {
+                    return Err("request on an unallocated channel");
+                }
+                let response = match data.split_first() {
+                    Some((&AUTHENTICATOR_GET_INFO, [])) => GET_INFO_RESPONSE,
+                    Some((&AUTHENTICATOR_GET_INFO, _)) => {
+                        return Err("authenticatorGetInfo with parameters")
+                    }
+                    Some((&AUTHENTICATOR_MAKE_CREDENTIAL, parameters)) => {
+                        check_make_credential(parameters)?;
+                        MAKE_CREDENTIAL_RESPONSE
+                    }
+                    _ => return Err("unexpected CTAP2 command"),
+                };
+                self.device_response.start(cid, cmd, &[response])
+            }
+            _ => Err("unexpected CTAPHID command"),
+        }
+    }
+
+    /// Checks a complete response against the recording.
+    fn check_response(&self, cid: u32, cmd: u8, data: &[u8]) -> Result<(), &'static str> {
+        let exchange = self.current();
+        if cid != exchange.cid || cmd != exchange.cmd {
+            return Err("response on another channel or command");
+        }
+        if data != exchange.response {
+            return Err("response differs from the recording");
+        }
+        if cmd == CTAPHID_CBOR {
+            let Some((&CTAP2_OK, body)) = data.split_first() else {
+                return Err("error status");
+            };
+            let mut cbor = Cbor::new(body);
+            if cbor.skip().is_none() || !cbor.is_done() {
+                return Err("malformed CBOR response");
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&self, reason: &str) {
+        debug!("CtapHid: {:?} failed: {}", self.current().step, reason);
+        self.finish(Err(CapsuleTestError::IncorrectResult));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        hil::usb::UsbController::detach(self.host);
+        if result.is_ok() {
+            debug!("CtapHid: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, A: Alarm<'a>> FakeUsbHostClient for TestCtapHid<'a, A> {
+    fn report_sent(&self, result: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        if result.is_err() {
+            self.fail("report stalled");
+        } else if !self.host_request.is_done() {
+            self.host_send();
+        }
+    }
+
+    fn report_received(&self, report: &[u8]) {
+        if self.finished.get() {
+            return;
+        }
+        match self.host_response.add(report) {
+            Ok(false) => (),
+            Ok(true) => {
+                let checked = self
+                    .host_response
+                    .take(|cid, cmd, data| self.check_response(cid, cmd, data))
+                    .unwrap_or(Err("response missing"));
+                if let Err(reason) = checked {
+                    self.fail(reason);
+                } else if self.exchange.get() + 1 == EXCHANGES.len() {
+                    self.finish(Ok(()));
+                } else {
+                    self.exchange.set(self.exchange.get() + 1);
+                    self.start_exchange();
+                }
+            }
+            Err(reason) => self.fail(reason),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> hil::usb_hid::Client<'a, [u8; REPORT_LEN]> for TestCtapHid<'a, A> {
+    fn packet_received(
+        &'a self,
+        result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; REPORT_LEN],
+        _endpoint: usize,
+    ) {
+        let added = result
+            .map_err(|_| "receive failed")
+            .and_then(|()| self.device_request.add(&buffer[..]));
+        // Take the next report of the host.
+        if let Err((_, buffer)) = self.hid.receive_buffer(buffer) {
+            self.recv_buffer.replace(buffer);
+        }
+        if self.finished.get() {
+            return;
+        }
+        match added {
+            Ok(false) => (),
+            Ok(true) => {
+                let responded = self
+                    .device_request
+                    .take(|cid, cmd, data| self.respond(cid, cmd, data))
+                    .unwrap_or(Err("request missing"));
+                match responded {
+                    Ok(()) => self.device_send(),
+                    Err(reason) => self.fail(reason),
+                }
+            }
+            Err(reason) => self.fail(reason),
+        }
+    }
+
+    fn packet_transmitted(
+        &'a self,
+        result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; REPORT_LEN],
+        _endpoint: usize,
+    ) {
+        self.send_buffer.replace(buffer);
+        if self.finished.get() {
+            return;
+        }
+        if result.is_err() {
+            self.fail("transmission failed");
+        } else if !self.device_response.is_done() {
+            self.device_send();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for TestCtapHid<'a, A> {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        self.fail("timed out");
+    }
+}
+
+impl<'a, A: Alarm<'a>> CapsuleTest for TestCtapHid<'a, A> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,275 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Smoke test for `hil::ethernet::EthernetAdapterDatapath` implementations.
+//!
+//! The MAC must be configured by the board so that every transmitted frame
+//! is received again, for example in its internal loopback mode. The cases
+//! are:
+//!
+//! 1. `ReceiveDisabled`: a frame sent before `enable_receive()` is not
+//!    delivered.
+//! 2. `Frame`: a full-size frame is received unchanged. Its length must
+//!    match exactly, so a MAC that passes the FCS trailer on to the client
+//!    fails this case.
+//! 3. `ShortFrame`: a frame shorter than the Ethernet minimum is received
+//!    with its contents intact. The MAC may pad it to the minimum length.
+//!
+//! Each transmission must complete with the buffer, length and transmission
+//! identifier it was started with.
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::ethernet::{EthernetAdapterDatapath, EthernetAdapterDatapathClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Time a frame may take to be sent and received.
+const FRAME_TIMEOUT_MS: u32 = 100;
+
+/// Minimum frame length excluding the FCS; shorter frames are padded.
+const MIN_FRAME_LEN: usize = 60;
+
+/// Largest standard frame excluding the FCS.
+const MAX_FRAME_LEN: usize = 1514;
+
+/// Length of the short frame: an Ethernet header and a 28-byte payload.
+const SHORT_FRAME_LEN: usize = 42;
+
+/// Locally administered unicast address used as source and destination.
+const TEST_ADDRESS: [u8; 6] = [0x02, 0x00, 0x00, 0x54, 0x4f, 0x43];
+
+/// Ethertype reserved for local experiments.
+const TEST_ETHERTYPE: [u8; 2] = [0x88, 0xb5];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    ReceiveDisabled,
+    Frame,
+    ShortFrame,
+}
+
+impl Step {
+    /// Transmission identifier used for the step's frame.
+    fn identifier(self) -> usize {
+        0x7e57_0000 + self as usize
+    }
+}
+
+pub struct TestEthernetLoopback<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> {
+    mac: &'a E,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    sent: Cell<bool>,
+    received: Cell<bool>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> TestEthernetLoopback<'a, E, A> {
+    /// `buffer` must hold a frame of `MAX_FRAME_LEN` bytes.
+    pub fn new(mac: &'a E, alarm: &'a A, buffer: &'static mut [u8]) -> Self {
+        TestEthernetLoopback {
+            mac,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            len: Cell::new(0),
+            sent: Cell::new(false),
+            received: Cell::new(false),
+            step: Cell::new(Step::ReceiveDisabled),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.mac.set_client(self);
+        self.alarm.set_alarm_client(self);
+
+        if self.buffer.map_or(0, |buffer| buffer.len()) < MAX_FRAME_LEN {
+            self.fail("buffer too small", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        self.mac.disable_receive();
+        self.send(Step::ReceiveDisabled, MAX_FRAME_LEN);
+    }
+
+    fn send(&self, step: Step, len: usize) {
+        self.step.set(step);
+        self.len.set(len);
+        self.sent.set(false);
+        self.received.set(false);
+
+        let Some(buffer) = self.buffer.take() else {
+            self.fail("buffer missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        buffer[0..6].copy_from_slice(&TEST_ADDRESS);
+        buffer[6..12].copy_from_slice(&TEST_ADDRESS);
+        buffer[12..14].copy_from_slice(&TEST_ETHERTYPE);
+        for (i, byte) in buffer[14..len].iter_mut().enumerate() {
+            *byte = payload_byte(step, i);
+        }
+
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(FRAME_TIMEOUT_MS));
+        if let Err((e, buffer)) = self
+            .mac
+            .transmit_frame(buffer, len as u16, step.identifier())
+        {
+            self.buffer.replace(buffer);
+            self.fail("transmit_frame rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    /// Checks a received frame against the one in flight.
+    fn check_frame(&self, frame: &[u8]) -> Result<(), &'static str> {
+        let step = self.step.get();
+        let len = self.len.get();
+        let expected_len = len.max(MIN_FRAME_LEN);
+        if frame.len() == len + 4 {
+            return Err("FCS passed to client");
+        }
+        if frame.len() < len || frame.len() > expected_len {
+            debug!(
+                "EthernetLoopback: received {} bytes, sent {}",
+                frame.len(),
+                len
+            );
+            return Err("wrong frame length");
+        }
+        if frame[0..6] != TEST_ADDRESS
+            || frame[6..12] != TEST_ADDRESS
+            || frame[12..14] != TEST_ETHERTYPE
+        {
+            return Err("header corrupted");
+        }
+        if frame[14..len]
+            .iter()
+            .enumerate()
+            .any(|(i, byte)| *byte != payload_byte(step, i))
+        {
+            return Err("payload corrupted");
+        }
+        Ok(())
+    }
+
+    /// Moves on once the frame was sent and, if receiving, received.
+    fn frame_done(&self) {
+        if !self.sent.get() || (!self.received.get() && self.step.get() != Step::ReceiveDisabled) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        match self.step.get() {
+            Step::ReceiveDisabled => {
+                // Give the frame the time it would take to come back.
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(FRAME_TIMEOUT_MS));
+            }
+            Step::Frame => self.send(Step::ShortFrame, SHORT_FRAME_LEN),
+            Step::ShortFrame => self.finish(Ok(())),
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("EthernetLoopback: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        self.mac.disable_receive();
+        if result.is_ok() {
+            debug!("EthernetLoopback: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+fn payload_byte(step: Step, index: usize) -> u8 {
+    (index as u8).wrapping_mul(7).wrapping_add(step as u8)
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> EthernetAdapterDatapathClient
+    for TestEthernetLoopback<'a, E, A>
+{
+    fn transmit_frame_done(
+        &self,
+        err: Result<(), ErrorCode>,
+        frame_buffer: &'static mut [u8],
+        len: u16,
+        transmission_identifier: usize,
+        _timestamp: Option<u64>,
+    ) {
+        self.buffer.replace(frame_buffer);
+        if self.finished.get() {
+            return;
+        }
+        if let Err(e) = err {
+            self.fail("transmission failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+        if len as usize != self.len.get() || transmission_identifier != self.step.get().identifier()
+        {
+            self.fail(
+                "transmission reported for another frame",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        self.sent.set(true);
+        self.frame_done();
+    }
+
+    fn received_frame(&self, frame: &[u8], _timestamp: Option<u64>) {
+        if self.finished.get() {
+            return;
+        }
+        if self.step.get() == Step::ReceiveDisabled {
+            self.fail(
+                "frame received while disabled",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        if let Err(reason) = self.check_frame(frame) {
+            self.fail(reason, CapsuleTestError::IncorrectResult);
+            return;
+        }
+        self.received.set(true);
+        self.frame_done();
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> AlarmClient
+    for TestEthernetLoopback<'a, E, A>
+{
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        if self.step.get() == Step::ReceiveDisabled && self.sent.get() {
+            self.mac.enable_receive();
+            self.send(Step::Frame, MAX_FRAME_LEN);
+        } else {
+            self.fail("timed out", CapsuleTestError::IncorrectResult);
+        }
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> CapsuleTest
+    for TestEthernetLoopback<'a, E, A>
+{
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -5,9 +5,16 @@
 pub mod aes;
 pub mod aes_ccm;
 pub mod aes_gcm;
+pub mod can;
 pub mod crc;
+pub mod ctap;
+pub mod ethernet;
 pub mod hmac_sha256;
 pub mod kv_system;
+pub mod screen;
+pub mod sensors;
 pub mod sha256;
 pub mod siphash24;
+pub mod sx127x;
+pub mod touch;
 pub mod udp;
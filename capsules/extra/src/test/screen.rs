@@ -0,0 +1,491 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Test for `hil::screen` drivers that send their framebuffer over a
+//! [`Bus`](crate::bus::Bus).
+//!
+//! Screens generally cannot be read back, so the test places a
+//! [`CaptureBus`] between the screen driver and its bus. The capture bus
+//! checksums every byte written to the screen's memory write address and
+//! either forwards the transfer to the real bus or, with no bus attached,
+//! completes it itself. The latter lets the test run on boards without a
+//! display.
+//!
+//! [`TestScreen`] writes a known pattern through the screen driver and
+//! compares the number of bytes and the checksum the bus saw with the
+//! pattern. The cases are:
+//!
+//! 1. `FullFrame`: a frame covering the whole screen, written in
+//!    buffer-sized chunks with `continue_write`.
+//! 2. `PartialFrame`: a frame covering the middle of the screen.
+//! 3. `Rotate`: after rotating by 90 degrees the resolution is swapped.
+//! 4. `RotatedFrame`: a frame in the rotated coordinates.
+//! 5. `Restore`: rotating back restores the original resolution.
+
+use core::cell::Cell;
+
+use crate::bus::{self, Bus, BusAddr, DataWidth};
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::screen::{Screen, ScreenClient, ScreenRotation, ScreenSetup, ScreenSetupClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Fletcher-32 checksum over a byte stream.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct FrameChecksum {
+    sum1: u32,
+    sum2: u32,
+}
+
+impl FrameChecksum {
+    pub fn add(&mut self, byte: u8) {
+        self.sum1 = (self.sum1 + byte as u32) % 0xFFFF;
+        self.sum2 = (self.sum2 + self.sum1) % 0xFFFF;
+    }
+
+    pub fn value(&self) -> u32 {
+        (self.sum2 << 16) | self.sum1
+    }
+}
+
+/// Byte at `offset` of the pattern the test writes into each frame.
+pub fn pattern_byte(offset: usize) -> u8 {
+    // A prime period, so the pattern does not line up with rows or pixels.
+    (offset % 251) as u8
+}
+
+/// Framebuffer data captured from a screen's bus.
+pub trait FrameCapture {
+    /// Discards everything captured so far.
+    fn reset(&self);
+
+    /// Number of framebuffer bytes captured since the last reset, and their
+    /// checksum.
+    fn captured(&self) -> (usize, FrameChecksum);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pending {
+    None,
+    SetAddress,
+    Write(usize),
+}
+
+/// A bus shim that checksums the framebuffer data a screen driver writes.
+///
+/// With an `inner` bus, all operations are forwarded and the inner bus must
+/// have the capture bus set as its client. Without one, writes and address
+/// changes complete from a deferred call and reads are not supported.
+pub struct CaptureBus<'a, A: BusAddr> {
+    inner: Option<&'a dyn Bus<'a, A>>,
+    /// Address the screen writes its framebuffer to, as a big endian number.
+    data_address: u64,
+    /// Whether the screen driver swaps each pair of bytes before sending
+    /// them, which is undone before checksumming.
+    swapped_pairs: bool,
+    in_data: Cell<bool>,
+    count: Cell<usize>,
+    checksum: Cell<FrameChecksum>,
+    buffer: TakeCell<'static, [u8]>,
+    pending: Cell<Pending>,
+    deferred_call: DeferredCall,
+    client: OptionalCell<&'a dyn bus::Client>,
+}
+
+impl<'a, A: BusAddr> CaptureBus<'a, A> {
+    pub fn new(inner: Option<&'a dyn Bus<'a, A>>, data_address: u64, swapped_pairs: bool) -> Self {
+        CaptureBus {
+            inner,
+            data_address,
+            swapped_pairs,
+            in_data: Cell::new(false),
+            count: Cell::new(0),
+            checksum: Cell::new(FrameChecksum::default()),
+            buffer: TakeCell::empty(),
+            pending: Cell::new(Pending::None),
+            deferred_call: DeferredCall::new(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn capture(&self, data: &[u8]) {
+        let mut checksum = self.checksum.get();
+        if self.swapped_pairs {
+            for pair in data.chunks(2) {
+                pair.iter().rev().for_each(|byte| checksum.add(*byte));
+            }
+        } else {
+            data.iter().for_each(|byte| checksum.add(*byte));
+        }
+        self.checksum.set(checksum);
+        self.count.set(self.count.get() + data.len());
+    }
+
+    fn complete_later(&self, pending: Pending) -> Result<(), ErrorCode> {
+        if self.pending.get() != Pending::None {
+            return Err(ErrorCode::BUSY);
+        }
+        self.pending.set(pending);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a, A: BusAddr> Bus<'a, A> for CaptureBus<'a, A> {
+    fn set_addr(&self, addr: A) -> Result<(), ErrorCode> {
+        let address = addr
+            .bytes()
+            .fold(0u64, |address, byte| (address << 8) | byte as u64);
+        self.in_data.set(address == self.data_address);
+        match self.inner {
+            Some(bus) => bus.set_addr(addr),
+            None => self.complete_later(Pending::SetAddress),
+        }
+    }
+
+    fn write(
+        &self,
+        data_width: DataWidth,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let bytes = len * data_width.width_in_bytes();
+        if bytes > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        if self.in_data.get() {
+            self.capture(&buffer[..bytes]);
+        }
+        match self.inner {
+            Some(bus) => bus.write(data_width, buffer, len),
+            None => match self.complete_later(Pending::Write(len)) {
+                Ok(()) => {
+                    self.buffer.replace(buffer);
+                    Ok(())
+                }
+                Err(e) => Err((e, buffer)),
+            },
+        }
+    }
+
+    fn read(
+        &self,
+        data_width: DataWidth,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        match self.inner {
+            Some(bus) => bus.read(data_width, buffer, len),
+            None => Err((ErrorCode::NOSUPPORT, buffer)),
+        }
+    }
+
+    fn set_client(&self, client: &'a dyn bus::Client) {
+        self.client.set(client);
+    }
+}
+
+impl<A: BusAddr> bus::Client for CaptureBus<'_, A> {
+    fn command_complete(
+        &self,
+        buffer: Option<&'static mut [u8]>,
+        len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.client
+            .map(move |client| client.command_complete(buffer, len, status));
+    }
+}
+
+impl<A: BusAddr> DeferredCallClient for CaptureBus<'_, A> {
+    fn handle_deferred_call(&self) {
+        let len = match self.pending.replace(Pending::None) {
+            Pending::None => return,
+            Pending::SetAddress => 0,
+            Pending::Write(len) => len,
+        };
+        let buffer = self.buffer.take();
+        self.client
+            .map(move |client| client.command_complete(buffer, len, Ok(())));
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+impl<A: BusAddr> FrameCapture for CaptureBus<'_, A> {
+    fn reset(&self) {
+        self.count.set(0);
+        self.checksum.set(FrameChecksum::default());
+    }
+
+    fn captured(&self) -> (usize, FrameChecksum) {
+        (self.count.get(), self.checksum.get())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    WaitReady,
+    FullFrame,
+    PartialFrame,
+    Rotate,
+    RotatedFrame,
+    Restore,
+}
+
+pub struct TestScreen<'a, S: Screen<'a> + ScreenSetup<'a>> {
+    screen: &'a S,
+    capture: &'a dyn FrameCapture,
+    buffer: TakeCell<'static, [u8]>,
+    /// Resolution before rotating.
+    resolution: Cell<(usize, usize)>,
+    frame_bytes: Cell<usize>,
+    written: Cell<usize>,
+    chunk: Cell<usize>,
+    expected: Cell<FrameChecksum>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, S: Screen<'a> + ScreenSetup<'a>> TestScreen<'a, S> {
+    /// `buffer` is the write chunk size and must hold a whole number of
+    /// pixels.
+    pub fn new(screen: &'a S, capture: &'a dyn FrameCapture, buffer: &'static mut [u8]) -> Self {
+        TestScreen {
+            screen,
+            capture,
+            buffer: TakeCell::new(buffer),
+            resolution: Cell::new((0, 0)),
+            frame_bytes: Cell::new(0),
+            written: Cell::new(0),
+            chunk: Cell::new(0),
+            expected: Cell::new(FrameChecksum::default()),
+            step: Cell::new(Step::WaitReady),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the screen clients and waits for `screen_is_ready()`, so the
+    /// board must initialize the screen after calling this.
+    pub fn run(&'a self) {
+        Screen::set_client(self.screen, self);
+        ScreenSetup::set_client(self.screen, self);
+        self.step.set(Step::WaitReady);
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.screen
+            .get_pixel_format()
+            .get_bits_per_pixel()
+            .div_ceil(8)
+    }
+
+    fn start_frame(&self, step: Step, x: usize, y: usize, width: usize, height: usize) {
+        self.step.set(step);
+        self.frame_bytes
+            .set(width * height * self.bytes_per_pixel());
+        self.written.set(0);
+        self.expected.set(FrameChecksum::default());
+        self.capture.reset();
+        if let Err(e) = self.screen.set_write_frame(x, y, width, height) {
+            self.fail("set_write_frame rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    fn write_chunk(&self) {
+        let Some(buffer) = self.buffer.take() else {
+            self.fail("buffer missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        let written = self.written.get();
+        let len = buffer.len().min(self.frame_bytes.get() - written);
+        let mut expected = self.expected.get();
+        for (i, byte) in buffer[..len].iter_mut().enumerate() {
+            *byte = pattern_byte(written + i);
+            expected.add(*byte);
+        }
+        self.expected.set(expected);
+        self.chunk.set(len);
+
+        let mut data = SubSliceMut::new(buffer);
+        data.slice(..len);
+        if let Err(e) = self.screen.write(data, written != 0) {
+            self.fail("write rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    fn check_frame(&self) {
+        let (count, checksum) = self.capture.captured();
+        if count != self.frame_bytes.get() {
+            debug!(
+                "ScreenTest: captured {} of {} bytes",
+                count,
+                self.frame_bytes.get()
+            );
+            self.fail(
+                "wrong frame size on the bus",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        if checksum != self.expected.get() {
+            debug!(
+                "ScreenTest: checksum {:#010x}, expected {:#010x}",
+                checksum.value(),
+                self.expected.get().value()
+            );
+            self.fail("frame checksum mismatch", CapsuleTestError::IncorrectResult);
+            return;
+        }
+
+        match self.step.get() {
+            Step::FullFrame => {
+                let (width, height) = self.resolution.get();
+                self.start_frame(
+                    Step::PartialFrame,
+                    width / 4,
+                    height / 4,
+                    width / 2,
+                    height / 2,
+                );
+            }
+            Step::PartialFrame => self.rotate(Step::Rotate, ScreenRotation::Rotated90),
+            Step::RotatedFrame => self.rotate(Step::Restore, ScreenRotation::Normal),
+            _ => self.fail("unexpected frame", CapsuleTestError::IncorrectResult),
+        }
+    }
+
+    fn rotate(&self, step: Step, rotation: ScreenRotation) {
+        self.step.set(step);
+        if let Err(e) = self.screen.set_rotation(rotation) {
+            self.fail("set_rotation rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("ScreenTest: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        if result.is_ok() {
+            debug!("ScreenTest: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, S: Screen<'a> + ScreenSetup<'a>> ScreenClient for TestScreen<'a, S> {
+    fn command_complete(&self, result: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        match (self.step.get(), result) {
+            (Step::FullFrame | Step::PartialFrame | Step::RotatedFrame, Ok(())) => {
+                self.write_chunk()
+            }
+            (_, Ok(())) => self.fail(
+                "unexpected command_complete",
+                CapsuleTestError::IncorrectResult,
+            ),
+            (_, Err(e)) => self.fail("set_write_frame failed", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+
+    fn write_complete(&self, buffer: SubSliceMut<'static, u8>, result: Result<(), ErrorCode>) {
+        self.buffer.replace(buffer.take());
+        if self.finished.get() {
+            return;
+        }
+        if let Err(e) = result {
+            self.fail("write failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+
+        self.written.set(self.written.get() + self.chunk.get());
+        if self.written.get() < self.frame_bytes.get() {
+            self.write_chunk();
+        } else {
+            self.check_frame();
+        }
+    }
+
+    fn screen_is_ready(&self) {
+        if self.finished.get() || self.step.get() != Step::WaitReady {
+            return;
+        }
+        let (width, height) = self.screen.get_resolution();
+        self.resolution.set((width, height));
+        if self.buffer.map_or(0, |buffer| buffer.len()) % self.bytes_per_pixel() != 0 {
+            self.fail(
+                "buffer holds partial pixels",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        }
+        self.start_frame(Step::FullFrame, 0, 0, width, height);
+    }
+}
+
+impl<'a, S: Screen<'a> + ScreenSetup<'a>> ScreenSetupClient for TestScreen<'a, S> {
+    fn command_complete(&self, result: Result<(), ErrorCode>) {
+        if self.finished.get() {
+            return;
+        }
+        if let Err(e) = result {
+            self.fail("set_rotation failed", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+
+        let (width, height) = self.resolution.get();
+        match self.step.get() {
+            Step::Rotate => {
+                if self.screen.get_rotation() != ScreenRotation::Rotated90
+                    || self.screen.get_resolution() != (height, width)
+                {
+                    self.fail(
+                        "resolution not swapped after rotation",
+                        CapsuleTestError::IncorrectResult,
+                    );
+                    return;
+                }
+                // The left half in rotated coordinates, which does not
+                // match any frame written before.
+                self.start_frame(Step::RotatedFrame, 0, 0, height / 2, width);
+            }
+            Step::Restore => {
+                if self.screen.get_rotation() != ScreenRotation::Normal
+                    || self.screen.get_resolution() != (width, height)
+                {
+                    self.fail(
+                        "resolution not restored after rotation",
+                        CapsuleTestError::IncorrectResult,
+                    );
+                    return;
+                }
+                self.finish(Ok(()));
+            }
+            _ => self.fail(
+                "unexpected setup command_complete",
+                CapsuleTestError::IncorrectResult,
+            ),
+        }
+    }
+}
+
+impl<'a, S: Screen<'a> + ScreenSetup<'a>> CapsuleTest for TestScreen<'a, S> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
@@ -0,0 +1,284 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Plausibility test for attached environment sensors.
+//!
+//! The board creates the drivers for the sensors it has attached, starts
+//! their initialization, and registers every quantity they measure with
+//! [`TestSensorPlausibility::add`]. The test then reads each quantity once
+//! through its `hil::sensors` interface and checks the value is within a range
+//! any indoor test setup produces. Drivers that are still initializing may
+//! return `BUSY`, in which case the read is retried for a while.
+//!
+//! With no sensors registered the test passes immediately, so boards can run
+//! it unconditionally and let their configuration decide what is checked.
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::sensors::{
+    HumidityClient, HumidityDriver, PressureClient, PressureDriver, TemperatureClient,
+    TemperatureDriver,
+};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Maximum number of quantities that can be registered.
+pub const MAX_SENSOR_CHECKS: usize = 8;
+
+/// Delay before retrying a read the driver rejected with `BUSY`.
+const RETRY_MS: u32 = 100;
+
+/// Number of `BUSY` retries before a sensor is considered broken.
+const MAX_RETRIES: usize = 20;
+
+/// Time a started read may take to complete.
+const READ_TIMEOUT_MS: u32 = 1000;
+
+/// Plausible temperatures, in centidegrees Celsius.
+const TEMPERATURE_RANGE: (i64, i64) = (0, 5000);
+
+/// Plausible relative humidity, in hundredths of a percent.
+const HUMIDITY_RANGE: (i64, i64) = (500, 9500);
+
+/// Plausible atmospheric pressure, in hPa.
+const PRESSURE_RANGE: (i64, i64) = (800, 1100);
+
+/// A quantity measured by an attached sensor.
+#[derive(Clone, Copy)]
+pub enum Quantity<'a> {
+    Temperature(&'a dyn TemperatureDriver<'a>),
+    Humidity(&'a dyn HumidityDriver<'a>),
+    Pressure(&'a dyn PressureDriver<'a>),
+}
+
+impl Quantity<'_> {
+    fn range(&self) -> (i64, i64) {
+        match self {
+            Quantity::Temperature(_) => TEMPERATURE_RANGE,
+            Quantity::Humidity(_) => HUMIDITY_RANGE,
+            Quantity::Pressure(_) => PRESSURE_RANGE,
+        }
+    }
+
+    fn unit_name(&self) -> &'static str {
+        match self {
+            Quantity::Temperature(_) => "temperature (cC)",
+            Quantity::Humidity(_) => "humidity (0.01%)",
+            Quantity::Pressure(_) => "pressure (hPa)",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SensorCheck<'a> {
+    /// Name of the sensor, for the test output.
+    name: &'static str,
+    quantity: Quantity<'a>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Retrying,
+    Reading,
+}
+
+pub struct TestSensorPlausibility<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    checks: [OptionalCell<SensorCheck<'a>>; MAX_SENSOR_CHECKS],
+    count: Cell<usize>,
+    index: Cell<usize>,
+    retries: Cell<usize>,
+    state: Cell<State>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, A: Alarm<'a>> TestSensorPlausibility<'a, A> {
+    pub fn new(alarm: &'a A) -> Self {
+        TestSensorPlausibility {
+            alarm,
+            checks: [const { OptionalCell::empty() }; MAX_SENSOR_CHECKS],
+            count: Cell::new(0),
+            index: Cell::new(0),
+            retries: Cell::new(0),
+            state: Cell::new(State::Idle),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Registers a quantity `name` measures. Returns `NOMEM` once
+    /// `MAX_SENSOR_CHECKS` quantities are registered.
+    pub fn add(&self, name: &'static str, quantity: Quantity<'a>) -> Result<(), ErrorCode> {
+        let count = self.count.get();
+        let slot = self.checks.get(count).ok_or(ErrorCode::NOMEM)?;
+        slot.set(SensorCheck { name, quantity });
+        self.count.set(count + 1);
+        Ok(())
+    }
+
+    pub fn run(&'a self) {
+        self.alarm.set_alarm_client(self);
+        for check in self.checks.iter().filter_map(|check| check.get()) {
+            match check.quantity {
+                Quantity::Temperature(driver) => driver.set_client(self),
+                Quantity::Humidity(driver) => driver.set_client(self),
+                Quantity::Pressure(driver) => driver.set_client(self),
+            }
+        }
+        if self.count.get() == 0 {
+            debug!("SensorPlausibility: no sensors attached, skipping");
+            self.finish(Ok(()));
+            return;
+        }
+        self.start(0);
+    }
+
+    fn current(&self) -> Option<SensorCheck<'a>> {
+        self.checks
+            .get(self.index.get())
+            .and_then(|check| check.get())
+    }
+
+    fn start(&self, index: usize) {
+        self.index.set(index);
+        self.retries.set(0);
+        match self.current() {
+            Some(_) => self.read(),
+            None => self.finish(Ok(())),
+        }
+    }
+
+    fn read(&self) {
+        let Some(check) = self.current() else {
+            return;
+        };
+        // Some drivers call back before returning, so be ready for the
+        // reading first.
+        self.state.set(State::Reading);
+        self.wait(READ_TIMEOUT_MS);
+        let result = match check.quantity {
+            Quantity::Temperature(driver) => driver.read_temperature(),
+            Quantity::Humidity(driver) => driver.read_humidity(),
+            Quantity::Pressure(driver) => driver.read_atmospheric_pressure(),
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(ErrorCode::BUSY) if self.retries.get() < MAX_RETRIES => {
+                self.retries.set(self.retries.get() + 1);
+                self.state.set(State::Retrying);
+                self.wait(RETRY_MS);
+            }
+            Err(e) => self.fail(check, "read rejected", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Checks the value read for the current quantity and moves on.
+    fn check(&self, value: Result<i64, ErrorCode>) {
+        if self.finished.get() || self.state.get() != State::Reading {
+            return;
+        }
+        self.state.set(State::Idle);
+        let _ = self.alarm.disarm();
+        let Some(check) = self.current() else {
+            return;
+        };
+
+        match value {
+            Ok(value) => {
+                let (min, max) = check.quantity.range();
+                debug!(
+                    "SensorPlausibility: {} {} = {}",
+                    check.name,
+                    check.quantity.unit_name(),
+                    value
+                );
+                if value < min || value > max {
+                    self.fail(
+                        check,
+                        "value implausible",
+                        CapsuleTestError::IncorrectResult,
+                    );
+                } else {
+                    self.start(self.index.get() + 1);
+                }
+            }
+            Err(e) => self.fail(check, "read failed", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+
+    fn fail(&self, check: SensorCheck, reason: &str, error: CapsuleTestError) {
+        debug!(
+            "SensorPlausibility: {} {} failed: {}",
+            check.name,
+            check.quantity.unit_name(),
+            reason
+        );
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        if result.is_ok() {
+            debug!("SensorPlausibility: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for TestSensorPlausibility<'a, A> {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        match self.state.get() {
+            State::Retrying => {
+                self.state.set(State::Idle);
+                self.read();
+            }
+            State::Reading => {
+                if let Some(check) = self.current() {
+                    self.fail(check, "no reading", CapsuleTestError::IncorrectResult);
+                }
+            }
+            State::Idle => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> TemperatureClient for TestSensorPlausibility<'a, A> {
+    fn callback(&self, value: Result<i32, ErrorCode>) {
+        self.check(value.map(i64::from));
+    }
+}
+
+impl<'a, A: Alarm<'a>> HumidityClient for TestSensorPlausibility<'a, A> {
+    fn callback(&self, value: usize) {
+        self.check(Ok(value as i64));
+    }
+}
+
+impl<'a, A: Alarm<'a>> PressureClient for TestSensorPlausibility<'a, A> {
+    fn callback(&self, value: Result<u32, ErrorCode>) {
+        self.check(value.map(i64::from));
+    }
+}
+
+impl<'a, A: Alarm<'a>> CapsuleTest for TestSensorPlausibility<'a, A> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
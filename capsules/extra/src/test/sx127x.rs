@@ -0,0 +1,397 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Hardware test for a Semtech SX1276/77/78/79 LoRa transceiver on a SPI
+//! bus.
+//!
+//! The module's antenna port must be terminated with a dummy load: the test
+//! transmits one LoRa packet at the lowest output power of the RFO pin. The
+//! cases are:
+//!
+//! 1. `Version`: after an optional reset the version register reads `0x12`.
+//! 2. `LoRaMode`: the radio enters LoRa sleep mode, which can only be
+//!    selected from sleep.
+//! 3. `ChannelReadback`: the carrier frequency registers read back the
+//!    channel written to them.
+//! 4. `PollIrq`: after loading the FIFO and entering transmit mode, the
+//!    `TxDone` flag and no other interrupt flag is raised within
+//!    `TX_TIMEOUT_MS`.
+//! 5. `ReturnedToStandby`: the radio returns to standby after the packet.
+//! 6. `IrqCleared`: writing the `TxDone` bit clears it.
+//!
+//! The radio is left in LoRa sleep mode when the test passes.
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::hil::gpio;
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Bytes in the transmitted packet.
+pub const PAYLOAD_LEN: usize = 16;
+
+/// Length required of the SPI write and read buffers.
+pub const BUFFER_LEN: usize = PAYLOAD_LEN + 1;
+
+/// SPI clock rate; the SX127x supports up to 10 MHz.
+const SPI_RATE: u32 = 1_000_000;
+
+/// Time the reset line is held low and then given to the radio to start.
+const RESET_PULSE_MS: u32 = 1;
+const RESET_SETTLE_MS: u32 = 10;
+
+/// Interval between reads of the interrupt flags while transmitting.
+const POLL_INTERVAL_MS: u32 = 10;
+
+/// Time allowed for the packet, which takes about 50 ms at the default
+/// modem settings (SF7, 125 kHz).
+const TX_TIMEOUT_MS: u32 = 1000;
+
+/// Crystal frequency, which sets the frequency synthesizer step.
+const FXOSC_HZ: u64 = 32_000_000;
+
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_VERSION: u8 = 0x42;
+
+/// Set in the address byte of register writes.
+const WRITE_ACCESS: u8 = 0x80;
+
+const VERSION: u8 = 0x12;
+
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+
+/// RFO pin at its minimum output power of -4.2 dBm.
+const PA_CONFIG_MIN_POWER: u8 = 0x00;
+
+/// FIFO address the packet is loaded at, also the reset value of
+/// `RegFifoTxBaseAddr`.
+const TX_BASE_ADDR: u8 = 0x80;
+
+const IRQ_TX_DONE: u8 = 0x08;
+const IRQ_ALL: u8 = 0xFF;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Reset,
+    ResetRelease,
+    Version,
+    Sleep,
+    LoRaSleep,
+    LoRaMode,
+    Channel,
+    ChannelReadback,
+    PowerConfig,
+    Standby,
+    ClearIrq,
+    FifoPointers,
+    LoadFifo,
+    PayloadLength,
+    Transmit,
+    PollIrq,
+    ReturnedToStandby,
+    AckIrq,
+    IrqCleared,
+    Shutdown,
+}
+
+pub struct TestSx127x<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> {
+    spi: &'a S,
+    reset: Option<&'a dyn gpio::Pin>,
+    alarm: &'a A,
+    frf: [u8; 3],
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    step: Cell<Step>,
+    polls: Cell<u32>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> TestSx127x<'a, S, A> {
+    /// `reset` is the pin connected to the radio's NRESET line, if any. The
+    /// test transmits on `frequency_hz`, which must be a frequency the module
+    /// is built for. Both buffers must hold `BUFFER_LEN` bytes.
+    pub fn new(
+        spi: &'a S,
+        reset: Option<&'a dyn gpio::Pin>,
+        alarm: &'a A,
+        frequency_hz: u32,
+        write_buffer: &'static mut [u8],
+        read_buffer: &'static mut [u8],
+    ) -> Self {
+        let frf = ((frequency_hz as u64) << 19) / FXOSC_HZ;
+        TestSx127x {
+            spi,
+            reset,
+            alarm,
+            frf: [(frf >> 16) as u8, (frf >> 8) as u8, frf as u8],
+            write_buffer: TakeCell::new(write_buffer),
+            read_buffer: TakeCell::new(read_buffer),
+            step: Cell::new(Step::Reset),
+            polls: Cell::new(0),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.spi.set_client(self);
+        self.alarm.set_alarm_client(self);
+
+        if self.write_buffer.map_or(0, |buffer| buffer.len()) < BUFFER_LEN
+            || self.read_buffer.map_or(0, |buffer| buffer.len()) < BUFFER_LEN
+        {
+            self.fail("buffers too small", CapsuleTestError::IncorrectResult);
+            return;
+        }
+        if let Err(e) =
+            self.spi
+                .configure(ClockPolarity::IdleLow, ClockPhase::SampleLeading, SPI_RATE)
+        {
+            self.fail("configuring SPI", CapsuleTestError::ErrorCode(e));
+            return;
+        }
+
+        match self.reset {
+            Some(reset) => {
+                reset.make_output();
+                reset.clear();
+                self.step.set(Step::Reset);
+                self.wait(RESET_PULSE_MS);
+            }
+            None => self.start(Step::Version),
+        }
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Issues the register access of `step`.
+    fn start(&self, step: Step) {
+        self.step.set(step);
+        match step {
+            Step::Version => self.read_registers(REG_VERSION, 1),
+            Step::Sleep => self.write_registers(REG_OP_MODE, &[MODE_SLEEP]),
+            Step::LoRaSleep => self.write_registers(REG_OP_MODE, &[MODE_LONG_RANGE | MODE_SLEEP]),
+            Step::LoRaMode => self.read_registers(REG_OP_MODE, 1),
+            Step::Channel => self.write_registers(REG_FRF_MSB, &self.frf),
+            Step::ChannelReadback => self.read_registers(REG_FRF_MSB, self.frf.len()),
+            Step::PowerConfig => self.write_registers(REG_PA_CONFIG, &[PA_CONFIG_MIN_POWER]),
+            Step::Standby => self.write_registers(REG_OP_MODE, &[MODE_LONG_RANGE | MODE_STANDBY]),
+            Step::ClearIrq => self.write_registers(REG_IRQ_FLAGS, &[IRQ_ALL]),
+            // RegFifoAddrPtr is followed by RegFifoTxBaseAddr.
+            Step::FifoPointers => {
+                self.write_registers(REG_FIFO_ADDR_PTR, &[TX_BASE_ADDR, TX_BASE_ADDR])
+            }
+            Step::LoadFifo => {
+                let mut payload = [0; PAYLOAD_LEN];
+                for (i, byte) in payload.iter_mut().enumerate() {
+                    *byte = (i as u8).wrapping_mul(0x1D) ^ 0x5A;
+                }
+                self.write_registers(REG_FIFO, &payload);
+            }
+            Step::PayloadLength => self.write_registers(REG_PAYLOAD_LENGTH, &[PAYLOAD_LEN as u8]),
+            Step::Transmit => self.write_registers(REG_OP_MODE, &[MODE_LONG_RANGE | MODE_TX]),
+            Step::PollIrq => self.read_registers(REG_IRQ_FLAGS, 1),
+            Step::ReturnedToStandby => self.read_registers(REG_OP_MODE, 1),
+            Step::AckIrq => self.write_registers(REG_IRQ_FLAGS, &[IRQ_TX_DONE]),
+            Step::IrqCleared => self.read_registers(REG_IRQ_FLAGS, 1),
+            Step::Shutdown => self.write_registers(REG_OP_MODE, &[MODE_LONG_RANGE | MODE_SLEEP]),
+            Step::Reset | Step::ResetRelease => self.fail(
+                "step has no register access",
+                CapsuleTestError::IncorrectResult,
+            ),
+        }
+    }
+
+    fn write_registers(&self, register: u8, data: &[u8]) {
+        self.transfer(register | WRITE_ACCESS, |buffer| {
+            buffer[..data.len()].copy_from_slice(data);
+            data.len()
+        });
+    }
+
+    fn read_registers(&self, register: u8, len: usize) {
+        self.transfer(register, |buffer| {
+            buffer[..len].fill(0);
+            len
+        });
+    }
+
+    /// Sends the address byte followed by the bytes `fill` places in the
+    /// rest of the write buffer, returning how many it placed.
+    fn transfer(&self, address: u8, fill: impl FnOnce(&mut [u8]) -> usize) {
+        let (Some(write), Some(read)) = (self.write_buffer.take(), self.read_buffer.take()) else {
+            self.fail("buffers missing", CapsuleTestError::IncorrectResult);
+            return;
+        };
+        write[0] = address;
+        let len = fill(&mut write[1..]) + 1;
+
+        let mut write = SubSliceMut::new(write);
+        write.slice(..len);
+        let mut read = SubSliceMut::new(read);
+        read.slice(..len);
+        if let Err((e, write, read)) = self.spi.read_write_bytes(write, Some(read)) {
+            self.write_buffer.replace(write.take());
+            read.map(|read| self.read_buffer.replace(read.take()));
+            self.fail("read_write_bytes rejected", CapsuleTestError::ErrorCode(e));
+        }
+    }
+
+    /// Checks the registers read by the current step and starts the next.
+    fn check(&self, data: &[u8]) {
+        match self.step.get() {
+            Step::Version if data[0] != VERSION => {
+                debug!("Sx127x: version register reads {:#04x}", data[0]);
+                self.fail("unexpected version", CapsuleTestError::IncorrectResult);
+            }
+            Step::Version => self.start(Step::Sleep),
+            Step::Sleep => self.start(Step::LoRaSleep),
+            Step::LoRaSleep => self.start(Step::LoRaMode),
+            Step::LoRaMode if data[0] != MODE_LONG_RANGE | MODE_SLEEP => self.fail(
+                "LoRa sleep mode not entered",
+                CapsuleTestError::IncorrectResult,
+            ),
+            Step::LoRaMode => self.start(Step::Channel),
+            Step::Channel => self.start(Step::ChannelReadback),
+            Step::ChannelReadback if data[..self.frf.len()] != self.frf => {
+                self.fail("channel not retained", CapsuleTestError::IncorrectResult)
+            }
+            Step::ChannelReadback => self.start(Step::PowerConfig),
+            Step::PowerConfig => self.start(Step::Standby),
+            Step::Standby => self.start(Step::ClearIrq),
+            Step::ClearIrq => self.start(Step::FifoPointers),
+            Step::FifoPointers => self.start(Step::LoadFifo),
+            Step::LoadFifo => self.start(Step::PayloadLength),
+            Step::PayloadLength => self.start(Step::Transmit),
+            Step::Transmit => {
+                self.polls.set(0);
+                self.start(Step::PollIrq);
+            }
+            Step::PollIrq if data[0] == 0 => {
+                if self.polls.get() * POLL_INTERVAL_MS >= TX_TIMEOUT_MS {
+                    self.fail("TxDone not raised", CapsuleTestError::IncorrectResult);
+                } else {
+                    self.polls.set(self.polls.get() + 1);
+                    self.wait(POLL_INTERVAL_MS);
+                }
+            }
+            Step::PollIrq if data[0] != IRQ_TX_DONE => {
+                debug!("Sx127x: interrupt flags {:#04x}", data[0]);
+                self.fail(
+                    "unexpected interrupt flags",
+                    CapsuleTestError::IncorrectResult,
+                );
+            }
+            Step::PollIrq => self.start(Step::ReturnedToStandby),
+            Step::ReturnedToStandby if data[0] != MODE_LONG_RANGE | MODE_STANDBY => self.fail(
+                "not in standby after TxDone",
+                CapsuleTestError::IncorrectResult,
+            ),
+            Step::ReturnedToStandby => self.start(Step::AckIrq),
+            Step::AckIrq => self.start(Step::IrqCleared),
+            Step::IrqCleared if data[0] != 0 => {
+                self.fail("TxDone not cleared", CapsuleTestError::IncorrectResult)
+            }
+            Step::IrqCleared => self.start(Step::Shutdown),
+            Step::Shutdown => self.finish(Ok(())),
+            Step::Reset | Step::ResetRelease => self.fail(
+                "unexpected read_write_done",
+                CapsuleTestError::IncorrectResult,
+            ),
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("Sx127x: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        if result.is_ok() {
+            debug!("Sx127x: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> SpiMasterClient for TestSx127x<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+        status: Result<usize, ErrorCode>,
+    ) {
+        let length = write_buffer.len();
+        self.write_buffer.replace(write_buffer.take());
+        let Some(mut read_buffer) = read_buffer else {
+            self.fail(
+                "read buffer not returned",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        };
+        // The first byte is clocked in while the address is sent.
+        let mut data = [0; PAYLOAD_LEN];
+        let received = &read_buffer.as_slice()[1..];
+        let copied = received.len().min(PAYLOAD_LEN);
+        data[..copied].copy_from_slice(&received[..copied]);
+        self.read_buffer.replace(read_buffer.take());
+        if self.finished.get() {
+            return;
+        }
+
+        match status {
+            Ok(transferred) if transferred == length => self.check(&data),
+            Ok(_) => self.fail("short transfer", CapsuleTestError::IncorrectResult),
+            Err(e) => self.fail("transfer failed", CapsuleTestError::ErrorCode(e)),
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> AlarmClient for TestSx127x<'a, S, A> {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        match self.step.get() {
+            Step::Reset => {
+                // NRESET is released by letting the line float.
+                self.reset.map(|reset| reset.make_input());
+                self.step.set(Step::ResetRelease);
+                self.wait(RESET_SETTLE_MS);
+            }
+            Step::ResetRelease => self.start(Step::Version),
+            Step::PollIrq => self.start(Step::PollIrq),
+            _ => self.fail("unexpected alarm", CapsuleTestError::IncorrectResult),
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> CapsuleTest for TestSx127x<'a, S, A> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
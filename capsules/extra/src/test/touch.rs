@@ -0,0 +1,458 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Test for `hil::touch::MultiTouch` event reporting.
+//!
+//! Every reported event is checked against the touches currently held down:
+//! a touch must be pressed before it moves or is released, an ID may only be
+//! pressed once until it is released, an ID appears at most once per report,
+//! coordinates are within the panel, and `get_touch()` returns the reported
+//! events from within the callback.
+//!
+//! In attended mode the test runs on a real panel and asks the operator to
+//!
+//! 1. `Drag`: press one finger, move it, and release it, then
+//! 2. `TwoFinger`: press two fingers at once and release them.
+//!
+//! In unattended mode the events come from a [`FakeTouch`] source. The test
+//! checks that injecting while the source is disabled fails (`Disabled`),
+//! then queues a script of overlapping touches at once and checks that every
+//! report arrives in order and unchanged (`Script`).
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::hil::touch::{MultiTouch, MultiTouchClient, TouchEvent, TouchStatus};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Maximum number of simultaneous touches tracked by the test and reported
+/// by `FakeTouch`.
+pub const MAX_TOUCHES: usize = 4;
+
+/// Number of reports `FakeTouch` can queue.
+pub const FAKE_QUEUE_LEN: usize = 8;
+
+/// Time the operator has for each attended step.
+const ATTENDED_TIMEOUT_MS: u32 = 30_000;
+
+/// Time the scripted reports may take to arrive.
+const UNATTENDED_TIMEOUT_MS: u32 = 1000;
+
+const fn touch(status: TouchStatus, id: usize, x: u16, y: u16) -> TouchEvent {
+    TouchEvent {
+        status,
+        x,
+        y,
+        id,
+        size: None,
+        pressure: None,
+    }
+}
+
+/// Reports injected in unattended mode. Touch 0 is released and pressed
+/// again, so IDs are reused after a release.
+const SCRIPT: [&[TouchEvent]; 7] = [
+    &[touch(TouchStatus::Pressed, 0, 10, 20)],
+    &[touch(TouchStatus::Moved, 0, 15, 25)],
+    &[
+        touch(TouchStatus::Moved, 0, 20, 30),
+        touch(TouchStatus::Pressed, 1, 100, 120),
+    ],
+    &[
+        touch(TouchStatus::Released, 0, 20, 30),
+        touch(TouchStatus::Moved, 1, 110, 130),
+    ],
+    &[touch(TouchStatus::Released, 1, 110, 130)],
+    &[touch(TouchStatus::Pressed, 0, 50, 60)],
+    &[touch(TouchStatus::Released, 0, 50, 60)],
+];
+
+/// Panel size the script fits in.
+pub const SCRIPT_RESOLUTION: (u16, u16) = (128, 160);
+
+fn same_event(a: &TouchEvent, b: &TouchEvent) -> bool {
+    let same_status = matches!(
+        (a.status, b.status),
+        (TouchStatus::Unstarted, TouchStatus::Unstarted)
+            | (TouchStatus::Pressed, TouchStatus::Pressed)
+            | (TouchStatus::Released, TouchStatus::Released)
+            | (TouchStatus::Moved, TouchStatus::Moved)
+    );
+    same_status
+        && a.id == b.id
+        && a.x == b.x
+        && a.y == b.y
+        && a.size == b.size
+        && a.pressure == b.pressure
+}
+
+#[derive(Clone, Copy)]
+struct Report {
+    events: [TouchEvent; MAX_TOUCHES],
+    len: usize,
+}
+
+const EMPTY_REPORT: Report = Report {
+    events: [touch(TouchStatus::Unstarted, 0, 0, 0); MAX_TOUCHES],
+    len: 0,
+};
+
+/// A multi-touch source that reports injected events.
+///
+/// Injected reports are queued and delivered in order, one per deferred
+/// call, as a panel would report consecutive samples.
+pub struct FakeTouch<'a> {
+    queue: [Cell<Report>; FAKE_QUEUE_LEN],
+    head: Cell<usize>,
+    queued: Cell<usize>,
+    /// Report being delivered, returned by `get_touch()`.
+    current: Cell<Report>,
+    enabled: Cell<bool>,
+    deferred_call: DeferredCall,
+    client: OptionalCell<&'a dyn MultiTouchClient>,
+}
+
+impl FakeTouch<'_> {
+    pub fn new() -> Self {
+        FakeTouch {
+            queue: [const { Cell::new(EMPTY_REPORT) }; FAKE_QUEUE_LEN],
+            head: Cell::new(0),
+            queued: Cell::new(0),
+            current: Cell::new(EMPTY_REPORT),
+            enabled: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Queues one report. Returns `OFF` while disabled, `SIZE` for more than
+    /// `MAX_TOUCHES` events and `NOMEM` when the queue is full.
+    pub fn inject(&self, events: &[TouchEvent]) -> Result<(), ErrorCode> {
+        if !self.enabled.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if events.len() > MAX_TOUCHES {
+            return Err(ErrorCode::SIZE);
+        }
+        let queued = self.queued.get();
+        if queued == FAKE_QUEUE_LEN {
+            return Err(ErrorCode::NOMEM);
+        }
+
+        let mut report = EMPTY_REPORT;
+        report.events[..events.len()].copy_from_slice(events);
+        report.len = events.len();
+        self.queue[(self.head.get() + queued) % FAKE_QUEUE_LEN].set(report);
+        self.queued.set(queued + 1);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a> MultiTouch<'a> for FakeTouch<'a> {
+    fn enable(&self) -> Result<(), ErrorCode> {
+        self.enabled.set(true);
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), ErrorCode> {
+        self.enabled.set(false);
+        self.queued.set(0);
+        Ok(())
+    }
+
+    fn get_num_touches(&self) -> usize {
+        MAX_TOUCHES
+    }
+
+    fn get_touch(&self, index: usize) -> Option<TouchEvent> {
+        let report = self.current.get();
+        report.events[..report.len].get(index).copied()
+    }
+
+    fn set_client(&self, client: &'a dyn MultiTouchClient) {
+        self.client.set(client);
+    }
+}
+
+impl DeferredCallClient for FakeTouch<'_> {
+    fn handle_deferred_call(&self) {
+        let queued = self.queued.get();
+        if queued == 0 {
+            return;
+        }
+        let head = self.head.get();
+        let report = self.queue[head].get();
+        self.head.set((head + 1) % FAKE_QUEUE_LEN);
+        self.queued.set(queued - 1);
+        if queued > 1 {
+            self.deferred_call.set();
+        }
+
+        self.current.set(report);
+        self.client
+            .map(|client| client.touch_events(&report.events, report.len));
+        self.current.set(EMPTY_REPORT);
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Disabled,
+    Script,
+    Drag,
+    TwoFinger,
+}
+
+pub struct TestTouch<'a, A: Alarm<'a>> {
+    source: &'a dyn MultiTouch<'a>,
+    /// Injector for unattended mode, the same object as `source`.
+    fake: Option<&'a FakeTouch<'a>>,
+    alarm: &'a A,
+    resolution: (u16, u16),
+    active: [OptionalCell<usize>; MAX_TOUCHES],
+    max_active: Cell<usize>,
+    moves: Cell<usize>,
+    received: Cell<usize>,
+    step: Cell<Step>,
+    finished: Cell<bool>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, A: Alarm<'a>> TestTouch<'a, A> {
+    /// Attended test of a real panel of `resolution` (width, height).
+    pub fn new_attended(
+        source: &'a dyn MultiTouch<'a>,
+        alarm: &'a A,
+        resolution: (u16, u16),
+    ) -> Self {
+        Self::new(source, None, alarm, resolution)
+    }
+
+    /// Unattended test with events injected into `fake`.
+    pub fn new_unattended(fake: &'a FakeTouch<'a>, alarm: &'a A) -> Self {
+        Self::new(fake, Some(fake), alarm, SCRIPT_RESOLUTION)
+    }
+
+    fn new(
+        source: &'a dyn MultiTouch<'a>,
+        fake: Option<&'a FakeTouch<'a>>,
+        alarm: &'a A,
+        resolution: (u16, u16),
+    ) -> Self {
+        TestTouch {
+            source,
+            fake,
+            alarm,
+            resolution,
+            active: [const { OptionalCell::empty() }; MAX_TOUCHES],
+            max_active: Cell::new(0),
+            moves: Cell::new(0),
+            received: Cell::new(0),
+            step: Cell::new(Step::Drag),
+            finished: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&'a self) {
+        self.source.set_client(self);
+        self.alarm.set_alarm_client(self);
+
+        match self.fake {
+            Some(fake) => {
+                self.step.set(Step::Disabled);
+                let _ = self.source.disable();
+                if fake.inject(SCRIPT[0]) != Err(ErrorCode::OFF) {
+                    self.fail("injected while disabled", CapsuleTestError::IncorrectResult);
+                    return;
+                }
+                if let Err(e) = self.source.enable() {
+                    self.fail("enable failed", CapsuleTestError::ErrorCode(e));
+                    return;
+                }
+
+                self.step.set(Step::Script);
+                self.wait(UNATTENDED_TIMEOUT_MS);
+                for report in SCRIPT.iter() {
+                    if let Err(e) = fake.inject(report) {
+                        self.fail("inject failed", CapsuleTestError::ErrorCode(e));
+                        return;
+                    }
+                }
+            }
+            None => {
+                if let Err(e) = self.source.enable() {
+                    self.fail("enable failed", CapsuleTestError::ErrorCode(e));
+                    return;
+                }
+                self.step.set(Step::Drag);
+                debug!("TouchTest: press one finger, move it, and release it");
+                self.wait(ATTENDED_TIMEOUT_MS);
+            }
+        }
+    }
+
+    fn wait(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn num_active(&self) -> usize {
+        self.active.iter().filter(|id| id.is_some()).count()
+    }
+
+    /// Updates the held touches with one reported event.
+    fn track(&self, event: &TouchEvent) -> Result<(), &'static str> {
+        if event.x >= self.resolution.0 || event.y >= self.resolution.1 {
+            return Err("coordinates outside the panel");
+        }
+        let held = self.active.iter().find(|id| id.get() == Some(event.id));
+        match (event.status, held) {
+            (TouchStatus::Unstarted, _) => Err("unstarted touch reported"),
+            (TouchStatus::Pressed, Some(_)) => Err("touch pressed twice"),
+            (TouchStatus::Pressed, None) => {
+                let slot = self
+                    .active
+                    .iter()
+                    .find(|id| id.is_none())
+                    .ok_or("too many touches")?;
+                slot.set(event.id);
+                Ok(())
+            }
+            (TouchStatus::Moved, Some(_)) => {
+                self.moves.set(self.moves.get() + 1);
+                Ok(())
+            }
+            (TouchStatus::Released, Some(slot)) => {
+                slot.clear();
+                Ok(())
+            }
+            (TouchStatus::Moved, None) => Err("touch moved without press"),
+            (TouchStatus::Released, None) => Err("touch released without press"),
+        }
+    }
+
+    fn check_report(&self, events: &[TouchEvent]) -> Result<(), &'static str> {
+        for (index, event) in events.iter().enumerate() {
+            if events[..index].iter().any(|other| other.id == event.id) {
+                return Err("touch reported twice");
+            }
+            if !self
+                .source
+                .get_touch(index)
+                .is_some_and(|touch| same_event(&touch, event))
+            {
+                return Err("get_touch differs from report");
+            }
+            self.track(event)?;
+        }
+        self.max_active
+            .set(self.max_active.get().max(self.num_active()));
+        Ok(())
+    }
+
+    /// Advances the test after a report that passed the checks.
+    fn progress(&self, events: &[TouchEvent]) {
+        match self.step.get() {
+            Step::Script => {
+                let received = self.received.get();
+                let matches = SCRIPT.get(received).is_some_and(|expected| {
+                    expected.len() == events.len()
+                        && expected.iter().zip(events).all(|(a, b)| same_event(a, b))
+                });
+                if !matches {
+                    debug!("TouchTest: report {} differs from the script", received);
+                    self.fail("report changed", CapsuleTestError::IncorrectResult);
+                    return;
+                }
+                self.received.set(received + 1);
+                if received + 1 == SCRIPT.len() {
+                    if self.num_active() != 0 || self.max_active.get() != 2 {
+                        self.fail("touches not tracked", CapsuleTestError::IncorrectResult);
+                    } else {
+                        self.finish(Ok(()));
+                    }
+                }
+            }
+            Step::Drag => {
+                if self.num_active() == 0 && self.max_active.get() == 1 && self.moves.get() > 0 {
+                    self.max_active.set(0);
+                    self.step.set(Step::TwoFinger);
+                    debug!("TouchTest: press two fingers at once and release them");
+                    self.wait(ATTENDED_TIMEOUT_MS);
+                } else if self.num_active() == 0 {
+                    // Tapped, or used several fingers; let the operator retry.
+                    self.max_active.set(0);
+                    self.moves.set(0);
+                }
+            }
+            Step::TwoFinger => {
+                if self.num_active() == 0 && self.max_active.get() >= 2 {
+                    self.finish(Ok(()));
+                }
+            }
+            Step::Disabled => self.fail("report while disabled", CapsuleTestError::IncorrectResult),
+        }
+    }
+
+    fn fail(&self, reason: &str, error: CapsuleTestError) {
+        debug!("TouchTest: {:?} failed: {}", self.step.get(), reason);
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let _ = self.alarm.disarm();
+        let _ = self.source.disable();
+        if result.is_ok() {
+            debug!("TouchTest: all cases passed");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<'a, A: Alarm<'a>> MultiTouchClient for TestTouch<'a, A> {
+    fn touch_events(&self, touch_events: &[TouchEvent], len: usize) {
+        if self.finished.get() {
+            return;
+        }
+        let Some(events) = touch_events.get(..len) else {
+            self.fail(
+                "more touches than events",
+                CapsuleTestError::IncorrectResult,
+            );
+            return;
+        };
+        match self.check_report(events) {
+            Ok(()) => self.progress(events),
+            Err(reason) => self.fail(reason, CapsuleTestError::IncorrectResult),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for TestTouch<'a, A> {
+    fn alarm(&self) {
+        if self.finished.get() {
+            return;
+        }
+        self.fail("timed out", CapsuleTestError::IncorrectResult);
+    }
+}
+
+impl<'a, A: Alarm<'a>> CapsuleTest for TestTouch<'a, A> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
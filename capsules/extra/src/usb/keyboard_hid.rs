@@ -38,7 +38,10 @@ const N_ENDPOINTS: usize = 1;
 
 /// The HID report descriptor for keyboard from
 /// <https://www.usb.org/sites/default/files/hid1_11.pdf>.
-static REPORT_DESCRIPTOR: &[u8] = &[
+///
+/// Hosts read it with a GET_DESCRIPTOR request; it is public so tests can
+/// check the reports it describes.
+pub static REPORT_DESCRIPTOR: &[u8] = &[
     0x05, 0x01, // Usage Page (Generic Desktop),
     0x09, 0x06, // Usage (Keyboard),
     0xA1, 0x01, // Collection (Application),
@@ -0,0 +1,136 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Access control lists (ACL) for flash
+//!
+//! <https://infocenter.nordicsemi.com/topic/ps_nrf52840/acl.html>
+//!
+//! The ACL peripheral of the nRF52833 and nRF52840 protects up to eight
+//! page-aligned regions of flash from being written or erased, and optionally
+//! from being read. The hardware blocks such accesses, whether they come from
+//! the CPU or the NVMC. The configuration of a region can only be written once
+//! after reset. The nRF52832 has the BPROT peripheral instead, which this
+//! module does not support.
+//!
+//! Example protecting the last page of flash from writes:
+//!
+//! ```rust,ignore
+//! let acl = nrf52::acl::Acl::new();
+//! acl.protect_region(0, 0xFF000, 4096, nrf52::acl::Permissions::ReadOnly)?;
+//! ```
+
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+const ACL_BASE: StaticRef<AclRegisters> =
+    unsafe { StaticRef::new(0x4001E000 as *const AclRegisters) };
+
+/// Number of regions the ACL can protect.
+pub const NUM_REGIONS: usize = 8;
+
+const PAGE_SIZE: usize = 4096;
+
+register_structs! {
+    AclRegisters {
+        (0x000 => _reserved0),
+        (0x800 => regions: [AclRegion; NUM_REGIONS]),
+        (0x880 => @END),
+    },
+
+    AclRegion {
+        /// Start address of the region, page aligned
+        (0x0 => addr: ReadWrite<u32>),
+        /// Size of the region in bytes, a multiple of the page size. A region
+        /// is unused while this is zero.
+        (0x4 => size: ReadWrite<u32>),
+        /// Access permissions of the region
+        (0x8 => perm: ReadWrite<u32, Permission::Register>),
+        (0xc => _reserved0),
+        (0x10 => @END),
+    }
+}
+
+register_bitfields! [u32,
+    Permission [
+        /// Configure write and erase permissions
+        WRITE OFFSET(1) NUMBITS(1) [
+            Enable = 0,
+            Disable = 1
+        ],
+        /// Configure read permissions
+        READ OFFSET(2) NUMBITS(1) [
+            Enable = 0,
+            Disable = 1
+        ]
+    ]
+];
+
+/// Accesses allowed to a protected region.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Permissions {
+    /// The region can be read but not written or erased.
+    ReadOnly,
+    /// The region can be written and erased but not read.
+    WriteOnly,
+    /// The region can be neither read, written nor erased.
+    NoAccess,
+}
+
+pub struct Acl {
+    registers: StaticRef<AclRegisters>,
+}
+
+impl Acl {
+    pub const fn new() -> Acl {
+        Acl {
+            registers: ACL_BASE,
+        }
+    }
+
+    /// Protects `size` bytes of flash starting at `address` using region
+    /// `index`.
+    ///
+    /// Returns `INVAL` if `index` is out of range or the region is not page
+    /// aligned, and `ALREADY` if region `index` was configured since the last
+    /// reset.
+    pub fn protect_region(
+        &self,
+        index: usize,
+        address: usize,
+        size: usize,
+        permissions: Permissions,
+    ) -> Result<(), ErrorCode> {
+        let region = self.registers.regions.get(index).ok_or(ErrorCode::INVAL)?;
+        if size == 0 || address % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if region.size.get() != 0 {
+            return Err(ErrorCode::ALREADY);
+        }
+
+        region.addr.set(address as u32);
+        region.size.set(size as u32);
+        region.perm.write(match permissions {
+            Permissions::ReadOnly => Permission::WRITE::Disable + Permission::READ::Enable,
+            Permissions::WriteOnly => Permission::WRITE::Enable + Permission::READ::Disable,
+            Permissions::NoAccess => Permission::WRITE::Disable + Permission::READ::Disable,
+        });
+        Ok(())
+    }
+
+    /// Returns whether any byte of the `len` bytes at `address` is in a region
+    /// that cannot be written or erased.
+    pub fn is_write_protected(&self, address: usize, len: usize) -> bool {
+        self.registers.regions.iter().any(|region| {
+            let start = region.addr.get() as usize;
+            let size = region.size.get() as usize;
+            size != 0
+                && region.perm.is_set(Permission::WRITE)
+                && address < start + size
+                && start < address + len
+        })
+    }
+}
@@ -14,6 +14,8 @@ use kernel::utilities::registers::{register_bitfields, ReadOnly, ReadWrite, Writ
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 
+use crate::easydma;
+
 #[repr(C)]
 struct AdcRegisters {
     /// Start the ADC and prepare the result buffer in RAM
@@ -501,24 +503,29 @@ impl Adc<'_> {
                         ret_buf[i] <<= 4;
                     }
 
-                    self.highspeed_client.map(|client| {
-                        client.samples_ready(ret_buf, length);
-                    });
-
                     // Optionally setup to continue reading. We already
-                    // configured the address if valid.
+                    // configured the address if valid. This happens before the
+                    // callback so the client can provide the following buffer.
                     let length2 = self.next_length.get();
-                    if length2 > 0 {
-                        self.length.set(length2);
-                        self.buffer.put(self.next_buffer.take());
-                        self.registers
-                            .result_maxcnt
-                            .write(RESULT_MAXCNT::MAXCNT.val(length2 as u32));
-                        kernel::debug!("len2 {}", length2);
-
-                        // self.registers.tasks_sample.write(TASK::TASK::SET);
-                        self.registers.tasks_start.write(TASK::TASK::SET);
+                    self.next_length.set(0);
+                    match self.next_buffer.take() {
+                        Some(next) if length2 > 0 => {
+                            self.length.set(length2);
+                            self.buffer.replace(next);
+                            self.registers
+                                .result_maxcnt
+                                .write(RESULT_MAXCNT::MAXCNT.val(length2 as u32));
+
+                            self.registers.tasks_start.write(TASK::TASK::SET);
+                        }
+                        next => {
+                            self.next_buffer.put(next);
+                        }
                     }
+
+                    self.highspeed_client.map(|client| {
+                        client.samples_ready(ret_buf, length);
+                    });
                 } else if self.registers.events_stopped.is_set(EVENT::EVENT) {
                     self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
                 }
@@ -617,6 +624,13 @@ impl<'a> hil::adc::Adc<'a> for Adc<'a> {
 
     fn stop_sampling(&self) -> Result<(), ErrorCode> {
         self.registers.tasks_stop.write(TASK::TASK::SET);
+        if let AdcMode::HighSpeed = self.mode.get() {
+            // No callbacks are allowed after stopping, so ignore the
+            // remaining events and any pending buffer swap.
+            self.registers.inten.set(0);
+            self.next_length.set(0);
+            self.mode.set(AdcMode::Idle);
+        }
         Ok(())
     }
 
@@ -646,6 +660,10 @@ impl<'a> hil::adc::AdcHighSpeed<'a> for Adc<'a> {
         if length1 == 0 {
             // At least need to take one sample.
             Err((ErrorCode::INVAL, buffer1, buffer2))
+        } else if let Err(error) =
+            easydma::check(buffer1.as_ptr(), length1).and(easydma::check(buffer2.as_ptr(), length2))
+        {
+            Err((error, buffer1, buffer2))
         } else {
             // Store the second buffer for later use
             self.next_buffer.replace(buffer2);
@@ -667,6 +685,11 @@ impl<'a> hil::adc::AdcHighSpeed<'a> for Adc<'a> {
             // Set the frequency best we can.
             self.setup_frequency(frequency);
 
+            // Drop events left over from a previous, stopped operation.
+            self.registers.events_started.write(EVENT::EVENT::CLEAR);
+            self.registers.events_end.write(EVENT::EVENT::CLEAR);
+            self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
+
             // Enable the ADC
             self.registers.enable.write(ENABLE::ENABLE::SET);
 
@@ -541,6 +541,8 @@ pub struct Radio<'a> {
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
     buffer: TakeCell<'static, [u8]>,
+    /// RSSI of the last received packet, in dBm.
+    rssi: Cell<Option<i8>>,
 }
 
 impl<'a> Radio<'a> {
@@ -551,6 +553,7 @@ impl<'a> Radio<'a> {
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            rssi: Cell::new(None),
         }
     }
 
@@ -558,6 +561,15 @@ impl<'a> Radio<'a> {
         self.registers.mode.matches_all(Mode::MODE::BLE_1MBIT)
     }
 
+    /// RSSI of the last packet passed to the receive client, in dBm, or
+    /// `None` if the radio did not sample it.
+    ///
+    /// The sample is taken when the access address is received, so it is
+    /// also set for packets with a CRC error.
+    pub fn last_rssi_dbm(&self) -> Option<i8> {
+        self.rssi.get()
+    }
+
     fn tx(&self) {
         self.registers.event_ready.write(Event::READY::CLEAR);
         self.registers.task_txen.write(Task::ENABLE::SET);
@@ -565,6 +577,11 @@ impl<'a> Radio<'a> {
 
     fn rx(&self) {
         self.registers.event_ready.write(Event::READY::CLEAR);
+        self.registers.event_rssiend.write(Event::READY::CLEAR);
+        // Sample the signal strength once the access address matched
+        self.registers
+            .shorts
+            .write(Shortcut::ADDRESS_RSSISTART::SET);
         self.registers.task_rxen.write(Task::ENABLE::SET);
     }
 
@@ -638,6 +655,11 @@ impl<'a> Radio<'a> {
                 | nrf5x::constants::RADIO_STATE_RXIDLE
                 | nrf5x::constants::RADIO_STATE_RXDISABLE
                 | nrf5x::constants::RADIO_STATE_RX => {
+                    let rssi = self.registers.event_rssiend.is_set(Event::READY).then(|| {
+                        // RSSISAMPLE holds the received signal strength as -dBm
+                        -(self.registers.rssisample.read(RssiSample::RSSISAMPLE) as i8)
+                    });
+                    self.rssi.set(rssi);
                     self.radio_off();
                     unsafe {
                         self.rx_client.map(|client| {
@@ -40,7 +40,9 @@ pub struct Nrf52DefaultPeripherals<'a> {
     pub timer2: crate::timer::Timer,
     pub uarte0: crate::uart::Uarte<'a>,
     pub spim0: crate::spi::SPIM<'a>,
+    pub twi0: crate::i2c::TWI<'a>,
     pub twi1: crate::i2c::TWI<'a>,
+    pub spis1: crate::spis::SPIS<'a>,
     pub spim2: crate::spi::SPIM<'a>,
     pub adc: crate::adc::Adc<'a>,
     pub nvmc: crate::nvmc::Nvmc,
@@ -63,7 +65,9 @@ impl Nrf52DefaultPeripherals<'_> {
             timer2: crate::timer::Timer::new(2),
             uarte0: crate::uart::Uarte::new(crate::uart::UARTE0_BASE),
             spim0: crate::spi::SPIM::new(0),
+            twi0: crate::i2c::TWI::new_twi0(),
             twi1: crate::i2c::TWI::new_twi1(),
+            spis1: crate::spis::SPIS::new(1),
             spim2: crate::spi::SPIM::new(2),
             // Default to 3.3 V VDD reference.
             adc: crate::adc::Adc::new(3300),
@@ -94,8 +98,14 @@ impl kernel::platform::chip::InterruptService for Nrf52DefaultPeripherals<'_> {
             crate::peripheral_interrupts::TIMER1 => self.timer1.handle_interrupt(),
             crate::peripheral_interrupts::TIMER2 => self.timer2.handle_interrupt(),
             crate::peripheral_interrupts::UART0 => self.uarte0.handle_interrupt(),
-            crate::peripheral_interrupts::SPI0_TWI0 => self.spim0.handle_interrupt(),
-            crate::peripheral_interrupts::SPI1_TWI1 => self.twi1.handle_interrupt(),
+            crate::peripheral_interrupts::SPI0_TWI0 => match self.twi0.is_enabled() {
+                false => self.spim0.handle_interrupt(),
+                true => self.twi0.handle_interrupt(),
+            },
+            crate::peripheral_interrupts::SPI1_TWI1 => match self.spis1.is_enabled() {
+                false => self.twi1.handle_interrupt(),
+                true => self.spis1.handle_interrupt(),
+            },
             crate::peripheral_interrupts::SPIM2_SPIS2_SPI2 => self.spim2.handle_interrupt(),
             crate::peripheral_interrupts::ADC => self.adc.handle_interrupt(),
             _ => return false,
@@ -71,6 +71,10 @@ pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
 #[cfg_attr(all(target_arch = "arm", target_os = "none"), used)]
 pub static IRQS: [unsafe extern "C" fn(); 80] = [CortexM4F::GENERIC_ISR; 80];
 
+/// Anomalies from the nRF52832 errata document that [`init()`] works around,
+/// on every nRF52 part.
+pub const NRF52832_STARTUP_ERRATA: [u16; 6] = [12, 16, 31, 37, 57, 108];
+
 #[no_mangle]
 pub unsafe extern "C" fn init() {
     // Apply early initialization workarounds for anomalies documented on
@@ -0,0 +1,56 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks of the buffers handed to EasyDMA.
+//!
+//! EasyDMA only reaches the Data RAM. Given a buffer anywhere else, such as a
+//! constant in flash, a peripheral does not fault: it transmits garbage, or
+//! nothing, and reports the transfer done. Each element of a buffer must
+//! also be aligned to its size, as EasyDMA accesses the elements of 16-bit
+//! buffers, such as SAADC samples, as half-words.
+//!
+//! In a kernel built with the `kernel_test` feature, the drivers check each
+//! buffer before they start a transfer, and reject a buffer EasyDMA cannot
+//! use with `ErrorCode::INVAL`, which fails the test that handed it over.
+//! The TWIM is the exception: `hil::i2c::Error` has no variant for an
+//! invalid argument, so it rejects the buffer with
+//! `hil::i2c::Error::NotSupported`, which converts to `ErrorCode::NOSUPPORT`.
+//! The check prints the address of the buffer:
+//!
+//! ```text
+//! EasyDMA: buffer at 0x0002f1c4 (12 bytes) not in RAM or misaligned
+//! ```
+//!
+//! In any other kernel, the check is compiled out.
+
+use core::mem::size_of;
+use core::ops::Range;
+
+use kernel::ErrorCode;
+
+/// Addresses of the Data RAM, of the largest nRF52.
+const DATA_RAM: Range<usize> = 0x2000_0000..0x2004_0000;
+
+/// Checks that EasyDMA can transfer `len` elements at `address`, in a kernel
+/// test build.
+pub fn check<T>(address: *const T, len: usize) -> Result<(), ErrorCode> {
+    if !kernel::test::KERNEL_TEST {
+        return Ok(());
+    }
+    let start = address as usize;
+    let bytes = len * size_of::<T>();
+    if DATA_RAM.contains(&start)
+        && bytes <= DATA_RAM.end - start
+        && start % size_of::<T>().max(1) == 0
+    {
+        Ok(())
+    } else {
+        kernel::debug!(
+            "EasyDMA: buffer at {:#010x} ({} bytes) not in RAM or misaligned",
+            start,
+            bytes
+        );
+        Err(ErrorCode::INVAL)
+    }
+}
@@ -244,9 +244,9 @@ register_bitfields! [u32,
 ];
 
 /// Variant describes part variant, hardware version, and production configuration.
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 #[repr(u32)]
-pub(crate) enum Variant {
+pub enum Variant {
     AAA0 = 0x41414130,
     AAAA = 0x41414141,
     AAAB = 0x41414142,
@@ -268,9 +268,10 @@ pub(crate) enum Variant {
     Unspecified = 0xffffffff,
 }
 
-#[derive(PartialEq, Debug)]
+/// Part code of the chip.
+#[derive(Clone, Copy, PartialEq, Debug)]
 #[repr(u32)]
-enum Part {
+pub enum Part {
     N52832 = 0x52832,
     N52833 = 0x52833,
     N52840 = 0x52840,
@@ -328,7 +329,7 @@ impl Ficr {
         }
     }
 
-    fn part(&self) -> Part {
+    pub fn part(&self) -> Part {
         match self.registers.info_part.get() {
             0x52832 => Part::N52832,
             0x52833 => Part::N52833,
@@ -337,7 +338,7 @@ impl Ficr {
         }
     }
 
-    pub(crate) fn variant(&self) -> Variant {
+    pub fn variant(&self) -> Variant {
         // If you update this, make sure to update
         // `has_updated_approtect_logic()` as well.
         match self.registers.info_variant.get() {
@@ -363,6 +364,15 @@ impl Ficr {
         }
     }
 
+    /// Returns the hardware revision letter encoded in the variant, for
+    /// example `'D'` for `AAD0`, or `None` if the variant is not recognized.
+    pub fn hardware_revision(&self) -> Option<char> {
+        match self.variant() {
+            Variant::Unspecified => None,
+            variant => Some(char::from((variant as u32 >> 8) as u8)),
+        }
+    }
+
     /// Returns if this variant of the nRF52 has the updated APPROTECT logic.
     /// This changed occurred towards the end of 2021 with chips becoming widely
     /// available/used in 2023.
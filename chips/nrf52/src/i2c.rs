@@ -6,16 +6,33 @@
 //!
 //! This module supports nRF52's two I2C master (`TWI`) peripherals,
 //! and the I2C slave (`TWIS`).
+//!
+//! A slave may stretch the clock, and the TWIM waits for it without limit.
+//! With a timer set with `set_timer_ref()`, the master instead stops a
+//! transfer that has not ended `TIMEOUT_US`, the SMBus tTIMEOUT,max, after
+//! its bytes would have been sent at 100 kHz, and reports `Error::Timeout`.
+//!
+//! A slave reset in the middle of a read may hold SDA low until it is clocked
+//! through the rest of its byte, and the master cannot start a transfer until
+//! then. `recover_bus()` frees the bus.
 
+use crate::timer::TimerAlarm;
+use enum_primitive::cast::FromPrimitive;
 use kernel::hil;
+use kernel::hil::gpio::{Configure, Input, Output};
+use kernel::hil::time::{Alarm, AlarmClient, Ticks32, Time};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::cells::VolatileCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite, WriteOnly};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use nrf5x::gpio::{GPIOPin, Pin};
 use nrf5x::pinmux::Pinmux;
 
+use crate::easydma;
+
 /// Uninitialized `TWI` instances.
 const INSTANCES: [StaticRef<TwiRegisters>; 2] = unsafe {
     [
@@ -24,6 +41,22 @@ const INSTANCES: [StaticRef<TwiRegisters>; 2] = unsafe {
     ]
 };
 
+/// Time a slave may hold the clock low in a transfer, in microseconds: the
+/// SMBus tTIMEOUT,max. SMBus slaves may stretch the clock for up to 25 ms in
+/// total in a message, tLOW:SEXT.
+pub const TIMEOUT_US: u32 = 35_000;
+
+/// Time of a byte and its acknowledgement at 100 kHz, the slowest speed.
+const BYTE_US: u32 = 90;
+
+/// Clock pulses that free a slave holding SDA low: the rest of its byte and
+/// the acknowledgement.
+const RECOVERY_PULSES: usize = 9;
+
+/// Busy-wait iterations of half a clock period of the recovery, at least
+/// 5 us, for 100 kHz, at 64 MHz.
+const RECOVERY_HALF_PERIOD: usize = 120;
+
 /// An I2C master device.
 ///
 /// A `TWI` instance wraps a `registers::TWI` together with
@@ -34,6 +67,7 @@ pub struct TWI<'a> {
     slave_client: OptionalCell<&'a dyn hil::i2c::I2CHwSlaveClient>,
     buf: TakeCell<'static, [u8]>,
     slave_read_buf: TakeCell<'static, [u8]>,
+    timer: OptionalCell<&'a TimerAlarm<'a>>,
 }
 
 /// I2C bus speed.
@@ -44,7 +78,7 @@ pub enum Speed {
     K400 = 0x06400000,
 }
 
-impl TWI<'_> {
+impl<'a> TWI<'a> {
     const fn new(registers: StaticRef<TwiRegisters>) -> Self {
         Self {
             registers,
@@ -52,6 +86,7 @@ impl TWI<'_> {
             slave_client: OptionalCell::empty(),
             buf: TakeCell::empty(),
             slave_read_buf: TakeCell::empty(),
+            timer: OptionalCell::empty(),
         }
     }
 
@@ -63,12 +98,97 @@ impl TWI<'_> {
         TWI::new(INSTANCES[1])
     }
 
+    /// Sets the timer that limits the time of master transfers. The timer
+    /// must have this `TWI` as its alarm client, and no other user.
+    pub fn set_timer_ref(&self, timer: &'a TimerAlarm<'a>) {
+        self.timer.set(timer);
+    }
+
+    /// Starts the timeout of a master transfer of `bytes` bytes, counting the
+    /// address.
+    fn start_timeout(&self, bytes: usize) {
+        self.timer.map(|timer| {
+            let now = timer.now();
+            timer.set_alarm(
+                now,
+                Ticks32::from(TIMEOUT_US + BYTE_US * (bytes as u32 + 1)),
+            );
+        });
+    }
+
+    fn stop_timeout(&self) {
+        self.timer.map(|timer| timer.disarm());
+    }
+
     /// Configures an already constructed `TWI`.
     pub fn configure(&self, scl: Pinmux, sda: Pinmux) {
         self.registers.psel_scl.set(scl);
         self.registers.psel_sda.set(sda);
     }
 
+    /// Frees a bus a slave holds SDA low on: clocks SCL until the slave
+    /// releases SDA, at most nine times, and then sends a STOP condition, as
+    /// section 3.1.16 of the I2C specification (UM10204) describes. The pins
+    /// are those set with `configure()`, and no master transfer may be in
+    /// progress.
+    ///
+    /// Returns `Err(ErrorCode::FAIL)` if SDA is still low.
+    pub fn recover_bus(&self) -> Result<(), ErrorCode> {
+        if self.buf.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let pin = |pinmux: Pinmux| Pin::from_u32(pinmux.into()).map(GPIOPin::new);
+        let (Some(scl), Some(sda)) = (
+            pin(self.registers.psel_scl.get()),
+            pin(self.registers.psel_sda.get()),
+        ) else {
+            return Err(ErrorCode::INVAL);
+        };
+        let enable = self.registers.enable.get();
+        self.disable();
+
+        // The pins drive only low, like the TWI, so a high output releases
+        // the line to the pull-up.
+        let half_period = || {
+            for _ in 0..RECOVERY_HALF_PERIOD {
+                cortexm4f::support::nop();
+            }
+        };
+        for pin in [&scl, &sda] {
+            pin.set_i2c_pin_cfg();
+            pin.set();
+            pin.make_input();
+            pin.make_output();
+        }
+        for _ in 0..RECOVERY_PULSES {
+            if sda.read() {
+                break;
+            }
+            scl.clear();
+            half_period();
+            scl.set();
+            half_period();
+        }
+        // STOP: SDA rises while SCL is high.
+        scl.clear();
+        sda.clear();
+        half_period();
+        scl.set();
+        half_period();
+        sda.set();
+        half_period();
+        let released = sda.read();
+
+        scl.set_i2c_pin_cfg();
+        sda.set_i2c_pin_cfg();
+        self.registers.enable.set(enable);
+        if released {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
     /// Sets the I2C bus speed to one of three possible values
     /// enumerated in `Speed`.
     pub fn set_speed(&self, speed: Speed) {
@@ -119,6 +239,7 @@ impl TWI<'_> {
         if self.is_master_enabled() {
             if self.registers.events_stopped.is_set(EVENT::EVENT) {
                 self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
+                self.stop_timeout();
 
                 self.client.map(|client| match self.buf.take() {
                     None => (),
@@ -131,6 +252,7 @@ impl TWI<'_> {
 
             if self.registers.events_error.is_set(EVENT::EVENT) {
                 self.registers.events_error.write(EVENT::EVENT::CLEAR);
+                self.stop_timeout();
                 let errorsrc = self.registers.errorsrc_master.extract();
                 self.registers
                     .errorsrc_master
@@ -151,8 +273,6 @@ impl TWI<'_> {
                 });
             }
         } else {
-            self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
-
             // If RX started (master started write) and we don't have a buffer then report
             // write_expected()
             if self.registers.events_rxstarted.is_set(EVENT::EVENT) {
@@ -175,38 +295,53 @@ impl TWI<'_> {
                 });
             }
 
-            // Write command received
-            if self.registers.events_write.is_set(EVENT::EVENT) {
-                self.registers.events_write.write(EVENT::EVENT::CLEAR);
-                let length = self.registers.rxd_amount.read(AMOUNT::AMOUNT) as usize;
-                self.slave_client.map(|client| match self.buf.take() {
-                    None => (),
-                    Some(buf) => {
-                        self.clear_events();
-                        client.command_complete(
-                            buf,
-                            length,
-                            hil::i2c::SlaveTransmissionType::Write,
-                        );
-                    }
-                });
+            // An overflow means the master wrote more bytes than the receive
+            // buffer holds. The TWIS NACKs the extra bytes and the transfer is
+            // still reported with the received amount once it stops.
+            if self.registers.events_error.is_set(EVENT::EVENT) {
+                self.registers.events_error.write(EVENT::EVENT::CLEAR);
+                self.registers
+                    .errorsrc_slave
+                    .set(self.registers.errorsrc_slave.get());
             }
 
-            if self.registers.events_read.is_set(EVENT::EVENT) {
-                self.registers.events_read.write(EVENT::EVENT::CLEAR);
-                let length = self.registers.txd_amount.read(AMOUNT::AMOUNT) as usize;
-                self.slave_client
-                    .map(|client| match self.slave_read_buf.take() {
+            // Only report transfers once the master has ended the transaction,
+            // so the amounts include every byte. A write followed by a read
+            // with a repeated start reports both.
+            if self.registers.events_stopped.is_set(EVENT::EVENT) {
+                self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
+                let write = self.registers.events_write.is_set(EVENT::EVENT);
+                let read = self.registers.events_read.is_set(EVENT::EVENT);
+                let rx_length = self.registers.rxd_amount.read(AMOUNT::AMOUNT) as usize;
+                let tx_length = self.registers.txd_amount.read(AMOUNT::AMOUNT) as usize;
+                self.clear_events();
+
+                if write {
+                    self.slave_client.map(|client| match self.buf.take() {
                         None => (),
                         Some(buf) => {
-                            self.clear_events();
                             client.command_complete(
                                 buf,
-                                length,
-                                hil::i2c::SlaveTransmissionType::Read,
+                                rx_length,
+                                hil::i2c::SlaveTransmissionType::Write,
                             );
                         }
                     });
+                }
+
+                if read {
+                    self.slave_client
+                        .map(|client| match self.slave_read_buf.take() {
+                            None => (),
+                            Some(buf) => {
+                                client.command_complete(
+                                    buf,
+                                    tx_length,
+                                    hil::i2c::SlaveTransmissionType::Read,
+                                );
+                            }
+                        });
+                }
             }
         }
 
@@ -253,6 +388,10 @@ impl<'a> hil::i2c::I2CMaster<'a> for TWI<'a> {
         write_len: usize,
         read_len: usize,
     ) -> Result<(), (hil::i2c::Error, &'static mut [u8])> {
+        // `hil::i2c::Error` cannot carry `ErrorCode::INVAL`, see `easydma`.
+        if easydma::check(data.as_ptr(), write_len.max(read_len)).is_err() {
+            return Err((hil::i2c::Error::NotSupported, data));
+        }
         self.registers
             .address_0
             .write(ADDRESS::ADDRESS.val(addr as u32));
@@ -276,6 +415,7 @@ impl<'a> hil::i2c::I2CMaster<'a> for TWI<'a> {
             .write(INTE::STOPPED::Enable + INTE::ERROR::Enable);
         // start the transfer
         self.registers.tasks_starttx.write(TASK::TASK::SET);
+        self.start_timeout(write_len + read_len + 1);
         self.buf.replace(data);
         Ok(())
     }
@@ -286,6 +426,9 @@ impl<'a> hil::i2c::I2CMaster<'a> for TWI<'a> {
         data: &'static mut [u8],
         len: usize,
     ) -> Result<(), (hil::i2c::Error, &'static mut [u8])> {
+        if easydma::check(data.as_ptr(), len).is_err() {
+            return Err((hil::i2c::Error::NotSupported, data));
+        }
         self.registers
             .address_0
             .write(ADDRESS::ADDRESS.val(addr as u32));
@@ -303,6 +446,7 @@ impl<'a> hil::i2c::I2CMaster<'a> for TWI<'a> {
             .write(INTE::STOPPED::Enable + INTE::ERROR::Enable);
         // start the transfer
         self.registers.tasks_starttx.write(TASK::TASK::SET);
+        self.start_timeout(len);
         self.buf.replace(data);
         Ok(())
     }
@@ -313,6 +457,9 @@ impl<'a> hil::i2c::I2CMaster<'a> for TWI<'a> {
         buffer: &'static mut [u8],
         len: usize,
     ) -> Result<(), (hil::i2c::Error, &'static mut [u8])> {
+        if easydma::check(buffer.as_ptr(), len).is_err() {
+            return Err((hil::i2c::Error::NotSupported, buffer));
+        }
         self.registers
             .address_0
             .write(ADDRESS::ADDRESS.val(addr as u32));
@@ -330,11 +477,33 @@ impl<'a> hil::i2c::I2CMaster<'a> for TWI<'a> {
             .write(INTE::STOPPED::Enable + INTE::ERROR::Enable);
         // start the transfer
         self.registers.tasks_startrx.write(TASK::TASK::SET);
+        self.start_timeout(len);
         self.buf.replace(buffer);
         Ok(())
     }
 }
 
+impl AlarmClient for TWI<'_> {
+    /// Stops a master transfer that timed out. A slave that still holds the
+    /// clock low keeps the TWIM from sending the stop condition, so the TWIM
+    /// is disabled, which releases its pins, and enabled again, ready for the
+    /// next transfer.
+    fn alarm(&self) {
+        if !self.is_master_enabled() {
+            return;
+        }
+        if let Some(buf) = self.buf.take() {
+            self.disable_interrupts();
+            self.registers.tasks_stop.write(TASK::TASK::SET);
+            self.disable();
+            self.enable_master();
+            self.client.map(move |client| {
+                client.command_complete(buf, Err(hil::i2c::Error::Timeout));
+            });
+        }
+    }
+}
+
 impl<'a> hil::i2c::I2CSlave<'a> for TWI<'a> {
     fn set_slave_client(&self, client: &'a dyn hil::i2c::I2CHwSlaveClient) {
         self.slave_client.set(client);
@@ -389,7 +558,7 @@ impl<'a> hil::i2c::I2CSlave<'a> for TWI<'a> {
 
         self.registers
             .intenset
-            .modify(INTE::STOPPED::Enable + INTE::ERROR::Enable + INTE::READ::Enable);
+            .modify(INTE::STOPPED::Enable + INTE::ERROR::Enable);
 
         self.slave_read_buf.replace(data);
 
@@ -30,8 +30,8 @@
 //! immediately triggers a START task. The START task notifies the radio to begin
 //! officially "listening for packets" (RX state). Upon completing receiving the
 //! packet, the radio issues an END event. The driver then determines if the
-//! received packet has requested to be acknowledged (bit flag) and sends an ACK
-//! accordingly. Finally, the received packet buffer and accompanying fields are
+//! received packet has requested to be acknowledged (bit flag) and is addressed
+//! to this device, and sends an ACK accordingly. Finally, the received packet buffer and accompanying fields are
 //! passed to the registered radio client. This marks the end of a receive cycle
 //! and a new READY event is issued to once again begin listening for packets.
 //!
@@ -58,7 +58,14 @@
 //! This radio state machine provides nine possible states the radio can exist
 //! in. For ease of implementation and clarity, this driver also maintains a
 //! simplified state machine. These states consist of the radio being off (OFF),
-//! receiving (RX), transmitting (TX), or acknowledging (ACK).
+//! receiving (RX), transmitting (TX), acknowledging (ACK), or measuring the
+//! energy on a channel (ED).
+//!
+//! For an energy detection (ED) measurement, requested with `energy_detect()`,
+//! the driver disables the radio, ramps the receiver up on the measured
+//! channel with the DISABLED_RXEN and READY_EDSTART shortcuts, and waits for
+//! the EDEND event. It then disables the radio again and returns to RX on the
+//! configured channel before reporting the level.
 
 // Author: Tyler Potyondy
 // 8/21/23
@@ -81,6 +88,19 @@ const RADIO_BASE: StaticRef<RadioRegisters> =
 
 const ACK_FLAG: u8 = 0b00100000;
 
+// Fields of the MAC frame control field, which is sent least significant byte
+// first.
+const FCF_PAN_ID_COMPRESSION: u16 = 1 << 6;
+const FCF_SEQ_SUPPRESSED: u16 = 1 << 8;
+const FCF_DST_MODE_POS: u16 = 10;
+const FCF_VERSION_POS: u16 = 12;
+const FCF_SRC_MODE_POS: u16 = 14;
+const ADDR_MODE_NONE: u16 = 0b00;
+const ADDR_MODE_SHORT: u16 = 0b10;
+const ADDR_MODE_LONG: u16 = 0b11;
+const FRAME_VERSION_2015: u16 = 0b10;
+const BROADCAST_PAN: u16 = 0xFFFF;
+
 pub const IEEE802154_PAYLOAD_LENGTH: usize = 255;
 pub const IEEE802154_BACKOFF_PERIOD: usize = 320; //microseconds = 20 symbols
 pub const IEEE802154_ACK_TIME: usize = 512; //microseconds = 32 symbols
@@ -132,8 +152,12 @@ struct RadioRegisters {
     /// Stop the bit counter
     /// - Address: 0x020 - 0x024
     task_bcstop: WriteOnly<u32, Task::Register>,
-    /// Reserved
-    _reserved1: [u32; 2],
+    /// Start the energy detect measurement used in IEEE 802.15.4 mode
+    /// - Address: 0x024 - 0x028
+    task_edstart: WriteOnly<u32, Task::Register>,
+    /// Stop the energy detect measurement
+    /// - Address: 0x028 - 0x02c
+    task_edstop: WriteOnly<u32, Task::Register>,
     /// Stop the bit counter
     /// - Address: 0x02c - 0x030
     task_ccastart: WriteOnly<u32, Task::Register>,
@@ -182,8 +206,12 @@ struct RadioRegisters {
     /// IEEE 802.15.4 length field received
     /// - Address: 0x138 - 0x13c
     event_framestart: ReadWrite<u32, Event::Register>,
-    /// Reserved
-    _reserved5: [u32; 2],
+    /// Sampling of energy detection complete
+    /// - Address: 0x13c - 0x140
+    event_edend: ReadWrite<u32, Event::Register>,
+    /// The sampling of energy detection has stopped
+    /// - Address: 0x140 - 0x144
+    event_edstopped: ReadWrite<u32, Event::Register>,
     /// Wireless medium in idle - clear to send
     /// - Address: 0x144-0x148
     event_ccaidle: ReadWrite<u32, Event::Register>,
@@ -309,8 +337,14 @@ struct RadioRegisters {
     /// Radio mode configuration register
     /// - Address: 0x650 - 0x654
     modecnf0: ReadWrite<u32, RadioModeConfig::Register>,
+    /// Number of energy detect iterations, minus one
+    /// - Address: 0x654 - 0x658
+    edcnt: ReadWrite<u32, EnergyDetectCount::Register>,
+    /// Energy detect level of the last measurement
+    /// - Address: 0x658 - 0x65c
+    edsample: ReadOnly<u32, EnergyDetectSample::Register>,
     /// Reserved
-    _reserved16: [u32; 6],
+    _reserved16: [u32; 4],
     /// Clear Channel Assesment (CCA) control register
     /// - Address: 0x66C - 0x670
     ccactrl: ReadWrite<u32, CCAControl::Register>,
@@ -354,6 +388,8 @@ register_bitfields! [u32,
         CCAIDLE_TXEN OFFSET(12) NUMBITS(1),
         /// Shortcut between RXREADY_CCASTART
         RXREADY_CCASTART OFFSET(11) NUMBITS(1),
+        /// Shortcut between READY event and EDSTART task
+        READY_EDSTART OFFSET(15) NUMBITS(1),
         /// Shortcut between TXREADY event and START task
         TXREADY_START OFFSET(19) NUMBITS(1),
 
@@ -384,6 +420,8 @@ register_bitfields! [u32,
         CRCERROR OFFSET(13) NUMBITS(1),
         /// CCAIDLE event
         FRAMESTART OFFSET(14) NUMBITS(1),
+        /// EDEND event
+        EDEND OFFSET(15) NUMBITS(1),
         /// CCAIDLE event
         CCAIDLE OFFSET(17) NUMBITS(1),
         /// CCABUSY event
@@ -393,6 +431,17 @@ register_bitfields! [u32,
         /// RXREADY event
         RXREADY OFFSET(22) NUMBITS(1),
     ],
+    /// Energy detect count register
+    EnergyDetectCount [
+        /// Number of 128 us iterations of an energy detect measurement, minus
+        /// one
+        EDCNT OFFSET(0) NUMBITS(21)
+    ],
+    /// Energy detect sample register
+    EnergyDetectSample [
+        /// Peak energy level of the last measurement
+        EDLVL OFFSET(0) NUMBITS(7)
+    ],
     /// Receive match register
     ReceiveMatch [
         /// Logical address of which previous packet was received
@@ -670,6 +719,31 @@ enum RadioState {
     RX,
     /// Transmitting an acknowledgement packet.
     ACK,
+    /// Measuring the energy on a channel.
+    ED,
+}
+
+/// Factor from the energy detect level of the hardware to the IEEE 802.15.4
+/// energy detection value.
+const ED_RSSISCALE: u8 = 4;
+
+/// Received power, in dBm, at the energy detect level 0.
+const ED_RSSIOFFS: i16 = -94;
+
+/// Largest number of iterations of an energy detect measurement.
+pub const MAX_ED_ITERATIONS: u32 = 1 << 21;
+
+/// Returns the received power, in dBm, of an IEEE 802.15.4 energy detection
+/// value reported to an `EnergyDetectClient`.
+pub fn energy_level_dbm(level: u8) -> i16 {
+    ED_RSSIOFFS + i16::from(level / ED_RSSISCALE)
+}
+
+/// Receives the result of `Radio::energy_detect`.
+pub trait EnergyDetectClient {
+    /// `level` is the peak energy measured on `channel`, as an IEEE 802.15.4
+    /// energy detection value from 0 to 255.
+    fn energy_detected(&self, channel: RadioChannel, level: u8);
 }
 
 /// We use a single deferred call for two operations: triggering config clients
@@ -690,6 +764,7 @@ pub struct Radio<'a> {
     tx_client: OptionalCell<&'a dyn radio::TxClient>,
     config_client: OptionalCell<&'a dyn radio::ConfigClient>,
     power_client: OptionalCell<&'a dyn radio::PowerClient>,
+    ed_client: OptionalCell<&'a dyn EnergyDetectClient>,
     tx_power: Cell<TxPower>,
     tx_buf: TakeCell<'static, [u8]>,
     rx_buf: TakeCell<'static, [u8]>,
@@ -701,6 +776,9 @@ pub struct Radio<'a> {
     cca_be: Cell<u8>,
     random_nonce: Cell<u32>,
     channel: Cell<RadioChannel>,
+    /// Channel of the energy detect measurement, and its level once done.
+    ed_channel: Cell<RadioChannel>,
+    ed_level: OptionalCell<u8>,
     timer0: OptionalCell<&'a TimerAlarm<'a>>,
     state: Cell<RadioState>,
     deferred_call: DeferredCall,
@@ -723,6 +801,7 @@ impl<'a> Radio<'a> {
             tx_client: OptionalCell::empty(),
             config_client: OptionalCell::empty(),
             power_client: OptionalCell::empty(),
+            ed_client: OptionalCell::empty(),
             tx_power: Cell::new(TxPower::ZerodBm),
             tx_buf: TakeCell::empty(),
             rx_buf: TakeCell::empty(),
@@ -734,6 +813,8 @@ impl<'a> Radio<'a> {
             cca_be: Cell::new(0),
             random_nonce: Cell::new(0xDEADBEEF),
             channel: Cell::new(RadioChannel::Channel26),
+            ed_channel: Cell::new(RadioChannel::Channel26),
+            ed_level: OptionalCell::empty(),
             timer0: OptionalCell::empty(),
             state: Cell::new(RadioState::OFF),
             deferred_call: DeferredCall::new(),
@@ -745,6 +826,52 @@ impl<'a> Radio<'a> {
         self.timer0.set(timer);
     }
 
+    pub fn set_energy_detect_client(&self, client: &'a dyn EnergyDetectClient) {
+        self.ed_client.set(client);
+    }
+
+    /// Measures the peak energy on `channel` over `iterations` periods of
+    /// 128 us, then listens on the configured channel again and passes the
+    /// level to the `EnergyDetectClient`. The radio receives no frames while
+    /// it measures.
+    ///
+    /// Returns `OFF` if the radio is off, `BUSY` while it transmits or
+    /// measures, and `INVAL` if `iterations` is 0 or more than
+    /// `MAX_ED_ITERATIONS`.
+    pub fn energy_detect(&self, channel: RadioChannel, iterations: u32) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            RadioState::OFF => return Err(ErrorCode::OFF),
+            RadioState::RX if self.tx_buf.is_none() => {}
+            _ => return Err(ErrorCode::BUSY),
+        }
+        if iterations == 0 || iterations > MAX_ED_ITERATIONS {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.disable_all_interrupts();
+        self.state.set(RadioState::ED);
+        self.ed_channel.set(channel);
+        self.registers.event_ready.write(Event::READY::CLEAR);
+        self.registers.event_disabled.write(Event::READY::CLEAR);
+        self.registers.event_edend.write(Event::READY::CLEAR);
+        self.registers
+            .edcnt
+            .write(EnergyDetectCount::EDCNT.val(iterations - 1));
+        self.registers
+            .frequency
+            .write(Frequency::FREQUENCY.val(channel as u32));
+
+        // Ramp the receiver up again on the channel, and start measuring once
+        // it is ready.
+        self.registers
+            .shorts
+            .write(Shortcut::DISABLED_RXEN::SET + Shortcut::READY_EDSTART::SET);
+        self.enable_interrupts();
+        self.enable_ed_interrupts();
+        self.registers.task_disable.write(Task::ENABLE::SET);
+        Ok(())
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.registers
             .mode
@@ -797,6 +924,61 @@ impl<'a> Radio<'a> {
         }
     }
 
+    /// Whether a received frame requests an ACK from this device. The radio
+    /// receives every frame on the channel, and only frames that are sent to
+    /// the PAN and the short or long address of this device may be
+    /// acknowledged, never broadcast frames. A frame whose destination PAN is
+    /// the broadcast PAN still belongs to this PAN.
+    ///
+    /// 2015 frames without a sequence number need an enhanced ACK, which the
+    /// driver does not send.
+    fn ack_requested(&self, frame: &[u8]) -> bool {
+        if frame.len() < radio::MHR_FC_SIZE + SEQ_NUM_LEN || frame[0] & ACK_FLAG == 0 {
+            return false;
+        }
+        let fcf = u16::from_le_bytes([frame[0], frame[1]]);
+        let dst_mode = (fcf >> FCF_DST_MODE_POS) & 0b11;
+        let src_mode = (fcf >> FCF_SRC_MODE_POS) & 0b11;
+        let version = (fcf >> FCF_VERSION_POS) & 0b11;
+        let pan_id_compression = fcf & FCF_PAN_ID_COMPRESSION != 0;
+        if dst_mode == ADDR_MODE_NONE
+            || (version == FRAME_VERSION_2015 && fcf & FCF_SEQ_SUPPRESSED != 0)
+        {
+            return false;
+        }
+
+        // Before 2015 the destination PAN comes with every destination
+        // address. 2015 frames may compress it away, as in table 7-2 of
+        // IEEE 802.15.4-2015, which implies the PAN of this device.
+        let dst_pan_present = version != FRAME_VERSION_2015
+            || !pan_id_compression
+            || (src_mode != ADDR_MODE_NONE
+                && !(dst_mode == ADDR_MODE_LONG && src_mode == ADDR_MODE_LONG));
+        let mut offset = radio::MHR_FC_SIZE + SEQ_NUM_LEN;
+        if dst_pan_present {
+            let Some(pan) = frame.get(offset..offset + 2) else {
+                return false;
+            };
+            let pan = u16::from_le_bytes([pan[0], pan[1]]);
+            if pan != self.pan.get() && pan != BROADCAST_PAN {
+                return false;
+            }
+            offset += 2;
+        }
+
+        match dst_mode {
+            ADDR_MODE_SHORT => frame
+                .get(offset..offset + 2)
+                .is_some_and(|addr| u16::from_le_bytes([addr[0], addr[1]]) == self.addr.get()),
+            // Long addresses are sent least significant byte first, and kept
+            // most significant byte first.
+            ADDR_MODE_LONG => frame
+                .get(offset..offset + 8)
+                .is_some_and(|addr| addr.iter().eq(self.addr_long.get().iter().rev())),
+            _ => false,
+        }
+    }
+
     // TODO: RECEIVING ACK FOR A SENT TX IS NOT IMPLEMENTED
     //
     // As a general note for the interrupt handler, event registers must still
@@ -807,6 +989,7 @@ impl<'a> Radio<'a> {
 
         let mut start_task = false;
         let mut rx_init = false;
+        let mut ed_level = None;
 
         match self.state.get() {
             // It should not be possible to receive an interrupt while the
@@ -867,7 +1050,11 @@ impl<'a> Radio<'a> {
                     // non deterministic time required to complete the function
                     // as it may be passed up the entirety of the networking
                     // stack (leading to ACK timeout being exceeded).
-                    if rbuf[radio::PSDU_OFFSET] & ACK_FLAG != 0 && crc.is_ok() {
+                    if crc.is_ok()
+                        && self.ack_requested(
+                            &rbuf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len],
+                        )
+                    {
                         self.ack_buf
                             .take()
                             .map_or(Err(ErrorCode::NOMEM), |ack_buf| {
@@ -1010,6 +1197,27 @@ impl<'a> Radio<'a> {
                     rx_init = true;
                 }
             }
+            RadioState::ED => {
+                // The READY_EDSTART shortcut started the measurement.
+                self.registers.event_ready.write(Event::READY::CLEAR);
+                let disabled = self.registers.event_disabled.is_set(Event::READY);
+                self.registers.event_disabled.write(Event::READY::CLEAR);
+
+                if self.registers.event_edend.is_set(Event::READY) {
+                    self.registers.event_edend.write(Event::READY::CLEAR);
+                    let level = self.registers.edsample.read(EnergyDetectSample::EDLVL) as u8;
+                    self.ed_level.set(level.saturating_mul(ED_RSSISCALE));
+
+                    // Turn the receiver off, to turn it on again on the
+                    // configured channel once it is disabled.
+                    self.registers.shorts.set(0);
+                    self.registers.task_disable.write(Task::ENABLE::SET);
+                } else if disabled && self.ed_level.is_some() {
+                    self.ieee802154_set_channel_freq();
+                    rx_init = true;
+                    ed_level = self.ed_level.take();
+                }
+            }
             RadioState::ACK => {
                 ////////////////////////////////////////////////////////////////
                 // NOTE: This state machine assumes that the READY_START
@@ -1071,6 +1279,20 @@ impl<'a> Radio<'a> {
         if start_task {
             self.registers.task_start.write(Task::ENABLE::SET);
         }
+        if self.state.get() == RadioState::ED {
+            self.enable_ed_interrupts();
+        }
+        if let Some(level) = ed_level {
+            self.ed_client.map(|client| {
+                client.energy_detected(self.ed_channel.get(), level);
+            });
+        }
+    }
+
+    fn enable_ed_interrupts(&self) {
+        self.registers
+            .intenset
+            .write(Interrupt::EDEND::SET + Interrupt::DISABLED::SET);
     }
 
     pub fn enable_interrupts(&self) {
@@ -1255,8 +1477,9 @@ impl<'a> kernel::hil::radio::RadioConfig<'a> for Radio<'a> {
     /// to the specified values. **However**, the nRF52840 IEEE 802.15.4 radio
     /// does not support hardware-level address filtering (see
     /// [here](https://devzone.nordicsemi.com/f/nordic-q-a/19320/using-nrf52840-for-802-15-4)).
-    /// So setting the addresses and PAN ID have no meaning for this chip and
-    /// any filtering must be done in higher layers in software.
+    /// So the addresses and PAN ID only decide which received frames the
+    /// driver acknowledges, and any filtering must be done in higher layers in
+    /// software.
     ///
     /// Issues a callback to the config client when done.
     fn config_commit(&self) {
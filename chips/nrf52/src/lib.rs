@@ -4,6 +4,7 @@
 
 #![no_std]
 
+pub mod acl;
 pub mod acomp;
 pub mod adc;
 pub mod approtect;
@@ -11,6 +12,7 @@ pub mod ble_radio;
 pub mod chip;
 pub mod clock;
 pub mod crt1;
+pub mod easydma;
 pub mod ficr;
 pub mod i2c;
 pub mod ieee802154_radio;
@@ -19,9 +21,11 @@ pub mod power;
 pub mod ppi;
 pub mod pwm;
 pub mod spi;
+pub mod spis;
 pub mod uart;
 pub mod uicr;
 pub mod usbd;
+pub mod wdt;
 
 pub use crate::crt1::init;
 pub use nrf5x::{
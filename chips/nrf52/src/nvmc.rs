@@ -18,6 +18,8 @@ use kernel::utilities::registers::{register_bitfields, ReadOnly, ReadWrite};
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 
+use crate::acl::Acl;
+
 const NVMC_BASE: StaticRef<NvmcRegisters> =
     unsafe { StaticRef::new(0x4001E400 as *const NvmcRegisters) };
 
@@ -202,6 +204,7 @@ pub enum FlashState {
 
 pub struct Nvmc {
     registers: StaticRef<NvmcRegisters>,
+    acl: OptionalCell<&'static Acl>,
     client: OptionalCell<&'static dyn hil::flash::Client<Nvmc>>,
     buffer: TakeCell<'static, NrfPage>,
     state: Cell<FlashState>,
@@ -212,6 +215,7 @@ impl Nvmc {
     pub fn new() -> Self {
         Self {
             registers: NVMC_BASE,
+            acl: OptionalCell::empty(),
             client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
             state: Cell::new(FlashState::Ready),
@@ -219,6 +223,18 @@ impl Nvmc {
         }
     }
 
+    /// Use `acl` to reject writes and erases of protected pages before they
+    /// reach the hardware, which would block them.
+    pub fn set_acl(&self, acl: &'static Acl) {
+        self.acl.set(acl);
+    }
+
+    fn is_page_protected(&self, page_number: usize) -> bool {
+        self.acl.map_or(false, |acl| {
+            acl.is_write_protected(page_number * PAGE_SIZE, PAGE_SIZE)
+        })
+    }
+
     /// Configure the NVMC to allow writes to flash.
     pub fn configure_writeable(&self) {
         self.registers.config.write(Configuration::WEN::Wen);
@@ -325,6 +341,10 @@ impl Nvmc {
         page_number: usize,
         data: &'static mut NrfPage,
     ) -> Result<(), (ErrorCode, &'static mut NrfPage)> {
+        if self.is_page_protected(page_number) {
+            return Err((ErrorCode::INVAL, data));
+        }
+
         // Need to erase the page if the page is not filled
         // with 0xFF.
         if !self.is_page_blank(page_number) {
@@ -362,6 +382,10 @@ impl Nvmc {
     }
 
     fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        if self.is_page_protected(page_number) {
+            return Err(ErrorCode::INVAL);
+        }
+
         // Do the basic erase.
         if !self.is_page_blank(page_number) {
             self.erase_page_helper(page_number);
@@ -369,4 +369,30 @@ impl<'a> Power<'a> {
     pub fn set_gpregret(&self, val: u8) {
         self.registers.gpregret.write(Byte::VALUE.val(val as u32));
     }
+
+    /// Return the contents of the second general purpose retention register,
+    /// GPREGRET2, which like GPREGRET preserves its state across a soft or
+    /// watchdog reset.
+    pub fn get_gpregret2(&self) -> u8 {
+        self.registers.gpregret2.read(Byte::VALUE) as u8
+    }
+
+    /// Set the value of the second general purpose retention register,
+    /// GPREGRET2.
+    pub fn set_gpregret2(&self, val: u8) {
+        self.registers.gpregret2.write(Byte::VALUE.val(val as u32));
+    }
+
+    /// Return whether the watchdog caused a reset since the reset reasons
+    /// were last cleared.
+    pub fn is_watchdog_reset(&self) -> bool {
+        self.registers.resetreas.is_set(ResetReason::DOG)
+    }
+
+    /// Clear the reset reasons. The chip accumulates them until they are
+    /// cleared, so a later reset reports only its own reason.
+    pub fn clear_reset_reasons(&self) {
+        // The register bits are cleared by writing 1 to them.
+        self.registers.resetreas.set(self.registers.resetreas.get());
+    }
 }
@@ -37,77 +37,47 @@
 //! * Francine Mäkelä
 //! * Date: May 04, 2018
 
-use kernel::utilities::registers::interfaces::Writeable;
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, FieldValue, ReadWrite};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+/// Number of channels whose end points can be configured. Channels 20 to 31
+/// are pre-programmed.
+pub const NUM_CONFIGURABLE_CHANNELS: usize = 20;
+
+/// Number of channel groups.
+pub const NUM_GROUPS: usize = 6;
 
 const PPI_BASE: StaticRef<PpiRegisters> =
     unsafe { StaticRef::new(0x4001F000 as *const PpiRegisters) };
 
 #[repr(C)]
 struct PpiRegisters {
-    tasks_chg0_en: ReadWrite<u32, Control::Register>,
-    tasks_chg0_dis: ReadWrite<u32, Control::Register>,
-    tasks_chg1_en: ReadWrite<u32, Control::Register>,
-    tasks_chg1_dis: ReadWrite<u32, Control::Register>,
-    tasks_chg2_en: ReadWrite<u32, Control::Register>,
-    tasks_chg2_dis: ReadWrite<u32, Control::Register>,
-    tasks_chg3_en: ReadWrite<u32, Control::Register>,
-    tasks_chg3_dis: ReadWrite<u32, Control::Register>,
-    tasks_chg4_en: ReadWrite<u32, Control::Register>,
-    tasks_chg4_dis: ReadWrite<u32, Control::Register>,
-    tasks_chg5_en: ReadWrite<u32, Control::Register>,
-    tasks_chg5_dis: ReadWrite<u32, Control::Register>,
+    tasks_chg: [GroupTasks; NUM_GROUPS],
     _reserved1: [u32; 308],
     chen: ReadWrite<u32, Channel::Register>,
     chenset: ReadWrite<u32, Channel::Register>,
     chenclr: ReadWrite<u32, Channel::Register>,
-    ch0_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch0_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch1_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch1_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch2_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch2_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch3_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch3_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch4_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch4_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch5_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch5_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch6_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch6_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch7_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch7_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch8_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch8_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch9_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch9_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch10_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch10_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch11_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch11_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch12_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch12_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch13_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch13_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch14_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch14_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch15_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch15_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch16_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch16_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch17_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch17_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch18_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch18_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch19_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch19_tep: ReadWrite<u32, TaskEndPoint::Register>,
+    ch: [ChannelEndPoints; NUM_CONFIGURABLE_CHANNELS],
     _reserved2: [u32; 148],
-    chg: [ReadWrite<u32, Channel::Register>; 6],
+    chg: [ReadWrite<u32, Channel::Register>; NUM_GROUPS],
     _reserved3: [u32; 62],
     fork_tep: [ReadWrite<u32, TaskEndPoint::Register>; 32],
 }
 
+#[repr(C)]
+struct GroupTasks {
+    en: ReadWrite<u32, Control::Register>,
+    dis: ReadWrite<u32, Control::Register>,
+}
+
+#[repr(C)]
+struct ChannelEndPoints {
+    eep: ReadWrite<u32, EventEndPoint::Register>,
+    tep: ReadWrite<u32, TaskEndPoint::Register>,
+}
+
 register_bitfields! [u32,
     Control [
         ENABLE OFFSET(0) NUMBITS(1)
@@ -172,4 +142,63 @@ impl Ppi {
     pub fn disable(&self, channels: FieldValue<u32, Channel::Register>) {
         self.registers.chenclr.write(channels);
     }
+
+    /// Returns whether `channel` is enabled.
+    pub fn is_enabled(&self, channel: usize) -> bool {
+        channel < 32 && self.registers.chen.get() & (1 << channel) != 0
+    }
+
+    /// Connects the event register at address `event` to the task register
+    /// at address `task` through `channel`, and clears the channel's fork.
+    /// The channel must be enabled separately.
+    pub fn configure_channel(
+        &self,
+        channel: usize,
+        event: usize,
+        task: usize,
+    ) -> Result<(), ErrorCode> {
+        let end_points = self.registers.ch.get(channel).ok_or(ErrorCode::INVAL)?;
+        end_points
+            .eep
+            .write(EventEndPoint::ADDRESS.val(event as u32));
+        end_points.tep.write(TaskEndPoint::ADDRESS.val(task as u32));
+        self.registers.fork_tep[channel].set(0);
+        Ok(())
+    }
+
+    /// Makes `channels` the members of channel group `group`.
+    pub fn set_group(
+        &self,
+        group: usize,
+        channels: FieldValue<u32, Channel::Register>,
+    ) -> Result<(), ErrorCode> {
+        self.registers
+            .chg
+            .get(group)
+            .ok_or(ErrorCode::INVAL)?
+            .write(channels);
+        Ok(())
+    }
+
+    /// Enables all channels in channel group `group`.
+    pub fn enable_group(&self, group: usize) -> Result<(), ErrorCode> {
+        self.registers
+            .tasks_chg
+            .get(group)
+            .ok_or(ErrorCode::INVAL)?
+            .en
+            .write(Control::ENABLE::SET);
+        Ok(())
+    }
+
+    /// Disables all channels in channel group `group`.
+    pub fn disable_group(&self, group: usize) -> Result<(), ErrorCode> {
+        self.registers
+            .tasks_chg
+            .get(group)
+            .ok_or(ErrorCode::INVAL)?
+            .dis
+            .write(Control::ENABLE::SET);
+        Ok(())
+    }
 }
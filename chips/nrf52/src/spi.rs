@@ -5,7 +5,7 @@
 //! Implementation of SPI for NRF52 using EasyDMA.
 //!
 //! This file only implements support for the three SPI master (`SPIM`)
-//! peripherals, and not SPI slave (`SPIS`).
+//! peripherals. SPI slave (`SPIS`) is in `spis`.
 //!
 //! Although `kernel::hil::spi::SpiMaster` is implemented for `SPIM`,
 //! only the functions marked with `x` are fully defined:
@@ -24,8 +24,8 @@
 //! * ✓ get_polarity
 //! * ✓ set_phase
 //! * ✓ get_phase
-//! * hold_low
-//! * release_low
+//! * ✓ hold_low
+//! * ✓ release_low
 //!
 //! Author
 //! -------------------
@@ -46,6 +46,8 @@ use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 use nrf5x::pinmux::Pinmux;
 
+use crate::easydma;
+
 const INSTANCES: [StaticRef<SpimRegisters>; 3] = unsafe {
     [
         StaticRef::new(0x40003000 as *const SpimRegisters),
@@ -245,6 +247,8 @@ pub struct SPIM<'a> {
     client: OptionalCell<&'a dyn hil::spi::SpiMasterClient>,
     chip_select: OptionalCell<ChipSelectPolar<'a, crate::gpio::GPIOPin<'a>>>,
     busy: Cell<bool>,
+    /// Keep chip select active after a transfer completes.
+    hold_low: Cell<bool>,
     tx_buf: MapCell<SubSliceMut<'static, u8>>,
     rx_buf: MapCell<SubSliceMut<'static, u8>>,
     transfer_len: Cell<usize>,
@@ -257,6 +261,7 @@ impl<'a> SPIM<'a> {
             client: OptionalCell::empty(),
             chip_select: OptionalCell::empty(),
             busy: Cell::new(false),
+            hold_low: Cell::new(false),
             tx_buf: MapCell::empty(),
             rx_buf: MapCell::empty(),
             transfer_len: Cell::new(0),
@@ -273,7 +278,9 @@ impl<'a> SPIM<'a> {
                 return;
             }
 
-            self.chip_select.map(|cs| cs.deactivate());
+            if !self.hold_low.get() {
+                self.chip_select.map(|cs| cs.deactivate());
+            }
             self.registers.events_end.write(EVENT::EVENT::CLEAR);
 
             // When we are no longer active or busy we can disable the
@@ -371,6 +378,14 @@ impl<'a> hil::spi::SpiMaster<'a> for SPIM<'a> {
         if self.chip_select.is_none() {
             return Err((ErrorCode::NODEVICE, tx_buf, rx_buf));
         }
+        let dma_check = easydma::check(tx_buf.as_ptr(), tx_buf.len()).and(
+            rx_buf
+                .as_ref()
+                .map_or(Ok(()), |buf| easydma::check(buf.as_ptr(), buf.len())),
+        );
+        if let Err(error) = dma_check {
+            return Err((error, tx_buf, rx_buf));
+        }
         self.chip_select.map(|cs| cs.activate());
 
         // Setup transmit data registers
@@ -478,14 +493,13 @@ impl<'a> hil::spi::SpiMaster<'a> for SPIM<'a> {
         }
     }
 
-    // The following two trait functions are not implemented for
-    // SAM4L, and appear to not provide much functionality. Let's not
-    // bother implementing them unless needed.
+    // Chip select is driven in software, so holding it low only means not
+    // deactivating it when a transfer ends.
     fn hold_low(&self) {
-        unimplemented!("SPI: Use `read_write_bytes()` instead.");
+        self.hold_low.set(true);
     }
 
     fn release_low(&self) {
-        unimplemented!("SPI: Use `read_write_bytes()` instead.");
+        self.hold_low.set(false);
     }
 }
@@ -0,0 +1,356 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Implementation of the SPI slave (`SPIS`) for nRF52, using EasyDMA.
+//!
+//! The CPU and the SPIS share the buffers of a transaction through a
+//! semaphore. `read_write_bytes()` points the SPIS at the buffers while the
+//! CPU holds the semaphore, and then releases it. The SPIS serves the next
+//! transaction the master selects it for with the buffers, and gives the
+//! semaphore back to the CPU when the chip select goes high, which ends the
+//! operation. A transaction while the CPU holds the semaphore, with no
+//! buffers ready, is ignored, and the master reads the byte set with
+//! `set_write_byte()`.
+//!
+//! A master that clocks more bytes than the buffers hold reads the byte set
+//! with `set_write_byte()` for the rest, and the bytes it writes past the
+//! read buffer are lost. The operation then ends with `Err(ErrorCode::SIZE)`,
+//! with the bytes that fit in the buffers.
+//!
+//! The SPIS has no event for the chip select going low, so the client's
+//! `chip_selected()` is never called.
+
+use core::cell::Cell;
+use kernel::hil;
+use kernel::utilities::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
+use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite, WriteOnly};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use nrf5x::pinmux::Pinmux;
+
+use crate::easydma;
+
+const INSTANCES: [StaticRef<SpisRegisters>; 3] = unsafe {
+    [
+        StaticRef::new(0x40003000 as *const SpisRegisters),
+        StaticRef::new(0x40004000 as *const SpisRegisters),
+        StaticRef::new(0x40023000 as *const SpisRegisters),
+    ]
+};
+
+register_structs! {
+    SpisRegisters {
+        (0x000 => _reserved0),
+        (0x024 => tasks_acquire: WriteOnly<u32, TASK::Register>),
+        (0x028 => tasks_release: WriteOnly<u32, TASK::Register>),
+        (0x02C => _reserved1),
+        (0x104 => events_end: ReadWrite<u32, EVENT::Register>),
+        (0x108 => _reserved2),
+        (0x128 => events_acquired: ReadWrite<u32, EVENT::Register>),
+        (0x12C => _reserved3),
+        (0x200 => shorts: ReadWrite<u32, SHORTS::Register>),
+        (0x204 => _reserved4),
+        (0x304 => intenset: ReadWrite<u32, INTE::Register>),
+        (0x308 => intenclr: ReadWrite<u32, INTE::Register>),
+        (0x30C => _reserved5),
+        (0x400 => semstat: ReadWrite<u32, SEMSTAT::Register>),
+        (0x404 => _reserved6),
+        (0x440 => status: ReadWrite<u32, STATUS::Register>),
+        (0x444 => _reserved7),
+        (0x500 => enable: ReadWrite<u32, ENABLE::Register>),
+        (0x504 => _reserved8),
+        (0x508 => psel_sck: VolatileCell<Pinmux>),
+        (0x50C => psel_miso: VolatileCell<Pinmux>),
+        (0x510 => psel_mosi: VolatileCell<Pinmux>),
+        (0x514 => psel_csn: VolatileCell<Pinmux>),
+        (0x518 => _reserved9),
+        (0x534 => rxd_ptr: ReadWrite<u32>),
+        (0x538 => rxd_maxcnt: ReadWrite<u32, MAXCNT::Register>),
+        (0x53C => rxd_amount: ReadWrite<u32, MAXCNT::Register>),
+        (0x540 => _reserved10),
+        (0x544 => txd_ptr: ReadWrite<u32>),
+        (0x548 => txd_maxcnt: ReadWrite<u32, MAXCNT::Register>),
+        (0x54C => txd_amount: ReadWrite<u32, MAXCNT::Register>),
+        (0x550 => _reserved11),
+        (0x554 => config: ReadWrite<u32, CONFIG::Register>),
+        (0x558 => _reserved12),
+        (0x55C => def: ReadWrite<u32>),
+        (0x560 => _reserved13),
+        (0x5C0 => orc: ReadWrite<u32>),
+        (0x5C4 => @END),
+    }
+}
+
+register_bitfields![u32,
+    SHORTS [
+        /// Acquire the semaphore for the CPU at the end of a transaction
+        END_ACQUIRE 2
+    ],
+    INTE [
+        END 1,
+        ACQUIRED 10
+    ],
+    SEMSTAT [
+        SEMSTAT OFFSET(0) NUMBITS(2) [
+            Free = 0,
+            Cpu = 1,
+            Spis = 2,
+            CpuPending = 3
+        ]
+    ],
+    STATUS [
+        /// The master read more bytes than the transmit buffer holds
+        OVERREAD 0,
+        /// The master wrote more bytes than the receive buffer holds
+        OVERFLOW 1
+    ],
+    MAXCNT [
+        MAXCNT OFFSET(0) NUMBITS(16)
+    ],
+    CONFIG [
+        ORDER OFFSET(0) NUMBITS(1) [
+            MsbFirst = 0,
+            LsbFirst = 1
+        ],
+        CPHA OFFSET(1) NUMBITS(1) [
+            Leading = 0,
+            Trailing = 1
+        ],
+        CPOL OFFSET(2) NUMBITS(1) [
+            ActiveHigh = 0,
+            ActiveLow = 1
+        ]
+    ],
+    ENABLE [
+        ENABLE OFFSET(0) NUMBITS(4) [
+            Disable = 0,
+            Enable = 2
+        ]
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    TASK [
+        TASK 0
+    ]
+];
+
+/// A SPI slave device.
+pub struct SPIS<'a> {
+    registers: StaticRef<SpisRegisters>,
+    client: OptionalCell<&'a dyn hil::spi::SpiSlaveClient>,
+    write_buf: TakeCell<'static, [u8]>,
+    read_buf: TakeCell<'static, [u8]>,
+    /// Whether buffers were handed over and the operation has not ended.
+    busy: Cell<bool>,
+    /// Whether the SPIS has the buffers, rather than waiting for the CPU to
+    /// acquire the semaphore to hand them over.
+    armed: Cell<bool>,
+    len: Cell<usize>,
+}
+
+impl<'a> SPIS<'a> {
+    pub const fn new(instance: usize) -> SPIS<'a> {
+        SPIS {
+            registers: INSTANCES[instance],
+            client: OptionalCell::empty(),
+            write_buf: TakeCell::empty(),
+            read_buf: TakeCell::empty(),
+            busy: Cell::new(false),
+            armed: Cell::new(false),
+            len: Cell::new(0),
+        }
+    }
+
+    /// Configures an already constructed `SPIS`.
+    pub fn configure(&self, mosi: Pinmux, miso: Pinmux, sck: Pinmux, csn: Pinmux) {
+        self.registers.psel_mosi.set(mosi);
+        self.registers.psel_miso.set(miso);
+        self.registers.psel_sck.set(sck);
+        self.registers.psel_csn.set(csn);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.registers.enable.matches_all(ENABLE::ENABLE::Enable)
+    }
+
+    /// Turns the SPIS off, and clears the shortcut, which the SPIM and TWI of
+    /// the instance share.
+    fn disable(&self) {
+        self.registers.intenclr.set(0xFFFF_FFFF);
+        self.registers.shorts.set(0);
+        self.registers.enable.write(ENABLE::ENABLE::Disable);
+    }
+
+    /// Points the SPIS at the buffers and hands them over. The CPU must hold
+    /// the semaphore.
+    fn arm(&self) {
+        let len = self.len.get();
+        // A side without a buffer transfers no bytes.
+        self.registers.txd_maxcnt.set(0);
+        self.registers.rxd_maxcnt.set(0);
+        self.write_buf.map(|buf| {
+            self.registers.txd_ptr.set(buf.as_ptr() as u32);
+            self.registers
+                .txd_maxcnt
+                .write(MAXCNT::MAXCNT.val(buf.len().min(len) as u32));
+        });
+        self.read_buf.map(|buf| {
+            self.registers.rxd_ptr.set(buf.as_mut_ptr() as u32);
+            self.registers
+                .rxd_maxcnt
+                .write(MAXCNT::MAXCNT.val(buf.len().min(len) as u32));
+        });
+        self.registers
+            .status
+            .write(STATUS::OVERREAD::SET + STATUS::OVERFLOW::SET);
+        self.armed.set(true);
+        self.registers.tasks_release.write(TASK::TASK::SET);
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.registers.events_acquired.is_set(EVENT::EVENT) {
+            self.registers.events_acquired.write(EVENT::EVENT::CLEAR);
+            if self.busy.get() && !self.armed.get() {
+                self.arm();
+            }
+        }
+
+        if self.registers.events_end.is_set(EVENT::EVENT) {
+            // The shortcut gave the semaphore back to the CPU.
+            self.registers.events_end.write(EVENT::EVENT::CLEAR);
+            if !self.armed.replace(false) {
+                return;
+            }
+            self.busy.set(false);
+            let len = self
+                .registers
+                .rxd_amount
+                .get()
+                .max(self.registers.txd_amount.get()) as usize;
+            let status = if self.registers.status.get() != 0 {
+                Err(ErrorCode::SIZE)
+            } else {
+                Ok(())
+            };
+            self.client.map(|client| {
+                client.read_write_done(self.write_buf.take(), self.read_buf.take(), len, status)
+            });
+        }
+    }
+}
+
+impl<'a> hil::spi::SpiSlave<'a> for SPIS<'a> {
+    fn init(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.registers.shorts.write(SHORTS::END_ACQUIRE::SET);
+        self.registers
+            .intenset
+            .write(INTE::END::SET + INTE::ACQUIRED::SET);
+        self.registers.enable.write(ENABLE::ENABLE::Enable);
+        // Transactions are ignored until buffers are handed over.
+        self.registers.tasks_acquire.write(TASK::TASK::SET);
+        Ok(())
+    }
+
+    fn has_client(&self) -> bool {
+        self.client.is_some()
+    }
+
+    fn set_client(&self, client: Option<&'a dyn hil::spi::SpiSlaveClient>) {
+        match client {
+            Some(client) => self.client.set(client),
+            None => {
+                self.client.clear();
+                self.disable();
+            }
+        }
+    }
+
+    fn set_write_byte(&self, write_byte: u8) {
+        self.registers.def.set(write_byte as u32);
+        self.registers.orc.set(write_byte as u32);
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: Option<&'static mut [u8]>,
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            Option<&'static mut [u8]>,
+            Option<&'static mut [u8]>,
+        ),
+    > {
+        if self.busy.get() {
+            return Err((ErrorCode::BUSY, write_buffer, read_buffer));
+        }
+        if len == 0 {
+            return Err((ErrorCode::INVAL, write_buffer, read_buffer));
+        }
+        let dma_check = write_buffer
+            .as_ref()
+            .map_or(Ok(()), |buf| easydma::check(buf.as_ptr(), buf.len()))
+            .and(
+                read_buffer
+                    .as_ref()
+                    .map_or(Ok(()), |buf| easydma::check(buf.as_ptr(), buf.len())),
+            );
+        if let Err(error) = dma_check {
+            return Err((error, write_buffer, read_buffer));
+        }
+        write_buffer.map(|buf| self.write_buf.replace(buf));
+        read_buffer.map(|buf| self.read_buf.replace(buf));
+        self.len.set(len);
+        self.busy.set(true);
+        if self.registers.semstat.matches_all(SEMSTAT::SEMSTAT::Cpu) {
+            self.arm();
+        } else {
+            self.registers.tasks_acquire.write(TASK::TASK::SET);
+        }
+        Ok(())
+    }
+
+    fn set_polarity(&self, polarity: hil::spi::ClockPolarity) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.registers.config.modify(match polarity {
+            hil::spi::ClockPolarity::IdleLow => CONFIG::CPOL::ActiveHigh,
+            hil::spi::ClockPolarity::IdleHigh => CONFIG::CPOL::ActiveLow,
+        });
+        Ok(())
+    }
+
+    fn get_polarity(&self) -> hil::spi::ClockPolarity {
+        match self.registers.config.read(CONFIG::CPOL) {
+            0 => hil::spi::ClockPolarity::IdleLow,
+            _ => hil::spi::ClockPolarity::IdleHigh,
+        }
+    }
+
+    fn set_phase(&self, phase: hil::spi::ClockPhase) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.registers.config.modify(match phase {
+            hil::spi::ClockPhase::SampleLeading => CONFIG::CPHA::Leading,
+            hil::spi::ClockPhase::SampleTrailing => CONFIG::CPHA::Trailing,
+        });
+        Ok(())
+    }
+
+    fn get_phase(&self) -> hil::spi::ClockPhase {
+        match self.registers.config.read(CONFIG::CPHA) {
+            0 => hil::spi::ClockPhase::SampleLeading,
+            _ => hil::spi::ClockPhase::SampleTrailing,
+        }
+    }
+}
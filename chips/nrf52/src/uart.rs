@@ -20,6 +20,8 @@ use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 use nrf5x::pinmux;
 
+use crate::easydma;
+
 const UARTE_MAX_BUFFER_SIZE: u32 = 0xff;
 
 static mut BYTE: u8 = 0;
@@ -27,6 +29,10 @@ static mut BYTE: u8 = 0;
 pub const UARTE0_BASE: StaticRef<UarteRegisters> =
     unsafe { StaticRef::new(0x40002000 as *const UarteRegisters) };
 
+/// The second UARTE, which only the nRF52833 and nRF52840 have.
+pub const UARTE1_BASE: StaticRef<UarteRegisters> =
+    unsafe { StaticRef::new(0x40028000 as *const UarteRegisters) };
+
 #[repr(C)]
 pub struct UarteRegisters {
     task_startrx: WriteOnly<u32, Task::Register>,
@@ -172,7 +178,10 @@ pub struct Uarte<'a> {
     rx_buffer: kernel::utilities::cells::TakeCell<'static, [u8]>,
     rx_remaining_bytes: Cell<usize>,
     rx_abort_in_progress: Cell<bool>,
-    offset: Cell<usize>,
+    // Transmit and receive run at the same time, so each keeps its own
+    // position in its buffer.
+    tx_offset: Cell<usize>,
+    rx_offset: Cell<usize>,
 }
 
 #[derive(Copy, Clone)]
@@ -194,7 +203,8 @@ impl<'a> Uarte<'a> {
             rx_buffer: kernel::utilities::cells::TakeCell::empty(),
             rx_remaining_bytes: Cell::new(0),
             rx_abort_in_progress: Cell::new(false),
-            offset: Cell::new(0),
+            tx_offset: Cell::new(0),
+            rx_offset: Cell::new(0),
         }
     }
 
@@ -275,8 +285,8 @@ impl<'a> Uarte<'a> {
         self.registers.enable.write(Uart::ENABLE::ON);
     }
 
-    #[allow(dead_code)]
-    fn disable_uart(&self) {
+    /// Turns the UARTE off. `initialize` turns it back on.
+    pub fn disable_uart(&self) {
         self.registers.enable.write(Uart::ENABLE::OFF);
     }
 
@@ -319,7 +329,7 @@ impl<'a> Uarte<'a> {
                 });
             } else {
                 // Not all bytes have been transmitted then update offset and continue transmitting
-                self.offset.set(self.offset.get() + tx_bytes);
+                self.tx_offset.set(self.tx_offset.get() + tx_bytes);
                 self.tx_remaining_bytes.set(rem);
                 self.set_tx_dma_pointer_to_buffer();
                 self.registers
@@ -347,7 +357,7 @@ impl<'a> Uarte<'a> {
                     self.rx_buffer.take().map(|rx_buffer| {
                         client.received_buffer(
                             rx_buffer,
-                            self.offset.get() + rx_bytes,
+                            self.rx_offset.get() + rx_bytes,
                             Err(ErrorCode::CANCEL),
                             uart::Error::None,
                         );
@@ -361,7 +371,7 @@ impl<'a> Uarte<'a> {
                 // where we are storing in the buffer.
                 self.rx_remaining_bytes
                     .set(self.rx_remaining_bytes.get().saturating_sub(rx_bytes));
-                self.offset.set(self.offset.get() + rx_bytes);
+                self.rx_offset.set(self.rx_offset.get() + rx_bytes);
 
                 let rem = self.rx_remaining_bytes.get();
                 if rem == 0 {
@@ -370,7 +380,7 @@ impl<'a> Uarte<'a> {
                         self.rx_buffer.take().map(|rx_buffer| {
                             client.received_buffer(
                                 rx_buffer,
-                                self.offset.get(),
+                                self.rx_offset.get(),
                                 Ok(()),
                                 uart::Error::None,
                             );
@@ -419,7 +429,7 @@ impl<'a> Uarte<'a> {
         self.tx_buffer.map(|tx_buffer| {
             self.registers
                 .txd_ptr
-                .set(tx_buffer[self.offset.get()..].as_ptr() as u32);
+                .set(tx_buffer[self.tx_offset.get()..].as_ptr() as u32);
         });
     }
 
@@ -427,7 +437,7 @@ impl<'a> Uarte<'a> {
         self.rx_buffer.map(|rx_buffer| {
             self.registers
                 .rxd_ptr
-                .set(rx_buffer[self.offset.get()..].as_ptr() as u32);
+                .set(rx_buffer[self.rx_offset.get()..].as_ptr() as u32);
         });
     }
 
@@ -435,7 +445,7 @@ impl<'a> Uarte<'a> {
     fn setup_buffer_transmit(&self, buf: &'static mut [u8], tx_len: usize) {
         self.tx_remaining_bytes.set(tx_len);
         self.tx_len.set(tx_len);
-        self.offset.set(0);
+        self.tx_offset.set(0);
         self.tx_buffer.replace(buf);
         self.set_tx_dma_pointer_to_buffer();
 
@@ -462,6 +472,8 @@ impl<'a> uart::Transmit<'a> for Uarte<'a> {
             Err((ErrorCode::SIZE, tx_data))
         } else if self.tx_buffer.is_some() {
             Err((ErrorCode::BUSY, tx_data))
+        } else if let Err(error) = easydma::check(tx_data.as_ptr(), tx_len) {
+            Err((error, tx_data))
         } else {
             self.setup_buffer_transmit(tx_data, tx_len);
             Ok(())
@@ -512,9 +524,12 @@ impl<'a> uart::Receive<'a> for Uarte<'a> {
         }
         // truncate rx_len if necessary
         let truncated_length = core::cmp::min(rx_len, rx_buf.len());
+        if let Err(error) = easydma::check(rx_buf.as_ptr(), truncated_length) {
+            return Err((error, rx_buf));
+        }
 
         self.rx_remaining_bytes.set(truncated_length);
-        self.offset.set(0);
+        self.rx_offset.set(0);
         self.rx_buffer.replace(rx_buf);
         self.set_rx_dma_pointer_to_buffer();
 
@@ -832,6 +832,11 @@ impl<'a> Usbd<'a> {
         self.state.unwrap_or_panic() // Unwrap fail = get_state: state value is in use
     }
 
+    /// State of the transfer state machine of `endpoint`.
+    pub fn endpoint_state(&self, endpoint: usize) -> EndpointState {
+        self.descriptors[endpoint].state.get()
+    }
+
     // Powers the USB PHY on
     fn enable(&self) {
         if self.get_state() != UsbState::Disabled {
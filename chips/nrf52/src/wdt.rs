@@ -0,0 +1,123 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Implementation of the nRF52 hardware watchdog timer.
+//!
+//! Once started, the watchdog cannot be stopped by anything but a reset. It
+//! is configured to pause while the CPU sleeps and while a debugger halts it,
+//! so `suspend()` only has to reload it.
+
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
+use kernel::utilities::StaticRef;
+
+const WDT_BASE: StaticRef<WdtRegisters> =
+    unsafe { StaticRef::new(0x40010000 as *const WdtRegisters) };
+
+/// Frequency of the clock the watchdog counts, the 32.768 kHz LFCLK.
+const LFCLK_HZ: u32 = 32768;
+
+/// Value to write to a reload request register to reload the watchdog.
+const RELOAD_VALUE: u32 = 0x6E524635;
+
+#[repr(C)]
+struct WdtRegisters {
+    tasks_start: WriteOnly<u32, Task::Register>,
+    _reserved0: [u8; 252],
+    events_timeout: ReadWrite<u32, Event::Register>,
+    _reserved1: [u8; 512],
+    intenset: ReadWrite<u32, Interrupt::Register>,
+    intenclr: ReadWrite<u32, Interrupt::Register>,
+    _reserved2: [u8; 244],
+    runstatus: ReadOnly<u32, RunStatus::Register>,
+    reqstatus: ReadOnly<u32, ReloadRequests::Register>,
+    _reserved3: [u8; 252],
+    crv: ReadWrite<u32>,
+    rren: ReadWrite<u32, ReloadRequests::Register>,
+    config: ReadWrite<u32, Config::Register>,
+    _reserved4: [u8; 240],
+    rr: [WriteOnly<u32>; 8],
+}
+
+register_bitfields! [u32,
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+    Event [
+        TIMEOUT OFFSET(0) NUMBITS(1)
+    ],
+    Interrupt [
+        TIMEOUT OFFSET(0) NUMBITS(1)
+    ],
+    RunStatus [
+        RUNNING OFFSET(0) NUMBITS(1)
+    ],
+    ReloadRequests [
+        RR0 OFFSET(0) NUMBITS(1)
+    ],
+    Config [
+        /// Keep the watchdog running while the CPU sleeps
+        SLEEP OFFSET(0) NUMBITS(1) [
+            Pause = 0,
+            Run = 1
+        ],
+        /// Keep the watchdog running while a debugger halts the CPU
+        HALT OFFSET(3) NUMBITS(1) [
+            Pause = 0,
+            Run = 1
+        ]
+    ]
+];
+
+pub struct Wdt {
+    registers: StaticRef<WdtRegisters>,
+    timeout_ms: u32,
+}
+
+impl Wdt {
+    /// Creates the watchdog, which resets the chip if it is not tickled for
+    /// `timeout_ms` milliseconds once it has been set up.
+    pub const fn new(timeout_ms: u32) -> Wdt {
+        Wdt {
+            registers: WDT_BASE,
+            timeout_ms,
+        }
+    }
+
+    /// Returns whether the watchdog runs, which it does from `setup()` until
+    /// the next reset.
+    pub fn is_running(&self) -> bool {
+        self.registers.runstatus.is_set(RunStatus::RUNNING)
+    }
+
+    fn start(&self) {
+        // The counter reload value only takes effect before the watchdog is
+        // started. The minimum value is 15 ticks.
+        let ticks = (self.timeout_ms as u64 * LFCLK_HZ as u64 / 1000).max(15);
+        self.registers.crv.set(ticks.min(u32::MAX as u64) as u32);
+        self.registers.rren.write(ReloadRequests::RR0::SET);
+        self.registers
+            .config
+            .write(Config::SLEEP::Pause + Config::HALT::Pause);
+        self.registers.tasks_start.write(Task::ENABLE::SET);
+    }
+}
+
+impl kernel::platform::watchdog::WatchDog for Wdt {
+    fn setup(&self) {
+        if !self.is_running() {
+            self.start();
+        }
+    }
+
+    fn tickle(&self) {
+        self.registers.rr[0].set(RELOAD_VALUE);
+    }
+
+    fn suspend(&self) {
+        // The watchdog pauses by itself while the CPU sleeps, so start the
+        // sleep with a full timeout instead.
+        self.tickle();
+    }
+}
@@ -13,8 +13,10 @@ use nrf52::chip::Nrf52DefaultPeripherals;
 //create all base nrf52 peripherals
 pub struct Nrf52840DefaultPeripherals<'a> {
     pub nrf52: Nrf52DefaultPeripherals<'a>,
+    pub acl: crate::acl::Acl,
     pub ieee802154_radio: crate::ieee802154_radio::Radio<'a>,
     pub usbd: crate::usbd::Usbd<'a>,
+    pub uarte1: crate::uart::Uarte<'a>,
     pub gpio_port: crate::gpio::Port<'a, { crate::gpio::NUM_PINS }>,
 }
 
@@ -24,8 +26,10 @@ impl Nrf52840DefaultPeripherals<'_> {
     ) -> Self {
         Self {
             nrf52: Nrf52DefaultPeripherals::new(),
+            acl: crate::acl::Acl::new(),
             ieee802154_radio: crate::ieee802154_radio::Radio::new(ieee802154_radio_ack_buf),
             usbd: crate::usbd::Usbd::new(),
+            uarte1: crate::uart::Uarte::new(crate::uart::UARTE1_BASE),
             gpio_port: crate::gpio::nrf52840_gpio_create(),
         }
     }
@@ -35,6 +39,7 @@ impl Nrf52840DefaultPeripherals<'_> {
         self.nrf52.timer0.set_alarm_client(&self.ieee802154_radio);
         self.nrf52.pwr_clk.set_usb_client(&self.usbd);
         self.usbd.set_power_ref(&self.nrf52.pwr_clk);
+        self.nrf52.nvmc.set_acl(&self.acl);
         kernel::deferred_call::DeferredCallClient::register(&self.ieee802154_radio);
         self.nrf52.init();
     }
@@ -43,6 +48,7 @@ impl kernel::platform::chip::InterruptService for Nrf52840DefaultPeripherals<'_>
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
         match interrupt {
             crate::peripheral_interrupts::USBD => self.usbd.handle_interrupt(),
+            crate::peripheral_interrupts::UART1 => self.uarte1.handle_interrupt(),
             nrf52::peripheral_interrupts::GPIOTE => self.gpio_port.handle_interrupt(),
             nrf52::peripheral_interrupts::RADIO => {
                 match (
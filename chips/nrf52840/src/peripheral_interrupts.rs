@@ -3,7 +3,6 @@
 // Copyright Tock Contributors 2022.
 
 pub const USBD: u32 = 39;
-#[allow(dead_code)]
 pub const UART1: u32 = 40;
 #[allow(dead_code)]
 pub const QSPI: u32 = 41;
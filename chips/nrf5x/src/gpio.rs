@@ -546,6 +546,61 @@ impl<'a> hil::gpio::Interrupt<'a> for GPIOPin<'a> {
 }
 
 impl GPIOPin<'_> {
+    /// Hands the pin to a GPIOTE channel in task mode, so that the channel's
+    /// OUT task toggles it without the CPU. Returns the address of the OUT
+    /// task, for use as a PPI task end point, or `None` if no channel is free.
+    ///
+    /// The pin cannot have interrupts enabled at the same time.
+    pub fn enable_toggle_task(&self, initial_high: bool) -> Option<usize> {
+        let channel = self
+            .allocated_channel
+            .get()
+            .or_else(|| self.allocate_channel().ok())?;
+        self.allocated_channel.set(channel);
+
+        let pin: u32 = (GPIO_PER_PORT as u32 * self.port as u32) + self.pin as u32;
+        let initial = if initial_high {
+            Config::OUTINIT::High
+        } else {
+            Config::OUTINIT::Low
+        };
+        self.gpiote_registers.config[channel]
+            .write(Config::MODE::Task + Config::PSEL.val(pin) + Config::POLARITY::Toggle + initial);
+        Some(core::ptr::from_ref(&self.gpiote_registers.task_out[channel]) as usize)
+    }
+
+    /// Hands the pin to a GPIOTE channel in event mode without an interrupt,
+    /// so that `edge` on the pin triggers PPI channels. Returns the address
+    /// of the IN event, for use as a PPI event end point, or `None` if no
+    /// channel is free. `disable_interrupts()` frees the channel.
+    pub fn enable_event(&self, edge: hil::gpio::InterruptEdge) -> Option<usize> {
+        let channel = self
+            .allocated_channel
+            .get()
+            .or_else(|| self.allocate_channel().ok())?;
+        self.allocated_channel.set(channel);
+
+        let polarity = match edge {
+            hil::gpio::InterruptEdge::EitherEdge => Config::POLARITY::Toggle,
+            hil::gpio::InterruptEdge::RisingEdge => Config::POLARITY::LoToHi,
+            hil::gpio::InterruptEdge::FallingEdge => Config::POLARITY::HiToLo,
+        };
+        let pin: u32 = (GPIO_PER_PORT as u32 * self.port as u32) + self.pin as u32;
+        self.gpiote_registers.event_in[channel].write(EventsIn::EVENT::NotReady);
+        self.gpiote_registers.config[channel]
+            .write(Config::MODE::Event + Config::PSEL.val(pin) + polarity);
+        Some(core::ptr::from_ref(&self.gpiote_registers.event_in[channel]) as usize)
+    }
+
+    /// Returns the pin to regular GPIO control after
+    /// [`GPIOPin::enable_toggle_task()`].
+    pub fn disable_toggle_task(&self) {
+        if let Some(channel) = self.allocated_channel.take() {
+            self.gpiote_registers.config[channel]
+                .write(Config::MODE::CLEAR + Config::PSEL::CLEAR + Config::POLARITY::CLEAR);
+        }
+    }
+
     /// Allocate a GPIOTE channel
     /// If the channel couldn't be allocated return error instead
     fn allocate_channel(&self) -> Result<usize, ()> {
@@ -105,6 +105,12 @@ impl Rtc<'_> {
         }
     }
 
+    /// Returns the prescaler, which divides the 32.768 kHz clock by
+    /// `prescaler + 1`.
+    pub fn prescaler(&self) -> u32 {
+        self.registers.prescaler.read(Prescaler::PRESCALER)
+    }
+
     pub fn handle_interrupt(&self) {
         if self.registers.events_ovrflw.is_set(Event::READY) {
             self.registers.events_ovrflw.write(Event::READY::CLEAR);
@@ -202,6 +202,7 @@ register_bitfields![u32,
     ]
 ];
 
+#[derive(Clone, Copy)]
 pub enum BitmodeValue {
     Size16Bits = 0,
     Size8Bits = 1,
@@ -231,6 +232,81 @@ impl Timer {
         self.client.set(client);
     }
 
+    /// Clears the counter and starts it in timer mode, counting at 16 MHz
+    /// divided by `2^prescaler`. `prescaler` is at most 9.
+    pub fn start(&self, prescaler: u8, bitmode: BitmodeValue) {
+        self.registers.tasks_stop.write(Task::ENABLE::SET);
+        self.registers.mode.set(0);
+        self.registers.bitmode.set(bitmode as u32);
+        self.registers.prescaler.set(u32::from(prescaler.min(9)));
+        self.registers.tasks_clear.write(Task::ENABLE::SET);
+        self.registers.tasks_start.write(Task::ENABLE::SET);
+    }
+
+    /// Clears the counter and starts it in counter mode, in which it counts
+    /// the triggers of its COUNT task.
+    pub fn start_counter(&self, bitmode: BitmodeValue) {
+        self.registers.tasks_stop.write(Task::ENABLE::SET);
+        // Low power counter mode.
+        self.registers.mode.set(2);
+        self.registers.bitmode.set(bitmode as u32);
+        self.registers.tasks_clear.write(Task::ENABLE::SET);
+        self.registers.tasks_start.write(Task::ENABLE::SET);
+    }
+
+    /// Stops the counter.
+    pub fn stop(&self) {
+        self.registers.tasks_stop.write(Task::ENABLE::SET);
+    }
+
+    /// Generates compare event `index` when the counter reaches `value`. If
+    /// `clear` is set, the event also clears the counter, so it repeats
+    /// periodically.
+    pub fn set_compare(&self, index: usize, value: u32, clear: bool) {
+        self.registers.cc[index].set(value);
+        let shorts = self.registers.shorts.get() & !(1 << index);
+        self.registers
+            .shorts
+            .set(shorts | (u32::from(clear) << index));
+    }
+
+    /// Captures the counter into CC register `index` and returns it.
+    pub fn capture(&self, index: usize) -> u32 {
+        self.registers.tasks_capture[index].write(Task::ENABLE::SET);
+        self.registers.cc[index].get()
+    }
+
+    /// Returns the value last captured into CC register `index`, without
+    /// capturing the counter again.
+    pub fn captured(&self, index: usize) -> u32 {
+        self.registers.cc[index].get()
+    }
+
+    /// Enables the interrupt of compare event `index`, which
+    /// `handle_interrupt` disables again once the event happened.
+    pub fn enable_interrupt(&self, index: usize) {
+        // The COMPARE bits start at bit 16.
+        self.registers.intenset.set(1 << (16 + index));
+    }
+
+    /// Returns the address of compare event `index`, for use as a PPI event
+    /// end point.
+    pub fn compare_event_address(&self, index: usize) -> usize {
+        core::ptr::from_ref(&self.registers.events_compare[index]) as usize
+    }
+
+    /// Returns the address of capture task `index`, for use as a PPI task
+    /// end point.
+    pub fn capture_task_address(&self, index: usize) -> usize {
+        core::ptr::from_ref(&self.registers.tasks_capture[index]) as usize
+    }
+
+    /// Returns the address of the COUNT task, for use as a PPI task end
+    /// point.
+    pub fn count_task_address(&self) -> usize {
+        core::ptr::from_ref(&self.registers.tasks_count) as usize
+    }
+
     /// When an interrupt occurs, check if any of the 4 compares have
     /// created an event, and if so, add it to the bitmask of triggered
     /// events that is passed to the client.
@@ -312,7 +388,9 @@ impl<'a> TimerAlarm<'a> {
 }
 
 impl Time for TimerAlarm<'_> {
-    type Frequency = hil::time::Freq16KHz;
+    // The driver never writes PRESCALER, so the counter runs at 16 MHz
+    // divided by 2^4, the reset value.
+    type Frequency = hil::time::Freq1MHz;
     // Note: we always use BITMODE::32.
     type Ticks = hil::time::Ticks32;
 
@@ -549,21 +549,32 @@ impl<'a> Can<'a> {
         self.registers.can_mcr.modify(CAN_MCR::TXFP::CLEAR);
 
         match self.automatic_retransmission.get() {
-            true => self.registers.can_mcr.modify(CAN_MCR::AWUM::SET),
-            false => self.registers.can_mcr.modify(CAN_MCR::AWUM::CLEAR),
+            true => self.registers.can_mcr.modify(CAN_MCR::NART::CLEAR),
+            false => self.registers.can_mcr.modify(CAN_MCR::NART::SET),
         }
 
         match self.automatic_wake_up.get() {
-            true => self.registers.can_mcr.modify(CAN_MCR::NART::CLEAR),
-            false => self.registers.can_mcr.modify(CAN_MCR::NART::SET),
+            true => self.registers.can_mcr.modify(CAN_MCR::AWUM::SET),
+            false => self.registers.can_mcr.modify(CAN_MCR::AWUM::CLEAR),
         }
 
+        // the mode bits of a previous enable must be cleared, as the
+        // peripheral keeps them while asleep
         if let Some(operating_mode_settings) = self.operating_mode.get() {
             match operating_mode_settings {
-                can::OperationMode::Loopback => self.registers.can_btr.modify(CAN_BTR::LBKM::SET),
-                can::OperationMode::Monitoring => self.registers.can_btr.modify(CAN_BTR::SILM::SET),
+                can::OperationMode::Loopback => self
+                    .registers
+                    .can_btr
+                    .modify(CAN_BTR::LBKM::SET + CAN_BTR::SILM::CLEAR),
+                can::OperationMode::Monitoring => self
+                    .registers
+                    .can_btr
+                    .modify(CAN_BTR::LBKM::CLEAR + CAN_BTR::SILM::SET),
                 can::OperationMode::Freeze => return Err(kernel::ErrorCode::INVAL),
-                _ => {}
+                can::OperationMode::Normal => self
+                    .registers
+                    .can_btr
+                    .modify(CAN_BTR::LBKM::CLEAR + CAN_BTR::SILM::CLEAR),
             }
         }
 
@@ -701,7 +712,7 @@ impl<'a> Can<'a> {
                             .modify(CAN_TIxR::IDE::CLEAR);
                         self.registers.can_tx_mailbox[tx_mailbox]
                             .can_tir
-                            .modify(CAN_TIxR::STID.val(id as u32 & 0xeff));
+                            .modify(CAN_TIxR::STID.val(id as u32 & 0x7ff));
                         self.registers.can_tx_mailbox[tx_mailbox]
                             .can_tir
                             .modify(CAN_TIxR::EXID.val(0));
@@ -712,7 +723,7 @@ impl<'a> Can<'a> {
                             .modify(CAN_TIxR::IDE::SET);
                         self.registers.can_tx_mailbox[tx_mailbox]
                             .can_tir
-                            .modify(CAN_TIxR::STID.val((id & 0xffc0000) >> 18));
+                            .modify(CAN_TIxR::STID.val((id & 0x1ffc0000) >> 18));
                         self.registers.can_tx_mailbox[tx_mailbox]
                             .can_tir
                             .modify(CAN_TIxR::EXID.val(id & 0x003fffff));
@@ -844,8 +855,15 @@ impl<'a> Can<'a> {
             }
         }
 
+        // keep the error of the error interrupt if it came first, as it is
+        // more specific, and report the state change otherwise
         if let Err(err) = state {
-            self.can_state.set(CanState::RunningError(err))
+            if self.can_state.get() == CanState::Normal {
+                self.can_state.set(CanState::RunningError(err));
+                self.controller_client.map(|controller_client| {
+                    controller_client.state_changed(can::State::Error(err));
+                });
+            }
         }
 
         self.transmit_client.map(|transmit_client| {
@@ -883,8 +901,8 @@ impl<'a> Can<'a> {
         let message_length = self.registers.can_rx_mailbox[rx_mailbox]
             .can_rdtr
             .read(CAN_RDTxR::DLC) as usize;
-        let recv: u64 = ((self.registers.can_rx_mailbox[0].can_rdhr.get() as u64) << 32)
-            | (self.registers.can_rx_mailbox[0].can_rdlr.get() as u64);
+        let recv: u64 = ((self.registers.can_rx_mailbox[rx_mailbox].can_rdhr.get() as u64) << 32)
+            | (self.registers.can_rx_mailbox[rx_mailbox].can_rdlr.get() as u64);
         let rx_buf = recv.to_le_bytes();
         self.rx_buffer.map(|rx| {
             rx[..8].copy_from_slice(&rx_buf[..8]);
@@ -950,6 +968,10 @@ impl<'a> Can<'a> {
         }
 
         // Check if there is an error interrupt
+        if self.registers.can_msr.read(CAN_MSR::ERRI) == 1 {
+            // mark the interrupt as handled
+            self.registers.can_msr.modify(CAN_MSR::ERRI::SET);
+        }
         // Warning flag
         if self.registers.can_esr.read(CAN_ESR::EWGF) == 1 {
             self.can_state
@@ -967,19 +989,19 @@ impl<'a> Can<'a> {
         }
         // Last Error Code
         match self.registers.can_esr.read(CAN_ESR::LEC) {
-            0x001 => self
+            0b001 => self
                 .can_state
                 .set(CanState::RunningError(can::Error::Stuff)),
-            0x010 => self.can_state.set(CanState::RunningError(can::Error::Form)),
-            0x011 => self.can_state.set(CanState::RunningError(can::Error::Ack)),
-            0x100 => self
+            0b010 => self.can_state.set(CanState::RunningError(can::Error::Form)),
+            0b011 => self.can_state.set(CanState::RunningError(can::Error::Ack)),
+            0b100 => self
                 .can_state
                 .set(CanState::RunningError(can::Error::BitRecessive)),
-            0x101 => self
+            0b101 => self
                 .can_state
                 .set(CanState::RunningError(can::Error::BitDominant)),
-            0x110 => self.can_state.set(CanState::RunningError(can::Error::Crc)),
-            0x111 => self
+            0b110 => self.can_state.set(CanState::RunningError(can::Error::Crc)),
+            0b111 => self
                 .can_state
                 .set(CanState::RunningError(can::Error::SetBySoftware)),
             _ => {}
@@ -1078,11 +1100,12 @@ impl DeferredCallClient for Can<'_> {
                             controller_client.state_changed(self.can_state.get().into());
                             controller_client.enabled(Err(enable_err));
                         });
+                    } else {
+                        self.controller_client.map(|controller_client| {
+                            controller_client.state_changed(can::State::Running);
+                            controller_client.enabled(Ok(()));
+                        });
                     }
-                    self.controller_client.map(|controller_client| {
-                        controller_client.state_changed(can::State::Running);
-                        controller_client.enabled(Ok(()));
-                    });
                 }
                 AsyncAction::AbortReceive => {
                     if let Some(rx) = self.rx_buffer.take() {
@@ -1288,6 +1311,36 @@ impl can::Controller for Can<'_> {
     }
 }
 
+impl can::Filter for Can<'_> {
+    fn enable_filter(&self, filter: can::FilterParameters) -> Result<(), kernel::ErrorCode> {
+        if filter.number as usize >= self.filter_count()
+            || filter.fifo_number >= can::Configure::receive_fifo_count(self)
+        {
+            return Err(kernel::ErrorCode::INVAL);
+        }
+        self.config_filter(filter, true);
+        self.enable_filter_config();
+        Ok(())
+    }
+
+    fn disable_filter(&self, number: u32) -> Result<(), kernel::ErrorCode> {
+        if number as usize >= self.filter_count() {
+            return Err(kernel::ErrorCode::INVAL);
+        }
+        self.registers.can_fmr.modify(CAN_FMR::FINIT::SET);
+        self.registers.can_fa1r.modify(
+            CAN_FA1R::FACT.val(self.registers.can_fa1r.read(CAN_FA1R::FACT) & !(1 << number)),
+        );
+        self.enable_filter_config();
+        Ok(())
+    }
+
+    fn filter_count(&self) -> usize {
+        // each filter bank has two registers
+        FILTER_COUNT / 2
+    }
+}
+
 impl can::Transmit<{ can::STANDARD_CAN_PACKET_SIZE }> for Can<'_> {
     fn set_client(
         &self,
@@ -125,3 +125,18 @@ pub unsafe trait NetworkCapabilityCreationCapability {}
 /// debug writer mechanism has access to debugging print messages which may
 /// contain information about the operation of the kernel.
 pub unsafe trait SetDebugWriterCapability {}
+
+/// The `SetInvariantClientCapability` allows the holder to receive the kernel
+/// invariants that do not hold in builds with the `kernel_test` feature.
+///
+/// The reports include the locations of checks in the kernel, and the client
+/// runs in the middle of kernel operations.
+pub unsafe trait SetInvariantClientCapability {}
+
+/// The `SyscallTraceCapability` allows the holder to choose a process and
+/// receive its system calls and their return values in builds with the
+/// `kernel_test` feature.
+///
+/// The reports include the arguments and return values of the system calls,
+/// which may point into the memory of the process.
+pub unsafe trait SyscallTraceCapability {}
@@ -80,6 +80,27 @@ pub(crate) struct Config {
     // credentials checking, e.g., whether elf2tab and tockloader are generating
     // properly formatted footers.
     pub(crate) debug_process_credentials: bool,
+
+    /// Whether the kernel should record every buffer allocated with
+    /// `static_buf!()`, `static_named_buf!()` or `static_init!()`.
+    ///
+    /// If enabled, the type, address and size of each buffer is kept in a log
+    /// that boards can print with
+    /// [`static_allocations()`](crate::utilities::static_init::static_allocations)
+    /// to see how much memory each component takes.
+    // The log itself takes memory, so it is only included when enabled.
+    pub(crate) debug_static_allocations: bool,
+
+    /// Whether the kernel should check its invariants.
+    ///
+    /// If enabled, each `kernel_debug_assert!` checks its invariant and
+    /// reports a violation to the client set with
+    /// [`set_invariant_client()`](crate::invariant::set_invariant_client).
+    /// The kernel loop also counts how long deferred calls wait to be
+    /// serviced, see
+    /// [`DeferredCall::take_longest_wait()`](crate::deferred_call::DeferredCall::take_longest_wait).
+    // Meant for test kernels, as the checks cost time and code size.
+    pub(crate) kernel_test: bool,
 }
 
 /// A unique instance of `Config` where compile-time configuration options are
@@ -92,4 +113,6 @@ pub(crate) const CONFIG: Config = Config {
     debug_load_processes: cfg!(feature = "debug_load_processes"),
     debug_panics: !cfg!(feature = "no_debug_panics"),
     debug_process_credentials: cfg!(feature = "debug_process_credentials"),
+    debug_static_allocations: cfg!(feature = "debug_static_allocations"),
+    kernel_test: cfg!(feature = "kernel_test"),
 };
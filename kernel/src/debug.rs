@@ -305,6 +305,8 @@ pub struct DebugWriter {
     internal_buffer: TakeCell<'static, RingBuffer<'static, u8>>,
     // Number of debug!() calls.
     count: Cell<usize>,
+    // Bytes of debug output dropped because the internal buffer was full.
+    dropped: Cell<usize>,
 }
 
 /// Static variable that holds the kernel's reference to the debug tool.
@@ -350,6 +352,7 @@ impl DebugWriter {
             output_buffer: TakeCell::new(out_buffer),
             internal_buffer: TakeCell::new(internal_buffer),
             count: Cell::new(0), // how many debug! calls
+            dropped: Cell::new(0),
         }
     }
 
@@ -404,6 +407,10 @@ impl DebugWriter {
     fn available_len(&self) -> usize {
         self.internal_buffer.map_or(0, |rb| rb.available_len())
     }
+
+    fn get_dropped(&self) -> usize {
+        self.dropped.get()
+    }
 }
 
 impl hil::uart::TransmitClient for DebugWriter {
@@ -449,6 +456,10 @@ impl DebugWriterWrapper {
         self.dw
             .map_or(0, |dw| dw.available_len().saturating_sub(FULL_MSG.len()))
     }
+
+    fn get_dropped(&self) -> usize {
+        self.dw.map_or(0, |dw| dw.get_dropped())
+    }
 }
 
 impl IoWrite for DebugWriterWrapper {
@@ -459,22 +470,19 @@ impl IoWrite for DebugWriterWrapper {
                 let available_len_for_msg =
                     ring_buffer.available_len().saturating_sub(FULL_MSG.len());
 
-                if available_len_for_msg >= bytes.len() {
-                    for &b in bytes {
-                        ring_buffer.enqueue(b);
-                    }
-                    bytes.len()
-                } else {
-                    for &b in &bytes[..available_len_for_msg] {
-                        ring_buffer.enqueue(b);
-                    }
-                    // When the buffer is close to full, print a warning and drop the current
-                    // string.
+                let written = bytes.len().min(available_len_for_msg);
+                for &b in &bytes[..written] {
+                    ring_buffer.enqueue(b);
+                }
+                if written < bytes.len() {
+                    // When the buffer is close to full, print a warning and drop the rest
+                    // of the current string.
                     for &b in FULL_MSG {
                         ring_buffer.enqueue(b);
                     }
-                    available_len_for_msg
+                    dw.dropped.add(bytes.len() - written);
                 }
+                written
             })
         })
     }
@@ -527,6 +535,13 @@ pub fn debug_available_len() -> usize {
     writer.available_len()
 }
 
+/// Return how many bytes of debug output have been dropped since boot,
+/// because the internal debug buffer had no room for them.
+pub fn debug_dropped_len() -> usize {
+    let writer = unsafe { get_debug_writer() };
+    writer.get_dropped()
+}
+
 fn write_header(writer: &mut DebugWriterWrapper, (file, line): &(&'static str, u32)) -> Result {
     writer.increment_count();
     let count = writer.get_count();
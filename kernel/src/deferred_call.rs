@@ -131,6 +131,15 @@ static mut BITMASK: Cell<u32> = Cell::new(0);
 static mut DEFCALLS: [OptionalCell<DynDefCallRef<'static>>; 32] =
     [const { OptionalCell::empty() }; 32];
 
+/// Number of kernel loop iterations each deferred call has been pending at
+/// the start of, in builds with the `kernel_test` feature. A deferred call
+/// that is serviced within one iteration of being set is never pending at
+/// the start of more than one.
+static mut WAITS: [Cell<u8>; 32] = [const { Cell::new(0) }; 32];
+
+/// The most iterations in [`WAITS`] since the wait was last taken.
+static mut LONGEST_WAIT: Cell<u8> = Cell::new(0);
+
 pub struct DeferredCall {
     idx: usize,
 }
@@ -222,6 +231,40 @@ impl DeferredCall {
         bitmask.get() != 0
     }
 
+    /// Counts the pending deferred calls as waiting for one more kernel loop
+    /// iteration. Called at the start of each iteration in builds with the
+    /// `kernel_test` feature.
+    pub(crate) fn count_waits() {
+        // SAFETY: No accesses to BITMASK/WAITS/LONGEST_WAIT are via an &mut,
+        // and the Tock kernel is single-threaded so all accesses will occur
+        // from this thread.
+        let bitmask = unsafe { &*addr_of!(BITMASK) };
+        let waits = unsafe { &*addr_of!(WAITS) };
+        let longest = unsafe { &*addr_of!(LONGEST_WAIT) };
+        let pending = bitmask.get();
+        for (bit, wait) in waits.iter().enumerate() {
+            if pending & (1 << bit) == 0 {
+                wait.set(0);
+            } else {
+                wait.set(wait.get().saturating_add(1));
+                longest.set(longest.get().max(wait.get()));
+            }
+        }
+    }
+
+    /// Returns the most kernel loop iterations any deferred call was pending
+    /// at the start of since the last call, and starts over. A deferred call
+    /// set by a process waits for one iteration, in which the kernel services
+    /// it before it runs a process again, so more means the deferred call was
+    /// starved. Always 0 without the `kernel_test` feature.
+    pub fn take_longest_wait() -> u32 {
+        // SAFETY: No accesses to LONGEST_WAIT are via an &mut, and the Tock
+        // kernel is single-threaded so all accesses will occur from this
+        // thread.
+        let longest = unsafe { &*addr_of!(LONGEST_WAIT) };
+        u32::from(longest.replace(0))
+    }
+
     /// This function should be called at the beginning of the kernel loop to
     /// verify that deferred calls have been correctly initialized. This
     /// function verifies two things:
@@ -33,6 +33,10 @@ pub enum Error {
 
     /// The underlying device has another request in progress
     Busy,
+
+    /// The transfer did not end in time, for example because a slave held
+    /// the clock low for longer than the bus allows. The master stopped it.
+    Timeout,
 }
 
 impl From<Error> for ErrorCode {
@@ -43,6 +47,7 @@ impl From<Error> for ErrorCode {
             Error::Overrun => ErrorCode::SIZE,
             Error::NotSupported => ErrorCode::NOSUPPORT,
             Error::Busy => ErrorCode::BUSY,
+            Error::Timeout => ErrorCode::FAIL,
         }
     }
 }
@@ -56,6 +61,7 @@ impl Display for Error {
             Error::Overrun => "I2C receive overrun",
             Error::NotSupported => "I2C/SMBus command not supported",
             Error::Busy => "I2C/SMBus is busy",
+            Error::Timeout => "I2C/SMBus transfer timed out",
         };
         write!(fmt, "{}", display_str)
     }
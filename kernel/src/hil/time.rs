@@ -939,6 +939,51 @@ mod tests {
         assert_eq!(us, u32::MAX);
     }
 
+    struct Test32KHz24();
+    impl Time for Test32KHz24 {
+        type Frequency = Freq32KHz;
+        type Ticks = Ticks24;
+
+        fn now(&self) -> Self::Ticks {
+            0u32.into()
+        }
+    }
+
+    #[test]
+    fn test_rounding32khz() {
+        // 1 ms is 32.768 ticks, and both directions round down.
+        let t = Test32KHz24().ticks_from_ms(1);
+        assert_eq!(t.into_u32(), 32);
+
+        let ms = Test32KHz24().ticks_to_ms(32u32.into());
+        assert_eq!(ms, 0);
+
+        let ms = Test32KHz24().ticks_to_ms(33u32.into());
+        assert_eq!(ms, 1);
+
+        let t = Test32KHz24().ticks_from_us(30);
+        assert_eq!(t.into_u32(), 0);
+
+        let t = Test32KHz24().ticks_from_us(31);
+        assert_eq!(t.into_u32(), 1);
+
+        // A round trip never gains time and loses at most one unit.
+        for ms in [1, 7, 125, 1000, 511_999] {
+            let back = Test32KHz24().ticks_to_ms(Test32KHz24().ticks_from_ms(ms));
+            assert!(back == ms || back == ms - 1);
+        }
+
+        // 512 s is the first whole second that does not fit in 24 bits.
+        let t = Test32KHz24().ticks_from_seconds(511);
+        assert_eq!(t.into_u32(), 511 * 32_768);
+
+        let t = Test32KHz24().ticks_from_seconds(512);
+        assert_eq!(t, Ticks24::max_value());
+
+        let s = Test32KHz24().ticks_to_seconds(Ticks24::max_value());
+        assert_eq!(s, 511);
+    }
+
     #[test]
     fn test_dyn_object() {
         let time: &dyn Time<Frequency = Freq1KHz, Ticks = Ticks24> = &Test1KHz24();
@@ -0,0 +1,104 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Checks of kernel invariants for test builds.
+//!
+//! The kernel checks some of its invariants, for example that a grant is
+//! allocated before it is entered, with `kernel_debug_assert!`. Normally the
+//! checks are compiled out. With the `kernel_test` feature of the kernel
+//! crate, a violated invariant is reported with the location of the check to
+//! the client a board sets, so a test harness can fail the test that was
+//! running instead of the kernel corrupting its state silently. Without a
+//! client, the kernel prints the violation to the debug output.
+//!
+//! ```ignore
+//! kernel::invariant::set_invariant_client(
+//!     invariant_monitor,
+//!     create_capability!(kernel::capabilities::SetInvariantClientCapability),
+//! );
+//! ```
+//!
+//! Violations do not stop the kernel, which continues as it would have
+//! without the check. The client also sees every process state change, which
+//! the kernel checks against the process state machine.
+
+use core::fmt;
+use core::ptr::addr_of;
+
+use crate::capabilities::SetInvariantClientCapability;
+use crate::process::{ProcessId, State};
+
+/// A kernel invariant that did not hold.
+#[derive(Clone, Copy, Debug)]
+pub struct Violation {
+    /// The invariant, as stated at the check.
+    pub invariant: &'static str,
+    /// Source file of the check.
+    pub file: &'static str,
+    /// Line of the check.
+    pub line: u32,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.invariant)
+    }
+}
+
+/// Receives the kernel invariants that do not hold.
+pub trait InvariantClient {
+    /// Called for each violated invariant, right at the check.
+    fn violated(&self, violation: Violation);
+
+    /// Called for each state change of a process, before the change is
+    /// checked against [`State::can_transition_to`].
+    fn process_state_changed(&self, _processid: ProcessId, _from: State, _to: State) {}
+}
+
+/// Client the kernel reports violations to.
+static mut INVARIANT_CLIENT: Option<&'static dyn InvariantClient> = None;
+
+/// Function used by board main.rs to receive the violated invariants.
+pub fn set_invariant_client<C: SetInvariantClientCapability>(
+    client: &'static dyn InvariantClient,
+    _cap: C,
+) {
+    unsafe {
+        INVARIANT_CLIENT = Some(client);
+    }
+}
+
+/// Reports a violated invariant. Called by `kernel_debug_assert!`.
+pub(crate) fn report(invariant: &'static str, file: &'static str, line: u32) {
+    let violation = Violation {
+        invariant,
+        file,
+        line,
+    };
+    match unsafe { *addr_of!(INVARIANT_CLIENT) } {
+        Some(client) => client.violated(violation),
+        None => crate::debug!("Kernel invariant violated: {}", violation),
+    }
+}
+
+/// Reports a state change of a process.
+pub(crate) fn report_transition(processid: ProcessId, from: State, to: State) {
+    if let Some(client) = unsafe { *addr_of!(INVARIANT_CLIENT) } {
+        client.process_state_changed(processid, from, to);
+    }
+}
+
+/// Checks a kernel invariant in builds with the `kernel_test` feature.
+///
+/// The condition is type-checked but not evaluated in other builds, so it
+/// must not have side effects.
+macro_rules! kernel_debug_assert {
+    ($cond:expr, $invariant:literal $(,)?) => {
+        if $crate::config::CONFIG.kernel_test && !$cond {
+            $crate::invariant::report($invariant, file!(), line!());
+        }
+    };
+}
+
+pub(crate) use kernel_debug_assert;
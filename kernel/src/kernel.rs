@@ -17,6 +17,7 @@ use crate::debug;
 use crate::deferred_call::DeferredCall;
 use crate::errorcode::ErrorCode;
 use crate::grant::{AllowRoSize, AllowRwSize, Grant, UpcallSize};
+use crate::invariant::kernel_debug_assert;
 use crate::ipc;
 use crate::memop;
 use crate::platform::chip::Chip;
@@ -314,6 +315,31 @@ impl Kernel {
         Err(())
     }
 
+    /// Remove a stopped process from the processes array, freeing its slot for
+    /// a new process.
+    ///
+    /// The process must be terminated or faulted, so that no capsule holds
+    /// grant state for it. Its `ProcessId` is no longer valid afterwards. The
+    /// memory the process was loaded into is not reclaimed.
+    ///
+    /// Returns `INVAL` if `processid` does not refer to a process and `BUSY`
+    /// if the process is still running.
+    pub fn remove_process(
+        &self,
+        processid: ProcessId,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) -> Result<(), ErrorCode> {
+        let process = self.get_process(processid).ok_or(ErrorCode::INVAL)?;
+        match process.get_state() {
+            process::State::Terminated | process::State::Faulted => {}
+            _ => return Err(ErrorCode::BUSY),
+        }
+        if let Some(slot) = self.processes.get(processid.index) {
+            slot.proc.set(None);
+        }
+        Ok(())
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -357,6 +383,9 @@ impl Kernel {
     ) {
         let scheduler = resources.scheduler();
 
+        if config::CONFIG.kernel_test {
+            DeferredCall::count_waits();
+        }
         resources.watchdog().tickle();
         unsafe {
             // Ask the scheduler if we should do tasks inside of the kernel,
@@ -373,11 +402,13 @@ impl Kernel {
                     // No kernel work ready, so ask scheduler for a process.
                     match scheduler.next() {
                         SchedulingDecision::RunProcess((processid, timeslice_us)) => {
-                            self.process_map_or((), processid, |process| {
+                            let ran = self.process_map_or(false, processid, |process| {
                                 let (reason, time_executed) =
                                     self.do_process(resources, chip, process, ipc, timeslice_us);
                                 scheduler.result(reason, time_executed);
+                                true
                             });
+                            kernel_debug_assert!(ran, "the scheduler only runs existing processes");
                         }
                         SchedulingDecision::TrySleep => {
                             // For testing, it may be helpful to
@@ -708,6 +739,10 @@ impl Kernel {
             }
         });
 
+        if let Some(us) = time_executed_us {
+            process.debug_time_executed(us);
+        }
+
         // Reset the scheduler timer in case it unconditionally triggers
         // interrupts upon expiration. We do not want it to expire while the
         // chip is sleeping, for example.
@@ -730,6 +765,7 @@ impl Kernel {
     ) {
         // Hook for process debugging.
         process.debug_syscall_called(syscall);
+        crate::syscall_trace::report_syscall(process.processid(), syscall);
 
         // Enforce platform-specific syscall filtering here.
         //
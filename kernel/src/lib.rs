@@ -114,6 +114,7 @@ pub mod errorcode;
 pub mod grant;
 pub mod hil;
 pub mod introspection;
+pub mod invariant;
 pub mod ipc;
 pub mod platform;
 pub mod process;
@@ -122,6 +123,8 @@ pub mod processbuffer;
 pub mod scheduler;
 pub mod storage_permissions;
 pub mod syscall;
+pub mod syscall_trace;
+pub mod test;
 pub mod upcall;
 pub mod utilities;
 
@@ -854,6 +854,10 @@ pub trait Process {
     /// and the state of the memory protection unit (MPU).
     fn print_full_process(&self, writer: &mut dyn Write);
 
+    /// Capture the state of the process a test or a crash dump looks at in
+    /// one [`ProcessSnapshot`].
+    fn debug_snapshot(&self) -> ProcessSnapshot;
+
     // debug
 
     /// Returns how many syscalls this app has called.
@@ -868,6 +872,14 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how long this process has executed in microseconds, including
+    /// the time the kernel spent handling its system calls. Time the process
+    /// ran cooperatively, without a timeslice, is not counted.
+    fn debug_executed_time_us(&self) -> u64;
+
+    /// Add `us` microseconds to the time this process has executed.
+    fn debug_time_executed(&self, us: u32);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
@@ -996,6 +1008,45 @@ pub enum State {
     Terminated,
 }
 
+impl State {
+    /// Returns whether a process may go from this state to `next`.
+    ///
+    /// A process runs, yields and is stopped and resumed while it is active,
+    /// and leaves any active state, stopped or not, by faulting or by being
+    /// terminated. A faulted process is terminated before it starts again, so
+    /// it never runs again without a restart. Kernels built with the
+    /// `kernel_test` feature check every state change of a `ProcessStandard`
+    /// against this.
+    pub fn can_transition_to(self, next: State) -> bool {
+        match (self, next) {
+            (State::Running, State::Yielded | State::YieldedFor(_)) => true,
+            (State::Yielded | State::YieldedFor(_), State::Running) => true,
+            (State::Running, State::Stopped(StoppedState::Running))
+            | (State::Yielded, State::Stopped(StoppedState::Yielded))
+            | (State::Stopped(StoppedState::Running), State::Running)
+            | (State::Stopped(StoppedState::Yielded), State::Yielded) => true,
+            (State::YieldedFor(waiting), State::Stopped(StoppedState::YieldedFor(stopped)))
+            | (State::Stopped(StoppedState::YieldedFor(stopped)), State::YieldedFor(waiting)) => {
+                waiting == stopped
+            }
+            // A stopped process faults when the kernel sets a fault on it, for
+            // example from the process console.
+            (
+                State::Running | State::Yielded | State::YieldedFor(_) | State::Stopped(_),
+                State::Faulted,
+            ) => true,
+            (
+                State::Running | State::Yielded | State::YieldedFor(_) | State::Stopped(_),
+                State::Terminated,
+            ) => true,
+            (State::Faulted, State::Terminated) => true,
+            // Starting or restarting a process, or stopping it after a fault.
+            (State::Terminated, State::Yielded | State::Faulted) => true,
+            _ => false,
+        }
+    }
+}
+
 /// States a process could previously have been in when stopped.
 ///
 /// This is public so external implementations of `Process` can re-use these
@@ -1175,3 +1226,33 @@ pub struct ProcessSizes {
     /// `ProcessX` struct).
     pub process_control_block: usize,
 }
+
+/// The number of MPU regions [`Process::add_mpu_region`] can add to a process.
+pub const MAX_ADDED_MPU_REGIONS: usize = 6;
+
+/// A snapshot of the state of a process, for debugging.
+///
+/// The values are copied when [`Process::debug_snapshot`] is called, so a
+/// snapshot stays the same as the process goes on.
+#[derive(Copy, Clone)]
+pub struct ProcessSnapshot {
+    /// The identifier of the process.
+    pub processid: ProcessId,
+    /// The state of the process.
+    pub state: State,
+    /// The address the process resumes executing at, if the architecture
+    /// reports it.
+    pub pc: Option<usize>,
+    /// The stack pointer of the process, if the architecture reports it.
+    pub sp: Option<usize>,
+    /// How many times the process has been restarted.
+    pub restart_count: usize,
+    /// The number of grants allocated for the process, or `None` if the
+    /// process is not running.
+    pub grants_allocated: Option<usize>,
+    /// The number of bytes at the end of the process's memory that the kernel
+    /// uses for grants, the upcall queue and the process control block.
+    pub grant_region_size: usize,
+    /// The MPU regions added with [`Process::add_mpu_region`].
+    pub mpu_regions: [Option<mpu::Region>; MAX_ADDED_MPU_REGIONS],
+}
@@ -19,14 +19,16 @@ use crate::collections::ring_buffer::RingBuffer;
 use crate::config;
 use crate::debug;
 use crate::errorcode::ErrorCode;
+use crate::invariant::{self, kernel_debug_assert};
 use crate::kernel::Kernel;
 use crate::platform::chip::Chip;
 use crate::platform::mpu::{self, MPU};
 use crate::process::BinaryVersion;
 use crate::process::ProcessBinary;
+use crate::process::MAX_ADDED_MPU_REGIONS;
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, Task};
 use crate::process::{FaultAction, ProcessCustomGrantIdentifier, ProcessId};
-use crate::process::{ProcessAddresses, ProcessSizes, ShortId};
+use crate::process::{ProcessAddresses, ProcessSizes, ProcessSnapshot, ShortId};
 use crate::process::{State, StoppedState};
 use crate::process_checker::AcceptedCredential;
 use crate::process_loading::ProcessLoadError;
@@ -35,6 +37,7 @@ use crate::process_policies::ProcessStandardStoragePermissionsPolicy;
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
 use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn, UserspaceKernelBoundary};
+use crate::syscall_trace;
 use crate::upcall::UpcallId;
 use crate::utilities::capability_ptr::{CapabilityPtr, CapabilityPtrPermissions};
 use crate::utilities::cells::{MapCell, NumericCellExt, OptionalCell};
@@ -128,6 +131,16 @@ pub trait ProcessStandardDebug: Default {
     /// Reset the recorded count of the number of the process has exceeded its
     /// timeslice to 0.
     fn reset_timeslice_expiration_count(&self);
+
+    /// Add `us` microseconds to the recorded time the process has executed.
+    fn add_executed_time(&self, us: u32);
+    /// Get the recorded time in microseconds the process has executed.
+    ///
+    /// This should return 0 if
+    /// [`ProcessStandardDebug::add_executed_time()`] is never called.
+    fn get_executed_time_us(&self) -> u64;
+    /// Reset the recorded time the process has executed to 0.
+    fn reset_executed_time(&self);
 }
 
 /// A debugging implementation for [`ProcessStandard`] that records the full
@@ -179,6 +192,10 @@ struct ProcessStandardDebugFullInner {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How long this process has executed in microseconds, counting the time
+    /// the kernel spent on its behalf.
+    executed_time_us: u64,
 }
 
 impl ProcessStandardDebug for ProcessStandardDebugFull {
@@ -265,6 +282,15 @@ impl ProcessStandardDebug for ProcessStandardDebugFull {
     fn reset_timeslice_expiration_count(&self) {
         self.debug.map(|d| d.timeslice_expiration_count = 0);
     }
+    fn add_executed_time(&self, us: u32) {
+        self.debug.map(|d| d.executed_time_us += us as u64);
+    }
+    fn get_executed_time_us(&self) -> u64 {
+        self.debug.map_or(0, |d| d.executed_time_us)
+    }
+    fn reset_executed_time(&self) {
+        self.debug.map(|d| d.executed_time_us = 0);
+    }
 }
 
 impl Default for ProcessStandardDebugFull {
@@ -319,6 +345,11 @@ impl ProcessStandardDebug for () {
         0
     }
     fn reset_timeslice_expiration_count(&self) {}
+    fn add_executed_time(&self, _us: u32) {}
+    fn get_executed_time_us(&self) -> u64 {
+        0
+    }
+    fn reset_executed_time(&self) {}
 }
 
 /// Entry that is stored in the grant pointer table at the top of process
@@ -471,7 +502,7 @@ pub struct ProcessStandard<'a, C: 'static + Chip, D: 'static + ProcessStandardDe
     mpu_config: MapCell<<<C as Chip>::MPU as MPU>::MpuConfig>,
 
     /// MPU regions are saved as a pointer-size pair.
-    mpu_regions: [Cell<Option<mpu::Region>>; 6],
+    mpu_regions: [Cell<Option<mpu::Region>>; MAX_ADDED_MPU_REGIONS],
 
     /// Essentially a list of upcalls that want to call functions in the
     /// process.
@@ -591,23 +622,23 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
 
     fn set_yielded_state(&self) {
         if self.state.get() == State::Running {
-            self.state.set(State::Yielded);
+            self.set_state(State::Yielded);
         }
     }
 
     fn set_yielded_for_state(&self, upcall_id: UpcallId) {
         if self.state.get() == State::Running {
-            self.state.set(State::YieldedFor(upcall_id));
+            self.set_state(State::YieldedFor(upcall_id));
         }
     }
 
     fn stop(&self) {
         match self.state.get() {
-            State::Running => self.state.set(State::Stopped(StoppedState::Running)),
-            State::Yielded => self.state.set(State::Stopped(StoppedState::Yielded)),
-            State::YieldedFor(upcall_id) => self
-                .state
-                .set(State::Stopped(StoppedState::YieldedFor(upcall_id))),
+            State::Running => self.set_state(State::Stopped(StoppedState::Running)),
+            State::Yielded => self.set_state(State::Stopped(StoppedState::Yielded)),
+            State::YieldedFor(upcall_id) => {
+                self.set_state(State::Stopped(StoppedState::YieldedFor(upcall_id)))
+            }
             State::Stopped(_stopped_state) => {
                 // Already stopped, nothing to do.
             }
@@ -620,9 +651,9 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
     fn resume(&self) {
         if let State::Stopped(stopped_state) = self.state.get() {
             match stopped_state {
-                StoppedState::Running => self.state.set(State::Running),
-                StoppedState::Yielded => self.state.set(State::Yielded),
-                StoppedState::YieldedFor(upcall_id) => self.state.set(State::YieldedFor(upcall_id)),
+                StoppedState::Running => self.set_state(State::Running),
+                StoppedState::Yielded => self.set_state(State::Yielded),
+                StoppedState::YieldedFor(upcall_id) => self.set_state(State::YieldedFor(upcall_id)),
             }
         }
     }
@@ -634,7 +665,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         match action {
             FaultAction::Panic => {
                 // process faulted. Panic and print status
-                self.state.set(State::Faulted);
+                self.set_state(State::Faulted);
                 panic!("Process {} had a fault", self.get_process_name());
             }
             FaultAction::Restart => {
@@ -647,7 +678,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
                 // clearing all of the grant regions will cause capsules to drop
                 // this app as well.
                 self.terminate(None);
-                self.state.set(State::Faulted);
+                self.set_state(State::Faulted);
             }
         }
     }
@@ -660,7 +691,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
 
         // Reset to start the process.
         if let Ok(()) = self.reset() {
-            self.state.set(State::Yielded);
+            self.set_state(State::Yielded);
         }
     }
 
@@ -678,7 +709,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         // If there is a kernel policy that controls restarts, it should be
         // implemented here. For now, always restart.
         if let Ok(()) = self.reset() {
-            self.state.set(State::Yielded);
+            self.set_state(State::Yielded);
         }
 
         // Decide what to do with res later. E.g., if we can't restart
@@ -710,7 +741,8 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         self.completion_code.set(completion_code);
 
         // Mark the app as stopped so the scheduler won't try to run it.
-        self.state.set(State::Terminated);
+        self.set_state(State::Terminated);
+        kernel_debug_assert!(!self.has_tasks(), "terminated processes have no tasks");
     }
 
     fn get_restart_count(&self) -> usize {
@@ -1144,6 +1176,10 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
                     Some(grant_entry) => {
                         // Get a copy of the actual grant pointer.
                         let grant_ptr = grant_entry.grant_ptr;
+                        kernel_debug_assert!(
+                            !grant_ptr.is_null(),
+                            "only allocated grants are entered"
+                        );
 
                         // Check if the grant pointer is marked that the grant
                         // has already been entered. If so, return an error.
@@ -1196,6 +1232,10 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
             if let Some(grant_entry) = grant_pointers.get_mut(grant_num) {
                 // Get a copy of the actual grant pointer.
                 let grant_ptr = grant_entry.grant_ptr;
+                kernel_debug_assert!(
+                    (grant_ptr as usize) & 0x1 == 0x1,
+                    "only entered grants are left"
+                );
 
                 // Now, to mark that the grant has been released, we set the
                 // lowest bit back to zero and save this as the grant
@@ -1255,6 +1295,8 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
     }
 
     fn set_syscall_return_value(&self, return_value: SyscallReturn) {
+        syscall_trace::report_return(self.processid(), return_value);
+
         match self.stored_state.map(|stored_state| unsafe {
             // Actually set the return value for a particular process.
             //
@@ -1279,7 +1321,11 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
                 // be moved to the running state having called Yield-WaitFor and
                 // now needing to be resumed. Either way we can set the state to
                 // running.
-                self.state.set(State::Running);
+                kernel_debug_assert!(
+                    matches!(self.state.get(), State::Running | State::YieldedFor(_)),
+                    "syscall returns only to a running or yielded-for process"
+                );
+                self.set_state(State::Running);
             }
 
             Some(Err(())) => {
@@ -1298,6 +1344,8 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
     }
 
     fn set_process_function(&self, callback: FunctionCall) {
+        syscall_trace::report_function_call(self.processid(), callback);
+
         // See if we can actually enqueue this function for this process.
         // Architecture-specific code handles actually doing this since the
         // exact method is both architecture- and implementation-specific.
@@ -1324,7 +1372,11 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
 
                 // Move this process to the "running" state so the scheduler
                 // will schedule it.
-                self.state.set(State::Running);
+                kernel_debug_assert!(
+                    matches!(self.state.get(), State::Yielded | State::YieldedFor(_)),
+                    "upcalls run only in a yielded process"
+                );
+                self.set_state(State::Running);
             }
 
             Some(Err(())) => {
@@ -1389,6 +1441,14 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         self.debug.increment_timeslice_expiration_count();
     }
 
+    fn debug_executed_time_us(&self) -> u64 {
+        self.debug.get_executed_time_us()
+    }
+
+    fn debug_time_executed(&self, us: u32) {
+        self.debug.add_executed_time(us);
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.increment_syscall_count();
         self.debug.set_last_syscall(last_syscall);
@@ -1516,6 +1576,35 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         }
     }
 
+    fn debug_snapshot(&self) -> ProcessSnapshot {
+        let pointers = self.stored_state.and_then(|stored_state| {
+            // We guarantee the memory bounds pointers provided to the UKB are
+            // correct.
+            unsafe {
+                self.chip.userspace_kernel_boundary().process_pointers(
+                    self.mem_start(),
+                    self.app_break.get(),
+                    stored_state,
+                )
+            }
+        });
+        let mut mpu_regions = [None; MAX_ADDED_MPU_REGIONS];
+        for (region, cell) in mpu_regions.iter_mut().zip(self.mpu_regions.iter()) {
+            *region = cell.get();
+        }
+
+        ProcessSnapshot {
+            processid: self.processid(),
+            state: self.state.get(),
+            pc: pointers.map(|(pc, _)| pc),
+            sp: pointers.map(|(_, sp)| sp),
+            restart_count: self.restart_count.get(),
+            grants_allocated: self.grant_allocated_count(),
+            grant_region_size: self.mem_end() as usize - self.kernel_memory_break() as usize,
+            mpu_regions,
+        }
+    }
+
     fn get_stored_state(&self, out: &mut [u8]) -> Result<usize, ErrorCode> {
         self.stored_state
             .map(|stored_state| {
@@ -2021,6 +2110,7 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         self.debug.reset_syscall_count();
         self.debug.reset_dropped_upcall_count();
         self.debug.reset_timeslice_expiration_count();
+        self.debug.reset_executed_time();
 
         // Reset MPU region configuration.
         //
@@ -2036,6 +2126,12 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         let mut mpu_config = self.mpu_config.take().ok_or(ErrorCode::FAIL)?;
         self.chip.mpu().reset_config(&mut mpu_config);
 
+        // Regions added with `add_mpu_region()` are gone from the reset
+        // configuration, so forget them as well.
+        for region in self.mpu_regions.iter() {
+            region.set(None);
+        }
+
         // Allocate MPU region for flash.
         let app_mpu_flash = self.chip.mpu().allocate_region(
             self.flash.as_ptr(),
@@ -2134,7 +2230,7 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         self.restart_count.increment();
 
         // Mark the state as `Yielded` for the scheduler.
-        self.state.set(State::Yielded);
+        self.set_state(State::Yielded);
 
         // And queue up this app to be restarted.
         let flash_start = self.flash_start();
@@ -2200,6 +2296,22 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
             && buf_end_addr <= self.flash_end()
     }
 
+    /// Moves the process to `state`.
+    ///
+    /// In kernels built with the `kernel_test` feature, the change is
+    /// reported to the invariant client and checked against the process state
+    /// machine.
+    fn set_state(&self, state: State) {
+        let previous = self.state.replace(state);
+        if config::CONFIG.kernel_test && previous != state {
+            invariant::report_transition(self.processid(), previous, state);
+            kernel_debug_assert!(
+                previous.can_transition_to(state),
+                "process state changes follow the process state machine"
+            );
+        }
+    }
+
     /// Reset all `grant_ptr`s to NULL.
     unsafe fn grant_ptrs_reset(&self) {
         self.grant_pointers.map(|grant_pointers| {
@@ -13,6 +13,13 @@
 //! process running to not be the highest priority process at any point while it
 //! is running. The only way for a process to longer be the highest priority is
 //! for an interrupt to occur, which will cause the process to stop running.
+//!
+//! The scheduler does not implement priority inheritance or any other protocol
+//! against priority inversion. If a low priority process holds a resource a
+//! high priority process waits for, for example exclusive use of a capsule,
+//! any ready process of a priority in between runs instead of the low priority
+//! process, and the high priority process waits for as long as that process
+//! keeps running.
 
 use crate::deferred_call::DeferredCall;
 use crate::kernel::Kernel;
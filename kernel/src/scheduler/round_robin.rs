@@ -22,6 +22,7 @@ use core::cell::Cell;
 use core::num::NonZeroU32;
 
 use crate::collections::list::{List, ListLink, ListNode};
+use crate::invariant::kernel_debug_assert;
 use crate::platform::chip::Chip;
 use crate::process::ProcessSlot;
 use crate::process::StoppedExecutingReason;
@@ -141,6 +142,12 @@ impl<C: Chip> Scheduler<C> for RoundRobinSched<'_> {
             }
             _ => false,
         };
+        kernel_debug_assert!(
+            self.processes
+                .head()
+                .is_some_and(|node| node.proc.get().is_some()),
+            "the process that ran is at the head of the round robin queue"
+        );
         self.last_rescheduled.set(reschedule);
         if !reschedule {
             self.processes.push_tail(self.processes.pop_head().unwrap());
@@ -712,4 +712,23 @@ pub trait UserspaceKernelBoundary {
     /// Store architecture specific (e.g. CPU registers or status flags) data
     /// for a process. On success returns the number of elements written to out.
     fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Return the program counter and the stack pointer of a process
+    /// identified by the stored state for that process, for debugging, or
+    /// `None` if the architecture does not report them.
+    ///
+    /// ### Safety
+    ///
+    /// This function guarantees that it if needs to read process memory, it
+    /// will only read memory starting at `accessible_memory_start` and before
+    /// `app_brk`. The caller is responsible for guaranteeing that those
+    /// pointers are valid for the process.
+    unsafe fn process_pointers(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &Self::StoredState,
+    ) -> Option<(usize, usize)> {
+        None
+    }
 }
@@ -0,0 +1,103 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Tracing of the system calls of one process for test builds.
+//!
+//! With the `kernel_test` feature of the kernel crate, the kernel reports
+//! each system call the traced process makes, the value it returns for it,
+//! and each function call it pushes to the process, to the client a board
+//! sets. A test harness records them to check the exact exchange between the
+//! kernel and an app. In other builds the reports are compiled out.
+//!
+//! ```ignore
+//! kernel::syscall_trace::set_syscall_trace_client(
+//!     recorder,
+//!     create_capability!(kernel::capabilities::SyscallTraceCapability),
+//! );
+//! kernel::syscall_trace::trace_process(
+//!     Some(processid),
+//!     create_capability!(kernel::capabilities::SyscallTraceCapability),
+//! );
+//! ```
+//!
+//! Only one process is traced at a time, and the client runs in the middle
+//! of the kernel handling the call, so it must only record what it sees.
+
+use core::ptr::addr_of;
+
+use crate::capabilities::SyscallTraceCapability;
+use crate::config;
+use crate::process::{FunctionCall, ProcessId};
+use crate::syscall::{Syscall, SyscallReturn};
+
+/// Receives the system calls of the traced process.
+pub trait SyscallTraceClient {
+    /// Called when the process makes `syscall`, before the kernel handles it.
+    fn syscall(&self, processid: ProcessId, syscall: Syscall);
+
+    /// Called when the kernel sets `return_value` as the result of the last
+    /// system call of the process.
+    fn syscall_returned(&self, processid: ProcessId, return_value: SyscallReturn);
+
+    /// Called when the kernel pushes `function_call`, such as an upcall, to
+    /// the process.
+    fn function_called(&self, _processid: ProcessId, _function_call: FunctionCall) {}
+}
+
+/// Client the kernel reports the system calls to.
+static mut TRACE_CLIENT: Option<&'static dyn SyscallTraceClient> = None;
+
+/// The process whose system calls are reported.
+static mut TRACED_PROCESS: Option<ProcessId> = None;
+
+/// Function used by board main.rs to receive the system calls of the traced
+/// process.
+pub fn set_syscall_trace_client<C: SyscallTraceCapability>(
+    client: &'static dyn SyscallTraceClient,
+    _cap: C,
+) {
+    unsafe {
+        TRACE_CLIENT = Some(client);
+    }
+}
+
+/// Traces the system calls of `processid` from now on, instead of those of
+/// the process traced so far, or stops tracing with `None`.
+pub fn trace_process<C: SyscallTraceCapability>(processid: Option<ProcessId>, _cap: C) {
+    unsafe {
+        TRACED_PROCESS = processid;
+    }
+}
+
+/// The client, if `processid` is traced.
+fn traced_client(processid: ProcessId) -> Option<&'static dyn SyscallTraceClient> {
+    if !config::CONFIG.kernel_test {
+        return None;
+    }
+    if unsafe { *addr_of!(TRACED_PROCESS) } != Some(processid) {
+        return None;
+    }
+    unsafe { *addr_of!(TRACE_CLIENT) }
+}
+
+/// Reports a system call of a process.
+pub(crate) fn report_syscall(processid: ProcessId, syscall: Syscall) {
+    if let Some(client) = traced_client(processid) {
+        client.syscall(processid, syscall);
+    }
+}
+
+/// Reports the return value of a system call of a process.
+pub(crate) fn report_return(processid: ProcessId, return_value: SyscallReturn) {
+    if let Some(client) = traced_client(processid) {
+        client.syscall_returned(processid, return_value);
+    }
+}
+
+/// Reports a function call pushed to a process.
+pub(crate) fn report_function_call(processid: ProcessId, function_call: FunctionCall) {
+    if let Some(client) = traced_client(processid) {
+        client.function_called(processid, function_call);
+    }
+}
@@ -0,0 +1,14 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Support for tests of kernel components and their implementations.
+
+pub mod bench;
+pub mod rng;
+pub mod vectors;
+
+/// Whether the kernel is built for a test kernel, with the `kernel_test`
+/// feature. Test layers outside the kernel crate, such as fault injection,
+/// only act when it is set.
+pub const KERNEL_TEST: bool = crate::config::CONFIG.kernel_test;
@@ -0,0 +1,102 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Reproducible pseudo-random data for tests.
+//!
+//! `TestRng` is xoshiro128++, a small and fast generator that is not suitable
+//! for cryptography. A 64-bit seed determines everything it returns, so a
+//! test that draws its inputs from it and prints the seed when it fails can
+//! be run again with exactly the same inputs:
+//!
+//! ```rust,ignore
+//! let rng = TestRng::new(seed);
+//! let len = rng.below(64) as usize + 1;
+//! rng.fill(&mut buffer[..len]);
+//! ```
+//!
+//! The sequence for a seed is part of the interface, and does not change
+//! between releases.
+
+use core::cell::Cell;
+
+pub struct TestRng {
+    state: Cell<[u32; 4]>,
+}
+
+impl TestRng {
+    /// Expands `seed` into the generator state with SplitMix64, as the
+    /// authors of xoshiro recommend, so similar seeds give unrelated
+    /// sequences.
+    pub const fn new(seed: u64) -> Self {
+        let (seed, low) = splitmix64(seed);
+        let (_, high) = splitmix64(seed);
+        TestRng {
+            state: Cell::new([
+                low as u32,
+                (low >> 32) as u32,
+                high as u32,
+                (high >> 32) as u32,
+            ]),
+        }
+    }
+
+    pub fn next_u32(&self) -> u32 {
+        let mut s = self.state.get();
+        let result = s[0].wrapping_add(s[3]).rotate_left(7).wrapping_add(s[0]);
+        let t = s[1] << 9;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(11);
+        self.state.set(s);
+        result
+    }
+
+    /// Returns a number less than `bound`, which must not be 0. The bias
+    /// towards some numbers is too small to matter for tests.
+    pub fn below(&self, bound: u32) -> u32 {
+        ((u64::from(self.next_u32()) * u64::from(bound)) >> 32) as u32
+    }
+
+    /// Returns `true` with a probability of one in `chance`.
+    pub fn one_in(&self, chance: u32) -> bool {
+        self.below(chance) == 0
+    }
+
+    pub fn fill(&self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Advances the SplitMix64 state `x`, returning the new state and the output.
+const fn splitmix64(x: u64) -> (u64, u64) {
+    let x = x.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (x, z ^ (z >> 31))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence() {
+        let rng = TestRng::new(0x0123456789abcdef);
+        let values: [u32; 4] = core::array::from_fn(|_| rng.next_u32());
+        assert_eq!(values, [0xa60e46da, 0x5259c0d8, 0x7ddfa05f, 0x21731e45]);
+
+        let rng = TestRng::new(0);
+        let mut bytes = [0; 6];
+        rng.fill(&mut bytes);
+        assert_eq!(bytes, [0xa3, 0xda, 0x53, 0x46, 0x58, 0x2b]);
+        assert!((0..100).all(|_| rng.below(3) < 3));
+    }
+}
@@ -0,0 +1,194 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! AES-128 in the block cipher modes and in GCM.
+//!
+//! The block cipher mode vectors are the examples of NIST SP 800-38A, which
+//! encrypt the same four blocks with the same key in every mode, and the
+//! example of FIPS 197 appendix C.1. The GCM vectors are from Project
+//! Wycheproof.
+
+use crate::hil::symmetric_encryption::{AES128_BLOCK_SIZE, AES128_KEY_SIZE};
+
+/// Block cipher mode of an `Aes128Vector`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aes128Mode {
+    Ecb,
+    Cbc,
+    Ctr,
+}
+
+/// Encryption of `plaintext` with AES-128 in a block cipher mode.
+#[derive(Clone, Copy, Debug)]
+pub struct Aes128Vector {
+    pub mode: Aes128Mode,
+    pub key: &'static [u8; AES128_KEY_SIZE],
+    /// Initialization vector for CBC, initial counter block for CTR, and
+    /// empty for ECB.
+    pub iv: &'static [u8],
+    pub plaintext: &'static [u8],
+    pub ciphertext: &'static [u8],
+}
+
+/// Authenticated encryption of `plaintext` and `aad` with AES-128-GCM.
+#[derive(Clone, Copy, Debug)]
+pub struct Aes128GcmVector {
+    pub key: &'static [u8; AES128_KEY_SIZE],
+    pub iv: &'static [u8],
+    pub aad: &'static [u8],
+    pub plaintext: &'static [u8],
+    pub ciphertext: &'static [u8],
+    /// The tag, which some vectors truncate.
+    pub tag: &'static [u8],
+}
+
+/// ECB-AES128 of NIST SP 800-38A, F.1.1.
+pub static SP800_38A_ECB: Aes128Vector = Aes128Vector {
+    mode: Aes128Mode::Ecb,
+    key: &SP800_38A_KEY,
+    iv: &[],
+    plaintext: &SP800_38A_PLAINTEXT,
+    ciphertext: &[
+        0x3a, 0xd7, 0x7b, 0xb4, 0x0d, 0x7a, 0x36, 0x60, 0xa8, 0x9e, 0xca, 0xf3, 0x24, 0x66, 0xef,
+        0x97, 0xf5, 0xd3, 0xd5, 0x85, 0x03, 0xb9, 0x69, 0x9d, 0xe7, 0x85, 0x89, 0x5a, 0x96, 0xfd,
+        0xba, 0xaf, 0x43, 0xb1, 0xcd, 0x7f, 0x59, 0x8e, 0xce, 0x23, 0x88, 0x1b, 0x00, 0xe3, 0xed,
+        0x03, 0x06, 0x88, 0x7b, 0x0c, 0x78, 0x5e, 0x27, 0xe8, 0xad, 0x3f, 0x82, 0x23, 0x20, 0x71,
+        0x04, 0x72, 0x5d, 0xd4,
+    ],
+};
+
+/// CBC-AES128 of NIST SP 800-38A, F.2.1.
+pub static SP800_38A_CBC: Aes128Vector = Aes128Vector {
+    mode: Aes128Mode::Cbc,
+    key: &SP800_38A_KEY,
+    iv: &[
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ],
+    plaintext: &SP800_38A_PLAINTEXT,
+    ciphertext: &[
+        0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19,
+        0x7d, 0x50, 0x86, 0xcb, 0x9b, 0x50, 0x72, 0x19, 0xee, 0x95, 0xdb, 0x11, 0x3a, 0x91, 0x76,
+        0x78, 0xb2, 0x73, 0xbe, 0xd6, 0xb8, 0xe3, 0xc1, 0x74, 0x3b, 0x71, 0x16, 0xe6, 0x9e, 0x22,
+        0x22, 0x95, 0x16, 0x3f, 0xf1, 0xca, 0xa1, 0x68, 0x1f, 0xac, 0x09, 0x12, 0x0e, 0xca, 0x30,
+        0x75, 0x86, 0xe1, 0xa7,
+    ],
+};
+
+/// CTR-AES128 of NIST SP 800-38A, F.5.1.
+pub static SP800_38A_CTR: Aes128Vector = Aes128Vector {
+    mode: Aes128Mode::Ctr,
+    key: &SP800_38A_KEY,
+    iv: &[
+        0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe,
+        0xff,
+    ],
+    plaintext: &SP800_38A_PLAINTEXT,
+    ciphertext: &[
+        0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6,
+        0xce, 0x98, 0x06, 0xf6, 0x6b, 0x79, 0x70, 0xfd, 0xff, 0x86, 0x17, 0x18, 0x7b, 0xb9, 0xff,
+        0xfd, 0xff, 0x5a, 0xe4, 0xdf, 0x3e, 0xdb, 0xd5, 0xd3, 0x5e, 0x5b, 0x4f, 0x09, 0x02, 0x0d,
+        0xb0, 0x3e, 0xab, 0x1e, 0x03, 0x1d, 0xda, 0x2f, 0xbe, 0x03, 0xd1, 0x79, 0x21, 0x70, 0xa0,
+        0xf3, 0x00, 0x9c, 0xee,
+    ],
+};
+
+const SP800_38A_KEY: [u8; AES128_KEY_SIZE] = [
+    0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+];
+
+const SP800_38A_PLAINTEXT: [u8; 4 * AES128_BLOCK_SIZE] = [
+    0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+    0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf, 0x8e, 0x51,
+    0x30, 0xc8, 0x1c, 0x46, 0xa3, 0x5c, 0xe4, 0x11, 0xe5, 0xfb, 0xc1, 0x19, 0x1a, 0x0a, 0x52, 0xef,
+    0xf6, 0x9f, 0x24, 0x45, 0xdf, 0x4f, 0x9b, 0x17, 0xad, 0x2b, 0x41, 0x7b, 0xe6, 0x6c, 0x37, 0x10,
+];
+
+static AES128: [&Aes128Vector; 4] = [
+    &SP800_38A_ECB,
+    &Aes128Vector {
+        mode: Aes128Mode::Ecb,
+        key: &[
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ],
+        iv: &[],
+        plaintext: &[
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ],
+        ciphertext: &[
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a,
+        ],
+    },
+    &SP800_38A_CBC,
+    &SP800_38A_CTR,
+];
+
+static AES128_GCM: [Aes128GcmVector; 2] = [
+    // Wycheproof `aes_gcm_test.json`, tcId 12: authentication only, with a
+    // truncated tag.
+    Aes128GcmVector {
+        key: &[
+            0x26, 0x73, 0x0f, 0x1a, 0xd2, 0x4b, 0x76, 0xd6, 0x6f, 0x7a, 0xb8, 0x45, 0x9d, 0xdc,
+            0xd1, 0x17,
+        ],
+        iv: &[
+            0x1f, 0xfb, 0x3e, 0x75, 0x71, 0xcb, 0x70, 0x14, 0x5e, 0xa5, 0x16, 0x53,
+        ],
+        aad: &[
+            0xbf, 0xc3, 0xa8, 0x08, 0xc0, 0x60, 0xcd, 0xfd, 0x2a, 0xb7, 0x69, 0x1b, 0x32, 0x4a,
+            0xb3, 0x59, 0x29, 0xe8, 0x0f, 0x26, 0x2b, 0xf3, 0xb9, 0x4c, 0xc2, 0xf4, 0x5c, 0x62,
+            0xbb, 0x0f, 0x32, 0xbc, 0x4e, 0x4b, 0x96, 0x73, 0x69, 0x11, 0x0a, 0x7b, 0x4c, 0x47,
+            0x82, 0x7e, 0x93, 0xa9, 0xec, 0xd7, 0xfc, 0xda, 0x5e, 0x6a, 0x97, 0x39, 0xa0, 0xd1,
+            0x78, 0x6d, 0x6d, 0xc7, 0xa4, 0x5c, 0x9c, 0x1e, 0x8e, 0xcc, 0x8f, 0x90, 0xdc, 0x70,
+            0xbc, 0x5a, 0x5a, 0xe1, 0xa0, 0x31, 0x3f, 0xd6, 0xef, 0x87, 0xd7, 0xb3, 0x6e, 0x3d,
+            0x48, 0xc4, 0x44, 0x8f, 0x70, 0x3e,
+        ],
+        plaintext: &[],
+        ciphertext: &[],
+        tag: &[
+            0x45, 0xa9, 0xbe, 0x4c, 0x84, 0x9e, 0xcb, 0x25, 0x85, 0x42, 0x1a, 0x1f, 0x08, 0xe6,
+        ],
+    },
+    // Wycheproof `aes_gcm_test.json`, tcId 13.
+    Aes128GcmVector {
+        key: &[
+            0x8f, 0x85, 0xd3, 0x66, 0x16, 0xa9, 0x5f, 0xc1, 0x05, 0x86, 0xc3, 0x16, 0xb3, 0x05,
+            0x37, 0x70,
+        ],
+        iv: &[
+            0xd3, 0x20, 0xb5, 0x00, 0x26, 0x96, 0x09, 0xac, 0xe1, 0xbe, 0x67, 0xce,
+        ],
+        aad: &[],
+        plaintext: &[
+            0x3a, 0x75, 0x8e, 0xe0, 0x72, 0xfc, 0x70, 0xa6, 0x42, 0x75, 0xb5, 0x6e, 0x72, 0xcb,
+            0x23, 0xa1, 0x59, 0x04, 0x58, 0x9c, 0xef, 0xbe, 0xeb, 0x58, 0x48, 0xec, 0x53, 0xff,
+            0xc0, 0x6c, 0x7a, 0x5d,
+        ],
+        ciphertext: &[
+            0xfb, 0x2f, 0xe3, 0xeb, 0x40, 0xed, 0xfb, 0xd2, 0x2a, 0x51, 0x6b, 0xec, 0x35, 0x9d,
+            0x4b, 0xb4, 0x23, 0x8a, 0x07, 0x00, 0xa4, 0x6f, 0xee, 0x11, 0x36, 0xa0, 0x61, 0x85,
+            0x40, 0x22, 0x9c, 0x41,
+        ],
+        tag: &[
+            0x42, 0x26, 0x93, 0x16, 0xce, 0xce, 0x7d, 0x88, 0x2c, 0xc6, 0x8c, 0x3e, 0xd9, 0xd2,
+            0xf0, 0xae,
+        ],
+    },
+];
+
+/// Returns the vectors of AES-128 in `mode`.
+pub fn aes128(mode: Aes128Mode) -> impl Iterator<Item = &'static Aes128Vector> {
+    AES128
+        .iter()
+        .copied()
+        .filter(move |vector| vector.mode == mode)
+}
+
+/// Returns the vectors of AES-128-GCM.
+pub fn aes128_gcm() -> &'static [Aes128GcmVector] {
+    &AES128_GCM
+}
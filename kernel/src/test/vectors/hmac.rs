@@ -0,0 +1,191 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! HMAC with the SHA-2 family.
+//!
+//! The vectors are test cases 1, 2, 3 and 6 of RFC 4231, which have keys
+//! shorter than a block and a key longer than one, and the HMAC-SHA-256
+//! example of Wikipedia.
+
+use super::sha2::Sha2;
+use super::Message;
+
+/// MAC of `message` under `key` with HMAC-`algorithm`.
+#[derive(Clone, Copy, Debug)]
+pub struct HmacVector {
+    pub algorithm: Sha2,
+    pub key: Message,
+    pub message: Message,
+    pub mac: &'static [u8],
+}
+
+/// HMAC-SHA-256 of RFC 4231 test case 2, with a key shorter than the MAC.
+pub static RFC4231_CASE_2_SHA256: HmacVector = HmacVector {
+    algorithm: Sha2::Sha256,
+    key: Message::bytes(b"Jefe"),
+    message: Message::bytes(b"what do ya want for nothing?"),
+    mac: &[
+        0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95, 0x75,
+        0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9, 0x64, 0xec,
+        0x38, 0x43,
+    ],
+};
+
+/// HMAC-SHA-256 of the pangram under `"key"`, the example of
+/// <https://en.wikipedia.org/wiki/HMAC#Examples>.
+pub static WIKIPEDIA_SHA256: HmacVector = HmacVector {
+    algorithm: Sha2::Sha256,
+    key: Message::bytes(b"key"),
+    message: Message::bytes(b"The quick brown fox jumps over the lazy dog"),
+    mac: &[
+        0xf7, 0xbc, 0x83, 0xf4, 0x30, 0x53, 0x84, 0x24, 0xb1, 0x32, 0x98, 0xe6, 0xaa, 0x6f, 0xb1,
+        0x43, 0xef, 0x4d, 0x59, 0xa1, 0x49, 0x46, 0x17, 0x59, 0x97, 0x47, 0x9d, 0xbc, 0x2d, 0x1a,
+        0x3c, 0xd8,
+    ],
+};
+
+static HMAC: [&HmacVector; 13] = [
+    // Test case 1.
+    &HmacVector {
+        algorithm: Sha2::Sha256,
+        key: Message::repeated(&[0x0b], 20),
+        message: Message::bytes(b"Hi There"),
+        mac: &[
+            0xb0, 0x34, 0x4c, 0x61, 0xd8, 0xdb, 0x38, 0x53, 0x5c, 0xa8, 0xaf, 0xce, 0xaf, 0x0b,
+            0xf1, 0x2b, 0x88, 0x1d, 0xc2, 0x00, 0xc9, 0x83, 0x3d, 0xa7, 0x26, 0xe9, 0x37, 0x6c,
+            0x2e, 0x32, 0xcf, 0xf7,
+        ],
+    },
+    &RFC4231_CASE_2_SHA256,
+    // Test case 3.
+    &HmacVector {
+        algorithm: Sha2::Sha256,
+        key: Message::repeated(&[0xaa], 20),
+        message: Message::repeated(&[0xdd], 50),
+        mac: &[
+            0x77, 0x3e, 0xa9, 0x1e, 0x36, 0x80, 0x0e, 0x46, 0x85, 0x4d, 0xb8, 0xeb, 0xd0, 0x91,
+            0x81, 0xa7, 0x29, 0x59, 0x09, 0x8b, 0x3e, 0xf8, 0xc1, 0x22, 0xd9, 0x63, 0x55, 0x14,
+            0xce, 0xd5, 0x65, 0xfe,
+        ],
+    },
+    // Test case 6.
+    &HmacVector {
+        algorithm: Sha2::Sha256,
+        key: Message::repeated(&[0xaa], 131),
+        message: Message::bytes(b"Test Using Larger Than Block-Size Key - Hash Key First"),
+        mac: &[
+            0x60, 0xe4, 0x31, 0x59, 0x1e, 0xe0, 0xb6, 0x7f, 0x0d, 0x8a, 0x26, 0xaa, 0xcb, 0xf5,
+            0xb7, 0x7f, 0x8e, 0x0b, 0xc6, 0x21, 0x37, 0x28, 0xc5, 0x14, 0x05, 0x46, 0x04, 0x0f,
+            0x0e, 0xe3, 0x7f, 0x54,
+        ],
+    },
+    &WIKIPEDIA_SHA256,
+    // Test case 1.
+    &HmacVector {
+        algorithm: Sha2::Sha384,
+        key: Message::repeated(&[0x0b], 20),
+        message: Message::bytes(b"Hi There"),
+        mac: &[
+            0xaf, 0xd0, 0x39, 0x44, 0xd8, 0x48, 0x95, 0x62, 0x6b, 0x08, 0x25, 0xf4, 0xab, 0x46,
+            0x90, 0x7f, 0x15, 0xf9, 0xda, 0xdb, 0xe4, 0x10, 0x1e, 0xc6, 0x82, 0xaa, 0x03, 0x4c,
+            0x7c, 0xeb, 0xc5, 0x9c, 0xfa, 0xea, 0x9e, 0xa9, 0x07, 0x6e, 0xde, 0x7f, 0x4a, 0xf1,
+            0x52, 0xe8, 0xb2, 0xfa, 0x9c, 0xb6,
+        ],
+    },
+    // Test case 2.
+    &HmacVector {
+        algorithm: Sha2::Sha384,
+        key: Message::bytes(b"Jefe"),
+        message: Message::bytes(b"what do ya want for nothing?"),
+        mac: &[
+            0xaf, 0x45, 0xd2, 0xe3, 0x76, 0x48, 0x40, 0x31, 0x61, 0x7f, 0x78, 0xd2, 0xb5, 0x8a,
+            0x6b, 0x1b, 0x9c, 0x7e, 0xf4, 0x64, 0xf5, 0xa0, 0x1b, 0x47, 0xe4, 0x2e, 0xc3, 0x73,
+            0x63, 0x22, 0x44, 0x5e, 0x8e, 0x22, 0x40, 0xca, 0x5e, 0x69, 0xe2, 0xc7, 0x8b, 0x32,
+            0x39, 0xec, 0xfa, 0xb2, 0x16, 0x49,
+        ],
+    },
+    // Test case 3.
+    &HmacVector {
+        algorithm: Sha2::Sha384,
+        key: Message::repeated(&[0xaa], 20),
+        message: Message::repeated(&[0xdd], 50),
+        mac: &[
+            0x88, 0x06, 0x26, 0x08, 0xd3, 0xe6, 0xad, 0x8a, 0x0a, 0xa2, 0xac, 0xe0, 0x14, 0xc8,
+            0xa8, 0x6f, 0x0a, 0xa6, 0x35, 0xd9, 0x47, 0xac, 0x9f, 0xeb, 0xe8, 0x3e, 0xf4, 0xe5,
+            0x59, 0x66, 0x14, 0x4b, 0x2a, 0x5a, 0xb3, 0x9d, 0xc1, 0x38, 0x14, 0xb9, 0x4e, 0x3a,
+            0xb6, 0xe1, 0x01, 0xa3, 0x4f, 0x27,
+        ],
+    },
+    // Test case 6.
+    &HmacVector {
+        algorithm: Sha2::Sha384,
+        key: Message::repeated(&[0xaa], 131),
+        message: Message::bytes(b"Test Using Larger Than Block-Size Key - Hash Key First"),
+        mac: &[
+            0x4e, 0xce, 0x08, 0x44, 0x85, 0x81, 0x3e, 0x90, 0x88, 0xd2, 0xc6, 0x3a, 0x04, 0x1b,
+            0xc5, 0xb4, 0x4f, 0x9e, 0xf1, 0x01, 0x2a, 0x2b, 0x58, 0x8f, 0x3c, 0xd1, 0x1f, 0x05,
+            0x03, 0x3a, 0xc4, 0xc6, 0x0c, 0x2e, 0xf6, 0xab, 0x40, 0x30, 0xfe, 0x82, 0x96, 0x24,
+            0x8d, 0xf1, 0x63, 0xf4, 0x49, 0x52,
+        ],
+    },
+    // Test case 1.
+    &HmacVector {
+        algorithm: Sha2::Sha512,
+        key: Message::repeated(&[0x0b], 20),
+        message: Message::bytes(b"Hi There"),
+        mac: &[
+            0x87, 0xaa, 0x7c, 0xde, 0xa5, 0xef, 0x61, 0x9d, 0x4f, 0xf0, 0xb4, 0x24, 0x1a, 0x1d,
+            0x6c, 0xb0, 0x23, 0x79, 0xf4, 0xe2, 0xce, 0x4e, 0xc2, 0x78, 0x7a, 0xd0, 0xb3, 0x05,
+            0x45, 0xe1, 0x7c, 0xde, 0xda, 0xa8, 0x33, 0xb7, 0xd6, 0xb8, 0xa7, 0x02, 0x03, 0x8b,
+            0x27, 0x4e, 0xae, 0xa3, 0xf4, 0xe4, 0xbe, 0x9d, 0x91, 0x4e, 0xeb, 0x61, 0xf1, 0x70,
+            0x2e, 0x69, 0x6c, 0x20, 0x3a, 0x12, 0x68, 0x54,
+        ],
+    },
+    // Test case 2.
+    &HmacVector {
+        algorithm: Sha2::Sha512,
+        key: Message::bytes(b"Jefe"),
+        message: Message::bytes(b"what do ya want for nothing?"),
+        mac: &[
+            0x16, 0x4b, 0x7a, 0x7b, 0xfc, 0xf8, 0x19, 0xe2, 0xe3, 0x95, 0xfb, 0xe7, 0x3b, 0x56,
+            0xe0, 0xa3, 0x87, 0xbd, 0x64, 0x22, 0x2e, 0x83, 0x1f, 0xd6, 0x10, 0x27, 0x0c, 0xd7,
+            0xea, 0x25, 0x05, 0x54, 0x97, 0x58, 0xbf, 0x75, 0xc0, 0x5a, 0x99, 0x4a, 0x6d, 0x03,
+            0x4f, 0x65, 0xf8, 0xf0, 0xe6, 0xfd, 0xca, 0xea, 0xb1, 0xa3, 0x4d, 0x4a, 0x6b, 0x4b,
+            0x63, 0x6e, 0x07, 0x0a, 0x38, 0xbc, 0xe7, 0x37,
+        ],
+    },
+    // Test case 3.
+    &HmacVector {
+        algorithm: Sha2::Sha512,
+        key: Message::repeated(&[0xaa], 20),
+        message: Message::repeated(&[0xdd], 50),
+        mac: &[
+            0xfa, 0x73, 0xb0, 0x08, 0x9d, 0x56, 0xa2, 0x84, 0xef, 0xb0, 0xf0, 0x75, 0x6c, 0x89,
+            0x0b, 0xe9, 0xb1, 0xb5, 0xdb, 0xdd, 0x8e, 0xe8, 0x1a, 0x36, 0x55, 0xf8, 0x3e, 0x33,
+            0xb2, 0x27, 0x9d, 0x39, 0xbf, 0x3e, 0x84, 0x82, 0x79, 0xa7, 0x22, 0xc8, 0x06, 0xb4,
+            0x85, 0xa4, 0x7e, 0x67, 0xc8, 0x07, 0xb9, 0x46, 0xa3, 0x37, 0xbe, 0xe8, 0x94, 0x26,
+            0x74, 0x27, 0x88, 0x59, 0xe1, 0x32, 0x92, 0xfb,
+        ],
+    },
+    // Test case 6.
+    &HmacVector {
+        algorithm: Sha2::Sha512,
+        key: Message::repeated(&[0xaa], 131),
+        message: Message::bytes(b"Test Using Larger Than Block-Size Key - Hash Key First"),
+        mac: &[
+            0x80, 0xb2, 0x42, 0x63, 0xc7, 0xc1, 0xa3, 0xeb, 0xb7, 0x14, 0x93, 0xc1, 0xdd, 0x7b,
+            0xe8, 0xb4, 0x9b, 0x46, 0xd1, 0xf4, 0x1b, 0x4a, 0xee, 0xc1, 0x12, 0x1b, 0x01, 0x37,
+            0x83, 0xf8, 0xf3, 0x52, 0x6b, 0x56, 0xd0, 0x37, 0xe0, 0x5f, 0x25, 0x98, 0xbd, 0x0f,
+            0xd2, 0x21, 0x5d, 0x6a, 0x1e, 0x52, 0x95, 0xe6, 0x4f, 0x73, 0xf6, 0x3f, 0x0a, 0xec,
+            0x8b, 0x91, 0x5a, 0x98, 0x5d, 0x78, 0x65, 0x98,
+        ],
+    },
+];
+
+/// Returns the vectors of HMAC with `algorithm`.
+pub fn hmac(algorithm: Sha2) -> impl Iterator<Item = &'static HmacVector> {
+    HMAC.iter()
+        .copied()
+        .filter(move |vector| vector.algorithm == algorithm)
+}
@@ -0,0 +1,94 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Known-answer vectors for the cryptographic HILs.
+//!
+//! Every test of a cryptographic implementation takes its vectors from here,
+//! so the vectors are written down, and checked, once. Each module holds the
+//! vectors of one family of algorithms and returns those of an algorithm
+//! through an iterator:
+//!
+//! ```rust,ignore
+//! use kernel::test::vectors::sha2::{self, Sha2};
+//!
+//! for vector in sha2::sha2(Sha2::Sha256) {
+//!     // Hash `vector.message` and compare the digest to `vector.digest`.
+//! }
+//! ```
+//!
+//! The vectors a single test picks are also named statics, for example
+//! `sha2::SHA256_HELLO`. The HILs take mutable buffers, so tests copy the
+//! data of a vector into buffers of their own.
+
+pub mod aes;
+pub mod ecdsa;
+pub mod hmac;
+pub mod sha2;
+
+/// Message of a vector, stored as a pattern that is repeated so long
+/// messages, like the million `a`s of FIPS 180-2, take no space.
+#[derive(Clone, Copy, Debug)]
+pub struct Message {
+    pattern: &'static [u8],
+    repeat: usize,
+}
+
+impl Message {
+    /// Message of `bytes`.
+    pub const fn bytes(bytes: &'static [u8]) -> Self {
+        Message {
+            pattern: bytes,
+            repeat: 1,
+        }
+    }
+
+    /// Message of `repeat` copies of `pattern`.
+    pub const fn repeated(pattern: &'static [u8], repeat: usize) -> Self {
+        Message { pattern, repeat }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.pattern.len() * self.repeat
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the message in chunks of at most the length of the pattern,
+    /// for implementations that take a message in parts.
+    pub fn chunks(&self) -> impl Iterator<Item = &'static [u8]> {
+        core::iter::repeat_n(self.pattern, self.repeat)
+    }
+
+    /// Copies the message to the start of `buffer`. Returns the length of the
+    /// message, or `None` if `buffer` is too short for it.
+    pub fn copy_to(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.len();
+        let buffer = buffer.get_mut(..len)?;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.pattern[i % self.pattern.len()];
+        }
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_copy() {
+        let message = Message::repeated(b"ab", 3);
+        assert_eq!(message.len(), 6);
+
+        let mut buffer = [0; 8];
+        assert_eq!(message.copy_to(&mut buffer), Some(6));
+        assert_eq!(&buffer, b"ababab\0\0");
+        assert_eq!(message.copy_to(&mut buffer[..5]), None);
+
+        assert!(Message::bytes(b"").is_empty());
+        assert_eq!(Message::bytes(b"").copy_to(&mut []), Some(0));
+    }
+}
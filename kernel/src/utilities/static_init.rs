@@ -4,6 +4,10 @@
 
 //! Support for statically initializing objects in memory.
 
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::config;
+
 /// Allocates a statically-sized global array of memory and initializes the
 /// memory for a particular data structure.
 ///
@@ -74,6 +78,116 @@ pub fn static_buf_check_used(used: &mut bool) {
     }
 }
 
+/// Number of buffers the static allocation log holds. Buffers allocated after
+/// the log is full are only counted.
+const STATIC_ALLOCATION_LOG_LEN: usize = 256;
+
+/// A buffer allocated with [`static_buf!()`](crate::static_buf) or
+/// [`static_named_buf!()`](crate::static_named_buf).
+#[derive(Clone, Copy)]
+pub struct StaticAllocation {
+    /// Name of the type stored in the buffer.
+    pub type_name: &'static str,
+    /// Address of the buffer.
+    pub address: usize,
+    /// Size of the buffer in bytes, including the flag that tracks whether it
+    /// was used.
+    pub size: usize,
+}
+
+struct StaticAllocationLog {
+    entries: [StaticAllocation; STATIC_ALLOCATION_LOG_LEN],
+    len: usize,
+    dropped: usize,
+}
+
+static mut STATIC_ALLOCATION_LOG: StaticAllocationLog = StaticAllocationLog {
+    entries: [StaticAllocation {
+        type_name: "",
+        address: 0,
+        size: 0,
+    }; STATIC_ALLOCATION_LOG_LEN],
+    len: 0,
+    dropped: 0,
+};
+
+/// Internal helper function for [`static_buf!()`](crate::static_buf).
+///
+/// This must be public to work within the macro but should never be used
+/// directly.
+///
+/// Adds the buffer to the static allocation log if the kernel is built with
+/// the `debug_static_allocations` feature. Otherwise this is inlined as
+/// nothing, and the log is not included in the kernel.
+#[inline(always)]
+pub fn static_buf_record(type_name: &'static str, address: usize, size: usize) {
+    if config::CONFIG.debug_static_allocations {
+        static_buf_record_log(StaticAllocation {
+            type_name,
+            address,
+            size,
+        });
+    }
+}
+
+#[inline(never)]
+fn static_buf_record_log(allocation: StaticAllocation) {
+    // SAFETY: the kernel is single threaded and this is not reentrant, so
+    // there is no other reference to the log.
+    let log = unsafe { &mut *addr_of_mut!(STATIC_ALLOCATION_LOG) };
+    if log.len < STATIC_ALLOCATION_LOG_LEN {
+        log.entries[log.len] = allocation;
+        log.len += 1;
+    } else {
+        log.dropped += 1;
+    }
+}
+
+/// The static allocations recorded so far.
+#[derive(Clone, Copy)]
+pub struct StaticAllocations {
+    len: usize,
+    dropped: usize,
+}
+
+impl StaticAllocations {
+    /// Iterates over the recorded buffers, in the order they were allocated.
+    pub fn iter(&self) -> impl Iterator<Item = StaticAllocation> {
+        (0..self.len).map(|i| {
+            // SAFETY: as in `static_buf_record_log()`, and entries below
+            // `len` are never written again.
+            unsafe { (*addr_of!(STATIC_ALLOCATION_LOG)).entries[i] }
+        })
+    }
+
+    /// Total size of the recorded buffers in bytes.
+    pub fn total_size(&self) -> usize {
+        self.iter().map(|allocation| allocation.size).sum()
+    }
+
+    /// Number of buffers allocated after the log was full, which are missing
+    /// from [`StaticAllocations::iter()`].
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// Returns the static buffers allocated so far.
+///
+/// Returns `None` if the kernel is not built with the
+/// `debug_static_allocations` feature.
+pub fn static_allocations() -> Option<StaticAllocations> {
+    if !config::CONFIG.debug_static_allocations {
+        return None;
+    }
+    // SAFETY: as in `static_buf_record_log()`.
+    let log = unsafe { &*addr_of!(STATIC_ALLOCATION_LOG) };
+    Some(StaticAllocations {
+        len: log.len,
+        dropped: log.dropped,
+    })
+}
+
 /// Allocates an uninitialized statically-sized global region of memory for a
 /// data structure.
 ///
@@ -115,6 +229,14 @@ macro_rules! static_buf {
         // boolean to true otherwise.
         $crate::utilities::static_init::static_buf_check_used(&mut BUF.1);
 
+        // Record the buffer if the kernel is configured to do so. Otherwise
+        // this compiles to nothing.
+        $crate::utilities::static_init::static_buf_record(
+            core::any::type_name::<$T>(),
+            core::ptr::addr_of!(BUF) as usize,
+            core::mem::size_of::<(core::mem::MaybeUninit<$T>, bool)>(),
+        );
+
         // If we get to this point we can wrap our buffer to be eventually
         // initialized.
         &mut BUF.0
@@ -169,6 +291,14 @@ macro_rules! static_named_buf {
         // boolean to true otherwise.
         $crate::utilities::static_init::static_buf_check_used(&mut BUF.1);
 
+        // Record the buffer if the kernel is configured to do so. Otherwise
+        // this compiles to nothing.
+        $crate::utilities::static_init::static_buf_record(
+            core::any::type_name::<$T>(),
+            core::ptr::addr_of!(BUF) as usize,
+            core::mem::size_of::<(core::mem::MaybeUninit<$T>, bool)>(),
+        );
+
         // If we get to this point we can wrap our buffer to be eventually
         // initialized.
         &mut BUF.0
@@ -4,7 +4,10 @@
 
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
-use crate::tickv::{TicKV, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
+use crate::success_codes::SuccessCode;
+use crate::tickv::{
+    TicKV, CHECK_SUM_LEN, HASH_OFFSET, HEADER_LENGTH, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET,
+};
 use core::hash::{Hash, Hasher};
 use std::cell::Cell;
 use std::cell::RefCell;
@@ -665,4 +668,55 @@ mod no_check_store_flast_ctrl {
             Err(ErrorCode::KeyNotFound)
         );
     }
+
+    #[test]
+    fn test_bit_flip() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let value: [u8; 32] = [0x23; 32];
+        let mut buf: [u8; 32] = [0; 32];
+
+        println!("Add Key ONE");
+        tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
+
+        // Find the value of key ONE in flash
+        let (region, start) = (0..2)
+            .find_map(|region| {
+                tickv.controller.buf.borrow()[region]
+                    .windows(value.len())
+                    .position(|window| window == value)
+                    .map(|offset| (region, offset))
+            })
+            .unwrap();
+        assert!(start >= HEADER_LENGTH);
+
+        // Flip each bit of the value and of the check sum in turn. The check
+        // sum must catch every one of them.
+        for offset in start..(start + value.len() + CHECK_SUM_LEN) {
+            for bit in 0..8 {
+                tickv.controller.buf.borrow_mut()[region][offset] ^= 1 << bit;
+
+                println!("Get key ONE with bit {} of byte {} flipped", bit, offset);
+                assert_eq!(
+                    tickv.get_key(get_hashed_key(b"ONE"), &mut buf),
+                    Err(ErrorCode::InvalidCheckSum)
+                );
+
+                tickv.controller.buf.borrow_mut()[region][offset] ^= 1 << bit;
+            }
+        }
+
+        println!("Get key ONE");
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf),
+            Ok((SuccessCode::Complete, value.len()))
+        );
+        assert_eq!(buf, value);
+    }
 }
@@ -0,0 +1,193 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Writes the results of a run as JUnit XML, for test report tools.
+//!
+//! Each image is a test suite, and each of its tests a test case with its
+//! output as `system-out`. A run that did not finish, because the kernel
+//! panicked or the image went quiet, adds an `error` to a `run` test case, and
+//! so do tests the image said it runs that never finished. The average
+//! current of a test, if measured, is a property of its test case. A test
+//! that failed as the launcher expected is reported skipped, and one that
+//! passed although expected to fail is reported passed.
+
+use std::fmt::Write;
+
+use crate::results::{Outcome, Run};
+
+/// Escapes `text` for an XML attribute or text node.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Not allowed in XML 1.0, even escaped.
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {
+                escaped.push(char::REPLACEMENT_CHARACTER)
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Why the run did not finish, if it did not.
+fn run_error(run: &Run) -> Option<String> {
+    let missing = run.results.missing();
+    match (&run.error, &run.results.panic) {
+        (Some(error), _) => Some(error.clone()),
+        (None, Some(panic)) => Some(format!("kernel panicked: {}", panic)),
+        (None, None) if !run.results.finished => Some("run did not finish".to_string()),
+        (None, None) if !missing.is_empty() => Some(format!("tests {:?} did not finish", missing)),
+        (None, None) => None,
+    }
+}
+
+/// The JUnit report of `runs`.
+pub fn report(runs: &[Run]) -> String {
+    let mut xml = String::new();
+    // Writing to a `String` cannot fail.
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(xml, "<testsuites>");
+    for run in runs {
+        write_suite(&mut xml, run);
+    }
+    let _ = writeln!(xml, "</testsuites>");
+    xml
+}
+
+fn write_suite(xml: &mut String, run: &Run) {
+    let results = &run.results;
+    let suite = escape(run.name());
+    let run_error = run_error(run);
+    let failures = results.count(|outcome| matches!(outcome, Outcome::Failed(_)));
+    let skipped = results
+        .count(|outcome| matches!(outcome, Outcome::Skipped(_) | Outcome::ExpectedFailure(_)));
+
+    let _ = writeln!(
+        xml,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}">"#,
+        suite,
+        results.tests.len() + usize::from(run_error.is_some()),
+        failures,
+        usize::from(run_error.is_some()),
+        skipped,
+    );
+    if let Some(seed) = &results.seed {
+        let _ = writeln!(xml, "    <properties>");
+        let _ = writeln!(
+            xml,
+            r#"      <property name="seed" value="{}"/>"#,
+            escape(seed)
+        );
+        let _ = writeln!(xml, "    </properties>");
+    }
+
+    for test in &results.tests {
+        let _ = writeln!(
+            xml,
+            r#"    <testcase name="{}" classname="{}">"#,
+            escape(&test.label()),
+            suite
+        );
+        if let Some(current) = &test.current {
+            let _ = writeln!(xml, "      <properties>");
+            let _ = writeln!(
+                xml,
+                r#"        <property name="average_current" value="{}"/>"#,
+                current
+            );
+            let _ = writeln!(xml, "      </properties>");
+        }
+        match &test.outcome {
+            Outcome::Passed | Outcome::UnexpectedPass(_) => (),
+            Outcome::Skipped(None) => {
+                let _ = writeln!(xml, "      <skipped/>");
+            }
+            Outcome::Skipped(Some(reason)) => {
+                let _ = writeln!(xml, r#"      <skipped message="{}"/>"#, escape(reason));
+            }
+            Outcome::ExpectedFailure(reason) => {
+                let _ = writeln!(
+                    xml,
+                    r#"      <skipped message="expected failure: {}"/>"#,
+                    escape(reason)
+                );
+            }
+            Outcome::Failed(reason) => {
+                let _ = writeln!(
+                    xml,
+                    r#"      <failure message="{}"/>"#,
+                    escape(reason.as_deref().unwrap_or("test failed"))
+                );
+            }
+        }
+        if !test.output.is_empty() {
+            let _ = writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                escape(&test.output.join("\n"))
+            );
+        }
+        let _ = writeln!(xml, "    </testcase>");
+    }
+
+    if let Some(run_error) = run_error {
+        let _ = writeln!(xml, r#"    <testcase name="run" classname="{}">"#, suite);
+        let _ = writeln!(xml, r#"      <error message="{}"/>"#, escape(&run_error));
+        let _ = writeln!(xml, "    </testcase>");
+    }
+
+    let _ = writeln!(xml, "  </testsuite>");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::Results;
+
+    #[test]
+    fn reports_results() {
+        let mut results = Results::default();
+        for line in [
+            "Test seed: 0x0000000000000001",
+            "Test 0 passed",
+            "Aes: <Ctr> failed: \"data\" & tag differ",
+            "Test 1 failed",
+            "Ppi: no loopback pins configured, skipping",
+            "Test 2 passed",
+            "panicked at src/main.rs:10:5:",
+        ] {
+            results.add_line(line);
+        }
+
+        let run = Run {
+            image: Some("target/shard-0.elf".to_string()),
+            board: None,
+            results,
+            error: None,
+        };
+        let xml = report(&[run]);
+        assert!(xml.contains(r#"<testsuite name="shard-0.elf""#));
+        assert!(xml.contains(r#"tests="4" failures="1" errors="1" skipped="1""#));
+        assert!(xml.contains(r#"<property name="seed" value="0x0000000000000001"/>"#));
+        assert!(xml.contains(r#"<testcase name="test 0" classname="shard-0.elf">"#));
+        assert!(xml.contains(
+            r#"<failure message="&lt;Ctr&gt; failed: &quot;data&quot; &amp; tag differ"/>"#
+        ));
+        assert!(xml.contains(r#"<skipped message="no loopback pins configured"/>"#));
+        assert!(
+            xml.contains(r#"<error message="kernel panicked: panicked at src/main.rs:10:5:"/>"#)
+        );
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        assert_eq!(escape("a\x1b[0mb\tc"), "a\u{fffd}[0mb\tc");
+    }
+}
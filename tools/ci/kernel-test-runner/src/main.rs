@@ -0,0 +1,512 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Collects the results of a kernel test image, such as
+//! `boards/configurations/nrf52840dk/nrf52840dk-test-kernel`, from its
+//! serial port or RTT, optionally flashing it first.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+mod junit;
+mod output;
+mod peer;
+mod power;
+mod probe;
+mod results;
+mod update;
+
+use output::Line;
+use power::Capture;
+use probe::{Probe, Rtt};
+use results::{Results, Run, TestRecord};
+use serialport::SerialPort;
+use update::Update;
+
+/// Exit status when a test failed or the kernel panicked.
+const EXIT_FAILED: i32 = 1;
+/// Exit status when the run did not finish, or could not start.
+const EXIT_ERROR: i32 = 2;
+
+/// Time allowed without any output. The slowest tests wait about a minute
+/// for a peer board without printing.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Time the output of a panic is collected for after the last byte, if the
+/// crash dump does not end before.
+const PANIC_DUMP_TIME: Duration = Duration::from_secs(2);
+
+/// The chip of the nRF52840-DK.
+const DEFAULT_CHIP: &str = "nRF52840_xxAA";
+
+const USAGE: &str = "\
+Usage: kernel-test-runner [options] <serial port>
+       kernel-test-runner [options] --flash <elf> --rtt
+       kernel-test-runner [options] --peer <serial port> <serial port>
+
+Options:
+  --baud <rate>     baud rate of the serial port (default 115200)
+  --timeout <secs>  fail when the image prints nothing for this long
+                    (default 120)
+  --seed <hex>      run the tests with this seed instead of a random one
+  --flash <elf>     flash the image with probe-rs and reset the board before
+                    collecting results; given several times, runs each image
+                    in turn, for suites split over several images
+  --chip <name>     probe-rs name of the chip (default nRF52840_xxAA)
+  --probe <probe>   probe-rs selector of the probe, VID:PID[:serial], when
+                    several boards are connected
+  --junit <file>    also write the results to this file as JUnit XML
+  --rtt             read the output over RTT instead of a serial port; the
+                    seed can only be sent over a serial port
+  --peer <port>     serial port of a second board, flashed with the other
+                    radio peer role, to run the tests on both boards at once
+  --peer-probe <probe>
+                    probe-rs selector of the probe of the second board; with
+                    --probe, the runner resets both boards to start the tests
+  --power-capture <command>
+                    start this shell command, which captures the current of
+                    the board, before the tests, and close its input after
+  --power-log <csv> capture to read the current of each test from
+  --power-sync <column>
+                    column of the capture that records the power_sync pin of
+                    the board, with [n] for bit n of a column of bit strings
+  --update <bin>    send this kernel, built for the second flash bank, to the
+                    board over the serial port before collecting results
+
+Exits with 0 when every test passed or skipped itself, 1 when a test failed
+or did not finish, or the kernel panicked, and 2 when a run did not finish.";
+
+pub struct Options {
+    /// The serial port, or `None` to read RTT.
+    port: Option<String>,
+    baud: u32,
+    idle_timeout: Duration,
+    seed: Option<String>,
+    images: Vec<String>,
+    probe: Probe,
+    junit: Option<String>,
+    /// The serial port of a second board.
+    peer: Option<String>,
+    peer_probe: Option<Probe>,
+    power_capture: Option<String>,
+    power_log: Option<String>,
+    power_sync: Option<String>,
+    update: Option<Update>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut port = None;
+    let mut baud = 115200;
+    let mut idle_timeout = DEFAULT_IDLE_TIMEOUT;
+    let mut seed = None;
+    let mut images = Vec::new();
+    let mut probe = Probe {
+        chip: DEFAULT_CHIP.to_string(),
+        selector: None,
+    };
+    let mut rtt = false;
+    let mut junit = None;
+    let mut peer = None;
+    let mut peer_selector = None;
+    let mut power_capture = None;
+    let mut power_log = None;
+    let mut power_sync = None;
+    let mut update = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--baud" => baud = value()?.parse().map_err(|_| "invalid baud rate")?,
+            "--timeout" => {
+                let secs = value()?.parse().map_err(|_| "invalid timeout")?;
+                idle_timeout = Duration::from_secs(secs);
+            }
+            "--seed" => {
+                let hex = value()?;
+                let hex = hex.trim_start_matches("0x");
+                u64::from_str_radix(hex, 16).map_err(|_| "invalid seed")?;
+                seed = Some(hex.to_string());
+            }
+            "--flash" => images.push(value()?),
+            "--chip" => probe.chip = value()?,
+            "--probe" => probe.selector = Some(value()?),
+            "--rtt" => rtt = true,
+            "--junit" => junit = Some(value()?),
+            "--peer" => peer = Some(value()?),
+            "--peer-probe" => peer_selector = Some(value()?),
+            "--power-capture" => power_capture = Some(value()?),
+            "--power-log" => power_log = Some(value()?),
+            "--power-sync" => power_sync = Some(value()?),
+            "--update" => update = Some(Update::load(&value()?)?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => port = Some(arg),
+        }
+    }
+
+    match (&port, rtt) {
+        (None, false) => return Err("no serial port given".to_string()),
+        (Some(_), true) => return Err("--rtt takes no serial port".to_string()),
+        _ => (),
+    }
+    if rtt && images.is_empty() {
+        return Err("--rtt needs the image to --flash".to_string());
+    }
+    if rtt && seed.is_some() {
+        return Err("--seed needs a serial port".to_string());
+    }
+    if peer.is_some() && (rtt || !images.is_empty()) {
+        return Err("--peer runs boards that were flashed already".to_string());
+    }
+    if peer_selector.is_some() && (peer.is_none() || probe.selector.is_none()) {
+        return Err("--peer-probe needs --peer and --probe".to_string());
+    }
+    if power_log.is_some() != power_sync.is_some() {
+        return Err("--power-log and --power-sync go together".to_string());
+    }
+    if power_capture.is_some() && power_log.is_none() {
+        return Err("--power-capture needs --power-log".to_string());
+    }
+    if update.is_some() && (rtt || !images.is_empty() || peer.is_some()) {
+        return Err("--update sends the image over a single serial port".to_string());
+    }
+    let peer_probe = peer_selector.map(|selector| Probe {
+        chip: probe.chip.clone(),
+        selector: Some(selector),
+    });
+
+    Ok(Options {
+        port,
+        baud,
+        idle_timeout,
+        seed,
+        images,
+        probe,
+        junit,
+        peer,
+        peer_probe,
+        power_capture,
+        power_log,
+        power_sync,
+        update,
+    })
+}
+
+/// The serial port the image prints to, opened once for all images.
+pub struct Serial {
+    input: Box<dyn SerialPort>,
+    seed_output: Box<dyn SerialPort>,
+}
+
+fn open_serial(name: &str, baud: u32) -> Result<Serial, String> {
+    let input = serialport::new(name, baud)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .map_err(|e| format!("cannot open {}: {}", name, e))?;
+    let seed_output = input
+        .try_clone()
+        .map_err(|e| format!("cannot open {}: {}", name, e))?;
+    Ok(Serial { input, seed_output })
+}
+
+/// Flashes and starts `image`, if any, and reads its output until it
+/// finishes, panics or goes quiet, echoing each line.
+fn run(
+    options: &Options,
+    serial: Option<&mut Serial>,
+    image: Option<&str>,
+    results: &mut Results,
+) -> Result<(), String> {
+    let Some(serial) = serial else {
+        // `--rtt` always comes with images, which `probe-rs run` flashes
+        // and starts.
+        let mut rtt = Rtt::start(&options.probe, image.unwrap())?;
+        return read_output(options, &mut rtt, None, "", results);
+    };
+
+    // The port is open before the image starts, so no output is lost.
+    if let Some(image) = image {
+        options.probe.flash(image)?;
+        options.probe.reset()?;
+    }
+    read_output(
+        options,
+        &mut serial.input,
+        Some(&mut serial.seed_output),
+        "",
+        results,
+    )
+}
+
+/// Reads lines from `input` until the image finishes, panics or goes quiet,
+/// echoing each one after `label`. The seed, if any, is sent to
+/// `seed_output`, after the update, if any.
+fn read_output(
+    options: &Options,
+    input: &mut dyn Read,
+    mut seed_output: Option<&mut dyn Write>,
+    label: &str,
+    results: &mut Results,
+) -> Result<(), String> {
+    let mut line = Vec::new();
+    let mut buffer = [0; 256];
+    let mut last_output = Instant::now();
+    let mut panicked = false;
+    let mut update = options.update.as_ref();
+    // The seed of the run, sent again when the board restarts after a panic.
+    let mut restart_seed = None;
+    loop {
+        if panicked && last_output.elapsed() > PANIC_DUMP_TIME {
+            return Ok(());
+        }
+        if last_output.elapsed() > options.idle_timeout {
+            return Err(format!(
+                "no output for {} s",
+                options.idle_timeout.as_secs()
+            ));
+        }
+
+        let len = match input.read(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(format!("reading output: {}", e)),
+        };
+        last_output = Instant::now();
+
+        for &byte in &buffer[..len] {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).into_owned();
+            line.clear();
+            println!("{}{}", label, text.trim_end());
+
+            match results.add_line(&text) {
+                Line::Seed(_) => {
+                    if let (Some(update), Some(output)) = (update, &mut seed_output) {
+                        output
+                            .write_all(update.command().as_bytes())
+                            .map_err(|e| format!("sending update: {}", e))?;
+                    } else if let (Some(seed), Some(output)) = (&options.seed, &mut seed_output) {
+                        output
+                            .write_all(format!("seed {}\n", seed).as_bytes())
+                            .map_err(|e| format!("sending seed: {}", e))?;
+                    } else if let (Some(seed), Some(output)) = (&restart_seed, &mut seed_output) {
+                        output
+                            .write_all(format!("seed {}\n", seed).as_bytes())
+                            .map_err(|e| format!("sending seed: {}", e))?;
+                    }
+                }
+                Line::UpdatePage(index) => {
+                    let (Some(update), Some(output)) = (update, &mut seed_output) else {
+                        continue;
+                    };
+                    let page = update
+                        .page(index)
+                        .ok_or(format!("the board asks for page {} of the update", index))?;
+                    output
+                        .write_all(page)
+                        .map_err(|e| format!("sending update: {}", e))?;
+                }
+                Line::Updated if update.is_some() => {
+                    // The results are those of the new kernel.
+                    update = None;
+                    *results = Results::default();
+                }
+                Line::Start { .. } if update.is_some() => {
+                    return Err("the board started the tests instead of the update".to_string());
+                }
+                Line::UpdateFailed(reason) if update.is_some() => {
+                    return Err(format!("update failed: {}", reason));
+                }
+                Line::Panic => panicked = true,
+                Line::DumpEnd if panicked => return Ok(()),
+                Line::Restarting => {
+                    panicked = false;
+                    if restart_seed.is_none() {
+                        restart_seed = results.seed.clone();
+                    }
+                }
+                Line::Finished => return Ok(()),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Runs each image in turn, or the image the board runs already.
+fn run_images(options: &Options, mut serial: Option<&mut Serial>) -> Vec<Run> {
+    // Without images, the runner reads a board that was flashed already.
+    let images: Vec<Option<String>> = if options.images.is_empty() {
+        vec![None]
+    } else {
+        options.images.iter().cloned().map(Some).collect()
+    };
+    let mut runs = Vec::new();
+    for image in images {
+        let mut results = Results::default();
+        let error = run(
+            options,
+            serial.as_deref_mut(),
+            image.as_deref(),
+            &mut results,
+        )
+        .err();
+        runs.push(Run {
+            image,
+            board: None,
+            results,
+            error,
+        });
+    }
+    runs
+}
+
+/// Runs the tests on the board at `serial` and the peer board at
+/// `peer_port` at once.
+fn run_peers(options: &Options, serial: &mut Serial, peer_port: &str) -> Result<[Run; 2], String> {
+    let mut peer_serial = open_serial(peer_port, options.baud)?;
+    let peer_probe = options.peer_probe.as_ref();
+    let boards = [
+        peer::Board {
+            port: options.port.as_deref().unwrap(),
+            serial,
+            probe: peer_probe.map(|_| &options.probe),
+        },
+        peer::Board {
+            port: peer_port,
+            serial: &mut peer_serial,
+            probe: peer_probe,
+        },
+    ];
+    peer::run(options, boards)
+}
+
+/// Attaches the average current of each period the sync input is high in
+/// the capture at `path` to the next of `tests`.
+fn measure_power<'t>(
+    path: &str,
+    sync: &str,
+    tests: impl Iterator<Item = &'t mut TestRecord>,
+) -> Result<(), String> {
+    let csv = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let periods = power::sync_periods(&csv, sync)?;
+    let tests: Vec<&mut TestRecord> = tests.collect();
+    if periods.len() != tests.len() {
+        return Err(format!(
+            "{} sync periods in the capture for {} tests",
+            periods.len(),
+            tests.len()
+        ));
+    }
+    for (test, current) in tests.into_iter().zip(periods) {
+        test.current = Some(current);
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(error) => {
+            if !error.is_empty() {
+                eprintln!("{}\n", error);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    let mut serial = match &options.port {
+        Some(port) => match open_serial(port, options.baud) {
+            Ok(serial) => Some(serial),
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(EXIT_ERROR);
+            }
+        },
+        None => None,
+    };
+
+    let capture = options
+        .power_capture
+        .as_deref()
+        .map(Capture::start)
+        .transpose()
+        .unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(EXIT_ERROR);
+        });
+
+    let mut runs: Vec<Run> = match (&options.peer, serial.as_mut()) {
+        (Some(peer), Some(serial)) => match run_peers(&options, serial, peer) {
+            Ok(runs) => runs.into(),
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(EXIT_ERROR);
+            }
+        },
+        (_, serial) => run_images(&options, serial),
+    };
+
+    if let Some(Err(error)) = capture.map(Capture::stop) {
+        eprintln!("{}", error);
+    }
+    if let (Some(log), Some(sync)) = (&options.power_log, &options.power_sync) {
+        // With two boards, the first one is measured.
+        let measured = match options.peer {
+            Some(_) => &mut runs[..1],
+            None => &mut runs[..],
+        };
+        let tests = measured
+            .iter_mut()
+            .flat_map(|run| run.results.tests.iter_mut());
+        if let Err(error) = measure_power(log, sync, tests) {
+            eprintln!("No power measurements: {}", error);
+        }
+    }
+
+    for run in &runs {
+        if runs.len() > 1 {
+            println!("\n{}:", run.name());
+        }
+        run.results.print_summary();
+    }
+
+    let mut status = 0;
+    if let Ok(runs) = <&[Run; 2]>::try_from(&runs[..]) {
+        if options.peer.is_some() {
+            peer::print_failures(runs);
+            if let Err(error) = peer::check_roles(runs) {
+                eprintln!("{}", error);
+                status = EXIT_ERROR;
+            }
+        }
+    }
+
+    if let Some(path) = &options.junit {
+        if let Err(error) = fs::write(path, junit::report(&runs)) {
+            eprintln!("Cannot write {}: {}", path, error);
+            process::exit(EXIT_ERROR);
+        }
+    }
+
+    for run in &runs {
+        if let Some(error) = &run.error {
+            eprintln!("{} did not finish: {}", run.name(), error);
+            status = EXIT_ERROR;
+        } else if !run.ended() {
+            eprintln!("{} did not finish", run.name());
+            status = EXIT_ERROR;
+        } else if !run.results.success() && status == 0 {
+            status = EXIT_FAILED;
+        }
+    }
+    process::exit(status);
+}
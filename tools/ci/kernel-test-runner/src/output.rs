@@ -0,0 +1,405 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Parses the lines a kernel test image prints.
+//!
+//! Each test prints diagnostics prefixed with its name and ends with one of
+//!
+//! ```text
+//! Sha256: all cases passed
+//! Sx127x: Version failed: unexpected version
+//! SpiConformance: no chip select loopback configured, skipping
+//! ```
+//!
+//! Some older tests print other messages instead. Before each test the test
+//! launcher prints `Test <index>: <name>`, and after it `Test <index> passed`
+//! or `Test <index> failed`, which also covers failures it detects itself, and
+//! `All tests finished.` at the end. For a test it expects to fail, it prints
+//! `Test <index> xfail: <reason>` or `Test <index> xpass: <reason>` instead.
+//! Newer images add the time the core ran for the test after the result, as
+//! `Test <index> passed (<us> us)`, and end a test that skipped itself with
+//! `Test <index> skipped: <reason>`. Older images report it passed, after the
+//! line the test printed.
+//! Before the first test it prints `Test suite: <count> tests`, so tests that
+//! never finished can be told apart from tests the image does not have.
+//! Images built to print TAP 14 print the plan `1..<count>` instead, and
+//! `ok <index + 1> - <name>` or `not ok <index + 1> - <name>` after each test,
+//! followed by `# TODO <reason>` for a test the launcher expects to fail and
+//! `# SKIP <reason>` for a test that skipped itself.
+//! While it receives a new kernel, the image prints `Update: ...` lines. After
+//! a panic, it prints a crash dump that ends with `--- crash dump end ---`, and
+//! `Panic: restarting after test <index>` if it resets to run the next test.
+
+/// What a line of output means to the runner.
+#[derive(Debug, PartialEq)]
+pub enum Line<'l> {
+    /// `Test seed: 0x...`, printed before the first test.
+    Seed(&'l str),
+    /// The number of tests the image runs.
+    Suite(usize),
+    /// The role of the board in tests with a second board.
+    Role(&'l str),
+    /// The launcher started the test at `index`.
+    Start { index: usize, name: &'l str },
+    /// A test passed all its cases.
+    Passed(&'l str),
+    /// A test failed a case.
+    Failed { test: &'l str, reason: &'l str },
+    /// A test skipped itself, as the hardware it needs is not configured,
+    /// with the reason it printed. Only older images report such a test
+    /// passed.
+    Skipped { test: &'l str, reason: &'l str },
+    /// The test at `index` finished, after the core ran for it for
+    /// `core_us`, if the launcher printed it.
+    Done {
+        index: usize,
+        passed: bool,
+        core_us: Option<u32>,
+    },
+    /// The test at `index`, which the launcher expects to fail for `reason`,
+    /// finished.
+    Expected {
+        index: usize,
+        passed: bool,
+        core_us: Option<u32>,
+        reason: &'l str,
+    },
+    /// The test at `index` skipped itself for `reason`.
+    Skip {
+        index: usize,
+        core_us: Option<u32>,
+        reason: &'l str,
+    },
+    /// The kernel panicked.
+    Panic,
+    /// The crash dump after a panic ended.
+    DumpEnd,
+    /// The board resets after a panic and continues after the test that
+    /// panicked.
+    Restarting,
+    /// The launcher ran the last test.
+    Finished,
+    /// The board asks for page `n` of a new kernel.
+    UpdatePage(usize),
+    /// The board wrote the new kernel and restarts in it.
+    Updated,
+    /// The board could not receive the new kernel.
+    UpdateFailed(&'l str),
+    /// Anything else, such as a diagnostic.
+    Other,
+}
+
+/// Splits the result of a test, as `3 passed (1840 us)` after `Test `, into
+/// the index, the result and the time the core ran for the test, if given.
+fn test_result(rest: &str) -> Option<(usize, &str, Option<u32>)> {
+    let (index, rest) = rest.split_once(' ')?;
+    let index = index.parse().ok()?;
+    match rest.split_once(" (") {
+        Some((result, time)) => {
+            let core_us = time.strip_suffix(" us)")?.parse().ok()?;
+            Some((index, result, Some(core_us)))
+        }
+        None => Some((index, rest, None)),
+    }
+}
+
+/// Whether `name` looks like a test name, e.g. `Sha256` or `AesCtr`.
+fn is_test_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn parse(line: &str) -> Line<'_> {
+    let line = line.trim_end();
+    if line == "All tests finished." {
+        return Line::Finished;
+    }
+    if line.starts_with("panicked at") || line.contains("Kernel panic") {
+        return Line::Panic;
+    }
+    if line == "--- crash dump end ---" {
+        return Line::DumpEnd;
+    }
+    if line.starts_with("Panic: restarting") {
+        return Line::Restarting;
+    }
+    if let Some(update) = line.strip_prefix("Update: ") {
+        if let Some(Ok(page)) = update.strip_prefix("send page ").map(str::parse) {
+            return Line::UpdatePage(page);
+        }
+        if update.starts_with("image written") {
+            return Line::Updated;
+        }
+        if let Some(reason) = update.strip_prefix("failed: ") {
+            return Line::UpdateFailed(reason);
+        }
+        return Line::Other;
+    }
+    if let Some(seed) = line.strip_prefix("Test seed: ") {
+        return Line::Seed(seed);
+    }
+    if let Some(Ok(count)) = line
+        .strip_prefix("Test suite: ")
+        .and_then(|rest| rest.strip_suffix(" tests"))
+        .map(str::parse)
+    {
+        return Line::Suite(count);
+    }
+    if let Some(Ok(count)) = line.strip_prefix("1..").map(str::parse) {
+        return Line::Suite(count);
+    }
+    if let Some((passed, rest)) = line
+        .strip_prefix("ok ")
+        .map(|rest| (true, rest))
+        .or_else(|| line.strip_prefix("not ok ").map(|rest| (false, rest)))
+    {
+        let number = rest.split_once(' ').map_or(rest, |(number, _)| number);
+        if let Some(index) = number.parse::<usize>().ok().and_then(|n| n.checked_sub(1)) {
+            if let Some((_, reason)) = rest.split_once(" # SKIP ") {
+                return Line::Skip {
+                    index,
+                    core_us: None,
+                    reason,
+                };
+            }
+            return match rest.split_once(" # TODO ") {
+                Some((_, reason)) => Line::Expected {
+                    index,
+                    passed,
+                    core_us: None,
+                    reason,
+                },
+                None => Line::Done {
+                    index,
+                    passed,
+                    core_us: None,
+                },
+            };
+        }
+    }
+    if let Some(role) = line.strip_prefix("Radio peer role: ") {
+        return Line::Role(role);
+    }
+    if let Some((index, name)) = line
+        .strip_prefix("Test ")
+        .and_then(|rest| rest.split_once(": "))
+    {
+        if let Ok(index) = index.parse() {
+            return Line::Start { index, name };
+        }
+        match test_result(index) {
+            Some((index, result @ ("xfail" | "xpass"), core_us)) => {
+                return Line::Expected {
+                    index,
+                    passed: result == "xpass",
+                    core_us,
+                    reason: name,
+                };
+            }
+            Some((index, "skipped", core_us)) => {
+                return Line::Skip {
+                    index,
+                    core_us,
+                    reason: name,
+                };
+            }
+            _ => (),
+        }
+    }
+    if let Some((index, result @ ("passed" | "failed"), core_us)) =
+        line.strip_prefix("Test ").and_then(test_result)
+    {
+        return Line::Done {
+            index,
+            passed: result == "passed",
+            core_us,
+        };
+    }
+
+    let Some((test, rest)) = line.split_once(": ") else {
+        return Line::Other;
+    };
+    if !is_test_name(test) {
+        Line::Other
+    } else if rest == "all cases passed" {
+        Line::Passed(test)
+    } else if rest.contains(" failed: ") {
+        Line::Failed { test, reason: rest }
+    } else if rest.ends_with("skipping") {
+        Line::Skipped {
+            test,
+            reason: rest.strip_suffix(", skipping").unwrap_or(rest),
+        }
+    } else {
+        Line::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results() {
+        assert_eq!(parse("Sha256: all cases passed\r"), Line::Passed("Sha256"));
+        assert_eq!(
+            parse("Sx127x: Version failed: unexpected version"),
+            Line::Failed {
+                test: "Sx127x",
+                reason: "Version failed: unexpected version"
+            }
+        );
+        assert_eq!(
+            parse("Ppi: no loopback pins configured, skipping"),
+            Line::Skipped {
+                test: "Ppi",
+                reason: "no loopback pins configured"
+            }
+        );
+    }
+
+    #[test]
+    fn launcher() {
+        assert_eq!(
+            parse("Test seed: 0x0123456789abcdef"),
+            Line::Seed("0x0123456789abcdef")
+        );
+        assert_eq!(parse("Test suite: 51 tests"), Line::Suite(51));
+        assert_eq!(parse("Radio peer role: Initiator"), Line::Role("Initiator"));
+        assert_eq!(
+            parse("Test 12: aes128_ctr"),
+            Line::Start {
+                index: 12,
+                name: "aes128_ctr"
+            }
+        );
+        assert_eq!(
+            parse("Test 12 passed"),
+            Line::Done {
+                index: 12,
+                passed: true,
+                core_us: None
+            }
+        );
+        assert_eq!(
+            parse("Test 3 failed"),
+            Line::Done {
+                index: 3,
+                passed: false,
+                core_us: None
+            }
+        );
+        assert_eq!(
+            parse("Test 7 xfail: issue #123"),
+            Line::Expected {
+                index: 7,
+                passed: false,
+                core_us: None,
+                reason: "issue #123"
+            }
+        );
+        assert_eq!(
+            parse("Test 7 xpass: issue #123"),
+            Line::Expected {
+                index: 7,
+                passed: true,
+                core_us: None,
+                reason: "issue #123"
+            }
+        );
+        assert_eq!(
+            parse("Test 12 passed (1840 us)"),
+            Line::Done {
+                index: 12,
+                passed: true,
+                core_us: Some(1840)
+            }
+        );
+        assert_eq!(
+            parse("Test 7 xfail (412 us): issue #123"),
+            Line::Expected {
+                index: 7,
+                passed: false,
+                core_us: Some(412),
+                reason: "issue #123"
+            }
+        );
+        assert_eq!(
+            parse("Test 9 skipped (35 us): no loopback pins configured"),
+            Line::Skip {
+                index: 9,
+                core_us: Some(35),
+                reason: "no loopback pins configured"
+            }
+        );
+        assert_eq!(
+            parse("Test 3 failed: 2 kernel invariants violated"),
+            Line::Other
+        );
+        assert_eq!(parse("All tests finished."), Line::Finished);
+        assert_eq!(parse("Update: send page 3"), Line::UpdatePage(3));
+        assert_eq!(
+            parse("Update: image written, starting bank B"),
+            Line::Updated
+        );
+        assert_eq!(
+            parse("Update: failed: CRC-32 mismatch"),
+            Line::UpdateFailed("CRC-32 mismatch")
+        );
+        assert_eq!(parse("Update: running bank B"), Line::Other);
+        assert_eq!(parse("panicked at src/main.rs:10:5:"), Line::Panic);
+        assert_eq!(parse("--- crash dump end ---\r"), Line::DumpEnd);
+        assert_eq!(parse("Panic: restarting after test 12"), Line::Restarting);
+    }
+
+    #[test]
+    fn tap() {
+        assert_eq!(parse("TAP version 14"), Line::Other);
+        assert_eq!(parse("1..51\r"), Line::Suite(51));
+        assert_eq!(
+            parse("ok 13 - aes128_ctr"),
+            Line::Done {
+                index: 12,
+                passed: true,
+                core_us: None
+            }
+        );
+        assert_eq!(
+            parse("not ok 1 - sha256"),
+            Line::Done {
+                index: 0,
+                passed: false,
+                core_us: None
+            }
+        );
+        assert_eq!(
+            parse("not ok 8 - nvmc_erase # TODO issue #123"),
+            Line::Expected {
+                index: 7,
+                passed: false,
+                core_us: None,
+                reason: "issue #123"
+            }
+        );
+        assert_eq!(
+            parse("ok 10 - ppi # SKIP no loopback pins configured"),
+            Line::Skip {
+                index: 9,
+                core_us: None,
+                reason: "no loopback pins configured"
+            }
+        );
+        assert_eq!(parse("ok 0 - sha256"), Line::Other);
+    }
+
+    #[test]
+    fn diagnostics() {
+        assert_eq!(parse("Sx127x: version register reads 0x00"), Line::Other);
+        assert_eq!(
+            parse("Initialization complete. Entering main loop"),
+            Line::Other
+        );
+        assert_eq!(parse("0x20001000: all cases passed"), Line::Other);
+    }
+}
@@ -0,0 +1,177 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Runs the tests on two boards at once, for the tests that need a second
+//! board within radio range.
+//!
+//! Each board is flashed with an image configured for its role, `Initiator`
+//! or `Responder`, which it prints as `Radio peer role: <role>` before the
+//! first test. The runner reads both serial ports at the same time, checks
+//! that the boards have one role each, and prints the output of both boards
+//! side by side for each test that failed on either.
+
+use std::path::Path;
+use std::thread;
+
+use crate::probe::Probe;
+use crate::results::{Outcome, Results, Run, TestRecord};
+use crate::{read_output, Options, Serial};
+
+/// A board and the port it prints to.
+pub struct Board<'a> {
+    pub port: &'a str,
+    pub serial: &'a mut Serial,
+    /// Probe that resets the board, if the runner starts the tests.
+    pub probe: Option<&'a Probe>,
+}
+
+impl Board<'_> {
+    /// Prefix of the lines of the board when they are echoed.
+    fn label(&self) -> String {
+        let name = Path::new(self.port)
+            .file_name()
+            .and_then(|name| name.to_str());
+        format!("[{}] ", name.unwrap_or(self.port))
+    }
+}
+
+/// Reads the output of both boards until both finished, panicked or went
+/// quiet.
+pub fn run(options: &Options, boards: [Board; 2]) -> Result<[Run; 2], String> {
+    // The ports are open before the images start, so no output is lost.
+    if boards.iter().all(|board| board.probe.is_some()) {
+        for board in &boards {
+            board.probe.unwrap().reset()?;
+        }
+    } else {
+        println!("Reset both boards to start the tests.");
+    }
+
+    let runs = thread::scope(|scope| {
+        let readers = boards.map(|board| {
+            scope.spawn(move || {
+                let label = board.label();
+                let mut results = Results::default();
+                let error = read_output(
+                    options,
+                    &mut board.serial.input,
+                    Some(&mut board.serial.seed_output),
+                    &label,
+                    &mut results,
+                )
+                .err();
+                Run {
+                    image: None,
+                    board: Some(board.port.to_string()),
+                    results,
+                    error,
+                }
+            })
+        });
+        readers.map(|reader| reader.join().unwrap())
+    });
+    Ok(runs)
+}
+
+/// Checks that one board is the initiator and the other the responder.
+pub fn check_roles(runs: &[Run; 2]) -> Result<(), String> {
+    match [&runs[0].results.role, &runs[1].results.role] {
+        [Some(first), Some(second)] if first != second => Ok(()),
+        [Some(role), Some(_)] => Err(format!("both boards have the {} role", role)),
+        [None, _] => Err(format!("{} has no radio peer role", runs[0].name())),
+        [_, None] => Err(format!("{} has no radio peer role", runs[1].name())),
+    }
+}
+
+fn print_test(run: &Run, test: Option<&TestRecord>) {
+    let Some(test) = test else {
+        println!("  {}: did not finish", run.name());
+        return;
+    };
+    match &test.outcome {
+        Outcome::Passed => println!("  {}: ok", run.name()),
+        Outcome::Skipped(reason) => println!(
+            "  {}: skipped: {}",
+            run.name(),
+            reason.as_deref().unwrap_or("no reason given")
+        ),
+        Outcome::Failed(reason) => println!(
+            "  {}: FAILED: {}",
+            run.name(),
+            reason.as_deref().unwrap_or("see output")
+        ),
+        Outcome::ExpectedFailure(reason) => {
+            println!("  {}: failed as expected: {}", run.name(), reason)
+        }
+        Outcome::UnexpectedPass(reason) => {
+            println!("  {}: passed unexpectedly: {}", run.name(), reason)
+        }
+    }
+    for line in &test.output {
+        println!("        | {}", line);
+    }
+}
+
+/// Prints the output of both boards for each test that failed on either.
+pub fn print_failures(runs: &[Run; 2]) {
+    let failed = |test: Option<&TestRecord>| {
+        test.is_some_and(|test| matches!(test.outcome, Outcome::Failed(_)))
+    };
+
+    let mut indices: Vec<usize> = runs
+        .iter()
+        .flat_map(|run| run.results.tests.iter().map(|test| test.index))
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+    for index in indices {
+        let tests = [runs[0].results.test(index), runs[1].results.test(index)];
+        if !tests.into_iter().any(failed) {
+            continue;
+        }
+        let test = tests.into_iter().flatten().next().unwrap();
+        println!("\n{} on both boards:", test.label());
+        print_test(&runs[0], tests[0]);
+        print_test(&runs[1], tests[1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board_run(port: &str, lines: &[&str]) -> Run {
+        let mut results = Results::default();
+        for line in lines {
+            results.add_line(line);
+        }
+        Run {
+            image: None,
+            board: Some(port.to_string()),
+            results,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn roles() {
+        let initiator = board_run("/dev/ttyACM0", &["Radio peer role: Initiator"]);
+        let responder = board_run("/dev/ttyACM1", &["Radio peer role: Responder"]);
+        let unset = board_run("/dev/ttyACM1", &[]);
+        assert_eq!(check_roles(&[initiator, responder]), Ok(()));
+
+        let initiator = board_run("/dev/ttyACM0", &["Radio peer role: Initiator"]);
+        let second = board_run("/dev/ttyACM1", &["Radio peer role: Initiator"]);
+        assert_eq!(
+            check_roles(&[initiator, second]),
+            Err("both boards have the Initiator role".to_string())
+        );
+
+        let initiator = board_run("/dev/ttyACM0", &["Radio peer role: Initiator"]);
+        assert_eq!(
+            check_roles(&[initiator, unset]),
+            Err("ttyACM1 has no radio peer role".to_string())
+        );
+    }
+}
@@ -0,0 +1,183 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Measures the current each test draws with a power analyzer, such as a
+//! PPK2 or a Joulescope.
+//!
+//! The board holds its `power_sync` pin high while each test runs. Wired to a
+//! digital input of the analyzer, it is recorded along with the current. The
+//! runner starts the capture tool of the analyzer before the tests, stops it
+//! after them, and reads the samples the tool exported as CSV: the column
+//! whose name starts with `Current`, in the unit its name gives in brackets,
+//! for example `Current(uA)`, and the column of the sync input. Each period
+//! the sync input is high is the next test that ran.
+
+use std::fmt;
+use std::process::{Child, Command, Stdio};
+
+/// The average current a test drew.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Current {
+    pub average: f64,
+    pub unit: String,
+}
+
+impl fmt::Display for Current {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} {}", self.average, self.unit)
+    }
+}
+
+/// A capture tool running in the background.
+pub struct Capture {
+    child: Child,
+}
+
+impl Capture {
+    /// Starts `command` with the shell.
+    pub fn start(command: &str) -> Result<Capture, String> {
+        let child = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run {}: {}", command, e))?;
+        Ok(Capture { child })
+    }
+
+    /// Stops the tool by closing its standard input, and waits for it to
+    /// write the capture.
+    pub fn stop(mut self) -> Result<(), String> {
+        drop(self.child.stdin.take());
+        let status = self
+            .child
+            .wait()
+            .map_err(|e| format!("power capture: {}", e))?;
+        if !status.success() {
+            return Err(format!("power capture failed: {}", status));
+        }
+        Ok(())
+    }
+}
+
+/// Column of the sync input: a column of numbers, or with `[n]` the `n`th
+/// character of a column of bit strings, as `D0-D7[0]`.
+struct SyncColumn<'s> {
+    name: &'s str,
+    bit: Option<usize>,
+}
+
+impl<'s> SyncColumn<'s> {
+    fn parse(spec: &'s str) -> Result<Self, String> {
+        let Some((name, bit)) = spec.strip_suffix(']').and_then(|s| s.split_once('[')) else {
+            return Ok(SyncColumn {
+                name: spec,
+                bit: None,
+            });
+        };
+        let bit = bit
+            .parse()
+            .map_err(|_| format!("invalid sync column {}", spec))?;
+        Ok(SyncColumn {
+            name,
+            bit: Some(bit),
+        })
+    }
+
+    fn is_high(&self, value: &str) -> bool {
+        match self.bit {
+            Some(bit) => value.trim().chars().nth(bit) == Some('1'),
+            None => value.trim().parse::<f64>().is_ok_and(|value| value != 0.0),
+        }
+    }
+}
+
+/// The average current of each period the sync input is high in `csv`.
+pub fn sync_periods(csv: &str, sync: &str) -> Result<Vec<Current>, String> {
+    let sync = SyncColumn::parse(sync)?;
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or("empty power capture")?
+        .trim_start_matches('#')
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    let current = header
+        .iter()
+        .position(|name| name.to_ascii_lowercase().starts_with("current"))
+        .ok_or("no current column in the power capture")?;
+    let unit = header[current]
+        .split_once('(')
+        .and_then(|(_, unit)| unit.strip_suffix(')'))
+        .unwrap_or("A")
+        .to_string();
+    let sync_index = header
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(sync.name))
+        .ok_or(format!("no {} column in the power capture", sync.name))?;
+
+    let mut periods = Vec::new();
+    // Sum and count of the samples of the period in progress.
+    let mut period: Option<(f64, usize)> = None;
+    for line in lines {
+        let values: Vec<&str> = line.split(',').collect();
+        let (Some(sample), Some(sync_value)) = (values.get(current), values.get(sync_index)) else {
+            continue;
+        };
+        let Ok(sample) = sample.trim().parse::<f64>() else {
+            continue;
+        };
+        match (sync.is_high(sync_value), &mut period) {
+            (true, Some((sum, count))) => {
+                *sum += sample;
+                *count += 1;
+            }
+            (true, None) => period = Some((sample, 1)),
+            (false, Some(_)) => {
+                let (sum, count) = period.take().unwrap();
+                periods.push(Current {
+                    average: sum / count as f64,
+                    unit: unit.clone(),
+                });
+            }
+            (false, None) => (),
+        }
+    }
+    // The capture stopped before the last test finished.
+    if let Some((sum, count)) = period {
+        periods.push(Current {
+            average: sum / count as f64,
+            unit,
+        });
+    }
+    Ok(periods)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_capture() {
+        let csv = "\
+Timestamp(ms),Current(uA),D0-D7
+0.00,5.0,00000000
+0.01,100.0,10000000
+0.02,200.0,10000000
+0.03,5.0,00000000
+0.04,40.0,10000000
+";
+        let periods = sync_periods(csv, "D0-D7[0]").unwrap();
+        assert_eq!(periods.len(), 2);
+        assert_eq!(periods[0].to_string(), "150.0 uA");
+        assert_eq!(periods[1].average, 40.0);
+
+        let csv = "#time,current,sync\n0,1e-3,0\n1,2e-3,1\n2,1e-3,0\n";
+        let periods = sync_periods(csv, "sync").unwrap();
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].unit, "A");
+        assert!(sync_periods(csv, "D0").is_err());
+    }
+}
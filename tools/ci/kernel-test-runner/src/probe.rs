@@ -0,0 +1,121 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Flashes the image and reads its RTT output with the `probe-rs` tool.
+
+use std::io::{self, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// A debug probe connected to a board.
+pub struct Probe {
+    /// `probe-rs` name of the chip on the board.
+    pub chip: String,
+    /// `probe-rs` selector of the probe, when several are connected.
+    pub selector: Option<String>,
+}
+
+impl Probe {
+    /// A `probe-rs` command for the chip and probe.
+    fn command(&self, subcommand: &str) -> Command {
+        let mut command = Command::new("probe-rs");
+        command.args([subcommand, "--chip", &self.chip]);
+        if let Some(selector) = &self.selector {
+            command.args(["--probe", selector]);
+        }
+        command
+    }
+
+    /// Runs `command`, failing if it does not exit successfully.
+    fn run(mut command: Command) -> Result<(), String> {
+        let status = command
+            .status()
+            .map_err(|e| format!("cannot run probe-rs: {}", e))?;
+        if !status.success() {
+            return Err(format!("{:?} failed: {}", command, status));
+        }
+        Ok(())
+    }
+
+    /// Writes `elf` to the flash of the chip and verifies it.
+    pub fn flash(&self, elf: &str) -> Result<(), String> {
+        let mut command = self.command("download");
+        command.args(["--verify", elf]);
+        Probe::run(command)
+    }
+
+    /// Resets the chip, starting the image.
+    pub fn reset(&self) -> Result<(), String> {
+        Probe::run(self.command("reset"))
+    }
+}
+
+/// The RTT output of an image `probe-rs run` flashed and started.
+///
+/// Reads time out like those of a serial port, so the runner can notice an
+/// image that went quiet.
+pub struct Rtt {
+    child: Child,
+    output: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl Rtt {
+    pub fn start(probe: &Probe, elf: &str) -> Result<Rtt, String> {
+        let mut child = probe
+            .command("run")
+            .arg(elf)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run probe-rs: {}", e))?;
+
+        let mut stdout = child.stdout.take().unwrap();
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 256];
+            while let Ok(len @ 1..) = stdout.read(&mut buffer) {
+                if sender.send(buffer[..len].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Rtt {
+            child,
+            output,
+            pending: Vec::new(),
+        })
+    }
+}
+
+impl Read for Rtt {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.pending = match self.output.recv_timeout(Duration::from_millis(100)) {
+                Ok(bytes) => bytes,
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "probe-rs exited",
+                    ))
+                }
+            };
+        }
+        let len = buffer.len().min(self.pending.len());
+        buffer[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Drop for Rtt {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
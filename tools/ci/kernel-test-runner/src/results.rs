@@ -0,0 +1,477 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Collects the results of a run from the lines the image prints.
+
+use std::path::Path;
+
+use crate::output::{self, Line};
+use crate::power::Current;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// The test skipped itself, with the reason it printed, if any.
+    Skipped(Option<String>),
+    /// The test failed, with the reason it printed, if any.
+    Failed(Option<String>),
+    /// The test failed, as the launcher expected for the reason it printed.
+    ExpectedFailure(String),
+    /// The test passed, although the launcher expected it to fail for the
+    /// reason it printed.
+    UnexpectedPass(String),
+}
+
+/// A test that finished.
+#[derive(Debug)]
+pub struct TestRecord {
+    /// Index of the test in the launcher.
+    pub index: usize,
+    /// The name the launcher gave the test, or else the name the test
+    /// printed, if any.
+    pub name: Option<String>,
+    pub outcome: Outcome,
+    /// The lines printed while the test ran.
+    pub output: Vec<String>,
+    /// How long the core ran for the test, in microseconds, if the launcher
+    /// printed it.
+    pub core_us: Option<u32>,
+    /// The average current the board drew while the test ran, if measured.
+    pub current: Option<Current>,
+}
+
+impl TestRecord {
+    /// The name the test printed, or its index.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("test {}", self.index),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Results {
+    pub tests: Vec<TestRecord>,
+    /// The seed the image printed.
+    pub seed: Option<String>,
+    /// The number of tests the image said it runs.
+    pub suite: Option<usize>,
+    /// The role the board said it has in tests with a second board.
+    pub role: Option<String>,
+    /// Whether the launcher ran the last test.
+    pub finished: bool,
+    /// The line the kernel panicked with, if it did.
+    pub panic: Option<String>,
+    // What the running test printed so far.
+    started: Option<String>,
+    name: Option<String>,
+    skipped: bool,
+    reason: Option<String>,
+    output: Vec<String>,
+}
+
+impl Results {
+    /// Adds a line of output, returning what it means.
+    pub fn add_line<'l>(&mut self, line: &'l str) -> Line<'l> {
+        let parsed = output::parse(line);
+        match parsed {
+            Line::Seed(seed) => self.seed = Some(seed.to_string()),
+            Line::Suite(count) => self.suite = Some(count),
+            Line::Role(role) => self.role = Some(role.to_string()),
+            Line::Start { name, .. } => self.started = Some(name.to_string()),
+            Line::Passed(test) => self.name = Some(test.to_string()),
+            Line::Skipped { test, reason } => {
+                self.name = Some(test.to_string());
+                self.skipped = true;
+                self.reason.get_or_insert_with(|| reason.to_string());
+            }
+            Line::Failed { test, reason } => {
+                self.name = Some(test.to_string());
+                self.reason.get_or_insert_with(|| reason.to_string());
+            }
+            Line::Done {
+                index,
+                passed,
+                core_us,
+            } => {
+                let outcome = match (passed, self.skipped) {
+                    (true, true) => Outcome::Skipped(self.reason.take()),
+                    (true, false) => Outcome::Passed,
+                    (false, _) => Outcome::Failed(self.reason.take()),
+                };
+                self.finish_test(index, outcome, core_us);
+                return parsed;
+            }
+            Line::Skip {
+                index,
+                core_us,
+                reason,
+            } => {
+                let outcome = Outcome::Skipped(Some(reason.to_string()));
+                self.finish_test(index, outcome, core_us);
+                return parsed;
+            }
+            Line::Expected {
+                index,
+                passed,
+                core_us,
+                reason,
+            } => {
+                let outcome = if passed {
+                    Outcome::UnexpectedPass(reason.to_string())
+                } else {
+                    Outcome::ExpectedFailure(reason.to_string())
+                };
+                self.finish_test(index, outcome, core_us);
+                return parsed;
+            }
+            Line::Panic => self.panic = Some(line.trim_end().to_string()),
+            // The launcher reports the test that panicked failed after the
+            // reset, and the run goes on.
+            Line::Restarting => self.reason = self.panic.take(),
+            Line::Finished => self.finished = true,
+            Line::DumpEnd
+            | Line::UpdatePage(_)
+            | Line::Updated
+            | Line::UpdateFailed(_)
+            | Line::Other => (),
+        }
+        self.output.push(line.trim_end().to_string());
+        parsed
+    }
+
+    /// Records the test at `index`, with what it printed, and starts the next.
+    fn finish_test(&mut self, index: usize, outcome: Outcome, core_us: Option<u32>) {
+        self.tests.push(TestRecord {
+            index,
+            name: self.started.take().or(self.name.take()),
+            outcome,
+            output: std::mem::take(&mut self.output),
+            core_us,
+            current: None,
+        });
+        self.name = None;
+        self.skipped = false;
+        self.reason = None;
+    }
+
+    pub fn count(&self, outcome: fn(&Outcome) -> bool) -> usize {
+        self.tests
+            .iter()
+            .filter(|test| outcome(&test.outcome))
+            .count()
+    }
+
+    /// The test at `index`, if it finished.
+    pub fn test(&self, index: usize) -> Option<&TestRecord> {
+        self.tests.iter().find(|test| test.index == index)
+    }
+
+    /// Indices of the tests the image said it runs that did not finish.
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.suite.unwrap_or(0))
+            .filter(|index| self.test(*index).is_none())
+            .collect()
+    }
+
+    /// Whether every test of the image ran and passed, skipped itself or did
+    /// what the launcher expected of it, and the run ended normally. A test
+    /// expected to fail that passed does not fail the run.
+    pub fn success(&self) -> bool {
+        self.finished
+            && self.panic.is_none()
+            && self.missing().is_empty()
+            && self.count(|outcome| matches!(outcome, Outcome::Failed(_))) == 0
+    }
+
+    pub fn print_summary(&self) {
+        println!();
+        for test in &self.tests {
+            let details: Vec<String> = test
+                .core_us
+                .map(|core_us| format!("{} us", core_us))
+                .into_iter()
+                .chain(test.current.as_ref().map(ToString::to_string))
+                .collect();
+            let label = if details.is_empty() {
+                test.label()
+            } else {
+                format!("{} ({})", test.label(), details.join(", "))
+            };
+            match &test.outcome {
+                Outcome::Passed => println!("ok      {}", label),
+                Outcome::Skipped(Some(reason)) => println!("skipped {}: {}", label, reason),
+                Outcome::Skipped(None) => println!("skipped {}", label),
+                Outcome::ExpectedFailure(reason) => println!("xfail   {}: {}", label, reason),
+                Outcome::UnexpectedPass(reason) => println!("XPASS   {}: {}", label, reason),
+                Outcome::Failed(reason) => {
+                    println!(
+                        "FAILED  {}: {}",
+                        label,
+                        reason.as_deref().unwrap_or("see output")
+                    );
+                    for line in &test.output {
+                        println!("        | {}", line);
+                    }
+                }
+            }
+        }
+        println!(
+            "{} passed, {} failed, {} skipped",
+            self.count(|outcome| *outcome == Outcome::Passed),
+            self.count(|outcome| matches!(outcome, Outcome::Failed(_))),
+            self.count(|outcome| matches!(outcome, Outcome::Skipped(_))),
+        );
+        let expected_failures =
+            self.count(|outcome| matches!(outcome, Outcome::ExpectedFailure(_)));
+        let unexpected_passes = self.count(|outcome| matches!(outcome, Outcome::UnexpectedPass(_)));
+        if expected_failures + unexpected_passes > 0 {
+            println!(
+                "{} failed as expected, {} passed unexpectedly",
+                expected_failures, unexpected_passes
+            );
+        }
+        let missing = self.missing();
+        if !missing.is_empty() {
+            println!("Tests that did not finish: {:?}", missing);
+        }
+        if let Some(panic) = &self.panic {
+            println!("Kernel panicked: {}", panic);
+        }
+        if let Some(seed) = &self.seed {
+            println!("Test seed: {}", seed);
+        }
+    }
+}
+
+/// The results of one image.
+pub struct Run {
+    /// The image flashed for the run, if the runner flashed one.
+    pub image: Option<String>,
+    /// The serial port of the board, when two boards run together.
+    pub board: Option<String>,
+    pub results: Results,
+    /// Why reading the output of the image stopped early, if it did.
+    pub error: Option<String>,
+}
+
+impl Run {
+    /// The file name of the image or the serial port, or `kernel-tests`.
+    pub fn name(&self) -> &str {
+        self.image
+            .as_deref()
+            .or(self.board.as_deref())
+            .map(|path| {
+                Path::new(path)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or(path)
+            })
+            .unwrap_or("kernel-tests")
+    }
+
+    /// Whether the run ended, with or without a panic, rather than going
+    /// quiet or stopping early.
+    pub fn ended(&self) -> bool {
+        self.error.is_none() && (self.results.finished || self.results.panic.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_tests() {
+        let mut results = Results::default();
+        for line in [
+            "Test suite: 4 tests",
+            "Test seed: 0x0000000000000001",
+            "Sha256Test: Verification result: Ok(true)",
+            "Test 0 passed",
+            "Sx127x: Version failed: unexpected version",
+            "Test 1 failed",
+            "Test 2: ppi",
+            "Ppi: no loopback pins configured, skipping",
+            "Test 2 passed (95 us)",
+            "All tests finished.",
+        ] {
+            results.add_line(line);
+        }
+
+        assert_eq!(results.tests.len(), 3);
+        assert_eq!(results.tests[0].label(), "test 0");
+        assert_eq!(results.tests[0].outcome, Outcome::Passed);
+        assert_eq!(results.tests[0].output.len(), 3);
+        assert_eq!(results.tests[1].label(), "Sx127x");
+        assert_eq!(
+            results.tests[1].outcome,
+            Outcome::Failed(Some("Version failed: unexpected version".to_string()))
+        );
+        assert_eq!(results.tests[1].core_us, None);
+        assert_eq!(results.tests[2].label(), "ppi");
+        assert_eq!(results.tests[2].core_us, Some(95));
+        assert_eq!(
+            results.tests[2].outcome,
+            Outcome::Skipped(Some("no loopback pins configured".to_string()))
+        );
+        assert_eq!(results.missing(), [3]);
+        assert!(results.finished);
+        assert!(!results.success());
+    }
+
+    #[test]
+    fn skipped_tests() {
+        let mut results = Results::default();
+        for line in [
+            "Test suite: 1 tests",
+            "Test 0: ppi",
+            "Ppi: no loopback pins configured, skipping",
+            "Test 0 skipped (95 us): no loopback pins configured",
+            "All tests finished.",
+        ] {
+            results.add_line(line);
+        }
+
+        assert_eq!(results.tests.len(), 1);
+        assert_eq!(results.tests[0].core_us, Some(95));
+        assert_eq!(
+            results.tests[0].outcome,
+            Outcome::Skipped(Some("no loopback pins configured".to_string()))
+        );
+        assert!(results.success());
+    }
+
+    #[test]
+    fn expected_failures() {
+        let mut results = Results::default();
+        for line in [
+            "Test suite: 2 tests",
+            "Test 0: nvmc_erase",
+            "NvmcErase: Erase failed: page not erased",
+            "Test 0 xfail: issue #123",
+            "Test 1: sha256",
+            "Test 1 xpass: issue #456",
+            "All tests finished.",
+        ] {
+            results.add_line(line);
+        }
+
+        assert_eq!(results.tests.len(), 2);
+        assert_eq!(results.tests[0].label(), "nvmc_erase");
+        assert_eq!(
+            results.tests[0].outcome,
+            Outcome::ExpectedFailure("issue #123".to_string())
+        );
+        assert_eq!(results.tests[0].output.len(), 3);
+        assert_eq!(
+            results.tests[1].outcome,
+            Outcome::UnexpectedPass("issue #456".to_string())
+        );
+        assert!(results.success());
+    }
+
+    #[test]
+    fn continues_after_panic() {
+        let mut results = Results::default();
+        for line in [
+            "Test suite: 2 tests",
+            "Test 0: aes128_ctr",
+            "panicked at src/test/aes_test.rs:10:5:",
+            "Panic: restarting after test 0",
+            "--- crash dump end ---",
+            "Test suite: 2 tests",
+            "Test 0 failed: the kernel panicked",
+            "Test 0 failed",
+            "Test 1: sha256",
+            "Test 1 passed",
+            "All tests finished.",
+        ] {
+            results.add_line(line);
+        }
+
+        assert_eq!(results.panic, None);
+        assert_eq!(results.tests.len(), 2);
+        assert_eq!(results.tests[0].label(), "aes128_ctr");
+        assert_eq!(
+            results.tests[0].outcome,
+            Outcome::Failed(Some("panicked at src/test/aes_test.rs:10:5:".to_string()))
+        );
+        assert_eq!(results.tests[1].outcome, Outcome::Passed);
+        assert!(results.missing().is_empty());
+        assert!(!results.success());
+    }
+
+    #[test]
+    fn continues_tap_after_panic() {
+        let mut results = Results::default();
+        for line in [
+            "TAP version 14",
+            "1..2",
+            "Test 0: aes128_ctr",
+            "Panic: restarting after test 0",
+            "--- crash dump end ---",
+            "Test 0 failed: the kernel panicked",
+            "not ok 1 - aes128_ctr",
+            "Test 1: sha256",
+            "ok 2 - sha256",
+            "All tests finished.",
+        ] {
+            results.add_line(line);
+        }
+
+        assert_eq!(results.suite, Some(2));
+        assert_eq!(results.tests.len(), 2);
+        assert!(matches!(results.tests[0].outcome, Outcome::Failed(_)));
+        assert_eq!(results.tests[1].outcome, Outcome::Passed);
+        assert!(results.missing().is_empty());
+    }
+
+    #[test]
+    fn resumes_after_watchdog_reset() {
+        let mut results = Results::default();
+        for line in [
+            "Test suite: 2 tests",
+            "Test 0: sha256",
+            "Test 0 passed",
+            "Test 1: watchdog",
+            "Watchdog: 1 setups, 812 tickles, 40 suspends, 40 resumes",
+            "Watchdog: starving the watchdog, expecting a reset within 5000 ms",
+            "Test suite: 2 tests",
+            "Watchdog: reset by the watchdog, resuming",
+            "Watchdog: all cases passed",
+            "Test 1 passed",
+            "All tests finished.",
+        ] {
+            results.add_line(line);
+        }
+
+        assert_eq!(results.tests.len(), 2);
+        assert_eq!(results.tests[1].label(), "watchdog");
+        assert_eq!(results.tests[1].outcome, Outcome::Passed);
+        assert!(results.missing().is_empty());
+        assert!(results.success());
+
+        let mut results = Results::default();
+        for line in [
+            "Test suite: 1 tests",
+            "Test 0: watchdog",
+            "Test suite: 1 tests",
+            "Watchdog: Resume failed: reset not caused by the watchdog",
+            "Test 0 failed",
+            "All tests finished.",
+        ] {
+            results.add_line(line);
+        }
+
+        assert_eq!(
+            results.tests[0].outcome,
+            Outcome::Failed(Some(
+                "Resume failed: reset not caused by the watchdog".to_string()
+            ))
+        );
+        assert!(!results.success());
+    }
+}
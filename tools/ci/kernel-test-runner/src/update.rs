@@ -0,0 +1,73 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Sends a new kernel over the serial port to a board that runs the test
+//! kernel, which writes it to its second flash bank and restarts in it.
+//!
+//! The image is the raw binary of a kernel built with the `bank_b` feature.
+//! When the board prints its seed, the runner sends `update <length> <crc32>`
+//! instead of the seed, and then each page the board asks for with `Update:
+//! send page <n>`. A board that runs the kernel in its second bank restarts
+//! in the first one to receive the image and prints its seed again, so the
+//! runner sends the command again. After `Update: image written`, the board
+//! restarts in the new kernel, and the runner collects its results as usual.
+
+use std::fs;
+
+/// Size of the flash pages the board writes.
+const PAGE_SIZE: usize = 4096;
+
+pub struct Update {
+    image: Vec<u8>,
+}
+
+impl Update {
+    pub fn load(path: &str) -> Result<Update, String> {
+        let image = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        if image.is_empty() {
+            return Err(format!("{} is empty", path));
+        }
+        Ok(Update { image })
+    }
+
+    /// The line that starts the update.
+    pub fn command(&self) -> String {
+        format!("update {} {:08x}\n", self.image.len(), crc32(&self.image))
+    }
+
+    /// The bytes of page `index` of the image.
+    pub fn page(&self, index: usize) -> Option<&[u8]> {
+        self.image.chunks(PAGE_SIZE).nth(index)
+    }
+}
+
+/// CRC-32 (IEEE) of `data`, as the board computes it.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let update = Update {
+            image: vec![0xA5; PAGE_SIZE + 10],
+        };
+        assert_eq!(update.command(), "update 4106 a85ece04\n");
+        assert_eq!(update.page(0).map(<[u8]>::len), Some(PAGE_SIZE));
+        assert_eq!(update.page(1).map(<[u8]>::len), Some(10));
+        assert_eq!(update.page(2), None);
+    }
+}